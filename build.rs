@@ -1,9 +1,104 @@
-use spirv_builder::{MetadataPrintout, SpirvBuilder};
+use spirv_builder::{Capability, MetadataPrintout, SpirvBuilder};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+//rust-gpuはTaskEXTとMeshEXTの実行モデルに対応していないので、--mesh-shadingのシェーダーはGLSLで書いてglslcでコンパイルする
+const MESH_SHADING_SHADERS: [(&str, &str); 2] = [
+    ("shaders/glsl/mesh_shading.task", "mesh_shading_task.spv"),
+    ("shaders/glsl/mesh_shading.mesh", "mesh_shading_mesh.spv"),
+];
 
 fn main() -> Result<(), anyhow::Error> {
     SpirvBuilder::new("./shaders/rust-shader/", "spirv-unknown-vulkan1.2")
         .print_metadata(MetadataPrintout::Full)
         .build()?;
 
+    //レイトレーシングとray queryのシェーダーはcapabilityと拡張が必要なので別のモジュールにする
+    SpirvBuilder::new("./shaders/ray-tracing-shader/", "spirv-unknown-vulkan1.2")
+        .capability(Capability::RayTracingKHR)
+        .extension("SPV_KHR_ray_tracing")
+        .print_metadata(MetadataPrintout::Full)
+        .build()?;
+
+    SpirvBuilder::new("./shaders/ray-query-shader/", "spirv-unknown-vulkan1.2")
+        .capability(Capability::RayQueryKHR)
+        .extension("SPV_KHR_ray_query")
+        .print_metadata(MetadataPrintout::Full)
+        .build()?;
+
+    //テッセレーションとジオメトリシェーダーはcapabilityが要るので別のモジュールにする
+    SpirvBuilder::new("./shaders/tessellation-shader/", "spirv-unknown-vulkan1.2")
+        .capability(Capability::Tessellation)
+        .print_metadata(MetadataPrintout::Full)
+        .build()?;
+
+    SpirvBuilder::new("./shaders/geometry-shader/", "spirv-unknown-vulkan1.2")
+        .capability(Capability::Geometry)
+        .print_metadata(MetadataPrintout::Full)
+        .build()?;
+
+    compile_mesh_shading_shaders()?;
+
+    Ok(())
+}
+
+//GLSLCの環境変数、VULKAN_SDKのbin、PATHの順にglslcを探す
+fn glslc() -> PathBuf {
+    if let Some(path) = env::var_os("GLSLC") {
+        return PathBuf::from(path);
+    }
+
+    let name = if cfg!(windows) { "glslc.exe" } else { "glslc" };
+
+    match env::var_os("VULKAN_SDK") {
+        Some(sdk) => Path::new(&sdk).join("bin").join(name),
+        None => PathBuf::from(name),
+    }
+}
+
+//SPIR-Vをenv!で埋め込めるように、rust-gpuのシェーダーと同じくパスをrustc-envで渡す
+//glslcが無い場合は空のファイルを渡し、実行時に--mesh-shadingを使えないものとして扱う
+fn compile_mesh_shading_shaders() -> Result<(), anyhow::Error> {
+    println!("cargo:rerun-if-env-changed=GLSLC");
+    println!("cargo:rerun-if-env-changed=VULKAN_SDK");
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let glslc = glslc();
+
+    for (source, name) in MESH_SHADING_SHADERS {
+        println!("cargo:rerun-if-changed={}", source);
+
+        let output = out_dir.join(name);
+
+        //SPV_EXT_mesh_shaderはSPIR-V 1.4以降が必要
+        let status = Command::new(&glslc)
+            .arg("--target-env=vulkan1.2")
+            .arg(source)
+            .arg("-o")
+            .arg(&output)
+            .status();
+
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => anyhow::bail!(
+                "{} failed to compile {}: {}",
+                glslc.display(),
+                source,
+                status
+            ),
+            Err(error) => {
+                println!(
+                    "cargo:warning={} was not found ({}), --mesh-shading is unavailable",
+                    glslc.display(),
+                    error
+                );
+                std::fs::write(&output, [])?;
+            }
+        }
+
+        println!("cargo:rustc-env={}={}", name, output.display());
+    }
+
     Ok(())
 }
@@ -0,0 +1,82 @@
+#![cfg_attr(
+    target_arch = "spirv",
+    no_std,
+    feature(register_attr),
+    register_attr(spirv)
+)]
+
+//Geometryのcapabilityはモジュール全体に付くので、geometry_shaderに対応していないデバイス(MoltenVKなど)でも読み込むrust-shaderとは別のクレートにしている
+//フラグメントシェーダーはrust-shaderのmain_fs_unlitをそのまま使う
+
+#[cfg(not(target_arch = "spirv"))]
+use spirv_std::macros::spirv;
+
+use spirv_std::glam::{Mat4, Vec3, Vec3A, Vec4};
+
+//rust-shaderのUniformBufferObjectと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct UniformBufferObject {
+    pub view: Mat4,
+    pub proj: Mat4,
+    pub light_view_proj: Mat4,
+    pub frame_index: u32,
+    pub shadow_texel_size: f32,
+    pub _padding: [u32; 2],
+}
+
+//rust-shaderのObjectUniformsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct ObjectUniforms {
+    pub model: Mat4,
+    pub color: Vec4,
+}
+
+//ワールド座標での法線の線の長さ
+const NORMAL_LENGTH: f32 = 0.1;
+//法線の線の色
+const NORMAL_COLOR: Vec3 = Vec3::new(1.0, 1.0, 0.0);
+
+//頂点とその法線の先をそれぞれクリップ座標にしてジオメトリシェーダーに渡す
+#[spirv(vertex)]
+pub fn main_vs_normals(
+    position: Vec3,
+    //Vertexの頂点属性の順番に合わせるために受け取るが使わない
+    _in_color: Vec3,
+    in_normal: Vec3,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(uniform, descriptor_set = 1, binding = 0)] object: &ObjectUniforms,
+    base: &mut Vec4,
+    tip: &mut Vec4,
+) {
+    let world = object.model * position.extend(1.0);
+    let world_normal = (object.model * in_normal.extend(0.0))
+        .truncate()
+        .normalize();
+    let view_proj = ubo.proj * ubo.view;
+
+    *base = view_proj * world;
+    *tip = view_proj * (world.truncate() + world_normal * NORMAL_LENGTH).extend(1.0);
+}
+
+//POINT_LISTの頂点1つにつき、頂点から法線の先までの線を1本出す
+#[spirv(geometry(input_points = 1, output_line_strip = 2))]
+pub fn main_gs_normals(
+    base: &[Vec4; 1],
+    tip: &[Vec4; 1],
+    #[spirv(position)] out_pos: &mut Vec4,
+    color: &mut Vec3A,
+) {
+    unsafe {
+        *out_pos = base[0];
+        *color = Vec3A::from(NORMAL_COLOR);
+        spirv_std::arch::emit_vertex();
+
+        *out_pos = tip[0];
+        *color = Vec3A::from(NORMAL_COLOR);
+        spirv_std::arch::emit_vertex();
+
+        spirv_std::arch::end_primitive();
+    }
+}
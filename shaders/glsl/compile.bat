@@ -1,3 +1,5 @@
 glslc.exe shader.vert -o ../output/vert.spv
 glslc.exe shader.frag -o ../output/frag.spv
+glslc.exe --target-env=vulkan1.2 mesh_shading.task -o ../output/mesh_shading.task.spv
+glslc.exe --target-env=vulkan1.2 mesh_shading.mesh -o ../output/mesh_shading.mesh.spv
 pause
\ No newline at end of file
@@ -0,0 +1,294 @@
+#![cfg_attr(
+    target_arch = "spirv",
+    no_std,
+    feature(register_attr),
+    register_attr(spirv)
+)]
+
+//RayQueryKHRのcapabilityはモジュール全体に付くので、ray queryに対応していないデバイスでも読み込むrust-shaderとは別のクレートにしている
+//頂点シェーダーはrust-shaderのmain_vs_shadowedをそのまま使い、location = 3のshadow_coordは読まない
+
+#[cfg(not(target_arch = "spirv"))]
+use spirv_std::macros::spirv;
+
+//no_stdではf32::powfが無いのでnum_traits経由で使う
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
+use spirv_std::ray_tracing::{AccelerationStructure, CommittedIntersection, RayFlags};
+
+use spirv_std::arch::IndexUnchecked;
+use spirv_std::glam::{Vec3, Vec3A, Vec4};
+
+//rust-shaderのLightUniformsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct LightUniforms {
+    pub ambient: Vec4,
+    pub camera_position: Vec4,
+    pub mode: u32,
+    pub light_count: u32,
+    pub tile_count_x: u32,
+    pub tile_stride: u32,
+}
+
+//rust-shaderのLightと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Light {
+    pub position: Vec4,
+    pub direction: Vec4,
+    pub color: Vec4,
+    pub cos_inner: f32,
+    pub cos_outer: f32,
+    pub kind: u32,
+    pub _padding: u32,
+}
+
+//ホスト側のlighting::LightingModeと同じ値
+const LIGHTING_UNLIT: u32 = 0;
+const LIGHTING_FULL: u32 = 2;
+
+//ホスト側のlighting::LightKindと同じ値
+const LIGHT_DIRECTIONAL: u32 = 0;
+const LIGHT_SPOT: u32 = 2;
+
+//rust-shaderのTILE_SIZEと同じ
+const TILE_SIZE: u32 = 16;
+
+//rust-shaderと同じBlinn-Phongのハイライトの鋭さ
+const SHININESS: f32 = 32.0;
+
+//自分自身の三角形に当たらないように、レイの始点を法線方向にずらす距離
+const SHADOW_RAY_OFFSET: f32 = 0.001;
+//ディレクショナルライトへのレイの長さ、カメラのfarに合わせる
+const SHADOW_RAY_LENGTH: f32 = 100.0;
+
+//main_fs_shadowedのシャドウマップの代わりに、set = 3のTLASへライトに向かうレイを1本飛ばして影を付ける
+#[allow(clippy::too_many_arguments)]
+#[spirv(fragment)]
+pub fn main_fs_ray_query_shadowed(
+    output: &mut Vec4,
+    color: Vec3A,
+    world_position: Vec3A,
+    normal: Vec3A,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] lights: &[Light],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &[u32],
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    // layout(set = 3, binding = 0) uniform accelerationStructureEXT
+    #[spirv(descriptor_set = 3, binding = 0)] top_level: &AccelerationStructure,
+) {
+    *output = lighting(
+        color,
+        world_position,
+        normal,
+        light,
+        lights,
+        light_tiles,
+        frag_coord,
+        top_level,
+    )
+    .extend(1.0);
+}
+
+#[allow(clippy::too_many_arguments)]
+#[spirv(fragment)]
+pub fn main_fs_ray_query_shadowed_encode_srgb(
+    output: &mut Vec4,
+    color: Vec3A,
+    world_position: Vec3A,
+    normal: Vec3A,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] lights: &[Light],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &[u32],
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    #[spirv(descriptor_set = 3, binding = 0)] top_level: &AccelerationStructure,
+) {
+    *output = encode_srgb(
+        lighting(
+            color,
+            world_position,
+            normal,
+            light,
+            lights,
+            light_tiles,
+            frag_coord,
+            top_level,
+        )
+        .extend(1.0),
+    );
+}
+
+//rust-shaderのlightingと同じ計算で、shadowをシャドウマップではなくshadow_visibilityで求める
+//シャドウマップと違って向きに依存しないので、全ての種類のライトにレイを飛ばす
+#[allow(clippy::too_many_arguments)]
+fn lighting(
+    color: Vec3A,
+    world_position: Vec3A,
+    normal: Vec3A,
+    light: &LightUniforms,
+    lights: &[Light],
+    light_tiles: &[u32],
+    frag_coord: Vec4,
+    top_level: &AccelerationStructure,
+) -> Vec3 {
+    let color = Vec3::from(color);
+
+    if light.mode == LIGHTING_UNLIT {
+        return color;
+    }
+
+    let world_position = Vec3::from(world_position);
+    //補間されると長さが1ではなくなる
+    let normal = Vec3::from(normal).normalize();
+    let to_camera = (light.camera_position.truncate() - world_position).normalize();
+
+    let mut result = color * light.ambient.truncate();
+
+    let tile = light_tile(light, frag_coord);
+    let count = tile_light_count(light, light_tiles, tile);
+
+    let mut index = 0;
+    while index < count {
+        let source = unsafe { lights.index_unchecked(tile_light_index(light_tiles, tile, index)) };
+        let (to_light, light_distance, radiance) = light_radiance(source, world_position);
+
+        let diffuse = normal.dot(to_light).max(0.0);
+
+        //裏側から光が当たっている面や光が届かない所は影かどうかに関わらず暗いのでレイを飛ばさない
+        if diffuse > 0.0 && radiance != Vec3::ZERO {
+            let shadow =
+                shadow_visibility(top_level, world_position, normal, to_light, light_distance);
+            let radiance = radiance * shadow;
+
+            result += color * radiance * diffuse;
+
+            if light.mode == LIGHTING_FULL {
+                let half = (to_light + to_camera).normalize();
+                let specular = normal.dot(half).max(0.0).powf(SHININESS);
+
+                result += radiance * specular;
+            }
+        }
+
+        index += 1;
+    }
+
+    result
+}
+
+//rust-shaderのlight_tileと同じ
+fn light_tile(light: &LightUniforms, frag_coord: Vec4) -> u32 {
+    if light.tile_count_x == 0 {
+        return u32::MAX;
+    }
+
+    let x = frag_coord.x as u32 / TILE_SIZE;
+    let y = frag_coord.y as u32 / TILE_SIZE;
+
+    (y * light.tile_count_x + x) * light.tile_stride
+}
+
+fn tile_light_count(light: &LightUniforms, light_tiles: &[u32], tile: u32) -> usize {
+    if tile == u32::MAX {
+        light.light_count as usize
+    } else {
+        unsafe { *light_tiles.index_unchecked(tile as usize) as usize }
+    }
+}
+
+fn tile_light_index(light_tiles: &[u32], tile: u32, index: usize) -> usize {
+    if tile == u32::MAX {
+        index
+    } else {
+        unsafe { *light_tiles.index_unchecked(tile as usize + 1 + index) as usize }
+    }
+}
+
+//rust-shaderのlight_radianceと同じ、ディレクショナルライトの距離はSHADOW_RAY_LENGTHにする
+fn light_radiance(light: &Light, world_position: Vec3) -> (Vec3, f32, Vec3) {
+    let color = light.color.truncate() * light.color.w;
+
+    if light.kind == LIGHT_DIRECTIONAL {
+        return (
+            -light.direction.truncate().normalize(),
+            SHADOW_RAY_LENGTH,
+            color,
+        );
+    }
+
+    let offset = light.position.truncate() - world_position;
+    let distance = offset.length();
+    let to_light = offset / distance.max(1e-4);
+
+    let mut radiance = color * range_attenuation(distance, light.position.w);
+
+    if light.kind == LIGHT_SPOT {
+        let cos_angle = (-to_light).dot(light.direction.truncate().normalize());
+        radiance *= smoothstep(light.cos_outer, light.cos_inner, cos_angle);
+    }
+
+    (to_light, distance, radiance)
+}
+
+//rust-shaderのrange_attenuationと同じ
+fn range_attenuation(distance: f32, range: f32) -> f32 {
+    let ratio = distance / range;
+    let window = (1.0 - ratio * ratio * ratio * ratio).max(0.0);
+
+    window * window / (1.0 + distance * distance)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+//ライトまでの間に三角形があれば0.0、無ければ1.0
+//どれか1つに当たれば良いので最初に当たった所で止める
+fn shadow_visibility(
+    top_level: &AccelerationStructure,
+    world_position: Vec3,
+    normal: Vec3,
+    to_light: Vec3,
+    light_distance: f32,
+) -> f32 {
+    let origin = world_position + normal * SHADOW_RAY_OFFSET;
+
+    spirv_std::ray_query!(let mut shadow_ray);
+
+    let intersection = unsafe {
+        shadow_ray.initialize(
+            top_level,
+            RayFlags::OPAQUE | RayFlags::TERMINATE_ON_FIRST_HIT,
+            0xff,
+            origin,
+            0.0,
+            to_light,
+            light_distance,
+        );
+
+        //全てOPAQUEなので候補を確認する必要は無い
+        while shadow_ray.proceed() {}
+
+        shadow_ray.get_committed_intersection_type()
+    };
+
+    match intersection {
+        CommittedIntersection::None => 1.0,
+        _ => 0.0,
+    }
+}
+
+//rust-shaderのencode_srgbと同じ
+fn encode_srgb(color: Vec4) -> Vec4 {
+    fn encode(value: f32) -> f32 {
+        if value <= 0.0031308 {
+            value * 12.92
+        } else {
+            1.055 * value.powf(1.0 / 2.4) - 0.055
+        }
+    }
+
+    Vec4::new(encode(color.x), encode(color.y), encode(color.z), color.w)
+}
@@ -0,0 +1,153 @@
+#![cfg_attr(
+    target_arch = "spirv",
+    no_std,
+    feature(register_attr),
+    register_attr(spirv)
+)]
+
+//RayTracingKHRのcapabilityはモジュール全体に付くので、対応していないデバイスでも読み込むrust-shaderとは別のクレートにしている
+
+#[cfg(not(target_arch = "spirv"))]
+use spirv_std::macros::spirv;
+
+//no_stdではf32::powfが無いのでnum_traits経由で使う
+use spirv_std::glam::{Mat3, Mat4, UVec2, UVec3, Vec2, Vec3, Vec4, Vec4Swizzles};
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
+use spirv_std::ray_tracing::{AccelerationStructure, RayFlags};
+use spirv_std::Image;
+
+//ホスト側のray_tracing::RayTracingConstantsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct RayTracingConstants {
+    pub view_inverse: Mat4,
+    pub proj_inverse: Mat4,
+    //OUTPUT_*のどれか、blit先のswapchainの色空間に合わせてエンコードする
+    pub output_encoding: u32,
+    pub _padding: [u32; 3],
+}
+
+//ホスト側のray_tracing::OUTPUT_*と同じ値
+const OUTPUT_LINEAR: u32 = 0;
+const OUTPUT_SRGB: u32 = 1;
+const OUTPUT_PQ: u32 = 2;
+
+//レイを飛ばす範囲、カメラのnearとfarに合わせる
+const T_MIN: f32 = 0.1;
+const T_MAX: f32 = 100.0;
+
+//どこにも当たらなかった場合の色
+const MISS_COLOR: Vec3 = Vec3::new(0.1, 0.1, 0.15);
+
+//画素ごとにカメラからレイを飛ばし、payloadに入った色をstorage imageに書き込む
+#[spirv(ray_generation)]
+pub fn main_rgen(
+    #[spirv(launch_id)] launch_id: UVec3,
+    #[spirv(launch_size)] launch_size: UVec3,
+    #[spirv(push_constant)] constants: &RayTracingConstants,
+    #[spirv(descriptor_set = 0, binding = 0)] top_level: &AccelerationStructure,
+    #[spirv(descriptor_set = 0, binding = 1)] image: &Image!(2D, format=rgba16f, sampled=false),
+    #[spirv(ray_payload)] payload: &mut Vec3,
+) {
+    //画素の中心を-1から1のクリップ座標にする
+    let pixel = Vec2::new(launch_id.x as f32, launch_id.y as f32) + Vec2::splat(0.5);
+    let uv = pixel / Vec2::new(launch_size.x as f32, launch_size.y as f32);
+    let ndc = uv * 2.0 - Vec2::ONE;
+
+    let origin = constants.view_inverse * Vec4::new(0.0, 0.0, 0.0, 1.0);
+    let target = constants.proj_inverse * Vec4::new(ndc.x, ndc.y, 1.0, 1.0);
+    let direction = constants.view_inverse * (target.xyz() / target.w).normalize().extend(0.0);
+
+    unsafe {
+        top_level.trace_ray(
+            RayFlags::OPAQUE,
+            0xff,
+            0,
+            0,
+            0,
+            origin.xyz(),
+            T_MIN,
+            direction.xyz(),
+            T_MAX,
+            payload,
+        );
+
+        let color = payload.extend(1.0);
+        let color = match constants.output_encoding {
+            OUTPUT_SRGB => encode_srgb(color),
+            OUTPUT_PQ => encode_pq(color),
+            _ => color,
+        };
+
+        image.write(UVec2::new(launch_id.x, launch_id.y), color);
+    }
+}
+
+//rust-shaderのencode_srgbと同じ、blitは値を変換しないのでここでエンコードしておく
+fn encode_srgb(color: Vec4) -> Vec4 {
+    fn encode(value: f32) -> f32 {
+        if value <= 0.0031308 {
+            value * 12.92
+        } else {
+            1.055 * value.powf(1.0 / 2.4) - 0.055
+        }
+    }
+
+    Vec4::new(encode(color.x), encode(color.y), encode(color.z), color.w)
+}
+
+//rust-shaderのPQ_PAPER_WHITE_NITSと同じ
+const PQ_PAPER_WHITE_NITS: f32 = 203.0;
+const PQ_MAX_NITS: f32 = 10000.0;
+
+//rust-shaderのencode_pqと同じ、BT.2020の原色に変換してからPQの伝達関数でエンコードする
+fn encode_pq(color: Vec4) -> Vec4 {
+    fn encode(value: f32) -> f32 {
+        const M1: f32 = 0.1593017578125;
+        const M2: f32 = 78.84375;
+        const C1: f32 = 0.8359375;
+        const C2: f32 = 18.8515625;
+        const C3: f32 = 18.6875;
+
+        let y = (value * PQ_PAPER_WHITE_NITS / PQ_MAX_NITS)
+            .clamp(0.0, 1.0)
+            .powf(M1);
+
+        ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+    }
+
+    let rec709_to_rec2020 = Mat3::from_cols(
+        Vec3::new(0.6274, 0.0691, 0.0164),
+        Vec3::new(0.3293, 0.9195, 0.0880),
+        Vec3::new(0.0433, 0.0114, 0.8956),
+    );
+    let rec2020 = rec709_to_rec2020 * color.truncate();
+
+    Vec4::new(
+        encode(rec2020.x),
+        encode(rec2020.y),
+        encode(rec2020.z),
+        color.w,
+    )
+}
+
+#[spirv(miss)]
+pub fn main_rmiss(#[spirv(incoming_ray_payload)] payload: &mut Vec3) {
+    *payload = MISS_COLOR;
+}
+
+//hit_attributeには三角形の2番目と3番目の頂点の重心座標が入る
+#[spirv(closest_hit)]
+pub fn main_rchit(
+    #[spirv(incoming_ray_payload)] payload: &mut Vec3,
+    #[spirv(hit_attribute)] barycentrics: &mut Vec2,
+) {
+    let barycentrics = *barycentrics;
+
+    *payload = Vec3::new(
+        1.0 - barycentrics.x - barycentrics.y,
+        barycentrics.x,
+        barycentrics.y,
+    );
+}
@@ -9,44 +9,2364 @@
 use spirv_std::macros::spirv;
 
 use spirv_std::arch::IndexUnchecked;
+//no_stdではf32::powfが無いのでnum_traits経由で使う
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
+use spirv_std::{Image, Sampler};
 
 //A/aが付いてるやつはSPIR-Vのアライメント考慮
-use spirv_std::glam::{vec3a, vec4, Vec3A, Vec4};
+use spirv_std::glam::{Mat3, Mat4, UVec2, UVec3, UVec4, Vec2, Vec3, Vec3A, Vec4};
+
+//ホスト側のuniform_buffer::UniformBufferObjectと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct UniformBufferObject {
+    pub view: Mat4,
+    pub proj: Mat4,
+    //ワールド座標からシャドウマップのクリップ座標への変換
+    pub light_view_proj: Mat4,
+    //--ubo-stressの時にCPUが書き込んだフレーム番号
+    pub frame_index: u32,
+    //シャドウマップの1テクセル分のuvの大きさ
+    pub shadow_texel_size: f32,
+    //デバッグ表示の深度に使うカメラのnearとfar
+    pub near: f32,
+    pub far: f32,
+    //一番遠い深度値、深度値を逆にした場合は0.0
+    pub far_depth: f32,
+    pub _padding: [u32; 3],
+}
+
+//ホスト側のobject_buffer::ObjectUniformsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct ObjectUniforms {
+    pub model: Mat4,
+    pub color: Vec4,
+}
+
+//ホスト側のlighting::LightUniformsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct LightUniforms {
+    pub ambient: Vec4,
+    pub camera_position: Vec4,
+    //LIGHTING_*のどれか
+    pub mode: u32,
+    //binding = 3のlightsのうち使う数、0の場合は環境光だけになる
+    pub light_count: u32,
+    //binding = 4のタイルの横の数、0の場合はタイルを使わずにlight_count個の全てのライトを読む
+    pub tile_count_x: u32,
+    //binding = 4の1つのタイルが使うu32の数
+    pub tile_stride: u32,
+}
+
+//ホスト側のlighting::Lightと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Light {
+    //xyzがポイントライトとスポットライトの位置、wが光の届く距離
+    pub position: Vec4,
+    //xyzがディレクショナルライトとスポットライトの光の進む向き
+    pub direction: Vec4,
+    //xyzが色、wが強さ
+    pub color: Vec4,
+    //スポットライトの内側と外側の角度のcos
+    pub cos_inner: f32,
+    pub cos_outer: f32,
+    //LIGHT_*のどれか
+    pub kind: u32,
+    pub _padding: u32,
+}
+
+//ホスト側のlighting::LightingModeと同じ値
+const LIGHTING_UNLIT: u32 = 0;
+const LIGHTING_FULL: u32 = 2;
+
+//ホスト側のlighting::LightKindと同じ値
+const LIGHT_DIRECTIONAL: u32 = 0;
+const LIGHT_SPOT: u32 = 2;
+
+//ホスト側のlight_culling::TILE_SIZEと同じ、1つのタイルの縦横のピクセル数
+const TILE_SIZE: u32 = 16;
+
+//Blinn-Phongのハイライトの鋭さ
+const SHININESS: f32 = 32.0;
 
 #[spirv(vertex)]
 pub fn main_vs(
-    // gl_VertexIndex
-    #[spirv(vertex_index)] vert_id: i32,
+    // layout(location = 0) in
+    position: Vec3,
+    // layout(location = 1) in
+    in_color: Vec3,
+    // layout(location = 2) in
+    in_normal: Vec3,
+    // layout(set = 0, binding = 0) uniform
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    // layout(set = 1, binding = 0) uniform、オフセットはバインド時に指定される
+    #[spirv(uniform, descriptor_set = 1, binding = 0)] object: &ObjectUniforms,
     // gl_Position
     #[spirv(position)] out_pos: &mut Vec4,
     // 何も指定せずに &mut したのでlayout(location = 0) outとなる
     color: &mut Vec3A,
+    // layout(location = 1) out
+    world_position: &mut Vec3A,
+    // layout(location = 2) out
+    normal: &mut Vec3A,
 ) {
-    *out_pos = *unsafe {
-        [
-            vec4(0.0, -1.0, 0.0, 1.0),
-            vec4(1.0, 1.0, 0.0, 1.0),
-            vec4(-1.0, 1.0, 0.0, 1.0),
-        ]
-        .index_unchecked(vert_id as usize)
-    };
+    let (clip, world, world_normal) = transform(position, in_normal, ubo, object);
+
+    *out_pos = clip;
+    *color = in_color.into();
+    *world_position = world.into();
+    *normal = world_normal.into();
+}
+
+//main_vsに加えてGPUが読んだフレーム番号をstorage bufferに書き出す
+//頂点シェーダーからの書き込みにはvertex_pipeline_stores_and_atomicsが必要なので別のエントリポイントにしている
+#[allow(clippy::too_many_arguments)]
+#[spirv(vertex)]
+pub fn main_vs_ubo_stress(
+    #[spirv(vertex_index)] vert_id: i32,
+    position: Vec3,
+    in_color: Vec3,
+    in_normal: Vec3,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(uniform, descriptor_set = 1, binding = 0)] object: &ObjectUniforms,
+    // layout(set = 0, binding = 1) buffer
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] readback: &mut [u32],
+    #[spirv(position)] out_pos: &mut Vec4,
+    color: &mut Vec3A,
+    world_position: &mut Vec3A,
+    normal: &mut Vec3A,
+) {
+    let (clip, world, world_normal) = transform(position, in_normal, ubo, object);
+
+    *out_pos = clip;
+    *color = in_color.into();
+    *world_position = world.into();
+    *normal = world_normal.into();
+
+    if vert_id == 0 {
+        unsafe { *readback.index_unchecked_mut(0) = ubo.frame_index };
+    }
+}
+
+//ホスト側のmesh::Vertexのf32の数、position, color, normal, tex_coord, tangentの順に並んでいる
+const VERTEX_FLOATS: usize = 15;
+
+//offsetから連続する3つのf32
+fn read_vec3(data: &[f32], offset: usize) -> Vec3 {
+    unsafe {
+        Vec3::new(
+            *data.index_unchecked(offset),
+            *data.index_unchecked(offset + 1),
+            *data.index_unchecked(offset + 2),
+        )
+    }
+}
+
+//頂点入力を使わずにset = 2のstorage bufferからインデックスと頂点を読む
+//cmd_drawのvertex_indexをインデックスバッファの位置として使うので、頂点数はインデックスの数で描画する
+#[allow(clippy::too_many_arguments)]
+#[spirv(vertex)]
+pub fn main_vs_pulled(
+    #[spirv(vertex_index)] vert_id: i32,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(uniform, descriptor_set = 1, binding = 0)] object: &ObjectUniforms,
+    #[spirv(storage_buffer, descriptor_set = 2, binding = 0)] vertices: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 2, binding = 1)] indices: &[u32],
+    #[spirv(position)] out_pos: &mut Vec4,
+    color: &mut Vec3A,
+    world_position: &mut Vec3A,
+    normal: &mut Vec3A,
+) {
+    let index = unsafe { *indices.index_unchecked(vert_id as usize) } as usize;
+    let base = index * VERTEX_FLOATS;
+
+    let (clip, world, world_normal) = transform(
+        read_vec3(vertices, base),
+        read_vec3(vertices, base + 6),
+        ubo,
+        object,
+    );
+
+    *out_pos = clip;
+    *color = read_vec3(vertices, base + 3).into();
+    *world_position = world.into();
+    *normal = world_normal.into();
+}
+
+//インスタンスごとの平行移動と拡大率、色をinstance rateの頂点属性から受け取る
+//モデル行列を使わないのでset = 1は参照しない
+//回転させないので法線はそのまま使える
+#[allow(clippy::too_many_arguments)]
+#[spirv(vertex)]
+pub fn main_vs_instanced(
+    position: Vec3,
+    _in_color: Vec3,
+    in_normal: Vec3,
+    // layout(location = 3) in、xyzが平行移動でwが拡大率
+    instance_offset_scale: Vec4,
+    // layout(location = 4) in
+    instance_color: Vec3,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(position)] out_pos: &mut Vec4,
+    color: &mut Vec3A,
+    world_position: &mut Vec3A,
+    normal: &mut Vec3A,
+) {
+    let world = position * instance_offset_scale.w + instance_offset_scale.truncate();
+
+    *out_pos = ubo.proj * ubo.view * world.extend(1.0);
+
+    *color = instance_color.into();
+    *world_position = world.into();
+    *normal = in_normal.into();
+}
+
+//main_vs_instancedに加えて、インスタンスごとのテクスチャ配列のレイヤーをmain_fs_texture_arrayに渡す
+//メッシュにテクスチャ座標が無いので、-1.0から1.0の三角形のXYをそのまま0.0から1.0にする
+#[allow(clippy::too_many_arguments)]
+#[spirv(vertex)]
+pub fn main_vs_instanced_array(
+    position: Vec3,
+    _in_color: Vec3,
+    in_normal: Vec3,
+    instance_offset_scale: Vec4,
+    instance_color: Vec3,
+    // layout(location = 5) in
+    instance_layer: u32,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(position)] out_pos: &mut Vec4,
+    color: &mut Vec3A,
+    world_position: &mut Vec3A,
+    normal: &mut Vec3A,
+    tex_coord: &mut Vec2,
+    #[spirv(flat)] layer: &mut u32,
+) {
+    let world = position * instance_offset_scale.w + instance_offset_scale.truncate();
+
+    *out_pos = ubo.proj * ubo.view * world.extend(1.0);
+
+    *color = instance_color.into();
+    *world_position = world.into();
+    *normal = in_normal.into();
+    *tex_coord = position.truncate() * 0.5 + Vec2::splat(0.5);
+    *layer = instance_layer;
+}
+
+//main_vsと同じ変換で、頂点カラーにObjectUniformsの色を掛けてアルファと一緒に渡す
+#[spirv(vertex)]
+pub fn main_vs_transparent(
+    position: Vec3,
+    in_color: Vec3,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(uniform, descriptor_set = 1, binding = 0)] object: &ObjectUniforms,
+    #[spirv(position)] out_pos: &mut Vec4,
+    color: &mut Vec4,
+) {
+    *out_pos = ubo.proj * ubo.view * object.model * position.extend(1.0);
+
+    *color = (in_color * object.color.truncate()).extend(object.color.w);
+}
+
+//ホスト側のshadow_map::ShadowConstantsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct ShadowConstants {
+    pub light_view_proj: Mat4,
+}
+
+//main_vsと同じ頂点とモデル行列をライトの行列で変換し、シャドウマップに深度値だけを書き込む
+//フラグメントシェーダーは使わない
+#[spirv(vertex)]
+pub fn main_vs_shadow(
+    position: Vec3,
+    _in_color: Vec3,
+    // layout(push_constant) uniform
+    #[spirv(push_constant)] constants: &ShadowConstants,
+    #[spirv(uniform, descriptor_set = 1, binding = 0)] object: &ObjectUniforms,
+    #[spirv(position)] out_pos: &mut Vec4,
+) {
+    *out_pos = constants.light_view_proj * object.model * position.extend(1.0);
+}
+
+//main_vsに加えてシャドウマップのクリップ座標を渡す
+#[allow(clippy::too_many_arguments)]
+#[spirv(vertex)]
+pub fn main_vs_shadowed(
+    position: Vec3,
+    in_color: Vec3,
+    in_normal: Vec3,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(uniform, descriptor_set = 1, binding = 0)] object: &ObjectUniforms,
+    #[spirv(position)] out_pos: &mut Vec4,
+    color: &mut Vec3A,
+    world_position: &mut Vec3A,
+    normal: &mut Vec3A,
+    // layout(location = 3) out
+    shadow_coord: &mut Vec4,
+) {
+    let (clip, world, world_normal) = transform(position, in_normal, ubo, object);
+
+    *out_pos = clip;
+    *color = in_color.into();
+    *world_position = world.into();
+    *normal = world_normal.into();
+
+    *shadow_coord = ubo.light_view_proj * world.extend(1.0);
+}
+
+//ホスト側のmaterial_textures::MAX_TEXTURESと同じ値
+//RuntimeArrayを使うとモジュール全体にRuntimeDescriptorArrayのcapabilityが付き、
+//descriptor indexingが無いデバイスでは他のエントリーポイントも使えなくなるので固定長の配列にする
+const MAX_MATERIAL_TEXTURES: usize = 64;
+
+type MaterialTexture = Image!(2D, type=f32, sampled);
+
+//ホスト側のmaterial_textures::MaterialConstantsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct MaterialConstants {
+    pub index: u32,
+}
+
+//main_vsに加えてテクスチャ座標を出力する
+//頂点にUVが無いのでモデル空間のXYをそのまま使い、サンプラーのREPEATで繰り返す
+#[allow(clippy::too_many_arguments)]
+#[spirv(vertex)]
+pub fn main_vs_textured(
+    position: Vec3,
+    in_color: Vec3,
+    in_normal: Vec3,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(uniform, descriptor_set = 1, binding = 0)] object: &ObjectUniforms,
+    #[spirv(position)] out_pos: &mut Vec4,
+    color: &mut Vec3A,
+    world_position: &mut Vec3A,
+    normal: &mut Vec3A,
+    // layout(location = 3) out
+    tex_coord: &mut Vec2,
+) {
+    let (clip, world, world_normal) = transform(position, in_normal, ubo, object);
+
+    *out_pos = clip;
+    *color = in_color.into();
+    *world_position = world.into();
+    *normal = world_normal.into();
+
+    //画像の上がモデルの+Y側になるように反転する
+    *tex_coord = Vec2::new(position.x + 0.5, 0.5 - position.y);
+}
+
+//ホスト側のnormal_map::NormalMapConstantsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct NormalMapConstants {
+    //0の場合は法線マップを読まずに頂点の法線でライティングする
+    pub enabled: u32,
+}
+
+//main_vsに加えて頂点のテクスチャ座標と、ワールド座標に変換したtangentを出力する
+//tangentのwは裏返りの符号なのでそのまま渡す
+#[allow(clippy::too_many_arguments)]
+#[spirv(vertex)]
+pub fn main_vs_normal_mapped(
+    position: Vec3,
+    in_color: Vec3,
+    in_normal: Vec3,
+    // layout(location = 3) in
+    in_tex_coord: Vec2,
+    // layout(location = 4) in
+    in_tangent: Vec4,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(uniform, descriptor_set = 1, binding = 0)] object: &ObjectUniforms,
+    #[spirv(position)] out_pos: &mut Vec4,
+    color: &mut Vec3A,
+    world_position: &mut Vec3A,
+    normal: &mut Vec3A,
+    tex_coord: &mut Vec2,
+    // layout(location = 4) out
+    tangent: &mut Vec4,
+) {
+    let (clip, world, world_normal) = transform(position, in_normal, ubo, object);
+
+    *out_pos = clip;
+    *color = in_color.into();
+    *world_position = world.into();
+    *normal = world_normal.into();
+    *tex_coord = in_tex_coord;
+    *tangent = (object.model * in_tangent.truncate().extend(0.0))
+        .truncate()
+        .extend(in_tangent.w);
+}
+
+//main_vsの前に頂点と法線をジョイントの行列で動かす
+//ジョイントの行列はset = 2のstorage bufferに、ホスト側のSkeleton::joint_paletteの順番で並んでいる
+#[allow(clippy::too_many_arguments)]
+#[spirv(vertex)]
+pub fn main_vs_skinned(
+    position: Vec3,
+    in_color: Vec3,
+    in_normal: Vec3,
+    // layout(location = 3) in、binding = 1の頂点バッファ
+    in_joints: UVec4,
+    // layout(location = 4) in
+    in_weights: Vec4,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(uniform, descriptor_set = 1, binding = 0)] object: &ObjectUniforms,
+    #[spirv(storage_buffer, descriptor_set = 2, binding = 0)] joint_matrices: &[Mat4],
+    #[spirv(position)] out_pos: &mut Vec4,
+    color: &mut Vec3A,
+    world_position: &mut Vec3A,
+    normal: &mut Vec3A,
+) {
+    let skin = skin_matrix(joint_matrices, in_joints, in_weights);
+
+    let (clip, world, world_normal) = transform(
+        (skin * position.extend(1.0)).truncate(),
+        (skin * in_normal.extend(0.0)).truncate(),
+        ubo,
+        object,
+    );
+
+    *out_pos = clip;
+    *color = in_color.into();
+    *world_position = world.into();
+    *normal = world_normal.into();
+}
+
+//4つのジョイントの行列を重みで混ぜる
+//重みが全て0の頂点は動かさない、合計が1でない場合は合計で割る
+fn skin_matrix(joint_matrices: &[Mat4], joints: UVec4, weights: Vec4) -> Mat4 {
+    let total = weights.x + weights.y + weights.z + weights.w;
+
+    if total <= 0.0 {
+        return Mat4::IDENTITY;
+    }
+
+    let mut skin = Mat4::ZERO;
+    let mut i = 0;
+
+    while i < 4 {
+        let joint = unsafe { *joint_matrices.index_unchecked(joints[i] as usize) };
+        skin = skin + joint * weights[i];
+        i += 1;
+    }
+
+    skin * (1.0 / total)
+}
+
+//クリップ座標とワールド座標の位置と法線を返す
+//プロジェクション行列でY軸を反転させているのでワールド座標ではY軸が上向き
+//法線はモデル行列の拡大率が等方的な場合だけ正しく変換できる、正規化はフラグメントシェーダーで行う
+fn transform(
+    position: Vec3,
+    in_normal: Vec3,
+    ubo: &UniformBufferObject,
+    object: &ObjectUniforms,
+) -> (Vec4, Vec3, Vec3) {
+    let world = object.model * position.extend(1.0);
+
+    (
+        ubo.proj * ubo.view * world,
+        world.truncate(),
+        (object.model * in_normal.extend(0.0)).truncate(),
+    )
+}
+
+//lightsのうちfrag_coordのタイルに届くライトでBlinn-Phongのライティングをした色を返す
+//shadowはシャドウマップで求めた光が届く割合で、シャドウマップを描画したディレクショナルライトにだけ掛ける
+#[allow(clippy::too_many_arguments)]
+fn lighting(
+    color: Vec3A,
+    world_position: Vec3A,
+    normal: Vec3A,
+    light: &LightUniforms,
+    lights: &[Light],
+    light_tiles: &[u32],
+    frag_coord: Vec4,
+    shadow: f32,
+) -> Vec3 {
+    let color = Vec3::from(color);
+
+    if light.mode == LIGHTING_UNLIT {
+        return color;
+    }
+
+    let world_position = Vec3::from(world_position);
+    //補間されると長さが1ではなくなる
+    let normal = Vec3::from(normal).normalize();
+    let to_camera = (light.camera_position.truncate() - world_position).normalize();
+
+    let mut result = color * light.ambient.truncate();
+
+    let tile = light_tile(light, frag_coord);
+    let count = tile_light_count(light, light_tiles, tile);
+
+    let mut index = 0;
+    while index < count {
+        let source = unsafe { lights.index_unchecked(tile_light_index(light_tiles, tile, index)) };
+        let (to_light, _, mut radiance) = light_radiance(source, world_position);
+
+        if source.kind == LIGHT_DIRECTIONAL {
+            radiance *= shadow;
+        }
+
+        result += blinn_phong(color, normal, to_camera, to_light, radiance, light.mode);
+        index += 1;
+    }
+
+    result
+}
+
+//binding = 4でfrag_coordのタイルが始まる位置、タイルを使わない場合はu32::MAX
+//タイルはmain_cs_light_cullingが0番目に数、その後ろに届くライトの番号を書き込む
+fn light_tile(light: &LightUniforms, frag_coord: Vec4) -> u32 {
+    if light.tile_count_x == 0 {
+        return u32::MAX;
+    }
+
+    let x = frag_coord.x as u32 / TILE_SIZE;
+    let y = frag_coord.y as u32 / TILE_SIZE;
+
+    (y * light.tile_count_x + x) * light.tile_stride
+}
 
-    *color = *unsafe {
-        [
-            vec3a(1.0, 0.0, 0.0),
-            vec3a(0.0, 1.0, 0.0),
-            vec3a(0.0, 0.0, 1.0),
-        ]
-        .index_unchecked(vert_id as usize)
+fn tile_light_count(light: &LightUniforms, light_tiles: &[u32], tile: u32) -> usize {
+    if tile == u32::MAX {
+        light.light_count as usize
+    } else {
+        unsafe { *light_tiles.index_unchecked(tile as usize) as usize }
+    }
+}
+
+//タイルのindex番目のライトのlightsでの番号
+fn tile_light_index(light_tiles: &[u32], tile: u32, index: usize) -> usize {
+    if tile == u32::MAX {
+        index
+    } else {
+        unsafe { *light_tiles.index_unchecked(tile as usize + 1 + index) as usize }
+    }
+}
+
+//world_positionから見たライトの向きとライトまでの距離、届く光の色と強さ
+//ディレクショナルライトは距離で弱くならず、距離はf32::MAXにする
+fn light_radiance(light: &Light, world_position: Vec3) -> (Vec3, f32, Vec3) {
+    let color = light.color.truncate() * light.color.w;
+
+    if light.kind == LIGHT_DIRECTIONAL {
+        return (-light.direction.truncate().normalize(), f32::MAX, color);
+    }
+
+    let offset = light.position.truncate() - world_position;
+    let distance = offset.length();
+    let to_light = offset / distance.max(1e-4);
+
+    let mut radiance = color * range_attenuation(distance, light.position.w);
+
+    if light.kind == LIGHT_SPOT {
+        let cos_angle = (-to_light).dot(light.direction.truncate().normalize());
+        radiance *= smoothstep(light.cos_outer, light.cos_inner, cos_angle);
+    }
+
+    (to_light, distance, radiance)
+}
+
+//逆二乗で弱くなる光に、rangeで丁度0になる窓を掛ける
+//rangeより遠くには全く届かないので、届く範囲でライトをカリングできる
+fn range_attenuation(distance: f32, range: f32) -> f32 {
+    let ratio = distance / range;
+    let window = (1.0 - ratio * ratio * ratio * ratio).max(0.0);
+
+    window * window / (1.0 + distance * distance)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+//1つのライトの拡散反射光と鏡面反射光
+fn blinn_phong(
+    color: Vec3,
+    normal: Vec3,
+    to_camera: Vec3,
+    to_light: Vec3,
+    radiance: Vec3,
+    mode: u32,
+) -> Vec3 {
+    let diffuse = normal.dot(to_light).max(0.0);
+    let mut result = color * radiance * diffuse;
+
+    //裏側から光が当たっている面にはハイライトを付けない
+    if mode == LIGHTING_FULL && diffuse > 0.0 {
+        let half = (to_light + to_camera).normalize();
+        let specular = normal.dot(half).max(0.0).powf(SHININESS);
+
+        result += radiance * specular;
+    }
+
+    result
+}
+
+//ホスト側のlight_culling::LightCullingConstantsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct LightCullingConstants {
+    //タイルの四隅をNDCからビュー空間に戻す
+    pub inverse_projection: Mat4,
+    //シーンを描画する画像の大きさ
+    pub extent: UVec2,
+    pub tile_count: UVec2,
+}
+
+//1つのスレッドが1つのタイルを受け持ち、タイルの視錐台と交わるライトの番号をlight_tilesに書き込む
+//深度のプリパスは使わず、視錐台はカメラのnearからfarまでにする
+#[spirv(compute(threads(8, 8)))]
+pub fn main_cs_light_culling(
+    #[spirv(global_invocation_id)] id: UVec3,
+    #[spirv(push_constant)] constants: &LightCullingConstants,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] lights: &[Light],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &mut [u32],
+) {
+    let tile = id.truncate();
+
+    //最後のワークグループはタイルの数を超える
+    if tile.x >= constants.tile_count.x || tile.y >= constants.tile_count.y {
+        return;
+    }
+
+    let planes = tile_frustum(constants, tile);
+    let base = ((tile.y * constants.tile_count.x + tile.x) * light.tile_stride) as usize;
+
+    let mut count = 0;
+    let mut index = 0;
+    while index < light.light_count {
+        let source = unsafe { lights.index_unchecked(index as usize) };
+
+        if light_in_frustum(source, ubo.view, &planes) {
+            unsafe { *light_tiles.index_unchecked_mut(base + 1 + count as usize) = index };
+            count += 1;
+        }
+
+        index += 1;
+    }
+
+    unsafe { *light_tiles.index_unchecked_mut(base) = count };
+}
+
+//ビュー空間でのタイルの視錐台の6つの平面、xyzが内側を向く法線でwが原点からの距離
+fn tile_frustum(constants: &LightCullingConstants, tile: UVec2) -> [Vec4; 6] {
+    let extent = Vec2::new(constants.extent.x as f32, constants.extent.y as f32);
+    let min = tile * TILE_SIZE;
+    let max = (min + UVec2::splat(TILE_SIZE)).min(constants.extent);
+
+    //Vulkanのクリップ座標はfrag_coordと同じく上が-1.0
+    let min = Vec2::new(min.x as f32, min.y as f32) / extent * 2.0 - Vec2::ONE;
+    let max = Vec2::new(max.x as f32, max.y as f32) / extent * 2.0 - Vec2::ONE;
+
+    let inverse = constants.inverse_projection;
+    let near_00 = unproject(inverse, Vec3::new(min.x, min.y, 0.0));
+    let near_10 = unproject(inverse, Vec3::new(max.x, min.y, 0.0));
+    let near_01 = unproject(inverse, Vec3::new(min.x, max.y, 0.0));
+    let near_11 = unproject(inverse, Vec3::new(max.x, max.y, 0.0));
+    let far_00 = unproject(inverse, Vec3::new(min.x, min.y, 1.0));
+    let far_10 = unproject(inverse, Vec3::new(max.x, min.y, 1.0));
+    let far_01 = unproject(inverse, Vec3::new(min.x, max.y, 1.0));
+    let far_11 = unproject(inverse, Vec3::new(max.x, max.y, 1.0));
+
+    //透視投影か正射影か、Y軸の反転で三角形の向きが変わるので、中心が内側になるように法線の向きを決める
+    let center = (near_00 + near_11 + far_00 + far_11) * 0.25;
+
+    [
+        plane(near_00, near_01, far_00, center),
+        plane(near_10, near_11, far_10, center),
+        plane(near_00, near_10, far_00, center),
+        plane(near_01, near_11, far_01, center),
+        plane(near_00, near_10, near_01, center),
+        plane(far_00, far_10, far_01, center),
+    ]
+}
+
+fn unproject(inverse_projection: Mat4, ndc: Vec3) -> Vec3 {
+    let position = inverse_projection * ndc.extend(1.0);
+    position.truncate() / position.w
+}
+
+//a, b, cを通り、insideの側に法線が向く平面
+fn plane(a: Vec3, b: Vec3, c: Vec3, inside: Vec3) -> Vec4 {
+    let normal = (b - a).cross(c - a).normalize();
+    let normal = if normal.dot(inside - a) < 0.0 {
+        -normal
+    } else {
+        normal
     };
+
+    normal.extend(-normal.dot(a))
+}
+
+//ディレクショナルライトはどのタイルにも届く
+//スポットライトも円錐ではなく、届く距離を半径にしたポイントライトと同じ球で判定する
+fn light_in_frustum(light: &Light, view: Mat4, planes: &[Vec4; 6]) -> bool {
+    if light.kind == LIGHT_DIRECTIONAL {
+        return true;
+    }
+
+    let center = (view * light.position.truncate().extend(1.0)).truncate();
+    let radius = light.position.w;
+
+    let mut index = 0;
+    while index < 6 {
+        let plane = unsafe { planes.index_unchecked(index) };
+
+        if plane.truncate().dot(center) + plane.w < -radius {
+            return false;
+        }
+
+        index += 1;
+    }
+
+    true
 }
 
+//ライトのタイルのヒートマップをシーンに重ねる時の不透明度
+const LIGHT_TILES_OPACITY: f32 = 0.5;
+
+//main_cs_light_cullingが残したタイルごとのライトの数を、オーバードローと同じ色でシーンに重ねる
+//タイルの境目が分かるように、タイルの左端と上端のピクセルを暗くする
 #[spirv(fragment)]
-pub fn main_fs(
-    // layout(location = 0) out
+pub fn main_fs_light_tiles_heatmap(
+    output: &mut Vec4,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &[u32],
+    #[spirv(frag_coord)] frag_coord: Vec4,
+) {
+    *output = light_tiles_heatmap(light, light_tiles, frag_coord);
+}
+
+#[spirv(fragment)]
+pub fn main_fs_light_tiles_heatmap_encode_srgb(
     output: &mut Vec4,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &[u32],
+    #[spirv(frag_coord)] frag_coord: Vec4,
+) {
+    *output = encode_srgb(light_tiles_heatmap(light, light_tiles, frag_coord));
+}
+
+fn light_tiles_heatmap(light: &LightUniforms, light_tiles: &[u32], frag_coord: Vec4) -> Vec4 {
+    let count = tile_light_count(light, light_tiles, light_tile(light, frag_coord));
+    let color = overdraw_heatmap(count as u32);
+
+    let edge = frag_coord.x as u32 % TILE_SIZE == 0 || frag_coord.y as u32 % TILE_SIZE == 0;
+    let color = if edge { color * 0.5 } else { color };
+
+    color.extend(LIGHT_TILES_OPACITY)
+}
+
+//ホスト側のcompute::ComputeConstantsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct ComputeConstants {
+    pub count: u32,
+}
+
+//--compute-testでホスト側が結果を確認するのでcompute::expected_valueと同じ値を書き込む
+#[spirv(compute(threads(64)))]
+pub fn main_cs(
+    // gl_GlobalInvocationID
+    #[spirv(global_invocation_id)] id: UVec3,
+    // layout(push_constant) uniform
+    #[spirv(push_constant)] constants: &ComputeConstants,
+    // layout(set = 0, binding = 0) buffer
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] data: &mut [u32],
+) {
+    let index = id.x;
+
+    //最後のワークグループは要素数を超えるので書き込まない
+    if index < constants.count {
+        unsafe { *data.index_unchecked_mut(index as usize) = index * 2 + 1 };
+    }
+}
+
+//パーティクルの重力加速度
+const GRAVITY: f32 = 2.0;
+
+//床や壁で跳ね返った時に残る速度の割合
+const RESTITUTION: f32 = 0.8;
+
+//ホスト側のparticles::Particleと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Particle {
+    pub position: Vec4,
+    pub velocity: Vec4,
+    pub color: Vec4,
+}
+
+//ホスト側のparticles::ParticleConstantsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct ParticleConstants {
+    pub delta_time: f32,
+    pub count: u32,
+}
+
+//前のフレームのパーティクルを重力で落下させ、-1.0から1.0の箱の中で跳ね返らせてこのフレームのバッファに書き込む
+#[spirv(compute(threads(64)))]
+pub fn main_cs_particles(
+    #[spirv(global_invocation_id)] id: UVec3,
+    #[spirv(push_constant)] constants: &ParticleConstants,
+    // 前のフレームのバッファ
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] previous: &[Particle],
+    // このフレームのバッファ
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] particles: &mut [Particle],
+) {
+    let index = id.x;
+
+    if index >= constants.count {
+        return;
+    }
+
+    let particle = unsafe { previous.index_unchecked(index as usize) };
+    let delta_time = constants.delta_time;
+
+    let mut velocity = particle.velocity;
+    velocity.y -= GRAVITY * delta_time;
+
+    let mut position = particle.position + velocity * delta_time;
+
+    if position.y < -1.0 {
+        position.y = -1.0;
+        velocity.y = -velocity.y * RESTITUTION;
+    }
+
+    if position.x < -1.0 {
+        position.x = -1.0;
+        velocity.x = -velocity.x * RESTITUTION;
+    } else if position.x > 1.0 {
+        position.x = 1.0;
+        velocity.x = -velocity.x * RESTITUTION;
+    }
+
+    unsafe {
+        *particles.index_unchecked_mut(index as usize) = Particle {
+            position,
+            velocity,
+            color: particle.color,
+        };
+    }
+}
+
+//main_cs_particlesが書き込んだバッファを頂点バッファとして読んで点を描画する
+#[spirv(vertex)]
+pub fn main_vs_particle(
     // layout(location = 0) in
-    color: Vec3A,
+    position: Vec4,
+    // layout(location = 1) in
+    in_color: Vec4,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(position)] out_pos: &mut Vec4,
+    // gl_PointSize、POINT_LISTでは書き込まないと大きさが未定義になる
+    // 1.0より大きくするにはlarge_pointsが必要
+    #[spirv(point_size)] point_size: &mut f32,
+    color: &mut Vec3A,
 ) {
-    *output = color.extend(1.0);
+    *out_pos = ubo.proj * ubo.view * position.truncate().extend(1.0);
+
+    *point_size = 1.0;
+
+    *color = in_color.truncate().into();
+}
+
+//ホスト側のprocedural_texture::ProceduralConstantsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct ProceduralConstants {
+    pub time: f32,
+    pub size: u32,
+}
+
+//プロシージャルテクスチャのチェッカー模様の1辺のマス目の数
+const PROCEDURAL_CELLS: f32 = 8.0;
+
+//格子点ごとの0.0から1.0の疑似乱数
+fn hash(cell: Vec2) -> f32 {
+    let value = cell.dot(Vec2::new(127.1, 311.7)).sin() * 43758.547;
+    value - value.floor()
+}
+
+//格子点の乱数をsmoothstepで補間したバリューノイズ
+fn value_noise(position: Vec2) -> f32 {
+    let cell = position.floor();
+    let fraction = position - cell;
+    let t = fraction * fraction * (Vec2::splat(3.0) - 2.0 * fraction);
+
+    let a = hash(cell);
+    let b = hash(cell + Vec2::X);
+    let c = hash(cell + Vec2::Y);
+    let d = hash(cell + Vec2::ONE);
+
+    let top = a + (b - a) * t.x;
+    let bottom = c + (d - c) * t.x;
+    top + (bottom - top) * t.y
+}
+
+//timeで流れるチェッカー模様にノイズで濃淡を付けた色、値はリニア
+fn procedural_color(id: UVec2, constants: &ProceduralConstants) -> Vec4 {
+    let uv = Vec2::new(id.x as f32, id.y as f32) / constants.size as f32;
+    let scrolled = uv * PROCEDURAL_CELLS + Vec2::new(constants.time * 0.5, 0.0);
+
+    let checker = ((scrolled.x.floor() + scrolled.y.floor()) as i32 & 1) as f32;
+    let noise = value_noise(uv * 16.0 + Vec2::splat(constants.time * 0.2));
+
+    let dark = Vec3::new(0.05, 0.1, 0.3);
+    let light = Vec3::new(0.9, 0.6, 0.2);
+    let color = dark + (light - dark) * checker;
+
+    (color * (0.6 + 0.4 * noise)).extend(1.0)
+}
+
+//size×sizeのテクスチャの各テクセルにprocedural_colorを書き込む
+//sRGBのフォーマットはstorage imageに使えないのでUNORMのまま書き込み、リニアの値として読む
+#[spirv(compute(threads(8, 8)))]
+pub fn main_cs_procedural_texture(
+    #[spirv(global_invocation_id)] id: UVec3,
+    #[spirv(push_constant)] constants: &ProceduralConstants,
+    #[spirv(descriptor_set = 0, binding = 0)] image: &Image!(2D, format=rgba8, sampled=false),
+) {
+    let id = id.truncate();
+
+    if id.x >= constants.size || id.y >= constants.size {
+        return;
+    }
+
+    unsafe { image.write(id, procedural_color(id, constants)) };
+}
+
+//STORAGE_IMAGEにR8G8B8A8_UNORMが使えないデバイスで使う
+#[spirv(compute(threads(8, 8)))]
+pub fn main_cs_procedural_texture_rgba32f(
+    #[spirv(global_invocation_id)] id: UVec3,
+    #[spirv(push_constant)] constants: &ProceduralConstants,
+    #[spirv(descriptor_set = 0, binding = 0)] image: &Image!(2D, format=rgba32f, sampled=false),
+) {
+    let id = id.truncate();
+
+    if id.x >= constants.size || id.y >= constants.size {
+        return;
+    }
+
+    unsafe { image.write(id, procedural_color(id, constants)) };
+}
+
+//ホスト側のmipmap::DownsampleConstantsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct DownsampleConstants {
+    pub src_size: UVec2,
+    pub dst_size: UVec2,
+    //1の場合はsRGBの値をリニアに戻してから平均する
+    pub srgb: u32,
+}
+
+//sRGBでエンコードされた値をリニアに戻す、アルファはそのまま
+fn decode_srgb(color: Vec4) -> Vec4 {
+    fn decode(value: f32) -> f32 {
+        if value <= 0.04045 {
+            value / 12.92
+        } else {
+            ((value + 0.055) / 1.055).powf(2.4)
+        }
+    }
+
+    Vec4::new(decode(color.x), decode(color.y), decode(color.z), color.w)
+}
+
+//src_sizeの段の2×2のテクセルを平均してdst_sizeの段の1テクセルにする
+//奇数の大きさの段では端のテクセルを繰り返して読む
+#[spirv(compute(threads(8, 8)))]
+pub fn main_cs_downsample(
+    #[spirv(global_invocation_id)] id: UVec3,
+    #[spirv(push_constant)] constants: &DownsampleConstants,
+    #[spirv(descriptor_set = 0, binding = 0)] src: &Image!(2D, format=rgba8, sampled=false),
+    #[spirv(descriptor_set = 0, binding = 1)] dst: &Image!(2D, format=rgba8, sampled=false),
+) {
+    let id = id.truncate();
+
+    if id.x >= constants.dst_size.x || id.y >= constants.dst_size.y {
+        return;
+    }
+
+    let last = constants.src_size - UVec2::ONE;
+    let mut sum = Vec4::ZERO;
+
+    for offset in [UVec2::ZERO, UVec2::X, UVec2::Y, UVec2::ONE] {
+        let texel: Vec4 = src.read((id * 2 + offset).min(last));
+        sum += if constants.srgb == 1 {
+            decode_srgb(texel)
+        } else {
+            texel
+        };
+    }
+
+    let average = sum * 0.25;
+    let color = if constants.srgb == 1 {
+        encode_srgb(average)
+    } else {
+        average
+    };
+
+    unsafe { dst.write(id, color) };
+}
+
+//TRIANGLE_STRIPの14頂点で立方体を作る時の各頂点のx, y, z座標のビット列
+//i番目のビットがi番目の頂点の座標で、0が-1.0で1が1.0になる
+const SKYBOX_X_BITS: u32 = 0x287a;
+const SKYBOX_Y_BITS: u32 = 0x02af;
+const SKYBOX_Z_BITS: u32 = 0x31e3;
+
+//原点を中心とする一辺2の立方体を描画し、立方体上の位置をキューブマップを引く方向として渡す
+//view行列の平行移動を除くのでカメラが移動してもスカイボックスは常に同じ距離に見える
+#[spirv(vertex)]
+pub fn main_vs_skybox(
+    #[spirv(vertex_index)] vert_id: i32,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(position)] out_pos: &mut Vec4,
+    // layout(location = 0) out
+    direction: &mut Vec3A,
+) {
+    let index = vert_id as u32;
+    let corner = |bits: u32| ((bits >> index) & 1) as f32 * 2.0 - 1.0;
+
+    let position = Vec3::new(
+        corner(SKYBOX_X_BITS),
+        corner(SKYBOX_Y_BITS),
+        corner(SKYBOX_Z_BITS),
+    );
+
+    let mut view = ubo.view;
+    view.w_axis = Vec4::W;
+
+    let clip = ubo.proj * view * position.extend(1.0);
+
+    //zをw * far_depthにして深度値を常に一番遠い値にする
+    *out_pos = Vec4::new(clip.x, clip.y, clip.w * ubo.far_depth, clip.w);
+
+    *direction = position.into();
+}
+
+//ホスト側のocclusion_culling::OcclusionConstantsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct OcclusionConstants {
+    pub min: Vec4,
+    pub max: Vec4,
+}
+
+//スカイボックスと同じ14頂点の立方体をワールド座標のバウンディングボックスに合わせて描画する
+//OCCLUSIONクエリで深度テストを通ったサンプル数を数えるだけなのでフラグメントシェーダーは無い
+#[spirv(vertex)]
+pub fn main_vs_occlusion_box(
+    #[spirv(vertex_index)] vert_id: i32,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(push_constant)] constants: &OcclusionConstants,
+    #[spirv(position)] out_pos: &mut Vec4,
+) {
+    let index = vert_id as u32;
+    let corner = |bits: u32| ((bits >> index) & 1) as f32;
+
+    let t = Vec3::new(
+        corner(SKYBOX_X_BITS),
+        corner(SKYBOX_Y_BITS),
+        corner(SKYBOX_Z_BITS),
+    );
+
+    let min = constants.min.truncate();
+    let max = constants.max.truncate();
+    let position = min + (max - min) * t;
+
+    *out_pos = ubo.proj * ubo.view * position.extend(1.0);
+}
+
+//キューブマップはSRGBフォーマットなのでサンプリングした値はリニアになっている
+#[spirv(fragment)]
+pub fn main_fs_skybox(
+    output: &mut Vec4,
+    direction: Vec3A,
+    // layout(set = 1, binding = 0) uniform textureCube
+    #[spirv(descriptor_set = 1, binding = 0)] cubemap: &Image!(cube, type=f32, sampled),
+    // layout(set = 1, binding = 1) uniform sampler
+    #[spirv(descriptor_set = 1, binding = 1)] sampler: &Sampler,
+) {
+    *output = cubemap.sample(*sampler, Vec3::from(direction));
+}
+
+#[spirv(fragment)]
+pub fn main_fs_skybox_encode_srgb(
+    output: &mut Vec4,
+    direction: Vec3A,
+    #[spirv(descriptor_set = 1, binding = 0)] cubemap: &Image!(cube, type=f32, sampled),
+    #[spirv(descriptor_set = 1, binding = 1)] sampler: &Sampler,
+) {
+    *output = encode_srgb(cubemap.sample(*sampler, Vec3::from(direction)));
+}
+
+//画面全体を覆う三角形を頂点バッファを使わずに描画する
+//(-1, -1), (3, -1), (-1, 3)の三角形で、画面の範囲のuvが0.0から1.0になる
+#[spirv(vertex)]
+pub fn main_vs_fullscreen(
+    #[spirv(vertex_index)] vert_id: i32,
+    #[spirv(position)] out_pos: &mut Vec4,
+    // layout(location = 0) out
+    uv: &mut Vec2,
+) {
+    let index = vert_id as u32;
+
+    *uv = Vec2::new(((index << 1) & 2) as f32, (index & 2) as f32);
+
+    *out_pos = (*uv * 2.0 - Vec2::ONE).extend(0.0).extend(1.0);
+}
+
+//ビネットで画面の中心からこの距離より外側を暗くし始める
+const VIGNETTE_INNER: f32 = 0.4;
+//この距離で最も暗くなる
+const VIGNETTE_OUTER: f32 = 0.9;
+
+//前のパスの画像の色を反転する
+//前のパスの画像はリニアの値で保持されている
+#[spirv(fragment)]
+pub fn main_fs_post_invert(
+    output: &mut Vec4,
+    uv: Vec2,
+    // layout(set = 0, binding = 0) uniform texture2D
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    // layout(set = 0, binding = 1) uniform sampler
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+) {
+    *output = post_invert(source.sample(*sampler, uv));
+}
+
+#[spirv(fragment)]
+pub fn main_fs_post_invert_encode_srgb(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+) {
+    *output = encode_srgb(post_invert(source.sample(*sampler, uv)));
+}
+
+#[spirv(fragment)]
+pub fn main_fs_post_invert_encode_pq(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+) {
+    *output = encode_pq(post_invert(source.sample(*sampler, uv)));
+}
+
+fn post_invert(color: Vec4) -> Vec4 {
+    (Vec3::ONE - color.truncate()).extend(color.w)
+}
+
+//前のパスの画像の端を暗くする
+#[spirv(fragment)]
+pub fn main_fs_post_vignette(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+) {
+    *output = post_vignette(source.sample(*sampler, uv), uv);
+}
+
+#[spirv(fragment)]
+pub fn main_fs_post_vignette_encode_srgb(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+) {
+    *output = encode_srgb(post_vignette(source.sample(*sampler, uv), uv));
+}
+
+#[spirv(fragment)]
+pub fn main_fs_post_vignette_encode_pq(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+) {
+    *output = encode_pq(post_vignette(source.sample(*sampler, uv), uv));
+}
+
+fn post_vignette(color: Vec4, uv: Vec2) -> Vec4 {
+    let distance = (uv - Vec2::splat(0.5)).length() * core::f32::consts::SQRT_2;
+    let t = ((distance - VIGNETTE_INNER) / (VIGNETTE_OUTER - VIGNETTE_INNER)).clamp(0.0, 1.0);
+    //smoothstep
+    let darkening = t * t * (3.0 - 2.0 * t);
+
+    (color.truncate() * (1.0 - darkening)).extend(color.w)
+}
+
+//前のパスの画像をそのまま書き出す、swapchainに合わせたエンコードだけを行う
+#[spirv(fragment)]
+pub fn main_fs_post_passthrough(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+) {
+    *output = source.sample(*sampler, uv);
+}
+
+#[spirv(fragment)]
+pub fn main_fs_post_passthrough_encode_srgb(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+) {
+    *output = encode_srgb(source.sample(*sampler, uv));
+}
+
+#[spirv(fragment)]
+pub fn main_fs_post_passthrough_encode_pq(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+) {
+    *output = encode_pq(source.sample(*sampler, uv));
+}
+
+//ホスト側のbloom::BloomConstantsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct BloomConstants {
+    //読む画像の1テクセル分のuvの大きさ
+    pub texel_size: Vec2,
+    pub threshold: f32,
+    pub intensity: f32,
+}
+
+//しきい値の手前のこの幅で、取り出す割合を0から滑らかに上げる
+const BLOOM_KNEE: f32 = 0.2;
+
+//colorのしきい値より明るい分だけを残す
+fn bloom_threshold(color: Vec3, threshold: f32) -> Vec3 {
+    let brightness = color.x.max(color.y).max(color.z);
+    let soft = (brightness - threshold + BLOOM_KNEE).clamp(0.0, 2.0 * BLOOM_KNEE);
+    let soft = soft * soft / (4.0 * BLOOM_KNEE + 1e-4);
+    let contribution = soft.max(brightness - threshold) / brightness.max(1e-4);
+
+    color * contribution
+}
+
+//描画する大きさの画像を半分の大きさに縮めながら、しきい値より明るい部分を取り出す
+//1つのピクセルが入力の2x2のピクセルを覆うので、その中心の4点を平均する
+#[spirv(fragment)]
+pub fn main_fs_bloom_extract(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    #[spirv(push_constant)] constants: &BloomConstants,
+) {
+    let half = constants.texel_size * 0.5;
+
+    let a: Vec4 = source.sample(*sampler, uv + Vec2::new(-half.x, -half.y));
+    let b: Vec4 = source.sample(*sampler, uv + Vec2::new(half.x, -half.y));
+    let c: Vec4 = source.sample(*sampler, uv + Vec2::new(-half.x, half.y));
+    let d: Vec4 = source.sample(*sampler, uv + Vec2::new(half.x, half.y));
+
+    let color = (a + b + c + d).truncate() * 0.25;
+
+    *output = bloom_threshold(color, constants.threshold).extend(1.0);
+}
+
+//dual filterの縮小、中心と斜めの4点を中心に重みを付けて平均する
+//斜めの点は入力の2x2のピクセルの間をLINEARで補間するので、少ない回数で広い範囲をぼかせる
+#[spirv(fragment)]
+pub fn main_fs_bloom_downsample(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    #[spirv(push_constant)] constants: &BloomConstants,
+) {
+    let half = constants.texel_size * 0.5;
+
+    let center: Vec4 = source.sample(*sampler, uv);
+    let a: Vec4 = source.sample(*sampler, uv + Vec2::new(-half.x, -half.y));
+    let b: Vec4 = source.sample(*sampler, uv + Vec2::new(half.x, -half.y));
+    let c: Vec4 = source.sample(*sampler, uv + Vec2::new(-half.x, half.y));
+    let d: Vec4 = source.sample(*sampler, uv + Vec2::new(half.x, half.y));
+
+    let color = (center * 4.0 + a + b + c + d).truncate() / 8.0;
+
+    *output = color.extend(1.0);
+}
+
+//dual filterの拡大、set = 0の1段小さい画像を上下左右と斜めの8点で広げ、set = 1の同じ大きさの縮めた画像に足す
+//texel_sizeは1段小さい画像のもの
+#[spirv(fragment)]
+pub fn main_fs_bloom_upsample(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] lower: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] lower_sampler: &Sampler,
+    #[spirv(descriptor_set = 1, binding = 0)] current: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 1, binding = 1)] current_sampler: &Sampler,
+    #[spirv(push_constant)] constants: &BloomConstants,
+) {
+    let half = constants.texel_size * 0.5;
+
+    let mut sum = Vec4::ZERO;
+
+    //上下左右は1テクセル離れた点、重みは1
+    let edges = [
+        Vec2::new(-2.0 * half.x, 0.0),
+        Vec2::new(2.0 * half.x, 0.0),
+        Vec2::new(0.0, -2.0 * half.y),
+        Vec2::new(0.0, 2.0 * half.y),
+    ];
+    //斜めは半テクセル離れた点、重みは2
+    let corners = [
+        Vec2::new(-half.x, -half.y),
+        Vec2::new(half.x, -half.y),
+        Vec2::new(-half.x, half.y),
+        Vec2::new(half.x, half.y),
+    ];
+
+    let mut i = 0;
+    while i < 4 {
+        let edge: Vec4 = lower.sample(*lower_sampler, uv + unsafe { *edges.index_unchecked(i) });
+        let corner: Vec4 =
+            lower.sample(*lower_sampler, uv + unsafe { *corners.index_unchecked(i) });
+        sum += edge + corner * 2.0;
+        i += 1;
+    }
+
+    let current: Vec4 = current.sample(*current_sampler, uv);
+
+    *output = (sum.truncate() / 12.0 + current.truncate()).extend(1.0);
+}
+
+//ホスト側のtonemap::TonemapConstantsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct TonemapConstants {
+    //色に掛ける倍率
+    pub exposure: f32,
+    //TONEMAP_*のどれか
+    pub operator: u32,
+}
+
+//main_fs_post_bloomはブルームの足す割合とトーンマッピングの両方を読む
+//ホスト側はBloomConstantsの後ろのoffsetにTonemapConstantsを書き込む
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct PostBloomConstants {
+    pub bloom: BloomConstants,
+    pub tonemap: TonemapConstants,
+}
+
+//ホスト側のtonemap::Tonemapperと同じ値
+const TONEMAP_REINHARD: u32 = 1;
+const TONEMAP_EXPOSURE: u32 = 2;
+
+//露出を掛けてから、operatorの方法で0.0から1.0に収める
+fn tonemap(color: Vec3, constants: &TonemapConstants) -> Vec3 {
+    let exposed = color * constants.exposure;
+
+    if constants.operator == TONEMAP_REINHARD {
+        exposed / (exposed + Vec3::ONE)
+    } else if constants.operator == TONEMAP_EXPOSURE {
+        exposed.clamp(Vec3::ZERO, Vec3::ONE)
+    } else {
+        tonemap_aces(exposed)
+    }
+}
+
+//ACESのフィルミックカーブの近似、1.0より明るい値を0.0から1.0に収める
+fn tonemap_aces(color: Vec3) -> Vec3 {
+    const A: f32 = 2.51;
+    const B: f32 = 0.03;
+    const C: f32 = 2.43;
+    const D: f32 = 0.59;
+    const E: f32 = 0.14;
+
+    let numerator = color * (color * A + Vec3::splat(B));
+    let denominator = color * (color * C + Vec3::splat(D)) + Vec3::splat(E);
+
+    (numerator / denominator).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+//前のパスの画像にset = 1のぼかした画像をintensityの割合で足し、トーンマッピングする
+//ブルームの後にPostEffect::Tonemapを重ねなくて良いように、1.0で切り詰める前にここでトーンマッピングする
+#[spirv(fragment)]
+pub fn main_fs_post_bloom(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    #[spirv(descriptor_set = 1, binding = 0)] bloom: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 1, binding = 1)] bloom_sampler: &Sampler,
+    #[spirv(push_constant)] constants: &PostBloomConstants,
+) {
+    *output = post_bloom(
+        source.sample(*sampler, uv),
+        bloom.sample(*bloom_sampler, uv),
+        constants,
+    );
+}
+
+#[spirv(fragment)]
+pub fn main_fs_post_bloom_encode_srgb(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    #[spirv(descriptor_set = 1, binding = 0)] bloom: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 1, binding = 1)] bloom_sampler: &Sampler,
+    #[spirv(push_constant)] constants: &PostBloomConstants,
+) {
+    *output = encode_srgb(post_bloom(
+        source.sample(*sampler, uv),
+        bloom.sample(*bloom_sampler, uv),
+        constants,
+    ));
+}
+
+#[spirv(fragment)]
+pub fn main_fs_post_bloom_encode_pq(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    #[spirv(descriptor_set = 1, binding = 0)] bloom: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 1, binding = 1)] bloom_sampler: &Sampler,
+    #[spirv(push_constant)] constants: &PostBloomConstants,
+) {
+    *output = encode_pq(post_bloom(
+        source.sample(*sampler, uv),
+        bloom.sample(*bloom_sampler, uv),
+        constants,
+    ));
+}
+
+fn post_bloom(color: Vec4, bloom: Vec4, constants: &PostBloomConstants) -> Vec4 {
+    let hdr = color.truncate() + bloom.truncate() * constants.bloom.intensity;
+
+    tonemap(hdr, &constants.tonemap).extend(color.w)
+}
+
+//前のパスの画像に露出を掛けてトーンマッピングする
+#[spirv(fragment)]
+pub fn main_fs_post_tonemap(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    #[spirv(push_constant)] constants: &TonemapConstants,
+) {
+    *output = post_tonemap(source.sample(*sampler, uv), constants);
+}
+
+#[spirv(fragment)]
+pub fn main_fs_post_tonemap_encode_srgb(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    #[spirv(push_constant)] constants: &TonemapConstants,
+) {
+    *output = encode_srgb(post_tonemap(source.sample(*sampler, uv), constants));
+}
+
+#[spirv(fragment)]
+pub fn main_fs_post_tonemap_encode_pq(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] source: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    #[spirv(push_constant)] constants: &TonemapConstants,
+) {
+    *output = encode_pq(post_tonemap(source.sample(*sampler, uv), constants));
+}
+
+fn post_tonemap(color: Vec4, constants: &TonemapConstants) -> Vec4 {
+    tonemap(color.truncate(), constants).extend(color.w)
+}
+
+//頂点カラーはリニアの値として扱い、set = 0, binding = 2と3のライトでライティングする
+#[allow(clippy::too_many_arguments)]
+#[spirv(fragment)]
+pub fn main_fs(
+    // layout(location = 0) out
+    output: &mut Vec4,
+    // layout(location = 0) in
+    color: Vec3A,
+    // layout(location = 1) in
+    world_position: Vec3A,
+    // layout(location = 2) in
+    normal: Vec3A,
+    // layout(set = 0, binding = 0) uniform
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    // layout(set = 0, binding = 2) uniform
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    // layout(set = 0, binding = 3) buffer、light_count個だけ読む
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] lights: &[Light],
+    // layout(set = 0, binding = 4) buffer、タイルごとのライトの番号
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &[u32],
+    // gl_FragCoord、どのタイルかを決める
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    // layout(constant_id = 0) const uint、1の場合は法線を、2の場合は深度を色にする
+    #[spirv(spec_constant(id = 0, default = 0))] debug_view: u32,
+) {
+    *output = match debug_view {
+        0 => lighting(
+            color,
+            world_position,
+            normal,
+            light,
+            lights,
+            light_tiles,
+            frag_coord,
+            1.0,
+        ),
+        _ => debug_view_color(debug_view, world_position, normal, ubo),
+    }
+    .extend(1.0);
+}
+
+//UNORMのswapchainに書き込む場合はハードウェアが変換しないので最後にシェーダーでエンコードする
+#[allow(clippy::too_many_arguments)]
+#[spirv(fragment)]
+pub fn main_fs_encode_srgb(
+    output: &mut Vec4,
+    color: Vec3A,
+    world_position: Vec3A,
+    normal: Vec3A,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] lights: &[Light],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &[u32],
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    #[spirv(spec_constant(id = 0, default = 0))] debug_view: u32,
+) {
+    let color = match debug_view {
+        0 => lighting(
+            color,
+            world_position,
+            normal,
+            light,
+            lights,
+            light_tiles,
+            frag_coord,
+            1.0,
+        ),
+        _ => debug_view_color(debug_view, world_position, normal, ubo),
+    };
+
+    *output = encode_srgb(color.extend(1.0));
+}
+
+//1の場合は-1から1の法線を0から1の色にする
+//2の場合はカメラからの距離をnearで黒、farで白にする、ビュー空間のzを使うので透視投影でも正射影でも線形になる
+//深度バッファの値は読まないので、深度値を逆にしたり無限遠にしたりしても同じ表示になる
+fn debug_view_color(
+    debug_view: u32,
+    world_position: Vec3A,
+    normal: Vec3A,
+    ubo: &UniformBufferObject,
+) -> Vec3 {
+    if debug_view == 1 {
+        Vec3::from(normal.normalize()) * 0.5 + Vec3::splat(0.5)
+    } else {
+        let view_position = ubo.view * Vec3::from(world_position).extend(1.0);
+        let depth = (-view_position.z - ubo.near) / (ubo.far - ubo.near);
+        Vec3::splat(depth.clamp(0.0, 1.0))
+    }
+}
+
+//オーバードローの表示で、set = 2のstorage bufferのこのピクセルの数を1つ増やす
+//先頭の要素は画像の幅で、ピクセルごとの数はその後ろに行の順に並ぶ
+//カラーアタッチメントには書き込まないので出力は無い
+#[spirv(fragment)]
+pub fn main_fs_overdraw(
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    #[spirv(storage_buffer, descriptor_set = 2, binding = 0)] counts: &mut [u32],
+) {
+    unsafe {
+        let index = overdraw_index(frag_coord, *counts.index_unchecked(0));
+
+        spirv_std::arch::atomic_i_increment::<
+            u32,
+            { spirv_std::memory::Scope::Device as u32 },
+            { spirv_std::memory::Semantics::NONE.bits() },
+        >(counts.index_unchecked_mut(index));
+    }
+}
+
+//main_fs_overdrawが数えたset = 0のstorage bufferを、フルスクリーンの三角形で色に変える
+#[spirv(fragment)]
+pub fn main_fs_overdraw_heatmap(
+    output: &mut Vec4,
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] counts: &[u32],
+) {
+    *output = overdraw_heatmap(read_overdraw(counts, frag_coord)).extend(1.0);
+}
+
+#[spirv(fragment)]
+pub fn main_fs_overdraw_heatmap_encode_srgb(
+    output: &mut Vec4,
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] counts: &[u32],
+) {
+    *output = encode_srgb(overdraw_heatmap(read_overdraw(counts, frag_coord)).extend(1.0));
+}
+
+fn read_overdraw(counts: &[u32], frag_coord: Vec4) -> u32 {
+    unsafe { *counts.index_unchecked(overdraw_index(frag_coord, *counts.index_unchecked(0))) }
+}
+
+//ピクセルの中心のfrag_coordから、幅widthのオーバードローのstorage bufferでの位置を求める
+fn overdraw_index(frag_coord: Vec4, width: u32) -> usize {
+    1 + frag_coord.y as usize * width as usize + frag_coord.x as usize
+}
+
+//描画されなかったピクセルは黒で、1回の青から緑、黄、赤と増やし、OVERDRAW_RAMP_MAX回以上は赤のままにする
+const OVERDRAW_RAMP_MAX: u32 = 8;
+
+fn overdraw_heatmap(count: u32) -> Vec3 {
+    if count == 0 {
+        return Vec3::ZERO;
+    }
+
+    let blue = Vec3::new(0.0, 0.0, 1.0);
+    let green = Vec3::new(0.0, 1.0, 0.0);
+    let yellow = Vec3::new(1.0, 1.0, 0.0);
+    let red = Vec3::new(1.0, 0.0, 0.0);
+
+    //1回目から最大回数までを3つの区間に分ける
+    let t = (count.min(OVERDRAW_RAMP_MAX) - 1) as f32 / (OVERDRAW_RAMP_MAX - 1) as f32 * 3.0;
+
+    if t < 1.0 {
+        blue.lerp(green, t)
+    } else if t < 2.0 {
+        green.lerp(yellow, t - 1.0)
+    } else {
+        yellow.lerp(red, t - 2.0)
+    }
+}
+
+//法線を持たないパーティクルは頂点カラーをそのまま書き込む
+#[spirv(fragment)]
+pub fn main_fs_unlit(output: &mut Vec4, color: Vec3A) {
+    *output = color.extend(1.0);
+}
+
+#[spirv(fragment)]
+pub fn main_fs_unlit_encode_srgb(output: &mut Vec4, color: Vec3A) {
+    *output = encode_srgb(color.extend(1.0));
+}
+
+//set = 2の全てのマテリアルのテクスチャからpush constantの番号のものを選んでサンプリングする
+//番号は描画ごとに一定なのでNonUniformの修飾は要らない
+#[allow(clippy::too_many_arguments)]
+#[spirv(fragment)]
+pub fn main_fs_bindless(
+    output: &mut Vec4,
+    color: Vec3A,
+    world_position: Vec3A,
+    normal: Vec3A,
+    // layout(location = 3) in
+    tex_coord: Vec2,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] lights: &[Light],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &[u32],
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    #[spirv(push_constant)] material: &MaterialConstants,
+    // layout(set = 2, binding = 0) uniform sampler
+    #[spirv(descriptor_set = 2, binding = 0)] sampler: &Sampler,
+    // layout(set = 2, binding = 1) uniform texture2D textures[MAX_MATERIAL_TEXTURES]
+    #[spirv(descriptor_set = 2, binding = 1)] textures: &[MaterialTexture; MAX_MATERIAL_TEXTURES],
+) {
+    let texture = unsafe { textures.index_unchecked(material.index as usize) };
+    let albedo = Vec3A::from(color) * Vec3A::from(texture.sample(*sampler, tex_coord).truncate());
+
+    *output = lighting(
+        albedo,
+        world_position,
+        normal,
+        light,
+        lights,
+        light_tiles,
+        frag_coord,
+        1.0,
+    )
+    .extend(1.0);
+}
+
+#[allow(clippy::too_many_arguments)]
+#[spirv(fragment)]
+pub fn main_fs_bindless_encode_srgb(
+    output: &mut Vec4,
+    color: Vec3A,
+    world_position: Vec3A,
+    normal: Vec3A,
+    tex_coord: Vec2,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] lights: &[Light],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &[u32],
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    #[spirv(push_constant)] material: &MaterialConstants,
+    #[spirv(descriptor_set = 2, binding = 0)] sampler: &Sampler,
+    #[spirv(descriptor_set = 2, binding = 1)] textures: &[MaterialTexture; MAX_MATERIAL_TEXTURES],
+) {
+    let texture = unsafe { textures.index_unchecked(material.index as usize) };
+    let albedo = Vec3A::from(color) * Vec3A::from(texture.sample(*sampler, tex_coord).truncate());
+
+    *output = encode_srgb(
+        lighting(
+            albedo,
+            world_position,
+            normal,
+            light,
+            lights,
+            light_tiles,
+            frag_coord,
+            1.0,
+        )
+        .extend(1.0),
+    );
+}
+
+//set = 2のテクスチャ配列からlayerのレイヤーをサンプリングする
+#[allow(clippy::too_many_arguments)]
+#[spirv(fragment)]
+pub fn main_fs_texture_array(
+    output: &mut Vec4,
+    color: Vec3A,
+    world_position: Vec3A,
+    normal: Vec3A,
+    tex_coord: Vec2,
+    #[spirv(flat)] layer: u32,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] lights: &[Light],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &[u32],
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    #[spirv(descriptor_set = 2, binding = 0)] sampler: &Sampler,
+    #[spirv(descriptor_set = 2, binding = 1)] textures: &Image!(2D, type=f32, sampled, arrayed),
+) {
+    let albedo = texture_array_albedo(color, tex_coord, layer, sampler, textures);
+
+    *output = lighting(
+        albedo,
+        world_position,
+        normal,
+        light,
+        lights,
+        light_tiles,
+        frag_coord,
+        1.0,
+    )
+    .extend(1.0);
+}
+
+#[allow(clippy::too_many_arguments)]
+#[spirv(fragment)]
+pub fn main_fs_texture_array_encode_srgb(
+    output: &mut Vec4,
+    color: Vec3A,
+    world_position: Vec3A,
+    normal: Vec3A,
+    tex_coord: Vec2,
+    #[spirv(flat)] layer: u32,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] lights: &[Light],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &[u32],
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    #[spirv(descriptor_set = 2, binding = 0)] sampler: &Sampler,
+    #[spirv(descriptor_set = 2, binding = 1)] textures: &Image!(2D, type=f32, sampled, arrayed),
+) {
+    let albedo = texture_array_albedo(color, tex_coord, layer, sampler, textures);
+
+    *output = encode_srgb(
+        lighting(
+            albedo,
+            world_position,
+            normal,
+            light,
+            lights,
+            light_tiles,
+            frag_coord,
+            1.0,
+        )
+        .extend(1.0),
+    );
+}
+
+//配列のテクスチャの座標は3つ目の成分がレイヤーの番号
+fn texture_array_albedo(
+    color: Vec3A,
+    tex_coord: Vec2,
+    layer: u32,
+    sampler: &Sampler,
+    textures: &Image!(2D, type=f32, sampled, arrayed),
+) -> Vec3A {
+    let texel: Vec4 = textures.sample(*sampler, tex_coord.extend(layer as f32));
+
+    color * Vec3A::from(texel.truncate())
+}
+
+//main_fs_bindlessが使えない場合に、マテリアルごとのset = 2からテクスチャを1枚だけ受け取る
+#[allow(clippy::too_many_arguments)]
+#[spirv(fragment)]
+pub fn main_fs_textured(
+    output: &mut Vec4,
+    color: Vec3A,
+    world_position: Vec3A,
+    normal: Vec3A,
+    tex_coord: Vec2,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] lights: &[Light],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &[u32],
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    #[spirv(descriptor_set = 2, binding = 0)] sampler: &Sampler,
+    // layout(set = 2, binding = 1) uniform texture2D
+    #[spirv(descriptor_set = 2, binding = 1)] texture: &MaterialTexture,
+) {
+    let albedo = Vec3A::from(color) * Vec3A::from(texture.sample(*sampler, tex_coord).truncate());
+
+    *output = lighting(
+        albedo,
+        world_position,
+        normal,
+        light,
+        lights,
+        light_tiles,
+        frag_coord,
+        1.0,
+    )
+    .extend(1.0);
+}
+
+#[allow(clippy::too_many_arguments)]
+#[spirv(fragment)]
+pub fn main_fs_textured_encode_srgb(
+    output: &mut Vec4,
+    color: Vec3A,
+    world_position: Vec3A,
+    normal: Vec3A,
+    tex_coord: Vec2,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] lights: &[Light],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &[u32],
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    #[spirv(descriptor_set = 2, binding = 0)] sampler: &Sampler,
+    #[spirv(descriptor_set = 2, binding = 1)] texture: &MaterialTexture,
+) {
+    let albedo = Vec3A::from(color) * Vec3A::from(texture.sample(*sampler, tex_coord).truncate());
+
+    *output = encode_srgb(
+        lighting(
+            albedo,
+            world_position,
+            normal,
+            light,
+            lights,
+            light_tiles,
+            frag_coord,
+            1.0,
+        )
+        .extend(1.0),
+    );
+}
+
+//法線マップの接空間の法線をTBN行列でワールド座標に戻す
+//法線マップはDirectXと同じく緑がVの増える方向(画像の下向き)で、tangentのwでVの向きを決める
+//tangentのwが0の頂点はUVを持たないので頂点の法線をそのまま使う
+fn perturb_normal(
+    normal: Vec3A,
+    tangent: Vec4,
+    tex_coord: Vec2,
+    sampler: &Sampler,
+    normal_map: &MaterialTexture,
+    constants: &NormalMapConstants,
+) -> Vec3A {
+    let normal = Vec3::from(normal).normalize();
+
+    if constants.enabled == 0 || tangent.w == 0.0 {
+        return normal.into();
+    }
+
+    //補間されると法線と直交しなくなるのでGram-Schmidtで直す
+    let t = tangent.truncate();
+    let t = (t - normal * normal.dot(t)).normalize();
+    let b = normal.cross(t) * tangent.w;
+
+    let texel: Vec4 = normal_map.sample(*sampler, tex_coord);
+    let m = texel.truncate() * 2.0 - Vec3::ONE;
+
+    (t * m.x + b * m.y + normal * m.z).normalize().into()
+}
+
+//set = 2の法線マップで法線を変えてライティングする
+//debug_viewの特殊化定数はmain_fsと同じで、法線の表示はperturb_normalで変えた後の法線を色にする
+#[allow(clippy::too_many_arguments)]
+#[spirv(fragment)]
+pub fn main_fs_normal_mapped(
+    output: &mut Vec4,
+    color: Vec3A,
+    world_position: Vec3A,
+    normal: Vec3A,
+    tex_coord: Vec2,
+    tangent: Vec4,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] lights: &[Light],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &[u32],
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    #[spirv(descriptor_set = 2, binding = 0)] sampler: &Sampler,
+    #[spirv(descriptor_set = 2, binding = 1)] normal_map: &MaterialTexture,
+    #[spirv(push_constant)] constants: &NormalMapConstants,
+    #[spirv(spec_constant(id = 0, default = 0))] debug_view: u32,
+) {
+    let normal = perturb_normal(normal, tangent, tex_coord, sampler, normal_map, constants);
+
+    *output = match debug_view {
+        0 => lighting(
+            color,
+            world_position,
+            normal,
+            light,
+            lights,
+            light_tiles,
+            frag_coord,
+            1.0,
+        ),
+        _ => debug_view_color(debug_view, world_position, normal, ubo),
+    }
+    .extend(1.0);
+}
+
+#[allow(clippy::too_many_arguments)]
+#[spirv(fragment)]
+pub fn main_fs_normal_mapped_encode_srgb(
+    output: &mut Vec4,
+    color: Vec3A,
+    world_position: Vec3A,
+    normal: Vec3A,
+    tex_coord: Vec2,
+    tangent: Vec4,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] lights: &[Light],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &[u32],
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    #[spirv(descriptor_set = 2, binding = 0)] sampler: &Sampler,
+    #[spirv(descriptor_set = 2, binding = 1)] normal_map: &MaterialTexture,
+    #[spirv(push_constant)] constants: &NormalMapConstants,
+    #[spirv(spec_constant(id = 0, default = 0))] debug_view: u32,
+) {
+    let normal = perturb_normal(normal, tangent, tex_coord, sampler, normal_map, constants);
+
+    let color = match debug_view {
+        0 => lighting(
+            color,
+            world_position,
+            normal,
+            light,
+            lights,
+            light_tiles,
+            frag_coord,
+            1.0,
+        ),
+        _ => debug_view_color(debug_view, world_position, normal, ubo),
+    };
+
+    *output = encode_srgb(color.extend(1.0));
+}
+
+//main_fsのライティングでシャドウマップの影を付ける
+#[allow(clippy::too_many_arguments)]
+#[spirv(fragment)]
+pub fn main_fs_shadowed(
+    output: &mut Vec4,
+    color: Vec3A,
+    world_position: Vec3A,
+    normal: Vec3A,
+    // layout(location = 3) in
+    shadow_coord: Vec4,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] lights: &[Light],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &[u32],
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    // layout(set = 2, binding = 0) uniform texture2D
+    #[spirv(descriptor_set = 2, binding = 0)] shadow_map: &Image!(2D, type=f32, sampled, depth),
+    // layout(set = 2, binding = 1) uniform sampler、compare_opを指定したもの
+    #[spirv(descriptor_set = 2, binding = 1)] sampler: &Sampler,
+) {
+    let shadow = shadow_visibility(shadow_coord, ubo.shadow_texel_size, shadow_map, sampler);
+
+    *output = lighting(
+        color,
+        world_position,
+        normal,
+        light,
+        lights,
+        light_tiles,
+        frag_coord,
+        shadow,
+    )
+    .extend(1.0);
+}
+
+#[allow(clippy::too_many_arguments)]
+#[spirv(fragment)]
+pub fn main_fs_shadowed_encode_srgb(
+    output: &mut Vec4,
+    color: Vec3A,
+    world_position: Vec3A,
+    normal: Vec3A,
+    shadow_coord: Vec4,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] light: &LightUniforms,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] lights: &[Light],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] light_tiles: &[u32],
+    #[spirv(frag_coord)] frag_coord: Vec4,
+    #[spirv(descriptor_set = 2, binding = 0)] shadow_map: &Image!(2D, type=f32, sampled, depth),
+    #[spirv(descriptor_set = 2, binding = 1)] sampler: &Sampler,
+) {
+    let shadow = shadow_visibility(shadow_coord, ubo.shadow_texel_size, shadow_map, sampler);
+
+    *output = encode_srgb(
+        lighting(
+            color,
+            world_position,
+            normal,
+            light,
+            lights,
+            light_tiles,
+            frag_coord,
+            shadow,
+        )
+        .extend(1.0),
+    );
+}
+
+//周囲3x3テクセルで深度を比較した結果を平均するPCF
+//比較はサンプラーのLESS_OR_EQUALで行われ、ライトから見て一番手前なら1.0になる
+//LINEARのサンプラーなので1回の比較でも2x2テクセルの結果が補間される
+fn shadow_visibility(
+    shadow_coord: Vec4,
+    texel_size: f32,
+    shadow_map: &Image!(2D, type=f32, sampled, depth),
+    sampler: &Sampler,
+) -> f32 {
+    let ndc = shadow_coord.truncate() / shadow_coord.w;
+
+    //ライトの正射影の奥より遠い所は影にしない
+    if ndc.z > 1.0 {
+        return 1.0;
+    }
+
+    //Vulkanのクリップ座標はuvと同じく上が-1.0なので反転させなくて良い
+    let uv = Vec2::new(ndc.x, ndc.y) * 0.5 + Vec2::splat(0.5);
+
+    let mut sum = 0.0;
+    let mut y = -1;
+    while y <= 1 {
+        let mut x = -1;
+        while x <= 1 {
+            let offset = Vec2::new(x as f32, y as f32) * texel_size;
+            sum += shadow_map.sample_depth_reference(*sampler, uv + offset, ndc.z);
+            x += 1;
+        }
+        y += 1;
+    }
+
+    sum / 9.0
+}
+
+//アルファはパイプラインのブレンドで使う
+#[spirv(fragment)]
+pub fn main_fs_transparent(output: &mut Vec4, color: Vec4) {
+    *output = color;
+}
+
+#[spirv(fragment)]
+pub fn main_fs_transparent_encode_srgb(output: &mut Vec4, color: Vec4) {
+    *output = encode_srgb(color);
+}
+
+//SpriteBatchの四角形、positionはCPU側で回転させたワールド座標のXY平面の位置
+#[spirv(vertex)]
+pub fn main_vs_sprite(
+    position: Vec2,
+    in_uv: Vec2,
+    in_color: Vec4,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    #[spirv(position)] out_pos: &mut Vec4,
+    uv: &mut Vec2,
+    color: &mut Vec4,
+) {
+    *uv = in_uv;
+    *color = in_color;
+    *out_pos = ubo.proj * ubo.view * position.extend(0.0).extend(1.0);
+}
+
+//テクスチャはSRGBフォーマットなのでサンプリングした値はリニアになっている
+//アルファはパイプラインのブレンドで使う
+#[spirv(fragment)]
+pub fn main_fs_sprite(
+    output: &mut Vec4,
+    uv: Vec2,
+    color: Vec4,
+    #[spirv(descriptor_set = 1, binding = 0)] texture: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 1, binding = 1)] sampler: &Sampler,
+) {
+    let texel: Vec4 = texture.sample(*sampler, uv);
+    *output = texel * color;
+}
+
+#[spirv(fragment)]
+pub fn main_fs_sprite_encode_srgb(
+    output: &mut Vec4,
+    uv: Vec2,
+    color: Vec4,
+    #[spirv(descriptor_set = 1, binding = 0)] texture: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 1, binding = 1)] sampler: &Sampler,
+) {
+    let texel: Vec4 = texture.sample(*sampler, uv);
+    *output = encode_srgb(texel * color);
+}
+
+//デバッグ用の文字の四角形、positionはCPU側でピクセルからクリップ座標にしてある
+#[spirv(vertex)]
+pub fn main_vs_text(
+    position: Vec2,
+    in_uv: Vec2,
+    #[spirv(position)] out_pos: &mut Vec4,
+    uv: &mut Vec2,
+) {
+    *uv = in_uv;
+    *out_pos = position.extend(0.0).extend(1.0);
+}
+
+//フォントのアトラスのRをカバレッジとしてアルファにする
+//白はリニアでもsRGBでも同じ値なので、出力のエンコードによらずこのエントリーポイントを使う
+#[spirv(fragment)]
+pub fn main_fs_text(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] atlas: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+) {
+    let coverage: Vec4 = atlas.sample(*sampler, uv);
+    *output = Vec4::new(1.0, 1.0, 1.0, coverage.x);
+}
+
+//PQでは1.0が最大輝度になってしまうので、白をSDRの白の明るさでエンコードする
+#[spirv(fragment)]
+pub fn main_fs_text_encode_pq(
+    output: &mut Vec4,
+    uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] atlas: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+) {
+    let coverage: Vec4 = atlas.sample(*sampler, uv);
+    *output = encode_pq(Vec4::new(1.0, 1.0, 1.0, coverage.x));
+}
+
+//eguiのメッシュ、positionはCPU側で論理ピクセルからクリップ座標にしてある
+//頂点の色はアルファを掛け済みのsRGBなので、テクスチャと同じくリニアに戻してから掛ける
+#[spirv(vertex)]
+pub fn main_vs_egui(
+    position: Vec2,
+    in_uv: Vec2,
+    in_color: Vec4,
+    #[spirv(position)] out_pos: &mut Vec4,
+    uv: &mut Vec2,
+    color: &mut Vec4,
+) {
+    *uv = in_uv;
+    *color = decode_srgb(in_color);
+    *out_pos = position.extend(0.0).extend(1.0);
+}
+
+//テクスチャはR8G8B8A8_SRGBなのでサンプリングでリニアに戻る、出力もアルファを掛け済みのまま
+#[spirv(fragment)]
+pub fn main_fs_egui(
+    output: &mut Vec4,
+    uv: Vec2,
+    color: Vec4,
+    #[spirv(descriptor_set = 0, binding = 0)] texture: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+) {
+    let texel: Vec4 = texture.sample(*sampler, uv);
+    *output = color * texel;
+}
+
+//エンコードはアルファを掛ける前の色に行うので、一度割ってから掛け直す
+#[spirv(fragment)]
+pub fn main_fs_egui_encode_srgb(
+    output: &mut Vec4,
+    uv: Vec2,
+    color: Vec4,
+    #[spirv(descriptor_set = 0, binding = 0)] texture: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+) {
+    let texel: Vec4 = texture.sample(*sampler, uv);
+    *output = premultiply(encode_srgb(unpremultiply(color * texel)));
+}
+
+#[spirv(fragment)]
+pub fn main_fs_egui_encode_pq(
+    output: &mut Vec4,
+    uv: Vec2,
+    color: Vec4,
+    #[spirv(descriptor_set = 0, binding = 0)] texture: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+) {
+    let texel: Vec4 = texture.sample(*sampler, uv);
+    *output = premultiply(encode_pq(unpremultiply(color * texel)));
+}
+
+//アルファが0の場合は色も0なので、割らずにそのまま返す
+fn unpremultiply(color: Vec4) -> Vec4 {
+    if color.w > 0.0 {
+        (color.truncate() / color.w).extend(color.w)
+    } else {
+        color
+    }
+}
+
+fn premultiply(color: Vec4) -> Vec4 {
+    (color.truncate() * color.w).extend(color.w)
+}
+
+//リニアの値をsRGBの伝達関数でエンコードする、アルファはそのまま
+//ライティングなどの計算は全てリニアで済ませてから最後に呼ぶ
+fn encode_srgb(color: Vec4) -> Vec4 {
+    fn encode(value: f32) -> f32 {
+        if value <= 0.0031308 {
+            value * 12.92
+        } else {
+            1.055 * value.powf(1.0 / 2.4) - 0.055
+        }
+    }
+
+    Vec4::new(encode(color.x), encode(color.y), encode(color.z), color.w)
+}
+
+//リニアの1.0に対応させる明るさ、BT.2408のSDRの白の基準
+const PQ_PAPER_WHITE_NITS: f32 = 203.0;
+//PQで表せる最大の明るさ
+const PQ_MAX_NITS: f32 = 10000.0;
+
+//BT.709の原色のリニアの値をBT.2020の原色に変換し、PQ(ST 2084)の伝達関数でエンコードする、アルファはそのまま
+fn encode_pq(color: Vec4) -> Vec4 {
+    fn encode(value: f32) -> f32 {
+        const M1: f32 = 0.1593017578125;
+        const M2: f32 = 78.84375;
+        const C1: f32 = 0.8359375;
+        const C2: f32 = 18.8515625;
+        const C3: f32 = 18.6875;
+
+        let y = (value * PQ_PAPER_WHITE_NITS / PQ_MAX_NITS)
+            .clamp(0.0, 1.0)
+            .powf(M1);
+
+        ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+    }
+
+    let rec709_to_rec2020 = Mat3::from_cols(
+        Vec3::new(0.6274, 0.0691, 0.0164),
+        Vec3::new(0.3293, 0.9195, 0.0880),
+        Vec3::new(0.0433, 0.0114, 0.8956),
+    );
+    let rec2020 = rec709_to_rec2020 * color.truncate();
+
+    Vec4::new(
+        encode(rec2020.x),
+        encode(rec2020.y),
+        encode(rec2020.z),
+        color.w,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TONEMAP_ACES: u32 = 0;
+
+    fn constants(operator: u32, exposure: f32) -> TonemapConstants {
+        TonemapConstants { exposure, operator }
+    }
+
+    fn assert_close(actual: Vec3, expected: Vec3) {
+        assert!(
+            (actual - expected).abs().max_element() < 1e-4,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    //ACESの近似式の出力を固定する、係数を変えたらここも計算し直す
+    #[test]
+    fn aces_matches_pinned_values() {
+        let aces = constants(TONEMAP_ACES, 1.0);
+        let pinned = [
+            (0.0, 0.0),
+            (0.18, 0.2669),
+            (0.5, 0.6163),
+            (1.0, 0.8038),
+            (2.0, 0.9149),
+            (4.0, 0.9734),
+            (16.0, 1.0),
+        ];
+
+        for (input, expected) in pinned {
+            assert_close(tonemap(Vec3::splat(input), &aces), Vec3::splat(expected));
+        }
+    }
+
+    //中間画像が1.0を超える値を残すので、1.0より明るい値同士も区別できる
+    #[test]
+    fn aces_keeps_highlights_above_one_apart() {
+        let aces = constants(TONEMAP_ACES, 1.0);
+        let mapped = tonemap(Vec3::new(1.0, 2.0, 4.0), &aces);
+
+        assert!(mapped.x < mapped.y && mapped.y < mapped.z && mapped.z < 1.0);
+    }
+
+    #[test]
+    fn exposure_is_applied_before_the_operator() {
+        let doubled = tonemap(Vec3::splat(0.5), &constants(TONEMAP_ACES, 2.0));
+
+        assert_close(doubled, Vec3::splat(0.8038));
+    }
+
+    #[test]
+    fn reinhard_and_exposure_operators() {
+        let reinhard = tonemap(Vec3::new(1.0, 3.0, 0.0), &constants(TONEMAP_REINHARD, 1.0));
+        assert_close(reinhard, Vec3::new(0.5, 0.75, 0.0));
+
+        let exposure = tonemap(Vec3::new(0.25, 1.0, 3.0), &constants(TONEMAP_EXPOSURE, 2.0));
+        assert_close(exposure, Vec3::new(0.5, 1.0, 1.0));
+    }
 }
@@ -0,0 +1,131 @@
+#![cfg_attr(
+    target_arch = "spirv",
+    no_std,
+    feature(register_attr),
+    register_attr(spirv)
+)]
+
+//Tessellationのcapabilityはモジュール全体に付くので、tessellation_shaderに対応していないデバイスでも読み込むrust-shaderとは別のクレートにしている
+//フラグメントシェーダーはrust-shaderのmain_fsをそのまま使う
+
+#[cfg(not(target_arch = "spirv"))]
+use spirv_std::macros::spirv;
+
+//no_stdではf32::sinとf32::cosが無いのでnum_traits経由で使う
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
+
+use spirv_std::arch::IndexUnchecked;
+use spirv_std::glam::{Mat4, Vec2, Vec3, Vec3A, Vec4};
+
+//rust-shaderのUniformBufferObjectと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct UniformBufferObject {
+    pub view: Mat4,
+    pub proj: Mat4,
+    pub light_view_proj: Mat4,
+    pub frame_index: u32,
+    pub shadow_texel_size: f32,
+    pub _padding: [u32; 2],
+}
+
+//ホスト側のtessellation::TessellationConstantsと同じレイアウト
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct TessellationConstants {
+    pub level: f32,
+}
+
+//ホスト側のtessellation::PATCH_CONTROL_POINTSと同じ、四角形の4隅
+const PATCH_CONTROL_POINTS: usize = 4;
+//制御シェーダーと評価シェーダーの入力はgl_MaxPatchVerticesの大きさの配列になる
+const MAX_PATCH_VERTICES: usize = 32;
+
+//高さ(y)の変位の大きさと、x, z方向の波の細かさ
+const HEIGHT_AMPLITUDE: f32 = 0.25;
+const HEIGHT_FREQUENCY: f32 = 3.0;
+
+//低い所と高い所の色
+const LOW_COLOR: Vec3 = Vec3::new(0.2, 0.4, 0.8);
+const HIGH_COLOR: Vec3 = Vec3::new(0.9, 0.9, 0.8);
+
+//パッチの制御点をそのまま制御シェーダーに渡す
+//Vertexのcolorとnormalは評価シェーダーで作り直すので読まない
+#[spirv(vertex)]
+pub fn main_vs_patch(position: Vec3, out_position: &mut Vec3) {
+    *out_position = position;
+}
+
+//制御点はそのまま評価シェーダーに渡し、分割数だけをpush constantから決める
+#[spirv(tessellation_control(output_vertices = 4))]
+pub fn main_tesc(
+    #[spirv(invocation_id)] invocation_id: u32,
+    #[spirv(push_constant)] constants: &TessellationConstants,
+    in_positions: &[Vec3; MAX_PATCH_VERTICES],
+    out_positions: &mut [Vec3; PATCH_CONTROL_POINTS],
+    #[spirv(tess_level_outer)] tess_level_outer: &mut [f32; 4],
+    #[spirv(tess_level_inner)] tess_level_inner: &mut [f32; 2],
+) {
+    let index = invocation_id as usize;
+
+    unsafe {
+        *out_positions.index_unchecked_mut(index) = *in_positions.index_unchecked(index);
+    }
+
+    //分割数はパッチ全体で1つなので最初の呼び出しだけが書き込む
+    if invocation_id == 0 {
+        *tess_level_outer = [constants.level; 4];
+        *tess_level_inner = [constants.level; 2];
+    }
+}
+
+//tess_coordで4隅を補間した点を高さの関数で持ち上げ、main_fsと同じlocationに色とワールド座標と法線を書き込む
+#[spirv(tessellation_evaluation(quads, spacing_equal, vertex_order_cw))]
+pub fn main_tese(
+    #[spirv(tess_coord)] tess_coord: Vec3,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBufferObject,
+    in_positions: &[Vec3; MAX_PATCH_VERTICES],
+    #[spirv(position)] out_pos: &mut Vec4,
+    out_color: &mut Vec3A,
+    out_world_position: &mut Vec3A,
+    out_normal: &mut Vec3A,
+) {
+    let corners = unsafe {
+        [
+            *in_positions.index_unchecked(0),
+            *in_positions.index_unchecked(1),
+            *in_positions.index_unchecked(2),
+            *in_positions.index_unchecked(3),
+        ]
+    };
+
+    let near = corners[0].lerp(corners[1], tess_coord.x);
+    let far = corners[3].lerp(corners[2], tess_coord.x);
+    let base = near.lerp(far, tess_coord.y);
+
+    let (height, slope) = height(Vec2::new(base.x, base.z));
+    let world_position = base + Vec3::new(0.0, height, 0.0);
+
+    *out_pos = ubo.proj * ubo.view * world_position.extend(1.0);
+
+    let t = (height / HEIGHT_AMPLITUDE) * 0.5 + 0.5;
+    *out_color = Vec3A::from(LOW_COLOR.lerp(HIGH_COLOR, t));
+    *out_world_position = Vec3A::from(world_position);
+    //y = h(x, z)の面の法線は(-dh/dx, 1, -dh/dz)
+    *out_normal = Vec3A::new(-slope.x, 1.0, -slope.y).normalize();
+}
+
+//xzでの高さと、その(dh/dx, dh/dz)
+fn height(position: Vec2) -> (f32, Vec2) {
+    let x = position.x * HEIGHT_FREQUENCY;
+    let z = position.y * HEIGHT_FREQUENCY;
+
+    let height = HEIGHT_AMPLITUDE * x.sin() * z.cos();
+    let slope = Vec2::new(
+        HEIGHT_AMPLITUDE * HEIGHT_FREQUENCY * x.cos() * z.cos(),
+        -HEIGHT_AMPLITUDE * HEIGHT_FREQUENCY * x.sin() * z.sin(),
+    );
+
+    (height, slope)
+}
@@ -0,0 +1,625 @@
+use crate::buffer;
+use crate::memory_budget;
+use crate::mesh::{Mesh, Vertex};
+use crate::one_time_commands::OneTimeCommands;
+use crate::synchronization::Synchronization;
+use ash::extensions::khr;
+use ash::{vk, Device, Instance};
+use glam::Mat4;
+use std::ffi::CStr;
+use std::mem;
+
+//加速構造をビルドするのに必要なデバイス拡張
+//VK_KHR_acceleration_structureはVK_KHR_deferred_host_operationsに依存する
+pub fn extension_names() -> [&'static CStr; 2] {
+    [
+        khr::AccelerationStructure::name(),
+        vk::KhrDeferredHostOperationsFn::name(),
+    ]
+}
+
+//DynamicAccelerationStructuresで毎フレーム行うTLASのビルドは速さを優先する
+const DYNAMIC_BUILD_FLAGS: vk::BuildAccelerationStructureFlagsKHR =
+    vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_BUILD;
+
+//加速構造1つとそれを格納するバッファ
+struct Level {
+    handle: vk::AccelerationStructureKHR,
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+}
+
+impl Level {
+    fn destroy(&self, loader: &khr::AccelerationStructure, device: &Device) {
+        unsafe {
+            loader.destroy_acceleration_structure(self.handle, None);
+            device.destroy_buffer(self.buffer, None);
+            memory_budget::free_memory(device, self.memory);
+        }
+    }
+}
+
+//bottom_level_addressのBLASをtransformで置いたTLASのインスタンス
+fn top_level_instance(
+    transform: Mat4,
+    bottom_level_address: vk::DeviceAddress,
+) -> vk::AccelerationStructureInstanceKHR {
+    //TransformMatrixKHRは3x4の行優先の行列なので、転置した列の最初の3つを使う
+    let mut matrix = [0.0; 12];
+    matrix.copy_from_slice(&transform.transpose().to_cols_array()[..12]);
+
+    vk::AccelerationStructureInstanceKHR {
+        transform: vk::TransformMatrixKHR { matrix },
+        instance_custom_index_and_mask: vk::Packed24_8::new(0, 0xff),
+        //三角形の向きは描画の向きと揃えていないので裏面も当たるようにする
+        instance_shader_binding_table_record_offset_and_flags: vk::Packed24_8::new(
+            0,
+            vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() as u8,
+        ),
+        acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
+            device_handle: bottom_level_address,
+        },
+    }
+}
+
+//instance_addressに並んだインスタンスを入力にするTLASのgeometry
+fn instances_geometry(instance_address: vk::DeviceAddress) -> vk::AccelerationStructureGeometryKHR {
+    let instances = vk::AccelerationStructureGeometryInstancesDataKHR::builder()
+        .array_of_pointers(false)
+        .data(vk::DeviceOrHostAddressConstKHR {
+            device_address: instance_address,
+        })
+        .build();
+
+    vk::AccelerationStructureGeometryKHR::builder()
+        .geometry_type(vk::GeometryTypeKHR::INSTANCES)
+        .geometry(vk::AccelerationStructureGeometryDataKHR { instances })
+        .build()
+}
+
+//メッシュ1つのBLASと、それを単位行列のインスタンス1つとして置いたTLAS
+//起動時に一度だけビルドするので、モデル行列の回転は反映されない
+pub struct AccelerationStructures {
+    loader: khr::AccelerationStructure,
+    bottom_level: Level,
+    top_level: Level,
+    instance_buffer: vk::Buffer,
+    instance_memory: vk::DeviceMemory,
+}
+
+impl AccelerationStructures {
+    //meshはMesh::with_usageでSHADER_DEVICE_ADDRESSとACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHRを付けて作ったものを渡す
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        mesh: &Mesh,
+    ) -> Result<Self, vk::Result> {
+        let loader = khr::AccelerationStructure::new(instance, device);
+
+        let builder = Builder::new(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            &loader,
+        );
+
+        let (bottom_level, bottom_level_address) = builder.build_bottom_level(mesh)?;
+
+        //ビルドの入力はsubmit前にホストから書き込めば見える
+        let (instance_buffer, instance_memory) = buffer::create_host_buffer_with_data(
+            instance,
+            physical_device,
+            device,
+            &[top_level_instance(Mat4::IDENTITY, bottom_level_address)],
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+        );
+
+        let top_level = builder.build(
+            vk::AccelerationStructureTypeKHR::TOP_LEVEL,
+            instances_geometry(buffer::device_address(device, instance_buffer)),
+            1,
+        );
+
+        let top_level = match top_level {
+            Ok(top_level) => top_level,
+            Err(error) => {
+                bottom_level.destroy(&loader, device);
+                unsafe {
+                    device.destroy_buffer(instance_buffer, None);
+                    memory_budget::free_memory(device, instance_memory);
+                }
+                return Err(error);
+            }
+        };
+
+        log::info!(
+            "Acceleration structures: {} triangles in 1 instance",
+            mesh.index_count() / 3
+        );
+
+        Ok(Self {
+            loader,
+            bottom_level,
+            top_level,
+            instance_buffer,
+            instance_memory,
+        })
+    }
+
+    //レイを飛ばすシェーダーのDescriptor Setに書き込むTLAS
+    pub fn top_level(&self) -> vk::AccelerationStructureKHR {
+        self.top_level.handle
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.top_level.destroy(&self.loader, device);
+        self.bottom_level.destroy(&self.loader, device);
+
+        unsafe {
+            device.destroy_buffer(self.instance_buffer, None);
+            memory_budget::free_memory(device, self.instance_memory);
+        }
+    }
+}
+
+//フレームごとにビルドし直すTLASと、その入力のインスタンスとスクラッチのバッファ
+struct FrameTopLevel {
+    level: Level,
+    instance_buffer: vk::Buffer,
+    instance_memory: vk::DeviceMemory,
+    //max_instances個分をマップしたまま書き込む
+    instances: *mut vk::AccelerationStructureInstanceKHR,
+    instance_address: vk::DeviceAddress,
+    scratch_buffer: vk::Buffer,
+    scratch_memory: vk::DeviceMemory,
+    scratch_address: vk::DeviceAddress,
+}
+
+impl FrameTopLevel {
+    fn destroy(&self, loader: &khr::AccelerationStructure, device: &Device) {
+        self.level.destroy(loader, device);
+
+        unsafe {
+            device.unmap_memory(self.instance_memory);
+            device.destroy_buffer(self.instance_buffer, None);
+            memory_budget::free_memory(device, self.instance_memory);
+            device.destroy_buffer(self.scratch_buffer, None);
+            memory_budget::free_memory(device, self.scratch_memory);
+        }
+    }
+}
+
+//メッシュ1つのBLASと、それを複数のインスタンスとして置いたフレームごとのTLAS
+//オブジェクトのモデル行列は毎フレーム変わるので、cmd_buildでインスタンスを書き込んでTLASだけビルドし直す
+pub struct DynamicAccelerationStructures {
+    loader: khr::AccelerationStructure,
+    bottom_level: Level,
+    bottom_level_address: vk::DeviceAddress,
+    top_levels: Vec<FrameTopLevel>,
+    max_instances: u32,
+}
+
+impl DynamicAccelerationStructures {
+    //meshはAccelerationStructures::newと同じくSHADER_DEVICE_ADDRESSとACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHRを付けて作ったもの
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        mesh: &Mesh,
+        frames_in_flight: usize,
+        max_instances: u32,
+    ) -> Result<Self, vk::Result> {
+        let loader = khr::AccelerationStructure::new(instance, device);
+
+        let builder = Builder::new(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            &loader,
+        );
+
+        let (bottom_level, bottom_level_address) = builder.build_bottom_level(mesh)?;
+
+        let mut top_levels = vec![];
+
+        for _ in 0..frames_in_flight {
+            match builder.create_frame_top_level(max_instances) {
+                Ok(top_level) => top_levels.push(top_level),
+                Err(error) => {
+                    for top_level in &top_levels {
+                        top_level.destroy(&loader, device);
+                    }
+                    bottom_level.destroy(&loader, device);
+                    return Err(error);
+                }
+            }
+        }
+
+        log::info!(
+            "Acceleration structures: {} triangles in up to {} instances, {} top levels",
+            mesh.index_count() / 3,
+            max_instances,
+            frames_in_flight
+        );
+
+        Ok(Self {
+            loader,
+            bottom_level,
+            bottom_level_address,
+            top_levels,
+            max_instances,
+        })
+    }
+
+    //frameのcmd_buildでビルドしたTLAS
+    pub fn top_level(&self, frame: usize) -> vk::AccelerationStructureKHR {
+        self.top_levels[frame].level.handle
+    }
+
+    //transformsをインスタンスとしてframeのTLASをビルドし、dst_stage_maskから読めるようにする
+    //frameのコマンドバッファの完了を待ってから呼ぶので、前回のビルドやシェーダーからの読み込みとは重ならない
+    //max_instancesより多い分は無視する
+    pub fn cmd_build(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        transforms: &[Mat4],
+        dst_stage_mask: vk::PipelineStageFlags2,
+    ) {
+        let top_level = &self.top_levels[frame];
+
+        let instances = transforms
+            .iter()
+            .take(self.max_instances as usize)
+            .map(|transform| top_level_instance(*transform, self.bottom_level_address))
+            .collect::<Vec<_>>();
+
+        unsafe {
+            top_level
+                .instances
+                .copy_from_nonoverlapping(instances.as_ptr(), instances.len())
+        };
+
+        let geometries = [instances_geometry(top_level.instance_address)];
+
+        let build_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+            .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
+            .flags(DYNAMIC_BUILD_FLAGS)
+            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+            .dst_acceleration_structure(top_level.level.handle)
+            .geometries(&geometries)
+            .scratch_data(vk::DeviceOrHostAddressKHR {
+                device_address: top_level.scratch_address,
+            })
+            .build();
+
+        let range_info = vk::AccelerationStructureBuildRangeInfoKHR::builder()
+            .primitive_count(instances.len() as u32)
+            .build();
+
+        unsafe {
+            self.loader.cmd_build_acceleration_structures(
+                command_buffer,
+                &[build_info],
+                &[&[range_info]],
+            )
+        };
+
+        let barrier = vk::MemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_KHR)
+            .src_access_mask(vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_KHR)
+            .dst_stage_mask(dst_stage_mask)
+            .dst_access_mask(vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_KHR)
+            .build();
+
+        synchronization.cmd_pipeline_barrier(device, command_buffer, &[barrier], &[], &[]);
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        for top_level in &self.top_levels {
+            top_level.destroy(&self.loader, device);
+        }
+
+        self.bottom_level.destroy(&self.loader, device);
+    }
+}
+
+//BLASとTLASのビルドで共通する引数
+struct Builder<'a> {
+    instance: &'a Instance,
+    physical_device: vk::PhysicalDevice,
+    device: &'a Device,
+    one_time_commands: &'a OneTimeCommands,
+    synchronization: &'a Synchronization,
+    loader: &'a khr::AccelerationStructure,
+    scratch_alignment: vk::DeviceSize,
+}
+
+impl<'a> Builder<'a> {
+    fn new(
+        instance: &'a Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &'a Device,
+        one_time_commands: &'a OneTimeCommands,
+        synchronization: &'a Synchronization,
+        loader: &'a khr::AccelerationStructure,
+    ) -> Self {
+        let mut properties = vk::PhysicalDeviceAccelerationStructurePropertiesKHR::default();
+        let mut properties2 = vk::PhysicalDeviceProperties2::builder()
+            .push_next(&mut properties)
+            .build();
+        unsafe { instance.get_physical_device_properties2(physical_device, &mut properties2) };
+
+        Self {
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            loader,
+            scratch_alignment: properties.min_acceleration_structure_scratch_offset_alignment
+                as vk::DeviceSize,
+        }
+    }
+
+    //meshの三角形からBLASをビルドし、TLASのインスタンスから参照するアドレスと一緒に返す
+    fn build_bottom_level(&self, mesh: &Mesh) -> Result<(Level, vk::DeviceAddress), vk::Result> {
+        let (vertex_address, index_address) = mesh
+            .device_addresses()
+            .expect("Mesh is not created with SHADER_DEVICE_ADDRESS");
+
+        let triangles = vk::AccelerationStructureGeometryTrianglesDataKHR::builder()
+            .vertex_format(vk::Format::R32G32B32_SFLOAT)
+            .vertex_data(vk::DeviceOrHostAddressConstKHR {
+                device_address: vertex_address,
+            })
+            //positionは先頭にあるのでVertexの大きさごとに読めば良い
+            .vertex_stride(mem::size_of::<Vertex>() as vk::DeviceSize)
+            .max_vertex(mesh.vertex_count() - 1)
+            .index_type(vk::IndexType::UINT32)
+            .index_data(vk::DeviceOrHostAddressConstKHR {
+                device_address: index_address,
+            })
+            .build();
+
+        let bottom_level = self.build(
+            vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL,
+            vk::AccelerationStructureGeometryKHR::builder()
+                .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
+                .geometry(vk::AccelerationStructureGeometryDataKHR { triangles })
+                //any hitシェーダーを使わないのでOPAQUEにする
+                .flags(vk::GeometryFlagsKHR::OPAQUE)
+                .build(),
+            mesh.index_count() / 3,
+        )?;
+
+        let address = unsafe {
+            self.loader.get_acceleration_structure_device_address(
+                &vk::AccelerationStructureDeviceAddressInfoKHR::builder()
+                    .acceleration_structure(bottom_level.handle)
+                    .build(),
+            )
+        };
+
+        Ok((bottom_level, address))
+    }
+
+    //geometry1つの加速構造をビルドし、終わるまで待つ
+    fn build(
+        &self,
+        ty: vk::AccelerationStructureTypeKHR,
+        geometry: vk::AccelerationStructureGeometryKHR,
+        primitive_count: u32,
+    ) -> Result<Level, vk::Result> {
+        let geometries = [geometry];
+
+        let mut build_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+            .ty(ty)
+            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+            .geometries(&geometries)
+            .build();
+
+        let sizes = self.build_sizes(&build_info, primitive_count);
+
+        let level = self.create_level(ty, sizes.acceleration_structure_size)?;
+
+        let (scratch_buffer, scratch_memory, scratch_address) =
+            self.create_scratch(sizes.build_scratch_size);
+
+        build_info.dst_acceleration_structure = level.handle;
+        build_info.scratch_data = vk::DeviceOrHostAddressKHR {
+            device_address: scratch_address,
+        };
+
+        let range_info = vk::AccelerationStructureBuildRangeInfoKHR::builder()
+            .primitive_count(primitive_count)
+            .build();
+
+        let result = self.one_time_commands.run(
+            self.device,
+            self.synchronization,
+            |command_buffer| unsafe {
+                self.loader.cmd_build_acceleration_structures(
+                    command_buffer,
+                    &[build_info],
+                    &[&[range_info]],
+                )
+            },
+        );
+
+        //完了を待っているのでスクラッチはすぐに破棄できる
+        unsafe {
+            self.device.destroy_buffer(scratch_buffer, None);
+            memory_budget::free_memory(&self.device, scratch_memory);
+        }
+
+        match result {
+            Ok(()) => Ok(level),
+            Err(error) => {
+                level.destroy(self.loader, self.device);
+                Err(error)
+            }
+        }
+    }
+
+    //max_instancesまでのインスタンスを入れられるTLASと、ビルドに使い回すバッファを作る
+    fn create_frame_top_level(&self, max_instances: u32) -> Result<FrameTopLevel, vk::Result> {
+        let instance_size = (mem::size_of::<vk::AccelerationStructureInstanceKHR>()
+            * max_instances as usize) as vk::DeviceSize;
+
+        //ビルドの入力はsubmit前にホストから書き込めば見える
+        let (instance_buffer, instance_memory) = buffer::create_buffer(
+            self.instance,
+            self.physical_device,
+            self.device,
+            instance_size,
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        );
+
+        let instance_address = buffer::device_address(self.device, instance_buffer);
+
+        let geometries = [instances_geometry(instance_address)];
+
+        let build_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+            .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
+            .flags(DYNAMIC_BUILD_FLAGS)
+            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+            .geometries(&geometries)
+            .build();
+
+        //max_instancesで求めた大きさはそれより少ないインスタンスのビルドにも使える
+        let sizes = self.build_sizes(&build_info, max_instances);
+
+        let level = match self.create_level(
+            vk::AccelerationStructureTypeKHR::TOP_LEVEL,
+            sizes.acceleration_structure_size,
+        ) {
+            Ok(level) => level,
+            Err(error) => {
+                unsafe {
+                    self.device.destroy_buffer(instance_buffer, None);
+                    memory_budget::free_memory(&self.device, instance_memory);
+                }
+                return Err(error);
+            }
+        };
+
+        let (scratch_buffer, scratch_memory, scratch_address) =
+            self.create_scratch(sizes.build_scratch_size);
+
+        let instances = unsafe {
+            self.device
+                .map_memory(
+                    instance_memory,
+                    0,
+                    instance_size,
+                    vk::MemoryMapFlags::empty(),
+                )
+                .unwrap() as *mut vk::AccelerationStructureInstanceKHR
+        };
+
+        Ok(FrameTopLevel {
+            level,
+            instance_buffer,
+            instance_memory,
+            instances,
+            instance_address,
+            scratch_buffer,
+            scratch_memory,
+            scratch_address,
+        })
+    }
+
+    fn build_sizes(
+        &self,
+        build_info: &vk::AccelerationStructureBuildGeometryInfoKHR,
+        primitive_count: u32,
+    ) -> vk::AccelerationStructureBuildSizesInfoKHR {
+        unsafe {
+            self.loader.get_acceleration_structure_build_sizes(
+                vk::AccelerationStructureBuildTypeKHR::DEVICE,
+                build_info,
+                &[primitive_count],
+            )
+        }
+    }
+
+    //sizeの大きさのバッファを確保して、そこに加速構造を作る
+    fn create_level(
+        &self,
+        ty: vk::AccelerationStructureTypeKHR,
+        size: vk::DeviceSize,
+    ) -> Result<Level, vk::Result> {
+        let (buffer, memory) = buffer::create_buffer(
+            self.instance,
+            self.physical_device,
+            self.device,
+            size,
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+
+        let create_info = vk::AccelerationStructureCreateInfoKHR::builder()
+            .buffer(buffer)
+            .size(size)
+            .ty(ty)
+            .build();
+
+        match unsafe {
+            self.loader
+                .create_acceleration_structure(&create_info, None)
+        } {
+            Ok(handle) => Ok(Level {
+                handle,
+                buffer,
+                memory,
+            }),
+            Err(error) => {
+                unsafe {
+                    self.device.destroy_buffer(buffer, None);
+                    memory_budget::free_memory(&self.device, memory);
+                }
+                Err(error)
+            }
+        }
+    }
+
+    //スクラッチのアドレスはアライメントを揃える必要があるので、余分に確保して先頭をずらす
+    fn create_scratch(
+        &self,
+        size: vk::DeviceSize,
+    ) -> (vk::Buffer, vk::DeviceMemory, vk::DeviceAddress) {
+        let (buffer, memory) = buffer::create_buffer(
+            self.instance,
+            self.physical_device,
+            self.device,
+            size + self.scratch_alignment,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+
+        let address = buffer::align_up(
+            buffer::device_address(self.device, buffer),
+            self.scratch_alignment,
+        );
+
+        (buffer, memory, address)
+    }
+}
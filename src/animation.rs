@@ -0,0 +1,409 @@
+use glam::{Mat4, Quat, Vec3};
+
+//Insert/Deleteで変える再生速度の1回分の量と上限
+pub const SPEED_STEP: f32 = 0.25;
+pub const MAX_SPEED: f32 = 4.0;
+
+//キーフレームの間の値の決め方、glTFのanimation.samplers[].interpolationと同じ
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    //前のキーフレームの値のまま次のキーフレームで切り替える
+    Step,
+    //前後のキーフレームを線形に補間する、回転は球面線形補間にする
+    Linear,
+}
+
+//ノードの平行移動、回転、拡大率のどれを動かすか
+#[derive(Clone, Debug)]
+pub enum ChannelValues {
+    Translation(Vec<Vec3>),
+    Rotation(Vec<Quat>),
+    Scale(Vec<Vec3>),
+}
+
+//1つのノードの1つの値を動かすキーフレーム、timesとvaluesは同じ長さ
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub node: usize,
+    pub interpolation: Interpolation,
+    //秒、昇順に並んでいる
+    pub times: Vec<f32>,
+    pub values: ChannelValues,
+}
+
+#[derive(Clone, Debug)]
+pub struct Animation {
+    pub name: String,
+    pub channels: Vec<Channel>,
+    //一番遅いキーフレームの時間、この長さで繰り返す
+    pub duration: f32,
+}
+
+//ノードの親に対する変換
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub parent: Option<usize>,
+    //アニメーションが動かさない場合に使う値
+    pub transform: Transform,
+}
+
+//スキンメッシュの頂点を動かすノードの階層
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    pub nodes: Vec<Node>,
+    //親が子より先に来るnodesの番号の順番、ワールド行列を1回の走査で求める
+    pub order: Vec<usize>,
+    //頂点のjointsの番号からnodesの番号
+    pub joints: Vec<usize>,
+    //jointsと同じ順番で、メッシュの座標からジョイントの座標への変換
+    pub inverse_bind_matrices: Vec<Mat4>,
+    //読み込み時に頂点を-1..1に収めた変換、ジョイントの行列の前後に掛けて頂点の座標に合わせる
+    pub normalize: Mat4,
+}
+
+impl Skeleton {
+    //timeのanimationの姿勢でのジョイントごとの行列、animationがNoneの場合はノードの初期の姿勢にする
+    //頂点シェーダーはこの行列をweightsで混ぜて頂点と法線に掛ける
+    pub fn joint_palette(&self, animation: Option<&Animation>, time: f32) -> Vec<Mat4> {
+        let mut transforms = self
+            .nodes
+            .iter()
+            .map(|node| node.transform)
+            .collect::<Vec<_>>();
+
+        for channel in animation.map_or(&[][..], |animation| &animation.channels[..]) {
+            let transform = &mut transforms[channel.node];
+
+            match &channel.values {
+                ChannelValues::Translation(values) => {
+                    transform.translation = sample(
+                        &channel.times,
+                        values,
+                        channel.interpolation,
+                        time,
+                        Vec3::lerp,
+                    )
+                }
+                ChannelValues::Rotation(values) => {
+                    transform.rotation = sample(
+                        &channel.times,
+                        values,
+                        channel.interpolation,
+                        time,
+                        Quat::slerp,
+                    )
+                }
+                ChannelValues::Scale(values) => {
+                    transform.scale = sample(
+                        &channel.times,
+                        values,
+                        channel.interpolation,
+                        time,
+                        Vec3::lerp,
+                    )
+                }
+            }
+        }
+
+        let mut world = vec![Mat4::IDENTITY; self.nodes.len()];
+
+        for &index in &self.order {
+            let local = transforms[index].matrix();
+
+            world[index] = match self.nodes[index].parent {
+                Some(parent) => world[parent] * local,
+                None => local,
+            };
+        }
+
+        let denormalize = self.normalize.inverse();
+
+        self.joints
+            .iter()
+            .zip(&self.inverse_bind_matrices)
+            .map(|(&joint, inverse_bind_matrix)| {
+                self.normalize * world[joint] * *inverse_bind_matrix * denormalize
+            })
+            .collect()
+    }
+}
+
+//timeを挟むキーフレームの番号と、その間の0.0から1.0の位置
+//最初より前は最初の、最後より後は最後のキーフレームの値にする
+fn keyframe(times: &[f32], time: f32) -> (usize, usize, f32) {
+    let last = times.len() - 1;
+
+    if time <= times[0] {
+        return (0, 0, 0.0);
+    }
+
+    if time >= times[last] {
+        return (last, last, 0.0);
+    }
+
+    //times[next - 1] <= time < times[next]
+    let next = times.partition_point(|&key| key <= time);
+    let previous = next - 1;
+    let span = times[next] - times[previous];
+
+    let factor = if span > 0.0 {
+        (time - times[previous]) / span
+    } else {
+        0.0
+    };
+
+    (previous, next, factor)
+}
+
+fn sample<T: Copy>(
+    times: &[f32],
+    values: &[T],
+    interpolation: Interpolation,
+    time: f32,
+    lerp: fn(T, T, f32) -> T,
+) -> T {
+    let (previous, next, factor) = keyframe(times, time);
+
+    match interpolation {
+        Interpolation::Step => values[previous],
+        Interpolation::Linear => lerp(values[previous], values[next], factor),
+    }
+}
+
+//スキンメッシュのアニメーションの再生状態
+//シミュレーションの固定間隔で進めるので、Pで一時停止している間も止まる
+pub struct Animator {
+    skeleton: Skeleton,
+    animations: Vec<Animation>,
+    current: usize,
+    //currentの再生位置(秒)
+    time: f32,
+    speed: f32,
+    playing: bool,
+}
+
+impl Animator {
+    pub fn new(skeleton: Skeleton, animations: Vec<Animation>) -> Self {
+        Self {
+            skeleton,
+            animations,
+            current: 0,
+            time: 0.0,
+            speed: 1.0,
+            playing: true,
+        }
+    }
+
+    pub fn animation_count(&self) -> usize {
+        self.animations.len()
+    }
+
+    //アニメーションを持たないモデルではNone
+    pub fn animation(&self) -> Option<&Animation> {
+        self.animations.get(self.current)
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    //durationで繰り返す
+    pub fn advance(&mut self, delta_seconds: f32) {
+        let duration = match self.animation() {
+            Some(animation) if self.playing => animation.duration,
+            _ => return,
+        };
+
+        self.time = if duration > 0.0 {
+            (self.time + delta_seconds * self.speed) % duration
+        } else {
+            0.0
+        };
+    }
+
+    pub fn toggle_playing(&mut self) {
+        self.playing = !self.playing;
+    }
+
+    //次のアニメーションを最初から再生する、最後の次は最初に戻る
+    pub fn next_animation(&mut self) {
+        if !self.animations.is_empty() {
+            self.current = (self.current + 1) % self.animations.len();
+            self.time = 0.0;
+        }
+    }
+
+    pub fn change_speed(&mut self, step: f32) {
+        self.speed = (self.speed + step).clamp(0.0, MAX_SPEED);
+    }
+
+    pub fn joint_palette(&self) -> Vec<Mat4> {
+        self.skeleton.joint_palette(self.animation(), self.time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    const TIMES: [f32; 3] = [1.0, 2.0, 4.0];
+
+    fn lerp(a: f32, b: f32, factor: f32) -> f32 {
+        a + (b - a) * factor
+    }
+
+    fn node(parent: Option<usize>, translation: Vec3) -> Node {
+        Node {
+            parent,
+            transform: Transform {
+                translation,
+                rotation: Quat::IDENTITY,
+                scale: Vec3::ONE,
+            },
+        }
+    }
+
+    //node 0を1秒で(2, 0, 0)まで動かし、その子のnode 1は(0, 1, 0)に置いたまま
+    fn two_bones() -> (Skeleton, Animation) {
+        let skeleton = Skeleton {
+            nodes: vec![node(None, Vec3::ZERO), node(Some(0), Vec3::Y)],
+            order: vec![0, 1],
+            joints: vec![0, 1],
+            inverse_bind_matrices: vec![Mat4::IDENTITY; 2],
+            normalize: Mat4::IDENTITY,
+        };
+        let animation = Animation {
+            name: "slide".to_string(),
+            channels: vec![Channel {
+                node: 0,
+                interpolation: Interpolation::Linear,
+                times: vec![0.0, 1.0],
+                values: ChannelValues::Translation(vec![Vec3::ZERO, Vec3::X * 2.0]),
+            }],
+            duration: 1.0,
+        };
+
+        (skeleton, animation)
+    }
+
+    #[test]
+    fn keyframe_clamps_outside_the_keyframes() {
+        assert_eq!(keyframe(&TIMES, 0.5), (0, 0, 0.0));
+        assert_eq!(keyframe(&TIMES, 1.0), (0, 0, 0.0));
+        assert_eq!(keyframe(&TIMES, 4.0), (2, 2, 0.0));
+        assert_eq!(keyframe(&TIMES, 9.0), (2, 2, 0.0));
+        assert_eq!(keyframe(&[3.0], 5.0), (0, 0, 0.0));
+    }
+
+    #[test]
+    fn keyframe_finds_the_surrounding_pair() {
+        assert_eq!(keyframe(&TIMES, 1.5), (0, 1, 0.5));
+        assert_eq!(keyframe(&TIMES, 2.0), (1, 2, 0.0));
+        assert_eq!(keyframe(&TIMES, 3.5), (1, 2, 0.75));
+    }
+
+    //同じ時間のキーフレームが2つある場合は、その時間で後ろの値に切り替わる
+    #[test]
+    fn duplicate_times_jump_to_the_later_value() {
+        let times = [0.0, 1.0, 1.0, 2.0];
+        let values = [0.0, 1.0, 5.0, 6.0];
+
+        assert_eq!(keyframe(&times, 0.5), (0, 1, 0.5));
+        assert_eq!(keyframe(&times, 1.0), (2, 3, 0.0));
+        assert_eq!(
+            sample(&times, &values, Interpolation::Linear, 1.5, lerp),
+            5.5
+        );
+    }
+
+    #[test]
+    fn step_holds_and_linear_blends() {
+        let values = [10.0, 20.0, 40.0];
+        let expected = [
+            (Interpolation::Step, 1.5, 10.0),
+            (Interpolation::Step, 3.9, 20.0),
+            (Interpolation::Step, 4.0, 40.0),
+            (Interpolation::Linear, 0.0, 10.0),
+            (Interpolation::Linear, 1.5, 15.0),
+            (Interpolation::Linear, 3.0, 30.0),
+        ];
+
+        for (interpolation, time, value) in expected {
+            assert_eq!(sample(&TIMES, &values, interpolation, time, lerp), value);
+        }
+    }
+
+    #[test]
+    fn rotation_is_slerped() {
+        let values = [Quat::IDENTITY, Quat::from_rotation_z(FRAC_PI_2)];
+        let halfway = sample(
+            &[0.0, 1.0],
+            &values,
+            Interpolation::Linear,
+            0.5,
+            Quat::slerp,
+        );
+
+        assert!(halfway.abs_diff_eq(Quat::from_rotation_z(FRAC_PI_2 * 0.5), 1e-5));
+    }
+
+    #[test]
+    fn joint_palette_applies_the_animation_through_the_hierarchy() {
+        let (skeleton, animation) = two_bones();
+
+        let rest = skeleton.joint_palette(None, 0.5);
+        assert!(rest[1]
+            .transform_point3(Vec3::ZERO)
+            .abs_diff_eq(Vec3::Y, 1e-5));
+
+        let palette = skeleton.joint_palette(Some(&animation), 0.5);
+        assert!(palette[0]
+            .transform_point3(Vec3::ZERO)
+            .abs_diff_eq(Vec3::X, 1e-5));
+        assert!(palette[1]
+            .transform_point3(Vec3::ZERO)
+            .abs_diff_eq(Vec3::new(1.0, 1.0, 0.0), 1e-5));
+    }
+
+    #[test]
+    fn animator_loops_and_respects_pause_and_speed() {
+        let (skeleton, animation) = two_bones();
+        let mut animator = Animator::new(skeleton, vec![animation]);
+
+        animator.advance(1.25);
+        assert!((animator.time - 0.25).abs() < 1e-5);
+
+        animator.toggle_playing();
+        animator.advance(0.5);
+        assert!((animator.time - 0.25).abs() < 1e-5);
+
+        animator.toggle_playing();
+        animator.change_speed(1.0);
+        animator.advance(0.25);
+        assert!((animator.time - 0.75).abs() < 1e-5);
+
+        animator.change_speed(MAX_SPEED);
+        assert_eq!(animator.speed(), MAX_SPEED);
+
+        animator.next_animation();
+        assert_eq!(animator.time, 0.0);
+    }
+}
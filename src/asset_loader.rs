@@ -0,0 +1,99 @@
+use crate::mesh::Vertex;
+use crate::obj_loader;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Instant;
+
+//ローダースレッドが読み込みとデコードを終えたモデル
+pub struct LoadedModel {
+    pub path: PathBuf,
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+//--async-assetsでファイルの読み込みとデコードをメインスレッドとは別のスレッドで行う
+//読み込み終わったものはチャンネルに送られ、メインスレッドがフレームごとにpollで受け取る
+pub struct AssetLoader {
+    ready: Receiver<Result<LoadedModel, String>>,
+    //まだ受け取っていないファイルの数
+    remaining: usize,
+}
+
+impl AssetLoader {
+    //pathsは渡した順番に読み込む
+    //終了時に読み込み中のファイルを待たないように、スレッドはjoinせずに切り離しておく
+    pub fn spawn(paths: Vec<PathBuf>) -> Self {
+        let (sender, ready) = mpsc::channel();
+        let remaining = paths.len();
+
+        thread::Builder::new()
+            .name("asset loader".to_owned())
+            .spawn(move || {
+                for path in paths {
+                    let started_at = Instant::now();
+
+                    let result = match obj_loader::load(&path) {
+                        Ok((vertices, indices)) => {
+                            log::info!(
+                                "Decoded {} in {:.1} ms",
+                                path.display(),
+                                started_at.elapsed().as_secs_f64() * 1000.0
+                            );
+
+                            Ok(LoadedModel {
+                                path,
+                                vertices,
+                                indices,
+                            })
+                        }
+                        Err(error) => Err(format!("Failed to load {}: {}", path.display(), error)),
+                    };
+
+                    //受け取る側が破棄された場合は残りを読まずに終わる
+                    if sender.send(result).is_err() {
+                        return;
+                    }
+                }
+            })
+            .expect("Failed to spawn the asset loader thread");
+
+        Self { ready, remaining }
+    }
+
+    //読み込み終わったモデルを全て受け取る、読み込みに失敗したものはログに出して捨てる
+    pub fn poll(&mut self) -> Vec<LoadedModel> {
+        let mut models = vec![];
+
+        loop {
+            match self.ready.try_recv() {
+                Ok(Ok(model)) => {
+                    self.remaining -= 1;
+                    models.push(model);
+                }
+                Ok(Err(error)) => {
+                    self.remaining -= 1;
+                    log::warn!("{}", error);
+                }
+                Err(TryRecvError::Empty) => break,
+                //スレッドがpanicした場合は残りのファイルは届かない
+                Err(TryRecvError::Disconnected) => {
+                    if self.remaining > 0 {
+                        log::warn!(
+                            "Asset loader thread stopped with {} files left",
+                            self.remaining
+                        );
+                        self.remaining = 0;
+                    }
+                    break;
+                }
+            }
+        }
+
+        models
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+}
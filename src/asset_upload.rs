@@ -0,0 +1,435 @@
+use crate::asset_loader::{AssetLoader, LoadedModel};
+use crate::buffer;
+use crate::memory_budget;
+use crate::mesh::Mesh;
+use crate::submit_queue::SubmitQueue;
+use crate::synchronization::{self, Synchronization};
+use crate::timeline_semaphore::TimelineSemaphore;
+use ash::{vk, Device, Instance};
+use std::collections::VecDeque;
+use std::mem;
+use std::path::PathBuf;
+use std::slice;
+
+//全てのアップロードで使い回すステージングバッファの大きさ
+const RING_SIZE: vk::DeviceSize = 16 * 1024 * 1024;
+
+//リングから切り出す領域の先頭のアラインメント、RING_SIZEはこの倍数にする
+//vkCmdCopyBufferのオフセットに制約は無いが、書き込みが揃ったアドレスから始まるようにしておく
+const RING_ALIGNMENT: vk::DeviceSize = 16;
+
+const MEGABYTE: f64 = 1024.0 * 1024.0;
+
+//HOST_VISIBLEなバッファを先頭から順に切り出し、GPUが読み終わった領域から再利用する
+//作成時からずっとマップしておく
+struct UploadRing {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped: *mut u8,
+    //次に切り出す位置、常にRING_ALIGNMENTに揃っている
+    head: vk::DeviceSize,
+    //GPUが読み終わっていない大きさ、headとtailが一致した時に空か満杯かを区別するために持つ
+    used: vk::DeviceSize,
+    //切り出したがまだsubmitに紐づけていない大きさ
+    unsubmitted: vk::DeviceSize,
+    //submitごとの(切り出した大きさ, そのsubmitがタイムラインにシグナルする値)、submitした順番
+    in_flight: VecDeque<(vk::DeviceSize, u64)>,
+}
+
+impl UploadRing {
+    fn new(instance: &Instance, physical_device: vk::PhysicalDevice, device: &Device) -> Self {
+        let (buffer, memory) = buffer::create_buffer(
+            instance,
+            physical_device,
+            device,
+            RING_SIZE,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        );
+
+        let mapped = unsafe {
+            device
+                .map_memory(memory, 0, RING_SIZE, vk::MemoryMapFlags::empty())
+                .unwrap()
+        };
+
+        Self {
+            buffer,
+            memory,
+            mapped: mapped as *mut u8,
+            head: 0,
+            used: 0,
+            unsubmitted: 0,
+            in_flight: VecDeque::new(),
+        }
+    }
+
+    fn tail(&self) -> vk::DeviceSize {
+        (self.head + RING_SIZE - self.used) % RING_SIZE
+    }
+
+    fn is_full(&self) -> bool {
+        self.used == RING_SIZE
+    }
+
+    //completedの値までシグナルされたsubmitが読んでいた領域を空ける
+    fn release(&mut self, completed: u64) {
+        while let Some(&(size, value)) = self.in_flight.front() {
+            if value > completed {
+                break;
+            }
+
+            self.in_flight.pop_front();
+            self.used -= size;
+        }
+
+        //空になったら先頭から使うと一度に大きく切り出せる
+        if self.used == 0 && self.unsubmitted == 0 {
+            self.head = 0;
+        }
+    }
+
+    //連続した領域を最大max_sizeだけ切り出し、(オフセット, 大きさ)を返す
+    //満杯の場合はNone、大きさはmax_sizeより小さくなることがある
+    fn allocate(&mut self, max_size: vk::DeviceSize) -> Option<(vk::DeviceSize, vk::DeviceSize)> {
+        if self.is_full() || max_size == 0 {
+            return None;
+        }
+
+        let tail = self.tail();
+
+        //headより後ろが空いている場合はバッファの終わりまで使う
+        //終わりまでの領域が足りず先頭側の方が大きい場合は、終わりまでを捨てて先頭から切り出す
+        let (offset, available, skipped) = if self.head >= tail {
+            let to_end = RING_SIZE - self.head;
+
+            if to_end < max_size && tail > to_end {
+                (0, tail, to_end)
+            } else {
+                (self.head, to_end, 0)
+            }
+        } else {
+            (self.head, tail - self.head, 0)
+        };
+
+        //offsetとavailableはRING_ALIGNMENTの倍数なので切り上げてもavailableを超えない
+        let size = max_size.min(available);
+        let consumed = skipped + buffer::align_up(size, RING_ALIGNMENT);
+
+        self.head = (offset + buffer::align_up(size, RING_ALIGNMENT)) % RING_SIZE;
+        self.used += consumed;
+        self.unsubmitted += consumed;
+
+        Some((offset, size))
+    }
+
+    //allocateした領域はGPUが読み終わるまで書き換えない
+    fn write(&self, offset: vk::DeviceSize, data: &[u8]) {
+        unsafe {
+            self.mapped
+                .add(offset as usize)
+                .copy_from_nonoverlapping(data.as_ptr(), data.len());
+        }
+    }
+
+    //前回からallocateした領域をvalueをシグナルするsubmitで読むことにする
+    fn finish_submit(&mut self, value: u64) {
+        if self.unsubmitted > 0 {
+            self.in_flight.push_back((self.unsubmitted, value));
+            self.unsubmitted = 0;
+        }
+    }
+
+    fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_buffer(self.buffer, None);
+            memory_budget::free_memory(device, self.memory);
+        }
+    }
+}
+
+//転送中のモデル
+struct MeshUpload {
+    path: PathBuf,
+    mesh: Mesh,
+    //(転送するバイト列, 転送先のバッファ, 転送済みの大きさ)、頂点とインデックスの順番
+    segments: VecDeque<(Vec<u8>, vk::Buffer, vk::DeviceSize)>,
+}
+
+//--async-assetsでAssetLoaderが読み込んだモデルをDEVICE_LOCALなバッファに転送する
+//1フレームにbudgetまでUploadRingにコピーしてアップロード用のキューにsubmitする
+//グラフィックスのキューとは別のキューなので、描画はtimelineを待ってからメッシュを読む
+pub struct AssetUploader {
+    loader: AssetLoader,
+    queue: SubmitQueue,
+    command_pool: vk::CommandPool,
+    //submitしたコマンドバッファとそのsubmitがtimelineにシグナルする値、submitした順番
+    command_buffers: VecDeque<(vk::CommandBuffer, u64)>,
+    //アップロードのsubmitごとに値を1つ進めるタイムラインセマフォ
+    timeline: TimelineSemaphore,
+    submitted_value: u64,
+    ring: UploadRing,
+    //1フレームでコピーする最大のバイト数
+    budget: vk::DeviceSize,
+    uploads: VecDeque<MeshUpload>,
+    //進捗の表示に使う、受け取った全てのモデルの大きさとそのうち転送した大きさ
+    total_bytes: vk::DeviceSize,
+    uploaded_bytes: vk::DeviceSize,
+}
+
+impl AssetUploader {
+    //queueのキューファミリーはqueue_family_index
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        queue: SubmitQueue,
+        queue_family_index: u32,
+        paths: Vec<PathBuf>,
+        budget: vk::DeviceSize,
+    ) -> Self {
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(
+                vk::CommandPoolCreateFlags::TRANSIENT
+                    | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            )
+            .queue_family_index(queue_family_index)
+            .build();
+
+        let command_pool = unsafe { device.create_command_pool(&pool_info, None).unwrap() };
+
+        log::info!(
+            "Streaming {} assets with a {:.1} MB upload budget per frame",
+            paths.len(),
+            budget as f64 / MEGABYTE
+        );
+
+        Self {
+            loader: AssetLoader::spawn(paths),
+            queue,
+            command_pool,
+            command_buffers: VecDeque::new(),
+            timeline: TimelineSemaphore::new(device, 0),
+            submitted_value: 0,
+            ring: UploadRing::new(instance, physical_device, device),
+            budget,
+            uploads: VecDeque::new(),
+            total_bytes: 0,
+            uploaded_bytes: 0,
+        }
+    }
+
+    //フレームごとに1回呼ぶ
+    //転送し終えたメッシュがある場合は、そのメッシュとグラフィックスのsubmitが待つtimelineの値を返す
+    pub fn pump(
+        &mut self,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        synchronization: &Synchronization,
+    ) -> Result<Option<(Mesh, u64)>, vk::Result> {
+        let completed = self.timeline.value(device)?;
+        self.ring.release(completed);
+
+        for model in self.loader.poll() {
+            self.begin_upload(instance, physical_device, device, model);
+        }
+
+        if self.uploads.is_empty() || self.ring.is_full() {
+            return Ok(None);
+        }
+
+        let command_buffer = self.begin_command_buffer(device, completed)?;
+
+        let mut budget = self.budget;
+        let mut finished = None;
+
+        //1回のsubmitで終わるメッシュは1つまでにする
+        while finished.is_none() {
+            let upload = match self.uploads.front_mut() {
+                Some(upload) => upload,
+                None => break,
+            };
+
+            let (data, dst_buffer, copied) = upload.segments.front_mut().unwrap();
+            let remaining = data.len() as vk::DeviceSize - *copied;
+
+            let (offset, size) = match self.ring.allocate(remaining.min(budget)) {
+                Some(region) => region,
+                None => break,
+            };
+
+            self.ring
+                .write(offset, &data[*copied as usize..(*copied + size) as usize]);
+
+            let region = vk::BufferCopy::builder()
+                .src_offset(offset)
+                .dst_offset(*copied)
+                .size(size)
+                .build();
+
+            unsafe {
+                device.cmd_copy_buffer(command_buffer, self.ring.buffer, *dst_buffer, &[region])
+            };
+
+            *copied += size;
+            budget -= size;
+            self.uploaded_bytes += size;
+
+            if *copied == data.len() as vk::DeviceSize {
+                upload.segments.pop_front();
+            }
+
+            if upload.segments.is_empty() {
+                finished = self.uploads.pop_front();
+            }
+        }
+
+        unsafe { device.end_command_buffer(command_buffer)? };
+
+        let value = self.submitted_value + 1;
+
+        let signal_semaphores = [synchronization::semaphore_submit_info(
+            self.timeline.handle(),
+            value,
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+        )];
+
+        self.queue.submit(
+            device,
+            synchronization,
+            &[],
+            &[command_buffer],
+            &signal_semaphores,
+            vk::Fence::null(),
+        )?;
+
+        self.submitted_value = value;
+        self.command_buffers.push_back((command_buffer, value));
+        self.ring.finish_submit(value);
+
+        Ok(finished.map(|upload| {
+            log::info!("Uploaded {}", upload.path.display());
+
+            (upload.mesh, value)
+        }))
+    }
+
+    //pumpが返したメッシュを使うsubmitでこのSemaphoreを待つ
+    pub fn timeline_handle(&self) -> vk::Semaphore {
+        self.timeline.handle()
+    }
+
+    //読み込みか転送が残っている場合のみSome
+    pub fn progress(&self) -> Option<String> {
+        if self.loader.remaining() == 0 && self.uploads.is_empty() {
+            return None;
+        }
+
+        Some(format!(
+            "assets: {} loading, {:.1}/{:.1} MB uploaded",
+            self.loader.remaining(),
+            self.uploaded_bytes as f64 / MEGABYTE,
+            self.total_bytes as f64 / MEGABYTE
+        ))
+    }
+
+    //GPUがアップロードを終えてから呼ぶ
+    pub fn destroy(&self, device: &Device) {
+        for upload in &self.uploads {
+            upload.mesh.destroy(device);
+        }
+
+        self.ring.destroy(device);
+        self.timeline.destroy(device);
+
+        //Command Bufferは所属するCommand Poolと一緒に破棄される
+        unsafe { device.destroy_command_pool(self.command_pool, None) };
+    }
+
+    fn begin_upload(
+        &mut self,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        model: LoadedModel,
+    ) {
+        //大きさ0のバッファは作れない
+        if model.vertices.is_empty() || model.indices.is_empty() {
+            log::warn!("{} has no triangles, skipping", model.path.display());
+            return;
+        }
+
+        let mesh = Mesh::allocate(
+            instance,
+            physical_device,
+            device,
+            &model.vertices,
+            &model.indices,
+        );
+        let (vertex_buffer, index_buffer) = mesh.buffers();
+
+        let segments = VecDeque::from([
+            (as_bytes(&model.vertices), vertex_buffer, 0),
+            (as_bytes(&model.indices), index_buffer, 0),
+        ]);
+
+        let size = segments
+            .iter()
+            .map(|(data, _, _)| data.len() as vk::DeviceSize)
+            .sum::<vk::DeviceSize>();
+        self.total_bytes += size;
+
+        log::info!(
+            "Uploading {} ({:.1} MB)",
+            model.path.display(),
+            size as f64 / MEGABYTE
+        );
+
+        self.uploads.push_back(MeshUpload {
+            path: model.path,
+            mesh,
+            segments,
+        });
+    }
+
+    //GPUが使い終わったコマンドバッファがあれば使い回す
+    fn begin_command_buffer(
+        &mut self,
+        device: &Device,
+        completed: u64,
+    ) -> Result<vk::CommandBuffer, vk::Result> {
+        let reusable =
+            matches!(self.command_buffers.front(), Some(&(_, value)) if value <= completed);
+
+        let command_buffer = if reusable {
+            let (command_buffer, _) = self.command_buffers.pop_front().unwrap();
+
+            unsafe {
+                device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?
+            };
+
+            command_buffer
+        } else {
+            let alloc_info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(self.command_pool)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_buffer_count(1)
+                .build();
+
+            unsafe { device.allocate_command_buffers(&alloc_info)?[0] }
+        };
+
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+            .build();
+
+        unsafe { device.begin_command_buffer(command_buffer, &begin_info)? };
+
+        Ok(command_buffer)
+    }
+}
+
+fn as_bytes<T: Copy>(data: &[T]) -> Vec<u8> {
+    unsafe { slice::from_raw_parts(data.as_ptr().cast::<u8>(), mem::size_of_val(data)) }.to_vec()
+}
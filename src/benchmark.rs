@@ -0,0 +1,185 @@
+use crate::camera::Camera;
+use crate::frame_stats::{self, FrameHistory};
+use glam::Vec3;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+//カメラが原点の周りを1周するフレーム数、--framesに関わらずフレーム番号だけで位置が決まる
+const ORBIT_FRAMES: u32 = 600;
+const ORBIT_RADIUS: f32 = 4.0;
+//上下にゆっくり動かして見下ろす角度も変える
+const ORBIT_HEIGHT: f32 = 1.5;
+const ORBIT_HEIGHT_AMPLITUDE: f32 = 1.0;
+
+//--benchでframes枚描画する間、カメラをフレーム番号で決まる軌道で動かし、終わったら集計を表示する
+//時間も固定間隔で進めるので、同じ引数で2回実行すれば同じ画面の列を描画する
+pub struct Benchmark {
+    frames: u32,
+    //描画し終えたフレームの数
+    frame: u32,
+    //最初のフレームを描画し始めた時刻
+    started_at: Option<Instant>,
+}
+
+impl Benchmark {
+    pub fn new(frames: u32) -> Self {
+        log::info!("Benchmark: rendering {} frames", frames);
+
+        Self {
+            frames,
+            frame: 0,
+            started_at: None,
+        }
+    }
+
+    //入力の代わりに、これから描画するフレームの位置にカメラを置く
+    pub fn update_camera(&mut self, camera: &mut Camera) {
+        self.started_at.get_or_insert_with(Instant::now);
+
+        let turn = (self.frame % ORBIT_FRAMES) as f32 / ORBIT_FRAMES as f32;
+        let angle = turn * std::f32::consts::TAU;
+
+        let position = Vec3::new(
+            angle.sin() * ORBIT_RADIUS,
+            ORBIT_HEIGHT + (angle * 2.0).sin() * ORBIT_HEIGHT_AMPLITUDE,
+            angle.cos() * ORBIT_RADIUS,
+        );
+
+        camera.look_at(position, Vec3::ZERO);
+    }
+
+    //1フレーム描画した後に呼び、framesに達したらtrueを返す
+    pub fn end_frame(&mut self) -> bool {
+        self.frame += 1;
+        self.frame >= self.frames
+    }
+
+    //FrameStats::keep_historyで記録したフレームごとの時間から集計する
+    pub fn report(&self, history: &FrameHistory) -> BenchmarkReport {
+        BenchmarkReport {
+            frames: self.frame,
+            wall_time: self
+                .started_at
+                .map_or(Duration::ZERO, |started_at| started_at.elapsed()),
+            cpu: Summary::new(&history.cpu_ms),
+            gpu: Summary::new(&history.gpu_ms),
+        }
+    }
+}
+
+//フレームごとのミリ秒の値の集計
+#[derive(Clone, Copy, Debug)]
+pub struct Summary {
+    samples: usize,
+    min: f64,
+    average: f64,
+    p50: f64,
+    p95: f64,
+    p99: f64,
+    max: f64,
+}
+
+impl Summary {
+    //値が無い場合はNone
+    fn new(values: &[f64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+
+        let mut sorted = values.to_vec();
+        //計測した時間にNaNは無い
+        sorted.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+
+        Some(Self {
+            samples: sorted.len(),
+            min: sorted[0],
+            average: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            p50: frame_stats::nearest_rank(&sorted, 50.0),
+            p95: frame_stats::nearest_rank(&sorted, 95.0),
+            p99: frame_stats::nearest_rank(&sorted, 99.0),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+//Displayは人が読む表、csvは別の実行と比べるためのもの
+pub struct BenchmarkReport {
+    frames: u32,
+    wall_time: Duration,
+    cpu: Option<Summary>,
+    //GPUタイムスタンプが使えない場合はNone
+    gpu: Option<Summary>,
+}
+
+impl BenchmarkReport {
+    fn rows(&self) -> impl Iterator<Item = (&'static str, Summary)> {
+        [("cpu", self.cpu), ("gpu", self.gpu)]
+            .into_iter()
+            .filter_map(|(name, summary)| Some((name, summary?)))
+    }
+
+    //1行目がヘッダー、wallの行はtotal_msだけを持つ
+    pub fn csv(&self) -> String {
+        let mut csv =
+            "metric,samples,min_ms,avg_ms,p50_ms,p95_ms,p99_ms,max_ms,total_ms\n".to_owned();
+
+        for (name, summary) in self.rows() {
+            csv.push_str(&format!(
+                "{},{},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4}\n",
+                name,
+                summary.samples,
+                summary.min,
+                summary.average,
+                summary.p50,
+                summary.p95,
+                summary.p99,
+                summary.max,
+                summary.average * summary.samples as f64
+            ));
+        }
+
+        csv.push_str(&format!(
+            "wall,{},,,,,,,{:.4}\n",
+            self.frames,
+            self.wall_time.as_secs_f64() * 1000.0
+        ));
+
+        csv
+    }
+}
+
+impl fmt::Display for BenchmarkReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let wall_seconds = self.wall_time.as_secs_f64();
+
+        writeln!(
+            f,
+            "Benchmark: {} frames in {:.3} s ({:.1} fps)",
+            self.frames,
+            wall_seconds,
+            self.frames as f64 / wall_seconds.max(f64::EPSILON)
+        )?;
+
+        writeln!(
+            f,
+            "{:<8} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            "ms", "min", "avg", "p50", "p95", "p99", "max"
+        )?;
+
+        for (name, summary) in self.rows() {
+            writeln!(
+                f,
+                "{:<8} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3}",
+                name,
+                summary.min,
+                summary.average,
+                summary.p50,
+                summary.p95,
+                summary.p99,
+                summary.max
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,500 @@
+use crate::dynamic_rendering::DynamicRendering;
+use crate::post_process::{OffscreenTarget, PostProcess};
+use ash::{vk, Device, Instance};
+
+//ぼかす画像は1.0より明るい値を足し合わせても切り詰められないように浮動小数点にする
+pub const BLOOM_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+//縮小する段数の上限、1段目が描画する大きさの半分で段ごとに半分になる
+const MAX_LEVELS: usize = 5;
+
+//この明るさより明るい部分だけをぼかす
+//シーンの中間画像はINTERMEDIATE_FORMATで1.0を超える値も残るので、表示できる白より明るい部分だけにする
+pub const THRESHOLD: f32 = 1.0;
+
+//--bloom-intensityと設定ファイルが無い場合にぼかした画像を足す割合
+pub const DEFAULT_INTENSITY: f32 = 0.6;
+
+//PageUp/PageDownで変える1回分の量と上限
+pub const INTENSITY_STEP: f32 = 0.1;
+pub const MAX_INTENSITY: f32 = 4.0;
+
+//シェーダー側のBloomConstantsと同じレイアウト
+//ブルームの全てのパスと、最後に足すエフェクトのパスで同じものを使う
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct BloomConstants {
+    //入力の画像の1テクセル分のuvの大きさ
+    pub texel_size: [f32; 2],
+    pub threshold: f32,
+    pub intensity: f32,
+}
+
+//ブルームのパスで使うフラグメントシェーダー
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BloomStage {
+    //しきい値より明るい部分を半分の大きさに取り出す
+    Extract,
+    //dual filterで半分の大きさにぼかしながら縮める
+    Downsample,
+    //1段小さい画像をdual filterで広げ、同じ大きさの縮めた画像に足す
+    Upsample,
+}
+
+impl BloomStage {
+    pub const ALL: [Self; 3] = [Self::Extract, Self::Downsample, Self::Upsample];
+
+    //ぼかす画像はBLOOM_FORMATなのでエンコードしない
+    pub fn fragment_entry_point(self) -> &'static str {
+        match self {
+            Self::Extract => "main_fs_bloom_extract",
+            Self::Downsample => "main_fs_bloom_downsample",
+            Self::Upsample => "main_fs_bloom_upsample",
+        }
+    }
+
+    //Upsampleだけは広げる画像をset = 0、足す画像をset = 1で読む
+    pub fn descriptor_set_count(self) -> usize {
+        match self {
+            Self::Extract | Self::Downsample => 1,
+            Self::Upsample => 2,
+        }
+    }
+}
+
+//frame_graphに登録するブルームの1つのパス、中身は書き込む段
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BloomPass {
+    //0段目に書き込む
+    Extract,
+    //段の1つ前を読んで書き込む
+    Downsample(usize),
+    //段の1つ後ろを広げて書き込む
+    Upsample(usize),
+}
+
+impl BloomPass {
+    pub fn stage(self) -> BloomStage {
+        match self {
+            Self::Extract => BloomStage::Extract,
+            Self::Downsample(_) => BloomStage::Downsample,
+            Self::Upsample(_) => BloomStage::Upsample,
+        }
+    }
+}
+
+//PostEffect::Bloomの前に、入力の画像の明るい部分を段ごとに半分の大きさの画像へ縮めてから広げ直す
+//downsは縮めた画像、upsは広げながら足した画像で、ups[0]がエフェクトのパスで足す結果になる
+//全ての画像は描画する大きさに依存するので、swapchainを作り直す度に作り直す
+pub struct Bloom {
+    //dynamic renderingの場合はnull
+    //画像を作り直してもフォーマットは変わらないので、パイプラインと同じく作り直さない
+    render_pass: vk::RenderPass,
+    descriptor_pool: vk::DescriptorPool,
+    //downsとupsをサンプリングするDescriptor Set、MAX_LEVELS段分を確保しておく
+    down_descriptor_sets: Vec<vk::DescriptorSet>,
+    up_descriptor_sets: Vec<vk::DescriptorSet>,
+    //縮めた画像を広げる時に間を補間するので、PostProcessとは違いLINEARにする
+    //samplerの破棄はSamplerCacheに任せる
+    sampler: vk::Sampler,
+    downs: Vec<OffscreenTarget>,
+    //downsより1段少ない、最後の段は縮めた画像をそのまま広げる
+    ups: Vec<OffscreenTarget>,
+    //downsとupsの段ごとの大きさ
+    extents: Vec<vk::Extent2D>,
+    //Extractが読む描画する大きさの画像
+    source_extent: vk::Extent2D,
+}
+
+impl Bloom {
+    //descriptor_set_layoutはPostProcessのもの、同じくimageとsamplerを別々に受け取る
+    //画像はcreate_targetsで作成する
+    pub fn new(
+        device: &Device,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+        sampler: vk::Sampler,
+        use_render_pass: bool,
+    ) -> Self {
+        let render_pass = if use_render_pass {
+            Self::create_render_pass(device)
+        } else {
+            vk::RenderPass::null()
+        };
+
+        let count = (MAX_LEVELS * 2 - 1) as u32;
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(count)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::SAMPLER)
+                .descriptor_count(count)
+                .build(),
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(count)
+            .build();
+
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+
+        let allocate = |count: usize| {
+            let set_layouts = vec![descriptor_set_layout; count];
+
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&set_layouts)
+                .build();
+
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() }
+        };
+
+        let down_descriptor_sets = allocate(MAX_LEVELS);
+        let up_descriptor_sets = allocate(MAX_LEVELS - 1);
+
+        Self {
+            render_pass,
+            descriptor_pool,
+            down_descriptor_sets,
+            up_descriptor_sets,
+            sampler,
+            downs: vec![],
+            ups: vec![],
+            extents: vec![],
+            source_extent: vk::Extent2D::default(),
+        }
+    }
+
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    pub fn levels(&self) -> usize {
+        self.downs.len()
+    }
+
+    //extentは描画する大きさ、古い画像はdestroy_targetsで破棄しておく
+    //1辺が1ピクセルになったところで縮めるのをやめる
+    pub fn create_targets(
+        &mut self,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        extent: vk::Extent2D,
+    ) {
+        self.source_extent = extent;
+        self.extents = (1..=MAX_LEVELS as u32)
+            .map(|level| vk::Extent2D {
+                width: extent.width >> level,
+                height: extent.height >> level,
+            })
+            .take_while(|extent| extent.width > 0 && extent.height > 0)
+            .collect();
+
+        //描画する大きさが2ピクセルより小さい場合も1段は作る
+        if self.extents.is_empty() {
+            self.extents.push(vk::Extent2D {
+                width: 1,
+                height: 1,
+            });
+        }
+
+        let create = |extent: vk::Extent2D| {
+            PostProcess::create_target(
+                instance,
+                physical_device,
+                device,
+                self.render_pass,
+                BLOOM_FORMAT,
+                extent,
+                None,
+            )
+        };
+
+        self.downs = self.extents.iter().map(|&extent| create(extent)).collect();
+        self.ups = self.extents[..self.extents.len() - 1]
+            .iter()
+            .map(|&extent| create(extent))
+            .collect();
+
+        for (target, &descriptor_set) in self
+            .downs
+            .iter()
+            .zip(&self.down_descriptor_sets)
+            .chain(self.ups.iter().zip(&self.up_descriptor_sets))
+        {
+            let image_info = [vk::DescriptorImageInfo::builder()
+                .image_view(target.view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .build()];
+
+            let sampler_info = [vk::DescriptorImageInfo::builder()
+                .sampler(self.sampler)
+                .build()];
+
+            let descriptor_writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .image_info(&image_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::SAMPLER)
+                    .image_info(&sampler_info)
+                    .build(),
+            ];
+
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+    }
+
+    //GPUが画像を使い終わってから呼ぶ
+    pub fn destroy_targets(&mut self, device: &Device) {
+        for target in self.downs.drain(..).chain(self.ups.drain(..)) {
+            target.destroy(device);
+        }
+    }
+
+    //記録する順番のパス、全て縮めてから小さい段から順に広げる
+    pub fn passes(&self) -> Vec<BloomPass> {
+        let levels = self.levels();
+
+        [BloomPass::Extract]
+            .into_iter()
+            .chain((1..levels).map(BloomPass::Downsample))
+            .chain((0..levels.saturating_sub(1)).rev().map(BloomPass::Upsample))
+            .collect()
+    }
+
+    //passが書き込む画像
+    pub fn target(&self, pass: BloomPass) -> &OffscreenTarget {
+        match pass {
+            BloomPass::Extract => &self.downs[0],
+            BloomPass::Downsample(level) => &self.downs[level],
+            BloomPass::Upsample(level) => &self.ups[level],
+        }
+    }
+
+    //passが書き込む画像の大きさ
+    pub fn extent(&self, pass: BloomPass) -> vk::Extent2D {
+        match pass {
+            BloomPass::Extract => self.extents[0],
+            BloomPass::Downsample(level) | BloomPass::Upsample(level) => self.extents[level],
+        }
+    }
+
+    //passがset = 0から順に読むDescriptor Set、sourceはExtractが読むPostProcessのDescriptor Set
+    pub fn descriptor_sets(
+        &self,
+        pass: BloomPass,
+        source: vk::DescriptorSet,
+    ) -> Vec<vk::DescriptorSet> {
+        match pass {
+            BloomPass::Extract => vec![source],
+            BloomPass::Downsample(level) => vec![self.down_descriptor_sets[level - 1]],
+            BloomPass::Upsample(level) => vec![
+                self.lower_descriptor_set(level),
+                self.down_descriptor_sets[level],
+            ],
+        }
+    }
+
+    //passが読むこのBloomの画像、frame_graphにサンプリングすることを登録する
+    pub fn input_images(&self, pass: BloomPass) -> Vec<vk::Image> {
+        match pass {
+            BloomPass::Extract => vec![],
+            BloomPass::Downsample(level) => vec![self.downs[level - 1].image],
+            BloomPass::Upsample(level) => {
+                vec![self.lower_image(level), self.downs[level].image]
+            }
+        }
+    }
+
+    //passが読む画像の1テクセル分のuvの大きさ、Upsampleでは広げる方の画像
+    pub fn texel_size(&self, pass: BloomPass) -> [f32; 2] {
+        let extent = match pass {
+            BloomPass::Extract => self.source_extent,
+            BloomPass::Downsample(level) => self.extents[level - 1],
+            BloomPass::Upsample(level) => self.extents[level + 1],
+        };
+
+        [1.0 / extent.width as f32, 1.0 / extent.height as f32]
+    }
+
+    //全ての画像、frame_graphに登録する
+    pub fn images(&self) -> Vec<vk::Image> {
+        self.downs
+            .iter()
+            .chain(&self.ups)
+            .map(|target| target.image)
+            .collect()
+    }
+
+    //エフェクトのパスでset = 1として読む、最後のパスが書き込んだ画像
+    pub fn result_descriptor_set(&self) -> vk::DescriptorSet {
+        match self.ups.first() {
+            Some(_) => self.up_descriptor_sets[0],
+            None => self.down_descriptor_sets[0],
+        }
+    }
+
+    pub fn result_image(&self) -> vk::Image {
+        self.ups.first().unwrap_or(&self.downs[0]).image
+    }
+
+    //最後の段は縮めた画像をそのまま広げ、それより上の段は1つ小さい段で広げた画像を広げる
+    fn lower_descriptor_set(&self, level: usize) -> vk::DescriptorSet {
+        if level + 1 == self.levels() - 1 {
+            self.down_descriptor_sets[level + 1]
+        } else {
+            self.up_descriptor_sets[level + 1]
+        }
+    }
+
+    fn lower_image(&self, level: usize) -> vk::Image {
+        if level + 1 == self.levels() - 1 {
+            self.downs[level + 1].image
+        } else {
+            self.ups[level + 1].image
+        }
+    }
+
+    //passの画像に描画し始める、全画面を上書きするのでクリアしない
+    //dynamic renderingの場合のレイアウトの遷移はframe_graphが行う
+    pub fn cmd_begin_pass(
+        &self,
+        device: &Device,
+        dynamic_rendering: Option<&DynamicRendering>,
+        command_buffer: vk::CommandBuffer,
+        pass: BloomPass,
+    ) {
+        let target = self.target(pass);
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::builder().x(0).y(0).build())
+            .extent(self.extent(pass))
+            .build();
+
+        match dynamic_rendering {
+            Some(dynamic_rendering) => {
+                let color_attachments = [vk::RenderingAttachmentInfo::builder()
+                    .image_view(target.view)
+                    .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .load_op(vk::AttachmentLoadOp::DONT_CARE)
+                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .build()];
+
+                let rendering_info = vk::RenderingInfo::builder()
+                    .render_area(render_area)
+                    .layer_count(1)
+                    .color_attachments(&color_attachments)
+                    .build();
+
+                dynamic_rendering.cmd_begin_rendering(device, command_buffer, &rendering_info);
+            }
+            None => unsafe {
+                let render_pass_info = vk::RenderPassBeginInfo::builder()
+                    .render_pass(self.render_pass)
+                    .framebuffer(target.framebuffer)
+                    .render_area(render_area)
+                    .build();
+
+                device.cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_info,
+                    vk::SubpassContents::INLINE,
+                );
+            },
+        }
+    }
+
+    pub fn cmd_end_pass(
+        &self,
+        device: &Device,
+        dynamic_rendering: Option<&DynamicRendering>,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        match dynamic_rendering {
+            Some(dynamic_rendering) => dynamic_rendering.cmd_end_rendering(device, command_buffer),
+            None => unsafe { device.cmd_end_render_pass(command_buffer) },
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_targets(device);
+
+        unsafe {
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+
+            if self.render_pass != vk::RenderPass::null() {
+                device.destroy_render_pass(self.render_pass, None);
+            }
+        }
+    }
+
+    //PostProcessのrender passからデプスバッファを除いたもの
+    //全画面を上書きするので前の内容は読まない
+    fn create_render_pass(device: &Device) -> vk::RenderPass {
+        let color_attachment = vk::AttachmentDescription::builder()
+            .format(BLOOM_FORMAT)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            //次のパスでサンプリングする
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build();
+
+        let color_attachment_refs = [vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build()];
+
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)
+            .build();
+
+        let dependencies = [
+            //前のフレームで次のパスがサンプリングし終わってから書き込む
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                )
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .build(),
+            //書き込みが終わってから次のパスのフラグメントシェーダーで読む
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build(),
+        ];
+
+        let attachments = [color_attachment];
+        let subpasses = [subpass];
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies)
+            .build();
+
+        unsafe { device.create_render_pass(&render_pass_info, None).unwrap() }
+    }
+}
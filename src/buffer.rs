@@ -0,0 +1,229 @@
+use crate::memory_budget::{self, MemoryCategory};
+use ash::{vk, Device, Instance};
+use std::mem;
+
+//type_filterのビットが立っているメモリタイプの中からpropertiesを全て満たすものを探す
+pub fn find_memory_type(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    type_filter: u32,
+    properties: vk::MemoryPropertyFlags,
+) -> u32 {
+    let memory_properties =
+        unsafe { instance.get_physical_device_memory_properties(physical_device) };
+
+    memory_properties.memory_types[..memory_properties.memory_type_count as usize]
+        .iter()
+        .enumerate()
+        .find(|(index, memory_type)| {
+            type_filter & (1 << index) != 0 && memory_type.property_flags.contains(properties)
+        })
+        .map(|(index, _)| index as u32)
+        .expect("Failed to find suitable memory type.")
+}
+
+//バッファを作成してメモリを割り当て、バインドまで行う
+pub fn create_buffer(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    device: &Device,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+) -> (vk::Buffer, vk::DeviceMemory) {
+    create_shared_buffer(
+        instance,
+        physical_device,
+        device,
+        size,
+        usage,
+        properties,
+        &[],
+    )
+}
+
+//queue_family_indicesに2つ以上のキューファミリーを渡した場合はCONCURRENTで作成する
+//所有権の移動のバリアを張らずに複数のキューファミリーから使える
+pub fn create_shared_buffer(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    device: &Device,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+    queue_family_indices: &[u32],
+) -> (vk::Buffer, vk::DeviceMemory) {
+    let buffer_info = vk::BufferCreateInfo::builder().size(size).usage(usage);
+
+    let buffer_info = if queue_family_indices.len() > 1 {
+        buffer_info
+            .sharing_mode(vk::SharingMode::CONCURRENT)
+            .queue_family_indices(queue_family_indices)
+            .build()
+    } else {
+        //1つのキューファミリーからしか使用しない
+        buffer_info.sharing_mode(vk::SharingMode::EXCLUSIVE).build()
+    };
+
+    let buffer = unsafe { device.create_buffer(&buffer_info, None).unwrap() };
+
+    let requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+
+    //デバイスアドレスを取得するバッファのメモリはDEVICE_ADDRESSを付けて確保する必要がある
+    let mut allocate_flags_info = vk::MemoryAllocateFlagsInfo::builder()
+        .flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS)
+        .build();
+
+    let mut alloc_info = vk::MemoryAllocateInfo::builder()
+        .allocation_size(requirements.size)
+        .memory_type_index(find_memory_type(
+            instance,
+            physical_device,
+            requirements.memory_type_bits,
+            properties,
+        ));
+
+    if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
+        alloc_info = alloc_info.push_next(&mut allocate_flags_info);
+    }
+
+    let memory = unsafe {
+        memory_budget::allocate_memory(
+            device,
+            &alloc_info,
+            MemoryCategory::from_buffer_usage(usage),
+        )
+        .unwrap()
+    };
+
+    unsafe { device.bind_buffer_memory(buffer, memory, 0).unwrap() };
+
+    (buffer, memory)
+}
+
+//alignmentは2の累乗
+pub fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+//SHADER_DEVICE_ADDRESSを付けて作ったバッファの64bitのアドレス
+pub fn device_address(device: &Device, buffer: vk::Buffer) -> vk::DeviceAddress {
+    let info = vk::BufferDeviceAddressInfo::builder()
+        .buffer(buffer)
+        .build();
+
+    unsafe { device.get_buffer_device_address(&info) }
+}
+
+//CPUから直接書き込めるメモリにバッファを作成してdataを書き込む
+pub fn create_host_buffer_with_data<T: Copy>(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    device: &Device,
+    data: &[T],
+    usage: vk::BufferUsageFlags,
+) -> (vk::Buffer, vk::DeviceMemory) {
+    create_shared_host_buffer_with_data(instance, physical_device, device, data, usage, &[])
+}
+
+//create_host_buffer_with_dataのcreate_shared_buffer版
+pub fn create_shared_host_buffer_with_data<T: Copy>(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    device: &Device,
+    data: &[T],
+    usage: vk::BufferUsageFlags,
+    queue_family_indices: &[u32],
+) -> (vk::Buffer, vk::DeviceMemory) {
+    let size = mem::size_of_val(data) as vk::DeviceSize;
+
+    let (buffer, memory) = create_shared_buffer(
+        instance,
+        physical_device,
+        device,
+        size,
+        usage,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        queue_family_indices,
+    );
+
+    unsafe {
+        let pointer = device
+            .map_memory(memory, 0, size, vk::MemoryMapFlags::empty())
+            .unwrap() as *mut T;
+        pointer.copy_from_nonoverlapping(data.as_ptr(), data.len());
+        device.unmap_memory(memory);
+    }
+
+    (buffer, memory)
+}
+
+//DEVICE_LOCALなバッファと、そこにdataを転送するためのステージングバッファ
+//cmd_copyを記録したコマンドの実行が終わってからfinishでステージングバッファを破棄する
+pub struct StagedBuffer {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    staging_buffer: vk::Buffer,
+    staging_memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+}
+
+impl StagedBuffer {
+    pub fn new<T: Copy>(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        data: &[T],
+        usage: vk::BufferUsageFlags,
+    ) -> Self {
+        let (staging_buffer, staging_memory) = create_host_buffer_with_data(
+            instance,
+            physical_device,
+            device,
+            data,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+        );
+
+        let size = mem::size_of_val(data) as vk::DeviceSize;
+
+        let (buffer, memory) = create_buffer(
+            instance,
+            physical_device,
+            device,
+            size,
+            usage | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+
+        Self {
+            buffer,
+            memory,
+            staging_buffer,
+            staging_memory,
+            size,
+        }
+    }
+
+    //使う側のステージとの同期は呼び出し側でバリアを張る
+    pub fn cmd_copy(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let region = vk::BufferCopy::builder()
+            .src_offset(0)
+            .dst_offset(0)
+            .size(self.size)
+            .build();
+
+        unsafe {
+            device.cmd_copy_buffer(command_buffer, self.staging_buffer, self.buffer, &[region])
+        };
+    }
+
+    //ステージングバッファを破棄して転送先のバッファを返す
+    pub fn finish(self, device: &Device) -> (vk::Buffer, vk::DeviceMemory) {
+        unsafe {
+            device.destroy_buffer(self.staging_buffer, None);
+            memory_budget::free_memory(device, self.staging_memory);
+        }
+
+        (self.buffer, self.memory)
+    }
+}
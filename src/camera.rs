@@ -0,0 +1,176 @@
+use crate::depth_buffer::DepthConvention;
+use crate::input::{AxisAction, InputMap, InputState};
+use glam::{Mat4, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+use winit::event::MouseButton;
+
+//真上や真下を向くとlook_atの上方向と視線が平行になってしまうので手前で止める
+const MAX_PITCH: f32 = 89.0 * std::f32::consts::PI / 180.0;
+
+//1秒あたりの移動量
+const MOVE_SPEED: f32 = 2.0;
+
+//マウスの移動量1あたりの回転量(ラジアン)
+const MOUSE_SENSITIVITY: f32 = 0.002;
+
+//視点のAxisActionを倒し切った場合の1秒あたりの回転量(ラジアン)
+const LOOK_SPEED: f32 = 2.0;
+
+//カメラの射影の方法
+//シーンのファイルには{"type": "orthographic", "height": 2.0}のように書く
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Projection {
+    Perspective,
+    //heightは画面の縦に映るワールド座標の長さ、横はアスペクト比に合わせる
+    Orthographic { height: f32 },
+}
+
+//yawとpitchで向きを表すFPS風のカメラ
+//yawが0の時は-Z方向を向く
+pub struct Camera {
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub projection: Projection,
+    //垂直方向の画角(ラジアン)、Perspectiveの場合だけ使う
+    pub fov_y: f32,
+    pub near: f32,
+    pub far: f32,
+    //ReverseInfiniteでもfarはカリングとデバッグ表示の深度に使う
+    pub depth: DepthConvention,
+}
+
+impl Camera {
+    pub fn new(position: Vec3) -> Self {
+        Self {
+            position,
+            yaw: 0.0,
+            pitch: 0.0,
+            projection: Projection::Perspective,
+            fov_y: 45.0_f32.to_radians(),
+            near: 0.1,
+            far: 100.0,
+            depth: DepthConvention::Standard,
+        }
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        Vec3::new(
+            self.yaw.sin() * self.pitch.cos(),
+            self.pitch.sin(),
+            -self.yaw.cos() * self.pitch.cos(),
+        )
+    }
+
+    //positionに移動してtargetの方を向く、forwardの逆
+    pub fn look_at(&mut self, position: Vec3, target: Vec3) {
+        let direction = (target - position).normalize();
+
+        self.position = position;
+        self.yaw = direction.x.atan2(-direction.z);
+        self.pitch = direction.y.asin();
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.forward().cross(Vec3::Y).normalize()
+    }
+
+    pub fn view_matrix(&self) -> Mat4 {
+        Mat4::look_at_rh(self.position, self.position + self.forward(), Vec3::Y)
+    }
+
+    //depthの向きで描画に使うプロジェクション行列
+    pub fn projection_matrix(&self, aspect_ratio: f32) -> Mat4 {
+        self.projection_matrix_with(aspect_ratio, self.depth)
+    }
+
+    //視錐台カリングはnearからfarまでの有限の範囲で行うので、depthによらずStandardの行列を使う
+    //ReverseInfiniteの行列ではfarの平面が無くなり、クリップ座標から戻すとfarが無限遠になる
+    pub fn culling_projection_matrix(&self, aspect_ratio: f32) -> Mat4 {
+        self.projection_matrix_with(aspect_ratio, DepthConvention::Standard)
+    }
+
+    //glamのperspective_rhとorthographic_rhは深度が0から1になるのでVulkanのクリップ空間と同じ
+    //nearとfarを入れ替えて渡すとnearが1でfarが0になる、正射影は無限遠にできないのでReverseと同じにする
+    //ただしVulkanはOpenGLとY軸の向きが逆なので反転させる
+    fn projection_matrix_with(&self, aspect_ratio: f32, depth: DepthConvention) -> Mat4 {
+        let (near, far) = if depth.is_reversed() {
+            (self.far, self.near)
+        } else {
+            (self.near, self.far)
+        };
+
+        let mut projection = match self.projection {
+            Projection::Perspective if depth == DepthConvention::ReverseInfinite => {
+                Mat4::perspective_infinite_reverse_rh(self.fov_y, aspect_ratio, self.near)
+            }
+            Projection::Perspective => Mat4::perspective_rh(self.fov_y, aspect_ratio, near, far),
+            Projection::Orthographic { height } => {
+                let half_extents = Self::orthographic_half_extents(height, aspect_ratio);
+
+                Mat4::orthographic_rh(
+                    -half_extents.x,
+                    half_extents.x,
+                    -half_extents.y,
+                    half_extents.y,
+                    near,
+                    far,
+                )
+            }
+        };
+        projection.y_axis.y *= -1.0;
+
+        projection
+    }
+
+    //正射影で画面に映るワールド座標の範囲の半分
+    pub fn orthographic_half_extents(height: f32, aspect_ratio: f32) -> Vec2 {
+        Vec2::new(height * aspect_ratio, height) * 0.5
+    }
+
+    //移動のAxisActionで前後左右と上下に移動し、右ボタンを押している間はマウスで視点を回転させる
+    //視点のAxisActionでも回転させ、マウスと同時の場合は足し合わせる
+    //カメラが動いた場合はtrueを返す
+    pub fn update(&mut self, input: &InputState, input_map: &InputMap, delta_seconds: f32) -> bool {
+        let mut moved = false;
+
+        if input.is_mouse_pressed(MouseButton::Right) {
+            let (dx, dy) = input.cursor_delta();
+
+            if dx != 0.0 || dy != 0.0 {
+                self.yaw += dx as f32 * MOUSE_SENSITIVITY;
+                self.pitch =
+                    (self.pitch - dy as f32 * MOUSE_SENSITIVITY).clamp(-MAX_PITCH, MAX_PITCH);
+                moved = true;
+            }
+        }
+
+        let look = Vec2::new(
+            input_map.axis(input, AxisAction::LookRight),
+            input_map.axis(input, AxisAction::LookUp),
+        );
+
+        if look != Vec2::ZERO {
+            self.yaw += look.x * LOOK_SPEED * delta_seconds;
+            self.pitch =
+                (self.pitch + look.y * LOOK_SPEED * delta_seconds).clamp(-MAX_PITCH, MAX_PITCH);
+            moved = true;
+        }
+
+        let forward = self.forward();
+        let right = self.right();
+
+        let direction = forward * input_map.axis(input, AxisAction::MoveForward)
+            + right * input_map.axis(input, AxisAction::MoveRight)
+            + Vec3::Y * input_map.axis(input, AxisAction::MoveUp);
+
+        //斜め移動で速くならないように長さを1までに抑える、1より短い値はそのままゆっくり動く
+        if direction != Vec3::ZERO {
+            self.position += direction.clamp_length_max(1.0) * MOVE_SPEED * delta_seconds;
+            moved = true;
+        }
+
+        moved
+    }
+}
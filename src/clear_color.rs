@@ -0,0 +1,168 @@
+use crate::color_space::{ColorEncoding, ColorFormat};
+use ash::vk;
+use std::str::FromStr;
+
+//画面をクリアする色
+//値はカラーピッカーなどで選んだ色と同じ見た目になるようにsRGB空間で保持する
+//SRGBフォーマットやリニアの中間画像にはto_linearでリニアに戻してから書き込む
+//ManualSrgbの場合は変換されずにそのまま表示されるのでsRGBの値をそのまま書き込む
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ClearColor {
+    pub rgba: [f32; 4],
+}
+
+impl ClearColor {
+    pub const BLACK: Self = Self {
+        rgba: [0.0, 0.0, 0.0, 1.0],
+    };
+
+    //彩度と明度が最大の色を色相(0.0から1.0)から作る
+    pub fn from_hue(hue: f32) -> Self {
+        let h = hue.rem_euclid(1.0) * 6.0;
+        let x = 1.0 - (h % 2.0 - 1.0).abs();
+
+        let [r, g, b] = match h as u32 {
+            0 => [1.0, x, 0.0],
+            1 => [x, 1.0, 0.0],
+            2 => [0.0, 1.0, x],
+            3 => [0.0, x, 1.0],
+            4 => [x, 0.0, 1.0],
+            _ => [1.0, 0.0, x],
+        };
+
+        Self {
+            rgba: [r, g, b, 1.0],
+        }
+    }
+
+    //アルファはガンマ補正の対象外
+    pub fn to_linear(self) -> Self {
+        let [r, g, b, a] = self.rgba;
+
+        Self {
+            rgba: [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a],
+        }
+    }
+
+    //クリアする画像のフォーマットに合わせてクリア値に変換する
+    //クリアはシェーダーの書き込みと同じ扱いになる
+    pub fn to_clear_value(self, format: ColorFormat) -> vk::ClearValue {
+        let color = match format.shader_output() {
+            ColorEncoding::Linear => self.to_linear(),
+            ColorEncoding::Srgb => self,
+            //Pqの画像にはポストプロセスのパスが全画面を上書きするのでクリア値は使われない
+            ColorEncoding::Pq => self.to_linear(),
+        };
+
+        vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: color.rgba,
+            },
+        }
+    }
+}
+
+//`r,g,b`もしくは`r,g,b,a`の形式で各値は0.0から1.0
+impl FromStr for ClearColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("Invalid clear color '{}', expected r,g,b or r,g,b,a", s))?;
+
+        let rgba = match values[..] {
+            [r, g, b] => [r, g, b, 1.0],
+            [r, g, b, a] => [r, g, b, a],
+            _ => {
+                return Err(format!(
+                    "Invalid clear color '{}', expected 3 or 4 components",
+                    s
+                ))
+            }
+        };
+
+        if rgba.iter().any(|value| !(0.0..=1.0).contains(value)) {
+            return Err(format!(
+                "Invalid clear color '{}', components must be between 0.0 and 1.0",
+                s
+            ));
+        }
+
+        Ok(Self { rgba })
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_three_components_with_opaque_alpha() {
+        assert_eq!(
+            "0.1,0.2,0.3".parse::<ClearColor>(),
+            Ok(ClearColor {
+                rgba: [0.1, 0.2, 0.3, 1.0]
+            })
+        );
+    }
+
+    #[test]
+    fn parses_four_components() {
+        assert_eq!(
+            "0,0.5,1,0.25".parse::<ClearColor>(),
+            Ok(ClearColor {
+                rgba: [0.0, 0.5, 1.0, 0.25]
+            })
+        );
+    }
+
+    #[test]
+    fn ignores_whitespace_around_components() {
+        assert_eq!(
+            " 0.1 , 0.2,0.3 ,\t1 ".parse::<ClearColor>(),
+            Ok(ClearColor {
+                rgba: [0.1, 0.2, 0.3, 1.0]
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_components() {
+        assert!("1.5,0,0".parse::<ClearColor>().is_err());
+        assert!("0,-0.1,0".parse::<ClearColor>().is_err());
+        assert!("0,0,0,2".parse::<ClearColor>().is_err());
+        assert!("inf,0,0".parse::<ClearColor>().is_err());
+    }
+
+    #[test]
+    fn rejects_nan() {
+        assert!("NaN,0,0".parse::<ClearColor>().is_err());
+        assert!("0,0,0,nan".parse::<ClearColor>().is_err());
+    }
+
+    #[test]
+    fn rejects_wrong_arity() {
+        assert!("".parse::<ClearColor>().is_err());
+        assert!("0.5".parse::<ClearColor>().is_err());
+        assert!("0.5,0.5".parse::<ClearColor>().is_err());
+        assert!("0,0,0,0,0".parse::<ClearColor>().is_err());
+    }
+
+    #[test]
+    fn rejects_non_numbers() {
+        assert!("red,green,blue".parse::<ClearColor>().is_err());
+        assert!("0,,0".parse::<ClearColor>().is_err());
+        assert!("0 0 0".parse::<ClearColor>().is_err());
+    }
+}
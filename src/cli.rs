@@ -0,0 +1,1386 @@
+use crate::bloom;
+use crate::clear_color::ClearColor;
+use crate::config_file::{self, ConfigFile};
+use crate::debug::ValidationSeverity;
+use crate::depth_buffer::DepthConvention;
+use crate::device_report::ReportFormat;
+use crate::display_surface::DisplayChoice;
+use crate::gamepad::GamepadSettings;
+use crate::instancing::GridDrawMode;
+use crate::light_manager;
+use crate::mipmap::MipmapMode;
+use crate::msaa;
+use crate::post_process::PostEffect;
+use crate::render_scale::ScaleFilter;
+use crate::shadow_map;
+use crate::swap_chain_utils::{PresentModePreference, SurfaceFormatPreference};
+use crate::tonemap::{self, Tonemapper};
+use crate::vulkan_app::{self, RunMode, SoftwareRendering};
+use clap::builder::FalseyValueParser;
+use clap::error::ErrorKind;
+use clap::Parser;
+use std::cmp::Ordering;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+//--benchで--framesが無い場合に描画するフレーム数
+const DEFAULT_BENCH_FRAMES: u32 = 1000;
+
+//ウィンドウの大きさのデフォルト、論理ピクセル
+const DEFAULT_WINDOW_SIZE: u32 = 800;
+
+const DEFAULT_WINDOW_TITLE: &str = "vulkan_tutorial";
+
+//--configが無い場合に読む設定ファイル、無ければ読まない
+const DEFAULT_CONFIG_PATH: &str = "vulkan_tutorial.toml";
+
+//--upload-budgetが無い場合の--async-assetsの1フレームあたりの転送量、メガバイト
+const DEFAULT_UPLOAD_BUDGET_MB: u64 = 4;
+
+//--sceneが無い場合にF5とF9でシーンを書き出して読み込むファイル
+const DEFAULT_SCENE_PATH: &str = "scene.json";
+
+//コマンドライン、環境変数、設定ファイルから起動時に一度だけ決める設定
+//コマンドライン、環境変数、設定ファイル、デフォルトの順に優先される
+//デモを切り替えるフラグはコマンドラインからのみ読み、demoにまとめる
+#[derive(Clone, Debug)]
+pub struct AppConfig {
+    pub window_width: u32,
+    pub window_height: u32,
+    pub window_title: String,
+    pub window_resizable: bool,
+    pub present_mode: PresentModePreference,
+    pub surface_format: SurfaceFormatPreference,
+    //Noneの場合はドライバの最小枚数から決める
+    pub image_count: Option<u32>,
+    //シーンの1ピクセルあたりのサンプル数、1の場合はマルチサンプリングしない
+    //デバイスが対応していない場合はvulkan_appで対応している一番近い数に減らす
+    pub msaa_samples: u32,
+    //CPUがGPUを待たずに記録できるフレーム数、フレームごとのバッファやSemaphoreをこの数だけ作る
+    pub frames_in_flight: u32,
+    pub clear_color: ClearColor,
+    //Noneの場合は制限しない、--displayではディスプレイのリフレッシュレートを使う
+    pub target_fps: Option<u32>,
+    //--post-effect bloomでぼかした画像を足す割合、起動後はPageUp/PageDownで変えられる
+    pub bloom_intensity: f32,
+    //--post-effect tonemapかbloomでのトーンマッピングの方法と露出(EV)、起動後はTとHome/Endで変えられる
+    pub tonemapper: Tonemapper,
+    pub exposure: f32,
+    //シーンの深度値の向き、パイプラインを作る時に決めるので起動後は変えられない
+    pub depth: DepthConvention,
+    pub software_rendering: SoftwareRendering,
+    pub run_mode: RunMode,
+    //--benchの場合のみSome、描画して終了するフレーム数
+    pub bench_frames: Option<u32>,
+    //falseの場合は検証レイヤーとDebugUtilsを有効にしない
+    pub validation: bool,
+    pub validation_severity: ValidationSeverity,
+    //設定ファイルの[gamepad]からのみ読む
+    pub gamepad: GamepadSettings,
+    pub demo: DemoConfig,
+}
+
+//起動時に描画するものや使う機能を切り替えるフラグ、設定ファイルには書かない
+//使えない組み合わせや機能はvulkan_appで警告して無視する
+#[derive(Clone, Debug)]
+pub struct DemoConfig {
+    //--infoでウィンドウを表示せずに物理デバイスの情報を表示して終了する、--jsonを付けるとJSONで出力する
+    pub info_format: Option<ReportFormat>,
+    //ウィンドウを作らずにVK_KHR_displayで列挙したディスプレイへ直接presentする
+    pub display: Option<DisplayChoice>,
+    //メインのウィンドウに描画した画像を映すウィンドウを追加で開く数
+    pub mirror_windows: usize,
+    //メインのパイプラインの作成時間を派生とキャッシュの有無で比べて表示し、終了する
+    pub bench_pipelines: bool,
+    //このフレームでデバイスロストを発生させる
+    pub simulate_device_lost_at: Option<u64>,
+    //instanceとdeviceのホストメモリの確保を数えて終了時に表示する
+    pub track_host_allocations: bool,
+    //背景の色相を時間で変化させる
+    pub animate_clear_color: bool,
+    //GPUが読んだUniform Bufferの値をCPUで確認する
+    pub ubo_stress: bool,
+    //OBJファイルのモデルを三角形や四角形の代わりに描画する
+    pub obj_path: Option<PathBuf>,
+    //.gltfか.glbのスキンメッシュを読み込み、最初のアニメーションで動かしながら描画する
+    pub gltf_path: Option<PathBuf>,
+    //--objのモデルを別スレッドで読み込み、フレームをまたいで転送する
+    pub async_assets: bool,
+    //--async-assetsの1フレームあたりの転送量の上限、バイト
+    pub upload_budget: u64,
+    //N×Nの四角形をそれぞれ別のモデル行列で描画する
+    pub quad_grid: Option<u32>,
+    //N×Nの三角形を1回のインスタンス描画で描画する
+    pub instanced_grid: Option<u32>,
+    //--instanced-gridの各インスタンスに貼るテクスチャ配列のレイヤー数
+    pub texture_array: Option<u32>,
+    pub grid_draw_mode: GridDrawMode,
+    //色付きの半透明な四角形を重ねて描画する
+    pub transparent_quads: bool,
+    //起動時にコンピュートシェーダーの結果を確認する
+    pub compute_test: bool,
+    //synchronization2が使えるデバイスでも古いバリアとsubmitを使う
+    pub legacy_sync: bool,
+    //render passとframebufferを使わずに描画する
+    pub dynamic_rendering: bool,
+    //オブジェクトごとにマテリアルのテクスチャを貼る
+    pub textured: bool,
+    //descriptor indexingが使える場合でもマテリアルごとのDescriptor Setで描画する
+    pub no_bindless: bool,
+    //--texturedの最初のマテリアルに使うKTX2のファイル
+    pub ktx2_texture: Option<PathBuf>,
+    //Noneの場合はフォーマットの機能から選ぶ
+    pub mipmaps: Option<MipmapMode>,
+    //起動時にBlitとComputeで生成したミップマップを読み戻し、段ごとの最大の差を出す
+    pub compare_mipmaps: bool,
+    //--texturedの最初のマテリアルにコンピュートシェーダーが毎フレーム書き込むテクスチャを使う
+    pub procedural_texture: bool,
+    //メッシュに生成した法線マップを貼る
+    pub normal_mapping: bool,
+    //前のフレームまでのOCCLUSIONクエリで隠れていたオブジェクトの描画を省く
+    pub occlusion_culling: bool,
+    //毎フレームの入力とフレーム時間を記録し、終了時に書き出すファイル
+    pub record_session: Option<PathBuf>,
+    //--recordで記録した入力をウィンドウのイベントの代わりに使い、最後まで再生したら終了する
+    pub replay_session: Option<PathBuf>,
+    //F5とF9でシーンの状態を書き出して読み込むファイル
+    pub scene_path: PathBuf,
+    //シェーダーに渡せるライトの数、storage bufferはこの数だけ確保する
+    pub max_lights: u32,
+    //モデルの周りを色の付いたポイントライトが回り、真上からスポットライトで照らす
+    pub light_demo: bool,
+    //シーンのパスの前に画面のタイルごとに届くライトを選ぶ
+    pub light_culling: bool,
+    //画面の右半分に原点の周りを自動で回るカメラから見たシーンを描画する
+    pub split_screen: bool,
+    //頂点入力を使わないパイプラインで描画する
+    pub vertex_pulling: bool,
+    //加速構造を作り、ラスタライズの代わりにレイトレーシングパイプラインで描画する
+    pub raytrace: bool,
+    //--shadowsのシャドウマップの代わりにフラグメントシェーダーのray queryで影を付ける
+    pub ray_query_shadows: bool,
+    //テッセレーションで分割して変位させた平面を描画する
+    pub tessellation: bool,
+    //VK_EXT_mesh_shaderのタスクシェーダーとメッシュシェーダーでメッシュを描画する
+    pub mesh_shading: bool,
+    //指定した順番に掛けるエフェクト
+    pub post_effects: Vec<PostEffect>,
+    //フレームの統計などを画面の左上に文字で重ねて描画する
+    pub debug_text: bool,
+    //メッシュの代わりに正射影のカメラで描画するスプライトの数
+    pub sprite_count: Option<u32>,
+    //カメラを正射影で始める
+    pub orthographic: bool,
+    //シーンを描画する解像度のウィンドウに対する倍率
+    pub render_scale: Option<f32>,
+    pub render_scale_filter: ScaleFilter,
+    //生成した空を背景に描画する、skybox_ktxがある場合はそちらを使う
+    pub skybox: bool,
+    pub skybox_ktx: Option<PathBuf>,
+    //Someの場合はディレクショナルライトの影を描画する、シャドウマップの一辺のテクセル数
+    pub shadow_map_size: Option<u32>,
+    //オブジェクトの描画をセカンダリコマンドバッファに記録するスレッドの数
+    pub record_threads: Option<usize>,
+    //オブジェクトごとのUniform BufferをVK_KHR_push_descriptorで紐づける
+    pub push_descriptors: bool,
+    //コンピュートシェーダーで動かして描画するパーティクルの数
+    pub particle_count: Option<u32>,
+}
+
+//parseが失敗したか、起動せずに終了する理由
+#[derive(Debug)]
+pub enum CliError {
+    //--helpか--write-default-configが指定された、中身は標準出力に表示するメッセージ
+    Exit(String),
+    Invalid(String),
+}
+
+//clapは--helpもエラーとして返すので、表示して正常に終了する
+impl From<clap::Error> for CliError {
+    fn from(error: clap::Error) -> Self {
+        match error.kind() {
+            ErrorKind::DisplayHelp | ErrorKind::DisplayVersion => Self::Exit(error.to_string()),
+            _ => Self::Invalid(error.to_string()),
+        }
+    }
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            window_width: DEFAULT_WINDOW_SIZE,
+            window_height: DEFAULT_WINDOW_SIZE,
+            window_title: DEFAULT_WINDOW_TITLE.to_string(),
+            window_resizable: true,
+            //以前の挙動と同じくMAILBOXを優先する
+            present_mode: PresentModePreference::LowLatency,
+            surface_format: SurfaceFormatPreference::Srgb,
+            image_count: None,
+            msaa_samples: 1,
+            frames_in_flight: vulkan_app::DEFAULT_FRAMES_IN_FLIGHT,
+            clear_color: ClearColor::BLACK,
+            target_fps: None,
+            bloom_intensity: bloom::DEFAULT_INTENSITY,
+            tonemapper: Tonemapper::Aces,
+            exposure: tonemap::DEFAULT_EXPOSURE,
+            depth: DepthConvention::Standard,
+            software_rendering: SoftwareRendering::Disabled,
+            run_mode: RunMode::Continuous,
+            bench_frames: None,
+            //リリースビルドでは検証レイヤーが無い環境でも起動できるようにする
+            validation: cfg!(debug_assertions),
+            validation_severity: ValidationSeverity::Verbose,
+            gamepad: GamepadSettings::default(),
+            demo: DemoConfig::default(),
+        }
+    }
+}
+
+//どのフラグも指定しなかった場合
+impl Default for DemoConfig {
+    fn default() -> Self {
+        Self {
+            info_format: None,
+            display: None,
+            mirror_windows: 0,
+            bench_pipelines: false,
+            simulate_device_lost_at: None,
+            track_host_allocations: false,
+            animate_clear_color: false,
+            ubo_stress: false,
+            obj_path: None,
+            gltf_path: None,
+            async_assets: false,
+            upload_budget: DEFAULT_UPLOAD_BUDGET_MB * 1024 * 1024,
+            quad_grid: None,
+            instanced_grid: None,
+            texture_array: None,
+            grid_draw_mode: GridDrawMode::Instanced,
+            transparent_quads: false,
+            compute_test: false,
+            legacy_sync: false,
+            dynamic_rendering: false,
+            textured: false,
+            no_bindless: false,
+            ktx2_texture: None,
+            mipmaps: None,
+            compare_mipmaps: false,
+            procedural_texture: false,
+            normal_mapping: false,
+            occlusion_culling: false,
+            record_session: None,
+            replay_session: None,
+            scene_path: PathBuf::from(DEFAULT_SCENE_PATH),
+            max_lights: light_manager::DEFAULT_MAX_LIGHTS,
+            light_demo: false,
+            light_culling: false,
+            split_screen: false,
+            vertex_pulling: false,
+            raytrace: false,
+            ray_query_shadows: false,
+            tessellation: false,
+            mesh_shading: false,
+            post_effects: vec![],
+            debug_text: false,
+            sprite_count: None,
+            orthographic: false,
+            render_scale: None,
+            render_scale_filter: ScaleFilter::Linear,
+            skybox: false,
+            skybox_ktx: None,
+            shadow_map_size: None,
+            record_threads: None,
+            push_descriptors: false,
+            particle_count: None,
+        }
+    }
+}
+
+impl AppConfig {
+    pub fn parse() -> Result<Self, CliError> {
+        let args = Args::try_parse()?;
+
+        if let Some(path) = &args.write_default_config {
+            config_file::write(path, &Self::default()).map_err(CliError::Invalid)?;
+
+            return Err(CliError::Exit(format!(
+                "Wrote the default settings to {}\n",
+                path
+            )));
+        }
+
+        //指定されたファイルは必ず読み、デフォルトのファイルは無ければ読まない
+        let file = ConfigFile::load(
+            args.config.as_deref().unwrap_or(DEFAULT_CONFIG_PATH),
+            args.config.is_some(),
+        )
+        .map_err(CliError::Invalid)?;
+
+        Self::merge(&args, file.as_ref())
+    }
+
+    //デフォルトの値を設定ファイル、環境変数とコマンドラインの順に上書きし、最後に全体を確かめる
+    fn merge(args: &Args, file: Option<&ConfigFile>) -> Result<Self, CliError> {
+        let mut config = Self::default();
+
+        if let Some(file) = file {
+            file.apply(&mut config);
+        }
+
+        if let Some(width) = args.width {
+            config.window_width = width;
+        }
+
+        if let Some(height) = args.height {
+            config.window_height = height;
+        }
+
+        if let Some(title) = &args.title {
+            config.window_title = title.clone();
+        }
+
+        if let Some(resizable) = args.resizable {
+            config.window_resizable = resizable;
+        }
+
+        if let Some(present_mode) = args.present_mode {
+            config.present_mode = present_mode;
+        }
+
+        if let Some(surface_format) = args.surface_format {
+            config.surface_format = surface_format;
+        }
+
+        if args.hdr {
+            config.surface_format = SurfaceFormatPreference::Hdr;
+        }
+
+        if let Some(image_count) = args.image_count {
+            config.image_count = Some(image_count);
+        }
+
+        if let Some(msaa_samples) = args.msaa {
+            config.msaa_samples = msaa_samples;
+        }
+
+        if let Some(clear_color) = args.clear_color {
+            config.clear_color = clear_color;
+        }
+
+        //0は制限しないのと同じにする
+        if let Some(target_fps) = args.target_fps {
+            config.target_fps = Some(target_fps).filter(|&fps| fps > 0);
+        }
+
+        if let Some(bloom_intensity) = args.bloom_intensity {
+            config.bloom_intensity = bloom_intensity;
+        }
+
+        if let Some(tonemapper) = args.tonemap {
+            config.tonemapper = tonemapper;
+        }
+
+        if let Some(exposure) = args.exposure {
+            config.exposure = exposure;
+        }
+
+        if let Some(depth) = args.depth {
+            config.depth = depth;
+        }
+
+        if let Some(validation) = args.validation {
+            config.validation = validation;
+        }
+
+        if let Some(severity) = args.validation_severity {
+            config.validation_severity = severity;
+        }
+
+        config.software_rendering = args.software_rendering();
+        config.run_mode = args.run_mode();
+        config.demo = DemoConfig::from_args(args)?;
+
+        //vsyncやフレームレート制限で頭打ちにならないようにする
+        if args.bench {
+            config.bench_frames = Some(args.frames.unwrap_or(DEFAULT_BENCH_FRAMES));
+            config.present_mode = PresentModePreference::Uncapped;
+            config.target_fps = None;
+            config.run_mode = RunMode::Continuous;
+        }
+
+        //どこで指定された値でも同じように確かめる
+        if config.window_width == 0 || config.window_height == 0 {
+            return Err(CliError::Invalid(
+                "The window width and height must be greater than 0".to_string(),
+            ));
+        }
+
+        if config.bench_frames == Some(0) {
+            return Err(CliError::Invalid(
+                "The benchmark must render at least 1 frame".to_string(),
+            ));
+        }
+
+        if !(0.0..=bloom::MAX_INTENSITY).contains(&config.bloom_intensity) {
+            return Err(CliError::Invalid(format!(
+                "The bloom intensity must be between 0.0 and {}",
+                bloom::MAX_INTENSITY
+            )));
+        }
+
+        if !(-tonemap::MAX_EXPOSURE..=tonemap::MAX_EXPOSURE).contains(&config.exposure) {
+            return Err(CliError::Invalid(format!(
+                "The exposure must be between -{0} and {0} EV",
+                tonemap::MAX_EXPOSURE
+            )));
+        }
+
+        if config.image_count == Some(0) {
+            return Err(CliError::Invalid(
+                "The swapchain image count must be at least 1".to_string(),
+            ));
+        }
+
+        if !msaa::is_valid_sample_count(config.msaa_samples) {
+            return Err(CliError::Invalid(format!(
+                "The MSAA sample count must be a power of two up to {}, got {}",
+                msaa::MAX_SAMPLES,
+                config.msaa_samples
+            )));
+        }
+
+        if !(1..=vulkan_app::MAX_FRAMES_IN_FLIGHT).contains(&config.frames_in_flight) {
+            return Err(CliError::Invalid(format!(
+                "The number of frames in flight must be between 1 and {}, got {}",
+                vulkan_app::MAX_FRAMES_IN_FLIGHT,
+                config.frames_in_flight
+            )));
+        }
+
+        Ok(config)
+    }
+}
+
+impl DemoConfig {
+    fn from_args(args: &Args) -> Result<Self, CliError> {
+        let mut demo = Self::default();
+
+        if args.info {
+            demo.info_format = Some(if args.json {
+                ReportFormat::Json
+            } else {
+                ReportFormat::Text
+            });
+        }
+
+        if let Some(display_index) = args.display {
+            demo.display = Some(DisplayChoice {
+                display_index,
+                mode_index: args.display_mode,
+            });
+        }
+
+        if let Some(mirror_windows) = args.mirror_windows {
+            demo.mirror_windows = mirror_windows;
+        }
+
+        demo.bench_pipelines = args.bench_pipelines;
+        demo.simulate_device_lost_at = args.simulate_device_lost;
+        demo.track_host_allocations = args.track_host_allocations;
+        demo.animate_clear_color = args.animate_clear_color;
+        demo.ubo_stress = args.ubo_stress;
+        demo.obj_path = args.obj.clone();
+        demo.gltf_path = args.gltf.clone();
+        demo.async_assets = args.async_assets;
+
+        if let Some(megabytes) = args.upload_budget {
+            demo.upload_budget = megabytes * 1024 * 1024;
+        }
+
+        demo.quad_grid = args.quad_grid;
+        demo.instanced_grid = args.instanced_grid;
+        demo.texture_array = args.texture_array;
+
+        //両方指定した場合はindirectにする
+        demo.grid_draw_mode = if args.indirect {
+            GridDrawMode::Indirect
+        } else if args.draw_per_instance {
+            GridDrawMode::PerInstance
+        } else {
+            GridDrawMode::Instanced
+        };
+
+        demo.transparent_quads = args.transparent_quads;
+        demo.compute_test = args.compute_test;
+        demo.legacy_sync = args.legacy_sync;
+        demo.dynamic_rendering = args.dynamic_rendering;
+        demo.textured = args.textured;
+        demo.no_bindless = args.no_bindless;
+        demo.ktx2_texture = args.ktx2.clone();
+        demo.mipmaps = args.mipmaps;
+        demo.compare_mipmaps = args.compare_mipmaps;
+        demo.procedural_texture = args.procedural_texture;
+        demo.normal_mapping = args.normal_mapping;
+        demo.occlusion_culling = args.occlusion_culling;
+        demo.record_session = args.record.clone();
+        demo.replay_session = args.replay.clone();
+
+        if let Some(scene_path) = &args.scene {
+            demo.scene_path = scene_path.clone();
+        }
+
+        if let Some(max_lights) = args.max_lights {
+            demo.max_lights = max_lights;
+        }
+
+        demo.light_demo = args.light_demo;
+        demo.light_culling = args.light_culling;
+        demo.split_screen = args.split_screen;
+        demo.vertex_pulling = args.vertex_pulling;
+        demo.raytrace = args.raytrace;
+        demo.ray_query_shadows = args.ray_query_shadows;
+        demo.tessellation = args.tessellation;
+        demo.mesh_shading = args.mesh_shading;
+
+        if let Some(value) = &args.post_effect {
+            demo.post_effects = post_effects(value)?;
+        }
+
+        demo.debug_text = args.debug_text;
+        demo.sprite_count = args.sprites;
+        demo.orthographic = args.orthographic;
+        demo.render_scale = args.render_scale;
+
+        if let Some(filter) = args.render_scale_filter {
+            demo.render_scale_filter = filter;
+        }
+
+        demo.skybox = args.skybox;
+        demo.skybox_ktx = args.skybox_ktx.clone();
+
+        //--shadow-map-sizeを指定した場合は--shadowsも有効になる
+        demo.shadow_map_size = args
+            .shadow_map_size
+            .or_else(|| args.shadows.then(|| shadow_map::DEFAULT_SIZE));
+
+        demo.record_threads = args.record_threads;
+        demo.push_descriptors = args.push_descriptors;
+        demo.particle_count = args.particles;
+
+        Ok(demo)
+    }
+}
+
+//`invert,vignette`のようにカンマ区切りで指定した順番に掛ける
+//bloomはBloomの画像を1組しか持たないので、1回しか指定できない
+fn post_effects(value: &str) -> Result<Vec<PostEffect>, CliError> {
+    let mut effects = vec![];
+
+    for name in value.split(',') {
+        match PostEffect::from_name(name.trim()) {
+            Some(PostEffect::Bloom) if effects.contains(&PostEffect::Bloom) => {
+                return Err(CliError::Invalid(
+                    "bloom can only be applied once".to_string(),
+                ));
+            }
+            Some(effect) => effects.push(effect),
+            None => {
+                return Err(CliError::Invalid(format!(
+                    "Unknown post effect '{}', expected invert, vignette, passthrough, bloom or tonemap",
+                    name
+                )));
+            }
+        }
+    }
+
+    Ok(effects)
+}
+
+//プログラム名を除いたコマンドライン引数と、代わりに使える環境変数
+//環境変数はclapがコマンドラインに無い場合にだけ読むので、コマンドライン、環境変数の順に優先される
+//ここで読めるのは値の形だけで、設定ファイルの値と合わせた確認はAppConfig::mergeが行う
+#[derive(Parser, Debug)]
+#[command(
+    name = env!("CARGO_PKG_NAME"),
+    after_help = "Options and environment variables override the settings file, run with\n--write-default-config vulkan_tutorial.toml to create one."
+)]
+struct Args {
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = "VULKAN_TUTORIAL_CONFIG",
+        help = "settings file to read (default: vulkan_tutorial.toml if present)"
+    )]
+    config: Option<String>,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "write the default settings to PATH and exit"
+    )]
+    write_default_config: Option<String>,
+    #[arg(
+        long,
+        value_name = "N",
+        help = "window width in logical pixels (default: 800)"
+    )]
+    width: Option<u32>,
+    #[arg(
+        long,
+        value_name = "N",
+        help = "window height in logical pixels (default: 800)"
+    )]
+    height: Option<u32>,
+    #[arg(
+        long,
+        value_name = "TEXT",
+        help = "window title (default: vulkan_tutorial)"
+    )]
+    title: Option<String>,
+    #[arg(
+        long,
+        value_name = "true|false",
+        help = "whether the window can be resized (default: true)"
+    )]
+    resizable: Option<bool>,
+    #[arg(
+        long,
+        value_name = "MODE",
+        env = "VULKAN_TUTORIAL_PRESENT_MODE",
+        help = "vsync, low-latency or uncapped (default: low-latency)"
+    )]
+    present_mode: Option<PresentModePreference>,
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        env = "VULKAN_TUTORIAL_SURFACE_FORMAT",
+        help = "srgb, unorm, hdr10, scrgb or hdr (default: srgb)"
+    )]
+    surface_format: Option<SurfaceFormatPreference>,
+    #[arg(
+        long,
+        help = "prefer an HDR10 or scRGB swapchain, same as --surface-format hdr"
+    )]
+    hdr: bool,
+    #[arg(
+        long,
+        value_name = "N",
+        env = "VULKAN_TUTORIAL_IMAGE_COUNT",
+        help = "swapchain image count, at least 1"
+    )]
+    image_count: Option<u32>,
+    #[arg(
+        long,
+        value_name = "N",
+        env = "VULKAN_TUTORIAL_MSAA",
+        help = "samples per pixel of the scene, a power of two up to 64, 1 to disable (default: 1)"
+    )]
+    msaa: Option<u32>,
+    #[arg(
+        long,
+        value_name = "R,G,B[,A]",
+        env = "VULKAN_TUTORIAL_CLEAR_COLOR",
+        help = "background color, each value from 0.0 to 1.0"
+    )]
+    clear_color: Option<ClearColor>,
+    #[arg(
+        long,
+        value_name = "N",
+        env = "VULKAN_TUTORIAL_TARGET_FPS",
+        help = "frame rate limit, 0 for unlimited"
+    )]
+    target_fps: Option<u32>,
+    #[arg(
+        long,
+        value_name = "X",
+        help = "with --post-effect bloom, how much of the blur to add (default: 0.6)"
+    )]
+    bloom_intensity: Option<f32>,
+    #[arg(
+        long,
+        value_name = "OPERATOR",
+        help = "aces, reinhard or exposure (default: aces)"
+    )]
+    tonemap: Option<Tonemapper>,
+    #[arg(
+        long,
+        value_name = "EV",
+        allow_negative_numbers = true,
+        help = "exposure in stops before tonemapping (default: 0.0)"
+    )]
+    exposure: Option<f32>,
+    #[arg(
+        long,
+        value_name = "CONVENTION",
+        help = "standard, reverse or reverse-infinite depth (default: standard)"
+    )]
+    depth: Option<DepthConvention>,
+    #[arg(
+        long,
+        help = "pick a CPU device if there is one [env: VULKAN_TUTORIAL_SOFTWARE=1]"
+    )]
+    prefer_software: bool,
+    #[arg(
+        long,
+        help = "fail unless a CPU device is found [env: VULKAN_TUTORIAL_SOFTWARE=require]"
+    )]
+    require_software: bool,
+    //--prefer-softwareと--require-softwareの代わりの環境変数、requireか1かtrue
+    #[arg(
+        long = "software",
+        env = "VULKAN_TUTORIAL_SOFTWARE",
+        hide = true,
+        value_parser = software_variable
+    )]
+    software: Option<SoftwareRendering>,
+    #[arg(
+        long,
+        env = "VULKAN_TUTORIAL_ON_DEMAND",
+        value_parser = FalseyValueParser::new(),
+        help = "redraw only after input or window events"
+    )]
+    on_demand: bool,
+    #[arg(
+        long,
+        value_name = "N",
+        help = "with --on-demand, also redraw at this interval"
+    )]
+    redraw_interval_ms: Option<u64>,
+    #[arg(
+        long,
+        help = "render --frames frames on a fixed camera path, print statistics and exit"
+    )]
+    bench: bool,
+    #[arg(
+        long,
+        value_name = "N",
+        help = "with --bench, the number of frames to render (default: 1000)"
+    )]
+    frames: Option<u32>,
+    #[arg(
+        long,
+        value_name = "true|false",
+        help = "enable the validation layers (default: true in debug builds)"
+    )]
+    validation: Option<bool>,
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        help = "verbose, info, warning or error (default: verbose)"
+    )]
+    validation_severity: Option<ValidationSeverity>,
+    #[arg(
+        long,
+        help = "print the physical devices without opening a window and exit"
+    )]
+    info: bool,
+    #[arg(long, help = "with --info, print JSON")]
+    json: bool,
+    #[arg(
+        long,
+        value_name = "INDEX",
+        help = "present to a display with VK_KHR_display instead of a window"
+    )]
+    display: Option<usize>,
+    #[arg(
+        long,
+        value_name = "INDEX",
+        help = "with --display, the display mode (default: the largest)"
+    )]
+    display_mode: Option<usize>,
+    #[arg(
+        long,
+        value_name = "N",
+        help = "open N more windows showing the main window's image"
+    )]
+    mirror_windows: Option<usize>,
+    #[arg(
+        long,
+        help = "time the pipeline creation with derivatives and the cache, then exit"
+    )]
+    bench_pipelines: bool,
+    #[arg(long, value_name = "FRAME", help = "lose the device at frame FRAME")]
+    simulate_device_lost: Option<u64>,
+    #[arg(long, help = "count the host allocations of the instance and device")]
+    track_host_allocations: bool,
+    #[arg(long, help = "cycle the hue of the background")]
+    animate_clear_color: bool,
+    #[arg(
+        long,
+        help = "check the uniform buffer values read by the GPU on the CPU"
+    )]
+    ubo_stress: bool,
+    #[arg(long, value_name = "PATH", help = "draw the OBJ model at PATH")]
+    obj: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "draw and animate the skinned .gltf or .glb model at PATH"
+    )]
+    gltf: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "load --obj on a worker thread and upload it over several frames"
+    )]
+    async_assets: bool,
+    #[arg(
+        long,
+        value_name = "MB",
+        value_parser = positive::<u64>,
+        help = "with --async-assets, the upload limit per frame (default: 4)"
+    )]
+    upload_budget: Option<u64>,
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = positive::<u32>,
+        help = "draw NxN quads with a model matrix each"
+    )]
+    quad_grid: Option<u32>,
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = positive::<u32>,
+        help = "draw NxN triangles with one instanced draw"
+    )]
+    instanced_grid: Option<u32>,
+    #[arg(
+        long,
+        value_name = "LAYERS",
+        value_parser = positive::<u32>,
+        help = "with --instanced-grid, texture each instance from a texture array"
+    )]
+    texture_array: Option<u32>,
+    #[arg(long, help = "with --instanced-grid, draw each instance separately")]
+    draw_per_instance: bool,
+    #[arg(
+        long,
+        help = "with --instanced-grid, draw with cmd_draw_indexed_indirect"
+    )]
+    indirect: bool,
+    #[arg(long, help = "draw overlapping translucent quads")]
+    transparent_quads: bool,
+    #[arg(long, help = "check the result of a compute shader at startup")]
+    compute_test: bool,
+    #[arg(
+        long,
+        help = "use the old barriers and submit even with synchronization2"
+    )]
+    legacy_sync: bool,
+    #[arg(long, help = "draw without render passes and framebuffers")]
+    dynamic_rendering: bool,
+    #[arg(long, help = "texture each object with its material")]
+    textured: bool,
+    #[arg(long, help = "with --textured, use a descriptor set per material")]
+    no_bindless: bool,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "with --textured, use the KTX2 file at PATH for the first material"
+    )]
+    ktx2: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "MODE",
+        help = "with --textured, blit, compute or none (default: by format)"
+    )]
+    mipmaps: Option<MipmapMode>,
+    #[arg(long, help = "compare the blit and compute mipmaps at startup")]
+    compare_mipmaps: bool,
+    #[arg(
+        long,
+        help = "with --textured, write the first material with a compute shader"
+    )]
+    procedural_texture: bool,
+    #[arg(long, help = "light the mesh with a generated normal map")]
+    normal_mapping: bool,
+    #[arg(
+        long,
+        help = "skip objects hidden in the previous frames' occlusion queries"
+    )]
+    occlusion_culling: bool,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "record the input and frame times and write them to PATH"
+    )]
+    record: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "replay a --record file instead of the window events, then exit"
+    )]
+    replay: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "file F5 and F9 save and load the scene to (default: scene.json)"
+    )]
+    scene: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = positive::<u32>,
+        help = "number of lights the shaders can read (default: 16)"
+    )]
+    max_lights: Option<u32>,
+    #[arg(
+        long,
+        help = "circle colored point lights around the model under a spot light"
+    )]
+    light_demo: bool,
+    #[arg(
+        long,
+        help = "pick the lights reaching each screen tile before shading"
+    )]
+    light_culling: bool,
+    #[arg(long, help = "draw an orbiting camera's view on the right half")]
+    split_screen: bool,
+    #[arg(
+        long,
+        help = "read the vertices from a storage buffer instead of vertex input"
+    )]
+    vertex_pulling: bool,
+    #[arg(
+        long,
+        help = "draw with a ray tracing pipeline instead of rasterization"
+    )]
+    raytrace: bool,
+    #[arg(long, help = "with --shadows, trace the shadows with ray queries")]
+    ray_query_shadows: bool,
+    #[arg(long, help = "draw a tessellated and displaced plane")]
+    tessellation: bool,
+    #[arg(
+        long,
+        help = "draw the mesh with a task and mesh shader instead of the vertex shader"
+    )]
+    mesh_shading: bool,
+    #[arg(
+        long,
+        value_name = "LIST",
+        help = "comma separated invert, vignette, passthrough, bloom or tonemap"
+    )]
+    post_effect: Option<String>,
+    #[arg(long, help = "draw the frame statistics as text")]
+    debug_text: bool,
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = positive::<u32>,
+        help = "draw N sprites with an orthographic camera instead of the mesh"
+    )]
+    sprites: Option<u32>,
+    #[arg(long, help = "start with an orthographic camera")]
+    orthographic: bool,
+    #[arg(
+        long,
+        value_name = "X",
+        value_parser = positive::<f32>,
+        help = "render at X times the window size and scale to the swapchain"
+    )]
+    render_scale: Option<f32>,
+    #[arg(
+        long,
+        value_name = "FILTER",
+        help = "linear or nearest (default: linear)"
+    )]
+    render_scale_filter: Option<ScaleFilter>,
+    #[arg(long, help = "draw a generated sky as the background")]
+    skybox: bool,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "draw the KTX cubemap at PATH as the background"
+    )]
+    skybox_ktx: Option<PathBuf>,
+    #[arg(long, help = "draw the shadows of the directional light")]
+    shadows: bool,
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = positive::<u32>,
+        help = "shadow map size in texels, implies --shadows (default: 2048)"
+    )]
+    shadow_map_size: Option<u32>,
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = positive::<usize>,
+        help = "record the objects on N threads into secondary command buffers"
+    )]
+    record_threads: Option<usize>,
+    #[arg(long, help = "bind the object uniforms with VK_KHR_push_descriptor")]
+    push_descriptors: bool,
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = positive::<u32>,
+        help = "simulate N particles in a compute shader"
+    )]
+    particles: Option<u32>,
+}
+
+impl Args {
+    fn software_rendering(&self) -> SoftwareRendering {
+        if self.require_software {
+            return SoftwareRendering::Require;
+        }
+
+        if self.prefer_software {
+            return SoftwareRendering::Prefer;
+        }
+
+        self.software.unwrap_or(SoftwareRendering::Disabled)
+    }
+
+    fn run_mode(&self) -> RunMode {
+        if !self.on_demand {
+            return RunMode::Continuous;
+        }
+
+        RunMode::OnDemand {
+            animation_interval: self.redraw_interval_ms.map(Duration::from_millis),
+        }
+    }
+}
+
+//VULKAN_TUTORIAL_SOFTWAREの値、それ以外の値は指定しなかったのと同じにする
+fn software_variable(value: &str) -> Result<SoftwareRendering, String> {
+    Ok(match value {
+        "require" => SoftwareRendering::Require,
+        "1" | "true" => SoftwareRendering::Prefer,
+        _ => SoftwareRendering::Disabled,
+    })
+}
+
+//グリッドの大きさや数など、0以下の値をエラーにする
+fn positive<T>(value: &str) -> Result<T, String>
+where
+    T: FromStr + PartialOrd + Default,
+    T::Err: fmt::Display,
+{
+    let value = value.parse::<T>().map_err(|error| error.to_string())?;
+
+    //NaNは0とも比べられないので、ここでエラーになる
+    if value.partial_cmp(&T::default()) != Some(Ordering::Greater) {
+        return Err("must be greater than 0".to_string());
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::sync::atomic::{self, AtomicBool};
+    use std::thread;
+
+    //clapは環境変数をプロセスから読むので、引数を読むテストは1つずつ行う
+    //このツールチェインではMutex::newをstaticに使えないので、AtomicBoolで待つ
+    static ENVIRONMENT: AtomicBool = AtomicBool::new(false);
+
+    struct EnvironmentLock;
+
+    impl EnvironmentLock {
+        fn acquire() -> Self {
+            while ENVIRONMENT
+                .compare_exchange_weak(
+                    false,
+                    true,
+                    atomic::Ordering::Acquire,
+                    atomic::Ordering::Relaxed,
+                )
+                .is_err()
+            {
+                thread::yield_now();
+            }
+
+            Self
+        }
+    }
+
+    //テストが失敗してpanicした場合も解放する
+    impl Drop for EnvironmentLock {
+        fn drop(&mut self) {
+            ENVIRONMENT.store(false, atomic::Ordering::Release);
+        }
+    }
+
+    const VARIABLES: [&str; 9] = [
+        "VULKAN_TUTORIAL_CONFIG",
+        "VULKAN_TUTORIAL_PRESENT_MODE",
+        "VULKAN_TUTORIAL_SURFACE_FORMAT",
+        "VULKAN_TUTORIAL_IMAGE_COUNT",
+        "VULKAN_TUTORIAL_MSAA",
+        "VULKAN_TUTORIAL_CLEAR_COLOR",
+        "VULKAN_TUTORIAL_TARGET_FPS",
+        "VULKAN_TUTORIAL_SOFTWARE",
+        "VULKAN_TUTORIAL_ON_DEMAND",
+    ];
+
+    //variablesだけを設定した環境でargsを読む
+    fn try_args(args: &[&str], variables: &[(&str, &str)]) -> Result<Args, clap::Error> {
+        let _lock = EnvironmentLock::acquire();
+
+        for name in VARIABLES {
+            env::remove_var(name);
+        }
+
+        for (name, value) in variables {
+            env::set_var(name, value);
+        }
+
+        let parsed =
+            Args::try_parse_from(std::iter::once("vulkan-tutorial").chain(args.iter().copied()));
+
+        for (name, _) in variables {
+            env::remove_var(name);
+        }
+
+        parsed
+    }
+
+    fn args(args: &[&str], variables: &[(&str, &str)]) -> Args {
+        try_args(args, variables).unwrap()
+    }
+
+    fn invalid_args(args: &[&str]) -> String {
+        match try_args(args, &[]) {
+            Err(error) => error.to_string(),
+            Ok(args) => panic!("expected an error, got {:?}", args),
+        }
+    }
+
+    fn file(text: &str) -> ConfigFile {
+        ConfigFile::parse("test.toml", text).unwrap()
+    }
+
+    fn merged(args: &Args, file: Option<&ConfigFile>) -> AppConfig {
+        AppConfig::merge(args, file).unwrap()
+    }
+
+    fn error(args: &Args, file: Option<&ConfigFile>) -> String {
+        match AppConfig::merge(args, file) {
+            Err(CliError::Invalid(message)) => message,
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    const VSYNC_FILE: &str =
+        "[window]\nwidth = 640\n\n[renderer]\npresent_mode = \"vsync\"\nimage_count = 2\n";
+
+    #[test]
+    fn defaults_without_any_source() {
+        let config = merged(&args(&[], &[]), None);
+
+        assert_eq!(config.window_width, DEFAULT_WINDOW_SIZE);
+        assert_eq!(config.window_height, DEFAULT_WINDOW_SIZE);
+        assert_eq!(config.present_mode, PresentModePreference::LowLatency);
+        assert_eq!(config.image_count, None);
+        assert_eq!(config.target_fps, None);
+        assert_eq!(config.software_rendering, SoftwareRendering::Disabled);
+        assert_eq!(config.run_mode, RunMode::Continuous);
+        assert_eq!(config.demo.scene_path, PathBuf::from(DEFAULT_SCENE_PATH));
+        assert_eq!(config.demo.max_lights, light_manager::DEFAULT_MAX_LIGHTS);
+    }
+
+    #[test]
+    fn file_overrides_defaults() {
+        let config = merged(&args(&[], &[]), Some(&file(VSYNC_FILE)));
+
+        assert_eq!(config.window_width, 640);
+        //ファイルに無い値はデフォルトのまま
+        assert_eq!(config.window_height, DEFAULT_WINDOW_SIZE);
+        assert_eq!(config.present_mode, PresentModePreference::Vsync);
+        assert_eq!(config.image_count, Some(2));
+    }
+
+    #[test]
+    fn environment_overrides_file() {
+        let args = args(
+            &[],
+            &[
+                ("VULKAN_TUTORIAL_PRESENT_MODE", "uncapped"),
+                ("VULKAN_TUTORIAL_IMAGE_COUNT", "4"),
+            ],
+        );
+        let config = merged(&args, Some(&file(VSYNC_FILE)));
+
+        assert_eq!(config.present_mode, PresentModePreference::Uncapped);
+        assert_eq!(config.image_count, Some(4));
+        assert_eq!(config.window_width, 640);
+    }
+
+    #[test]
+    fn command_line_overrides_environment_and_file() {
+        let args = args(
+            &["--present-mode", "low-latency", "--width", "1024"],
+            &[("VULKAN_TUTORIAL_PRESENT_MODE", "uncapped")],
+        );
+        let config = merged(&args, Some(&file(VSYNC_FILE)));
+
+        assert_eq!(config.present_mode, PresentModePreference::LowLatency);
+        assert_eq!(config.window_width, 1024);
+        assert_eq!(config.image_count, Some(2));
+    }
+
+    #[test]
+    fn software_flags_override_environment() {
+        let require = [("VULKAN_TUTORIAL_SOFTWARE", "require")];
+
+        assert_eq!(
+            merged(&args(&[], &require), None).software_rendering,
+            SoftwareRendering::Require
+        );
+        assert_eq!(
+            merged(&args(&["--prefer-software"], &require), None).software_rendering,
+            SoftwareRendering::Prefer
+        );
+        assert_eq!(
+            merged(&args(&[], &[("VULKAN_TUTORIAL_SOFTWARE", "1")]), None).software_rendering,
+            SoftwareRendering::Prefer
+        );
+        assert_eq!(
+            merged(&args(&[], &[("VULKAN_TUTORIAL_SOFTWARE", "no")]), None).software_rendering,
+            SoftwareRendering::Disabled
+        );
+    }
+
+    #[test]
+    fn on_demand_from_environment() {
+        let args = args(
+            &["--redraw-interval-ms", "250"],
+            &[("VULKAN_TUTORIAL_ON_DEMAND", "true")],
+        );
+
+        assert_eq!(
+            merged(&args, None).run_mode,
+            RunMode::OnDemand {
+                animation_interval: Some(Duration::from_millis(250))
+            }
+        );
+    }
+
+    #[test]
+    fn zero_target_fps_is_unlimited() {
+        let file = file("[renderer]\ntarget_fps = 30\n");
+
+        assert_eq!(merged(&args(&[], &[]), Some(&file)).target_fps, Some(30));
+        assert_eq!(
+            merged(&args(&["--target-fps", "0"], &[]), Some(&file)).target_fps,
+            None
+        );
+    }
+
+    #[test]
+    fn bench_overrides_pacing_from_every_source() {
+        let file = file("[renderer]\npresent_mode = \"vsync\"\ntarget_fps = 60\n");
+        let args = args(
+            &["--bench", "--on-demand"],
+            &[("VULKAN_TUTORIAL_TARGET_FPS", "30")],
+        );
+        let config = merged(&args, Some(&file));
+
+        assert_eq!(config.bench_frames, Some(DEFAULT_BENCH_FRAMES));
+        assert_eq!(config.present_mode, PresentModePreference::Uncapped);
+        assert_eq!(config.target_fps, None);
+        assert_eq!(config.run_mode, RunMode::Continuous);
+    }
+
+    #[test]
+    fn validation_covers_every_source() {
+        assert!(error(&args(&[], &[]), Some(&file("[window]\nwidth = 0\n"))).contains("width"));
+        assert!(
+            error(&args(&[], &[("VULKAN_TUTORIAL_IMAGE_COUNT", "0")]), None)
+                .contains("image count")
+        );
+        assert!(error(&args(&["--bench", "--frames", "0"], &[]), None).contains("1 frame"));
+        assert!(invalid_args(&["--width", "wide"]).contains("--width"));
+    }
+
+    #[test]
+    fn msaa_must_be_a_power_of_two_up_to_64() {
+        assert_eq!(merged(&args(&[], &[]), None).msaa_samples, 1);
+        assert_eq!(merged(&args(&["--msaa", "4"], &[]), None).msaa_samples, 4);
+        assert_eq!(
+            merged(&args(&[], &[("VULKAN_TUTORIAL_MSAA", "64")]), None).msaa_samples,
+            64
+        );
+
+        for samples in ["0", "3", "12", "128"] {
+            assert!(error(&args(&["--msaa", samples], &[]), None).contains("power of two"));
+        }
+    }
+
+    #[test]
+    fn frames_in_flight_come_from_the_file() {
+        assert_eq!(
+            merged(&args(&[], &[]), None).frames_in_flight,
+            vulkan_app::DEFAULT_FRAMES_IN_FLIGHT
+        );
+        assert_eq!(
+            merged(
+                &args(&[], &[]),
+                Some(&file("[renderer]\nframes_in_flight = 3\n"))
+            )
+            .frames_in_flight,
+            3
+        );
+        assert!(error(
+            &args(&[], &[]),
+            Some(&file("[renderer]\nframes_in_flight = 0\n"))
+        )
+        .contains("frames in flight"));
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        assert!(try_args(&["--textured", "--hdr"], &[]).is_ok());
+        assert!(invalid_args(&["--textured", "--texture"]).contains("--texture"));
+        assert!(invalid_args(&["extra"]).contains("extra"));
+        //値を取るオプションに値が無い場合は次のオプションを値として読まない
+        assert!(invalid_args(&["--title", "--textured"]).contains("--title"));
+    }
+
+    #[test]
+    fn help_exits_without_an_error() {
+        let error = try_args(&["--help"], &[]).unwrap_err();
+
+        assert!(matches!(CliError::from(error), CliError::Exit(help) if help.contains("--msaa")));
+    }
+
+    #[test]
+    fn demo_values_are_validated() {
+        let demo = |values: &[&str]| {
+            try_args(values, &[])
+                .map_err(CliError::from)
+                .and_then(|args| DemoConfig::from_args(&args))
+        };
+
+        assert_eq!(
+            demo(&["--shadows"]).unwrap().shadow_map_size,
+            Some(shadow_map::DEFAULT_SIZE)
+        );
+        assert_eq!(
+            demo(&["--shadow-map-size", "512"]).unwrap().shadow_map_size,
+            Some(512)
+        );
+        assert_eq!(
+            demo(&["--upload-budget", "2"]).unwrap().upload_budget,
+            2 * 1024 * 1024
+        );
+        assert_eq!(
+            demo(&["--post-effect", "vignette, bloom"])
+                .unwrap()
+                .post_effects,
+            vec![PostEffect::Vignette, PostEffect::Bloom]
+        );
+        assert!(demo(&["--quad-grid", "0"]).is_err());
+        assert!(demo(&["--render-scale", "NaN"]).is_err());
+        assert!(demo(&["--render-scale", "-1"]).is_err());
+        assert!(demo(&["--post-effect", "bloom,bloom"]).is_err());
+        assert!(demo(&["--post-effect", "sepia"]).is_err());
+    }
+}
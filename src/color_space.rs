@@ -0,0 +1,93 @@
+use ash::vk;
+
+//値がどの空間で表されているか
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorEncoding {
+    //光の強さに比例する値、ライティングやブレンドはこの空間で行う
+    Linear,
+    //sRGBの伝達関数でエンコードされた値、カラーピッカーの値やディスプレイに送る値はこちら
+    Srgb,
+    //HDR10の色空間の値、BT.2020の原色に変換してからPQ(ST 2084)の伝達関数でエンコードする
+    //リニアの1.0をSDRの白として扱う
+    Pq,
+}
+
+//ポストプロセスの中間画像のフォーマット
+//カラーアタッチメント、ブレンド、linearでのサンプリングは全ての実装でサポートされている
+pub const INTERMEDIATE_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+//画像のフォーマットと、そこに格納される値の空間の組
+//どのステージがリニアでどのステージがエンコード済みかを型で区別する
+//シェーダーの中の計算は常にリニアで行い、エンコードが必要なのは書き込む時だけ
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorFormat {
+    //SRGBフォーマット
+    //格納される値はsRGBだが、ハードウェアがサンプリング時にデコードし書き込み時にエンコードするのでシェーダーからはリニアに見える
+    Srgb(vk::Format),
+    //変換されないフォーマットにシェーダーでエンコードしたsRGBの値を格納する
+    //SRGB_NONLINEARの色空間でUNORMのswapchainを使う場合
+    ManualSrgb(vk::Format),
+    //変換されないフォーマットにリニアの値をそのまま格納する
+    //ポストプロセスの中間画像や、scRGB(EXTENDED_SRGB_LINEAR)のswapchain
+    //scRGBはリニアの1.0がSDRの白なのでエンコードは要らない
+    Linear(vk::Format),
+    //HDR10_ST2084の色空間のswapchainにシェーダーでPQにエンコードした値を格納する
+    //エンコードはポストプロセスの最後のパスでだけ行うので、シーンは必ず中間画像に描画する
+    Pq(vk::Format),
+}
+
+impl ColorFormat {
+    //swapchainのフォーマットは色空間と合わせて決まる
+    pub fn from_surface_format(surface_format: vk::SurfaceFormatKHR) -> Self {
+        if is_srgb_format(surface_format.format) {
+            Self::Srgb(surface_format.format)
+        } else if surface_format.color_space == vk::ColorSpaceKHR::HDR10_ST2084_EXT {
+            Self::Pq(surface_format.format)
+        } else if surface_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR {
+            Self::ManualSrgb(surface_format.format)
+        } else {
+            Self::Linear(surface_format.format)
+        }
+    }
+
+    pub fn format(self) -> vk::Format {
+        match self {
+            Self::Srgb(format)
+            | Self::ManualSrgb(format)
+            | Self::Linear(format)
+            | Self::Pq(format) => format,
+        }
+    }
+
+    //フラグメントシェーダーが書き込む値の空間
+    pub fn shader_output(self) -> ColorEncoding {
+        match self {
+            Self::ManualSrgb(_) => ColorEncoding::Srgb,
+            Self::Srgb(_) | Self::Linear(_) => ColorEncoding::Linear,
+            Self::Pq(_) => ColorEncoding::Pq,
+        }
+    }
+
+    //シーンのシェーダーにはPQのエントリーポイントが無いので、ポストプロセスのパスを通す必要がある
+    pub fn requires_post_process(self) -> bool {
+        matches!(self, Self::Pq(_))
+    }
+
+    //ポストプロセスの中間画像のColorFormat、swapchainのフォーマットに関わらず同じ
+    //中間画像にはリニアのまま書き込み、最後にswapchainに書き出すパスでエンコードする
+    //ブルームやトーンマッピングが1.0より明るい値を読めるように浮動小数点にする
+    pub fn intermediate(self) -> Self {
+        Self::Linear(INTERMEDIATE_FORMAT)
+    }
+}
+
+fn is_srgb_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::B8G8R8A8_SRGB
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::A8B8G8R8_SRGB_PACK32
+            | vk::Format::R8G8B8_SRGB
+            | vk::Format::B8G8R8_SRGB
+    )
+}
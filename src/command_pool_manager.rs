@@ -0,0 +1,210 @@
+use ash::{vk, Device};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+
+//CommandPoolManagerが作った、1つのスレッドの1つのキューファミリー用のCommand Pool
+//Command Poolとそこから確保したコマンドバッファは外部同期が必要なので、作ったスレッド以外では記録しない
+#[derive(Clone, Copy, Debug)]
+pub struct ThreadCommandPool {
+    handle: vk::CommandPool,
+    owner: ThreadId,
+}
+
+impl ThreadCommandPool {
+    pub fn allocate(
+        &self,
+        device: &Device,
+        level: vk::CommandBufferLevel,
+        count: u32,
+    ) -> Vec<vk::CommandBuffer> {
+        self.debug_assert_owner();
+
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(self.handle)
+            .level(level)
+            .command_buffer_count(count)
+            .build();
+
+        unsafe { device.allocate_command_buffers(&alloc_info).unwrap() }
+    }
+
+    //command_bufferはこのプールから確保したもの
+    pub fn begin(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        begin_info: &vk::CommandBufferBeginInfo,
+    ) {
+        self.debug_assert_owner();
+
+        unsafe {
+            device
+                .begin_command_buffer(command_buffer, begin_info)
+                .unwrap()
+        };
+    }
+
+    fn debug_assert_owner(&self) {
+        debug_assert_eq!(
+            thread::current().id(),
+            self.owner,
+            "A command buffer must be recorded on the thread that owns its command pool"
+        );
+    }
+}
+
+//(スレッド, キューファミリー)ごとのCommand Poolを、初めて要求された時に作って渡す
+//プールはスレッドではなくここが持つので、スレッドが待機したままでもdestroyで全て破棄できる
+pub struct CommandPoolManager {
+    flags: vk::CommandPoolCreateFlags,
+    pools: Mutex<HashMap<(ThreadId, u32), ThreadCommandPool>>,
+}
+
+impl CommandPoolManager {
+    //flagsは全てのプールに付ける、reset_allでまとめてリセットするならTRANSIENTにする
+    pub fn new(flags: vk::CommandPoolCreateFlags) -> Self {
+        Self {
+            flags,
+            pools: Mutex::new(HashMap::new()),
+        }
+    }
+
+    //呼び出したスレッドのqueue_family_index用のプール
+    pub fn pool(&self, device: &Device, queue_family_index: u32) -> ThreadCommandPool {
+        self.pool_with(queue_family_index, || {
+            let pool_info = vk::CommandPoolCreateInfo::builder()
+                .flags(self.flags)
+                .queue_family_index(queue_family_index)
+                .build();
+
+            unsafe { device.create_command_pool(&pool_info, None).unwrap() }
+        })
+    }
+
+    //呼び出したスレッドのqueue_family_index用のプールが無ければcreateで作る
+    //createはロックを取ったまま呼ぶので、同じ組のプールが2つ作られることはない
+    fn pool_with(
+        &self,
+        queue_family_index: u32,
+        create: impl FnOnce() -> vk::CommandPool,
+    ) -> ThreadCommandPool {
+        let owner = thread::current().id();
+        let mut pools = self.pools.lock().unwrap();
+
+        *pools.entry((owner, queue_family_index)).or_insert_with(|| {
+            let handle = create();
+
+            log::debug!(
+                "Created command pool for {:?} on queue family {}",
+                owner,
+                queue_family_index
+            );
+
+            ThreadCommandPool { handle, owner }
+        })
+    }
+
+    //全てのプールのコマンドバッファを初期状態に戻す、確保したコマンドバッファはそのまま使い続けられる
+    //どのプールのコマンドバッファも実行と記録が終わっていることは呼び出し側で保証する
+    pub fn reset_all(&self, device: &Device) -> Result<(), vk::Result> {
+        let pools = self.pools.lock().unwrap();
+
+        for pool in pools.values() {
+            unsafe { device.reset_command_pool(pool.handle, vk::CommandPoolResetFlags::empty())? };
+        }
+
+        Ok(())
+    }
+
+    //プールを破棄すると確保したコマンドバッファも解放される
+    pub fn destroy(&self, device: &Device) {
+        for (_, pool) in self.pools.lock().unwrap().drain() {
+            unsafe { device.destroy_command_pool(pool.handle, None) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    const THREADS: usize = 8;
+    const QUEUE_FAMILIES: u32 = 3;
+
+    //実際のプールの代わりに、作る度に違う値のハンドルを返す
+    fn fake_pool(next: &AtomicU64) -> vk::CommandPool {
+        vk::CommandPool::from_raw(next.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    #[test]
+    fn each_thread_gets_its_own_pool_per_queue_family() {
+        let manager = Arc::new(CommandPoolManager::new(
+            vk::CommandPoolCreateFlags::TRANSIENT,
+        ));
+        let next = Arc::new(AtomicU64::new(0));
+
+        let threads = (0..THREADS)
+            .map(|_| {
+                let manager = Arc::clone(&manager);
+                let next = Arc::clone(&next);
+
+                thread::spawn(move || {
+                    let mut pools = vec![];
+
+                    //2回目以降は最初に作ったプールが返る
+                    for _ in 0..100 {
+                        let round = (0..QUEUE_FAMILIES)
+                            .map(|family| manager.pool_with(family, || fake_pool(&next)))
+                            .collect::<Vec<_>>();
+
+                        if pools.is_empty() {
+                            pools = round;
+                        } else {
+                            for (first, again) in pools.iter().zip(&round) {
+                                assert_eq!(first.handle, again.handle);
+                            }
+                        }
+                    }
+
+                    for pool in &pools {
+                        assert_eq!(pool.owner, thread::current().id());
+                    }
+
+                    pools
+                        .iter()
+                        .map(|pool| pool.handle.as_raw())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let handles = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect::<Vec<_>>();
+
+        let expected = THREADS * QUEUE_FAMILIES as usize;
+        assert_eq!(handles.len(), expected);
+        assert_eq!(handles.iter().collect::<HashSet<_>>().len(), expected);
+        assert_eq!(next.load(Ordering::Relaxed), expected as u64);
+        assert_eq!(manager.pools.lock().unwrap().len(), expected);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn recording_on_another_thread_panics_in_debug() {
+        let manager = CommandPoolManager::new(vk::CommandPoolCreateFlags::empty());
+        let next = AtomicU64::new(0);
+        let pool = manager.pool_with(0, || fake_pool(&next));
+
+        pool.debug_assert_owner();
+
+        let result = thread::spawn(move || pool.debug_assert_owner()).join();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use crate::buffer;
+use crate::memory_budget;
+use crate::one_time_commands::OneTimeCommands;
+use crate::synchronization::Synchronization;
+use ash::{vk, Device, Instance};
+use std::ffi::CString;
+use std::mem;
+
+//main_csが書き込む要素数
+//ワークグループの大きさで割り切れない場合もシェーダー側で範囲外を書き込まないようにしている
+const ELEMENT_COUNT: u32 = 10_000;
+
+//シェーダー側のmain_csのthreadsと合わせる
+const WORKGROUP_SIZE: u32 = 64;
+
+//シェーダー側のComputeConstantsと同じレイアウト
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct ComputeConstants {
+    count: u32,
+}
+
+//main_csがindex番目に書き込む値
+fn expected_value(index: u32) -> u32 {
+    index * 2 + 1
+}
+
+//main_csでstorage bufferを埋めてCPUから読み戻し、期待した値になっているか確認する
+//one_time_commandsはCOMPUTEに対応したキューファミリーのものを渡す
+pub fn run_self_test(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    device: &Device,
+    one_time_commands: &OneTimeCommands,
+    synchronization: &Synchronization,
+    shader_module: vk::ShaderModule,
+) -> Result<(), String> {
+    let size = (mem::size_of::<u32>() as u32 * ELEMENT_COUNT) as vk::DeviceSize;
+
+    //シェーダーが書き込まなかった要素を検出できるように0で埋めておく
+    let (buffer, memory) = buffer::create_host_buffer_with_data(
+        instance,
+        physical_device,
+        device,
+        &vec![0u32; ELEMENT_COUNT as usize],
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+    );
+
+    let binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .build();
+
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+        .bindings(&[binding])
+        .build();
+
+    let descriptor_set_layout = unsafe {
+        device
+            .create_descriptor_set_layout(&layout_info, None)
+            .unwrap()
+    };
+
+    let pool_size = vk::DescriptorPoolSize::builder()
+        .ty(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .build();
+
+    let pool_info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(&[pool_size])
+        .max_sets(1)
+        .build();
+
+    let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+
+    let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(descriptor_pool)
+        .set_layouts(&[descriptor_set_layout])
+        .build();
+
+    let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] };
+
+    let buffer_info = vk::DescriptorBufferInfo::builder()
+        .buffer(buffer)
+        .offset(0)
+        .range(size)
+        .build();
+
+    let descriptor_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(&[buffer_info])
+        .build();
+
+    unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+
+    //書き込む要素数はPush Constantで渡す
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(mem::size_of::<ComputeConstants>() as u32)
+        .build();
+
+    let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(&[descriptor_set_layout])
+        .push_constant_ranges(&[push_constant_range])
+        .build();
+
+    let pipeline_layout = unsafe {
+        device
+            .create_pipeline_layout(&pipeline_layout_info, None)
+            .unwrap()
+    };
+
+    //起動時に一度だけ作るパイプラインなのでキャッシュには入れない
+    let pipeline = create_compute_pipeline(
+        device,
+        vk::PipelineCache::null(),
+        shader_module,
+        "main_cs",
+        pipeline_layout,
+    );
+
+    let constants = ComputeConstants {
+        count: ELEMENT_COUNT,
+    };
+
+    let result = one_time_commands.run(device, synchronization, |command_buffer| unsafe {
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline_layout,
+            0,
+            &[descriptor_set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            std::slice::from_raw_parts(
+                &constants as *const ComputeConstants as *const u8,
+                mem::size_of::<ComputeConstants>(),
+            ),
+        );
+
+        //切り上げて全ての要素をカバーする
+        device.cmd_dispatch(
+            command_buffer,
+            (ELEMENT_COUNT + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+            1,
+            1,
+        );
+
+        //シェーダーの書き込みをFenceの待機後にCPUから読めるようにする
+        let buffer_barrier = vk::BufferMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+            .dst_access_mask(vk::AccessFlags2::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+
+        synchronization.cmd_pipeline_barrier(device, command_buffer, &[], &[buffer_barrier], &[]);
+    });
+
+    let verification = result
+        .map_err(|error| format!("Failed to run compute shader: {}", error))
+        .and_then(|_| verify(device, memory, size));
+
+    unsafe {
+        device.destroy_pipeline(pipeline, None);
+        device.destroy_pipeline_layout(pipeline_layout, None);
+        device.destroy_descriptor_pool(descriptor_pool, None);
+        device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+        device.destroy_buffer(buffer, None);
+        memory_budget::free_memory(device, memory);
+    }
+
+    verification
+}
+
+//shader_moduleのentry_pointを使うコンピュートパイプラインを作成する
+pub fn create_compute_pipeline(
+    device: &Device,
+    pipeline_cache: vk::PipelineCache,
+    shader_module: vk::ShaderModule,
+    entry_point: &str,
+    pipeline_layout: vk::PipelineLayout,
+) -> vk::Pipeline {
+    //Lifetimeを確保するために一度変数にしている
+    let name = CString::new(entry_point).unwrap();
+
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader_module)
+        .name(name.as_c_str())
+        .build();
+
+    //コンピュートパイプラインはシェーダーステージが1つだけで固定機能ステージの設定は無い
+    let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(pipeline_layout)
+        .build();
+
+    unsafe {
+        device
+            .create_compute_pipelines(pipeline_cache, &[pipeline_info], None)
+            .map_err(|(_, error)| error)
+            .unwrap()[0]
+    }
+}
+
+fn verify(device: &Device, memory: vk::DeviceMemory, size: vk::DeviceSize) -> Result<(), String> {
+    let values = unsafe {
+        let pointer = device
+            .map_memory(memory, 0, size, vk::MemoryMapFlags::empty())
+            .unwrap() as *const u32;
+        let values = std::slice::from_raw_parts(pointer, ELEMENT_COUNT as usize).to_vec();
+        device.unmap_memory(memory);
+        values
+    };
+
+    let mismatches = values
+        .iter()
+        .enumerate()
+        .filter(|(index, value)| **value != expected_value(*index as u32))
+        .collect::<Vec<_>>();
+
+    match mismatches.first() {
+        None => Ok(()),
+        Some((index, value)) => Err(format!(
+            "{} of {} values are wrong, first at index {}: expected {}, got {}",
+            mismatches.len(),
+            ELEMENT_COUNT,
+            index,
+            expected_value(*index as u32),
+            value
+        )),
+    }
+}
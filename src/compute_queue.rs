@@ -0,0 +1,151 @@
+use crate::gpu_timer::GpuTimer;
+use crate::submit_queue::SubmitQueue;
+use crate::synchronization::{self, Synchronization};
+use ash::{vk, Device, Instance};
+
+//専用のコンピュートキューファミリーにフレームごとのコマンドを投げる
+//グラフィックスのsubmitはfinished_semaphoresを待つので、コマンドバッファの再利用はグラフィックスのframe_timelineで保証される
+pub struct ComputeQueue {
+    queue: SubmitQueue,
+    command_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+    //コンピュートの完了をグラフィックスのsubmitに知らせるSemaphore
+    finished_semaphores: Vec<vk::Semaphore>,
+    //タイムスタンプに対応していないキューファミリーではNone
+    timer: Option<GpuTimer>,
+}
+
+impl ComputeQueue {
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        queue: SubmitQueue,
+        queue_family_index: u32,
+        frames_in_flight: u32,
+    ) -> Self {
+        //Command Poolは単一のキューファミリーに対して作られるのでグラフィックスとは別に用意する
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(queue_family_index)
+            .build();
+
+        let command_pool = unsafe { device.create_command_pool(&pool_info, None).unwrap() };
+
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(frames_in_flight)
+            .build();
+
+        let command_buffers = unsafe { device.allocate_command_buffers(&alloc_info).unwrap() };
+
+        let semaphore_info = vk::SemaphoreCreateInfo::builder().build();
+
+        let finished_semaphores = (0..frames_in_flight)
+            .map(|_| unsafe { device.create_semaphore(&semaphore_info, None).unwrap() })
+            .collect();
+
+        let timer = GpuTimer::new(
+            instance,
+            physical_device,
+            device,
+            queue_family_index,
+            frames_in_flight,
+        );
+
+        log::info!("Async compute on queue family {}", queue_family_index);
+
+        Self {
+            queue,
+            command_pool,
+            command_buffers,
+            finished_semaphores,
+            timer,
+        }
+    }
+
+    pub fn queue_handle(&self) -> vk::Queue {
+        self.queue.handle()
+    }
+
+    //このフレームのコマンドバッファの記録を開始する
+    pub fn begin(&self, device: &Device, frame: usize) -> vk::CommandBuffer {
+        let command_buffer = self.command_buffers[frame];
+
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+            .build();
+
+        unsafe {
+            device
+                .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+                .unwrap();
+            device
+                .begin_command_buffer(command_buffer, &begin_info)
+                .unwrap();
+        }
+
+        if let Some(timer) = &self.timer {
+            timer.cmd_begin(device, command_buffer, frame);
+        }
+
+        command_buffer
+    }
+
+    //記録を終えてsubmitし、グラフィックスのsubmitで待つSemaphoreを返す
+    pub fn submit(
+        &mut self,
+        device: &Device,
+        synchronization: &Synchronization,
+        frame: usize,
+    ) -> Result<vk::Semaphore, vk::Result> {
+        let command_buffer = self.command_buffers[frame];
+        let finished_semaphore = self.finished_semaphores[frame];
+
+        if let Some(timer) = &mut self.timer {
+            timer.cmd_end(device, command_buffer, frame);
+        }
+
+        //タイムスタンプの書き込みも含めて終わってからシグナルする
+        //コマンドバッファとクエリの再利用はグラフィックスがこのSemaphoreを待つことで保証しているため
+        let signal_semaphores = [synchronization::semaphore_submit_info(
+            finished_semaphore,
+            0,
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+        )];
+
+        unsafe { device.end_command_buffer(command_buffer)? };
+
+        self.queue.submit(
+            device,
+            synchronization,
+            &[],
+            &[command_buffer],
+            &signal_semaphores,
+            vk::Fence::null(),
+        )?;
+
+        Ok(finished_semaphore)
+    }
+
+    //GpuTimer::read_range_nsのコンピュートキュー版
+    pub fn read_range_ns(&mut self, device: &Device, frame: usize) -> Option<(f64, f64)> {
+        self.timer.as_mut()?.read_range_ns(device, frame)
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            for semaphore in &self.finished_semaphores {
+                device.destroy_semaphore(*semaphore, None);
+            }
+
+            //Command Bufferは所属するCommand Poolと一緒に破棄される
+            device.destroy_command_pool(self.command_pool, None);
+        }
+
+        if let Some(timer) = &self.timer {
+            timer.destroy(device);
+        }
+    }
+}
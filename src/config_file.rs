@@ -0,0 +1,451 @@
+use crate::clear_color::ClearColor;
+use crate::cli::AppConfig;
+use crate::debug::ValidationSeverity;
+use crate::depth_buffer::DepthConvention;
+use crate::swap_chain_utils::{PresentModePreference, SurfaceFormatPreference};
+use crate::tonemap::Tonemapper;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::str::FromStr;
+
+//テーブルごとに知らないキーを集め、読み込んだ後に警告する
+type Unknown = BTreeMap<String, toml::Value>;
+
+//vulkan_tutorial.tomlの中身
+//書かれていないキーはNoneのままで、applyはSomeの値だけでAppConfigを上書きする
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct Settings {
+    window: WindowSettings,
+    renderer: RendererSettings,
+    gamepad: GamepadSettings,
+    debug: DebugSettings,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct WindowSettings {
+    width: Option<u32>,
+    height: Option<u32>,
+    title: Option<String>,
+    resizable: Option<bool>,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct RendererSettings {
+    #[serde(deserialize_with = "from_str")]
+    present_mode: Option<PresentModePreference>,
+    #[serde(deserialize_with = "from_str")]
+    surface_format: Option<SurfaceFormatPreference>,
+    image_count: Option<u32>,
+    msaa: Option<u32>,
+    frames_in_flight: Option<u32>,
+    #[serde(deserialize_with = "clear_color")]
+    clear_color: Option<ClearColor>,
+    //0は制限しないのと同じにする
+    target_fps: Option<u32>,
+    bloom_intensity: Option<f32>,
+    #[serde(deserialize_with = "from_str")]
+    tonemap: Option<Tonemapper>,
+    exposure: Option<f32>,
+    #[serde(deserialize_with = "from_str")]
+    depth: Option<DepthConvention>,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct GamepadSettings {
+    enabled: Option<bool>,
+    #[serde(deserialize_with = "dead_zone")]
+    dead_zone: Option<f32>,
+    sensitivity: SensitivitySettings,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+//[gamepad.sensitivity]、AxisActionごとの倍率
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct SensitivitySettings {
+    move_forward: Option<f32>,
+    move_right: Option<f32>,
+    move_up: Option<f32>,
+    look_right: Option<f32>,
+    look_up: Option<f32>,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct DebugSettings {
+    validation: Option<bool>,
+    #[serde(deserialize_with = "from_str")]
+    severity: Option<ValidationSeverity>,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+//AppConfigの値を上書きするだけで、コマンドラインとの優先順位はAppConfig::parseが決める
+pub struct ConfigFile {
+    path: String,
+    settings: Settings,
+}
+
+impl ConfigFile {
+    //requiredがfalseの場合はファイルが無くてもエラーにせずNoneを返す
+    pub fn load(path: &str, required: bool) -> Result<Option<Self>, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == io::ErrorKind::NotFound && !required => return Ok(None),
+            Err(error) => return Err(format!("Failed to read {}: {}", path, error)),
+        };
+
+        Self::parse(path, &text).map(Some)
+    }
+
+    //pathはエラーメッセージにだけ使う
+    //値の型や中身が違う場合はtomlのエラーに行と列が付く
+    pub fn parse(path: &str, text: &str) -> Result<Self, String> {
+        let settings = toml::from_str(text).map_err(|error| format!("{}: {}", path, error))?;
+
+        Ok(Self {
+            path: path.to_string(),
+            settings,
+        })
+    }
+
+    //知らないキーは警告だけして無視する
+    pub fn apply(&self, config: &mut AppConfig) {
+        for name in self.unknown_keys() {
+            log::warn!("{}: Unknown setting '{}' is ignored", self.path, name);
+        }
+
+        let Settings {
+            window,
+            renderer,
+            gamepad,
+            debug,
+            ..
+        } = &self.settings;
+
+        set(&mut config.window_width, window.width);
+        set(&mut config.window_height, window.height);
+        set(&mut config.window_title, window.title.clone());
+        set(&mut config.window_resizable, window.resizable);
+
+        set(&mut config.present_mode, renderer.present_mode);
+        set(&mut config.surface_format, renderer.surface_format);
+        set(&mut config.image_count, renderer.image_count.map(Some));
+        set(&mut config.msaa_samples, renderer.msaa);
+        set(&mut config.frames_in_flight, renderer.frames_in_flight);
+        set(&mut config.clear_color, renderer.clear_color);
+        set(
+            &mut config.target_fps,
+            renderer
+                .target_fps
+                .map(|fps| Some(fps).filter(|&fps| fps > 0)),
+        );
+        set(&mut config.bloom_intensity, renderer.bloom_intensity);
+        set(&mut config.tonemapper, renderer.tonemap);
+        set(&mut config.exposure, renderer.exposure);
+        set(&mut config.depth, renderer.depth);
+
+        let sensitivity = &mut config.gamepad.sensitivity;
+        set(
+            &mut sensitivity.move_forward,
+            gamepad.sensitivity.move_forward,
+        );
+        set(&mut sensitivity.move_right, gamepad.sensitivity.move_right);
+        set(&mut sensitivity.move_up, gamepad.sensitivity.move_up);
+        set(&mut sensitivity.look_right, gamepad.sensitivity.look_right);
+        set(&mut sensitivity.look_up, gamepad.sensitivity.look_up);
+        set(&mut config.gamepad.enabled, gamepad.enabled);
+        set(&mut config.gamepad.dead_zone, gamepad.dead_zone);
+
+        set(&mut config.validation, debug.validation);
+        set(&mut config.validation_severity, debug.severity);
+    }
+
+    //section.keyの形の名前、知らないテーブルはテーブルの名前だけ
+    fn unknown_keys(&self) -> Vec<String> {
+        let section = |name: &str, unknown: &Unknown| {
+            unknown
+                .keys()
+                .map(|key| format!("{}.{}", name, key))
+                .collect::<Vec<_>>()
+        };
+
+        let mut keys = self.settings.unknown.keys().cloned().collect::<Vec<_>>();
+        keys.extend(section("window", &self.settings.window.unknown));
+        keys.extend(section("renderer", &self.settings.renderer.unknown));
+        keys.extend(section("gamepad", &self.settings.gamepad.unknown));
+        keys.extend(section(
+            "gamepad.sensitivity",
+            &self.settings.gamepad.sensitivity.unknown,
+        ));
+        keys.extend(section("debug", &self.settings.debug.unknown));
+        keys
+    }
+}
+
+fn set<T>(target: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *target = value;
+    }
+}
+
+//文字列の値をコマンドラインの値と同じくFromStrで読む
+fn from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|text| text.parse().map_err(de::Error::custom))
+        .transpose()
+}
+
+//0.0以上1.0未満、1.0以上では全ての値が0になってしまう
+fn dead_zone<'de, D>(deserializer: D) -> Result<Option<f32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<f32>::deserialize(deserializer)? {
+        Some(dead_zone) if !(0.0..1.0).contains(&dead_zone) => Err(de::Error::custom(format!(
+            "dead_zone must be at least 0 and less than 1, got {}",
+            dead_zone
+        ))),
+        dead_zone => Ok(dead_zone),
+    }
+}
+
+//コマンドラインと同じ"r,g,b"の文字列か、[r, g, b]の配列
+fn clear_color<'de, D>(deserializer: D) -> Result<Option<ClearColor>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Text(String),
+        Components(Vec<f64>),
+    }
+
+    let text = match Option::<Value>::deserialize(deserializer)? {
+        None => return Ok(None),
+        Some(Value::Text(text)) => text,
+        Some(Value::Components(components)) => components
+            .iter()
+            .map(f64::to_string)
+            .collect::<Vec<_>>()
+            .join(","),
+    };
+
+    text.parse().map(Some).map_err(de::Error::custom)
+}
+
+//--write-default-configで書き出す、configの値を全て書いたファイル
+pub fn write(path: &str, config: &AppConfig) -> Result<(), String> {
+    fs::write(path, to_toml(config)).map_err(|error| format!("Failed to write {}: {}", path, error))
+}
+
+//コメントを残すためにシリアライズせずに書く、文字列のエスケープだけtomlに任せる
+fn to_toml(config: &AppConfig) -> String {
+    let [r, g, b, a] = config.clear_color.rgba;
+
+    format!(
+        "\
+# vulkan_tutorial settings
+# command line options and environment variables take precedence over this file
+
+[window]
+width = {}
+height = {}
+title = {}
+resizable = {}
+
+[renderer]
+# vsync, low-latency or uncapped
+present_mode = \"{}\"
+# srgb, unorm, hdr10, scrgb or hdr
+surface_format = \"{}\"
+# leave commented out to use the driver's minimum image count plus one
+{}image_count = {}
+# samples per pixel, a power of two up to 64, 1 disables multisampling
+msaa = {}
+# frames the CPU may record while the GPU is still rendering earlier ones
+frames_in_flight = {}
+clear_color = [{:?}, {:?}, {:?}, {:?}]
+# 0 for unlimited
+target_fps = {}
+# with --post-effect bloom, how much of the blurred highlights to add
+bloom_intensity = {:?}
+# with --post-effect tonemap or bloom: aces, reinhard or exposure
+tonemap = \"{}\"
+# exposure in stops before tonemapping
+exposure = {:?}
+# standard, reverse or reverse-infinite
+depth = \"{}\"
+
+[gamepad]
+enabled = {}
+# stick and trigger values up to this are ignored, from 0 up to but not including 1
+dead_zone = {:?}
+
+# multiplier for each axis, negative values invert it
+[gamepad.sensitivity]
+move_forward = {:?}
+move_right = {:?}
+move_up = {:?}
+look_right = {:?}
+look_up = {:?}
+
+[debug]
+validation = {}
+# verbose, info, warning or error
+severity = \"{}\"
+",
+        config.window_width,
+        config.window_height,
+        toml::Value::String(config.window_title.clone()),
+        config.window_resizable,
+        config.present_mode.name(),
+        config.surface_format.name(),
+        if config.image_count.is_some() {
+            ""
+        } else {
+            "# "
+        },
+        config.image_count.unwrap_or(3),
+        config.msaa_samples,
+        config.frames_in_flight,
+        r,
+        g,
+        b,
+        a,
+        config.target_fps.unwrap_or(0),
+        config.bloom_intensity,
+        config.tonemapper.name(),
+        config.exposure,
+        config.depth.name(),
+        config.gamepad.enabled,
+        config.gamepad.dead_zone,
+        config.gamepad.sensitivity.move_forward,
+        config.gamepad.sensitivity.move_right,
+        config.gamepad.sensitivity.move_up,
+        config.gamepad.sensitivity.look_right,
+        config.gamepad.sensitivity.look_up,
+        config.validation,
+        config.validation_severity.name(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn applied(text: &str) -> AppConfig {
+        let mut config = AppConfig::default();
+        ConfigFile::parse("test.toml", text)
+            .unwrap()
+            .apply(&mut config);
+        config
+    }
+
+    #[test]
+    fn the_default_file_reads_back_as_the_defaults() {
+        let defaults = AppConfig {
+            window_title: "\"quoted\" \\ title".to_string(),
+            ..AppConfig::default()
+        };
+        let file = ConfigFile::parse("test.toml", &to_toml(&defaults)).unwrap();
+        let mut config = AppConfig::default();
+        file.apply(&mut config);
+
+        assert!(file.unknown_keys().is_empty());
+        assert_eq!(config.window_title, defaults.window_title);
+        assert_eq!(config.present_mode, defaults.present_mode);
+        assert_eq!(config.image_count, defaults.image_count);
+        assert_eq!(config.msaa_samples, defaults.msaa_samples);
+        assert_eq!(config.frames_in_flight, defaults.frames_in_flight);
+        assert_eq!(config.clear_color, defaults.clear_color);
+        assert_eq!(config.depth, defaults.depth);
+        assert_eq!(config.gamepad, defaults.gamepad);
+    }
+
+    #[test]
+    fn renderer_values_are_read() {
+        let config = applied(
+            "[renderer]\nmsaa = 4\nframes_in_flight = 3\ntonemap = \"reinhard\"\nexposure = 1\n",
+        );
+
+        assert_eq!(config.msaa_samples, 4);
+        assert_eq!(config.frames_in_flight, 3);
+        assert_eq!(config.tonemapper, Tonemapper::Reinhard);
+        //整数もそのまま小数として読む
+        assert_eq!(config.exposure, 1.0);
+    }
+
+    #[test]
+    fn clear_color_is_a_string_or_an_array() {
+        let from_array = applied("[renderer]\nclear_color = [0.0, 0.5, 1.0]\n").clear_color;
+        let from_string = applied("[renderer]\nclear_color = \"0,0.5,1\"\n").clear_color;
+
+        assert_eq!(from_array.rgba, [0.0, 0.5, 1.0, 1.0]);
+        assert_eq!(from_array, from_string);
+    }
+
+    #[test]
+    fn unknown_keys_are_collected_per_table() {
+        let file = ConfigFile::parse(
+            "test.toml",
+            "volume = 3\n[window]\nwidht = 640\n[renderer]\nmsaa = 2\n[audio]\nmuted = true\n",
+        )
+        .unwrap();
+
+        assert_eq!(file.unknown_keys(), ["audio", "volume", "window.widht"]);
+    }
+
+    #[test]
+    fn gamepad_sensitivity_is_read_per_axis() {
+        let config = applied(
+            "[gamepad]\ndead_zone = 0.25\n[gamepad.sensitivity]\nlook_up = -1.5\nmove_up = 0.5\n",
+        );
+
+        assert_eq!(config.gamepad.dead_zone, 0.25);
+        assert_eq!(config.gamepad.sensitivity.look_up, -1.5);
+        assert_eq!(config.gamepad.sensitivity.move_up, 0.5);
+        assert_eq!(config.gamepad.sensitivity.look_right, 1.0);
+        assert!(config.gamepad.enabled);
+    }
+
+    #[test]
+    fn invalid_values_are_errors() {
+        for text in [
+            "[window]\nwidth = -1\n",
+            "[window]\nresizable = \"yes\"\n",
+            "[renderer]\npresent_mode = \"fast\"\n",
+            "[renderer]\nclear_color = [2, 0, 0]\n",
+            "[gamepad]\ndead_zone = 1.0\n",
+            "[gamepad.sensitivity]\nlook_up = \"fast\"\n",
+            "[window\nwidth = 1\n",
+        ] {
+            let error = ConfigFile::parse("test.toml", text).err().unwrap();
+
+            assert!(error.starts_with("test.toml: "), "{}", error);
+        }
+    }
+}
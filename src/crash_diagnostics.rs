@@ -0,0 +1,338 @@
+use crate::queue_family::QueueFamilyIndices;
+use ash::{vk, Device, Instance};
+use std::ffi::{c_void, CStr};
+use std::mem;
+use std::os::raw::c_char;
+use std::ptr;
+
+//ash 0.37.1が元にしているVulkan 1.3.235のヘッダーにはVK_EXT_device_faultが無いので、使う型と関数だけをここで宣言する
+const DEVICE_FAULT_NAME: &[u8] = b"VK_EXT_device_fault\0";
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_FAULT_FEATURES: vk::StructureType =
+    vk::StructureType::from_raw(1_000_341_000);
+const STRUCTURE_TYPE_DEVICE_FAULT_COUNTS: vk::StructureType =
+    vk::StructureType::from_raw(1_000_341_001);
+const STRUCTURE_TYPE_DEVICE_FAULT_INFO: vk::StructureType =
+    vk::StructureType::from_raw(1_000_341_002);
+
+pub fn device_fault_name() -> &'static CStr {
+    CStr::from_bytes_with_nul(DEVICE_FAULT_NAME).unwrap()
+}
+
+//VkPhysicalDeviceFaultFeaturesEXT
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct PhysicalDeviceFaultFeatures {
+    pub s_type: vk::StructureType,
+    pub p_next: *mut c_void,
+    pub device_fault: vk::Bool32,
+    pub device_fault_vendor_binary: vk::Bool32,
+}
+
+impl Default for PhysicalDeviceFaultFeatures {
+    fn default() -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_PHYSICAL_DEVICE_FAULT_FEATURES,
+            p_next: ptr::null_mut(),
+            device_fault: vk::FALSE,
+            device_fault_vendor_binary: vk::FALSE,
+        }
+    }
+}
+
+//先頭がs_typeとp_nextの構造体なので、ashの構造体と同じようにPhysicalDeviceFeatures2に繋げる
+unsafe impl vk::ExtendsPhysicalDeviceFeatures2 for PhysicalDeviceFaultFeatures {}
+
+//VkDeviceFaultCountsEXT
+#[repr(C)]
+struct FaultCounts {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    address_info_count: u32,
+    vendor_info_count: u32,
+    vendor_binary_size: vk::DeviceSize,
+}
+
+//VkDeviceFaultAddressInfoEXT
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct FaultAddressInfo {
+    //VkDeviceFaultAddressTypeEXT
+    address_type: i32,
+    reported_address: vk::DeviceAddress,
+    //2の累乗で、実際に失敗したアドレスはreported_addressをこの幅に切り捨てた範囲のどこか
+    address_precision: vk::DeviceSize,
+}
+
+//VkDeviceFaultVendorInfoEXT
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct FaultVendorInfo {
+    description: [c_char; vk::MAX_DESCRIPTION_SIZE],
+    vendor_fault_code: u64,
+    vendor_fault_data: u64,
+}
+
+//VkDeviceFaultInfoEXT
+#[repr(C)]
+struct FaultInfo {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    description: [c_char; vk::MAX_DESCRIPTION_SIZE],
+    p_address_infos: *mut FaultAddressInfo,
+    p_vendor_infos: *mut FaultVendorInfo,
+    p_vendor_binary_data: *mut c_void,
+}
+
+//vkGetDeviceFaultInfoEXT
+type GetDeviceFaultInfo =
+    unsafe extern "system" fn(vk::Device, *mut FaultCounts, *mut FaultInfo) -> vk::Result;
+
+fn address_type_name(address_type: i32) -> &'static str {
+    match address_type {
+        0 => "none",
+        1 => "invalid read",
+        2 => "invalid write",
+        3 => "invalid execute",
+        4 => "unknown instruction pointer",
+        5 => "invalid instruction pointer",
+        6 => "faulting instruction pointer",
+        _ => "unknown",
+    }
+}
+
+//ドライバが書き込むdescriptionはnull終端されている
+fn description(description: &[c_char; vk::MAX_DESCRIPTION_SIZE]) -> String {
+    unsafe { CStr::from_ptr(description.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+//コマンドバッファに記録するパスの境目
+//VK_NV_device_diagnostic_checkpointsのマーカーにはポインタではなく番号を入れ、読み戻した時にこの名前を引く
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Checkpoint {
+    FrameBegin,
+    Compute,
+    RayTracing,
+    LightCulling,
+    ShadowPass,
+    ScenePass,
+    DebugViewPass,
+    PostProcessPass,
+    FrameEnd,
+}
+
+const CHECKPOINTS: [Checkpoint; 9] = [
+    Checkpoint::FrameBegin,
+    Checkpoint::Compute,
+    Checkpoint::RayTracing,
+    Checkpoint::LightCulling,
+    Checkpoint::ShadowPass,
+    Checkpoint::ScenePass,
+    Checkpoint::DebugViewPass,
+    Checkpoint::PostProcessPass,
+    Checkpoint::FrameEnd,
+];
+
+impl Checkpoint {
+    pub fn name(self) -> &'static str {
+        match self {
+            Checkpoint::FrameBegin => "frame begin",
+            Checkpoint::Compute => "compute",
+            Checkpoint::RayTracing => "ray tracing",
+            Checkpoint::LightCulling => "light culling",
+            Checkpoint::ShadowPass => "shadow pass",
+            Checkpoint::ScenePass => "scene pass",
+            Checkpoint::DebugViewPass => "debug view pass",
+            Checkpoint::PostProcessPass => "post process pass",
+            Checkpoint::FrameEnd => "frame end",
+        }
+    }
+
+    //0はマーカーが無いのと区別できないので1から始める
+    fn marker(self) -> *const c_void {
+        (self as usize + 1) as *const c_void
+    }
+
+    fn from_marker(marker: *mut c_void) -> Option<Self> {
+        CHECKPOINTS.get((marker as usize).checked_sub(1)?).copied()
+    }
+}
+
+//ERROR_DEVICE_LOSTになった時に、VK_EXT_device_faultの失敗の情報とVK_NV_device_diagnostic_checkpointsで最後に通ったパスをログに出す
+//どちらの拡張も無いデバイスでは何もしない
+pub struct CrashDiagnostics {
+    //VK_EXT_device_faultとdeviceFaultの機能を有効にした場合のみSome
+    get_device_fault_info: Option<GetDeviceFaultInfo>,
+    //VK_NV_device_diagnostic_checkpointsを有効にした場合のみSome
+    checkpoints: Option<vk::NvDeviceDiagnosticCheckpointsFn>,
+}
+
+impl CrashDiagnostics {
+    //device_faultは論理デバイスでdeviceFaultの機能を有効にしたかどうか
+    //VK_NV_device_diagnostic_checkpointsはget_optional_device_extensionsでサポートされていれば有効にしている
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        device_fault: bool,
+    ) -> Self {
+        let get_device_fault_info = device_fault
+            .then(|| unsafe {
+                mem::transmute::<vk::PFN_vkVoidFunction, Option<GetDeviceFaultInfo>>(
+                    instance.get_device_proc_addr(
+                        device.handle(),
+                        b"vkGetDeviceFaultInfoEXT\0".as_ptr() as *const c_char,
+                    ),
+                )
+            })
+            .flatten();
+
+        let checkpoints = QueueFamilyIndices::is_device_extension_supported(
+            instance,
+            physical_device,
+            vk::NvDeviceDiagnosticCheckpointsFn::name(),
+        )
+        .then(|| {
+            vk::NvDeviceDiagnosticCheckpointsFn::load(|name| unsafe {
+                mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            })
+        });
+
+        let state = |enabled: bool| if enabled { "enabled" } else { "not supported" };
+
+        log::info!(
+            "Crash diagnostics: device fault {}, checkpoints {}",
+            state(get_device_fault_info.is_some()),
+            state(checkpoints.is_some())
+        );
+
+        Self {
+            get_device_fault_info,
+            checkpoints,
+        }
+    }
+
+    //checkpointより前のコマンドが各ステージに到達したことを記録する
+    pub fn cmd_checkpoint(&self, command_buffer: vk::CommandBuffer, checkpoint: Checkpoint) {
+        if let Some(checkpoints) = &self.checkpoints {
+            unsafe { (checkpoints.cmd_set_checkpoint_nv)(command_buffer, checkpoint.marker()) };
+        }
+    }
+
+    //ERROR_DEVICE_LOSTが返ってきた後に呼ぶ、queuesは(ログに出す名前, キュー)
+    pub fn report(&self, device: &Device, queues: &[(&str, vk::Queue)]) {
+        if let Some(get_device_fault_info) = self.get_device_fault_info {
+            unsafe { Self::report_fault(device, get_device_fault_info) };
+        }
+
+        if let Some(checkpoints) = &self.checkpoints {
+            for &(name, queue) in queues {
+                unsafe { Self::report_checkpoints(checkpoints, name, queue) };
+            }
+        }
+    }
+
+    unsafe fn report_fault(device: &Device, get_device_fault_info: GetDeviceFaultInfo) {
+        //1回目で数を読み、2回目でその数の配列に書き込ませる
+        let mut counts = FaultCounts {
+            s_type: STRUCTURE_TYPE_DEVICE_FAULT_COUNTS,
+            p_next: ptr::null_mut(),
+            address_info_count: 0,
+            vendor_info_count: 0,
+            vendor_binary_size: 0,
+        };
+
+        let result = get_device_fault_info(device.handle(), &mut counts, ptr::null_mut());
+        if result != vk::Result::SUCCESS {
+            log::warn!("Failed to get device fault counts: {}", result);
+            return;
+        }
+
+        let mut address_infos =
+            vec![FaultAddressInfo::default(); counts.address_info_count as usize];
+        let mut vendor_infos =
+            vec![mem::zeroed::<FaultVendorInfo>(); counts.vendor_info_count as usize];
+        //ベンダー独自のバイナリはdeviceFaultVendorBinaryを有効にしていないので読まない
+        counts.vendor_binary_size = 0;
+
+        let mut info = FaultInfo {
+            s_type: STRUCTURE_TYPE_DEVICE_FAULT_INFO,
+            p_next: ptr::null_mut(),
+            description: [0; vk::MAX_DESCRIPTION_SIZE],
+            p_address_infos: address_infos.as_mut_ptr(),
+            p_vendor_infos: vendor_infos.as_mut_ptr(),
+            p_vendor_binary_data: ptr::null_mut(),
+        };
+
+        match get_device_fault_info(device.handle(), &mut counts, &mut info) {
+            vk::Result::SUCCESS | vk::Result::INCOMPLETE => {}
+            error => {
+                log::warn!("Failed to get device fault info: {}", error);
+                return;
+            }
+        }
+
+        address_infos.truncate(counts.address_info_count as usize);
+        vendor_infos.truncate(counts.vendor_info_count as usize);
+
+        log::error!(
+            "Device fault: {} ({} addresses, {} vendor infos)",
+            description(&info.description),
+            address_infos.len(),
+            vendor_infos.len()
+        );
+
+        for address_info in &address_infos {
+            let precision = address_info.address_precision.max(1);
+            let lower = address_info.reported_address & !(precision - 1);
+
+            log::error!(
+                "  {} at 0x{:016x} (somewhere in 0x{:016x}..0x{:016x})",
+                address_type_name(address_info.address_type),
+                address_info.reported_address,
+                lower,
+                lower.saturating_add(precision)
+            );
+        }
+
+        for vendor_info in &vendor_infos {
+            log::error!(
+                "  vendor: {} (code 0x{:x}, data 0x{:x})",
+                description(&vendor_info.description),
+                vendor_info.vendor_fault_code,
+                vendor_info.vendor_fault_data
+            );
+        }
+    }
+
+    //ステージごとに最後に到達したチェックポイントが返ってくる
+    //TOP_OF_PIPEで到達していてBOTTOM_OF_PIPEで到達していないパスの中で失われたことになる
+    unsafe fn report_checkpoints(
+        checkpoints: &vk::NvDeviceDiagnosticCheckpointsFn,
+        name: &str,
+        queue: vk::Queue,
+    ) {
+        let mut count = 0;
+        (checkpoints.get_queue_checkpoint_data_nv)(queue, &mut count, ptr::null_mut());
+
+        let mut data = vec![vk::CheckpointDataNV::default(); count as usize];
+        (checkpoints.get_queue_checkpoint_data_nv)(queue, &mut count, data.as_mut_ptr());
+        data.truncate(count as usize);
+
+        if data.is_empty() {
+            log::error!("Checkpoints on {} queue: none reached", name);
+            return;
+        }
+
+        for checkpoint in &data {
+            log::error!(
+                "Checkpoints on {} queue: {:?} reached {}",
+                name,
+                checkpoint.stage,
+                Checkpoint::from_marker(checkpoint.p_checkpoint_marker)
+                    .map_or("unknown", Checkpoint::name)
+            );
+        }
+    }
+}
@@ -1,37 +1,69 @@
 use ash::extensions::ext::DebugUtils;
 use ash::prelude::VkResult;
+use ash::vk;
 use ash::vk::{DebugUtilsMessengerCreateInfoEXT, DebugUtilsMessengerEXT};
-use ash::{vk, Entry};
 use std::ffi::{c_void, CStr};
+use std::str::FromStr;
 
-//指定されたレイヤーの検証レイヤーが有効かどうか
-pub fn check_validation_layer_support(entry: &Entry) {
-    for required in crate::vulkan_app::REQUIRED_LAYERS.iter() {
-        let found = entry
-            .enumerate_instance_layer_properties()
-            .unwrap()
-            .iter()
-            .any(|layer| {
-                let name = unsafe { CStr::from_ptr(layer.layer_name.as_ptr()) };
-                let name = name.to_str().expect("Failed to get layer name pointer");
-                required == &name
-            });
+//検証レイヤーから受け取るメッセージの最低の重要度
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ValidationSeverity {
+    Verbose,
+    Info,
+    Warning,
+    Error,
+}
+
+impl ValidationSeverity {
+    //この重要度とそれより重要なもの全て
+    fn flags(self) -> vk::DebugUtilsMessageSeverityFlagsEXT {
+        let error = vk::DebugUtilsMessageSeverityFlagsEXT::ERROR;
+        let warning = error | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING;
+        let info = warning | vk::DebugUtilsMessageSeverityFlagsEXT::INFO;
+
+        match self {
+            Self::Verbose => info | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+            Self::Info => info,
+            Self::Warning => warning,
+            Self::Error => error,
+        }
+    }
+
+    //設定ファイルに書き出す時の名前、from_strで読める
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Verbose => "verbose",
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
 
-        if !found {
-            panic!("Validation layer not supported: {}", required);
+impl FromStr for ValidationSeverity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "verbose" => Ok(Self::Verbose),
+            "info" => Ok(Self::Info),
+            "warning" => Ok(Self::Warning),
+            "error" => Ok(Self::Error),
+            _ => Err(format!(
+                "Unknown severity '{}', expected verbose, info, warning or error",
+                s
+            )),
         }
     }
 }
 
 //DebugUtilsMessengerCreateInfoEXTを作成するためのもの
-pub fn populate_debug_messenger_create_info() -> DebugUtilsMessengerCreateInfoEXT {
+pub fn populate_debug_messenger_create_info(
+    severity: ValidationSeverity,
+) -> DebugUtilsMessengerCreateInfoEXT {
     DebugUtilsMessengerCreateInfoEXT::builder()
         //受け取ったメッセージの内容の危険度
-        .message_severity(
-            vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
-        )
+        .message_severity(severity.flags())
         //メッセージの種類
         .message_type(
             vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
@@ -45,8 +77,9 @@ pub fn populate_debug_messenger_create_info() -> DebugUtilsMessengerCreateInfoEX
 // DebugUtilsMessengerEXTはデバック情報をvulkan_debug_callbackに渡すためのもの
 pub fn setup_debug_utils_messenger_ext(
     debug_utils: &DebugUtils,
+    severity: ValidationSeverity,
 ) -> VkResult<DebugUtilsMessengerEXT> {
-    let create_info = populate_debug_messenger_create_info();
+    let create_info = populate_debug_messenger_create_info(severity);
 
     //よくVkDebugReportCallbackで代用しているのを見る
     unsafe { debug_utils.create_debug_utils_messenger(&create_info, None) }
@@ -73,3 +106,28 @@ unsafe extern "system" fn vulkan_debug_callback(
     //返り値はValidation Layerを中止するべきかどうかを返す
     vk::FALSE
 }
+
+//RenderDocなどのキャプチャツールでコマンドをまとめて表示するためのラベルを開始する
+//DebugUtilsは検証レイヤーを有効にした場合にだけ作るので、Noneの場合は何もしない
+pub fn cmd_begin_label(
+    debug_utils: Option<&DebugUtils>,
+    command_buffer: vk::CommandBuffer,
+    name: &CStr,
+    color: [f32; 4],
+) {
+    if let Some(debug_utils) = debug_utils {
+        let label = vk::DebugUtilsLabelEXT::builder()
+            .label_name(name)
+            .color(color)
+            .build();
+
+        unsafe { debug_utils.cmd_begin_debug_utils_label(command_buffer, &label) };
+    }
+}
+
+//cmd_begin_labelと対にして呼ぶ
+pub fn cmd_end_label(debug_utils: Option<&DebugUtils>, command_buffer: vk::CommandBuffer) {
+    if let Some(debug_utils) = debug_utils {
+        unsafe { debug_utils.cmd_end_debug_utils_label(command_buffer) };
+    }
+}
@@ -0,0 +1,632 @@
+use crate::buffer;
+use crate::memory_budget;
+use crate::one_time_commands::OneTimeCommands;
+use crate::staging_ring::StagingRing;
+use crate::synchronization::Synchronization;
+use crate::texture::Texture2D;
+use ash::{vk, Device, Instance};
+use std::mem;
+
+//フォントの1文字の縦横のピクセル数
+const GLYPH_SIZE: u32 = 8;
+//拡大率が1.0のモニターでフォントの1ピクセルを画面の何ピクセルで描画するか
+const GLYPH_SCALE: f32 = 2.0;
+//printでの改行の間隔のフォントのピクセル数
+const LINE_SPACING: u32 = GLYPH_SIZE + 2;
+
+//カバレッジだけなので1チャンネルで持つ
+const ATLAS_FORMAT: vk::Format = vk::Format::R8_UNORM;
+
+//アトラスに並べる最初の文字と、アトラスの列と行の数
+//0x20から0x7Eまでの95文字が16列6行に入る
+const FIRST_CHAR: u8 = b' ';
+const ATLAS_COLUMNS: u32 = 16;
+const ATLAS_ROWS: u32 = 6;
+const ATLAS_WIDTH: u32 = GLYPH_SIZE * ATLAS_COLUMNS;
+const ATLAS_HEIGHT: u32 = GLYPH_SIZE * ATLAS_ROWS;
+
+//1フレームに描画できる文字の数、超えた分は描画しない
+//1文字が4頂点なのでインデックスはu16に収まる
+const MAX_GLYPHS: usize = 4096;
+
+//font8x8のbasic(パブリックドメイン)の0x20から0x7Eまで
+//1文字8バイトで上の行から並び、最下位ビットが左端のピクセル
+const FONT: [[u8; 8]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], //' '
+    [0x18, 0x3C, 0x3C, 0x18, 0x18, 0x00, 0x18, 0x00], //'!'
+    [0x36, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], //'"'
+    [0x36, 0x36, 0x7F, 0x36, 0x7F, 0x36, 0x36, 0x00], //'#'
+    [0x0C, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x0C, 0x00], //'$'
+    [0x00, 0x63, 0x33, 0x18, 0x0C, 0x66, 0x63, 0x00], //'%'
+    [0x1C, 0x36, 0x1C, 0x6E, 0x3B, 0x33, 0x6E, 0x00], //'&'
+    [0x06, 0x06, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00], //"'"
+    [0x18, 0x0C, 0x06, 0x06, 0x06, 0x0C, 0x18, 0x00], //'('
+    [0x06, 0x0C, 0x18, 0x18, 0x18, 0x0C, 0x06, 0x00], //')'
+    [0x00, 0x66, 0x3C, 0xFF, 0x3C, 0x66, 0x00, 0x00], //'*'
+    [0x00, 0x0C, 0x0C, 0x3F, 0x0C, 0x0C, 0x00, 0x00], //'+'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x06], //','
+    [0x00, 0x00, 0x00, 0x3F, 0x00, 0x00, 0x00, 0x00], //'-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x00], //'.'
+    [0x60, 0x30, 0x18, 0x0C, 0x06, 0x03, 0x01, 0x00], //'/'
+    [0x3E, 0x63, 0x73, 0x7B, 0x6F, 0x67, 0x3E, 0x00], //'0'
+    [0x0C, 0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x3F, 0x00], //'1'
+    [0x1E, 0x33, 0x30, 0x1C, 0x06, 0x33, 0x3F, 0x00], //'2'
+    [0x1E, 0x33, 0x30, 0x1C, 0x30, 0x33, 0x1E, 0x00], //'3'
+    [0x38, 0x3C, 0x36, 0x33, 0x7F, 0x30, 0x78, 0x00], //'4'
+    [0x3F, 0x03, 0x1F, 0x30, 0x30, 0x33, 0x1E, 0x00], //'5'
+    [0x1C, 0x06, 0x03, 0x1F, 0x33, 0x33, 0x1E, 0x00], //'6'
+    [0x3F, 0x33, 0x30, 0x18, 0x0C, 0x0C, 0x0C, 0x00], //'7'
+    [0x1E, 0x33, 0x33, 0x1E, 0x33, 0x33, 0x1E, 0x00], //'8'
+    [0x1E, 0x33, 0x33, 0x3E, 0x30, 0x18, 0x0E, 0x00], //'9'
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x00], //':'
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x06], //';'
+    [0x18, 0x0C, 0x06, 0x03, 0x06, 0x0C, 0x18, 0x00], //'<'
+    [0x00, 0x00, 0x3F, 0x00, 0x00, 0x3F, 0x00, 0x00], //'='
+    [0x06, 0x0C, 0x18, 0x30, 0x18, 0x0C, 0x06, 0x00], //'>'
+    [0x1E, 0x33, 0x30, 0x18, 0x0C, 0x00, 0x0C, 0x00], //'?'
+    [0x3E, 0x63, 0x7B, 0x7B, 0x7B, 0x03, 0x1E, 0x00], //'@'
+    [0x0C, 0x1E, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x00], //'A'
+    [0x3F, 0x66, 0x66, 0x3E, 0x66, 0x66, 0x3F, 0x00], //'B'
+    [0x3C, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3C, 0x00], //'C'
+    [0x1F, 0x36, 0x66, 0x66, 0x66, 0x36, 0x1F, 0x00], //'D'
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x46, 0x7F, 0x00], //'E'
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x06, 0x0F, 0x00], //'F'
+    [0x3C, 0x66, 0x03, 0x03, 0x73, 0x66, 0x7C, 0x00], //'G'
+    [0x33, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x33, 0x00], //'H'
+    [0x1E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], //'I'
+    [0x78, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E, 0x00], //'J'
+    [0x67, 0x66, 0x36, 0x1E, 0x36, 0x66, 0x67, 0x00], //'K'
+    [0x0F, 0x06, 0x06, 0x06, 0x46, 0x66, 0x7F, 0x00], //'L'
+    [0x63, 0x77, 0x7F, 0x7F, 0x6B, 0x63, 0x63, 0x00], //'M'
+    [0x63, 0x67, 0x6F, 0x7B, 0x73, 0x63, 0x63, 0x00], //'N'
+    [0x1C, 0x36, 0x63, 0x63, 0x63, 0x36, 0x1C, 0x00], //'O'
+    [0x3F, 0x66, 0x66, 0x3E, 0x06, 0x06, 0x0F, 0x00], //'P'
+    [0x1E, 0x33, 0x33, 0x33, 0x3B, 0x1E, 0x38, 0x00], //'Q'
+    [0x3F, 0x66, 0x66, 0x3E, 0x36, 0x66, 0x67, 0x00], //'R'
+    [0x1E, 0x33, 0x07, 0x0E, 0x38, 0x33, 0x1E, 0x00], //'S'
+    [0x3F, 0x2D, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], //'T'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x3F, 0x00], //'U'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], //'V'
+    [0x63, 0x63, 0x63, 0x6B, 0x7F, 0x77, 0x63, 0x00], //'W'
+    [0x63, 0x63, 0x36, 0x1C, 0x1C, 0x36, 0x63, 0x00], //'X'
+    [0x33, 0x33, 0x33, 0x1E, 0x0C, 0x0C, 0x1E, 0x00], //'Y'
+    [0x7F, 0x63, 0x31, 0x18, 0x4C, 0x66, 0x7F, 0x00], //'Z'
+    [0x1E, 0x06, 0x06, 0x06, 0x06, 0x06, 0x1E, 0x00], //'['
+    [0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x40, 0x00], //'\\'
+    [0x1E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1E, 0x00], //']'
+    [0x08, 0x1C, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00], //'^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF], //'_'
+    [0x0C, 0x0C, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00], //'`'
+    [0x00, 0x00, 0x1E, 0x30, 0x3E, 0x33, 0x6E, 0x00], //'a'
+    [0x07, 0x06, 0x06, 0x3E, 0x66, 0x66, 0x3B, 0x00], //'b'
+    [0x00, 0x00, 0x1E, 0x33, 0x03, 0x33, 0x1E, 0x00], //'c'
+    [0x38, 0x30, 0x30, 0x3E, 0x33, 0x33, 0x6E, 0x00], //'d'
+    [0x00, 0x00, 0x1E, 0x33, 0x3F, 0x03, 0x1E, 0x00], //'e'
+    [0x1C, 0x36, 0x06, 0x0F, 0x06, 0x06, 0x0F, 0x00], //'f'
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x1F], //'g'
+    [0x07, 0x06, 0x36, 0x6E, 0x66, 0x66, 0x67, 0x00], //'h'
+    [0x0C, 0x00, 0x0E, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], //'i'
+    [0x30, 0x00, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E], //'j'
+    [0x07, 0x06, 0x66, 0x36, 0x1E, 0x36, 0x67, 0x00], //'k'
+    [0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], //'l'
+    [0x00, 0x00, 0x33, 0x7F, 0x7F, 0x6B, 0x63, 0x00], //'m'
+    [0x00, 0x00, 0x1F, 0x33, 0x33, 0x33, 0x33, 0x00], //'n'
+    [0x00, 0x00, 0x1E, 0x33, 0x33, 0x33, 0x1E, 0x00], //'o'
+    [0x00, 0x00, 0x3B, 0x66, 0x66, 0x3E, 0x06, 0x0F], //'p'
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x78], //'q'
+    [0x00, 0x00, 0x3B, 0x6E, 0x66, 0x06, 0x0F, 0x00], //'r'
+    [0x00, 0x00, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x00], //'s'
+    [0x08, 0x0C, 0x3E, 0x0C, 0x0C, 0x2C, 0x18, 0x00], //'t'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x33, 0x6E, 0x00], //'u'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], //'v'
+    [0x00, 0x00, 0x63, 0x6B, 0x7F, 0x7F, 0x36, 0x00], //'w'
+    [0x00, 0x00, 0x63, 0x36, 0x1C, 0x36, 0x63, 0x00], //'x'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x3E, 0x30, 0x1F], //'y'
+    [0x00, 0x00, 0x3F, 0x19, 0x0C, 0x26, 0x3F, 0x00], //'z'
+    [0x38, 0x0C, 0x0C, 0x07, 0x0C, 0x0C, 0x38, 0x00], //'{'
+    [0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00], //'|'
+    [0x07, 0x0C, 0x0C, 0x38, 0x0C, 0x0C, 0x07, 0x00], //'}'
+    [0x6E, 0x3B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], //'~'
+];
+
+//文字の四角形の頂点、座標はクリップ座標
+//シェーダー側のmain_vs_textの入力の順番とlocationを合わせる
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct TextVertex {
+    pub position: [f32; 2],
+    pub uv: [f32; 2],
+}
+
+impl TextVertex {
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(mem::size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+        [
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(mem::size_of::<[f32; 2]>() as u32)
+                .build(),
+        ]
+    }
+}
+
+//printで置いた1文字、座標はウィンドウの左上を原点にしたピクセル
+#[derive(Clone, Copy, Debug)]
+struct Glyph {
+    x: f32,
+    y: f32,
+    //アトラスでの(u0, v0, u1, v1)
+    uv: [f32; 4],
+}
+
+//文字cのアトラスでの(u0, v0, u1, v1)、フォントに無い文字は'?'にする
+fn glyph_uv(c: char) -> [f32; 4] {
+    let index = match c {
+        ' '..='~' => c as u32 - FIRST_CHAR as u32,
+        _ => (b'?' - FIRST_CHAR) as u32,
+    };
+
+    let column = index % ATLAS_COLUMNS;
+    let row = index / ATLAS_COLUMNS;
+
+    let u0 = (column * GLYPH_SIZE) as f32 / ATLAS_WIDTH as f32;
+    let v0 = (row * GLYPH_SIZE) as f32 / ATLAS_HEIGHT as f32;
+    let u1 = ((column + 1) * GLYPH_SIZE) as f32 / ATLAS_WIDTH as f32;
+    let v1 = ((row + 1) * GLYPH_SIZE) as f32 / ATLAS_HEIGHT as f32;
+
+    [u0, v0, u1, v1]
+}
+
+//(x, y)から右にtextの文字を並べてglyphsに追加する、'\n'で次の行に移り、空白は詰めずに飛ばす
+//描画されない分を溜め続けないように、glyphsがMAX_GLYPHSに達したら残りは捨てる
+fn layout_text(glyphs: &mut Vec<Glyph>, x: f32, y: f32, text: &str, scale: f32) {
+    let advance = GLYPH_SIZE as f32 * scale;
+    let line_height = LINE_SPACING as f32 * scale;
+    let (mut pen_x, mut pen_y) = (x, y);
+
+    for c in text.chars() {
+        if c == '\n' {
+            pen_x = x;
+            pen_y += line_height;
+            continue;
+        }
+
+        if glyphs.len() == MAX_GLYPHS {
+            return;
+        }
+
+        if c != ' ' {
+            glyphs.push(Glyph {
+                x: pen_x,
+                y: pen_y,
+                uv: glyph_uv(c),
+            });
+        }
+
+        pen_x += advance;
+    }
+}
+
+//1文字につき左上、右上、右下、左下の順番の4頂点、座標はextentで割ってクリップ座標にする
+fn glyph_vertices(glyphs: &[Glyph], scale: f32, extent: vk::Extent2D) -> Vec<TextVertex> {
+    let to_clip = |x: f32, y: f32| {
+        [
+            x / extent.width as f32 * 2.0 - 1.0,
+            //Vulkanのクリップ座標はYが下向きなのでピクセルの座標と向きが同じ
+            y / extent.height as f32 * 2.0 - 1.0,
+        ]
+    };
+
+    let size = GLYPH_SIZE as f32 * scale;
+
+    glyphs
+        .iter()
+        .flat_map(|glyph| {
+            let [u0, v0, u1, v1] = glyph.uv;
+            let (x0, y0) = (glyph.x, glyph.y);
+            let (x1, y1) = (x0 + size, y0 + size);
+
+            [
+                TextVertex {
+                    position: to_clip(x0, y0),
+                    uv: [u0, v0],
+                },
+                TextVertex {
+                    position: to_clip(x1, y0),
+                    uv: [u1, v0],
+                },
+                TextVertex {
+                    position: to_clip(x1, y1),
+                    uv: [u1, v1],
+                },
+                TextVertex {
+                    position: to_clip(x0, y1),
+                    uv: [u0, v1],
+                },
+            ]
+        })
+        .collect()
+}
+
+//glyph_verticesの4頂点を2つの三角形にする、全ての文字で共通なのでMAX_GLYPHS分を作っておく
+fn quad_indices() -> Vec<u16> {
+    (0..MAX_GLYPHS as u16)
+        .flat_map(|glyph| {
+            let base = glyph * 4;
+            [base, base + 1, base + 2, base + 2, base + 3, base]
+        })
+        .collect()
+}
+
+//FONTの文字をATLAS_COLUMNS列に並べた、1ピクセル1バイトのカバレッジ
+fn atlas_pixels() -> Vec<u8> {
+    let mut pixels = vec![0; (ATLAS_WIDTH * ATLAS_HEIGHT) as usize];
+
+    for (index, rows) in FONT.iter().enumerate() {
+        let origin_x = index as u32 % ATLAS_COLUMNS * GLYPH_SIZE;
+        let origin_y = index as u32 / ATLAS_COLUMNS * GLYPH_SIZE;
+
+        for (y, bits) in rows.iter().enumerate() {
+            for x in 0..GLYPH_SIZE {
+                if bits >> x & 1 == 1 {
+                    let offset = (origin_y + y as u32) * ATLAS_WIDTH + origin_x + x;
+                    pixels[offset as usize] = 255;
+                }
+            }
+        }
+    }
+
+    pixels
+}
+
+//画面に重ねて描画するデバッグ用の文字
+//コマンドの記録の前にprintで文字を置き、uploadでこのフレームの頂点バッファに書き込んでからcmd_drawで描画する
+//set = 0にフォントのアトラスのimageとsamplerを割り当てる
+pub struct DebugText {
+    atlas: Texture2D,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    //フレームごとにStagingRingに書き込んだ頂点の(バッファ, オフセット)
+    vertex_bindings: Vec<(vk::Buffer, vk::DeviceSize)>,
+    //文字ごとの四角形のインデックスは変わらないので1つだけ作る
+    index_buffer: vk::Buffer,
+    index_memory: vk::DeviceMemory,
+    //次のuploadまでにprintで置いた文字
+    glyphs: Vec<Glyph>,
+    //フレームごとの頂点バッファに書き込んだ文字の数
+    glyph_counts: Vec<u32>,
+    //フォントの1ピクセルを画面の何ピクセルで描画するか
+    //整数倍にしておくとNEARESTで拡大してもピクセルの幅が揃ってぼやけない
+    scale: f32,
+}
+
+impl DebugText {
+    //samplerの破棄はSamplerCacheに任せる
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        sampler: vk::Sampler,
+        frames_in_flight: u32,
+    ) -> Self {
+        let atlas = Texture2D::new(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            ATLAS_FORMAT,
+            vk::Extent2D {
+                width: ATLAS_WIDTH,
+                height: ATLAS_HEIGHT,
+            },
+            &atlas_pixels(),
+        );
+
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        };
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .build(),
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+
+        let set_layouts = [descriptor_set_layout];
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&set_layouts)
+            .build();
+
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] };
+
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .image_view(atlas.view())
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()];
+
+        let sampler_info = [vk::DescriptorImageInfo::builder().sampler(sampler).build()];
+
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(&image_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .image_info(&sampler_info)
+                .build(),
+        ];
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        let indices = quad_indices();
+
+        //書き換えないが小さいので、ステージングせずにCPUから見えるメモリに置く
+        let (index_buffer, index_memory) = buffer::create_host_buffer_with_data(
+            instance,
+            physical_device,
+            device,
+            &indices,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+        );
+
+        Self {
+            atlas,
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_set,
+            vertex_bindings: vec![(vk::Buffer::null(), 0); frames_in_flight as usize],
+            index_buffer,
+            index_memory,
+            glyphs: vec![],
+            glyph_counts: vec![0; frames_in_flight as usize],
+            scale: GLYPH_SCALE,
+        }
+    }
+
+    //パイプラインレイアウトのset = 0に使う
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    //ウィンドウのscale_factorに合わせて文字を大きくする、HiDPIのモニターでも論理ピクセルでの大きさが変わらない
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.scale = (GLYPH_SCALE * scale_factor as f32).round().max(1.0);
+    }
+
+    //ウィンドウの左上を原点にした物理ピクセルの(x, y)から右にtextを並べる、'\n'で次の行に移る
+    //次のuploadまでの全てのprintがまとめて1フレームに描画される
+    pub fn print(&mut self, x: f32, y: f32, text: &str) {
+        layout_text(&mut self.glyphs, x, y, text, self.scale);
+    }
+
+    //printで置いた文字の頂点をstaging_ringに書き込み、置いた文字を消す
+    //ピクセルの座標はextentで割ってクリップ座標にするので、ウィンドウの大きさが変わっても文字の大きさは変わらない
+    //staging_ringのbegin_frameでframeを始めた後に呼ぶ
+    pub fn upload(
+        &mut self,
+        device: &Device,
+        staging_ring: &mut StagingRing,
+        frame: usize,
+        extent: vk::Extent2D,
+    ) {
+        let vertices = glyph_vertices(&self.glyphs, self.scale, extent);
+        self.glyphs.clear();
+
+        //printでMAX_GLYPHSまでに抑えているのでインデックスバッファに収まる
+        let allocation = staging_ring.write(device, &vertices);
+        self.vertex_bindings[frame] = (allocation.buffer, allocation.offset);
+        self.glyph_counts[frame] = (vertices.len() / 4) as u32;
+    }
+
+    //他の描画の後に、描画先のパスの中で呼ぶ
+    //viewportとscissorは呼び出し側で設定しておく
+    pub fn cmd_draw(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        (pipeline, pipeline_layout): (vk::Pipeline, vk::PipelineLayout),
+        frame: usize,
+    ) {
+        let glyph_count = self.glyph_counts[frame];
+        let (vertex_buffer, vertex_offset) = self.vertex_bindings[frame];
+
+        if glyph_count == 0 {
+            return;
+        }
+
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[vertex_offset]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                self.index_buffer,
+                0,
+                vk::IndexType::UINT16,
+            );
+            device.cmd_draw_indexed(command_buffer, glyph_count * 6, 1, 0, 0, 0);
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_buffer(self.index_buffer, None);
+            memory_budget::free_memory(device, self.index_memory);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+        self.atlas.destroy(device);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_glyph_is_top_left_cell() {
+        //' 'は0番目なので左上のマス
+        assert_eq!(glyph_uv(' '), [0.0, 0.0, 1.0 / 16.0, 1.0 / 6.0]);
+    }
+
+    #[test]
+    fn glyph_uv_follows_atlas_grid() {
+        //'A'は0x41 - 0x20 = 33番目なので2行目の1列目
+        assert_eq!(
+            glyph_uv('A'),
+            [1.0 / 16.0, 2.0 / 6.0, 2.0 / 16.0, 3.0 / 6.0]
+        );
+        //'~'は94番目なので最後の行の14列目
+        assert_eq!(glyph_uv('~'), [14.0 / 16.0, 5.0 / 6.0, 15.0 / 16.0, 1.0]);
+    }
+
+    #[test]
+    fn glyphs_outside_the_font_use_question_mark() {
+        assert_eq!(glyph_uv('\u{3042}'), glyph_uv('?'));
+        assert_eq!(glyph_uv('\t'), glyph_uv('?'));
+        assert_eq!(glyph_uv('\u{7F}'), glyph_uv('?'));
+    }
+
+    #[test]
+    fn every_glyph_is_one_atlas_cell() {
+        for c in ' '..='~' {
+            let [u0, v0, u1, v1] = glyph_uv(c);
+
+            assert!((u1 - u0 - 1.0 / ATLAS_COLUMNS as f32).abs() < 1e-6);
+            assert!((v1 - v0 - 1.0 / ATLAS_ROWS as f32).abs() < 1e-6);
+            assert!(u1 <= 1.0 && v1 <= 1.0);
+        }
+    }
+
+    #[test]
+    fn layout_advances_and_wraps_lines() {
+        let mut glyphs = vec![];
+        layout_text(&mut glyphs, 10.0, 20.0, "ab c\nd", 2.0);
+
+        //空白は文字を置かずに1文字分進める
+        let positions = glyphs
+            .iter()
+            .map(|glyph| (glyph.x, glyph.y))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            [(10.0, 20.0), (26.0, 20.0), (58.0, 20.0), (10.0, 40.0)]
+        );
+        assert_eq!(glyphs[3].uv, glyph_uv('d'));
+    }
+
+    #[test]
+    fn layout_stops_at_max_glyphs() {
+        let mut glyphs = vec![];
+        let text = "x".repeat(MAX_GLYPHS + 10);
+
+        layout_text(&mut glyphs, 0.0, 0.0, &text, 1.0);
+        layout_text(&mut glyphs, 0.0, 0.0, "more", 1.0);
+
+        assert_eq!(glyphs.len(), MAX_GLYPHS);
+    }
+
+    #[test]
+    fn vertices_map_pixels_to_clip_space() {
+        let glyphs = [Glyph {
+            x: 0.0,
+            y: 50.0,
+            uv: glyph_uv('A'),
+        }];
+        let extent = vk::Extent2D {
+            width: 200,
+            height: 100,
+        };
+
+        //拡大率2.0で16ピクセル四方の四角形になる
+        let vertices = glyph_vertices(&glyphs, 2.0, extent);
+        let expected = [[-1.0, 0.0], [-0.84, 0.0], [-0.84, 0.32], [-1.0, 0.32]];
+
+        assert_eq!(vertices.len(), 4);
+
+        for (vertex, expected) in vertices.iter().zip(expected) {
+            assert!((vertex.position[0] - expected[0]).abs() < 1e-6);
+            assert!((vertex.position[1] - expected[1]).abs() < 1e-6);
+        }
+
+        let [u0, v0, u1, v1] = glyph_uv('A');
+        let uvs = vertices.iter().map(|vertex| vertex.uv).collect::<Vec<_>>();
+        assert_eq!(uvs, [[u0, v0], [u1, v0], [u1, v1], [u0, v1]]);
+    }
+
+    #[test]
+    fn quad_indices_make_two_triangles_per_glyph() {
+        let indices = quad_indices();
+
+        assert_eq!(indices.len(), MAX_GLYPHS * 6);
+        assert_eq!(indices[..12], [0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4]);
+        //最後の文字の頂点の番号もu16に収まる
+        assert_eq!(*indices.iter().max().unwrap() as usize, MAX_GLYPHS * 4 - 1);
+    }
+}
@@ -0,0 +1,320 @@
+use crate::buffer;
+use crate::memory_budget;
+use crate::synchronization::Synchronization;
+use ash::{vk, Device, Instance};
+use serde::{Deserialize, Serialize};
+use std::mem;
+use std::str::FromStr;
+
+//F3で切り替える、最終的な出力の代わりに表示するもの
+//シーンのファイルにはnameと同じ名前で書く
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DebugView {
+    //通常のライティング
+    Shading,
+    //ワールド座標の法線を色にする
+    Normals,
+    //カメラからの距離をnearで黒、farで白にする
+    Depth,
+    //ピクセルごとに描画されたフラグメントの数を色にする
+    Overdraw,
+    //--light-cullingの時にタイルごとに残ったライトの数を色にしてシーンに重ねる
+    LightTiles,
+}
+
+impl DebugView {
+    pub fn name(self) -> &'static str {
+        match self {
+            DebugView::Shading => "shading",
+            DebugView::Normals => "normals",
+            DebugView::Depth => "depth",
+            DebugView::Overdraw => "overdraw",
+            DebugView::LightTiles => "light-tiles",
+        }
+    }
+
+    //切り替えの順番、最後の次は最初に戻る
+    pub fn next(self) -> Self {
+        match self {
+            DebugView::Shading => DebugView::Normals,
+            DebugView::Normals => DebugView::Depth,
+            DebugView::Depth => DebugView::Overdraw,
+            DebugView::Overdraw => DebugView::LightTiles,
+            DebugView::LightTiles => DebugView::Shading,
+        }
+    }
+
+    //main_fsのDEBUG_VIEW_CONSTANT_IDの特殊化定数に入れる値
+    //オーバードローとライトのタイルは別のパイプラインで描画するので特殊化定数を使わない
+    pub fn spec_constant(self) -> Option<u32> {
+        match self {
+            DebugView::Normals => Some(1),
+            DebugView::Depth => Some(2),
+            DebugView::Shading | DebugView::Overdraw | DebugView::LightTiles => None,
+        }
+    }
+}
+
+impl FromStr for DebugView {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "shading" => Ok(Self::Shading),
+            "normals" => Ok(Self::Normals),
+            "depth" => Ok(Self::Depth),
+            "overdraw" => Ok(Self::Overdraw),
+            "light-tiles" => Ok(Self::LightTiles),
+            _ => Err(format!(
+                "Unknown debug view '{}', expected shading, normals, depth, overdraw or light-tiles",
+                s
+            )),
+        }
+    }
+}
+
+//main_fs_overdrawがピクセルごとのフラグメントの数をatomicに足していくフレームごとのstorage buffer
+//先頭のu32は画像の幅で、その後ろに行の順にピクセルの数が並ぶ
+//rust-gpuでは画像へのatomicが使えないので、R32_UINTのstorage imageの代わりにbufferにしている
+//数えるパイプラインのset = 2と、main_fs_overdraw_heatmapのset = 0で同じDescriptor Setを使う
+pub struct OverdrawCounters {
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    //描画する大きさに依存するので、swapchainを作り直す度に作り直す
+    buffers: Vec<(vk::Buffer, vk::DeviceMemory)>,
+    width: u32,
+}
+
+impl OverdrawCounters {
+    pub fn new(device: &Device, frames_in_flight: u32) -> Self {
+        let bindings = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build()];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        };
+
+        let pool_sizes = [vk::DescriptorPoolSize::builder()
+            .ty(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(frames_in_flight)
+            .build()];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(frames_in_flight)
+            .build();
+
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+
+        let set_layouts = vec![descriptor_set_layout; frames_in_flight as usize];
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&set_layouts)
+            .build();
+
+        let descriptor_sets = unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() };
+
+        Self {
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_sets,
+            buffers: vec![],
+            width: 0,
+        }
+    }
+
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    //extentは描画する大きさ、古いバッファはdestroy_buffersで破棄しておく
+    pub fn create_buffers(
+        &mut self,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        extent: vk::Extent2D,
+    ) {
+        let count = 1 + extent.width as vk::DeviceSize * extent.height as vk::DeviceSize;
+        let size = count * mem::size_of::<u32>() as vk::DeviceSize;
+
+        self.buffers = (0..self.descriptor_sets.len())
+            .map(|_| {
+                buffer::create_buffer(
+                    instance,
+                    physical_device,
+                    device,
+                    size,
+                    vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                )
+            })
+            .collect();
+        self.width = extent.width;
+
+        let buffer_infos = self
+            .buffers
+            .iter()
+            .map(|&(buffer, _)| {
+                [vk::DescriptorBufferInfo::builder()
+                    .buffer(buffer)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .build()]
+            })
+            .collect::<Vec<_>>();
+
+        let descriptor_writes = self
+            .descriptor_sets
+            .iter()
+            .zip(&buffer_infos)
+            .map(|(&descriptor_set, buffer_info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(buffer_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+    }
+
+    pub fn destroy_buffers(&mut self, device: &Device) {
+        for (buffer, memory) in self.buffers.drain(..) {
+            unsafe {
+                device.destroy_buffer(buffer, None);
+                memory_budget::free_memory(device, memory);
+            }
+        }
+    }
+
+    //レンダーパスの中ではクリアできないので、シーンのパスの前に記録する
+    //前のフレームで同じバッファを読んだヒートマップの描画が終わってから0にし、先頭に幅を書き込む
+    pub fn cmd_reset(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        command_buffer: vk::CommandBuffer,
+        current_frame: usize,
+    ) {
+        let (buffer, _) = self.buffers[current_frame];
+
+        let buffer_barrier = |src_stage, src_access, dst_stage, dst_access| {
+            vk::BufferMemoryBarrier2::builder()
+                .src_stage_mask(src_stage)
+                .src_access_mask(src_access)
+                .dst_stage_mask(dst_stage)
+                .dst_access_mask(dst_access)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(buffer)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+                .build()
+        };
+
+        synchronization.cmd_pipeline_barrier(
+            device,
+            command_buffer,
+            &[],
+            &[buffer_barrier(
+                vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                vk::AccessFlags2::SHADER_STORAGE_READ,
+                vk::PipelineStageFlags2::CLEAR | vk::PipelineStageFlags2::COPY,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            )],
+            &[],
+        );
+
+        //同じ範囲に書き込むと間にバリアが要るので、fillは幅の後ろから行う
+        let width_size = mem::size_of::<u32>() as vk::DeviceSize;
+
+        unsafe {
+            device.cmd_fill_buffer(command_buffer, buffer, width_size, vk::WHOLE_SIZE, 0);
+            device.cmd_update_buffer(command_buffer, buffer, 0, &self.width.to_ne_bytes());
+        }
+
+        synchronization.cmd_pipeline_barrier(
+            device,
+            command_buffer,
+            &[],
+            &[buffer_barrier(
+                vk::PipelineStageFlags2::CLEAR | vk::PipelineStageFlags2::COPY,
+                vk::AccessFlags2::TRANSFER_WRITE,
+                vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            )],
+            &[],
+        );
+    }
+
+    //シーンのパスで数え終わった後、ヒートマップのパスで読む前に記録する
+    pub fn cmd_wait_counts(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        command_buffer: vk::CommandBuffer,
+        current_frame: usize,
+    ) {
+        let (buffer, _) = self.buffers[current_frame];
+
+        let buffer_barrier = vk::BufferMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_STORAGE_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+
+        synchronization.cmd_pipeline_barrier(device, command_buffer, &[], &[buffer_barrier], &[]);
+    }
+
+    //数える時はset = 2、ヒートマップを描画する時はset = 0に紐づける
+    pub fn cmd_bind(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        set: u32,
+        current_frame: usize,
+    ) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                set,
+                &[self.descriptor_sets[current_frame]],
+                &[],
+            );
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_buffers(device);
+
+        unsafe {
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
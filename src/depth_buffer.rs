@@ -0,0 +1,244 @@
+use crate::buffer;
+use crate::memory_budget::{self, MemoryCategory};
+use ash::{vk, Device, Instance};
+use std::str::FromStr;
+
+//優先度の高い順に並べたデプスバッファのフォーマットの候補
+//D32_SFLOATが使えないデバイスもあるのでステンシル付きのものにフォールバックする
+const FORMAT_CANDIDATES: [vk::Format; 3] = [
+    vk::Format::D32_SFLOAT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D24_UNORM_S8_UINT,
+];
+
+//--depthと設定ファイルで選ぶ、シーンの深度値の向きとfarの扱い
+//シャドウマップはライトの行列で描くので常にStandardにする
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthConvention {
+    //nearが0.0でfarが1.0、チュートリアルと同じ
+    Standard,
+    //nearが1.0でfarが0.0、浮動小数点は0.0の近くほど細かいので、遠くで透視投影の分解能が落ちるのを打ち消す
+    Reverse,
+    //Reverseに加えてfarを無限遠にし、farより遠くもクリップしない
+    ReverseInfinite,
+}
+
+impl DepthConvention {
+    pub fn name(self) -> &'static str {
+        match self {
+            DepthConvention::Standard => "standard",
+            DepthConvention::Reverse => "reverse",
+            DepthConvention::ReverseInfinite => "reverse-infinite",
+        }
+    }
+
+    pub fn is_reversed(self) -> bool {
+        self != DepthConvention::Standard
+    }
+
+    //一番遠い深度値、クリアする値とスカイボックスの深度値に使う
+    pub fn far_depth(self) -> f32 {
+        if self.is_reversed() {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    //Standardで書いた深度テストの比較を、逆にした向きでも同じ意味になるように入れ替える
+    pub fn compare_op(self, compare_op: vk::CompareOp) -> vk::CompareOp {
+        if !self.is_reversed() {
+            return compare_op;
+        }
+
+        match compare_op {
+            vk::CompareOp::LESS => vk::CompareOp::GREATER,
+            vk::CompareOp::LESS_OR_EQUAL => vk::CompareOp::GREATER_OR_EQUAL,
+            vk::CompareOp::GREATER => vk::CompareOp::LESS,
+            vk::CompareOp::GREATER_OR_EQUAL => vk::CompareOp::LESS_OR_EQUAL,
+            other => other,
+        }
+    }
+}
+
+impl FromStr for DepthConvention {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "standard" => Ok(Self::Standard),
+            "reverse" => Ok(Self::Reverse),
+            "reverse-infinite" => Ok(Self::ReverseInfinite),
+            _ => Err(format!(
+                "Unknown depth convention '{}', expected standard, reverse or reverse-infinite",
+                s
+            )),
+        }
+    }
+}
+
+//画面全体の深度値を保持する画像
+//全てのフレームで共有するが、同じキューで順番に実行されるのでsubpass dependencyとバリアで前のフレームの書き込みを待てば良い
+pub struct DepthBuffer {
+    pub image: vk::Image,
+    memory: vk::DeviceMemory,
+    pub view: vk::ImageView,
+    pub format: vk::Format,
+    //MSAAの場合はシーンのカラーアタッチメントと同じサンプル数にする
+    pub samples: vk::SampleCountFlags,
+}
+
+impl DepthBuffer {
+    //候補の中でOPTIMALタイリングのデプスアタッチメントとして使える最初のフォーマットを返す
+    pub fn find_format(instance: &Instance, physical_device: vk::PhysicalDevice) -> vk::Format {
+        let format = FORMAT_CANDIDATES
+            .into_iter()
+            .find(|&format| {
+                let properties = unsafe {
+                    instance.get_physical_device_format_properties(physical_device, format)
+                };
+
+                properties
+                    .optimal_tiling_features
+                    .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+            })
+            .expect("No supported depth format");
+
+        log::info!("Depth format: {:?}", format);
+
+        format
+    }
+
+    //swapchainと同じ大きさで作るので、swapchainを作り直す度に作り直す
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        samples: vk::SampleCountFlags,
+    ) -> Self {
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(samples)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+
+        let image = unsafe { device.create_image(&image_info, None).unwrap() };
+
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
+
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(buffer::find_memory_type(
+                instance,
+                physical_device,
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ))
+            .build();
+
+        let memory = unsafe {
+            memory_budget::allocate_memory(device, &alloc_info, MemoryCategory::Attachment).unwrap()
+        };
+
+        unsafe { device.bind_image_memory(image, memory, 0).unwrap() };
+
+        //image viewからはステンシルを参照しないのでDEPTHだけにする
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                    .base_mip_level(0)
+                    .level_count(1)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .build();
+
+        let view = unsafe { device.create_image_view(&view_info, None).unwrap() };
+
+        Self {
+            image,
+            memory,
+            view,
+            format,
+            samples,
+        }
+    }
+
+    //レイアウトの遷移はステンシルを持つフォーマットでは両方のアスペクトに対して行う必要がある
+    pub fn subresource_range(&self) -> vk::ImageSubresourceRange {
+        let aspect_mask = if Self::has_stencil(self.format) {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        } else {
+            vk::ImageAspectFlags::DEPTH
+        };
+
+        vk::ImageSubresourceRange::builder()
+            .aspect_mask(aspect_mask)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build()
+    }
+
+    //render passのattachment 1に使う
+    //毎回DepthConvention::far_depthでクリアし、パスが終わった後の内容は使わない
+    pub fn attachment_description(
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> vk::AttachmentDescription {
+        vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(samples)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build()
+    }
+
+    //クリアは一番遠い深度値にする
+    pub fn clear_value(convention: DepthConvention) -> vk::ClearValue {
+        vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: convention.far_depth(),
+                stencil: 0,
+            },
+        }
+    }
+
+    fn has_stencil(format: vk::Format) -> bool {
+        matches!(
+            format,
+            vk::Format::D32_SFLOAT_S8_UINT | vk::Format::D24_UNORM_S8_UINT
+        )
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+            memory_budget::free_memory(device, self.memory);
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use ash::{vk, Device};
+use std::collections::HashMap;
+
+//最初のプールに入るDescriptor Setの数、プールを作り足すごとに倍にする
+const INITIAL_SETS_PER_POOL: u32 = 16;
+const MAX_SETS_PER_POOL: u32 = 4096;
+
+//Descriptor Set 1つあたりに見込む種類ごとのDescriptorの数
+//マテリアルのテクスチャはset 1つにsamplerが1つとテクスチャが複数入る
+const POOL_RATIOS: [(vk::DescriptorType, u32); 7] = [
+    (vk::DescriptorType::SAMPLER, 1),
+    (vk::DescriptorType::SAMPLED_IMAGE, 4),
+    (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1),
+    (vk::DescriptorType::UNIFORM_BUFFER, 1),
+    (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1),
+    (vk::DescriptorType::STORAGE_BUFFER, 1),
+    (vk::DescriptorType::STORAGE_IMAGE, 1),
+];
+
+//Descriptor Setを確保するプールが足りなくなったら新しいプールを作り足す
+//固定の大きさのプールを1つだけ使うとマテリアルが増えた時に溢れる
+pub struct DescriptorAllocator {
+    //確保に使っているプール
+    current_pool: Option<vk::DescriptorPool>,
+    //確保できなくなったプール
+    used_pools: Vec<vk::DescriptorPool>,
+    //resetで空にしたプール、新しく作る前にこちらを使う
+    free_pools: Vec<vk::DescriptorPool>,
+    //次に作るプールの大きさ
+    sets_per_pool: u32,
+}
+
+impl DescriptorAllocator {
+    pub fn new() -> Self {
+        Self {
+            current_pool: None,
+            used_pools: vec![],
+            free_pools: vec![],
+            sets_per_pool: INITIAL_SETS_PER_POOL,
+        }
+    }
+
+    //layoutのDescriptor Setを1つ確保する
+    //variable_countはVARIABLE_DESCRIPTOR_COUNTのbindingで実際に使う数
+    pub fn allocate(
+        &mut self,
+        device: &Device,
+        layout: vk::DescriptorSetLayout,
+        variable_count: Option<u32>,
+    ) -> vk::DescriptorSet {
+        let pool = match self.current_pool {
+            Some(pool) => pool,
+            None => self.next_pool(device),
+        };
+
+        match Self::try_allocate(device, pool, layout, variable_count) {
+            Ok(descriptor_set) => descriptor_set,
+            //プールの空きが無いか断片化している場合は新しいプールでやり直す
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => {
+                self.used_pools.push(pool);
+                self.current_pool = None;
+
+                let pool = self.next_pool(device);
+
+                Self::try_allocate(device, pool, layout, variable_count)
+                    .expect("Failed to allocate a descriptor set from a new pool")
+            }
+            Err(error) => panic!("Failed to allocate a descriptor set: {}", error),
+        }
+    }
+
+    fn try_allocate(
+        device: &Device,
+        pool: vk::DescriptorPool,
+        layout: vk::DescriptorSetLayout,
+        variable_count: Option<u32>,
+    ) -> Result<vk::DescriptorSet, vk::Result> {
+        let set_layouts = [layout];
+        let descriptor_counts = [variable_count.unwrap_or(0)];
+
+        let mut variable_count_info =
+            vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
+                .descriptor_counts(&descriptor_counts)
+                .build();
+
+        let mut alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(&set_layouts);
+
+        if variable_count.is_some() {
+            alloc_info = alloc_info.push_next(&mut variable_count_info);
+        }
+
+        unsafe { device.allocate_descriptor_sets(&alloc_info) }.map(|sets| sets[0])
+    }
+
+    //resetで空にしたプールがあればそれを使い、無ければ作る
+    fn next_pool(&mut self, device: &Device) -> vk::DescriptorPool {
+        let pool = self.free_pools.pop().unwrap_or_else(|| {
+            let pool = Self::create_pool(device, self.sets_per_pool);
+
+            log::info!("Descriptor pool: created for {} sets", self.sets_per_pool);
+
+            self.sets_per_pool = Self::grown_pool_size(self.sets_per_pool);
+
+            pool
+        });
+
+        self.current_pool = Some(pool);
+
+        pool
+    }
+
+    //sets_per_poolの次に作るプールの大きさ、MAX_SETS_PER_POOLで止める
+    fn grown_pool_size(sets_per_pool: u32) -> u32 {
+        (sets_per_pool * 2).min(MAX_SETS_PER_POOL)
+    }
+
+    fn pool_sizes(set_count: u32) -> Vec<vk::DescriptorPoolSize> {
+        POOL_RATIOS
+            .iter()
+            .map(|&(ty, ratio)| {
+                vk::DescriptorPoolSize::builder()
+                    .ty(ty)
+                    .descriptor_count(set_count * ratio)
+                    .build()
+            })
+            .collect()
+    }
+
+    fn create_pool(device: &Device, set_count: u32) -> vk::DescriptorPool {
+        let pool_sizes = Self::pool_sizes(set_count);
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(set_count)
+            .build();
+
+        unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() }
+    }
+
+    //確保した全てのDescriptor Setをまとめて解放する
+    //フレームごとに作り直す一時的なDescriptor Setに使い、そのフレームの完了を待った後に呼ぶ
+    #[allow(dead_code)]
+    pub fn reset(&mut self, device: &Device) {
+        let pools = self.used_pools.drain(..).chain(self.current_pool.take());
+
+        for pool in pools {
+            unsafe {
+                device
+                    .reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())
+                    .unwrap();
+            }
+            self.free_pools.push(pool);
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        let pools = self
+            .used_pools
+            .iter()
+            .chain(&self.free_pools)
+            .chain(&self.current_pool);
+
+        unsafe {
+            for pool in pools {
+                device.destroy_descriptor_pool(*pool, None);
+            }
+        }
+    }
+}
+
+impl Default for DescriptorAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//DescriptorLayoutCacheのキーになるbindingの内容
+//immutable samplerは使わないので比較しない
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+struct LayoutKey {
+    //bindingの番号順に並べる
+    bindings: Vec<(
+        u32,
+        vk::DescriptorType,
+        u32,
+        vk::ShaderStageFlags,
+        vk::DescriptorBindingFlags,
+    )>,
+}
+
+impl LayoutKey {
+    //binding_flagsは空かbindingsと同じ長さ
+    fn new(
+        bindings: &[vk::DescriptorSetLayoutBinding],
+        binding_flags: &[vk::DescriptorBindingFlags],
+    ) -> Self {
+        let mut bindings = bindings
+            .iter()
+            .enumerate()
+            .map(|(index, binding)| {
+                (
+                    binding.binding,
+                    binding.descriptor_type,
+                    binding.descriptor_count,
+                    binding.stage_flags,
+                    binding_flags
+                        .get(index)
+                        .copied()
+                        .unwrap_or_else(vk::DescriptorBindingFlags::empty),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        //同じbindingを違う順番で渡しても同じレイアウトになる
+        bindings.sort_by_key(|binding| binding.0);
+
+        Self { bindings }
+    }
+}
+
+//同じbindingのDescriptor Set Layoutを何度も作らないように使い回す
+//同じレイアウトのDescriptor Setは同じハンドルのレイアウトから作られるのでパイプラインレイアウトの互換性も確認しやすい
+#[derive(Default)]
+pub struct DescriptorLayoutCache {
+    layouts: HashMap<LayoutKey, vk::DescriptorSetLayout>,
+}
+
+impl DescriptorLayoutCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //bindingsのレイアウトを返す、無ければ作成する
+    //binding_flagsが空でない場合はDescriptorSetLayoutBindingFlagsCreateInfoで渡す
+    pub fn get(
+        &mut self,
+        device: &Device,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+        binding_flags: &[vk::DescriptorBindingFlags],
+    ) -> vk::DescriptorSetLayout {
+        *self
+            .layouts
+            .entry(LayoutKey::new(bindings, binding_flags))
+            .or_insert_with(|| Self::create_layout(device, bindings, binding_flags))
+    }
+
+    fn create_layout(
+        device: &Device,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+        binding_flags: &[vk::DescriptorBindingFlags],
+    ) -> vk::DescriptorSetLayout {
+        let mut binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder()
+            .binding_flags(binding_flags)
+            .build();
+
+        let mut layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+        if !binding_flags.is_empty() {
+            layout_info = layout_info.push_next(&mut binding_flags_info);
+        }
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            for layout in self.layouts.values() {
+                device.destroy_descriptor_set_layout(*layout, None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn binding(
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        descriptor_count: u32,
+        stage_flags: vk::ShaderStageFlags,
+    ) -> vk::DescriptorSetLayoutBinding {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(descriptor_type)
+            .descriptor_count(descriptor_count)
+            .stage_flags(stage_flags)
+            .build()
+    }
+
+    fn uniform() -> vk::DescriptorSetLayoutBinding {
+        binding(
+            0,
+            vk::DescriptorType::UNIFORM_BUFFER,
+            1,
+            vk::ShaderStageFlags::VERTEX,
+        )
+    }
+
+    fn sampler() -> vk::DescriptorSetLayoutBinding {
+        binding(
+            1,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            1,
+            vk::ShaderStageFlags::FRAGMENT,
+        )
+    }
+
+    #[test]
+    fn pools_double_up_to_the_limit() {
+        let mut sizes = vec![INITIAL_SETS_PER_POOL];
+
+        while *sizes.last().unwrap() < MAX_SETS_PER_POOL {
+            sizes.push(DescriptorAllocator::grown_pool_size(*sizes.last().unwrap()));
+        }
+
+        assert_eq!(sizes, [16, 32, 64, 128, 256, 512, 1024, 2048, 4096]);
+        assert_eq!(
+            DescriptorAllocator::grown_pool_size(MAX_SETS_PER_POOL),
+            MAX_SETS_PER_POOL
+        );
+    }
+
+    #[test]
+    fn pool_sizes_scale_with_set_count() {
+        let sizes = DescriptorAllocator::pool_sizes(32);
+
+        assert_eq!(sizes.len(), POOL_RATIOS.len());
+
+        for (size, &(ty, ratio)) in sizes.iter().zip(&POOL_RATIOS) {
+            assert_eq!(size.ty, ty);
+            assert_eq!(size.descriptor_count, 32 * ratio);
+        }
+    }
+
+    #[test]
+    fn layout_key_ignores_binding_order() {
+        let forward = LayoutKey::new(&[uniform(), sampler()], &[]);
+        let backward = LayoutKey::new(&[sampler(), uniform()], &[]);
+
+        assert_eq!(forward, backward);
+        assert_eq!(
+            [forward, backward]
+                .into_iter()
+                .collect::<HashSet<_>>()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn layout_key_treats_missing_flags_as_empty() {
+        let without_flags = LayoutKey::new(&[uniform(), sampler()], &[]);
+        let with_empty_flags = LayoutKey::new(
+            &[uniform(), sampler()],
+            &[vk::DescriptorBindingFlags::empty(); 2],
+        );
+
+        assert_eq!(without_flags, with_empty_flags);
+    }
+
+    #[test]
+    fn layout_key_distinguishes_binding_contents() {
+        let base = uniform();
+        let keys = [
+            LayoutKey::new(&[base], &[]),
+            LayoutKey::new(
+                &[binding(
+                    1,
+                    base.descriptor_type,
+                    base.descriptor_count,
+                    base.stage_flags,
+                )],
+                &[],
+            ),
+            LayoutKey::new(
+                &[binding(
+                    0,
+                    vk::DescriptorType::STORAGE_BUFFER,
+                    base.descriptor_count,
+                    base.stage_flags,
+                )],
+                &[],
+            ),
+            LayoutKey::new(
+                &[binding(0, base.descriptor_type, 4, base.stage_flags)],
+                &[],
+            ),
+            LayoutKey::new(
+                &[binding(
+                    0,
+                    base.descriptor_type,
+                    base.descriptor_count,
+                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                )],
+                &[],
+            ),
+            LayoutKey::new(&[base], &[vk::DescriptorBindingFlags::PARTIALLY_BOUND]),
+        ];
+
+        assert_eq!(keys.into_iter().collect::<HashSet<_>>().len(), 6);
+    }
+}
@@ -0,0 +1,340 @@
+use crate::crash_diagnostics::PhysicalDeviceFaultFeatures;
+use ash::{vk, Instance};
+
+//DeviceFeatureRequestで要求できる機能
+//拡張の機能の場合、拡張がサポートされていることは呼び出し側で確認しておく
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceFeature {
+    PipelineStatisticsQuery,
+    FillModeNonSolid,
+    GeometryShader,
+    TessellationShader,
+    VertexPipelineStoresAndAtomics,
+    FragmentStoresAndAtomics,
+    MultiDrawIndirect,
+    SamplerAnisotropy,
+    //BCやASTCのフォーマットの画像を作るのに必要、フォーマットのプロパティだけでは使えるか判断できない
+    TextureCompressionBc,
+    TextureCompressionAstcLdr,
+    //shaderSampledImageArrayDynamicIndexingと、main_fs_bindlessで使うVkPhysicalDeviceDescriptorIndexingFeaturesの4つ
+    DescriptorIndexing,
+    TimelineSemaphore,
+    Synchronization2,
+    DynamicRendering,
+    BufferDeviceAddress,
+    AccelerationStructure,
+    RayTracingPipeline,
+    RayQuery,
+    //vkGetDeviceFaultInfoEXTを呼ぶのに必要
+    DeviceFault,
+    //VkPhysicalDeviceMeshShaderFeaturesEXTのtaskShaderとmeshShader
+    MeshShader,
+}
+
+//論理デバイスで有効にした機能、falseの機能は使ってはいけない
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EnabledFeatures {
+    pub pipeline_statistics_query: bool,
+    pub fill_mode_non_solid: bool,
+    pub geometry_shader: bool,
+    pub tessellation_shader: bool,
+    pub vertex_pipeline_stores_and_atomics: bool,
+    pub fragment_stores_and_atomics: bool,
+    pub multi_draw_indirect: bool,
+    pub sampler_anisotropy: bool,
+    pub texture_compression_bc: bool,
+    pub texture_compression_astc_ldr: bool,
+    pub descriptor_indexing: bool,
+    pub timeline_semaphore: bool,
+    pub synchronization2: bool,
+    pub dynamic_rendering: bool,
+    pub buffer_device_address: bool,
+    pub acceleration_structure: bool,
+    pub ray_tracing_pipeline: bool,
+    pub ray_query: bool,
+    pub device_fault: bool,
+    pub mesh_shader: bool,
+}
+
+impl EnabledFeatures {
+    fn flag_mut(&mut self, feature: DeviceFeature) -> &mut bool {
+        match feature {
+            DeviceFeature::PipelineStatisticsQuery => &mut self.pipeline_statistics_query,
+            DeviceFeature::FillModeNonSolid => &mut self.fill_mode_non_solid,
+            DeviceFeature::GeometryShader => &mut self.geometry_shader,
+            DeviceFeature::TessellationShader => &mut self.tessellation_shader,
+            DeviceFeature::VertexPipelineStoresAndAtomics => {
+                &mut self.vertex_pipeline_stores_and_atomics
+            }
+            DeviceFeature::FragmentStoresAndAtomics => &mut self.fragment_stores_and_atomics,
+            DeviceFeature::MultiDrawIndirect => &mut self.multi_draw_indirect,
+            DeviceFeature::SamplerAnisotropy => &mut self.sampler_anisotropy,
+            DeviceFeature::TextureCompressionBc => &mut self.texture_compression_bc,
+            DeviceFeature::TextureCompressionAstcLdr => &mut self.texture_compression_astc_ldr,
+            DeviceFeature::DescriptorIndexing => &mut self.descriptor_indexing,
+            DeviceFeature::TimelineSemaphore => &mut self.timeline_semaphore,
+            DeviceFeature::Synchronization2 => &mut self.synchronization2,
+            DeviceFeature::DynamicRendering => &mut self.dynamic_rendering,
+            DeviceFeature::BufferDeviceAddress => &mut self.buffer_device_address,
+            DeviceFeature::AccelerationStructure => &mut self.acceleration_structure,
+            DeviceFeature::RayTracingPipeline => &mut self.ray_tracing_pipeline,
+            DeviceFeature::RayQuery => &mut self.ray_query,
+            DeviceFeature::DeviceFault => &mut self.device_fault,
+            DeviceFeature::MeshShader => &mut self.mesh_shader,
+        }
+    }
+}
+
+//get_physical_device_features2で読む時と、DeviceCreateInfoのpNextに繋ぐ時に使う構造体
+//featuresのp_nextが他のフィールドを指すので、Boxに入れて動かないようにしておく
+#[derive(Default)]
+struct FeatureChain {
+    features: vk::PhysicalDeviceFeatures2,
+    descriptor_indexing: vk::PhysicalDeviceDescriptorIndexingFeatures,
+    timeline_semaphore: vk::PhysicalDeviceTimelineSemaphoreFeatures,
+    synchronization2: vk::PhysicalDeviceSynchronization2Features,
+    dynamic_rendering: vk::PhysicalDeviceDynamicRenderingFeatures,
+    buffer_device_address: vk::PhysicalDeviceBufferDeviceAddressFeatures,
+    acceleration_structure: vk::PhysicalDeviceAccelerationStructureFeaturesKHR,
+    ray_tracing_pipeline: vk::PhysicalDeviceRayTracingPipelineFeaturesKHR,
+    ray_query: vk::PhysicalDeviceRayQueryFeaturesKHR,
+    device_fault: PhysicalDeviceFaultFeatures,
+    mesh_shader: vk::PhysicalDeviceMeshShaderFeaturesEXT,
+}
+
+impl FeatureChain {
+    //featuresに必要な構造体だけを繋ぐ、サポートされていない拡張の構造体を繋ぐと検証レイヤーがエラーを出す
+    fn new(features: &[DeviceFeature]) -> Box<Self> {
+        let mut chain = Box::<Self>::default();
+        let uses = |feature| features.contains(&feature);
+
+        let mut builder = vk::PhysicalDeviceFeatures2::builder();
+
+        if uses(DeviceFeature::DescriptorIndexing) {
+            builder = builder.push_next(&mut chain.descriptor_indexing);
+        }
+        if uses(DeviceFeature::TimelineSemaphore) {
+            builder = builder.push_next(&mut chain.timeline_semaphore);
+        }
+        if uses(DeviceFeature::Synchronization2) {
+            builder = builder.push_next(&mut chain.synchronization2);
+        }
+        if uses(DeviceFeature::DynamicRendering) {
+            builder = builder.push_next(&mut chain.dynamic_rendering);
+        }
+        if uses(DeviceFeature::BufferDeviceAddress) {
+            builder = builder.push_next(&mut chain.buffer_device_address);
+        }
+        if uses(DeviceFeature::AccelerationStructure) {
+            builder = builder.push_next(&mut chain.acceleration_structure);
+        }
+        if uses(DeviceFeature::RayTracingPipeline) {
+            builder = builder.push_next(&mut chain.ray_tracing_pipeline);
+        }
+        if uses(DeviceFeature::RayQuery) {
+            builder = builder.push_next(&mut chain.ray_query);
+        }
+        if uses(DeviceFeature::DeviceFault) {
+            builder = builder.push_next(&mut chain.device_fault);
+        }
+        if uses(DeviceFeature::MeshShader) {
+            builder = builder.push_next(&mut chain.mesh_shader);
+        }
+
+        let features = builder.build();
+        chain.features = features;
+        chain
+    }
+
+    fn is_enabled(&self, feature: DeviceFeature) -> bool {
+        let core = &self.features.features;
+        let indexing = &self.descriptor_indexing;
+
+        let flags = match feature {
+            DeviceFeature::PipelineStatisticsQuery => vec![core.pipeline_statistics_query],
+            DeviceFeature::FillModeNonSolid => vec![core.fill_mode_non_solid],
+            DeviceFeature::GeometryShader => vec![core.geometry_shader],
+            DeviceFeature::TessellationShader => vec![core.tessellation_shader],
+            DeviceFeature::VertexPipelineStoresAndAtomics => {
+                vec![core.vertex_pipeline_stores_and_atomics]
+            }
+            DeviceFeature::FragmentStoresAndAtomics => vec![core.fragment_stores_and_atomics],
+            DeviceFeature::MultiDrawIndirect => vec![core.multi_draw_indirect],
+            DeviceFeature::SamplerAnisotropy => vec![core.sampler_anisotropy],
+            DeviceFeature::TextureCompressionBc => vec![core.texture_compression_bc],
+            DeviceFeature::TextureCompressionAstcLdr => vec![core.texture_compression_astc_ldr],
+            DeviceFeature::DescriptorIndexing => vec![
+                core.shader_sampled_image_array_dynamic_indexing,
+                indexing.runtime_descriptor_array,
+                indexing.descriptor_binding_partially_bound,
+                indexing.descriptor_binding_variable_descriptor_count,
+                indexing.shader_sampled_image_array_non_uniform_indexing,
+            ],
+            DeviceFeature::TimelineSemaphore => vec![self.timeline_semaphore.timeline_semaphore],
+            DeviceFeature::Synchronization2 => vec![self.synchronization2.synchronization2],
+            DeviceFeature::DynamicRendering => vec![self.dynamic_rendering.dynamic_rendering],
+            DeviceFeature::BufferDeviceAddress => {
+                vec![self.buffer_device_address.buffer_device_address]
+            }
+            DeviceFeature::AccelerationStructure => {
+                vec![self.acceleration_structure.acceleration_structure]
+            }
+            DeviceFeature::RayTracingPipeline => {
+                vec![self.ray_tracing_pipeline.ray_tracing_pipeline]
+            }
+            DeviceFeature::RayQuery => vec![self.ray_query.ray_query],
+            DeviceFeature::DeviceFault => vec![self.device_fault.device_fault],
+            DeviceFeature::MeshShader => {
+                vec![self.mesh_shader.task_shader, self.mesh_shader.mesh_shader]
+            }
+        };
+
+        flags.into_iter().all(|flag| flag == vk::TRUE)
+    }
+
+    fn enable(&mut self, feature: DeviceFeature) {
+        let core = &mut self.features.features;
+        let indexing = &mut self.descriptor_indexing;
+
+        match feature {
+            DeviceFeature::PipelineStatisticsQuery => core.pipeline_statistics_query = vk::TRUE,
+            DeviceFeature::FillModeNonSolid => core.fill_mode_non_solid = vk::TRUE,
+            DeviceFeature::GeometryShader => core.geometry_shader = vk::TRUE,
+            DeviceFeature::TessellationShader => core.tessellation_shader = vk::TRUE,
+            DeviceFeature::VertexPipelineStoresAndAtomics => {
+                core.vertex_pipeline_stores_and_atomics = vk::TRUE
+            }
+            DeviceFeature::FragmentStoresAndAtomics => core.fragment_stores_and_atomics = vk::TRUE,
+            DeviceFeature::MultiDrawIndirect => core.multi_draw_indirect = vk::TRUE,
+            DeviceFeature::SamplerAnisotropy => core.sampler_anisotropy = vk::TRUE,
+            DeviceFeature::TextureCompressionBc => core.texture_compression_bc = vk::TRUE,
+            DeviceFeature::TextureCompressionAstcLdr => {
+                core.texture_compression_astc_ldr = vk::TRUE
+            }
+            DeviceFeature::DescriptorIndexing => {
+                core.shader_sampled_image_array_dynamic_indexing = vk::TRUE;
+                indexing.runtime_descriptor_array = vk::TRUE;
+                indexing.descriptor_binding_partially_bound = vk::TRUE;
+                indexing.descriptor_binding_variable_descriptor_count = vk::TRUE;
+                indexing.shader_sampled_image_array_non_uniform_indexing = vk::TRUE;
+            }
+            DeviceFeature::TimelineSemaphore => {
+                self.timeline_semaphore.timeline_semaphore = vk::TRUE
+            }
+            DeviceFeature::Synchronization2 => self.synchronization2.synchronization2 = vk::TRUE,
+            DeviceFeature::DynamicRendering => self.dynamic_rendering.dynamic_rendering = vk::TRUE,
+            DeviceFeature::BufferDeviceAddress => {
+                self.buffer_device_address.buffer_device_address = vk::TRUE
+            }
+            DeviceFeature::AccelerationStructure => {
+                self.acceleration_structure.acceleration_structure = vk::TRUE
+            }
+            DeviceFeature::RayTracingPipeline => {
+                self.ray_tracing_pipeline.ray_tracing_pipeline = vk::TRUE
+            }
+            DeviceFeature::RayQuery => self.ray_query.ray_query = vk::TRUE,
+            DeviceFeature::DeviceFault => self.device_fault.device_fault = vk::TRUE,
+            DeviceFeature::MeshShader => {
+                self.mesh_shader.task_shader = vk::TRUE;
+                self.mesh_shader.mesh_shader = vk::TRUE;
+            }
+        }
+    }
+}
+
+//論理デバイスで有効にする機能
+//requireした機能がサポートされていない場合はエラーにし、optionalな機能はサポートされている場合だけ有効にする
+#[derive(Clone, Debug, Default)]
+pub struct DeviceFeatureRequest {
+    required: Vec<DeviceFeature>,
+    optional: Vec<DeviceFeature>,
+}
+
+impl DeviceFeatureRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn require(mut self, feature: DeviceFeature) -> Self {
+        self.required.push(feature);
+        self
+    }
+
+    pub fn optional(mut self, feature: DeviceFeature) -> Self {
+        self.optional.push(feature);
+        self
+    }
+
+    //get_physical_device_features2でサポートを確認し、有効にする機能を決める
+    pub fn resolve(
+        &self,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> Result<DeviceFeatureChain, String> {
+        let features = self
+            .required
+            .iter()
+            .chain(&self.optional)
+            .copied()
+            .collect::<Vec<_>>();
+
+        let mut supported = FeatureChain::new(&features);
+
+        unsafe { instance.get_physical_device_features2(physical_device, &mut supported.features) };
+
+        let missing = self
+            .required
+            .iter()
+            .filter(|&&feature| !supported.is_enabled(feature))
+            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            return Err(format!(
+                "Required device features are not supported: {:?}",
+                missing
+            ));
+        }
+
+        let mut chain = FeatureChain::new(&features);
+        let mut enabled = EnabledFeatures::default();
+
+        for &feature in &features {
+            if supported.is_enabled(feature) {
+                chain.enable(feature);
+                *enabled.flag_mut(feature) = true;
+            }
+        }
+
+        let unsupported = self
+            .optional
+            .iter()
+            .filter(|&&feature| !supported.is_enabled(feature))
+            .collect::<Vec<_>>();
+
+        log::info!("Enabled device features: {:?}", enabled);
+
+        if !unsupported.is_empty() {
+            log::info!("Optional device features not supported: {:?}", unsupported);
+        }
+
+        Ok(DeviceFeatureChain { chain, enabled })
+    }
+}
+
+//DeviceFeatureRequest::resolveで決めた機能と、DeviceCreateInfoに繋ぐ構造体
+pub struct DeviceFeatureChain {
+    chain: Box<FeatureChain>,
+    enabled: EnabledFeatures,
+}
+
+impl DeviceFeatureChain {
+    pub fn enabled(&self) -> EnabledFeatures {
+        self.enabled
+    }
+
+    //DeviceCreateInfoにpush_nextする、enabled_featuresは同時に指定してはいけない
+    //create_deviceを呼ぶまでselfを残しておく
+    pub fn features2(&mut self) -> &mut vk::PhysicalDeviceFeatures2 {
+        &mut self.chain.features
+    }
+}
@@ -0,0 +1,54 @@
+use ash::{vk, Instance};
+use std::ffi::CStr;
+
+//PCI SIGで割り当てられたベンダーID
+const VENDOR_ID_NVIDIA: u32 = 0x10DE;
+const VENDOR_ID_INTEL: u32 = 0x8086;
+
+//VK_MAKE_API_VERSIONでパックされたバージョンを読める形にする
+pub fn api_version_string(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        vk::api_version_major(version),
+        vk::api_version_minor(version),
+        vk::api_version_patch(version)
+    )
+}
+
+//driver_versionのパック方法はベンダーごとに異なる
+//NVIDIAは10.8.8.6bit、Windows上のIntelは18.14bitでそれ以外はVulkanのパック方法に従うことが多い
+pub fn driver_version_string(properties: &vk::PhysicalDeviceProperties) -> String {
+    let version = properties.driver_version;
+
+    match properties.vendor_id {
+        VENDOR_ID_NVIDIA => format!(
+            "{}.{}.{}.{}",
+            (version >> 22) & 0x3ff,
+            (version >> 14) & 0xff,
+            (version >> 6) & 0xff,
+            version & 0x3f
+        ),
+        VENDOR_ID_INTEL if cfg!(target_os = "windows") => {
+            format!("{}.{}", version >> 14, version & 0x3fff)
+        }
+        _ => api_version_string(version),
+    }
+}
+
+//インスタンスとデバイスの両方が対応しているバージョン、デバイスの機能はこのバージョンまでしか使えない
+//デバイスのapi_versionはインスタンスのバージョンより低いことがある
+pub fn effective_api_version(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    instance_api_version: u32,
+) -> u32 {
+    let props = unsafe { instance.get_physical_device_properties(physical_device) };
+
+    props.api_version.min(instance_api_version)
+}
+
+pub fn device_name(properties: &vk::PhysicalDeviceProperties) -> String {
+    unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,496 @@
+use crate::device_info;
+use crate::queue_family::QueueFamilyIndices;
+use crate::swap_chain_utils::SwapChainSupportDetails;
+use crate::vulkan_app::VulkanApp;
+use ash::extensions::khr::Surface;
+use ash::{vk, Instance};
+use std::error::Error;
+use std::ffi::CStr;
+use winit::event_loop::EventLoop;
+use winit::window::WindowBuilder;
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+//--infoで表示する形式、--jsonを付けるとツールで読めるようにJSONで出力する
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Text,
+    Json,
+}
+
+struct QueueFamilyReport {
+    flags: vk::QueueFlags,
+    queue_count: u32,
+    timestamp_valid_bits: u32,
+    //surfaceを作れなかった場合はNone
+    present: Option<bool>,
+}
+
+struct SurfaceReport {
+    min_image_count: u32,
+    //0は上限が無いことを表す
+    max_image_count: u32,
+    formats: Vec<vk::SurfaceFormatKHR>,
+    present_modes: Vec<vk::PresentModeKHR>,
+    //このアプリケーションで使えるかどうか、pick_physical_deviceと同じ判定
+    suitable: bool,
+}
+
+struct DeviceReport {
+    properties: vk::PhysicalDeviceProperties,
+    queue_families: Vec<QueueFamilyReport>,
+    memory_heaps: Vec<vk::MemoryHeap>,
+    memory_types: Vec<vk::MemoryType>,
+    extensions: Vec<String>,
+    surface: Option<SurfaceReport>,
+    score: usize,
+}
+
+impl DeviceReport {
+    fn new(
+        instance: &Instance,
+        surface: Option<(&Surface, vk::SurfaceKHR)>,
+        physical_device: vk::PhysicalDevice,
+        instance_api_version: u32,
+    ) -> Result<Self, Box<dyn Error>> {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let memory_properties =
+            unsafe { instance.get_physical_device_memory_properties(physical_device) };
+
+        let queue_families =
+            unsafe { instance.get_physical_device_queue_family_properties(physical_device) }
+                .iter()
+                .enumerate()
+                .map(|(index, family)| QueueFamilyReport {
+                    flags: family.queue_flags,
+                    queue_count: family.queue_count,
+                    timestamp_valid_bits: family.timestamp_valid_bits,
+                    present: surface.map(|(surface, surface_khr)| unsafe {
+                        surface
+                            .get_physical_device_surface_support(
+                                physical_device,
+                                index as u32,
+                                surface_khr,
+                            )
+                            .unwrap_or(false)
+                    }),
+                })
+                .collect();
+
+        let mut extensions =
+            unsafe { instance.enumerate_device_extension_properties(physical_device)? }
+                .iter()
+                .map(|extension| {
+                    unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) }
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect::<Vec<_>>();
+        extensions.sort();
+
+        let surface = surface.map(|(surface, surface_khr)| {
+            let details = SwapChainSupportDetails::new(physical_device, surface, surface_khr);
+
+            SurfaceReport {
+                min_image_count: details.capabilities.min_image_count,
+                max_image_count: details.capabilities.max_image_count,
+                formats: details.formats,
+                present_modes: details.present_modes,
+                suitable: QueueFamilyIndices::is_device_suitable(
+                    instance,
+                    surface,
+                    surface_khr,
+                    physical_device,
+                    instance_api_version,
+                ),
+            }
+        });
+
+        Ok(Self {
+            properties,
+            queue_families,
+            memory_heaps: memory_properties.memory_heaps
+                [..memory_properties.memory_heap_count as usize]
+                .to_vec(),
+            memory_types: memory_properties.memory_types
+                [..memory_properties.memory_type_count as usize]
+                .to_vec(),
+            extensions,
+            surface,
+            score: QueueFamilyIndices::rate_device_suitability(instance, physical_device),
+        })
+    }
+
+    fn limits(&self) -> [(&'static str, String); 3] {
+        let limits = &self.properties.limits;
+
+        [
+            (
+                "max_image_dimension2_d",
+                limits.max_image_dimension2_d.to_string(),
+            ),
+            (
+                "max_push_constants_size",
+                limits.max_push_constants_size.to_string(),
+            ),
+            (
+                "max_sampler_anisotropy",
+                limits.max_sampler_anisotropy.to_string(),
+            ),
+        ]
+    }
+
+    fn text(&self, index: usize) -> String {
+        let properties = &self.properties;
+        let mut lines = vec![
+            format!(
+                "Device {}: {} ({:?})",
+                index,
+                device_info::device_name(properties),
+                properties.device_type
+            ),
+            format!(
+                "  vendor id: 0x{:04X}, device id: 0x{:04X}",
+                properties.vendor_id, properties.device_id
+            ),
+            format!(
+                "  api version: {}, driver version: {}",
+                device_info::api_version_string(properties.api_version),
+                device_info::driver_version_string(properties)
+            ),
+            format!("  suitability score: {}", self.score),
+            "  queue families:".to_string(),
+        ];
+
+        for (index, family) in self.queue_families.iter().enumerate() {
+            let present = match family.present {
+                Some(true) => ", present",
+                _ => "",
+            };
+
+            lines.push(format!(
+                "    {}: {:?} x{}, timestamp bits {}{}",
+                index, family.flags, family.queue_count, family.timestamp_valid_bits, present
+            ));
+        }
+
+        lines.push("  memory heaps:".to_string());
+        for (index, heap) in self.memory_heaps.iter().enumerate() {
+            lines.push(format!(
+                "    {}: {:.1} MiB {:?}",
+                index,
+                heap.size as f64 / MIB,
+                heap.flags
+            ));
+        }
+
+        lines.push("  memory types:".to_string());
+        for (index, memory_type) in self.memory_types.iter().enumerate() {
+            lines.push(format!(
+                "    {}: heap {} {:?}",
+                index, memory_type.heap_index, memory_type.property_flags
+            ));
+        }
+
+        lines.push("  limits:".to_string());
+        for (name, value) in self.limits() {
+            lines.push(format!("    {}: {}", name, value));
+        }
+
+        match &self.surface {
+            Some(surface) => {
+                lines.push(format!(
+                    "  surface: suitable: {}, image count {}..{}",
+                    surface.suitable,
+                    surface.min_image_count,
+                    match surface.max_image_count {
+                        0 => "unlimited".to_string(),
+                        count => count.to_string(),
+                    }
+                ));
+                lines.push("    formats:".to_string());
+                for format in &surface.formats {
+                    lines.push(format!(
+                        "      {:?} {:?}",
+                        format.format, format.color_space
+                    ));
+                }
+                lines.push(format!("    present modes: {:?}", surface.present_modes));
+            }
+            None => lines.push("  surface: not available".to_string()),
+        }
+
+        lines.push(format!("  extensions ({}):", self.extensions.len()));
+        for extension in &self.extensions {
+            lines.push(format!("    {}", extension));
+        }
+
+        lines.join("\n")
+    }
+
+    fn json(&self) -> Json {
+        let properties = &self.properties;
+
+        let queue_families = self
+            .queue_families
+            .iter()
+            .map(|family| {
+                Json::Object(vec![
+                    ("flags", Json::string(format!("{:?}", family.flags))),
+                    ("queue_count", Json::number(family.queue_count)),
+                    (
+                        "timestamp_valid_bits",
+                        Json::number(family.timestamp_valid_bits),
+                    ),
+                    ("present", family.present.map_or(Json::Null, Json::Bool)),
+                ])
+            })
+            .collect();
+
+        let memory_heaps = self
+            .memory_heaps
+            .iter()
+            .map(|heap| {
+                Json::Object(vec![
+                    ("size", Json::number(heap.size)),
+                    ("flags", Json::string(format!("{:?}", heap.flags))),
+                ])
+            })
+            .collect();
+
+        let memory_types = self
+            .memory_types
+            .iter()
+            .map(|memory_type| {
+                Json::Object(vec![
+                    ("heap_index", Json::number(memory_type.heap_index)),
+                    (
+                        "property_flags",
+                        Json::string(format!("{:?}", memory_type.property_flags)),
+                    ),
+                ])
+            })
+            .collect();
+
+        let limits = self
+            .limits()
+            .into_iter()
+            .map(|(name, value)| (name, Json::Number(value)))
+            .collect();
+
+        let surface = self.surface.as_ref().map_or(Json::Null, |surface| {
+            Json::Object(vec![
+                ("suitable", Json::Bool(surface.suitable)),
+                ("min_image_count", Json::number(surface.min_image_count)),
+                ("max_image_count", Json::number(surface.max_image_count)),
+                (
+                    "formats",
+                    Json::Array(
+                        surface
+                            .formats
+                            .iter()
+                            .map(|format| {
+                                Json::Object(vec![
+                                    ("format", Json::string(format!("{:?}", format.format))),
+                                    (
+                                        "color_space",
+                                        Json::string(format!("{:?}", format.color_space)),
+                                    ),
+                                ])
+                            })
+                            .collect(),
+                    ),
+                ),
+                (
+                    "present_modes",
+                    Json::Array(
+                        surface
+                            .present_modes
+                            .iter()
+                            .map(|mode| Json::string(format!("{:?}", mode)))
+                            .collect(),
+                    ),
+                ),
+            ])
+        });
+
+        Json::Object(vec![
+            ("name", Json::string(device_info::device_name(properties))),
+            (
+                "device_type",
+                Json::string(format!("{:?}", properties.device_type)),
+            ),
+            ("vendor_id", Json::number(properties.vendor_id)),
+            ("device_id", Json::number(properties.device_id)),
+            (
+                "api_version",
+                Json::string(device_info::api_version_string(properties.api_version)),
+            ),
+            (
+                "driver_version",
+                Json::string(device_info::driver_version_string(properties)),
+            ),
+            ("suitability_score", Json::number(self.score)),
+            ("queue_families", Json::Array(queue_families)),
+            ("memory_heaps", Json::Array(memory_heaps)),
+            ("memory_types", Json::Array(memory_types)),
+            ("limits", Json::Object(limits)),
+            ("surface", surface),
+            (
+                "extensions",
+                Json::Array(self.extensions.iter().cloned().map(Json::String).collect()),
+            ),
+        ])
+    }
+}
+
+//レポートを書き出すのに必要な分だけのJSON
+//数値は書き出す文字列のまま持つ
+enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    fn number(value: impl ToString) -> Self {
+        Self::Number(value.to_string())
+    }
+
+    fn string(value: impl Into<String>) -> Self {
+        Self::String(value.into())
+    }
+
+    //要素ごとに改行し、2スペースで字下げする
+    fn write(&self, out: &mut String, indent: usize) {
+        match self {
+            Self::Null => out.push_str("null"),
+            Self::Bool(value) => out.push_str(&value.to_string()),
+            Self::Number(value) => out.push_str(value),
+            Self::String(value) => write_string(out, value),
+            Self::Array(values) if values.is_empty() => out.push_str("[]"),
+            Self::Array(values) => {
+                out.push('[');
+                for (index, value) in values.iter().enumerate() {
+                    out.push_str(if index == 0 { "\n" } else { ",\n" });
+                    push_indent(out, indent + 1);
+                    value.write(out, indent + 1);
+                }
+                out.push('\n');
+                push_indent(out, indent);
+                out.push(']');
+            }
+            Self::Object(fields) if fields.is_empty() => out.push_str("{}"),
+            Self::Object(fields) => {
+                out.push('{');
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    out.push_str(if index == 0 { "\n" } else { ",\n" });
+                    push_indent(out, indent + 1);
+                    write_string(out, key);
+                    out.push_str(": ");
+                    value.write(out, indent + 1);
+                }
+                out.push('\n');
+                push_indent(out, indent);
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn push_indent(out: &mut String, indent: usize) {
+    out.push_str(&"  ".repeat(indent));
+}
+
+fn write_string(out: &mut String, value: &str) {
+    out.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+}
+
+//--infoで呼ぶ、ウィンドウを表示せずに全ての物理デバイスの情報を標準出力に書き出す
+pub fn print(format: ReportFormat) -> Result<(), Box<dyn Error>> {
+    let (entry, instance_api_version) = VulkanApp::load_entry()?;
+    //検証レイヤーのログが出力に混ざらないように、検証レイヤーは有効にしない
+    let instance = VulkanApp::create_instance(&entry, instance_api_version, false, None, None)?;
+
+    let physical_devices = unsafe { instance.enumerate_physical_devices()? };
+
+    //surfaceに関する情報はsurfaceが無いと取れないので、表示しないウィンドウを作る
+    //surfaceを作れない場合はsurfaceに関する情報だけを除いて表示する
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_visible(false)
+        .build(&event_loop)?;
+    let surface = Surface::new(&entry, &instance);
+    let surface_khr = match unsafe { ash_window::create_surface(&entry, &instance, &window, None) }
+    {
+        Ok(surface_khr) => Some(surface_khr),
+        Err(error) => {
+            log::warn!(
+                "Failed to create a surface, surface support is not reported: {}",
+                error
+            );
+            None
+        }
+    };
+
+    let reports = physical_devices
+        .iter()
+        .map(|&physical_device| {
+            DeviceReport::new(
+                &instance,
+                surface_khr.map(|surface_khr| (&surface, surface_khr)),
+                physical_device,
+                instance_api_version,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>();
+
+    unsafe {
+        if let Some(surface_khr) = surface_khr {
+            surface.destroy_surface(surface_khr, None);
+        }
+        instance.destroy_instance(None);
+    }
+
+    let reports = reports?;
+
+    match format {
+        ReportFormat::Text => {
+            let texts = reports
+                .iter()
+                .enumerate()
+                .map(|(index, report)| report.text(index))
+                .collect::<Vec<_>>();
+
+            println!("{}", texts.join("\n\n"));
+        }
+        ReportFormat::Json => {
+            let mut out = String::new();
+
+            Json::Object(vec![(
+                "devices",
+                Json::Array(reports.iter().map(DeviceReport::json).collect()),
+            )])
+            .write(&mut out, 0);
+
+            println!("{}", out);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use ash::extensions::khr::Display;
+use ash::{vk, Entry, Instance};
+use log::info;
+use std::ffi::CStr;
+
+//--display <index> と --display-mode <index> で選ぶ出力と表示モード
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DisplayChoice {
+    //全ての物理デバイスのディスプレイを順番に並べた時のindex
+    pub display_index: usize,
+    //Noneの場合は一番大きく、その中でリフレッシュレートが一番高いモードを選ぶ
+    pub mode_index: Option<usize>,
+}
+
+//surfaceを作った表示モード
+#[derive(Clone, Copy, Debug)]
+pub struct DisplayMode {
+    pub extent: vk::Extent2D,
+    //ミリヘルツ
+    pub refresh_rate: u32,
+}
+
+impl DisplayMode {
+    //フレームレートの目標に使う、0の場合はNone
+    pub fn refresh_rate_hz(&self) -> Option<u32> {
+        match (self.refresh_rate + 500) / 1000 {
+            0 => None,
+            hz => Some(hz),
+        }
+    }
+}
+
+//VK_KHR_displayでウィンドウシステムを介さずにディスプレイのプレーンに直接表示するsurfaceを作る
+//instanceはVK_KHR_displayを有効にして作ってあること
+pub fn create_display_surface(
+    entry: &Entry,
+    instance: &Instance,
+    choice: DisplayChoice,
+) -> Result<(vk::SurfaceKHR, DisplayMode), String> {
+    let loader = Display::new(entry, instance);
+
+    let physical_devices =
+        unsafe { instance.enumerate_physical_devices() }.map_err(|error| error.to_string())?;
+
+    let displays = physical_devices
+        .iter()
+        .flat_map(|&physical_device| {
+            unsafe { loader.get_physical_device_display_properties(physical_device) }
+                .unwrap_or_default()
+                .into_iter()
+                .map(move |properties| (physical_device, properties))
+        })
+        .collect::<Vec<_>>();
+
+    for (index, (_, properties)) in displays.iter().enumerate() {
+        info!(
+            "display {}: {} ({}x{})",
+            index,
+            display_name(properties),
+            properties.physical_resolution.width,
+            properties.physical_resolution.height
+        );
+    }
+
+    let &(physical_device, properties) = displays.get(choice.display_index).ok_or_else(|| {
+        format!(
+            "Display {} was not found, {} displays are available",
+            choice.display_index,
+            displays.len()
+        )
+    })?;
+
+    if !properties
+        .supported_transforms
+        .contains(vk::SurfaceTransformFlagsKHR::IDENTITY)
+    {
+        return Err(format!(
+            "Display {} does not support the identity transform",
+            choice.display_index
+        ));
+    }
+
+    let modes = unsafe { loader.get_display_mode_properties(physical_device, properties.display) }
+        .map_err(|error| error.to_string())?;
+
+    for (index, mode) in modes.iter().enumerate() {
+        let parameters = mode.parameters;
+        info!(
+            "  mode {}: {}x{} @ {:.2}Hz",
+            index,
+            parameters.visible_region.width,
+            parameters.visible_region.height,
+            parameters.refresh_rate as f32 / 1000.0
+        );
+    }
+
+    let mode = match choice.mode_index {
+        Some(index) => modes
+            .get(index)
+            .copied()
+            .ok_or_else(|| format!("Display mode {} was not found", index))?,
+        None => modes
+            .iter()
+            .copied()
+            .max_by_key(|mode| {
+                let region = mode.parameters.visible_region;
+                (region.width * region.height, mode.parameters.refresh_rate)
+            })
+            .ok_or_else(|| "The display has no modes".to_string())?,
+    };
+
+    let (plane_index, stack_index) = find_plane(&loader, physical_device, properties.display)?;
+
+    let capabilities = unsafe {
+        loader.get_display_plane_capabilities(physical_device, mode.display_mode, plane_index)
+    }
+    .map_err(|error| error.to_string())?;
+
+    //ウィンドウと同じくアルファは無視して表示する
+    if !capabilities
+        .supported_alpha
+        .contains(vk::DisplayPlaneAlphaFlagsKHR::OPAQUE)
+    {
+        return Err(format!("Display plane {} cannot be opaque", plane_index));
+    }
+
+    let extent = mode.parameters.visible_region;
+
+    let create_info = vk::DisplaySurfaceCreateInfoKHR::builder()
+        .display_mode(mode.display_mode)
+        .plane_index(plane_index)
+        .plane_stack_index(stack_index)
+        .transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
+        .global_alpha(1.0)
+        .alpha_mode(vk::DisplayPlaneAlphaFlagsKHR::OPAQUE)
+        .image_extent(extent)
+        .build();
+
+    let surface_khr = unsafe { loader.create_display_plane_surface(&create_info, None) }
+        .map_err(|error| error.to_string())?;
+
+    Ok((
+        surface_khr,
+        DisplayMode {
+            extent,
+            refresh_rate: mode.parameters.refresh_rate,
+        },
+    ))
+}
+
+//displayに表示でき、他のディスプレイで使われていないプレーンのindexとそのスタックの位置
+fn find_plane(
+    loader: &Display,
+    physical_device: vk::PhysicalDevice,
+    display: vk::DisplayKHR,
+) -> Result<(u32, u32), String> {
+    let planes = unsafe { loader.get_physical_device_display_plane_properties(physical_device) }
+        .map_err(|error| error.to_string())?;
+
+    for (index, plane) in planes.iter().enumerate() {
+        if plane.current_display != vk::DisplayKHR::null() && plane.current_display != display {
+            continue;
+        }
+
+        let supported_displays =
+            unsafe { loader.get_display_plane_supported_displays(physical_device, index as u32) }
+                .unwrap_or_default();
+
+        if supported_displays.contains(&display) {
+            return Ok((index as u32, plane.current_stack_index));
+        }
+    }
+
+    Err("No display plane can show the display".to_string())
+}
+
+fn display_name(properties: &vk::DisplayPropertiesKHR) -> String {
+    if properties.display_name.is_null() {
+        return "unknown".to_string();
+    }
+
+    unsafe { CStr::from_ptr(properties.display_name) }
+        .to_string_lossy()
+        .into_owned()
+}
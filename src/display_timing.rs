@@ -0,0 +1,132 @@
+use ash::extensions::google::DisplayTiming;
+use ash::vk::RefreshCycleDurationGOOGLE;
+use ash::{vk, Device, Instance};
+use log::info;
+
+//VK_GOOGLE_display_timingを使って表示タイミングを指定し、実際に表示された時刻との差を計測する
+//拡張がサポートされていないデバイスではそもそも作成しない
+pub struct FramePacer {
+    display_timing: DisplayTiming,
+    //ディスプレイのリフレッシュ間隔(ナノ秒)
+    refresh_duration: u64,
+    //queue_presentごとに増やすID、フィードバックと紐づけるために使う
+    next_present_id: u32,
+    //最後にフィードバックで受け取った表示情報
+    last_feedback: Option<vk::PastPresentationTimingGOOGLE>,
+    late_frames: u64,
+    dropped_frames: u64,
+}
+
+impl FramePacer {
+    pub fn new(instance: &Instance, device: &Device, swap_chain_khr: vk::SwapchainKHR) -> Self {
+        let mut frame_pacer = Self {
+            display_timing: DisplayTiming::new(instance, device),
+            refresh_duration: 0,
+            next_present_id: 1,
+            last_feedback: None,
+            late_frames: 0,
+            dropped_frames: 0,
+        };
+
+        frame_pacer.on_swap_chain_created(swap_chain_khr);
+
+        frame_pacer
+    }
+
+    //リフレッシュ間隔はswapchainごとに問い合わせる必要がある
+    pub fn on_swap_chain_created(&mut self, swap_chain_khr: vk::SwapchainKHR) {
+        let refresh_cycle: RefreshCycleDurationGOOGLE = unsafe {
+            self.display_timing
+                .get_refresh_cycle_duration(swap_chain_khr)
+                .unwrap_or_default()
+        };
+
+        self.refresh_duration = refresh_cycle.refresh_duration;
+        self.last_feedback = None;
+
+        info!(
+            "display timing: refresh duration {:.3} ms",
+            self.refresh_duration as f64 / 1_000_000.0
+        );
+    }
+
+    //次のqueue_presentに渡す表示時刻
+    //前回実際に表示された時刻からリフレッシュ間隔ごとに1枚ずつ表示されるように指定する
+    pub fn next_present_time(&mut self) -> vk::PresentTimeGOOGLE {
+        let present_id = self.next_present_id;
+        self.next_present_id = self.next_present_id.wrapping_add(1);
+
+        //0は時刻の指定なしを意味する
+        let desired_present_time = match self.last_feedback {
+            Some(feedback) if self.refresh_duration > 0 => {
+                let frames_ahead = present_id.wrapping_sub(feedback.present_id) as u64;
+                feedback.actual_present_time + self.refresh_duration * frames_ahead
+            }
+            _ => 0,
+        };
+
+        vk::PresentTimeGOOGLE {
+            present_id,
+            desired_present_time,
+        }
+    }
+
+    //queue_presentの後に呼び、表示の終わったフレームの遅延やフレーム落ちを集計する
+    pub fn collect_feedback(&mut self, swap_chain_khr: vk::SwapchainKHR) {
+        let timings = match unsafe {
+            self.display_timing
+                .get_past_presentation_timing(swap_chain_khr)
+        } {
+            Ok(timings) => timings,
+            Err(error) => {
+                log::warn!("Failed to get past presentation timing: {}", error);
+                return;
+            }
+        };
+
+        for timing in timings {
+            //指定した時刻から半フレーム以上遅れていれば遅延とみなす
+            if timing.desired_present_time != 0
+                && timing.actual_present_time
+                    > timing.desired_present_time + self.refresh_duration / 2
+            {
+                self.late_frames += 1;
+                log::debug!(
+                    "late frame: id {}, {:.3} ms late",
+                    timing.present_id,
+                    (timing.actual_present_time - timing.desired_present_time) as f64 / 1_000_000.0
+                );
+            }
+
+            //連続したフレームの間にリフレッシュ間隔以上の空きがあればその分フレームを落としている
+            if let Some(last) = self.last_feedback {
+                if self.refresh_duration > 0 && timing.present_id == last.present_id.wrapping_add(1)
+                {
+                    let elapsed = timing
+                        .actual_present_time
+                        .saturating_sub(last.actual_present_time);
+                    let skipped = (elapsed + self.refresh_duration / 2) / self.refresh_duration;
+
+                    if skipped > 1 {
+                        self.dropped_frames += skipped - 1;
+                        log::debug!(
+                            "dropped {} frame(s) before id {}",
+                            skipped - 1,
+                            timing.present_id
+                        );
+                    }
+                }
+            }
+
+            self.last_feedback = Some(timing);
+        }
+    }
+
+    pub fn late_frames(&self) -> u64 {
+        self.late_frames
+    }
+
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames
+    }
+}
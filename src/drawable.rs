@@ -0,0 +1,268 @@
+use crate::mesh::Mesh;
+use crate::transparency::{BlendMode, DrawCall};
+use ash::vk::Handle;
+use ash::{vk, Device};
+
+//描画に使うパイプラインと、それと一緒にset = 0に紐づけるDescriptor Set
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Material {
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub descriptor_set: vk::DescriptorSet,
+}
+
+//1回分の描画に使うメッシュとマテリアル
+//モデル行列はdraw_callのobject_indexが指すObjectBuffersに書き込んである
+#[derive(Clone, Copy)]
+pub struct Drawable<'a> {
+    pub mesh: &'a Mesh,
+    pub material: Material,
+    pub draw_call: DrawCall,
+}
+
+//不透明な物を同じパイプライン、同じDescriptor Set、同じメッシュ同士で隣り合うように並べ替える
+//drawablesはtransparency::sort_draw_callsの順番に並んでいること
+//半透明な物は奥から順に描画する必要があるので並べ替えない
+pub fn group_by_material(drawables: &mut [Drawable]) {
+    let opaque_count =
+        drawables.partition_point(|drawable| drawable.draw_call.blend_mode == BlendMode::Opaque);
+
+    //sort_by_keyは安定ソートなので同じマテリアルとメッシュの物は元の順番が保たれる
+    drawables[..opaque_count].sort_by_key(|drawable| {
+        (
+            drawable.material.pipeline.as_raw(),
+            drawable.material.descriptor_set.as_raw(),
+            drawable.mesh as *const Mesh as usize,
+        )
+    });
+}
+
+//直前に紐づけたパイプラインとset = 0とメッシュを覚えておき、変わった時だけ紐づけるコマンドを記録する
+#[derive(Default)]
+pub struct BindState<'a> {
+    pipeline: Option<vk::Pipeline>,
+    descriptor_set: Option<(vk::PipelineLayout, vk::DescriptorSet)>,
+    mesh: Option<&'a Mesh>,
+    //同じ物が紐づいていたので記録せずに済んだ紐づけの数
+    avoided_binds: usize,
+}
+
+impl<'a> BindState<'a> {
+    //set = 0を紐づけ直した場合はtrueを返す
+    //パイプラインレイアウトが変わるとset = 1以降も紐づけ直す必要があるので、呼び出し側はtrueの時にそれらを紐づける
+    pub fn bind_material(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        material: Material,
+    ) -> bool {
+        if self.pipeline == Some(material.pipeline) {
+            self.avoided_binds += 1;
+        } else {
+            unsafe {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    material.pipeline,
+                );
+            }
+            self.pipeline = Some(material.pipeline);
+        }
+
+        let descriptor_set = (material.pipeline_layout, material.descriptor_set);
+
+        if self.descriptor_set == Some(descriptor_set) {
+            self.avoided_binds += 1;
+            return false;
+        }
+
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                material.pipeline_layout,
+                0,
+                &[material.descriptor_set],
+                &[],
+            );
+        }
+        self.descriptor_set = Some(descriptor_set);
+
+        true
+    }
+
+    pub fn bind_mesh(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        mesh: &'a Mesh,
+    ) {
+        if self.mesh.map_or(false, |bound| std::ptr::eq(bound, mesh)) {
+            self.avoided_binds += 1;
+            return;
+        }
+
+        mesh.cmd_bind(device, command_buffer);
+        self.mesh = Some(mesh);
+    }
+
+    pub fn avoided_binds(&self) -> usize {
+        self.avoided_binds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transparency;
+
+    fn material(pipeline: u64, descriptor_set: u64) -> Material {
+        Material {
+            pipeline: vk::Pipeline::from_raw(pipeline),
+            pipeline_layout: vk::PipelineLayout::from_raw(1),
+            descriptor_set: vk::DescriptorSet::from_raw(descriptor_set),
+        }
+    }
+
+    fn drawable(
+        mesh: &Mesh,
+        material: Material,
+        blend_mode: BlendMode,
+        object_index: usize,
+        view_depth: f32,
+    ) -> Drawable {
+        Drawable {
+            mesh,
+            material,
+            draw_call: DrawCall {
+                blend_mode,
+                object_index,
+                view_depth,
+            },
+        }
+    }
+
+    fn object_indices(drawables: &[Drawable]) -> Vec<usize> {
+        drawables
+            .iter()
+            .map(|drawable| drawable.draw_call.object_index)
+            .collect()
+    }
+
+    #[test]
+    fn opaque_draws_are_grouped_by_pipeline_set_and_mesh() {
+        let meshes = [Mesh::placeholder(), Mesh::placeholder()];
+        let (a, b) = (material(10, 100), material(20, 100));
+        let a_other_set = material(10, 200);
+
+        let mut drawables = vec![
+            drawable(&meshes[0], b, BlendMode::Opaque, 0, 0.0),
+            drawable(&meshes[1], a, BlendMode::Opaque, 1, 0.0),
+            drawable(&meshes[0], a_other_set, BlendMode::Opaque, 2, 0.0),
+            drawable(&meshes[0], a, BlendMode::Opaque, 3, 0.0),
+            drawable(&meshes[0], b, BlendMode::Opaque, 4, 0.0),
+            drawable(&meshes[1], a, BlendMode::Opaque, 5, 0.0),
+        ];
+
+        group_by_material(&mut drawables);
+
+        //パイプライン、Descriptor Set、メッシュの順に比べて隣り合わせる
+        let keys = drawables
+            .iter()
+            .map(|drawable| {
+                (
+                    drawable.material.pipeline.as_raw(),
+                    drawable.material.descriptor_set.as_raw(),
+                    drawable.mesh as *const Mesh as usize,
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut sorted = keys.clone();
+        sorted.sort_unstable();
+        assert_eq!(keys, sorted);
+
+        //同じキーの物は元の順番のまま
+        let mesh_1_with_a = drawables
+            .iter()
+            .filter(|drawable| drawable.material == a && std::ptr::eq(drawable.mesh, &meshes[1]))
+            .map(|drawable| drawable.draw_call.object_index)
+            .collect::<Vec<_>>();
+        assert_eq!(mesh_1_with_a, [1, 5]);
+
+        let with_b = drawables
+            .iter()
+            .filter(|drawable| drawable.material == b)
+            .map(|drawable| drawable.draw_call.object_index)
+            .collect::<Vec<_>>();
+        assert_eq!(with_b, [0, 4]);
+    }
+
+    #[test]
+    fn transparent_draws_keep_back_to_front_order() {
+        let mesh = Mesh::placeholder();
+        let (a, b) = (material(10, 100), material(20, 100));
+
+        let mut draw_calls = vec![
+            DrawCall {
+                blend_mode: BlendMode::Transparent,
+                object_index: 0,
+                view_depth: -1.0,
+            },
+            DrawCall {
+                blend_mode: BlendMode::Opaque,
+                object_index: 1,
+                view_depth: -3.0,
+            },
+            DrawCall {
+                blend_mode: BlendMode::Transparent,
+                object_index: 2,
+                view_depth: -5.0,
+            },
+            DrawCall {
+                blend_mode: BlendMode::Opaque,
+                object_index: 3,
+                view_depth: -2.0,
+            },
+        ];
+        transparency::sort_draw_calls(&mut draw_calls);
+
+        //半透明な物はマテリアルが交互でもまとめない
+        let mut drawables = draw_calls
+            .iter()
+            .map(|draw_call| {
+                let material = if draw_call.object_index % 2 == 0 {
+                    a
+                } else {
+                    b
+                };
+                drawable(
+                    &mesh,
+                    material,
+                    draw_call.blend_mode,
+                    draw_call.object_index,
+                    draw_call.view_depth,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        group_by_material(&mut drawables);
+
+        assert_eq!(object_indices(&drawables), [1, 3, 2, 0]);
+    }
+
+    #[test]
+    fn empty_and_all_transparent_lists_are_untouched() {
+        let mesh = Mesh::placeholder();
+
+        let mut empty: Vec<Drawable> = vec![];
+        group_by_material(&mut empty);
+        assert!(empty.is_empty());
+
+        let mut drawables = vec![
+            drawable(&mesh, material(20, 100), BlendMode::Transparent, 0, -5.0),
+            drawable(&mesh, material(10, 100), BlendMode::Transparent, 1, -1.0),
+        ];
+        group_by_material(&mut drawables);
+        assert_eq!(object_indices(&drawables), [0, 1]);
+    }
+}
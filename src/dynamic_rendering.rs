@@ -0,0 +1,109 @@
+use crate::queue_family::QueueFamilyIndices;
+use ash::extensions::khr;
+use ash::{vk, Device, Instance};
+use std::ffi::CStr;
+
+//デバイスがdynamic renderingをどの形で使えるか
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DynamicRenderingSupport {
+    //Vulkan 1.3のコア機能
+    Core,
+    //VK_KHR_dynamic_rendering
+    Extension,
+    Unsupported,
+}
+
+impl DynamicRenderingSupport {
+    //api_versionはdevice_info::effective_api_versionで決めたもの
+    pub fn query(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        api_version: u32,
+    ) -> Self {
+        let is_core = api_version >= vk::make_api_version(0, 1, 3, 0);
+        //機能の確認にVulkan 1.1のvkGetPhysicalDeviceFeatures2を使う
+        let is_extension = !is_core
+            && api_version >= vk::make_api_version(0, 1, 1, 0)
+            && QueueFamilyIndices::is_device_extension_supported(
+                instance,
+                physical_device,
+                khr::DynamicRendering::name(),
+            );
+
+        if !(is_core || is_extension) {
+            return Self::Unsupported;
+        }
+
+        let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+        let mut features = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut dynamic_rendering_features)
+            .build();
+
+        unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+
+        if dynamic_rendering_features.dynamic_rendering != vk::TRUE {
+            Self::Unsupported
+        } else if is_core {
+            Self::Core
+        } else {
+            Self::Extension
+        }
+    }
+
+    //DeviceCreateInfoで有効にする必要のある拡張
+    pub fn extension_name(self) -> Option<&'static CStr> {
+        match self {
+            Self::Extension => Some(khr::DynamicRendering::name()),
+            _ => None,
+        }
+    }
+}
+
+//render passとframebufferを作らずにimage viewへ直接描画する
+//render passと違ってレイアウトの遷移は自動で行われないので呼び出し側でバリアを張る
+pub struct DynamicRendering {
+    //コア機能の場合はNone
+    extension: Option<khr::DynamicRendering>,
+}
+
+impl DynamicRendering {
+    //サポートされていない場合はNone
+    pub fn new(
+        instance: &Instance,
+        device: &Device,
+        support: DynamicRenderingSupport,
+    ) -> Option<Self> {
+        let extension = match support {
+            DynamicRenderingSupport::Core => None,
+            DynamicRenderingSupport::Extension => {
+                Some(khr::DynamicRendering::new(instance, device))
+            }
+            DynamicRenderingSupport::Unsupported => return None,
+        };
+
+        Some(Self { extension })
+    }
+
+    pub fn cmd_begin_rendering(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        rendering_info: &vk::RenderingInfo,
+    ) {
+        unsafe {
+            match &self.extension {
+                Some(extension) => extension.cmd_begin_rendering(command_buffer, rendering_info),
+                None => device.cmd_begin_rendering(command_buffer, rendering_info),
+            }
+        }
+    }
+
+    pub fn cmd_end_rendering(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            match &self.extension {
+                Some(extension) => extension.cmd_end_rendering(command_buffer),
+                None => device.cmd_end_rendering(command_buffer),
+            }
+        }
+    }
+}
@@ -0,0 +1,765 @@
+use crate::one_time_commands::OneTimeCommands;
+use crate::staging_ring::StagingRing;
+use crate::swap_chain_utils::PresentModePreference;
+use crate::synchronization::Synchronization;
+use crate::texture::Texture2D;
+use ash::{vk, Device, Instance};
+use egui::epaint::Primitive;
+use egui::{ClippedPrimitive, ImageData, Pos2, Rect, TextureId, TexturesDelta};
+use std::collections::HashMap;
+use std::mem;
+use winit::event::{ElementState, KeyboardInput, WindowEvent};
+use winit::window::Window;
+
+//eguiの色とテクスチャはsRGBでエンコードされているので、サンプリングでリニアに戻す
+const TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+//同時に持てるテクスチャの数、eguiが作るのはフォントのアトラスくらい
+const MAX_TEXTURES: u32 = 16;
+
+//選べるMSAAのサンプル数の候補
+const MSAA_CANDIDATES: [u32; 7] = [1, 2, 4, 8, 16, 32, 64];
+
+//eguiのメッシュの頂点、座標はクリップ座標
+//シェーダー側のmain_vs_eguiの入力の順番とlocationを合わせる
+//colorはeguiのままのアルファを掛け済みのsRGB
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct EguiVertex {
+    pub position: [f32; 2],
+    pub uv: [f32; 2],
+    pub color: [u8; 4],
+}
+
+impl EguiVertex {
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(mem::size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+        [
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(mem::size_of::<[f32; 2]>() as u32)
+                .build(),
+            //シェーダーでsRGBからリニアに戻すのでUNORMで読む
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(2)
+                .format(vk::Format::R8G8B8A8_UNORM)
+                .offset(mem::size_of::<[f32; 4]>() as u32)
+                .build(),
+        ]
+    }
+}
+
+//オーバーレイに表示するだけの値
+pub struct OverlayInfo {
+    pub fps: f64,
+    pub gpu_ms: Option<f64>,
+    //ポストプロセスや描画の倍率を変えている間はMSAAを使えない
+    pub msaa_available: bool,
+    pub wireframe_available: bool,
+}
+
+//オーバーレイで変えられる設定
+//VulkanAppが今の値を入れて渡し、runの後で変わった値だけを反映する
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OverlaySettings {
+    //ClearColorと同じくsRGBの値
+    pub clear_color: [u8; 3],
+    pub present_mode: PresentModePreference,
+    pub msaa_samples: u32,
+    pub wireframe: bool,
+}
+
+//eguiのテクスチャ1枚
+//部分的な更新は手元のピクセルを書き換えてから作り直すので、RGBA8のピクセルも持っておく
+struct OverlayTexture {
+    texture: Texture2D,
+    descriptor_set: vk::DescriptorSet,
+    size: [usize; 2],
+    pixels: Vec<u8>,
+}
+
+//uploadで頂点とインデックスを書き込んだ1つのメッシュ
+#[derive(Clone, Copy, Debug)]
+struct MeshDraw {
+    texture_id: TextureId,
+    scissor: vk::Rect2D,
+    first_index: u32,
+    index_count: u32,
+    vertex_offset: i32,
+}
+
+//F1で表示を切り替える、実行中に設定を変えるためのeguiのオーバーレイ
+//ウィンドウのイベントをon_window_eventで渡し、runでUIを組み立て、uploadでこのフレームの頂点を書き込んでからcmd_drawで描画する
+//set = 0にメッシュごとのテクスチャのimageとsamplerを割り当てる
+pub struct EguiOverlay {
+    context: egui::Context,
+    state: egui_winit::State,
+    visible: bool,
+    device_name: String,
+    //このデバイスで使えるMSAAのサンプル数
+    msaa_choices: Vec<u32>,
+    sampler: vk::Sampler,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    textures: HashMap<TextureId, OverlayTexture>,
+    //runで受け取り、update_texturesで反映するテクスチャの変更
+    textures_delta: TexturesDelta,
+    //最後のrunで作ったメッシュと、その時の画面の論理ピクセルでの範囲
+    primitives: Vec<ClippedPrimitive>,
+    screen_rect: Rect,
+    //フレームごとにStagingRingに書き込んだ頂点とインデックスの(バッファ, オフセット)
+    vertex_bindings: Vec<(vk::Buffer, vk::DeviceSize)>,
+    index_bindings: Vec<(vk::Buffer, vk::DeviceSize)>,
+    draws: Vec<Vec<MeshDraw>>,
+}
+
+impl EguiOverlay {
+    //samplerの破棄はSamplerCacheに任せる
+    //supported_samplesはmsaa::supported_sample_counts
+    pub fn new(
+        device: &Device,
+        sampler: vk::Sampler,
+        frames_in_flight: u32,
+        scale_factor: f64,
+        properties: &vk::PhysicalDeviceProperties,
+        device_name: String,
+        supported_samples: vk::SampleCountFlags,
+    ) -> Self {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        };
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(MAX_TEXTURES)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::SAMPLER)
+                .descriptor_count(MAX_TEXTURES)
+                .build(),
+        ];
+
+        //eguiが解放したテクスチャのセットを返せるようにする
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+            .pool_sizes(&pool_sizes)
+            .max_sets(MAX_TEXTURES)
+            .build();
+
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+
+        let msaa_choices = MSAA_CANDIDATES
+            .into_iter()
+            .filter(|&samples| supported_samples.contains(vk::SampleCountFlags::from_raw(samples)))
+            .collect();
+
+        Self {
+            context: egui::Context::default(),
+            state: egui_winit::State::from_pixels_per_point(
+                properties.limits.max_image_dimension2_d as usize,
+                scale_factor as f32,
+            ),
+            visible: false,
+            device_name,
+            msaa_choices,
+            sampler,
+            descriptor_set_layout,
+            descriptor_pool,
+            textures: HashMap::new(),
+            textures_delta: TexturesDelta::default(),
+            primitives: vec![],
+            screen_rect: Rect::NOTHING,
+            vertex_bindings: vec![(vk::Buffer::null(), 0); frames_in_flight as usize],
+            index_bindings: vec![(vk::Buffer::null(), 0); frames_in_flight as usize],
+            draws: vec![vec![]; frames_in_flight as usize],
+        }
+    }
+
+    //パイプラインレイアウトのset = 0に使う
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    //隠している間に別のモニターへ移った場合もあるので、ScaleFactorChangedのイベントとは別に合わせる
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.state.set_pixels_per_point(scale_factor as f32);
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    //隠している間はイベントを渡さず、runも何もしない
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+        self.primitives.clear();
+    }
+
+    //InputStateより先に渡し、trueの場合はカメラなどの操作に渡さない
+    //eguiのウィンドウの上で押したボタンとフォーカスのある欄へのキーだけを使わせ、離したことは常に渡して押しっぱなしにならないようにする
+    //wants_pointer_inputとwants_keyboard_inputは前回のrunの結果なので、押した時点で画面に出ていたUIで決まる
+    pub fn on_window_event(&mut self, event: &WindowEvent) -> bool {
+        if !self.visible {
+            return false;
+        }
+
+        self.state.on_event(&self.context, event);
+
+        match event {
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                ..
+            }
+            | WindowEvent::MouseWheel { .. } => self.context.wants_pointer_input(),
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => self.context.wants_keyboard_input(),
+            _ => false,
+        }
+    }
+
+    //UIを組み立ててsettingsを書き換え、メッシュを作る
+    //cursor_grabbedの間はカーソルの表示をeguiに変えさせない
+    //eguiがアニメーション中などで続けて描画する必要がある場合はtrueを返す
+    pub fn run(
+        &mut self,
+        window: &Window,
+        cursor_grabbed: bool,
+        info: &OverlayInfo,
+        settings: &mut OverlaySettings,
+    ) -> bool {
+        if !self.visible {
+            return false;
+        }
+
+        let raw_input = self.state.take_egui_input(window);
+        let device_name = &self.device_name;
+        let msaa_choices = &self.msaa_choices;
+
+        let output = self.context.run(raw_input, |context| {
+            egui::Window::new("vulkan-tutorial (F1)").show(context, |ui| {
+                ui.label(format!("{:.1} FPS", info.fps));
+                ui.label(match info.gpu_ms {
+                    Some(gpu_ms) => format!("GPU {:.2} ms", gpu_ms),
+                    None => "GPU time unavailable".to_owned(),
+                });
+                ui.label(device_name.as_str());
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.label("Clear color");
+                    ui.color_edit_button_srgb(&mut settings.clear_color);
+                });
+
+                egui::ComboBox::from_label("Present mode")
+                    .selected_text(settings.present_mode.name())
+                    .show_ui(ui, |ui| {
+                        for mode in [
+                            PresentModePreference::Vsync,
+                            PresentModePreference::LowLatency,
+                            PresentModePreference::Uncapped,
+                        ] {
+                            ui.selectable_value(&mut settings.present_mode, mode, mode.name());
+                        }
+                    });
+
+                ui.add_enabled_ui(info.msaa_available, |ui| {
+                    egui::ComboBox::from_label("MSAA")
+                        .selected_text(format!("{}x", settings.msaa_samples))
+                        .show_ui(ui, |ui| {
+                            for &samples in msaa_choices {
+                                ui.selectable_value(
+                                    &mut settings.msaa_samples,
+                                    samples,
+                                    format!("{}x", samples),
+                                );
+                            }
+                        });
+                });
+
+                ui.add_enabled(
+                    info.wireframe_available,
+                    egui::Checkbox::new(&mut settings.wireframe, "Wireframe"),
+                );
+            });
+        });
+
+        if !cursor_grabbed {
+            self.state
+                .handle_platform_output(window, &self.context, output.platform_output);
+        }
+
+        self.textures_delta.append(output.textures_delta);
+        self.primitives = self.context.tessellate(output.shapes);
+        self.screen_rect = self.context.input().screen_rect();
+
+        output.needs_repaint
+    }
+
+    //runで受け取ったテクスチャの変更を反映する、コマンドの記録の前に呼ぶ
+    pub fn update_textures(
+        &mut self,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+    ) {
+        let delta = mem::take(&mut self.textures_delta);
+
+        //描画中のフレームが使っているテクスチャを作り直すので、GPUが使い終わるのを待つ
+        //フォントのアトラスに文字が増えた時くらいしか起きない
+        let replaces = !delta.free.is_empty()
+            || delta
+                .set
+                .iter()
+                .any(|(id, _)| self.textures.contains_key(id));
+
+        if replaces {
+            unsafe { device.device_wait_idle().unwrap() };
+        }
+
+        for (id, image_delta) in &delta.set {
+            let pixels = rgba8_pixels(&image_delta.image);
+            let size = image_delta.image.size();
+
+            //posがSomeの場合は既にあるテクスチャの一部だけを書き換える
+            let (size, pixels) = match (image_delta.pos, self.textures.get_mut(id)) {
+                (Some(pos), Some(existing)) => {
+                    patch_pixels(&mut existing.pixels, existing.size, pos, size, &pixels);
+                    (existing.size, mem::take(&mut existing.pixels))
+                }
+                (Some(_), None) => {
+                    log::warn!("egui updated a part of unknown texture {:?}", id);
+                    continue;
+                }
+                (None, _) => (size, pixels),
+            };
+
+            let texture = Texture2D::new(
+                instance,
+                physical_device,
+                device,
+                one_time_commands,
+                synchronization,
+                TEXTURE_FORMAT,
+                vk::Extent2D {
+                    width: size[0] as u32,
+                    height: size[1] as u32,
+                },
+                &pixels,
+            );
+
+            let descriptor_set = match self.textures.remove(id) {
+                Some(previous) => {
+                    previous.texture.destroy(device);
+                    previous.descriptor_set
+                }
+                None => match self.allocate_descriptor_set(device) {
+                    Some(descriptor_set) => descriptor_set,
+                    None => {
+                        log::warn!("egui uses more than {} textures", MAX_TEXTURES);
+                        texture.destroy(device);
+                        continue;
+                    }
+                },
+            };
+
+            self.write_descriptor_set(device, descriptor_set, texture.view());
+
+            self.textures.insert(
+                *id,
+                OverlayTexture {
+                    texture,
+                    descriptor_set,
+                    size,
+                    pixels,
+                },
+            );
+        }
+
+        for id in &delta.free {
+            if let Some(texture) = self.textures.remove(id) {
+                texture.texture.destroy(device);
+                unsafe {
+                    device
+                        .free_descriptor_sets(self.descriptor_pool, &[texture.descriptor_set])
+                        .unwrap();
+                }
+            }
+        }
+    }
+
+    fn allocate_descriptor_set(&self, device: &Device) -> Option<vk::DescriptorSet> {
+        let set_layouts = [self.descriptor_set_layout];
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&set_layouts)
+            .build();
+
+        unsafe { device.allocate_descriptor_sets(&alloc_info) }
+            .ok()
+            .map(|sets| sets[0])
+    }
+
+    fn write_descriptor_set(
+        &self,
+        device: &Device,
+        descriptor_set: vk::DescriptorSet,
+        view: vk::ImageView,
+    ) {
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()];
+
+        let sampler_info = [vk::DescriptorImageInfo::builder()
+            .sampler(self.sampler)
+            .build()];
+
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(&image_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .image_info(&sampler_info)
+                .build(),
+        ];
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+    }
+
+    //最後のrunで作ったメッシュの頂点とインデックスをstaging_ringに書き込む
+    //extentは描画先の大きさで、論理ピクセルのクリップの範囲をその物理ピクセルのscissorにする
+    //staging_ringのbegin_frameでframeを始めた後に呼ぶ
+    pub fn upload(
+        &mut self,
+        device: &Device,
+        staging_ring: &mut StagingRing,
+        frame: usize,
+        extent: vk::Extent2D,
+    ) {
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        let draws = &mut self.draws[frame];
+        draws.clear();
+
+        for ClippedPrimitive {
+            clip_rect,
+            primitive,
+        } in &self.primitives
+        {
+            //PaintCallbackはこのUIでは使わない
+            let mesh = match primitive {
+                Primitive::Mesh(mesh) => mesh,
+                Primitive::Callback(_) => continue,
+            };
+
+            let scissor = scissor_rect(*clip_rect, self.screen_rect, extent);
+
+            if mesh.indices.is_empty()
+                || scissor.extent.width == 0
+                || scissor.extent.height == 0
+                || !self.textures.contains_key(&mesh.texture_id)
+            {
+                continue;
+            }
+
+            draws.push(MeshDraw {
+                texture_id: mesh.texture_id,
+                scissor,
+                first_index: indices.len() as u32,
+                index_count: mesh.indices.len() as u32,
+                vertex_offset: vertices.len() as i32,
+            });
+
+            vertices.extend(mesh.vertices.iter().map(|vertex| EguiVertex {
+                position: to_clip(vertex.pos, self.screen_rect),
+                uv: [vertex.uv.x, vertex.uv.y],
+                color: vertex.color.to_array(),
+            }));
+            indices.extend_from_slice(&mesh.indices);
+        }
+
+        if draws.is_empty() {
+            return;
+        }
+
+        let vertex_allocation = staging_ring.write(device, &vertices);
+        let index_allocation = staging_ring.write(device, &indices);
+        self.vertex_bindings[frame] = (vertex_allocation.buffer, vertex_allocation.offset);
+        self.index_bindings[frame] = (index_allocation.buffer, index_allocation.offset);
+    }
+
+    //他の描画の後に、描画先のパスの中で呼ぶ
+    //viewportは呼び出し側で設定しておき、メッシュごとに変えたscissorは最後にextent全体に戻す
+    pub fn cmd_draw(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        (pipeline, pipeline_layout): (vk::Pipeline, vk::PipelineLayout),
+        frame: usize,
+        extent: vk::Extent2D,
+    ) {
+        let draws = &self.draws[frame];
+
+        if !self.visible || draws.is_empty() {
+            return;
+        }
+
+        let (vertex_buffer, vertex_offset) = self.vertex_bindings[frame];
+        let (index_buffer, index_offset) = self.index_bindings[frame];
+
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[vertex_offset]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                index_buffer,
+                index_offset,
+                vk::IndexType::UINT32,
+            );
+
+            for draw in draws {
+                //uploadの後にテクスチャが解放されることはないが、念のため描画しない
+                let texture = match self.textures.get(&draw.texture_id) {
+                    Some(texture) => texture,
+                    None => continue,
+                };
+
+                device.cmd_set_scissor(command_buffer, 0, &[draw.scissor]);
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    0,
+                    &[texture.descriptor_set],
+                    &[],
+                );
+                device.cmd_draw_indexed(
+                    command_buffer,
+                    draw.index_count,
+                    1,
+                    draw.first_index,
+                    draw.vertex_offset,
+                    0,
+                );
+            }
+
+            device.cmd_set_scissor(
+                command_buffer,
+                0,
+                &[vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent,
+                }],
+            );
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        for texture in self.textures.values() {
+            texture.texture.destroy(device);
+        }
+
+        unsafe {
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
+
+//eguiの画像をTEXTURE_FORMATのピクセルにする
+//フォントはカバレッジなので、eguiと同じくアルファを掛け済みの白にする
+fn rgba8_pixels(image: &ImageData) -> Vec<u8> {
+    match image {
+        ImageData::Color(image) => image
+            .pixels
+            .iter()
+            .flat_map(|color| color.to_array())
+            .collect(),
+        ImageData::Font(image) => image
+            .srgba_pixels(1.0)
+            .flat_map(|color| color.to_array())
+            .collect(),
+    }
+}
+
+//RGBA8のtargetのposの位置に、sizeの大きさのpatchを書き込む
+//targetからはみ出す部分は捨てる
+fn patch_pixels(
+    target: &mut [u8],
+    target_size: [usize; 2],
+    pos: [usize; 2],
+    size: [usize; 2],
+    patch: &[u8],
+) {
+    let width = size[0].min(target_size[0].saturating_sub(pos[0]));
+    let height = size[1].min(target_size[1].saturating_sub(pos[1]));
+
+    for row in 0..height {
+        let source = row * size[0] * 4;
+        let destination = ((pos[1] + row) * target_size[0] + pos[0]) * 4;
+
+        target[destination..destination + width * 4]
+            .copy_from_slice(&patch[source..source + width * 4]);
+    }
+}
+
+//論理ピクセルの座標をクリップ座標にする、Vulkanのクリップ座標はYが下向きなので向きは同じ
+fn to_clip(position: Pos2, screen_rect: Rect) -> [f32; 2] {
+    let x = (position.x - screen_rect.min.x) / screen_rect.width();
+    let y = (position.y - screen_rect.min.y) / screen_rect.height();
+
+    [x * 2.0 - 1.0, y * 2.0 - 1.0]
+}
+
+//論理ピクセルのclip_rectを、extentの描画先での物理ピクセルのscissorにする
+//端のピクセルが欠けないように外側へ丸め、描画先の外は切り捨てる
+fn scissor_rect(clip_rect: Rect, screen_rect: Rect, extent: vk::Extent2D) -> vk::Rect2D {
+    let scale_x = extent.width as f32 / screen_rect.width();
+    let scale_y = extent.height as f32 / screen_rect.height();
+
+    let clamp_x = |x: f32| x.clamp(0.0, extent.width as f32);
+    let clamp_y = |y: f32| y.clamp(0.0, extent.height as f32);
+
+    let min_x = clamp_x(((clip_rect.min.x - screen_rect.min.x) * scale_x).floor());
+    let min_y = clamp_y(((clip_rect.min.y - screen_rect.min.y) * scale_y).floor());
+    let max_x = clamp_x(((clip_rect.max.x - screen_rect.min.x) * scale_x).ceil());
+    let max_y = clamp_y(((clip_rect.max.y - screen_rect.min.y) * scale_y).ceil());
+
+    vk::Rect2D {
+        offset: vk::Offset2D {
+            x: min_x as i32,
+            y: min_y as i32,
+        },
+        extent: vk::Extent2D {
+            width: (max_x - min_x).max(0.0) as u32,
+            height: (max_y - min_y).max(0.0) as u32,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::pos2;
+
+    fn screen() -> Rect {
+        Rect::from_min_max(pos2(0.0, 0.0), pos2(400.0, 300.0))
+    }
+
+    #[test]
+    fn corners_map_to_clip_space() {
+        assert_eq!(to_clip(pos2(0.0, 0.0), screen()), [-1.0, -1.0]);
+        assert_eq!(to_clip(pos2(400.0, 300.0), screen()), [1.0, 1.0]);
+        assert_eq!(to_clip(pos2(100.0, 150.0), screen()), [-0.5, 0.0]);
+    }
+
+    #[test]
+    fn scissors_are_in_physical_pixels() {
+        //拡大率2.0のウィンドウ
+        let extent = vk::Extent2D {
+            width: 800,
+            height: 600,
+        };
+        let scissor = scissor_rect(
+            Rect::from_min_max(pos2(10.25, 20.0), pos2(110.0, 70.75)),
+            screen(),
+            extent,
+        );
+
+        assert_eq!((scissor.offset.x, scissor.offset.y), (20, 40));
+        assert_eq!((scissor.extent.width, scissor.extent.height), (200, 102));
+    }
+
+    #[test]
+    fn scissors_outside_the_target_are_empty() {
+        let extent = vk::Extent2D {
+            width: 400,
+            height: 300,
+        };
+        let scissor = scissor_rect(
+            Rect::from_min_max(pos2(500.0, -50.0), pos2(600.0, -10.0)),
+            screen(),
+            extent,
+        );
+
+        assert_eq!(scissor.extent.width, 0);
+        assert_eq!(scissor.extent.height, 0);
+
+        let partial = scissor_rect(
+            Rect::from_min_max(pos2(-20.0, 250.0), pos2(50.0, 400.0)),
+            screen(),
+            extent,
+        );
+
+        assert_eq!((partial.offset.x, partial.offset.y), (0, 250));
+        assert_eq!((partial.extent.width, partial.extent.height), (50, 50));
+    }
+
+    #[test]
+    fn patches_overwrite_only_their_region() {
+        let mut target = vec![0u8; 3 * 2 * 4];
+        let patch = [7u8; 2 * 2 * 4];
+
+        //右下にはみ出す2x2は1列1行だけ書き込む
+        patch_pixels(&mut target, [3, 2], [2, 1], [2, 2], &patch);
+
+        let written = target
+            .chunks(4)
+            .map(|pixel| pixel[0] == 7)
+            .collect::<Vec<_>>();
+        assert_eq!(written, [false, false, false, false, false, true]);
+    }
+}
@@ -0,0 +1,203 @@
+use std::time::{Duration, Instant};
+
+//デバッガで止めた後や最小化から戻った時に巨大な経過時間を一度に処理しないように上限を設ける
+const MAX_DELTA: Duration = Duration::from_millis(250);
+
+//可変のフレーム時間とは別に固定間隔のシミュレーションを回すための時計
+//PresentModeやフレームレート制限によってシミュレーションの結果が変わらないようにする
+pub struct FrameClock {
+    last_tick_at: Instant,
+    //前回のtickからの経過時間(MAX_DELTAで制限済み)
+    delta: Duration,
+    //制限済みのdeltaの合計なので止まっていた時間は含まれない
+    elapsed: Duration,
+    //まだ固定間隔のupdateで消費していない時間
+    accumulator: Duration,
+    //trueの間はelapsedとaccumulatorを進めないので、固定間隔のupdateが呼ばれなくなる
+    paused: bool,
+    //このフレームでシミュレーションを進めた時間、pausedの間はstepを呼ばない限り0
+    simulation_delta: Duration,
+    //Someの場合は実際の経過時間の代わりに毎回この時間だけ進める
+    fixed_delta: Option<Duration>,
+}
+
+impl FrameClock {
+    pub fn new() -> Self {
+        Self {
+            last_tick_at: Instant::now(),
+            delta: Duration::ZERO,
+            elapsed: Duration::ZERO,
+            accumulator: Duration::ZERO,
+            paused: false,
+            simulation_delta: Duration::ZERO,
+            fixed_delta: None,
+        }
+    }
+
+    //--benchで描画の速さに関わらず毎フレーム同じだけ時間を進めるために使う
+    pub fn set_fixed_delta(&mut self, fixed_delta: Option<Duration>) {
+        self.fixed_delta = fixed_delta;
+    }
+
+    //1フレームに1回呼んで経過時間を進める
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        self.advance(self.fixed_delta.unwrap_or(now - self.last_tick_at));
+        self.last_tick_at = now;
+    }
+
+    fn advance(&mut self, delta: Duration) {
+        self.delta = delta.min(MAX_DELTA);
+
+        if self.paused {
+            self.simulation_delta = Duration::ZERO;
+            return;
+        }
+
+        self.simulation_delta = self.delta;
+        self.elapsed += self.delta;
+        self.accumulator += self.delta;
+    }
+
+    //一時停止中にtickの後で呼ぶと、固定間隔のupdateがちょうど1回呼ばれるだけ時間を進める
+    pub fn step(&mut self, dt_fixed: Duration) {
+        self.simulation_delta = dt_fixed;
+        self.elapsed += dt_fixed;
+        self.accumulator += dt_fixed;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    //--recordで書き出し、--replayでset_fixed_deltaに渡す
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    //一時停止中も進むので、カメラのように止めたくないものに使う
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    //固定間隔ではなくフレームごとに進めるシミュレーションに使う
+    pub fn simulation_delta_seconds(&self) -> f32 {
+        self.simulation_delta.as_secs_f32()
+    }
+
+    pub fn elapsed_seconds(&self) -> f32 {
+        self.elapsed.as_secs_f32()
+    }
+
+    //溜まった時間をdt_fixedずつ消費してupdate_fnを呼ぶ
+    //余った時間は次のフレームに持ち越し、呼んだ回数を返す
+    //dt_fixedが0の場合は溜まった時間が減らず終わらなくなるので、update_fnを呼ばずに0を返す
+    pub fn update(&mut self, dt_fixed: Duration, mut update_fn: impl FnMut(Duration)) -> u32 {
+        if dt_fixed.is_zero() {
+            return 0;
+        }
+
+        let mut steps = 0;
+
+        while self.accumulator >= dt_fixed {
+            update_fn(dt_fixed);
+            self.accumulator -= dt_fixed;
+            steps += 1;
+        }
+
+        steps
+    }
+
+    //前回の固定間隔のupdateから次のupdateまでの間のどこにいるかを0.0から1.0で返す
+    //描画時に前回と今回の状態を補間するのに使う
+    #[allow(dead_code)]
+    pub fn alpha(&self, dt_fixed: Duration) -> f32 {
+        self.accumulator.as_secs_f32() / dt_fixed.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT_FIXED: Duration = Duration::from_millis(10);
+
+    fn steps_after(clock: &mut FrameClock, delta: Duration) -> u32 {
+        clock.advance(delta);
+        clock.update(DT_FIXED, |dt| assert_eq!(dt, DT_FIXED))
+    }
+
+    #[test]
+    fn irregular_frames_carry_the_remainder() {
+        let mut clock = FrameClock::new();
+
+        assert_eq!(steps_after(&mut clock, Duration::from_millis(3)), 0);
+        //3 + 8 = 11msなので1回、1ms残る
+        assert_eq!(steps_after(&mut clock, Duration::from_millis(8)), 1);
+        //1 + 25 = 26msなので2回、6ms残る
+        assert_eq!(steps_after(&mut clock, Duration::from_millis(25)), 2);
+        //6 + 4 = 10msでちょうど1回
+        assert_eq!(steps_after(&mut clock, Duration::from_millis(4)), 1);
+        assert_eq!(clock.accumulator, Duration::ZERO);
+    }
+
+    #[test]
+    fn total_steps_match_elapsed_time() {
+        let mut clock = FrameClock::new();
+        let deltas = [7, 16, 1, 33, 9, 12, 0, 22];
+
+        let steps = deltas
+            .iter()
+            .map(|&milliseconds| steps_after(&mut clock, Duration::from_millis(milliseconds)))
+            .sum::<u32>();
+
+        //合計100msなので10回、残りは無い
+        assert_eq!(steps, 10);
+        assert_eq!(clock.elapsed, Duration::from_millis(100));
+        assert!(clock.accumulator < DT_FIXED);
+    }
+
+    #[test]
+    fn long_frames_are_clamped() {
+        let mut clock = FrameClock::new();
+
+        //1秒止まっていてもMAX_DELTAの250ms分だけ進める
+        assert_eq!(steps_after(&mut clock, Duration::from_secs(1)), 25);
+        assert_eq!(clock.delta(), MAX_DELTA);
+    }
+
+    #[test]
+    fn paused_clock_only_moves_by_step() {
+        let mut clock = FrameClock::new();
+        clock.set_paused(true);
+
+        assert_eq!(steps_after(&mut clock, Duration::from_millis(50)), 0);
+        assert_eq!(clock.simulation_delta_seconds(), 0.0);
+
+        clock.step(DT_FIXED);
+        assert_eq!(clock.update(DT_FIXED, |_| ()), 1);
+        assert_eq!(clock.elapsed, DT_FIXED);
+    }
+
+    #[test]
+    fn zero_dt_fixed_does_not_spin() {
+        let mut clock = FrameClock::new();
+        clock.advance(Duration::from_millis(16));
+
+        let steps = clock.update(Duration::ZERO, |_| panic!("update_fn must not be called"));
+
+        assert_eq!(steps, 0);
+    }
+
+    #[test]
+    fn alpha_is_fraction_of_the_next_step() {
+        let mut clock = FrameClock::new();
+        steps_after(&mut clock, Duration::from_millis(25));
+
+        assert!((clock.alpha(DT_FIXED) - 0.5).abs() < 1e-6);
+    }
+}
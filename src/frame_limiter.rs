@@ -0,0 +1,175 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+//OSのsleepは精度が1ms前後しかないので最後のこの時間はビジーループで待つ
+const SPIN_THRESHOLD: Duration = Duration::from_millis(1);
+
+//ブラケットキーで切り替える上限の候補
+const FPS_STEPS: [u32; 6] = [15, 30, 60, 120, 144, 240];
+
+//PresentModeとは独立してフレームレートの上限を設ける
+pub struct FrameLimiter {
+    target_fps: Option<u32>,
+    //次のフレームを開始してよい時刻
+    next_frame_at: Option<Instant>,
+}
+
+impl FrameLimiter {
+    pub fn new(target_fps: Option<u32>) -> Self {
+        Self {
+            target_fps: target_fps.filter(|fps| *fps > 0),
+            next_frame_at: None,
+        }
+    }
+
+    pub fn target_fps(&self) -> Option<u32> {
+        self.target_fps
+    }
+
+    pub fn target_frame_time(&self) -> Option<Duration> {
+        self.target_fps
+            .map(|fps| Duration::from_secs_f64(1.0 / fps as f64))
+    }
+
+    pub fn set_target_fps(&mut self, target_fps: Option<u32>) {
+        self.target_fps = target_fps.filter(|fps| *fps > 0);
+        self.reset();
+    }
+
+    //上限を一段階上げる、一番上の次は上限なし
+    pub fn raise(&mut self) {
+        let next = match self.target_fps {
+            Some(fps) => FPS_STEPS.iter().copied().find(|step| *step > fps),
+            None => None,
+        };
+
+        self.set_target_fps(next);
+    }
+
+    //上限を一段階下げる、上限なしの次は一番上の候補
+    pub fn lower(&mut self) {
+        let next = match self.target_fps {
+            Some(fps) => FPS_STEPS
+                .iter()
+                .copied()
+                .rev()
+                .find(|step| *step < fps)
+                .or(Some(FPS_STEPS[0])),
+            None => FPS_STEPS.last().copied(),
+        };
+
+        self.set_target_fps(next);
+    }
+
+    //最小化や一時停止から復帰した時に溜まった遅れを取り戻そうとしないようにスケジュールを捨てる
+    pub fn reset(&mut self) {
+        self.next_frame_at = None;
+    }
+
+    //presentの後に呼び、次のフレームの開始時刻まで待機する
+    pub fn wait(&mut self) {
+        let frame_time = match self.target_frame_time() {
+            Some(frame_time) => frame_time,
+            None => return,
+        };
+
+        let now = Instant::now();
+
+        let deadline = match self.next_frame_at {
+            Some(deadline) => deadline,
+            None => {
+                self.next_frame_at = Some(now + frame_time);
+                return;
+            }
+        };
+
+        if deadline > now {
+            let remaining = deadline - now;
+
+            if remaining > SPIN_THRESHOLD {
+                thread::sleep(remaining - SPIN_THRESHOLD);
+            }
+
+            while Instant::now() < deadline {
+                std::hint::spin_loop();
+            }
+        }
+
+        //1フレーム以上遅れている場合は連続でフレームを出して追いつこうとせずに今を基準にし直す
+        let now = Instant::now();
+        self.next_frame_at = if now > deadline + frame_time {
+            Some(now + frame_time)
+        } else {
+            Some(deadline + frame_time)
+        };
+    }
+}
+
+//RunMode::OnDemandでanimation_intervalごとに再描画する時刻
+//イベントの度にInstant::now()から計算し直すと、マウスを動かし続けている間は期限が先に延び続けて満了しないので、
+//満了した時だけ次の期限に進める
+pub struct AnimationTimer {
+    interval: Duration,
+    deadline: Instant,
+}
+
+impl AnimationTimer {
+    pub fn new(interval: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            deadline: now + interval,
+        }
+    }
+
+    //ControlFlow::WaitUntilに渡す時刻
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    //StartCause::ResumeTimeReachedで呼ぶ
+    //1間隔以上遅れている場合は溜まった分を続けて満了させずに今を基準にし直す
+    pub fn fire(&mut self, now: Instant) {
+        let next = self.deadline + self.interval;
+
+        self.deadline = if next <= now {
+            now + self.interval
+        } else {
+            next
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    //期限はfireした時だけ進み、途中で何度deadlineを読んでも変わらない
+    #[test]
+    fn deadline_only_moves_when_the_timer_fires() {
+        let start = Instant::now();
+        let mut timer = AnimationTimer::new(INTERVAL, start);
+
+        for _ in 0..10 {
+            assert_eq!(timer.deadline(), start + INTERVAL);
+        }
+
+        timer.fire(start + INTERVAL);
+        assert_eq!(timer.deadline(), start + INTERVAL * 2);
+
+        //少し遅れて満了しても間隔は元の期限から数える
+        timer.fire(start + INTERVAL * 2 + Duration::from_millis(30));
+        assert_eq!(timer.deadline(), start + INTERVAL * 3);
+    }
+
+    #[test]
+    fn falling_behind_restarts_from_now() {
+        let start = Instant::now();
+        let mut timer = AnimationTimer::new(INTERVAL, start);
+
+        let late = start + INTERVAL * 5;
+        timer.fire(late);
+        assert_eq!(timer.deadline(), late + INTERVAL);
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+//直近何フレーム分のフレーム時間を保持するか
+const ROLLING_WINDOW: usize = 120;
+
+//集計結果を返す間隔
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+//フレームごとのCPU時間を計測して一定間隔で集計する
+pub struct FrameStats {
+    frame_times: VecDeque<Duration>,
+    //GPUタイムスタンプクエリで計測した時間(ミリ秒)
+    gpu_times: VecDeque<f64>,
+    //専用のコンピュートキューで計測した時間と、そのうちグラフィックスと重なっていた時間(ミリ秒)
+    compute_times: VecDeque<(f64, f64)>,
+    //前のフレームの開始から次のフレームの開始までの時間
+    frame_intervals: VecDeque<Duration>,
+    //record_command_bufferに掛かったCPU時間
+    record_times: VecDeque<Duration>,
+    //フレームレート制限の目標のフレーム時間
+    target_frame_time: Option<Duration>,
+    frame_started_at: Instant,
+    previous_frame_started_at: Option<Instant>,
+    last_report_at: Instant,
+    frames_since_report: u32,
+    //直近のフレームで視錐台カリングにより省いたオブジェクトの数と全体の数
+    culling: Option<(usize, usize)>,
+    //直近のフレームでオクルージョンクエリにより省いたオブジェクトの数と全体の数
+    occlusion: Option<(usize, usize)>,
+    //直近のフレームで同じ物が紐づいていたので記録しなかった紐づけの数
+    avoided_binds: Option<usize>,
+    //直近のフレームでシーンを描画した解像度とswapchainの解像度
+    resolution: Option<((u32, u32), (u32, u32))>,
+    //keep_historyを呼んだ場合のみSome
+    history: Option<FrameHistory>,
+}
+
+//ROLLING_WINDOWで捨てずに記録した全てのフレームの時間(ミリ秒)
+#[derive(Clone, Debug, Default)]
+pub struct FrameHistory {
+    pub cpu_ms: Vec<f64>,
+    //GPUの計測結果は数フレーム遅れるので、最後の数フレーム分は入らない
+    pub gpu_ms: Vec<f64>,
+}
+
+//REPORT_INTERVALごとに返される集計値
+#[derive(Clone, Copy, Debug)]
+pub struct FrameReport {
+    pub fps: f64,
+    pub average_ms: f64,
+    pub p99_ms: f64,
+    pub gpu_average_ms: Option<f64>,
+    //(コンピュートの平均時間, グラフィックスと重なっていた平均時間)
+    pub compute_average_ms: Option<(f64, f64)>,
+    pub average_interval_ms: f64,
+    pub target_interval_ms: Option<f64>,
+    pub record_average_ms: f64,
+    //(省いたオブジェクトの数, 全体の数)
+    pub culling: Option<(usize, usize)>,
+    //(隠れていて省いたオブジェクトの数, 全体の数)
+    pub occlusion: Option<(usize, usize)>,
+    pub avoided_binds: Option<usize>,
+    //(描画した解像度, swapchainの解像度)
+    pub resolution: Option<((u32, u32), (u32, u32))>,
+}
+
+impl FrameStats {
+    pub fn new() -> Self {
+        let now = Instant::now();
+
+        Self {
+            frame_times: VecDeque::with_capacity(ROLLING_WINDOW),
+            gpu_times: VecDeque::with_capacity(ROLLING_WINDOW),
+            compute_times: VecDeque::with_capacity(ROLLING_WINDOW),
+            frame_intervals: VecDeque::with_capacity(ROLLING_WINDOW),
+            record_times: VecDeque::with_capacity(ROLLING_WINDOW),
+            target_frame_time: None,
+            frame_started_at: now,
+            previous_frame_started_at: None,
+            last_report_at: now,
+            frames_since_report: 0,
+            culling: None,
+            occlusion: None,
+            avoided_binds: None,
+            resolution: None,
+            history: None,
+        }
+    }
+
+    //--benchで最後にまとめて集計するために、これ以降の全てのフレームの時間を記録する
+    pub fn keep_history(&mut self) {
+        self.history = Some(FrameHistory::default());
+    }
+
+    pub fn history(&self) -> Option<&FrameHistory> {
+        self.history.as_ref()
+    }
+
+    pub fn begin_frame(&mut self) {
+        let now = Instant::now();
+
+        if let Some(previous) = self.previous_frame_started_at {
+            if self.frame_intervals.len() == ROLLING_WINDOW {
+                self.frame_intervals.pop_front();
+            }
+            self.frame_intervals.push_back(now - previous);
+        }
+
+        self.frame_started_at = now;
+        self.previous_frame_started_at = Some(now);
+    }
+
+    //最小化などで描画を止めた後は間隔の計測をやり直す
+    pub fn reset_interval(&mut self) {
+        self.previous_frame_started_at = None;
+    }
+
+    pub fn set_target_frame_time(&mut self, target_frame_time: Option<Duration>) {
+        self.target_frame_time = target_frame_time;
+        self.frame_intervals.clear();
+    }
+
+    //GPUの計測結果は数フレーム遅れて届くのでフレームの計測とは別に記録する
+    pub fn record_gpu_time(&mut self, milliseconds: f64) {
+        if self.gpu_times.len() == ROLLING_WINDOW {
+            self.gpu_times.pop_front();
+        }
+        self.gpu_times.push_back(milliseconds);
+
+        if let Some(history) = &mut self.history {
+            history.gpu_ms.push(milliseconds);
+        }
+    }
+
+    //非同期コンピュートの効果を確認するための計測値
+    pub fn record_compute_time(&mut self, milliseconds: f64, overlap_milliseconds: f64) {
+        if self.compute_times.len() == ROLLING_WINDOW {
+            self.compute_times.pop_front();
+        }
+        self.compute_times
+            .push_back((milliseconds, overlap_milliseconds));
+    }
+
+    //Descriptorの紐づけ方などによるコマンドの記録の速さを比べるための計測値
+    pub fn record_command_time(&mut self, duration: Duration) {
+        if self.record_times.len() == ROLLING_WINDOW {
+            self.record_times.pop_front();
+        }
+        self.record_times.push_back(duration);
+    }
+
+    //描画方法を切り替えた時に、前の方法で計測したGPU時間が平均に混ざらないようにする
+    pub fn reset_gpu_times(&mut self) {
+        self.gpu_times.clear();
+    }
+
+    //平均ではなく最後に記録した値を報告する
+    pub fn record_culling(&mut self, culled: usize, total: usize) {
+        self.culling = Some((culled, total));
+    }
+
+    //record_cullingと同じく最後に記録した値を報告する
+    pub fn record_occlusion(&mut self, occluded: usize, total: usize) {
+        self.occlusion = Some((occluded, total));
+    }
+
+    //record_cullingと同じく最後に記録した値を報告する
+    pub fn record_avoided_binds(&mut self, avoided_binds: usize) {
+        self.avoided_binds = Some(avoided_binds);
+    }
+
+    //record_cullingと同じく最後に記録した値を報告する
+    pub fn record_resolution(&mut self, render: (u32, u32), output: (u32, u32)) {
+        self.resolution = Some((render, output));
+    }
+
+    //フレームの終了を記録し、前回の集計からREPORT_INTERVAL経過していれば集計結果を返す
+    pub fn end_frame(&mut self) -> Option<FrameReport> {
+        let now = Instant::now();
+
+        if self.frame_times.len() == ROLLING_WINDOW {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(now - self.frame_started_at);
+        self.frames_since_report += 1;
+
+        if let Some(history) = &mut self.history {
+            history
+                .cpu_ms
+                .push((now - self.frame_started_at).as_secs_f64() * 1000.0);
+        }
+
+        let elapsed = now - self.last_report_at;
+
+        if elapsed < REPORT_INTERVAL {
+            return None;
+        }
+
+        let report = FrameReport {
+            fps: self.frames_since_report as f64 / elapsed.as_secs_f64(),
+            average_ms: self.average_ms(),
+            p99_ms: self.percentile_ms(99.0),
+            gpu_average_ms: self.gpu_average_ms(),
+            compute_average_ms: self.compute_average_ms(),
+            average_interval_ms: self.average_interval_ms(),
+            target_interval_ms: self
+                .target_frame_time
+                .map(|target| target.as_secs_f64() * 1000.0),
+            record_average_ms: self.record_average_ms(),
+            culling: self.culling,
+            occlusion: self.occlusion,
+            avoided_binds: self.avoided_binds,
+            resolution: self.resolution,
+        };
+
+        self.last_report_at = now;
+        self.frames_since_report = 0;
+
+        Some(report)
+    }
+
+    pub fn average_ms(&self) -> f64 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+
+        let total: Duration = self.frame_times.iter().sum();
+
+        total.as_secs_f64() * 1000.0 / self.frame_times.len() as f64
+    }
+
+    //フレームレート制限が正しく効いているかを確認するための実測値
+    pub fn average_interval_ms(&self) -> f64 {
+        if self.frame_intervals.is_empty() {
+            return 0.0;
+        }
+
+        let total: Duration = self.frame_intervals.iter().sum();
+
+        total.as_secs_f64() * 1000.0 / self.frame_intervals.len() as f64
+    }
+
+    pub fn record_average_ms(&self) -> f64 {
+        if self.record_times.is_empty() {
+            return 0.0;
+        }
+
+        let total: Duration = self.record_times.iter().sum();
+
+        total.as_secs_f64() * 1000.0 / self.record_times.len() as f64
+    }
+
+    pub fn gpu_average_ms(&self) -> Option<f64> {
+        if self.gpu_times.is_empty() {
+            return None;
+        }
+
+        Some(self.gpu_times.iter().sum::<f64>() / self.gpu_times.len() as f64)
+    }
+
+    pub fn compute_average_ms(&self) -> Option<(f64, f64)> {
+        if self.compute_times.is_empty() {
+            return None;
+        }
+
+        let (total, overlap) = self
+            .compute_times
+            .iter()
+            .fold((0.0, 0.0), |(total, overlap), (time, time_overlap)| {
+                (total + time, overlap + time_overlap)
+            });
+        let count = self.compute_times.len() as f64;
+
+        Some((total / count, overlap / count))
+    }
+
+    //percentileは0.0から100.0の範囲で指定する
+    pub fn percentile_ms(&self, percentile: f64) -> f64 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+
+        let mut sorted = self.frame_times.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+
+        nearest_rank(&sorted, percentile).as_secs_f64() * 1000.0
+    }
+}
+
+//昇順に並べたsortedのnearest-rank法でのパーセンタイル
+//percentileは0.0から100.0の範囲で指定し、sortedは空であってはいけない
+pub fn nearest_rank<T: Copy>(sorted: &[T], percentile: f64) -> T {
+    let rank = (percentile.clamp(0.0, 100.0) / 100.0 * sorted.len() as f64).ceil() as usize;
+    let index = rank.clamp(1, sorted.len()) - 1;
+
+    sorted[index]
+}
+
+impl fmt::Display for FrameReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.0} fps | {:.2} ms avg | {:.1} ms p99",
+            self.fps, self.average_ms, self.p99_ms
+        )?;
+
+        write!(f, " | {:.3} ms record", self.record_average_ms)?;
+
+        if let Some(gpu_average_ms) = self.gpu_average_ms {
+            write!(f, " | {:.2} ms gpu", gpu_average_ms)?;
+        }
+
+        if let Some((compute_ms, overlap_ms)) = self.compute_average_ms {
+            write!(
+                f,
+                " | {:.2} ms compute ({:.2} ms overlapped)",
+                compute_ms, overlap_ms
+            )?;
+        }
+
+        if let Some(target_interval_ms) = self.target_interval_ms {
+            write!(
+                f,
+                " | {:.2}/{:.2} ms frame/target",
+                self.average_interval_ms, target_interval_ms
+            )?;
+        }
+
+        if let Some((culled, total)) = self.culling {
+            write!(f, " | {}/{} culled", culled, total)?;
+        }
+
+        if let Some((occluded, total)) = self.occlusion {
+            write!(f, " | {}/{} occluded", occluded, total)?;
+        }
+
+        if let Some(avoided_binds) = self.avoided_binds {
+            write!(f, " | {} binds avoided", avoided_binds)?;
+        }
+
+        if let Some(((render_width, render_height), (output_width, output_height))) =
+            self.resolution
+        {
+            write!(
+                f,
+                " | {}x{} -> {}x{} render/output",
+                render_width, render_height, output_width, output_height
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_rank_of_hundred_samples() {
+        let sorted = (1..=100).collect::<Vec<u32>>();
+
+        assert_eq!(nearest_rank(&sorted, 0.0), 1);
+        assert_eq!(nearest_rank(&sorted, 50.0), 50);
+        assert_eq!(nearest_rank(&sorted, 99.0), 99);
+        assert_eq!(nearest_rank(&sorted, 100.0), 100);
+    }
+
+    #[test]
+    fn nearest_rank_rounds_rank_up() {
+        let sorted = (1..=10).collect::<Vec<u32>>();
+
+        //10 * 0.99 = 9.9なので10番目
+        assert_eq!(nearest_rank(&sorted, 99.0), 10);
+        //10 * 0.5 = 5なので5番目
+        assert_eq!(nearest_rank(&sorted, 50.0), 5);
+        assert_eq!(nearest_rank(&sorted, 51.0), 6);
+    }
+
+    #[test]
+    fn nearest_rank_of_single_sample() {
+        let sorted = [7];
+
+        for percentile in [0.0, 50.0, 99.0, 100.0] {
+            assert_eq!(nearest_rank(&sorted, percentile), 7);
+        }
+    }
+
+    #[test]
+    fn nearest_rank_clamps_percentile() {
+        let sorted = [1, 2, 3];
+
+        assert_eq!(nearest_rank(&sorted, -10.0), 1);
+        assert_eq!(nearest_rank(&sorted, 250.0), 3);
+    }
+
+    #[test]
+    fn percentile_of_no_frames_is_zero() {
+        let stats = FrameStats::new();
+
+        assert_eq!(stats.percentile_ms(0.0), 0.0);
+        assert_eq!(stats.percentile_ms(99.0), 0.0);
+    }
+
+    #[test]
+    fn percentile_ignores_insertion_order() {
+        let mut stats = FrameStats::new();
+        //秒にしておけばミリ秒への変換で誤差が出ない
+        stats
+            .frame_times
+            .extend([4, 1, 3, 2].map(Duration::from_secs));
+
+        assert_eq!(stats.percentile_ms(0.0), 1000.0);
+        assert_eq!(stats.percentile_ms(50.0), 2000.0);
+        assert_eq!(stats.percentile_ms(100.0), 4000.0);
+    }
+}
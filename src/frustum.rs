@@ -0,0 +1,209 @@
+use crate::mesh::Vertex;
+use glam::{Mat4, Vec3, Vec4};
+
+//軸に沿ったバウンディングボックス
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    //頂点が無い場合は原点の点になる
+    pub fn from_vertices(vertices: &[Vertex]) -> Self {
+        if vertices.is_empty() {
+            return Self {
+                min: Vec3::ZERO,
+                max: Vec3::ZERO,
+            };
+        }
+
+        vertices.iter().fold(
+            Self {
+                min: Vec3::splat(f32::MAX),
+                max: Vec3::splat(f32::MIN),
+            },
+            |aabb, vertex| {
+                let position = Vec3::from(vertex.position);
+
+                Self {
+                    min: aabb.min.min(position),
+                    max: aabb.max.max(position),
+                }
+            },
+        )
+    }
+
+    //変換後の8つの頂点を全て含むボックスを返す
+    //中心を変換し、半分の大きさには行列の各軸の絶対値を掛けて足すと8頂点を変換するのと同じ結果になる
+    pub fn transform(&self, matrix: Mat4) -> Self {
+        let center = (self.min + self.max) * 0.5;
+        let extent = (self.max - self.min) * 0.5;
+
+        let center = matrix.transform_point3(center);
+        let extent = matrix.x_axis.truncate().abs() * extent.x
+            + matrix.y_axis.truncate().abs() * extent.y
+            + matrix.z_axis.truncate().abs() * extent.z;
+
+        Self {
+            min: center - extent,
+            max: center + extent,
+        }
+    }
+}
+
+//視錐台の6つの平面
+//xyzが内側を向いた長さ1の法線で、dot(xyz, p) + wが負の点は外側にある
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    //クリップ座標で-w <= x <= w、-w <= y <= w、0 <= z <= wとなる範囲を行列の行から求める
+    //VulkanはOpenGLと違って深度が0から1なのでnearはz >= 0になる
+    pub fn from_view_proj(view_proj: Mat4) -> Self {
+        let [row0, row1, row2, row3] = [0, 1, 2, 3].map(|index| view_proj.row(index));
+
+        let planes = [
+            //left, right
+            row3 + row0,
+            row3 - row0,
+            //bottom, top、プロジェクション行列でY軸を反転させていても2つの組は変わらない
+            row3 + row1,
+            row3 - row1,
+            //near, far
+            row2,
+            row3 - row2,
+        ]
+        .map(|plane| plane / plane.truncate().length());
+
+        Self { planes }
+    }
+
+    //どれか1つの平面の完全に外側にある場合だけfalseを返す
+    //角の近くでは外側にあっても残る場合があるが、描画する分には問題ない
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            //法線の向きに一番遠い頂点が外側なら全ての頂点が外側にある
+            let farthest = Vec3::select(normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+
+            normal.dot(farthest) + plane.w >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aabb(min: [f32; 3], max: [f32; 3]) -> Aabb {
+        Aabb {
+            min: Vec3::from(min),
+            max: Vec3::from(max),
+        }
+    }
+
+    fn assert_planes(frustum: &Frustum, expected: [Vec4; 6]) {
+        for (plane, expected) in frustum.planes.iter().zip(expected) {
+            assert!(
+                plane.abs_diff_eq(expected, 1e-5),
+                "{:?} != {:?}",
+                plane,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn identity_gives_clip_space_box() {
+        let frustum = Frustum::from_view_proj(Mat4::IDENTITY);
+
+        //-1 <= x <= 1、-1 <= y <= 1、0 <= z <= 1
+        assert_planes(
+            &frustum,
+            [
+                Vec4::new(1.0, 0.0, 0.0, 1.0),
+                Vec4::new(-1.0, 0.0, 0.0, 1.0),
+                Vec4::new(0.0, 1.0, 0.0, 1.0),
+                Vec4::new(0.0, -1.0, 0.0, 1.0),
+                Vec4::new(0.0, 0.0, 1.0, 0.0),
+                Vec4::new(0.0, 0.0, -1.0, 1.0),
+            ],
+        );
+    }
+
+    #[test]
+    fn planes_are_normalized() {
+        //xとyを2倍すると-0.5 <= x <= 0.5になり、法線の長さで割ってwが0.5になる
+        let frustum = Frustum::from_view_proj(Mat4::from_scale(Vec3::new(2.0, 2.0, 1.0)));
+
+        assert_planes(
+            &frustum,
+            [
+                Vec4::new(1.0, 0.0, 0.0, 0.5),
+                Vec4::new(-1.0, 0.0, 0.0, 0.5),
+                Vec4::new(0.0, 1.0, 0.0, 0.5),
+                Vec4::new(0.0, -1.0, 0.0, 0.5),
+                Vec4::new(0.0, 0.0, 1.0, 0.0),
+                Vec4::new(0.0, 0.0, -1.0, 1.0),
+            ],
+        );
+    }
+
+    #[test]
+    fn perspective_planes() {
+        //画角90度、アスペクト比1、near 1、far 10では、側面が45度に傾き、-zの向きを見る
+        let frustum =
+            Frustum::from_view_proj(Mat4::perspective_rh(90.0_f32.to_radians(), 1.0, 1.0, 10.0));
+        let side = std::f32::consts::FRAC_1_SQRT_2;
+
+        assert_planes(
+            &frustum,
+            [
+                Vec4::new(side, 0.0, -side, 0.0),
+                Vec4::new(-side, 0.0, -side, 0.0),
+                Vec4::new(0.0, side, -side, 0.0),
+                Vec4::new(0.0, -side, -side, 0.0),
+                //z <= -1
+                Vec4::new(0.0, 0.0, -1.0, -1.0),
+                //z >= -10
+                Vec4::new(0.0, 0.0, 1.0, 10.0),
+            ],
+        );
+    }
+
+    #[test]
+    fn aabb_against_perspective_frustum() {
+        let frustum =
+            Frustum::from_view_proj(Mat4::perspective_rh(90.0_f32.to_radians(), 1.0, 1.0, 10.0));
+
+        //z = -5では-5 <= x <= 5の範囲が見える
+        assert!(frustum.intersects_aabb(&aabb([-1.0, -1.0, -6.0], [1.0, 1.0, -4.0])));
+        //nearより手前とfarより奥
+        assert!(!frustum.intersects_aabb(&aabb([-0.1, -0.1, -0.9], [0.1, 0.1, -0.5])));
+        assert!(!frustum.intersects_aabb(&aabb([-0.1, -0.1, -12.0], [0.1, 0.1, -11.0])));
+        //カメラの後ろ
+        assert!(!frustum.intersects_aabb(&aabb([-1.0, -1.0, 1.0], [1.0, 1.0, 2.0])));
+        //右の平面の完全に外側と、平面をまたぐ物
+        assert!(!frustum.intersects_aabb(&aabb([5.5, -0.5, -5.0], [6.5, 0.5, -5.0])));
+        assert!(frustum.intersects_aabb(&aabb([4.5, -0.5, -5.0], [5.5, 0.5, -5.0])));
+        //視錐台を全て含む大きな物
+        assert!(frustum.intersects_aabb(&aabb([-100.0; 3], [100.0; 3])));
+    }
+
+    #[test]
+    fn transformed_aabb_contains_rotated_box() {
+        let rotated = aabb([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0])
+            .transform(Mat4::from_rotation_y(45.0_f32.to_radians()));
+        let half_diagonal = 2.0_f32.sqrt();
+
+        assert!(rotated
+            .max
+            .abs_diff_eq(Vec3::new(half_diagonal, 1.0, half_diagonal), 1e-5));
+        assert!(rotated
+            .min
+            .abs_diff_eq(Vec3::new(-half_diagonal, -1.0, -half_diagonal), 1e-5));
+    }
+}
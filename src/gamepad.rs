@@ -0,0 +1,238 @@
+use crate::input::{AxisAction, InputState};
+use gilrs::{Axis, Button, Event, EventType, GamepadId, Gilrs};
+use std::time::{Duration, Instant};
+
+//ゲームパッドが1つも繋がっていない間に、接続のイベントを確認する間隔
+//繋がっていない間は毎フレームの処理をこの間隔の時刻の比較だけにする
+const HOTPLUG_INTERVAL: Duration = Duration::from_secs(1);
+
+//--on-demandでゲームパッドが繋がっている間にイベントを読む間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(16);
+
+pub const DEFAULT_DEAD_ZONE: f32 = 0.15;
+
+//設定ファイルの[gamepad]の値
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GamepadSettings {
+    //falseの場合はgilrsを初期化しない
+    pub enabled: bool,
+    //スティックとトリガーの値がこれ以下の場合は0にし、それより大きい値は0から1に広げ直す
+    pub dead_zone: f32,
+    pub sensitivity: AxisSensitivity,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dead_zone: DEFAULT_DEAD_ZONE,
+            sensitivity: AxisSensitivity::default(),
+        }
+    }
+}
+
+//AxisActionごとにデッドゾーンの後の値に掛ける倍率、負の値で向きを反転する
+//InputMap::axisが-1.0から1.0に収めるので、1.0より大きくすると少し倒しただけで最大の速さになる
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AxisSensitivity {
+    pub move_forward: f32,
+    pub move_right: f32,
+    pub move_up: f32,
+    pub look_right: f32,
+    pub look_up: f32,
+}
+
+impl Default for AxisSensitivity {
+    fn default() -> Self {
+        Self {
+            move_forward: 1.0,
+            move_right: 1.0,
+            move_up: 1.0,
+            look_right: 1.0,
+            look_up: 1.0,
+        }
+    }
+}
+
+impl AxisSensitivity {
+    pub fn get(&self, axis: AxisAction) -> f32 {
+        match axis {
+            AxisAction::MoveForward => self.move_forward,
+            AxisAction::MoveRight => self.move_right,
+            AxisAction::MoveUp => self.move_up,
+            AxisAction::LookRight => self.look_right,
+            AxisAction::LookUp => self.look_up,
+        }
+    }
+}
+
+//gilrsのイベントを毎フレーム読み、最後に操作されたゲームパッドの値をInputStateに渡す
+//左スティックで前後左右、右スティックで視点、右と左のトリガーで上下に動かす
+//ボタンはInputMapのgamepad_bindingsでActionになる
+pub struct Gamepads {
+    gilrs: Gilrs,
+    settings: GamepadSettings,
+    //スティックの値を読むゲームパッド、最後にイベントを送ってきたもの
+    active: Option<GamepadId>,
+    connected: usize,
+    next_hotplug_check: Instant,
+}
+
+impl Gamepads {
+    //無効にした場合とgilrsがこのプラットフォームに対応していない場合はNone
+    pub fn new(settings: GamepadSettings) -> Option<Self> {
+        if !settings.enabled {
+            return None;
+        }
+
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(error) => {
+                log::warn!("Gamepads are unavailable: {}", error);
+                return None;
+            }
+        };
+
+        //起動時に繋がっているゲームパッドにはConnectedのイベントが来ないので、gamepadsで数える
+        let connected = gilrs
+            .gamepads()
+            .inspect(|(_, gamepad)| log::info!("Gamepad connected: {}", gamepad.name()))
+            .count();
+        let active = gilrs.gamepads().map(|(id, _)| id).next();
+
+        Some(Self {
+            gilrs,
+            settings,
+            active,
+            connected,
+            next_hotplug_check: Instant::now(),
+        })
+    }
+
+    //InputState::end_frameの後、ウィンドウのイベントとは別にフレームごとに1回呼ぶ
+    pub fn poll(&mut self, input: &mut InputState) {
+        if self.connected == 0 {
+            let now = Instant::now();
+
+            if now < self.next_hotplug_check {
+                return;
+            }
+            self.next_hotplug_check = now + HOTPLUG_INTERVAL;
+        }
+
+        while let Some(Event { id, event, .. }) = self.gilrs.next_event() {
+            match event {
+                EventType::Connected => {
+                    self.connected += 1;
+                    log::info!("Gamepad connected: {}", self.gilrs.gamepad(id).name());
+                    continue;
+                }
+                EventType::Disconnected => {
+                    self.connected = self.connected.saturating_sub(1);
+                    log::info!("Gamepad disconnected: {}", self.gilrs.gamepad(id).name());
+
+                    //抜いたゲームパッドのボタンとスティックを押したままにしない
+                    if self.active == Some(id) {
+                        input.release_gamepad();
+                        self.active = self
+                            .gilrs
+                            .gamepads()
+                            .map(|(id, _)| id)
+                            .find(|&other| other != id);
+                    }
+                    continue;
+                }
+                _ => (),
+            }
+
+            //別のゲームパッドに持ち替えた場合は前のゲームパッドの状態を残さない
+            if self.active != Some(id) {
+                input.release_gamepad();
+                self.active = Some(id);
+            }
+
+            match event {
+                EventType::ButtonPressed(button, _) => input.press_gamepad_button(button),
+                EventType::ButtonReleased(button, _) => input.release_gamepad_button(button),
+                _ => (),
+            }
+        }
+
+        if let Some(id) = self.active {
+            let gamepad = self.gilrs.gamepad(id);
+            let trigger = |button| gamepad.button_data(button).map_or(0.0, |data| data.value());
+
+            //gilrsのスティックのYは上が正
+            for (axis, value) in [
+                (AxisAction::MoveForward, gamepad.value(Axis::LeftStickY)),
+                (AxisAction::MoveRight, gamepad.value(Axis::LeftStickX)),
+                (
+                    AxisAction::MoveUp,
+                    trigger(Button::RightTrigger2) - trigger(Button::LeftTrigger2),
+                ),
+                (AxisAction::LookRight, gamepad.value(Axis::RightStickX)),
+                (AxisAction::LookUp, gamepad.value(Axis::RightStickY)),
+            ] {
+                input.set_gamepad_axis(axis, self.axis_value(axis, value));
+            }
+        }
+    }
+
+    //次にpollを呼ぶ時刻、ゲームパッドのイベントではwinitのイベントループが起きないので--on-demandで使う
+    pub fn next_poll(&self) -> Instant {
+        if self.connected == 0 {
+            self.next_hotplug_check
+        } else {
+            Instant::now() + POLL_INTERVAL
+        }
+    }
+
+    fn axis_value(&self, axis: AxisAction, value: f32) -> f32 {
+        apply_dead_zone(value, self.settings.dead_zone) * self.settings.sensitivity.get(axis)
+    }
+}
+
+//dead_zone以下を0にし、dead_zoneから1.0までを0.0から1.0に広げて、倒し始めで急に動かないようにする
+fn apply_dead_zone(value: f32, dead_zone: f32) -> f32 {
+    let magnitude = value.abs().min(1.0);
+
+    if magnitude <= dead_zone {
+        0.0
+    } else {
+        value.signum() * (magnitude - dead_zone) / (1.0 - dead_zone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_inside_the_dead_zone_are_zero() {
+        assert_eq!(apply_dead_zone(0.1, 0.15), 0.0);
+        assert_eq!(apply_dead_zone(-0.15, 0.15), 0.0);
+        assert_eq!(apply_dead_zone(0.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn values_outside_the_dead_zone_are_rescaled() {
+        assert_eq!(apply_dead_zone(1.0, 0.2), 1.0);
+        assert_eq!(apply_dead_zone(-1.0, 0.2), -1.0);
+        assert!((apply_dead_zone(0.6, 0.2) - 0.5).abs() < 1e-6);
+        assert!((apply_dead_zone(-0.6, 0.2) + 0.5).abs() < 1e-6);
+        //スティックによっては1.0を少し超える値を返す
+        assert_eq!(apply_dead_zone(1.05, 0.2), 1.0);
+    }
+
+    #[test]
+    fn sensitivity_is_per_axis() {
+        let sensitivity = AxisSensitivity {
+            look_up: -0.5,
+            ..AxisSensitivity::default()
+        };
+
+        assert_eq!(sensitivity.get(AxisAction::LookUp), -0.5);
+        assert_eq!(sensitivity.get(AxisAction::LookRight), 1.0);
+        assert_eq!(sensitivity.get(AxisAction::MoveForward), 1.0);
+    }
+}
@@ -0,0 +1,756 @@
+use crate::animation::{
+    Animation, Channel, ChannelValues, Interpolation, Node, Skeleton, Transform,
+};
+use crate::json::{self, Json};
+use crate::mesh::Vertex;
+use crate::obj_loader;
+use crate::skinning::SkinVertex;
+use glam::{Mat4, Quat, Vec3};
+use std::fs;
+use std::path::Path;
+
+//頂点カラーを持たないglTFの色
+const DEFAULT_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+
+//GLBの先頭の4バイトと、チャンクの種類
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const CHUNK_JSON: u32 = 0x4E4F_534A;
+const CHUNK_BIN: u32 = 0x004E_4942;
+
+//primitives[].modeのTRIANGLES、省略された場合もこれになる
+const MODE_TRIANGLES: usize = 4;
+
+//accessors[].componentType
+const BYTE: usize = 5120;
+const UNSIGNED_BYTE: usize = 5121;
+const SHORT: usize = 5122;
+const UNSIGNED_SHORT: usize = 5123;
+const UNSIGNED_INT: usize = 5125;
+const FLOAT: usize = 5126;
+
+//--gltfで読み込むスキンメッシュ、verticesとskin_verticesは同じ順番
+pub struct SkinnedModel {
+    pub vertices: Vec<Vertex>,
+    pub skin_vertices: Vec<SkinVertex>,
+    pub indices: Vec<u32>,
+    pub skeleton: Skeleton,
+    pub animations: Vec<Animation>,
+}
+
+//.gltf(バッファは別のファイル)か.glbから、meshとskinの両方を持つ最初のノードのメッシュを読む
+//OBJと同じく既存のシーンと同じ大きさで見えるように-1..1に収め、その変換はSkeleton::normalizeに入れる
+//1つの頂点に影響するジョイントはJOINTS_0とWEIGHTS_0の4つまで
+pub fn load(path: &Path) -> Result<SkinnedModel, String> {
+    let bytes = fs::read(path).map_err(|error| error.to_string())?;
+
+    let (document, glb_buffer) = if bytes.starts_with(GLB_MAGIC) {
+        parse_glb(&bytes)?
+    } else {
+        let text = std::str::from_utf8(&bytes).map_err(|error| error.to_string())?;
+        (json::parse(text)?, None)
+    };
+
+    let gltf = Gltf {
+        document: &document,
+        buffers: load_buffers(&document, path, glb_buffer)?,
+    };
+
+    let (nodes, order) = load_nodes(&gltf)?;
+
+    let skinned_node = gltf
+        .array("nodes")?
+        .iter()
+        .find(|node| node.field("mesh").is_ok() && node.field("skin").is_ok())
+        .ok_or_else(|| "no node has both a mesh and a skin".to_owned())?;
+
+    let skin = gltf.element("skins", index(skinned_node.field("skin")?)?)?;
+
+    let joints = skin
+        .field("joints")?
+        .as_array()?
+        .iter()
+        .map(|joint| index(joint).and_then(|joint| check_node(joint, nodes.len())))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    //省略された場合は単位行列
+    let inverse_bind_matrices = match optional_index(skin, "inverseBindMatrices")? {
+        Some(accessor) => {
+            let values = gltf.read_accessor(accessor, &[16])?.1;
+            let matrices = values
+                .chunks_exact(16)
+                .map(|values| {
+                    let values = values.iter().map(|&value| value as f32).collect::<Vec<_>>();
+                    Mat4::from_cols_slice(&values)
+                })
+                .collect::<Vec<_>>();
+
+            if matrices.len() != joints.len() {
+                return Err(format!(
+                    "the skin has {} joints but {} inverse bind matrices",
+                    joints.len(),
+                    matrices.len()
+                ));
+            }
+
+            matrices
+        }
+        None => vec![Mat4::IDENTITY; joints.len()],
+    };
+
+    let mesh = gltf.element("meshes", index(skinned_node.field("mesh")?)?)?;
+
+    let mut vertices = vec![];
+    let mut skin_vertices = vec![];
+    let mut indices = vec![];
+
+    for primitive in mesh.optional_array("primitives")? {
+        let mode = optional_index(primitive, "mode")?.unwrap_or(MODE_TRIANGLES);
+
+        if mode != MODE_TRIANGLES {
+            log::warn!("Skipping a glTF primitive with mode {}", mode);
+            continue;
+        }
+
+        load_primitive(
+            &gltf,
+            primitive,
+            joints.len(),
+            &mut vertices,
+            &mut skin_vertices,
+            &mut indices,
+        )?;
+    }
+
+    if indices.is_empty() {
+        return Err("the skinned mesh has no triangles".to_owned());
+    }
+
+    let normalize = obj_loader::normalize_bounds(&mut vertices);
+
+    let animations = load_animations(&gltf, nodes.len())?;
+
+    log::info!(
+        "Loaded {}: {} vertices, {} triangles, {} joints, {} animations",
+        path.display(),
+        vertices.len(),
+        indices.len() / 3,
+        joints.len(),
+        animations.len()
+    );
+
+    Ok(SkinnedModel {
+        vertices,
+        skin_vertices,
+        indices,
+        skeleton: Skeleton {
+            nodes,
+            order,
+            joints,
+            inverse_bind_matrices,
+            normalize,
+        },
+        animations,
+    })
+}
+
+//GLBのヘッダーを確かめ、最初のJSONチャンクと最初のBINチャンクを返す
+fn parse_glb(bytes: &[u8]) -> Result<(Json, Option<Vec<u8>>), String> {
+    let read_u32 = |offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+            .ok_or_else(|| "truncated GLB".to_owned())
+    };
+
+    let version = read_u32(4)?;
+    if version != 2 {
+        return Err(format!("unsupported GLB version {}", version));
+    }
+
+    let length = read_u32(8)?.min(bytes.len());
+
+    let mut document = None;
+    let mut buffer = None;
+    let mut offset = 12;
+
+    //チャンクの大きさは4バイトの倍数なので、次のチャンクはそのまま後ろに続く
+    while offset + 8 <= length {
+        let chunk_length = read_u32(offset)?;
+        let chunk_type = read_u32(offset + 4)? as u32;
+        let data = bytes
+            .get(offset + 8..offset + 8 + chunk_length)
+            .ok_or_else(|| "truncated GLB chunk".to_owned())?;
+
+        match chunk_type {
+            CHUNK_JSON if document.is_none() => {
+                let text = std::str::from_utf8(data).map_err(|error| error.to_string())?;
+                document = Some(json::parse(text)?);
+            }
+            CHUNK_BIN if buffer.is_none() => buffer = Some(data.to_vec()),
+            _ => {}
+        }
+
+        offset += 8 + chunk_length;
+    }
+
+    let document = document.ok_or_else(|| "the GLB has no JSON chunk".to_owned())?;
+
+    Ok((document, buffer))
+}
+
+//uriは.gltfからの相対パス、uriの無い最初のバッファはGLBのBINチャンク
+fn load_buffers(
+    document: &Json,
+    path: &Path,
+    mut glb_buffer: Option<Vec<u8>>,
+) -> Result<Vec<Vec<u8>>, String> {
+    document
+        .optional_array("buffers")?
+        .iter()
+        .enumerate()
+        .map(|(buffer_index, buffer)| {
+            let data = match buffer.field("uri") {
+                Ok(uri) => {
+                    let uri = uri.as_str()?;
+
+                    if uri.starts_with("data:") {
+                        return Err(
+                            "embedded data URIs are not supported, export with a separate .bin or as .glb"
+                                .to_owned(),
+                        );
+                    }
+
+                    let buffer_path = path.parent().unwrap_or_else(|| Path::new("")).join(uri);
+                    fs::read(&buffer_path)
+                        .map_err(|error| format!("{}: {}", buffer_path.display(), error))?
+                }
+                Err(_) => glb_buffer
+                    .take()
+                    .filter(|_| buffer_index == 0)
+                    .ok_or_else(|| format!("buffers[{}] has no data", buffer_index))?,
+            };
+
+            let byte_length = index(buffer.field("byteLength")?)?;
+            if data.len() < byte_length {
+                return Err(format!(
+                    "buffers[{}] is {} bytes, expected {}",
+                    buffer_index,
+                    data.len(),
+                    byte_length
+                ));
+            }
+
+            Ok(data)
+        })
+        .collect()
+}
+
+//ノードの初期の姿勢と親、親が子より先に来る順番
+fn load_nodes(gltf: &Gltf) -> Result<(Vec<Node>, Vec<usize>), String> {
+    let nodes = gltf.array("nodes")?;
+    let mut parents = vec![None; nodes.len()];
+
+    for (node_index, node) in nodes.iter().enumerate() {
+        for child in node.optional_array("children")? {
+            let child = check_node(index(child)?, nodes.len())?;
+
+            if parents[child].is_some() {
+                return Err(format!("nodes[{}] has more than one parent", child));
+            }
+
+            parents[child] = Some(node_index);
+        }
+    }
+
+    let mut order = vec![];
+    let mut stack = (0..nodes.len())
+        .rev()
+        .filter(|&node| parents[node].is_none())
+        .collect::<Vec<_>>();
+
+    while let Some(node) = stack.pop() {
+        order.push(node);
+
+        for child in nodes[node].optional_array("children")?.iter().rev() {
+            stack.push(index(child)?);
+        }
+    }
+
+    //親が1つまでなので、根から辿れないノードは循環している
+    if order.len() != nodes.len() {
+        return Err("the node hierarchy has a cycle".to_owned());
+    }
+
+    let nodes = nodes
+        .iter()
+        .zip(parents)
+        .map(|(node, parent)| {
+            Ok(Node {
+                parent,
+                transform: node_transform(node)?,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok((nodes, order))
+}
+
+//matrixか、translation, rotation, scaleのうち指定されたもの
+fn node_transform(node: &Json) -> Result<Transform, String> {
+    if let Some(matrix) = optional_numbers(node, "matrix", 16)? {
+        let (scale, rotation, translation) =
+            Mat4::from_cols_slice(&matrix).to_scale_rotation_translation();
+
+        return Ok(Transform {
+            translation,
+            rotation,
+            scale,
+        });
+    }
+
+    Ok(Transform {
+        translation: optional_numbers(node, "translation", 3)?
+            .map_or(Vec3::ZERO, |values| Vec3::from_slice(&values)),
+        rotation: optional_numbers(node, "rotation", 4)?
+            .map_or(Quat::IDENTITY, |values| Quat::from_slice(&values)),
+        scale: optional_numbers(node, "scale", 3)?
+            .map_or(Vec3::ONE, |values| Vec3::from_slice(&values)),
+    })
+}
+
+//primitiveの頂点とインデックスを後ろに追加する
+//インデックスはverticesの先頭からの番号にする
+fn load_primitive(
+    gltf: &Gltf,
+    primitive: &Json,
+    joint_count: usize,
+    vertices: &mut Vec<Vertex>,
+    skin_vertices: &mut Vec<SkinVertex>,
+    indices: &mut Vec<u32>,
+) -> Result<(), String> {
+    let attributes = primitive.field("attributes")?;
+
+    let position_accessor = optional_index(attributes, "POSITION")?
+        .ok_or_else(|| "a primitive has no POSITION".to_owned())?;
+    let positions = gltf.read_accessor(position_accessor, &[3])?.1;
+    let vertex_count = positions.len() / 3;
+
+    //要素の数がPOSITIONと同じことを確かめる
+    let attribute = |name: &str, widths: &[usize]| -> Result<Option<(usize, Vec<f64>)>, String> {
+        let accessor = match optional_index(attributes, name)? {
+            Some(accessor) => accessor,
+            None => return Ok(None),
+        };
+
+        let (width, values) = gltf.read_accessor(accessor, widths)?;
+
+        if values.len() != vertex_count * width {
+            return Err(format!("{} has a different count from POSITION", name));
+        }
+
+        Ok(Some((width, values)))
+    };
+
+    let normals = attribute("NORMAL", &[3])?;
+    let colors = attribute("COLOR_0", &[3, 4])?;
+    let tex_coords = attribute("TEXCOORD_0", &[2])?;
+    let joints = attribute("JOINTS_0", &[4])?
+        .ok_or_else(|| "the skinned primitive has no JOINTS_0".to_owned())?
+        .1;
+    let weights = attribute("WEIGHTS_0", &[4])?
+        .ok_or_else(|| "the skinned primitive has no WEIGHTS_0".to_owned())?
+        .1;
+
+    if optional_index(attributes, "JOINTS_1")?.is_some() {
+        log::warn!("Only the first 4 joint influences of each glTF vertex are used");
+    }
+
+    let base = vertices.len();
+
+    for i in 0..vertex_count {
+        let vec3 = |values: &[f64], width: usize| {
+            [
+                values[width * i] as f32,
+                values[width * i + 1] as f32,
+                values[width * i + 2] as f32,
+            ]
+        };
+
+        vertices.push(Vertex {
+            position: vec3(&positions, 3),
+            color: colors
+                .as_ref()
+                .map_or(DEFAULT_COLOR, |(width, values)| vec3(values, *width)),
+            normal: normals
+                .as_ref()
+                .map_or([0.0; 3], |(width, values)| vec3(values, *width)),
+            tex_coord: tex_coords.as_ref().map_or([0.0; 2], |(_, values)| {
+                [values[2 * i] as f32, values[2 * i + 1] as f32]
+            }),
+            tangent: Vertex::NO_TANGENT,
+        });
+
+        let mut skin_vertex = SkinVertex {
+            joints: [0; 4],
+            weights: [0.0; 4],
+        };
+
+        for influence in 0..4 {
+            let joint = joints[4 * i + influence] as usize;
+            let weight = weights[4 * i + influence] as f32;
+
+            //重みが0のジョイントは読まないが、シェーダーが範囲外を読まないように確かめる
+            if joint >= joint_count {
+                return Err(format!(
+                    "a vertex refers to joint {} of {}",
+                    joint, joint_count
+                ));
+            }
+
+            skin_vertex.joints[influence] = joint as u32;
+            skin_vertex.weights[influence] = weight;
+        }
+
+        //書き出したツールによっては合計が1からずれるので正規化する
+        let total = skin_vertex.weights.iter().sum::<f32>();
+        if total > 0.0 {
+            skin_vertex.weights = skin_vertex.weights.map(|weight| weight / total);
+        }
+
+        skin_vertices.push(skin_vertex);
+    }
+
+    let first_index = indices.len();
+
+    match optional_index(primitive, "indices")? {
+        Some(accessor) => {
+            for value in gltf.read_accessor(accessor, &[1])?.1 {
+                let value = value as usize;
+
+                if value >= vertex_count {
+                    return Err(format!(
+                        "index {} is out of range of {} vertices",
+                        value, vertex_count
+                    ));
+                }
+
+                indices.push((base + value) as u32);
+            }
+        }
+        None => indices.extend((base..base + vertex_count).map(|index| index as u32)),
+    }
+
+    if normals.is_none() {
+        obj_loader::generate_smooth_normals(
+            &mut vertices[base..],
+            &indices[first_index..],
+            base as u32,
+        );
+    }
+
+    Ok(())
+}
+
+//アニメーションの無いモデルは空にする
+//変形のターゲットのweightsは使わないので無視する
+fn load_animations(gltf: &Gltf, node_count: usize) -> Result<Vec<Animation>, String> {
+    let mut has_cubic_spline = false;
+
+    let animations = gltf
+        .document
+        .optional_array("animations")?
+        .iter()
+        .enumerate()
+        .map(|(animation_index, animation)| {
+            let name = animation
+                .field("name")
+                .and_then(Json::as_str)
+                .map_or_else(|_| format!("animation {}", animation_index), str::to_owned);
+
+            let samplers = animation.optional_array("samplers")?;
+            let mut channels = vec![];
+
+            for channel in animation.optional_array("channels")? {
+                let target = channel.field("target")?;
+
+                let node = match optional_index(target, "node")? {
+                    Some(node) => check_node(node, node_count)?,
+                    None => continue,
+                };
+
+                let path = target.field("path")?.as_str()?;
+                let width = match path {
+                    "translation" | "scale" => 3,
+                    "rotation" => 4,
+                    _ => continue,
+                };
+
+                let sampler = samplers
+                    .get(index(channel.field("sampler")?)?)
+                    .ok_or_else(|| "a channel refers to a missing sampler".to_owned())?;
+
+                let times = gltf
+                    .read_accessor(index(sampler.field("input")?)?, &[1])?
+                    .1
+                    .into_iter()
+                    .map(|time| time as f32)
+                    .collect::<Vec<_>>();
+
+                let values = gltf
+                    .read_accessor(index(sampler.field("output")?)?, &[width])?
+                    .1;
+
+                let interpolation = sampler.field("interpolation").and_then(Json::as_str);
+                let cubic_spline = interpolation == Ok("CUBICSPLINE");
+                has_cubic_spline |= cubic_spline;
+
+                let interpolation = match interpolation {
+                    Ok("STEP") => Interpolation::Step,
+                    _ => Interpolation::Linear,
+                };
+
+                //CUBICSPLINEは各キーフレームに(入りの接線, 値, 出の接線)が並ぶので、値だけを線形に補間する
+                let values = if cubic_spline {
+                    values
+                        .chunks_exact(width * 3)
+                        .flat_map(|keyframe| keyframe[width..width * 2].to_vec())
+                        .collect()
+                } else {
+                    values
+                };
+
+                if times.is_empty() {
+                    continue;
+                }
+
+                if values.len() != times.len() * width {
+                    return Err(format!(
+                        "a {} channel has {} keyframes but {} values",
+                        path,
+                        times.len(),
+                        values.len() / width
+                    ));
+                }
+
+                let values = values.iter().map(|&value| value as f32).collect::<Vec<_>>();
+                let vectors =
+                    || -> Vec<Vec3> { values.chunks_exact(3).map(Vec3::from_slice).collect() };
+
+                let values = match path {
+                    "translation" => ChannelValues::Translation(vectors()),
+                    "scale" => ChannelValues::Scale(vectors()),
+                    _ => ChannelValues::Rotation(
+                        values
+                            .chunks_exact(4)
+                            .map(|values| Quat::from_slice(values).normalize())
+                            .collect(),
+                    ),
+                };
+
+                channels.push(Channel {
+                    node,
+                    interpolation,
+                    times,
+                    values,
+                });
+            }
+
+            let duration = channels
+                .iter()
+                .filter_map(|channel| channel.times.last())
+                .fold(0.0, |duration: f32, &time| duration.max(time));
+
+            Ok(Animation {
+                name,
+                channels,
+                duration,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    if has_cubic_spline {
+        log::warn!("CUBICSPLINE glTF animations are interpolated linearly");
+    }
+
+    Ok(animations)
+}
+
+//glTFのJSONと読み込んだバッファ
+struct Gltf<'a> {
+    document: &'a Json,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl<'a> Gltf<'a> {
+    fn array(&self, name: &str) -> Result<&'a [Json], String> {
+        self.document.optional_array(name)
+    }
+
+    fn element(&self, name: &str, element: usize) -> Result<&'a Json, String> {
+        self.array(name)?
+            .get(element)
+            .ok_or_else(|| format!("{}[{}] does not exist", name, element))
+    }
+
+    //(要素ごとの値の数, 全ての要素の値)、要素の値の数はwidthsのどれかでなければErrを返す
+    //normalizedの整数は符号無しなら0..1、符号付きなら-1..1にする
+    fn read_accessor(
+        &self,
+        accessor: usize,
+        widths: &[usize],
+    ) -> Result<(usize, Vec<f64>), String> {
+        let name = format!("accessors[{}]", accessor);
+        let accessor = self.element("accessors", accessor)?;
+
+        if accessor.field("sparse").is_ok() {
+            return Err(format!("{} is sparse, which is not supported", name));
+        }
+
+        let count = index(accessor.field("count")?)?;
+
+        let width = match accessor.field("type")?.as_str()? {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            "MAT4" => 16,
+            other => return Err(format!("{} has unsupported type {}", name, other)),
+        };
+
+        if !widths.contains(&width) {
+            return Err(format!("{} has an unexpected type", name));
+        }
+
+        let component_type = index(accessor.field("componentType")?)?;
+        let component_size = match component_type {
+            BYTE | UNSIGNED_BYTE => 1,
+            SHORT | UNSIGNED_SHORT => 2,
+            UNSIGNED_INT | FLOAT => 4,
+            _ => {
+                return Err(format!(
+                    "{} has unsupported component type {}",
+                    name, component_type
+                ))
+            }
+        };
+
+        let normalized = matches!(accessor.field("normalized"), Ok(Json::Bool(true)));
+
+        //bufferViewが無い場合は全て0
+        let view = match optional_index(accessor, "bufferView")? {
+            Some(view) => self.element("bufferViews", view)?,
+            None => return Ok((width, vec![0.0; count * width])),
+        };
+
+        let buffer = self
+            .buffers
+            .get(index(view.field("buffer")?)?)
+            .ok_or_else(|| format!("{} refers to a missing buffer", name))?;
+
+        let element_size = component_size * width;
+        let stride = optional_index(view, "byteStride")?.unwrap_or(element_size);
+        let view_offset = optional_index(view, "byteOffset")?.unwrap_or(0);
+        let view_end = view_offset + index(view.field("byteLength")?)?;
+        let start = view_offset + optional_index(accessor, "byteOffset")?.unwrap_or(0);
+
+        if count > 0 && start + stride * (count - 1) + element_size > view_end.min(buffer.len()) {
+            return Err(format!("{} is out of range of its buffer view", name));
+        }
+
+        let mut values = Vec::with_capacity(count * width);
+
+        for element in 0..count {
+            for component in 0..width {
+                let offset = start + element * stride + component * component_size;
+                let bytes = &buffer[offset..offset + component_size];
+
+                let value = match component_type {
+                    BYTE => normalize_signed(bytes[0] as i8 as f64, 127.0, normalized),
+                    UNSIGNED_BYTE => normalize_unsigned(bytes[0] as f64, 255.0, normalized),
+                    SHORT => normalize_signed(
+                        i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                        32767.0,
+                        normalized,
+                    ),
+                    UNSIGNED_SHORT => normalize_unsigned(
+                        u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                        65535.0,
+                        normalized,
+                    ),
+                    UNSIGNED_INT => {
+                        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+                    }
+                    _ => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                };
+
+                values.push(value);
+            }
+        }
+
+        Ok((width, values))
+    }
+}
+
+fn normalize_signed(value: f64, max: f64, normalized: bool) -> f64 {
+    if normalized {
+        (value / max).max(-1.0)
+    } else {
+        value
+    }
+}
+
+fn normalize_unsigned(value: f64, max: f64, normalized: bool) -> f64 {
+    if normalized {
+        value / max
+    } else {
+        value
+    }
+}
+
+fn index(value: &Json) -> Result<usize, String> {
+    let value = value.as_number()?;
+
+    if value >= 0.0 && value.fract() == 0.0 {
+        Ok(value as usize)
+    } else {
+        Err(format!("expected an index, found {}", value))
+    }
+}
+
+fn optional_index(object: &Json, name: &str) -> Result<Option<usize>, String> {
+    match object.field(name) {
+        Ok(value) => index(value).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+//lenの数の配列
+fn optional_numbers(object: &Json, name: &str, len: usize) -> Result<Option<Vec<f32>>, String> {
+    let values = match object.field(name) {
+        Ok(values) => values.as_array()?,
+        Err(_) => return Ok(None),
+    };
+
+    if values.len() != len {
+        return Err(format!(
+            "'{}' has {} values, expected {}",
+            name,
+            values.len(),
+            len
+        ));
+    }
+
+    values
+        .iter()
+        .map(|value| value.as_number().map(|value| value as f32))
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
+
+fn check_node(node: usize, node_count: usize) -> Result<usize, String> {
+    if node < node_count {
+        Ok(node)
+    } else {
+        Err(format!("nodes[{}] does not exist", node))
+    }
+}
@@ -0,0 +1,258 @@
+#[cfg(feature = "tracy")]
+use crate::one_time_commands::OneTimeCommands;
+#[cfg(feature = "tracy")]
+use crate::synchronization::Synchronization;
+use ash::{vk, Device, Instance};
+use log::info;
+#[cfg(feature = "tracy")]
+use profiling::tracy_client::{Client, GpuContext, GpuContextType, GpuSpan};
+
+//1フレームあたりのタイムスタンプクエリの数(レンダーパスの前と後)
+const QUERIES_PER_FRAME: u32 = 2;
+
+//レンダーパスの前後にタイムスタンプを書き込んでGPU上での処理時間を計測する
+pub struct GpuTimer {
+    query_pool: vk::QueryPool,
+    //1tickあたりのナノ秒
+    timestamp_period: f64,
+    //timestamp_valid_bitsより上位のビットは未定義なのでマスクする
+    timestamp_mask: u64,
+    //フレームごとにクエリが記録されて結果待ちかどうか
+    pending: Vec<bool>,
+    #[cfg(feature = "tracy")]
+    tracy: Option<TracyZones>,
+}
+
+//--features tracyでTracyに接続している場合に、計測した区間をGPUのゾーンとして送る
+//Tracyはcmd_beginとcmd_endの時点でCPU側のゾーンを作り、結果が出た後に送るtickを後から当てはめる
+#[cfg(feature = "tracy")]
+struct TracyZones {
+    context: GpuContext,
+    //フレームごとに記録したが、まだtickを送っていないゾーン
+    spans: Vec<Option<GpuSpan>>,
+}
+
+impl GpuTimer {
+    //タイムスタンプに対応していないキューファミリーの場合はNoneを返す
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        queue_family_index: u32,
+        frames_in_flight: u32,
+    ) -> Option<Self> {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let queue_families =
+            unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+
+        let valid_bits = queue_families[queue_family_index as usize].timestamp_valid_bits;
+
+        if valid_bits == 0 || properties.limits.timestamp_period == 0.0 {
+            log::warn!("Timestamp queries are not supported, GPU timing is disabled");
+            return None;
+        }
+
+        let timestamp_mask = if valid_bits >= 64 {
+            u64::MAX
+        } else {
+            (1u64 << valid_bits) - 1
+        };
+
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(QUERIES_PER_FRAME * frames_in_flight)
+            .build();
+
+        let query_pool = unsafe { device.create_query_pool(&create_info, None).unwrap() };
+
+        info!(
+            "GPU timer: timestamp_period {} ns, valid bits {}",
+            properties.limits.timestamp_period, valid_bits
+        );
+
+        Some(Self {
+            query_pool,
+            timestamp_period: properties.limits.timestamp_period as f64,
+            timestamp_mask,
+            pending: vec![false; frames_in_flight as usize],
+            #[cfg(feature = "tracy")]
+            tracy: None,
+        })
+    }
+
+    //TracyのGPUのコンテキストを作る、最初のフレームを記録する前に呼ぶ
+    //TracyはコンテキストのtickとCPUの時刻を比べて並べるので、一度だけタイムスタンプを書いて完了を待ち今のtickを得る
+    #[cfg(feature = "tracy")]
+    pub fn connect_tracy(
+        &mut self,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+    ) {
+        let client = match Client::running() {
+            Some(client) => client,
+            None => return,
+        };
+
+        let query_pool = self.query_pool;
+
+        let written = one_time_commands.run(device, synchronization, |command_buffer| unsafe {
+            device.cmd_reset_query_pool(command_buffer, query_pool, 0, QUERIES_PER_FRAME);
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                query_pool,
+                0,
+            );
+        });
+
+        let mut timestamp = [0u64];
+
+        let read = written.and_then(|_| unsafe {
+            device.get_query_pool_results(
+                query_pool,
+                0,
+                1,
+                &mut timestamp,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+            )
+        });
+
+        if let Err(error) = read {
+            log::warn!(
+                "Failed to read the calibration timestamp for Tracy: {}",
+                error
+            );
+            return;
+        }
+
+        match client.new_gpu_context(
+            Some("Vulkan"),
+            GpuContextType::Vulkan,
+            (timestamp[0] & self.timestamp_mask) as i64,
+            self.timestamp_period as f32,
+        ) {
+            Ok(context) => {
+                self.tracy = Some(TracyZones {
+                    context,
+                    spans: self.pending.iter().map(|_| None).collect(),
+                })
+            }
+            Err(error) => log::warn!("Failed to create a Tracy GPU context: {:?}", error),
+        }
+    }
+
+    //レンダーパスの開始前に呼ぶ
+    //クエリのリセットはレンダーパスの外で行う必要がある
+    pub fn cmd_begin(&mut self, device: &Device, command_buffer: vk::CommandBuffer, frame: usize) {
+        let first_query = frame as u32 * QUERIES_PER_FRAME;
+
+        unsafe {
+            device.cmd_reset_query_pool(
+                command_buffer,
+                self.query_pool,
+                first_query,
+                QUERIES_PER_FRAME,
+            );
+            //TOP_OF_PIPEは前のコマンドが全てのステージに入る前の時点を指す
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.query_pool,
+                first_query,
+            );
+        }
+
+        //前回の結果を読めずに上書きするゾーンは送らずに捨てる
+        #[cfg(feature = "tracy")]
+        if let Some(tracy) = &mut self.tracy {
+            tracy.spans[frame] = tracy
+                .context
+                .span_alloc("render pass", "record_command_buffer", file!(), line!())
+                .ok();
+        }
+    }
+
+    //レンダーパスの終了後に呼ぶ
+    pub fn cmd_end(&mut self, device: &Device, command_buffer: vk::CommandBuffer, frame: usize) {
+        unsafe {
+            //BOTTOM_OF_PIPEは全てのステージが完了した時点を指す
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.query_pool,
+                frame as u32 * QUERIES_PER_FRAME + 1,
+            );
+        }
+
+        self.pending[frame] = true;
+
+        #[cfg(feature = "tracy")]
+        if let Some(span) = self
+            .tracy
+            .as_mut()
+            .and_then(|tracy| tracy.spans[frame].as_mut())
+        {
+            span.end_zone();
+        }
+    }
+
+    //前回そのフレーム番号で記録した開始と終了の時刻をナノ秒で返す
+    //同じデバイスのキュー同士であれば時刻を比較できるので、処理が重なっていたかどうかを調べられる
+    //WAITを指定しないのでまだ結果が出ていない場合はブロックせずにNoneを返す
+    pub fn read_range_ns(&mut self, device: &Device, frame: usize) -> Option<(f64, f64)> {
+        if !self.pending[frame] {
+            return None;
+        }
+
+        //WITH_AVAILABILITYを指定すると各クエリの値の後ろに利用可能かどうかの値が書き込まれる
+        let mut results = [[0u64; 2]; QUERIES_PER_FRAME as usize];
+
+        let result = unsafe {
+            device.get_query_pool_results(
+                self.query_pool,
+                frame as u32 * QUERIES_PER_FRAME,
+                QUERIES_PER_FRAME,
+                &mut results,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY,
+            )
+        };
+
+        match result {
+            //NOT_READYは一部のクエリの結果がまだ出ていないことを示す
+            Ok(_) | Err(vk::Result::NOT_READY) => {}
+            Err(error) => {
+                log::warn!("Failed to read timestamp queries: {}", error);
+                return None;
+            }
+        }
+
+        let [[begin, begin_available], [end, end_available]] = results;
+
+        if begin_available == 0 || end_available == 0 {
+            return None;
+        }
+
+        self.pending[frame] = false;
+
+        let begin = begin & self.timestamp_mask;
+        let ticks = (end & self.timestamp_mask).wrapping_sub(begin) & self.timestamp_mask;
+
+        #[cfg(feature = "tracy")]
+        if let Some(span) = self
+            .tracy
+            .as_mut()
+            .and_then(|tracy| tracy.spans[frame].take())
+        {
+            span.upload_timestamp(begin as i64, begin.wrapping_add(ticks) as i64);
+        }
+
+        let begin_ns = begin as f64 * self.timestamp_period;
+
+        Some((begin_ns, begin_ns + ticks as f64 * self.timestamp_period))
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe { device.destroy_query_pool(self.query_pool, None) };
+    }
+}
@@ -0,0 +1,415 @@
+use ash::vk;
+use log::info;
+use std::alloc::{self, Layout};
+use std::ffi::c_void;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+//返すポインタの直前に確保した大きさ、アラインメント、スコープを書いておく
+//pfn_freeとpfn_reallocationには大きさとアラインメントが渡されないのでここから読む
+#[derive(Clone, Copy)]
+struct Header {
+    size: usize,
+    alignment: usize,
+    scope: usize,
+}
+
+//VkSystemAllocationScopeはCOMMANDからINSTANCEまでの0から4
+const SCOPES: [(vk::SystemAllocationScope, &str); 5] = [
+    (vk::SystemAllocationScope::COMMAND, "command"),
+    (vk::SystemAllocationScope::OBJECT, "object"),
+    (vk::SystemAllocationScope::CACHE, "cache"),
+    (vk::SystemAllocationScope::DEVICE, "device"),
+    (vk::SystemAllocationScope::INSTANCE, "instance"),
+];
+
+//SystemAllocationScopeごとの数、コールバックは複数のスレッドから呼ばれうるのでアトミックにする
+#[derive(Default)]
+struct ScopeCounters {
+    allocations: AtomicUsize,
+    live_allocations: AtomicUsize,
+    live_bytes: AtomicUsize,
+    peak_bytes: AtomicUsize,
+}
+
+//--track-host-allocationsで、ドライバがホストメモリを確保する時にこちらのコールバックを呼ばせる
+//確保はシステムのアロケータに転送し、SystemAllocationScopeごとの回数と大きさを数える
+//callbacksで作ったvk::AllocationCallbacksはselfを指すので、Boxに入れて動かないようにしておく
+pub struct HostAllocTracker {
+    counters: [ScopeCounters; 5],
+}
+
+impl HostAllocTracker {
+    pub fn new() -> Box<Self> {
+        Box::new(Self {
+            counters: Default::default(),
+        })
+    }
+
+    //instanceやdeviceの作成と破棄で同じものを渡す
+    pub fn callbacks(&self) -> vk::AllocationCallbacks {
+        vk::AllocationCallbacks::builder()
+            .user_data(self as *const Self as *mut c_void)
+            .pfn_allocation(Some(allocation))
+            .pfn_reallocation(Some(reallocation))
+            .pfn_free(Some(free))
+            .build()
+    }
+
+    //instanceを破棄した後に呼ぶ、この時点で解放されていないものはドライバのリーク
+    pub fn report(&self) {
+        for ((_, name), counters) in SCOPES.iter().zip(&self.counters) {
+            info!(
+                "host allocations ({}): {} total, {} live, {} bytes live, {} bytes peak",
+                name,
+                counters.allocations.load(Ordering::Relaxed),
+                counters.live_allocations.load(Ordering::Relaxed),
+                counters.live_bytes.load(Ordering::Relaxed),
+                counters.peak_bytes.load(Ordering::Relaxed)
+            );
+        }
+
+        let live = self
+            .counters
+            .iter()
+            .map(|counters| counters.live_allocations.load(Ordering::Relaxed))
+            .sum::<usize>();
+
+        debug_assert_eq!(live, 0, "{} host allocations were not freed", live);
+    }
+
+    fn record_allocation(&self, header: Header) {
+        let counters = &self.counters[header.scope];
+
+        counters.allocations.fetch_add(1, Ordering::Relaxed);
+        counters.live_allocations.fetch_add(1, Ordering::Relaxed);
+
+        let live_bytes = counters
+            .live_bytes
+            .fetch_add(header.size, Ordering::Relaxed)
+            + header.size;
+        counters.peak_bytes.fetch_max(live_bytes, Ordering::Relaxed);
+    }
+
+    fn record_free(&self, header: Header) {
+        let counters = &self.counters[header.scope];
+
+        counters.live_allocations.fetch_sub(1, Ordering::Relaxed);
+        counters
+            .live_bytes
+            .fetch_sub(header.size, Ordering::Relaxed);
+    }
+}
+
+//Headerを置いても返すポインタがalignmentに揃うように、Headerの分をalignmentの倍数に切り上げる
+//Headerのアラインメントより小さい場合も2の累乗でなければNoneにする
+fn layout(size: usize, alignment: usize) -> Option<(Layout, usize)> {
+    if !alignment.is_power_of_two() {
+        return None;
+    }
+
+    let alignment = alignment.max(std::mem::align_of::<Header>());
+    let offset = std::mem::size_of::<Header>().checked_add(alignment - 1)? / alignment * alignment;
+    let layout = Layout::from_size_align(offset.checked_add(size)?, alignment).ok()?;
+
+    Some((layout, offset))
+}
+
+fn scope_index(scope: vk::SystemAllocationScope) -> usize {
+    SCOPES
+        .iter()
+        .position(|(known, _)| *known == scope)
+        .unwrap_or(0)
+}
+
+unsafe fn allocate(tracker: &HostAllocTracker, header: Header) -> *mut c_void {
+    //alignmentが2の累乗でない場合はLayoutを作れないので確保に失敗したことにする
+    let (layout, offset) = match layout(header.size, header.alignment) {
+        Some(layout) if header.size > 0 => layout,
+        _ => return ptr::null_mut(),
+    };
+
+    let base = alloc::alloc(layout);
+
+    if base.is_null() {
+        return ptr::null_mut();
+    }
+
+    let memory = base.add(offset);
+    ptr::write_unaligned(memory.cast::<Header>().sub(1), header);
+
+    tracker.record_allocation(header);
+
+    memory.cast()
+}
+
+unsafe fn header_of(memory: *mut c_void) -> Header {
+    ptr::read_unaligned(memory.cast::<Header>().sub(1))
+}
+
+unsafe fn deallocate(tracker: &HostAllocTracker, memory: *mut c_void) {
+    let header = header_of(memory);
+    let (layout, offset) =
+        layout(header.size, header.alignment).expect("Header of a host allocation is corrupted");
+
+    tracker.record_free(header);
+
+    alloc::dealloc(memory.cast::<u8>().sub(offset), layout);
+}
+
+unsafe extern "system" fn allocation(
+    user_data: *mut c_void,
+    size: usize,
+    alignment: usize,
+    scope: vk::SystemAllocationScope,
+) -> *mut c_void {
+    let tracker = &*(user_data as *const HostAllocTracker);
+
+    allocate(
+        tracker,
+        Header {
+            size,
+            alignment,
+            scope: scope_index(scope),
+        },
+    )
+}
+
+//originalがnullの場合はallocationと同じ、sizeが0の場合はfreeと同じ
+//それ以外は新しく確保して中身を移し、失敗した場合はoriginalをそのまま残す
+unsafe extern "system" fn reallocation(
+    user_data: *mut c_void,
+    original: *mut c_void,
+    size: usize,
+    alignment: usize,
+    scope: vk::SystemAllocationScope,
+) -> *mut c_void {
+    let tracker = &*(user_data as *const HostAllocTracker);
+
+    if original.is_null() {
+        return allocation(user_data, size, alignment, scope);
+    }
+
+    if size == 0 {
+        deallocate(tracker, original);
+        return ptr::null_mut();
+    }
+
+    let previous = header_of(original);
+
+    let memory = allocate(
+        tracker,
+        Header {
+            size,
+            alignment,
+            scope: scope_index(scope),
+        },
+    );
+
+    if memory.is_null() {
+        return ptr::null_mut();
+    }
+
+    ptr::copy_nonoverlapping(
+        original.cast::<u8>(),
+        memory.cast::<u8>(),
+        previous.size.min(size),
+    );
+    deallocate(tracker, original);
+
+    memory
+}
+
+unsafe extern "system" fn free(user_data: *mut c_void, memory: *mut c_void) {
+    if memory.is_null() {
+        return;
+    }
+
+    deallocate(&*(user_data as *const HostAllocTracker), memory);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //(allocations, live_allocations, live_bytes, peak_bytes)
+    fn counts(tracker: &HostAllocTracker, scope: vk::SystemAllocationScope) -> [usize; 4] {
+        let counters = &tracker.counters[scope_index(scope)];
+
+        [
+            counters.allocations.load(Ordering::Relaxed),
+            counters.live_allocations.load(Ordering::Relaxed),
+            counters.live_bytes.load(Ordering::Relaxed),
+            counters.peak_bytes.load(Ordering::Relaxed),
+        ]
+    }
+
+    //ドライバと同じくcallbacksの関数ポインタから呼ぶ
+    unsafe fn allocate_with(
+        callbacks: &vk::AllocationCallbacks,
+        size: usize,
+        alignment: usize,
+        scope: vk::SystemAllocationScope,
+    ) -> *mut c_void {
+        callbacks.pfn_allocation.unwrap()(callbacks.p_user_data, size, alignment, scope)
+    }
+
+    unsafe fn reallocate_with(
+        callbacks: &vk::AllocationCallbacks,
+        original: *mut c_void,
+        size: usize,
+        alignment: usize,
+        scope: vk::SystemAllocationScope,
+    ) -> *mut c_void {
+        callbacks.pfn_reallocation.unwrap()(callbacks.p_user_data, original, size, alignment, scope)
+    }
+
+    unsafe fn free_with(callbacks: &vk::AllocationCallbacks, memory: *mut c_void) {
+        callbacks.pfn_free.unwrap()(callbacks.p_user_data, memory)
+    }
+
+    #[test]
+    fn header_offset_keeps_the_alignment() {
+        let header = std::mem::size_of::<Header>();
+
+        assert_eq!(layout(10, 1).unwrap().1, header);
+        assert_eq!(layout(10, 64).unwrap().1, 64);
+        assert_eq!(layout(10, 64).unwrap().0.size(), 74);
+        assert_eq!(layout(10, 4096).unwrap().1, 4096);
+        assert!(layout(10, 3).is_none());
+    }
+
+    #[test]
+    fn allocations_are_aligned_and_counted() {
+        let tracker = HostAllocTracker::new();
+        let callbacks = tracker.callbacks();
+        let scope = vk::SystemAllocationScope::OBJECT;
+
+        for (index, alignment) in [1, 2, 8, 16, 64, 256, 4096].into_iter().enumerate() {
+            let size = 100 + index;
+
+            unsafe {
+                let memory = allocate_with(&callbacks, size, alignment, scope);
+
+                assert!(!memory.is_null());
+                assert_eq!(memory as usize % alignment, 0, "alignment {}", alignment);
+                //Headerを壊さずに全体へ書き込める
+                ptr::write_bytes(memory.cast::<u8>(), 0xAB, size);
+                assert_eq!(header_of(memory).size, size);
+                assert_eq!(counts(&tracker, scope)[..3], [index + 1, 1, size]);
+
+                free_with(&callbacks, memory);
+            }
+
+            assert_eq!(counts(&tracker, scope)[1..3], [0, 0]);
+        }
+
+        assert_eq!(counts(&tracker, scope), [7, 0, 0, 106]);
+    }
+
+    #[test]
+    fn failed_allocations_are_not_counted() {
+        let tracker = HostAllocTracker::new();
+        let callbacks = tracker.callbacks();
+        let scope = vk::SystemAllocationScope::COMMAND;
+
+        unsafe {
+            assert!(allocate_with(&callbacks, 16, 3, scope).is_null());
+            assert!(allocate_with(&callbacks, 0, 8, scope).is_null());
+            free_with(&callbacks, ptr::null_mut());
+        }
+
+        assert_eq!(counts(&tracker, scope), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn reallocation_moves_the_contents() {
+        let tracker = HostAllocTracker::new();
+        let callbacks = tracker.callbacks();
+        let scope = vk::SystemAllocationScope::CACHE;
+
+        unsafe {
+            let memory = allocate_with(&callbacks, 100, 8, scope);
+            let bytes = (0..100).collect::<Vec<u8>>();
+            ptr::copy_nonoverlapping(bytes.as_ptr(), memory.cast::<u8>(), bytes.len());
+
+            //移す間は古い方も残っているのでpeakは両方の合計になる
+            let grown = reallocate_with(&callbacks, memory, 300, 256, scope);
+            assert_eq!(grown as usize % 256, 0);
+            assert_eq!(
+                std::slice::from_raw_parts(grown.cast::<u8>(), 100),
+                &bytes[..]
+            );
+            assert_eq!(counts(&tracker, scope), [2, 1, 300, 400]);
+
+            let shrunk = reallocate_with(&callbacks, grown, 10, 64, scope);
+            assert_eq!(shrunk as usize % 64, 0);
+            assert_eq!(
+                std::slice::from_raw_parts(shrunk.cast::<u8>(), 10),
+                &bytes[..10]
+            );
+            assert_eq!(counts(&tracker, scope), [3, 1, 10, 400]);
+
+            //大きさ0はfreeと同じ
+            assert!(reallocate_with(&callbacks, shrunk, 0, 64, scope).is_null());
+        }
+
+        assert_eq!(counts(&tracker, scope), [3, 0, 0, 400]);
+    }
+
+    #[test]
+    fn reallocation_from_null_allocates() {
+        let tracker = HostAllocTracker::new();
+        let callbacks = tracker.callbacks();
+        let scope = vk::SystemAllocationScope::DEVICE;
+
+        unsafe {
+            let memory = reallocate_with(&callbacks, ptr::null_mut(), 32, 16, scope);
+
+            assert!(!memory.is_null());
+            assert_eq!(memory as usize % 16, 0);
+            assert_eq!(counts(&tracker, scope), [1, 1, 32, 32]);
+
+            //新しく確保できなかった場合は元のものを残す
+            assert!(reallocate_with(&callbacks, memory, 64, 3, scope).is_null());
+            assert_eq!(counts(&tracker, scope), [1, 1, 32, 32]);
+
+            free_with(&callbacks, memory);
+        }
+
+        assert_eq!(counts(&tracker, scope)[1..3], [0, 0]);
+    }
+
+    #[test]
+    fn scopes_are_counted_separately() {
+        let tracker = HostAllocTracker::new();
+        let callbacks = tracker.callbacks();
+
+        unsafe {
+            let command = allocate_with(&callbacks, 8, 8, vk::SystemAllocationScope::COMMAND);
+            let instance = allocate_with(&callbacks, 24, 8, vk::SystemAllocationScope::INSTANCE);
+
+            assert_eq!(
+                counts(&tracker, vk::SystemAllocationScope::COMMAND),
+                [1, 1, 8, 8]
+            );
+            assert_eq!(
+                counts(&tracker, vk::SystemAllocationScope::INSTANCE),
+                [1, 1, 24, 24]
+            );
+            assert_eq!(
+                counts(&tracker, vk::SystemAllocationScope::OBJECT),
+                [0, 0, 0, 0]
+            );
+
+            free_with(&callbacks, command);
+            free_with(&callbacks, instance);
+        }
+
+        //知らないスコープはCOMMANDとして数える
+        assert_eq!(
+            scope_index(vk::SystemAllocationScope::from_raw(42)),
+            scope_index(vk::SystemAllocationScope::COMMAND)
+        );
+    }
+}
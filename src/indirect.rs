@@ -0,0 +1,112 @@
+use crate::buffer;
+use crate::memory_budget;
+use ash::{vk, Device, Instance};
+use std::mem;
+
+const STRIDE: u32 = mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+
+//cmd_draw_indexed_indirectで読むDrawIndexedIndirectCommandの配列
+//今はCPUから書き込んでいるが、後でコンピュートシェーダーから書き込めるようにSTORAGE_BUFFERとしても使えるようにしておく
+pub struct IndirectDrawBuffer {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped: *mut vk::DrawIndexedIndirectCommand,
+    capacity: u32,
+    draw_count: u32,
+    //1回のcmd_draw_indexed_indirectで発行できるドローの数
+    //multi_draw_indirectが無効な場合は1になる
+    max_draw_count: u32,
+}
+
+impl IndirectDrawBuffer {
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        capacity: u32,
+        multi_draw_indirect: bool,
+    ) -> Self {
+        let size = (STRIDE * capacity) as vk::DeviceSize;
+
+        let (buffer, memory) = buffer::create_buffer(
+            instance,
+            physical_device,
+            device,
+            size,
+            vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        );
+
+        let mapped = unsafe {
+            device
+                .map_memory(memory, 0, size, vk::MemoryMapFlags::empty())
+                .unwrap() as *mut vk::DrawIndexedIndirectCommand
+        };
+
+        let max_draw_count = if multi_draw_indirect {
+            let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+            properties.limits.max_draw_indirect_count.max(1)
+        } else {
+            log::warn!(
+                "multi_draw_indirect is not supported, indirect draws are issued one by one"
+            );
+            1
+        };
+
+        Self {
+            buffer,
+            memory,
+            mapped,
+            capacity,
+            draw_count: 0,
+            max_draw_count,
+        }
+    }
+
+    //GPUが読んでいる間に書き換えないように、使っているフレームの完了を待ってから呼ぶ
+    pub fn write(&mut self, commands: &[vk::DrawIndexedIndirectCommand]) {
+        assert!(
+            commands.len() as u32 <= self.capacity,
+            "{} indirect commands exceed capacity {}",
+            commands.len(),
+            self.capacity
+        );
+
+        unsafe {
+            self.mapped
+                .copy_from_nonoverlapping(commands.as_ptr(), commands.len())
+        };
+        self.draw_count = commands.len() as u32;
+    }
+
+    //頂点バッファとインデックスバッファはバインドされている前提
+    pub fn cmd_draw(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let mut first = 0;
+
+        //max_draw_countごとに分けて発行する
+        while first < self.draw_count {
+            let draw_count = (self.draw_count - first).min(self.max_draw_count);
+
+            unsafe {
+                device.cmd_draw_indexed_indirect(
+                    command_buffer,
+                    self.buffer,
+                    (first * STRIDE) as vk::DeviceSize,
+                    draw_count,
+                    STRIDE,
+                )
+            };
+
+            first += draw_count;
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_buffer(self.buffer, None);
+            memory_budget::free_memory(device, self.memory);
+        }
+    }
+}
@@ -0,0 +1,578 @@
+use gilrs::Button;
+use std::collections::{HashMap, HashSet};
+use winit::event::{
+    DeviceEvent, ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent,
+};
+
+//アプリケーション側はキーコードではなくActionで入力を問い合わせる
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    Info,
+    CyclePresentMode,
+    ToggleFullscreen,
+    ToggleWireframe,
+    //SRGBとUNORMのswapchainを切り替えてガンマ補正を確認する
+    ToggleGammaMode,
+    //ライティングなし、拡散反射光のみ、鏡面反射光ありを順番に切り替える
+    CycleLightingMode,
+    //視錐台カリングに使うカメラを固定して、カリングの境界を外から確認する
+    ToggleFrustumFreeze,
+    //--vertex-pullingで頂点入力のパイプラインとvertex pullingのパイプラインを切り替えてGPU時間を比べる
+    ToggleVertexPulling,
+    //--ray-query-shadowsでray queryの影とシャドウマップの影を切り替えて見比べる
+    ToggleRayQueryShadows,
+    //法線、深度、オーバードローのデバッグ表示を順番に切り替える
+    CycleDebugView,
+    //ジオメトリシェーダーで不透明なメッシュの法線を線で重ねる
+    ToggleNormals,
+    //カメラの透視投影と正射影を切り替える
+    ToggleProjection,
+    //--tessellationで平面の分割数を変える
+    RaiseTessellationLevel,
+    LowerTessellationLevel,
+    RaiseFrameLimit,
+    LowerFrameLimit,
+    //シーンを描画する解像度のswapchainに対する倍率を変える
+    RaiseRenderScale,
+    LowerRenderScale,
+    //--post-effect bloomでぼかした画像を足す割合を変える
+    RaiseBloomIntensity,
+    LowerBloomIntensity,
+    //--post-effect tonemapかbloomでトーンマッピングの方法と露出を変える
+    CycleTonemapper,
+    RaiseExposure,
+    LowerExposure,
+    //--normal-mappingで法線マップを使うかどうかを切り替えて比べる
+    ToggleNormalMapping,
+    //--gltfのアニメーションを止めるか、次のアニメーションにするか、再生速度を変える
+    ToggleAnimation,
+    CycleAnimation,
+    RaiseAnimationSpeed,
+    LowerAnimationSpeed,
+    //シミュレーションを止めて、止めている間は1フレームずつ進める
+    TogglePause,
+    AdvanceFrame,
+    //カメラとライティングと表示の切り替えを--sceneのファイルに書き出し、読み込んで戻す
+    SaveScene,
+    LoadScene,
+    //eguiのオーバーレイを表示して、実行中に設定を変えられるようにする
+    ToggleOverlay,
+}
+
+//押した瞬間ではなく-1.0から1.0の値で問い合わせる連続的な入力
+//キーボードでは正の向きと負の向きのキーの組で表すので、他の入力機器も同じ範囲の値を返すようにする
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AxisAction {
+    MoveForward,
+    MoveRight,
+    MoveUp,
+    //ゲームパッドの右スティックでの視点の回転、マウスはcursor_deltaで回す
+    LookRight,
+    LookUp,
+}
+
+//1フレーム分のInputStateの中身、--recordで書き出して--replayで同じ状態に戻す
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputSnapshot {
+    pub pressed: Vec<VirtualKeyCode>,
+    pub just_pressed: Vec<VirtualKeyCode>,
+    pub just_released: Vec<VirtualKeyCode>,
+    pub mouse_pressed: Vec<MouseButton>,
+    pub mouse_just_pressed: Vec<MouseButton>,
+    pub mouse_just_released: Vec<MouseButton>,
+    pub cursor_delta: (f64, f64),
+}
+
+//1回のイベント処理の間に受け取った入力の状態
+//just_pressedとjust_releasedはend_frameを呼ぶまでの間だけ立つ
+//ゲームパッドの値はGamepads::pollが入れる、--recordでは記録しない
+pub struct InputState {
+    pressed: HashSet<VirtualKeyCode>,
+    just_pressed: HashSet<VirtualKeyCode>,
+    just_released: HashSet<VirtualKeyCode>,
+    mouse_pressed: HashSet<MouseButton>,
+    mouse_just_pressed: HashSet<MouseButton>,
+    mouse_just_released: HashSet<MouseButton>,
+    //DeviceEvent::MouseMotionの移動量の合計
+    //カーソルの位置ではなくマウスの生の移動量なのでウィンドウの端で止まらない
+    cursor_delta: (f64, f64),
+    gamepad_pressed: HashSet<Button>,
+    gamepad_just_pressed: HashSet<Button>,
+    //デッドゾーンと倍率を適用した後の値、スティックを戻すまで残る
+    gamepad_axes: HashMap<AxisAction, f32>,
+}
+
+impl InputState {
+    pub fn new() -> Self {
+        Self {
+            pressed: HashSet::new(),
+            just_pressed: HashSet::new(),
+            just_released: HashSet::new(),
+            mouse_pressed: HashSet::new(),
+            mouse_just_pressed: HashSet::new(),
+            mouse_just_released: HashSet::new(),
+            cursor_delta: (0.0, 0.0),
+            gamepad_pressed: HashSet::new(),
+            gamepad_just_pressed: HashSet::new(),
+            gamepad_axes: HashMap::new(),
+        }
+    }
+
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => match state {
+                ElementState::Pressed => {
+                    //キーリピートでもPressedが送られてくるので押しっぱなしの間は一度だけ立てる
+                    if self.pressed.insert(*key) {
+                        self.just_pressed.insert(*key);
+                    }
+                }
+                ElementState::Released => {
+                    if self.pressed.remove(key) {
+                        self.just_released.insert(*key);
+                    }
+                }
+            },
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => {
+                    if self.mouse_pressed.insert(*button) {
+                        self.mouse_just_pressed.insert(*button);
+                    }
+                }
+                ElementState::Released => {
+                    if self.mouse_pressed.remove(button) {
+                        self.mouse_just_released.insert(*button);
+                    }
+                }
+            },
+            //フォーカスが外れている間のReleasedは届かないので押しっぱなしにならないように全て離す
+            WindowEvent::Focused(false) => self.release_all(),
+            _ => (),
+        }
+    }
+
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta } = event {
+            self.cursor_delta.0 += delta.0;
+            self.cursor_delta.1 += delta.1;
+        }
+    }
+
+    pub fn press_gamepad_button(&mut self, button: Button) {
+        if self.gamepad_pressed.insert(button) {
+            self.gamepad_just_pressed.insert(button);
+        }
+    }
+
+    pub fn release_gamepad_button(&mut self, button: Button) {
+        self.gamepad_pressed.remove(&button);
+    }
+
+    pub fn set_gamepad_axis(&mut self, axis: AxisAction, value: f32) {
+        self.gamepad_axes.insert(axis, value);
+    }
+
+    //ゲームパッドを抜いた場合と持ち替えた場合に、ボタンとスティックを全て離す
+    pub fn release_gamepad(&mut self) {
+        self.gamepad_pressed.clear();
+        self.gamepad_just_pressed.clear();
+        self.gamepad_axes.clear();
+    }
+
+    //溜まった入力を処理し終えた後に呼ぶ
+    pub fn end_frame(&mut self) {
+        self.just_pressed.clear();
+        self.just_released.clear();
+        self.mouse_just_pressed.clear();
+        self.mouse_just_released.clear();
+        self.cursor_delta = (0.0, 0.0);
+        self.gamepad_just_pressed.clear();
+    }
+
+    fn release_all(&mut self) {
+        self.just_released.extend(self.pressed.drain());
+        self.mouse_just_released.extend(self.mouse_pressed.drain());
+    }
+
+    pub fn is_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.pressed.contains(&key)
+    }
+
+    pub fn is_just_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.just_pressed.contains(&key)
+    }
+
+    #[allow(dead_code)]
+    pub fn is_just_released(&self, key: VirtualKeyCode) -> bool {
+        self.just_released.contains(&key)
+    }
+
+    pub fn is_mouse_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_pressed.contains(&button)
+    }
+
+    pub fn is_mouse_just_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_just_pressed.contains(&button)
+    }
+
+    pub fn is_mouse_just_released(&self, button: MouseButton) -> bool {
+        self.mouse_just_released.contains(&button)
+    }
+
+    pub fn cursor_delta(&self) -> (f64, f64) {
+        self.cursor_delta
+    }
+
+    pub fn is_gamepad_just_pressed(&self, button: Button) -> bool {
+        self.gamepad_just_pressed.contains(&button)
+    }
+
+    pub fn gamepad_axis(&self, axis: AxisAction) -> f32 {
+        self.gamepad_axes.get(&axis).copied().unwrap_or(0.0)
+    }
+
+    //end_frameの前に呼ぶ
+    pub fn snapshot(&self) -> InputSnapshot {
+        InputSnapshot {
+            pressed: self.pressed.iter().copied().collect(),
+            just_pressed: self.just_pressed.iter().copied().collect(),
+            just_released: self.just_released.iter().copied().collect(),
+            mouse_pressed: self.mouse_pressed.iter().copied().collect(),
+            mouse_just_pressed: self.mouse_just_pressed.iter().copied().collect(),
+            mouse_just_released: self.mouse_just_released.iter().copied().collect(),
+            cursor_delta: self.cursor_delta,
+        }
+    }
+
+    //ウィンドウのイベントの代わりに、snapshotを取った時と同じ状態にする
+    //ゲームパッドの値はsnapshotに無いので、再生中は残さない
+    pub fn restore(&mut self, snapshot: &InputSnapshot) {
+        self.release_gamepad();
+        self.pressed = snapshot.pressed.iter().copied().collect();
+        self.just_pressed = snapshot.just_pressed.iter().copied().collect();
+        self.just_released = snapshot.just_released.iter().copied().collect();
+        self.mouse_pressed = snapshot.mouse_pressed.iter().copied().collect();
+        self.mouse_just_pressed = snapshot.mouse_just_pressed.iter().copied().collect();
+        self.mouse_just_released = snapshot.mouse_just_released.iter().copied().collect();
+        self.cursor_delta = snapshot.cursor_delta;
+    }
+}
+
+//ActionとAxisActionとキーとゲームパッドのボタンの対応
+pub struct InputMap {
+    bindings: Vec<(Action, VirtualKeyCode)>,
+    gamepad_bindings: Vec<(Action, Button)>,
+    //正の向きのキーと負の向きのキー
+    axis_bindings: Vec<(AxisAction, VirtualKeyCode, VirtualKeyCode)>,
+}
+
+impl InputMap {
+    pub fn new() -> Self {
+        Self {
+            bindings: vec![
+                (Action::Quit, VirtualKeyCode::Escape),
+                (Action::Info, VirtualKeyCode::Space),
+                (Action::CyclePresentMode, VirtualKeyCode::V),
+                (Action::ToggleFullscreen, VirtualKeyCode::F11),
+                (Action::ToggleWireframe, VirtualKeyCode::F),
+                (Action::ToggleGammaMode, VirtualKeyCode::G),
+                (Action::CycleLightingMode, VirtualKeyCode::L),
+                (Action::ToggleFrustumFreeze, VirtualKeyCode::C),
+                //Pは一時停止に使う
+                (Action::ToggleVertexPulling, VirtualKeyCode::K),
+                (Action::ToggleRayQueryShadows, VirtualKeyCode::R),
+                (Action::CycleDebugView, VirtualKeyCode::F3),
+                (Action::ToggleNormals, VirtualKeyCode::N),
+                (Action::ToggleProjection, VirtualKeyCode::O),
+                (Action::RaiseTessellationLevel, VirtualKeyCode::Equals),
+                (Action::LowerTessellationLevel, VirtualKeyCode::Minus),
+                (Action::RaiseFrameLimit, VirtualKeyCode::RBracket),
+                (Action::LowerFrameLimit, VirtualKeyCode::LBracket),
+                //=と-はテッセレーションの分割数に使っているのでテンキーの+/-にする
+                (Action::RaiseRenderScale, VirtualKeyCode::NumpadAdd),
+                (Action::LowerRenderScale, VirtualKeyCode::NumpadSubtract),
+                (Action::RaiseBloomIntensity, VirtualKeyCode::PageUp),
+                (Action::LowerBloomIntensity, VirtualKeyCode::PageDown),
+                (Action::CycleTonemapper, VirtualKeyCode::T),
+                (Action::RaiseExposure, VirtualKeyCode::Home),
+                (Action::LowerExposure, VirtualKeyCode::End),
+                (Action::ToggleNormalMapping, VirtualKeyCode::M),
+                (Action::ToggleAnimation, VirtualKeyCode::J),
+                (Action::CycleAnimation, VirtualKeyCode::U),
+                (Action::RaiseAnimationSpeed, VirtualKeyCode::Insert),
+                (Action::LowerAnimationSpeed, VirtualKeyCode::Delete),
+                (Action::TogglePause, VirtualKeyCode::P),
+                (Action::AdvanceFrame, VirtualKeyCode::Period),
+                (Action::SaveScene, VirtualKeyCode::F5),
+                (Action::LoadScene, VirtualKeyCode::F9),
+                (Action::ToggleOverlay, VirtualKeyCode::F1),
+            ],
+            //Xboxの配置でのYボタン
+            gamepad_bindings: vec![(Action::ToggleWireframe, Button::North)],
+            axis_bindings: vec![
+                (
+                    AxisAction::MoveForward,
+                    VirtualKeyCode::W,
+                    VirtualKeyCode::S,
+                ),
+                (AxisAction::MoveRight, VirtualKeyCode::D, VirtualKeyCode::A),
+                (AxisAction::MoveUp, VirtualKeyCode::E, VirtualKeyCode::Q),
+            ],
+        }
+    }
+
+    //ActionとAxisActionに割り当てられた全てのキー、同じキーが2回出てくることもある
+    pub fn keys(&self) -> impl Iterator<Item = VirtualKeyCode> + '_ {
+        self.bindings.iter().map(|&(_, key)| key).chain(
+            self.axis_bindings
+                .iter()
+                .flat_map(|&(_, positive, negative)| [positive, negative]),
+        )
+    }
+
+    //割り当てられたキーの組の値とゲームパッドの値の合計、両方の向きのキーを押している場合は打ち消し合う
+    pub fn axis(&self, input: &InputState, axis: AxisAction) -> f32 {
+        let key_value = |key| if input.is_pressed(key) { 1.0 } else { 0.0 };
+
+        self.axis_bindings
+            .iter()
+            .filter(|(bound, _, _)| *bound == axis)
+            .map(|&(_, positive, negative)| key_value(positive) - key_value(negative))
+            .chain([input.gamepad_axis(axis)])
+            .sum::<f32>()
+            .clamp(-1.0, 1.0)
+    }
+
+    //割り当てられたキーかゲームパッドのボタンのどれかがこのフレームで押されたか
+    pub fn is_triggered(&self, input: &InputState, action: Action) -> bool {
+        self.triggered_actions(input)
+            .any(|triggered| triggered == action)
+    }
+
+    //このフレームで押されたActionを割り当ての順番で返す、キーの後にゲームパッドのボタンを並べる
+    pub fn triggered_actions<'a>(
+        &'a self,
+        input: &'a InputState,
+    ) -> impl Iterator<Item = Action> + 'a {
+        let keys = self
+            .bindings
+            .iter()
+            .filter(move |(_, key)| input.is_just_pressed(*key))
+            .map(|(action, _)| *action);
+        let buttons = self
+            .gamepad_bindings
+            .iter()
+            .filter(move |(_, button)| input.is_gamepad_just_pressed(*button))
+            .map(|(action, _)| *action);
+
+        keys.chain(buttons)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::event::DeviceId;
+
+    //KeyboardInputのmodifiersはwinit 0.26でdeprecatedだが、構造体を作るには指定する必要がある
+    #[allow(deprecated)]
+    fn key_event(key: VirtualKeyCode, state: ElementState) -> WindowEvent<'static> {
+        WindowEvent::KeyboardInput {
+            device_id: unsafe { DeviceId::dummy() },
+            input: KeyboardInput {
+                scancode: 0,
+                state,
+                virtual_keycode: Some(key),
+                modifiers: Default::default(),
+            },
+            is_synthetic: false,
+        }
+    }
+
+    #[allow(deprecated)]
+    fn mouse_event(button: MouseButton, state: ElementState) -> WindowEvent<'static> {
+        WindowEvent::MouseInput {
+            device_id: unsafe { DeviceId::dummy() },
+            state,
+            button,
+            modifiers: Default::default(),
+        }
+    }
+
+    fn press(input: &mut InputState, key: VirtualKeyCode) {
+        input.handle_window_event(&key_event(key, ElementState::Pressed));
+    }
+
+    fn release(input: &mut InputState, key: VirtualKeyCode) {
+        input.handle_window_event(&key_event(key, ElementState::Released));
+    }
+
+    #[test]
+    fn just_pressed_lasts_one_frame() {
+        let mut input = InputState::new();
+
+        press(&mut input, VirtualKeyCode::W);
+        assert!(input.is_pressed(VirtualKeyCode::W));
+        assert!(input.is_just_pressed(VirtualKeyCode::W));
+
+        input.end_frame();
+        assert!(input.is_pressed(VirtualKeyCode::W));
+        assert!(!input.is_just_pressed(VirtualKeyCode::W));
+    }
+
+    #[test]
+    fn key_repeat_does_not_press_again() {
+        let mut input = InputState::new();
+
+        press(&mut input, VirtualKeyCode::F);
+        input.end_frame();
+
+        //押しっぱなしの間に届くキーリピート
+        press(&mut input, VirtualKeyCode::F);
+        press(&mut input, VirtualKeyCode::F);
+        assert!(input.is_pressed(VirtualKeyCode::F));
+        assert!(!input.is_just_pressed(VirtualKeyCode::F));
+    }
+
+    #[test]
+    fn just_released_lasts_one_frame() {
+        let mut input = InputState::new();
+
+        press(&mut input, VirtualKeyCode::F);
+        input.end_frame();
+
+        release(&mut input, VirtualKeyCode::F);
+        assert!(!input.is_pressed(VirtualKeyCode::F));
+        assert!(input.is_just_released(VirtualKeyCode::F));
+
+        input.end_frame();
+        assert!(!input.is_just_released(VirtualKeyCode::F));
+    }
+
+    #[test]
+    fn release_without_press_is_ignored() {
+        let mut input = InputState::new();
+
+        release(&mut input, VirtualKeyCode::F);
+        assert!(!input.is_just_released(VirtualKeyCode::F));
+    }
+
+    #[test]
+    fn press_and_release_in_one_frame_are_both_seen() {
+        let mut input = InputState::new();
+
+        press(&mut input, VirtualKeyCode::Space);
+        release(&mut input, VirtualKeyCode::Space);
+        assert!(!input.is_pressed(VirtualKeyCode::Space));
+        assert!(input.is_just_pressed(VirtualKeyCode::Space));
+        assert!(input.is_just_released(VirtualKeyCode::Space));
+
+        //次のフレームで押し直せばまた立つ
+        input.end_frame();
+        press(&mut input, VirtualKeyCode::Space);
+        assert!(input.is_just_pressed(VirtualKeyCode::Space));
+    }
+
+    #[test]
+    fn losing_focus_releases_everything() {
+        let mut input = InputState::new();
+
+        press(&mut input, VirtualKeyCode::W);
+        press(&mut input, VirtualKeyCode::A);
+        input.handle_window_event(&mouse_event(MouseButton::Right, ElementState::Pressed));
+        input.end_frame();
+
+        input.handle_window_event(&WindowEvent::Focused(false));
+        assert!(!input.is_pressed(VirtualKeyCode::W));
+        assert!(!input.is_pressed(VirtualKeyCode::A));
+        assert!(!input.is_mouse_pressed(MouseButton::Right));
+        assert!(input.is_just_released(VirtualKeyCode::W));
+        assert!(input.is_just_released(VirtualKeyCode::A));
+        assert!(input.is_mouse_just_released(MouseButton::Right));
+
+        //フォーカスが戻った後に押し直すと押した瞬間として扱う
+        input.end_frame();
+        press(&mut input, VirtualKeyCode::W);
+        assert!(input.is_just_pressed(VirtualKeyCode::W));
+    }
+
+    #[test]
+    fn cursor_delta_accumulates_until_end_frame() {
+        let mut input = InputState::new();
+
+        input.handle_device_event(&DeviceEvent::MouseMotion { delta: (3.0, -1.0) });
+        input.handle_device_event(&DeviceEvent::MouseMotion { delta: (2.0, 4.0) });
+        assert_eq!(input.cursor_delta(), (5.0, 3.0));
+
+        input.end_frame();
+        assert_eq!(input.cursor_delta(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn actions_trigger_on_the_press_frame_only() {
+        let map = InputMap::new();
+        let mut input = InputState::new();
+
+        press(&mut input, VirtualKeyCode::F);
+        assert!(map.is_triggered(&input, Action::ToggleWireframe));
+
+        input.end_frame();
+        press(&mut input, VirtualKeyCode::F);
+        assert!(!map.is_triggered(&input, Action::ToggleWireframe));
+    }
+
+    #[test]
+    fn gamepad_buttons_trigger_actions_like_keys() {
+        let map = InputMap::new();
+        let mut input = InputState::new();
+
+        input.press_gamepad_button(Button::North);
+        assert!(map.is_triggered(&input, Action::ToggleWireframe));
+        assert_eq!(
+            map.triggered_actions(&input).collect::<Vec<_>>(),
+            [Action::ToggleWireframe]
+        );
+
+        //押しっぱなしの間は次のフレームで立たない
+        input.end_frame();
+        input.press_gamepad_button(Button::North);
+        assert!(!map.is_triggered(&input, Action::ToggleWireframe));
+
+        input.release_gamepad_button(Button::North);
+        input.press_gamepad_button(Button::North);
+        assert!(map.is_triggered(&input, Action::ToggleWireframe));
+    }
+
+    #[test]
+    fn gamepad_axes_add_to_the_keys() {
+        let map = InputMap::new();
+        let mut input = InputState::new();
+
+        input.set_gamepad_axis(AxisAction::MoveRight, -0.25);
+        assert_eq!(map.axis(&input, AxisAction::MoveRight), -0.25);
+
+        //キーと合わせても-1.0から1.0に収める
+        press(&mut input, VirtualKeyCode::A);
+        assert_eq!(map.axis(&input, AxisAction::MoveRight), -1.0);
+        press(&mut input, VirtualKeyCode::D);
+        assert_eq!(map.axis(&input, AxisAction::MoveRight), -0.25);
+
+        //スティックの値はフレームをまたいで残り、抜いた場合に消える
+        input.set_gamepad_axis(AxisAction::LookUp, 0.5);
+        input.end_frame();
+        assert_eq!(map.axis(&input, AxisAction::LookUp), 0.5);
+
+        input.release_gamepad();
+        assert_eq!(map.axis(&input, AxisAction::LookUp), 0.0);
+        assert_eq!(map.axis(&input, AxisAction::MoveRight), 0.0);
+    }
+}
@@ -0,0 +1,252 @@
+use ash::Entry;
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+
+//instanceで有効にするレイヤーと拡張
+//requireしたものが無い場合はinstanceを作らずにエラーにし、optionalなものは有る場合だけ有効にする
+#[derive(Clone, Debug, Default)]
+pub struct InstanceConfig {
+    required_layers: Vec<CString>,
+    optional_layers: Vec<CString>,
+    required_extensions: Vec<CString>,
+    optional_extensions: Vec<CString>,
+}
+
+//InstanceConfig::resolveで決めた、InstanceCreateInfoに渡す名前
+pub struct EnabledNames {
+    layers: Vec<CString>,
+    extensions: Vec<CString>,
+}
+
+impl InstanceConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn require_layer(mut self, name: &str) -> Self {
+        self.required_layers.push(layer_name(name));
+        self
+    }
+
+    pub fn optional_layer(mut self, name: &str) -> Self {
+        self.optional_layers.push(layer_name(name));
+        self
+    }
+
+    pub fn require_extension(mut self, name: &CStr) -> Self {
+        self.required_extensions.push(name.to_owned());
+        self
+    }
+
+    pub fn optional_extension(mut self, name: &CStr) -> Self {
+        self.optional_extensions.push(name.to_owned());
+        self
+    }
+
+    //ローダーが列挙するレイヤーと拡張に対して有効にするものを決め、ログに出す
+    pub fn resolve(&self, entry: &Entry) -> Result<EnabledNames, Box<dyn Error>> {
+        let available_layers = entry
+            .enumerate_instance_layer_properties()?
+            .iter()
+            .map(|layer| unsafe { CStr::from_ptr(layer.layer_name.as_ptr()) }.to_owned())
+            .collect::<Vec<_>>();
+
+        let available_extensions = entry
+            .enumerate_instance_extension_properties(None)?
+            .iter()
+            .map(|extension| {
+                unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) }.to_owned()
+            })
+            .collect::<Vec<_>>();
+
+        let enabled = self.filter(&available_layers, &available_extensions)?;
+
+        log::info!("Enabled instance layers: {:?}", enabled.layers);
+        log::info!("Enabled instance extensions: {:?}", enabled.extensions);
+
+        Ok(enabled)
+    }
+
+    //resolveのうちVulkanを呼ばない部分
+    fn filter(
+        &self,
+        available_layers: &[CString],
+        available_extensions: &[CString],
+    ) -> Result<EnabledNames, String> {
+        Ok(EnabledNames {
+            layers: select(
+                &self.required_layers,
+                &self.optional_layers,
+                available_layers,
+                "layer",
+            )?,
+            extensions: select(
+                &self.required_extensions,
+                &self.optional_extensions,
+                available_extensions,
+                "extension",
+            )?,
+        })
+    }
+}
+
+impl EnabledNames {
+    //InstanceCreateInfoに渡すポインタ、selfより長く使ってはいけない
+    pub fn layer_ptrs(&self) -> Vec<*const c_char> {
+        self.layers.iter().map(|name| name.as_ptr()).collect()
+    }
+
+    pub fn extension_ptrs(&self) -> Vec<*const c_char> {
+        self.extensions.iter().map(|name| name.as_ptr()).collect()
+    }
+}
+
+//レイヤーの名前はashの定数が無いので文字列で書く
+fn layer_name(name: &str) -> CString {
+    CString::new(name).expect("Failed to build CString")
+}
+
+//requiredは全て含め、optionalはavailableにあるものだけを含める、同じ名前は1つにまとめる
+fn select(
+    required: &[CString],
+    optional: &[CString],
+    available: &[CString],
+    kind: &str,
+) -> Result<Vec<CString>, String> {
+    let missing = required
+        .iter()
+        .filter(|name| !available.contains(name))
+        .map(|name| name.to_string_lossy())
+        .collect::<Vec<_>>();
+
+    if !missing.is_empty() {
+        return Err(format!(
+            "Required instance {} is not available: {}",
+            kind,
+            missing.join(", ")
+        ));
+    }
+
+    let mut selected: Vec<CString> = vec![];
+
+    for name in required
+        .iter()
+        .chain(optional.iter().filter(|name| available.contains(name)))
+    {
+        if !selected.contains(name) {
+            selected.push(name.clone());
+        }
+    }
+
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<CString> {
+        names.iter().map(|name| layer_name(name)).collect()
+    }
+
+    fn c(name: &str) -> CString {
+        layer_name(name)
+    }
+
+    const VALIDATION: &str = "VK_LAYER_KHRONOS_validation";
+    const MONITOR: &str = "VK_LAYER_LUNARG_monitor";
+
+    #[test]
+    fn optional_names_need_to_be_available() {
+        let config = InstanceConfig::new()
+            .require_layer(VALIDATION)
+            .optional_layer(MONITOR)
+            .require_extension(&c("VK_KHR_surface"))
+            .optional_extension(&c("VK_EXT_debug_utils"))
+            .optional_extension(&c("VK_EXT_swapchain_colorspace"));
+
+        let enabled = config
+            .filter(
+                &names(&[VALIDATION]),
+                &names(&["VK_KHR_surface", "VK_EXT_swapchain_colorspace"]),
+            )
+            .unwrap();
+
+        assert_eq!(enabled.layers, names(&[VALIDATION]));
+        assert_eq!(
+            enabled.extensions,
+            names(&["VK_KHR_surface", "VK_EXT_swapchain_colorspace"])
+        );
+    }
+
+    #[test]
+    fn missing_required_names_are_listed() {
+        let config = InstanceConfig::new()
+            .require_extension(&c("VK_KHR_surface"))
+            .require_extension(&c("VK_KHR_xcb_surface"))
+            .require_extension(&c("VK_KHR_wayland_surface"));
+
+        let error = config
+            .filter(&[], &names(&["VK_KHR_surface"]))
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            error,
+            "Required instance extension is not available: VK_KHR_xcb_surface, VK_KHR_wayland_surface"
+        );
+    }
+
+    #[test]
+    fn missing_required_layer_fails_before_extensions() {
+        let config = InstanceConfig::new().require_layer(VALIDATION);
+
+        let error = config.filter(&[], &[]).err().unwrap();
+
+        assert!(error.starts_with("Required instance layer"));
+        assert!(error.contains(VALIDATION));
+    }
+
+    #[test]
+    fn required_names_come_first_without_duplicates() {
+        //optionalを先に足しても、requiredのものが先に並ぶ
+        let selected = select(
+            &names(&["b", "a", "b"]),
+            &names(&["c", "a", "d", "c"]),
+            &names(&["a", "b", "c"]),
+            "extension",
+        )
+        .unwrap();
+
+        assert_eq!(selected, names(&["b", "a", "c"]));
+    }
+
+    #[test]
+    fn nothing_requested_selects_nothing() {
+        let enabled = InstanceConfig::new()
+            .filter(&names(&[VALIDATION]), &names(&["VK_KHR_surface"]))
+            .unwrap();
+
+        assert!(enabled.layers.is_empty());
+        assert!(enabled.extensions.is_empty());
+        assert!(enabled.layer_ptrs().is_empty());
+    }
+
+    #[test]
+    fn pointers_follow_the_selected_order() {
+        let enabled = InstanceConfig::new()
+            .optional_extension(&c("VK_EXT_debug_utils"))
+            .require_extension(&c("VK_KHR_surface"))
+            .filter(&[], &names(&["VK_EXT_debug_utils", "VK_KHR_surface"]))
+            .unwrap();
+
+        let extensions = enabled
+            .extension_ptrs()
+            .into_iter()
+            .map(|ptr| unsafe { CStr::from_ptr(ptr) }.to_str().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(extensions, ["VK_KHR_surface", "VK_EXT_debug_utils"]);
+    }
+}
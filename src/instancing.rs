@@ -0,0 +1,205 @@
+use crate::buffer;
+use crate::indirect::IndirectDrawBuffer;
+use crate::memory_budget;
+use ash::{vk, Device, Instance};
+use std::mem;
+
+//インスタンスごとのデータ
+//Vertexの後ろのlocationに割り当てる
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct InstanceData {
+    //xyzが平行移動、wが拡大率
+    pub offset_scale: [f32; 4],
+    pub color: [f32; 3],
+    //--texture-arrayでサンプリングするレイヤー
+    pub layer: u32,
+}
+
+impl InstanceData {
+    //binding = 1に割り当て、インスタンスが1つ進むごとに1要素進める
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(1)
+            .stride(mem::size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build()
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+        [
+            vk::VertexInputAttributeDescription::builder()
+                .binding(1)
+                .location(3)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(1)
+                .location(4)
+                .format(vk::Format::R32G32B32_SFLOAT)
+                .offset(mem::size_of::<[f32; 4]>() as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(1)
+                .location(5)
+                .format(vk::Format::R32_UINT)
+                .offset(mem::size_of::<[f32; 7]>() as u32)
+                .build(),
+        ]
+    }
+}
+
+//Indirect描画で1つのDrawIndexedIndirectCommandが受け持つインスタンス数
+const INSTANCES_PER_INDIRECT_DRAW: u32 = 1024;
+
+//InstancedGridのドローコールの発行方法
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GridDrawMode {
+    //1回のcmd_draw_indexedで全てのインスタンスを描画する
+    Instanced,
+    //インスタンス描画を使わずに1インスタンスずつ描画して比較する
+    PerInstance,
+    //INSTANCES_PER_INDIRECT_DRAWごとに分けたコマンドをcmd_draw_indexed_indirectで描画する
+    Indirect,
+}
+
+//--instanced-gridで描画するN×Nの三角形
+pub struct InstancedGrid {
+    instance_buffer: vk::Buffer,
+    instance_memory: vk::DeviceMemory,
+    instance_count: u32,
+    draw_mode: GridDrawMode,
+    //GridDrawMode::Indirectの場合のみSome
+    indirect_buffer: Option<IndirectDrawBuffer>,
+}
+
+impl InstancedGrid {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        size: u32,
+        draw_mode: GridDrawMode,
+        //meshのインデックス数、Indirect描画のコマンドに書き込む
+        index_count: u32,
+        multi_draw_indirect: bool,
+        //インスタンスの番号をこの数で割った余りをレイヤーにする
+        layer_count: u32,
+    ) -> Self {
+        //グリッド全体が-1.0から1.0の範囲に収まるようにする
+        let spacing = 2.0 / size as f32;
+
+        let instances = (0..size * size)
+            .map(|index| {
+                let x = (index % size) as f32;
+                let y = (index / size) as f32;
+
+                InstanceData {
+                    offset_scale: [
+                        -1.0 + spacing * (x + 0.5),
+                        -1.0 + spacing * (y + 0.5),
+                        0.0,
+                        //三角形は-1.0から1.0の大きさなのでspacingの8割に収める
+                        spacing * 0.4,
+                    ],
+                    color: [x / size as f32, y / size as f32, 1.0 - x / size as f32],
+                    layer: index % layer_count,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let (instance_buffer, instance_memory) = buffer::create_host_buffer_with_data(
+            instance,
+            physical_device,
+            device,
+            &instances,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        );
+
+        let instance_count = instances.len() as u32;
+
+        let indirect_buffer = if draw_mode == GridDrawMode::Indirect {
+            let commands = (0..instance_count)
+                .step_by(INSTANCES_PER_INDIRECT_DRAW as usize)
+                .map(|first_instance| vk::DrawIndexedIndirectCommand {
+                    index_count,
+                    instance_count: (instance_count - first_instance)
+                        .min(INSTANCES_PER_INDIRECT_DRAW),
+                    first_index: 0,
+                    vertex_offset: 0,
+                    first_instance,
+                })
+                .collect::<Vec<_>>();
+
+            let mut indirect_buffer = IndirectDrawBuffer::new(
+                instance,
+                physical_device,
+                device,
+                commands.len() as u32,
+                multi_draw_indirect,
+            );
+            //インスタンスバッファと同じく内容は変わらないので最初に一度だけ書き込む
+            indirect_buffer.write(&commands);
+
+            Some(indirect_buffer)
+        } else {
+            None
+        };
+
+        log::info!(
+            "Instanced grid: {} instances, {:?}",
+            instance_count,
+            draw_mode
+        );
+
+        Self {
+            instance_buffer,
+            instance_memory,
+            instance_count,
+            draw_mode,
+            indirect_buffer,
+        }
+    }
+
+    //メッシュの頂点バッファはbinding = 0にバインドされている前提
+    pub fn cmd_draw(&self, device: &Device, command_buffer: vk::CommandBuffer, index_count: u32) {
+        unsafe {
+            device.cmd_bind_vertex_buffers(command_buffer, 1, &[self.instance_buffer], &[0]);
+
+            match self.draw_mode {
+                GridDrawMode::Instanced => device.cmd_draw_indexed(
+                    command_buffer,
+                    index_count,
+                    self.instance_count,
+                    0,
+                    0,
+                    0,
+                ),
+                //first_instanceでインスタンスバッファの読み出し位置をずらす
+                GridDrawMode::PerInstance => {
+                    for instance in 0..self.instance_count {
+                        device.cmd_draw_indexed(command_buffer, index_count, 1, 0, 0, instance);
+                    }
+                }
+                GridDrawMode::Indirect => {
+                    if let Some(indirect_buffer) = &self.indirect_buffer {
+                        indirect_buffer.cmd_draw(device, command_buffer);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_buffer(self.instance_buffer, None);
+            memory_budget::free_memory(device, self.instance_memory);
+        }
+
+        if let Some(indirect_buffer) = &self.indirect_buffer {
+            indirect_buffer.destroy(device);
+        }
+    }
+}
@@ -1,11 +1,16 @@
-use ash::extensions::khr::{Surface, Win32Surface};
+use ash::extensions::khr::{Display, Surface, Win32Surface};
 use ash::prelude::VkResult;
 use ash::{vk, Entry, Instance, RawPtr};
+use std::ffi::CStr;
 use std::mem;
 
-#[allow(dead_code)]
-pub fn require_extension_names() -> Vec<*const i8> {
-    let surfaces = vec![Surface::name().as_ptr(), Win32Surface::name().as_ptr()];
+//displayがtrueの場合はウィンドウを使わずにディスプレイへ直接presentするためのVK_KHR_displayも要求する
+pub fn require_extension_names(display: bool) -> Vec<&'static CStr> {
+    let mut surfaces = vec![Surface::name(), Win32Surface::name()];
+
+    if display {
+        surfaces.push(Display::name());
+    }
 
     surfaces
 }
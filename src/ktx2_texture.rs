@@ -0,0 +1,510 @@
+use crate::device_features::EnabledFeatures;
+use crate::one_time_commands::OneTimeCommands;
+use crate::synchronization::Synchronization;
+use crate::texture::{MipLevel, Texture2D};
+use crate::texture_decode;
+use ash::{vk, Device, Instance};
+use basis_universal::{
+    DecodeFlags, LowLevelUastcTranscoder, SliceParametersUastc, TranscoderBlockFormat,
+};
+use ktx2::{Reader, SupercompressionScheme};
+use std::fs;
+use std::path::Path;
+use std::sync::Once;
+
+//Data Format Descriptorの値
+const MODEL_ETC1S: u8 = 163;
+const MODEL_UASTC: u8 = 166;
+const TRANSFER_SRGB: u8 = 2;
+//UASTCのサンプルのchannelType、下位4ビット
+const CHANNEL_UASTC_RGBA: u8 = 3;
+const CHANNEL_UASTC_RRRG: u8 = 5;
+
+//UASTCは4x4のテクセルを16バイトのブロックに入れる
+const UASTC_BLOCK_SIZE: usize = 16;
+
+//Basis Universalのデータを変換する先の候補、優先度の高い順で(sRGB, UNORM)
+//BC7はデスクトップ、ASTCはモバイルのGPUで使える
+//どちらも使えない場合はRGBA8に展開する
+const TRANSCODE_TARGETS: [(vk::Format, vk::Format); 2] = [
+    (vk::Format::BC7_SRGB_BLOCK, vk::Format::BC7_UNORM_BLOCK),
+    (
+        vk::Format::ASTC_4X4_SRGB_BLOCK,
+        vk::Format::ASTC_4X4_UNORM_BLOCK,
+    ),
+];
+
+static TRANSCODER_INIT: Once = Once::new();
+
+//Data Format Descriptorから読んだBasis Universalのデータの種類
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BasisModel {
+    Uastc,
+    //BasisLZで超圧縮されている
+    Etc1s,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct BasisLayout {
+    model: BasisModel,
+    has_alpha: bool,
+    srgb: bool,
+}
+
+//KTX2のコンテナを読んだもの
+//3Dテクスチャ、配列、キューブマップには対応しない
+struct Ktx2File {
+    //Basis Universalの場合はUNDEFINED
+    format: vk::Format,
+    basis: Option<BasisLayout>,
+    //levels[0]が一番大きく、offsetはファイルの先頭からの位置
+    //ファイルの中の各段はブロックの大きさと4の公倍数に揃っているので、そのまま転送に使える
+    levels: Vec<MipLevel>,
+    bytes: Vec<u8>,
+}
+
+//Ktx2File::decode_to_rgba8やtranscode_uastcで展開した全ての段
+struct DecodedLevels {
+    format: vk::Format,
+    levels: Vec<MipLevel>,
+    bytes: Vec<u8>,
+}
+
+impl Ktx2File {
+    fn parse(bytes: Vec<u8>) -> Result<Self, String> {
+        let reader = Reader::new(bytes.as_slice()).map_err(|error| format!("{:?}", error))?;
+        let header = reader.header();
+
+        if header.pixel_depth > 0 || header.layer_count > 0 || header.face_count != 1 {
+            return Err(
+                "Only 2D textures are supported, not 3D, array or cube map textures".to_owned(),
+            );
+        }
+
+        if header.pixel_height == 0 {
+            return Err("1D textures are not supported".to_owned());
+        }
+
+        let format = header.format.map_or(vk::Format::UNDEFINED, |format| {
+            vk::Format::from_raw(format.0.get() as i32)
+        });
+
+        let dfd_start = header.index.dfd_byte_offset as usize;
+        let dfd = bytes
+            .get(dfd_start..dfd_start + header.index.dfd_byte_length as usize)
+            .ok_or_else(|| "The data format descriptor is out of the file".to_owned())?;
+
+        let basis = match header.supercompression_scheme {
+            None if format == vk::Format::UNDEFINED => Some(basis_layout(dfd)?),
+            None => None,
+            Some(scheme) if scheme == SupercompressionScheme::BasisLZ => Some(basis_layout(dfd)?),
+            Some(scheme) => {
+                return Err(format!(
+                    "Supercompression scheme {:?} is not supported",
+                    scheme
+                ))
+            }
+        };
+
+        //段のデータはbytesの中を指すので、先頭からの位置に直す
+        let levels = reader
+            .levels()
+            .enumerate()
+            .map(|(level, data)| MipLevel {
+                extent: vk::Extent2D {
+                    width: (header.pixel_width >> level).max(1),
+                    height: (header.pixel_height >> level).max(1),
+                },
+                offset: (data.as_ptr() as usize - bytes.as_ptr() as usize) as vk::DeviceSize,
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Self {
+            format,
+            basis,
+            levels,
+            bytes,
+        })
+    }
+
+    //全ての段をRGBA8に展開する、このデバイスでサンプリングできないフォーマットのフォールバック
+    fn decode_to_rgba8(&self) -> Result<DecodedLevels, String> {
+        let format = texture_decode::rgba8_format(self.format).ok_or_else(|| {
+            format!(
+                "{:?} cannot be sampled on this device or decoded to RGBA8",
+                self.format
+            )
+        })?;
+
+        let mut levels = Vec::with_capacity(self.levels.len());
+        let mut bytes = Vec::with_capacity(self.rgba8_size() as usize);
+
+        for level in &self.levels {
+            let size = texture_decode::level_size(self.format, level.extent).unwrap();
+            let data = self.level_data(level, size)?;
+
+            levels.push(MipLevel {
+                extent: level.extent,
+                offset: bytes.len() as vk::DeviceSize,
+            });
+            bytes.extend(texture_decode::decode_to_rgba8(
+                self.format,
+                level.extent,
+                data,
+            )?);
+        }
+
+        Ok(DecodedLevels {
+            format,
+            levels,
+            bytes,
+        })
+    }
+
+    //UASTCの全ての段をtargetに変換する
+    //targetはTRANSCODE_TARGETSのどれかか、R8G8B8A8
+    fn transcode_uastc(
+        &self,
+        layout: BasisLayout,
+        target: vk::Format,
+    ) -> Result<DecodedLevels, String> {
+        let block_format = match target {
+            vk::Format::BC7_SRGB_BLOCK | vk::Format::BC7_UNORM_BLOCK => TranscoderBlockFormat::BC7,
+            vk::Format::ASTC_4X4_SRGB_BLOCK | vk::Format::ASTC_4X4_UNORM_BLOCK => {
+                TranscoderBlockFormat::ASTC_4x4
+            }
+            _ => TranscoderBlockFormat::RGBA32,
+        };
+
+        TRANSCODER_INIT.call_once(basis_universal::transcoder_init);
+        let transcoder = LowLevelUastcTranscoder::new();
+
+        let mut levels = Vec::with_capacity(self.levels.len());
+        let mut bytes = Vec::new();
+
+        for level in &self.levels {
+            let (columns, rows) = block_counts(level.extent);
+            let data =
+                self.level_data(level, columns as usize * rows as usize * UASTC_BLOCK_SIZE)?;
+
+            let transcoded = transcoder
+                .transcode_slice(
+                    data,
+                    SliceParametersUastc {
+                        num_blocks_x: columns,
+                        num_blocks_y: rows,
+                        has_alpha: layout.has_alpha,
+                        original_width: level.extent.width,
+                        original_height: level.extent.height,
+                    },
+                    DecodeFlags::HIGH_QUALITY,
+                    block_format,
+                )
+                .map_err(|error| {
+                    format!(
+                        "Failed to transcode mip level {}x{} to {:?}: {:?}",
+                        level.extent.width, level.extent.height, target, error
+                    )
+                })?;
+
+            if transcoded.len() != transcoded_size(block_format, level.extent) {
+                return Err(format!(
+                    "The transcoder returned {} bytes for mip level {}x{}",
+                    transcoded.len(),
+                    level.extent.width,
+                    level.extent.height
+                ));
+            }
+
+            levels.push(MipLevel {
+                extent: level.extent,
+                offset: bytes.len() as vk::DeviceSize,
+            });
+            bytes.extend(transcoded);
+        }
+
+        Ok(DecodedLevels {
+            format: target,
+            levels,
+            bytes,
+        })
+    }
+
+    fn level_data(&self, level: &MipLevel, size: usize) -> Result<&[u8], String> {
+        let start = level.offset as usize;
+
+        self.bytes.get(start..start + size).ok_or_else(|| {
+            format!(
+                "Mip level {}x{} is truncated",
+                level.extent.width, level.extent.height
+            )
+        })
+    }
+
+    //同じ大きさとミップマップの段数をRGBA8で持った場合の大きさ
+    fn rgba8_size(&self) -> vk::DeviceSize {
+        self.levels
+            .iter()
+            .map(|level| {
+                level.extent.width as vk::DeviceSize * level.extent.height as vk::DeviceSize * 4
+            })
+            .sum()
+    }
+}
+
+//UASTCかETC1Sか、アルファを持つか、sRGBかをData Format Descriptorの最初のブロックから読む
+//dfdはdfdTotalSizeから始まるKTX2の中のバイト列
+fn basis_layout(dfd: &[u8]) -> Result<BasisLayout, String> {
+    //dfdTotalSize、ブロックのヘッダー8バイト、colorModelからbytesPlaneまでの16バイト、最初のサンプル16バイト
+    if dfd.len() < 44 {
+        return Err("The data format descriptor is truncated".to_owned());
+    }
+
+    let model = match dfd[12] {
+        MODEL_UASTC => BasisModel::Uastc,
+        MODEL_ETC1S => BasisModel::Etc1s,
+        model => {
+            return Err(format!(
+                "Color model {} with an undefined format is not supported",
+                model
+            ))
+        }
+    };
+
+    //ETC1Sはアルファをもう一つのスライスに入れるので、サンプルが2つあればアルファを持つ
+    let has_alpha = match model {
+        BasisModel::Uastc => {
+            let channel = dfd[31] & 0x0F;
+            channel == CHANNEL_UASTC_RGBA || channel == CHANNEL_UASTC_RRRG
+        }
+        BasisModel::Etc1s => dfd.len() >= 60,
+    };
+
+    Ok(BasisLayout {
+        model,
+        has_alpha,
+        srgb: dfd[14] == TRANSFER_SRGB,
+    })
+}
+
+//4x4のブロックの列と行の数
+fn block_counts(extent: vk::Extent2D) -> (u32, u32) {
+    ((extent.width + 3) / 4, (extent.height + 3) / 4)
+}
+
+//トランスコーダーがその段に書き出すバイト数
+//RGBA32は隙間なく並んだテクセル、それ以外は16バイトの4x4のブロック
+fn transcoded_size(block_format: TranscoderBlockFormat, extent: vk::Extent2D) -> usize {
+    match block_format {
+        TranscoderBlockFormat::RGBA32 => extent.width as usize * extent.height as usize * 4,
+        _ => {
+            let (columns, rows) = block_counts(extent);
+            columns as usize * rows as usize * 16
+        }
+    }
+}
+
+//pathのKTX2のファイルを読み込み、ファイルに入っているミップマップを全て転送する
+//UASTCはBC7、ASTC、RGBA8の順に使えるものへ変換し、ETC1Sには対応しない
+//このデバイスで使えないフォーマットはtexture_decodeでRGBA8に展開できればそれを転送する
+//展開もできない場合はErrを返すので、呼び出し側でRGBA8のテクスチャにフォールバックする
+#[allow(clippy::too_many_arguments)]
+pub fn load_texture(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    device: &Device,
+    enabled_features: &EnabledFeatures,
+    one_time_commands: &OneTimeCommands,
+    synchronization: &Synchronization,
+    path: &Path,
+) -> Result<Texture2D, String> {
+    let bytes =
+        fs::read(path).map_err(|error| format!("Failed to read {}: {}", path.display(), error))?;
+    let file = Ktx2File::parse(bytes).map_err(|error| format!("{}: {}", path.display(), error))?;
+
+    let decoded = match file.basis {
+        Some(BasisLayout {
+            model: BasisModel::Etc1s,
+            ..
+        }) => {
+            return Err(format!(
+                "{}: ETC1S (BasisLZ) payloads are not supported, only UASTC can be transcoded",
+                path.display()
+            ))
+        }
+        Some(layout) => {
+            let pick = |(srgb, unorm): (vk::Format, vk::Format)| {
+                if layout.srgb {
+                    srgb
+                } else {
+                    unorm
+                }
+            };
+
+            let target = TRANSCODE_TARGETS
+                .into_iter()
+                .map(pick)
+                .find(|&format| {
+                    supports_format(instance, physical_device, enabled_features, format)
+                })
+                .unwrap_or_else(|| pick((vk::Format::R8G8B8A8_SRGB, vk::Format::R8G8B8A8_UNORM)));
+
+            let decoded = file
+                .transcode_uastc(layout, target)
+                .map_err(|error| format!("{}: {}", path.display(), error))?;
+
+            log::info!("{}: transcoded UASTC to {:?}", path.display(), target);
+
+            Some(decoded)
+        }
+        None if supports_format(instance, physical_device, enabled_features, file.format) => None,
+        None => {
+            let decoded = file
+                .decode_to_rgba8()
+                .map_err(|error| format!("{}: {}", path.display(), error))?;
+
+            log::info!(
+                "{}: {:?} cannot be sampled on this device, decoded to {:?} on the CPU",
+                path.display(),
+                file.format,
+                decoded.format
+            );
+
+            Some(decoded)
+        }
+    };
+
+    let (format, levels, bytes) = match &decoded {
+        Some(decoded) => (decoded.format, &decoded.levels, &decoded.bytes),
+        None => (file.format, &file.levels, &file.bytes),
+    };
+
+    let texture = Texture2D::with_mip_levels(
+        instance,
+        physical_device,
+        device,
+        one_time_commands,
+        synchronization,
+        format,
+        levels,
+        bytes,
+    );
+
+    let rgba8_size = file.rgba8_size();
+
+    log::info!(
+        "Loaded {}: {:?}, {}x{} with {} mip levels, {} KiB of VRAM ({} KiB less than RGBA8)",
+        path.display(),
+        format,
+        file.levels[0].extent.width,
+        file.levels[0].extent.height,
+        file.levels.len(),
+        texture.memory_size() / 1024,
+        rgba8_size.saturating_sub(texture.memory_size()) / 1024
+    );
+
+    Ok(texture)
+}
+
+//圧縮フォーマットはデバイスの機能を有効にしていないとフォーマットのプロパティに関わらず使えない
+fn supports_format(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    enabled_features: &EnabledFeatures,
+    format: vk::Format,
+) -> bool {
+    let raw = format.as_raw();
+
+    let feature_enabled = if (vk::Format::BC1_RGB_UNORM_BLOCK.as_raw()
+        ..=vk::Format::BC7_SRGB_BLOCK.as_raw())
+        .contains(&raw)
+    {
+        enabled_features.texture_compression_bc
+    } else if (vk::Format::ASTC_4X4_UNORM_BLOCK.as_raw()
+        ..=vk::Format::ASTC_12X12_SRGB_BLOCK.as_raw())
+        .contains(&raw)
+    {
+        enabled_features.texture_compression_astc_ldr
+    } else {
+        true
+    };
+
+    //デフォルトのサンプラーはLINEARで補間する
+    let required = vk::FormatFeatureFlags::SAMPLED_IMAGE
+        | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR
+        | vk::FormatFeatureFlags::TRANSFER_DST;
+
+    let properties =
+        unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+
+    feature_enabled && properties.optimal_tiling_features.contains(required)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //dfdTotalSizeから最初のサンプルまでのDFD、sample_countが2の場合は2つ目のサンプルも付ける
+    fn dfd(model: u8, transfer: u8, channel: u8, sample_count: usize) -> Vec<u8> {
+        let mut dfd = vec![0u8; 28 + 16 * sample_count];
+        dfd[12] = model;
+        dfd[14] = transfer;
+        dfd[31] = channel;
+        dfd
+    }
+
+    #[test]
+    fn uastc_alpha_comes_from_the_channel() {
+        assert_eq!(
+            basis_layout(&dfd(MODEL_UASTC, TRANSFER_SRGB, CHANNEL_UASTC_RGBA, 1)),
+            Ok(BasisLayout {
+                model: BasisModel::Uastc,
+                has_alpha: true,
+                srgb: true,
+            })
+        );
+        assert_eq!(
+            basis_layout(&dfd(MODEL_UASTC, 1, 0, 1)),
+            Ok(BasisLayout {
+                model: BasisModel::Uastc,
+                has_alpha: false,
+                srgb: false,
+            })
+        );
+    }
+
+    #[test]
+    fn etc1s_alpha_is_a_second_sample() {
+        assert!(
+            !basis_layout(&dfd(MODEL_ETC1S, TRANSFER_SRGB, 0, 1))
+                .unwrap()
+                .has_alpha
+        );
+        assert!(
+            basis_layout(&dfd(MODEL_ETC1S, TRANSFER_SRGB, 0, 2))
+                .unwrap()
+                .has_alpha
+        );
+    }
+
+    #[test]
+    fn other_models_and_short_descriptors_are_errors() {
+        //KHR_DF_MODEL_RGBSDA
+        assert!(basis_layout(&dfd(1, TRANSFER_SRGB, 0, 1)).is_err());
+        assert!(basis_layout(&[0; 20]).is_err());
+    }
+
+    #[test]
+    fn transcoded_levels_are_rounded_up_to_blocks() {
+        let extent = vk::Extent2D {
+            width: 5,
+            height: 3,
+        };
+
+        assert_eq!(transcoded_size(TranscoderBlockFormat::BC7, extent), 2 * 16);
+        assert_eq!(
+            transcoded_size(TranscoderBlockFormat::RGBA32, extent),
+            5 * 3 * 4
+        );
+    }
+}
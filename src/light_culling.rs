@@ -0,0 +1,180 @@
+use crate::compute;
+use crate::synchronization::Synchronization;
+use ash::{vk, Device};
+use glam::Mat4;
+use std::mem;
+
+//シェーダー側のTILE_SIZEと合わせる、1つのタイルの縦と横のピクセル数
+pub const TILE_SIZE: u32 = 16;
+
+//シェーダー側のmain_cs_light_cullingのthreadsと合わせる
+const WORKGROUP_SIZE: u32 = 8;
+
+//シェーダー側のLightCullingConstantsと同じレイアウト
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct LightCullingConstants {
+    inverse_projection: Mat4,
+    extent: [u32; 2],
+    tile_count: [u32; 2],
+}
+
+//--light-cullingの時にシーンのパスの前に、画面をTILE_SIZEのタイルに分けて各タイルに届くライトを選ぶ
+//結果はUniformBuffersのbinding = 4に書き込み、main_fs系のライティングはそのタイルのライトだけを読む
+//UniformBuffersのDescriptor Setをそのまま使うので、専用のDescriptor Setは持たない
+pub struct LightCulling {
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl LightCulling {
+    pub fn new(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        shader_module: vk::ShaderModule,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+    ) -> Self {
+        //逆射影行列と画像の大きさはPush Constantで渡す
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(mem::size_of::<LightCullingConstants>() as u32)
+            .build();
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&[descriptor_set_layout])
+            .push_constant_ranges(&[push_constant_range])
+            .build();
+
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .unwrap()
+        };
+
+        let pipeline = compute::create_compute_pipeline(
+            device,
+            pipeline_cache,
+            shader_module,
+            "main_cs_light_culling",
+            pipeline_layout,
+        );
+
+        Self {
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    //extentを覆うのに必要な横と縦のタイルの数、端のタイルは画像からはみ出す
+    pub fn tile_count(extent: vk::Extent2D) -> (u32, u32) {
+        (
+            (extent.width + TILE_SIZE - 1) / TILE_SIZE,
+            (extent.height + TILE_SIZE - 1) / TILE_SIZE,
+        )
+    }
+
+    //レンダーパスの外で、update_uniform_bufferでライトを書き込んだ後に記録する
+    //descriptor_setとtiles_bufferはそのフレームのUniformBuffersのもの
+    #[allow(clippy::too_many_arguments)]
+    pub fn cmd_cull(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        command_buffer: vk::CommandBuffer,
+        descriptor_set: vk::DescriptorSet,
+        tiles_buffer: vk::Buffer,
+        extent: vk::Extent2D,
+        inverse_projection: Mat4,
+    ) {
+        let (tile_count_x, tile_count_y) = Self::tile_count(extent);
+
+        let constants = LightCullingConstants {
+            inverse_projection,
+            extent: [extent.width, extent.height],
+            tile_count: [tile_count_x, tile_count_y],
+        };
+
+        let buffer_barrier = |src_stage, src_access, dst_stage, dst_access| {
+            vk::BufferMemoryBarrier2::builder()
+                .src_stage_mask(src_stage)
+                .src_access_mask(src_access)
+                .dst_stage_mask(dst_stage)
+                .dst_access_mask(dst_access)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(tiles_buffer)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+                .build()
+        };
+
+        unsafe {
+            //前回このバッファを読んだフラグメントシェーダーが終わってから書き換える
+            synchronization.cmd_pipeline_barrier(
+                device,
+                command_buffer,
+                &[],
+                &[buffer_barrier(
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_READ,
+                    vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                )],
+                &[],
+            );
+
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                std::slice::from_raw_parts(
+                    &constants as *const LightCullingConstants as *const u8,
+                    mem::size_of::<LightCullingConstants>(),
+                ),
+            );
+
+            device.cmd_dispatch(
+                command_buffer,
+                (tile_count_x + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                (tile_count_y + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                1,
+            );
+
+            //コンピュートシェーダーの書き込みをシーンのパスとヒートマップから読めるようにする
+            synchronization.cmd_pipeline_barrier(
+                device,
+                command_buffer,
+                &[],
+                &[buffer_barrier(
+                    vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_READ,
+                )],
+                &[],
+            );
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use crate::lighting::Light;
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+//--max-lightsが無い場合にシェーダーへ渡せるライトの数
+pub const DEFAULT_MAX_LIGHTS: u32 = 16;
+
+//--light-demoでモデルの周りを回るポイントライトの色
+const DEMO_COLORS: [[f32; 3]; 4] = [
+    [1.0, 0.25, 0.2],
+    [0.2, 1.0, 0.3],
+    [0.25, 0.4, 1.0],
+    [1.0, 0.8, 0.2],
+];
+
+//addで返すライトの番号、他のライトを削除しても変わらない
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LightId(u32);
+
+//animateでライトをcenterの周りの水平な円の上で動かす
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Orbit {
+    pub center: Vec3,
+    pub radius: f32,
+    //1秒あたりの角度(ラジアン)、負の場合は逆回り
+    pub speed: f32,
+    //animateの時間が0の時の角度
+    pub phase: f32,
+}
+
+impl Orbit {
+    fn position(&self, seconds: f32) -> Vec3 {
+        let angle = self.phase + self.speed * seconds;
+
+        self.center + Vec3::new(angle.cos(), 0.0, angle.sin()) * self.radius
+    }
+}
+
+//シーンのライトをUniformBuffersのbinding = 3にそのまま書き込める順番で持つ
+//max_lightsはstorage bufferの大きさなので、それより多くは追加できない
+pub struct LightManager {
+    max_lights: u32,
+    lights: Vec<Light>,
+    //lightsと同じ順番
+    ids: Vec<LightId>,
+    orbits: Vec<Option<Orbit>>,
+    next_id: u32,
+    //animateに渡された時間の合計、一時停止中は進まない
+    seconds: f32,
+}
+
+impl LightManager {
+    pub fn new(max_lights: u32) -> Self {
+        Self {
+            max_lights,
+            lights: vec![],
+            ids: vec![],
+            orbits: vec![],
+            next_id: 0,
+            seconds: 0.0,
+        }
+    }
+
+    pub fn max_lights(&self) -> u32 {
+        self.max_lights
+    }
+
+    //max_lightsに達している場合は追加せずに警告してNoneを返す
+    pub fn add(&mut self, light: Light) -> Option<LightId> {
+        if self.lights.len() >= self.max_lights as usize {
+            log::warn!(
+                "Only {} lights are supported, ignoring the new light (raise it with --max-lights)",
+                self.max_lights
+            );
+            return None;
+        }
+
+        let id = LightId(self.next_id);
+        self.next_id += 1;
+
+        self.lights.push(light);
+        self.ids.push(id);
+        self.orbits.push(None);
+
+        Some(id)
+    }
+
+    //既に削除されている場合はfalse
+    #[allow(dead_code)]
+    pub fn remove(&mut self, id: LightId) -> bool {
+        match self.index(id) {
+            Some(index) => {
+                self.lights.remove(index);
+                self.ids.remove(index);
+                self.orbits.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn light_mut(&mut self, id: LightId) -> Option<&mut Light> {
+        let index = self.index(id)?;
+        Some(&mut self.lights[index])
+    }
+
+    //Noneの場合は今の位置で止める
+    pub fn set_orbit(&mut self, id: LightId, orbit: Option<Orbit>) -> bool {
+        match self.index(id) {
+            Some(index) => {
+                self.orbits[index] = orbit;
+                true
+            }
+            None => false,
+        }
+    }
+
+    //固定間隔のupdateで呼び、Orbitを持つライトを動かす
+    pub fn animate(&mut self, delta_seconds: f32) {
+        self.seconds += delta_seconds;
+
+        for (light, orbit) in self.lights.iter_mut().zip(&self.orbits) {
+            if let Some(orbit) = orbit {
+                light.set_position(orbit.position(self.seconds));
+            }
+        }
+    }
+
+    //シェーダーに渡す順番のライト、長さはmax_lights以下
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    //lightsと同じ順番のライトとその軌道
+    pub fn entries(&self) -> impl Iterator<Item = (&Light, Option<Orbit>)> + '_ {
+        self.lights.iter().zip(self.orbits.iter().copied())
+    }
+
+    //Orbitを持つライトの今の位置はこの時間から決まる
+    pub fn seconds(&self) -> f32 {
+        self.seconds
+    }
+
+    //全てのライトを入れ替える、LightIdは振り直すので前に返したものは使えなくなる
+    //max_lightsに収まらない分はaddが警告して飛ばす
+    pub fn restore(
+        &mut self,
+        entries: impl IntoIterator<Item = (Light, Option<Orbit>)>,
+        seconds: f32,
+    ) {
+        self.lights.clear();
+        self.ids.clear();
+        self.orbits.clear();
+        self.seconds = seconds;
+
+        for (light, orbit) in entries {
+            if let Some(id) = self.add(light) {
+                self.set_orbit(id, orbit);
+            }
+        }
+    }
+
+    //--light-demoのモデルの周りを回るDEMO_COLORSのポイントライトと、真上から照らすスポットライトを追加する
+    //max_lightsに収まらない分はaddが警告して飛ばす
+    pub fn add_demo_lights(&mut self) {
+        let count = DEMO_COLORS.len();
+
+        for (index, color) in DEMO_COLORS.iter().enumerate() {
+            let orbit = Orbit {
+                center: Vec3::new(0.0, 0.6, 0.0),
+                radius: 1.5,
+                //隣り合うライトを逆向きに回して、色の重なり方を変え続ける
+                speed: if index % 2 == 0 { 0.8 } else { -0.6 },
+                phase: index as f32 / count as f32 * std::f32::consts::TAU,
+            };
+
+            let light = Light::point(orbit.position(0.0), Vec3::from(*color), 4.0, 3.0);
+
+            if let Some(id) = self.add(light) {
+                self.set_orbit(id, Some(orbit));
+            }
+        }
+
+        self.add(Light::spot(
+            Vec3::new(0.0, 3.0, 0.0),
+            Vec3::NEG_Y,
+            Vec3::ONE,
+            6.0,
+            5.0,
+            15f32.to_radians(),
+            25f32.to_radians(),
+        ));
+
+        log::info!("Light demo: {} lights", self.lights.len());
+    }
+
+    fn index(&self, id: LightId) -> Option<usize> {
+        self.ids.iter().position(|&other| other == id)
+    }
+}
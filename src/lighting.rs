@@ -0,0 +1,190 @@
+use glam::{Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+//ディレクショナルライトの光が進む向き、シャドウマップもこの向きから描画する
+pub const LIGHT_DIRECTION: [f32; 3] = [-0.4, -1.0, -0.3];
+
+//LIGHT_DIRECTIONのライトの色
+const LIGHT_COLOR: [f32; 3] = [1.0, 0.96, 0.9];
+
+//rangeが0だとシェーダーで0除算になるので、これより短くしない
+const MIN_RANGE: f32 = 0.01;
+
+//光が当たらない面でも真っ暗にならないように足す明るさ
+const AMBIENT: [f32; 3] = [0.15, 0.15, 0.18];
+
+//Lキーで切り替えるライティングの項、シェーダーのLIGHTING_*と同じ値にする
+//シーンのファイルにはnameと同じ名前で書く
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LightingMode {
+    //頂点カラーをそのまま出力する
+    Unlit = 0,
+    //環境光と拡散反射光だけ
+    Diffuse = 1,
+    //Blinn-Phongの鏡面反射光も加える
+    Full = 2,
+}
+
+impl LightingMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            LightingMode::Unlit => "unlit",
+            LightingMode::Diffuse => "diffuse",
+            LightingMode::Full => "full",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            LightingMode::Unlit => LightingMode::Diffuse,
+            LightingMode::Diffuse => LightingMode::Full,
+            LightingMode::Full => LightingMode::Unlit,
+        }
+    }
+}
+
+impl FromStr for LightingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "unlit" => Ok(Self::Unlit),
+            "diffuse" => Ok(Self::Diffuse),
+            "full" => Ok(Self::Full),
+            _ => Err(format!(
+                "Unknown lighting mode '{}', expected unlit, diffuse or full",
+                s
+            )),
+        }
+    }
+}
+
+//シェーダー側のLightUniformsと同じレイアウトにする
+//std140でずれないように全てVec4にそろえる
+//ライトそのものはbinding = 3のstorage bufferにLightの配列で渡す
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct LightUniforms {
+    pub ambient: Vec4,
+    //鏡面反射光の計算に使うワールド座標でのカメラの位置
+    pub camera_position: Vec4,
+    pub mode: u32,
+    //シェーダーが読むLightの数、0の場合は環境光だけになる
+    pub light_count: u32,
+    //--light-cullingの時に横に並ぶタイルの数、0の場合は全てのライトを読む
+    pub tile_count_x: u32,
+    //タイル1つ分のu32の数、先頭のライトの数とmax_lights個の番号
+    pub tile_stride: u32,
+}
+
+impl LightUniforms {
+    pub fn new(camera_position: Vec3, mode: LightingMode, light_count: u32) -> Self {
+        Self {
+            ambient: Vec3::from(AMBIENT).extend(1.0),
+            camera_position: camera_position.extend(1.0),
+            mode: mode as u32,
+            light_count,
+            tile_count_x: 0,
+            tile_stride: 0,
+        }
+    }
+}
+
+//シェーダーのLIGHT_*と同じ値にする
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LightKind {
+    //向きだけを持ち、距離で弱くならない
+    Directional = 0,
+    //位置から全ての向きに光り、rangeで0になる
+    Point = 1,
+    //ポイントライトの光をdirectionの周りの円錐に絞る
+    Spot = 2,
+}
+
+//シェーダー側のLightと同じレイアウトにする
+//storage bufferのstd430でも配列の要素が16バイトの倍数になるようにする
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct Light {
+    //xyzがポイントライトとスポットライトの位置、wが光の届く距離
+    pub position: Vec4,
+    //xyzがディレクショナルライトとスポットライトの光の進む向き
+    pub direction: Vec4,
+    //xyzが色、wが強さ
+    pub color: Vec4,
+    //スポットライトの光が弱くなり始める角度と0になる角度のcos
+    pub cos_inner: f32,
+    pub cos_outer: f32,
+    pub kind: u32,
+    pub _padding: u32,
+}
+
+impl Light {
+    //LIGHT_DIRECTIONのディレクショナルライト、シャドウマップと向きがそろう
+    pub fn sun() -> Self {
+        Self::directional(Vec3::from(LIGHT_DIRECTION), Vec3::from(LIGHT_COLOR), 1.0)
+    }
+
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self {
+            position: Vec4::ZERO,
+            direction: direction.normalize().extend(0.0),
+            color: color.extend(intensity),
+            cos_inner: 1.0,
+            cos_outer: 1.0,
+            kind: LightKind::Directional as u32,
+            _padding: 0,
+        }
+    }
+
+    //rangeの距離で光が丁度0になる
+    pub fn point(position: Vec3, color: Vec3, intensity: f32, range: f32) -> Self {
+        Self {
+            position: position.extend(range.max(MIN_RANGE)),
+            direction: Vec4::ZERO,
+            color: color.extend(intensity),
+            cos_inner: 1.0,
+            cos_outer: 1.0,
+            kind: LightKind::Point as u32,
+            _padding: 0,
+        }
+    }
+
+    //角度はdirectionからのラジアンで、innerからouterにかけて弱くなる
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Self {
+        //innerがouterより外側だとsmoothstepの向きが逆になる
+        let inner_angle = inner_angle.min(outer_angle);
+
+        Self {
+            direction: direction.normalize().extend(0.0),
+            cos_inner: inner_angle.cos(),
+            cos_outer: outer_angle.cos(),
+            kind: LightKind::Spot as u32,
+            ..Self::point(position, color, intensity, range)
+        }
+    }
+
+    pub fn set_position(&mut self, position: Vec3) {
+        self.position = position.extend(self.position.w);
+    }
+
+    //kindはシェーダーに渡すためにu32で持っている
+    pub fn light_kind(&self) -> LightKind {
+        match self.kind {
+            kind if kind == LightKind::Point as u32 => LightKind::Point,
+            kind if kind == LightKind::Spot as u32 => LightKind::Spot,
+            _ => LightKind::Directional,
+        }
+    }
+}
@@ -1,28 +1,188 @@
 extern crate core;
 
+use crate::cli::{AppConfig, CliError};
+use crate::vulkan_app::SurfaceSource;
 use crate::window_handlers::WindowHandlers;
 
-use ash::vk::RefreshCycleDurationGOOGLEBuilder;
 use log::info;
 use std::env;
 
+mod acceleration_structure;
+mod animation;
+mod asset_loader;
+mod asset_upload;
+mod benchmark;
+mod bloom;
+mod buffer;
+mod camera;
+mod clear_color;
+mod cli;
+mod color_space;
+mod command_pool_manager;
+mod compute;
+mod compute_queue;
+mod config_file;
+mod crash_diagnostics;
 mod debug;
+mod debug_text;
+mod debug_view;
+mod depth_buffer;
+mod descriptor_allocator;
+mod device_features;
+mod device_info;
+mod device_report;
+mod display_surface;
+mod display_timing;
+mod drawable;
+mod dynamic_rendering;
+mod egui_overlay;
+mod frame_clock;
+mod frame_limiter;
+mod frame_stats;
+mod frustum;
+mod gamepad;
+mod gltf_loader;
+mod gpu_timer;
+mod host_alloc;
+mod indirect;
+mod input;
+mod instance_config;
+mod instancing;
+mod json;
 mod khr_util;
+mod ktx2_texture;
+mod light_culling;
+mod light_manager;
+mod lighting;
+mod material_textures;
+mod memory_budget;
+mod mesh;
+mod mesh_shading;
+mod mipmap;
+mod msaa;
+mod normal_map;
+mod obj_loader;
+mod object_buffer;
+mod occlusion_culling;
+mod one_time_commands;
+mod parallel_renderer;
+mod particles;
+mod pipeline_bench;
+mod pipeline_cache;
+mod pipeline_statistics;
+mod post_process;
+mod procedural_texture;
 mod queue_family;
+mod queues;
+mod ray_query_shadows;
+mod ray_tracing;
+mod readback;
+mod render_graph;
+mod render_scale;
 mod required_names;
+mod sampler;
+mod scene_file;
+mod session;
+mod shadow_map;
+mod skinning;
+mod skybox;
+mod specialization;
+mod split_screen;
+mod sprite_batch;
+mod sprite_demo;
+mod staging_ring;
+mod submit_queue;
 mod swap_chain_utils;
+mod synchronization;
+mod tessellation;
+mod texture;
+mod texture_array;
+mod texture_atlas;
+mod texture_decode;
+mod texture_manager;
+mod timeline_semaphore;
+mod tonemap;
+mod transparency;
+mod uniform_buffer;
+mod vertex_pulling;
 mod vulkan_app;
 mod window_handlers;
+mod window_target;
 
 fn main() {
     env::set_var("RUST_LOG", "info");
     env::set_var("RUST_LOG", "DEBUG");
     env_logger::init();
 
-    let window_handlers = WindowHandlers::new();
+    //--features tracyではTracyのクライアントを先に起動し、最初のフレームから計測する
+    #[cfg(feature = "tracy")]
+    profiling::tracy_client::Client::start();
 
-    match vulkan_app::VulkanApp::new(&window_handlers.window) {
+    //--features puffinではpuffin_viewerから接続できるようにサーバーを立てておく
+    #[cfg(feature = "puffin")]
+    let _puffin_server = start_puffin_server();
+
+    //ウィンドウの大きさとタイトルも使うので、ウィンドウを作る前に読む
+    let config = match AppConfig::parse() {
+        Ok(config) => config,
+        Err(CliError::Exit(message)) => {
+            print!("{}", message);
+            return;
+        }
+        Err(CliError::Invalid(message)) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+
+    if let Some(format) = config.demo.info_format {
+        if let Err(error) = device_report::print(format) {
+            log::error!("Failed to report devices. Cause: {}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    //--displayではwinitを使わずにディスプレイへ直接presentする
+    //VulkanAppにconfigを渡した後でも使うので先に取り出す
+    let bench_pipelines = config.demo.bench_pipelines;
+
+    if let Some(display) = config.demo.display {
+        if config.demo.mirror_windows > 0 {
+            log::warn!("--mirror-windows is ignored with --display");
+        }
+
+        match vulkan_app::VulkanApp::new(SurfaceSource::Display(display), config) {
+            Ok(app) if bench_pipelines => app.run_pipeline_bench(),
+            Ok(app) => app.run_display(),
+            Err(error) => log::error!("Failed to create application. Cause: {}", error),
+        }
+        return;
+    }
+
+    let window_handlers = WindowHandlers::new(&config);
+
+    match vulkan_app::VulkanApp::new(SurfaceSource::Window(&window_handlers.window), config) {
+        Ok(app) if bench_pipelines => app.run_pipeline_bench(),
         Ok(app) => app.run(window_handlers),
         Err(error) => log::error!("Failed to create application. Cause: {}", error),
     }
 }
+
+#[cfg(feature = "puffin")]
+fn start_puffin_server() -> Option<puffin_http::Server> {
+    profiling::puffin::set_scopes_on(true);
+
+    let address = format!("127.0.0.1:{}", puffin_http::DEFAULT_PORT);
+
+    match puffin_http::Server::new(&address) {
+        Ok(server) => {
+            log::info!("Puffin server listening on {}", address);
+            Some(server)
+        }
+        Err(error) => {
+            log::warn!("Failed to start the puffin server. Cause: {}", error);
+            None
+        }
+    }
+}
@@ -0,0 +1,442 @@
+use crate::descriptor_allocator::{DescriptorAllocator, DescriptorLayoutCache};
+use crate::device_features::EnabledFeatures;
+use crate::one_time_commands::OneTimeCommands;
+use crate::queue_family::QueueFamilyIndices;
+use crate::synchronization::Synchronization;
+use crate::texture::{Texture2D, TextureOptions};
+use crate::texture_manager::{TextureHandle, TextureManager};
+use ash::{vk, Device, Instance};
+use std::ffi::CStr;
+use std::mem;
+use std::path::Path;
+
+//シェーダー側のMAX_MATERIAL_TEXTURESと同じ値にする
+//bindlessの場合のbinding = 1の上限で、実際に確保する数はvariable descriptor countで指定する
+pub const MAX_TEXTURES: u32 = 64;
+
+//生成するテクスチャの1辺のピクセル数
+const TEXTURE_SIZE: u32 = 64;
+
+//マテリアルごとのチェッカー模様の色と1辺のマス目の数
+const MATERIALS: [([u8; 3], u32); 6] = [
+    ([230, 80, 60], 2),
+    ([80, 200, 90], 4),
+    ([70, 110, 230], 8),
+    ([240, 200, 60], 4),
+    ([190, 80, 220], 2),
+    ([60, 200, 210], 8),
+];
+
+//bindlessに必要なdescriptor indexingの機能をデバイスがどの形で使えるか
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DescriptorIndexingSupport {
+    //Vulkan 1.2のコア機能
+    Core,
+    //VK_EXT_descriptor_indexing
+    Extension,
+    Unsupported,
+}
+
+impl DescriptorIndexingSupport {
+    //api_versionはdevice_info::effective_api_versionで決めたもの
+    pub fn query(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        api_version: u32,
+    ) -> Self {
+        let is_core = api_version >= vk::make_api_version(0, 1, 2, 0);
+        //拡張が依存するVK_KHR_maintenance3はVulkan 1.1のコアなので1.1以上の場合だけ使う
+        let is_extension = !is_core
+            && api_version >= vk::make_api_version(0, 1, 1, 0)
+            && QueueFamilyIndices::is_device_extension_supported(
+                instance,
+                physical_device,
+                vk::ExtDescriptorIndexingFn::name(),
+            );
+
+        if !(is_core || is_extension) {
+            return Self::Unsupported;
+        }
+
+        let mut indexing_features = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+        let mut features = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut indexing_features)
+            .build();
+
+        unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+
+        let supported = [
+            features
+                .features
+                .shader_sampled_image_array_dynamic_indexing,
+            indexing_features.runtime_descriptor_array,
+            indexing_features.descriptor_binding_partially_bound,
+            indexing_features.descriptor_binding_variable_descriptor_count,
+            indexing_features.shader_sampled_image_array_non_uniform_indexing,
+        ]
+        .into_iter()
+        .all(|feature| feature == vk::TRUE);
+
+        if !supported {
+            Self::Unsupported
+        } else if is_core {
+            Self::Core
+        } else {
+            Self::Extension
+        }
+    }
+
+    //DeviceCreateInfoで有効にする必要のある拡張
+    pub fn extension_name(self) -> Option<&'static CStr> {
+        match self {
+            Self::Extension => Some(vk::ExtDescriptorIndexingFn::name()),
+            _ => None,
+        }
+    }
+}
+
+//マテリアルのテクスチャをシェーダーに渡す方法
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureBinding {
+    //全てのテクスチャを1つの配列のbindingに入れて最初に一度だけ紐づけ、push constantのインデックスで引く
+    Bindless,
+    //マテリアルごとにDescriptor Setを作り、描画ごとに紐づけ直す
+    PerMaterial,
+}
+
+//main_fs_bindlessにpush constantで渡すマテリアルの番号
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct MaterialConstants {
+    pub index: u32,
+}
+
+//Texture2Dのフォーマット、チェッカー模様はsRGBの値で作る
+const TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+//白とcolorのチェッカー模様
+fn checker(color: [u8; 3], cells: u32) -> Vec<u8> {
+    let cell_size = TEXTURE_SIZE / cells;
+
+    (0..TEXTURE_SIZE)
+        .flat_map(|y| (0..TEXTURE_SIZE).map(move |x| (x, y)))
+        .flat_map(|(x, y)| {
+            let [r, g, b] = if (x / cell_size + y / cell_size) % 2 == 0 {
+                color
+            } else {
+                [255, 255, 255]
+            };
+            [r, g, b, 255]
+        })
+        .collect()
+}
+
+//--texturedでオブジェクトごとに貼るマテリアルのテクスチャ
+//メインのパイプラインのset = 2で、binding = 0がsampler、binding = 1がテクスチャ
+//variable descriptor countのbindingは一番大きい番号にする必要があるのでシャドウマップとは順番が逆になる
+pub struct MaterialTextures {
+    binding: TextureBinding,
+    //TextureManagerでacquireしたもの、destroyでreleaseする
+    textures: Vec<TextureHandle>,
+    //proceduralのテクスチャも含めたマテリアルの数
+    material_count: u32,
+    //DescriptorLayoutCacheが持つので破棄しない
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    //マテリアルが増えてもプールを作り足して確保する
+    descriptor_allocator: DescriptorAllocator,
+    //Bindlessの場合は全てのテクスチャを持つ1つ、PerMaterialの場合はテクスチャごとに1つ
+    descriptor_sets: Vec<vk::DescriptorSet>,
+}
+
+impl MaterialTextures {
+    //samplerの破棄はSamplerCacheに、Descriptor Set Layoutの破棄はDescriptorLayoutCacheに任せる
+    //ktx2がSomeの場合は最初のマテリアルのチェッカー模様の代わりにそのファイルを使う
+    //proceduralがSomeの場合はktx2より優先して最初のマテリアルにそのimage viewを使い、TextureManagerには登録しない
+    //チェッカー模様のミップマップはtexture_optionsで生成する
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        texture_manager: &mut TextureManager,
+        sampler: vk::Sampler,
+        binding: TextureBinding,
+        ktx2: Option<&Path>,
+        procedural: Option<vk::ImageView>,
+        enabled_features: &EnabledFeatures,
+        texture_options: TextureOptions,
+    ) -> Self {
+        let ktx2 = match (ktx2, procedural) {
+            (Some(_), Some(_)) => {
+                log::warn!("--ktx2 is ignored with --procedural-texture");
+                None
+            }
+            _ => ktx2,
+        };
+
+        //読み込めなかった場合はチェッカー模様のままにする
+        let mut loaded = ktx2.and_then(|path| {
+            texture_manager
+                .load_ktx2(
+                    instance,
+                    physical_device,
+                    device,
+                    enabled_features,
+                    one_time_commands,
+                    synchronization,
+                    path,
+                )
+                .map_err(|error| log::warn!("{}, using the RGBA8 checker texture", error))
+                .ok()
+        });
+
+        //起動時に一度だけ全てのテクスチャを転送する
+        //同じ色とマス目のマテリアルは同じテクスチャを共有する
+        //proceduralの場合は最初のマテリアルのチェッカー模様を作らない
+        let skipped = procedural.is_some() as usize;
+
+        let textures = MATERIALS
+            .iter()
+            .enumerate()
+            .skip(skipped)
+            .map(|(index, &(color, cells))| {
+                let handle = match loaded.take().filter(|_| index == 0) {
+                    Some(handle) => handle,
+                    None => texture_manager.get_or_insert_with(
+                        &format!("checker:{:?}/{}", color, cells),
+                        || {
+                            Texture2D::with_options(
+                                instance,
+                                physical_device,
+                                device,
+                                one_time_commands,
+                                synchronization,
+                                TEXTURE_FORMAT,
+                                vk::Extent2D {
+                                    width: TEXTURE_SIZE,
+                                    height: TEXTURE_SIZE,
+                                },
+                                &checker(color, cells),
+                                texture_options,
+                            )
+                        },
+                    ),
+                };
+
+                texture_manager.acquire(handle);
+                handle
+            })
+            .collect::<Vec<_>>();
+
+        let texture_count = (skipped + textures.len()) as u32;
+
+        let descriptor_set_layout =
+            Self::descriptor_set_layout_for(device, descriptor_layout_cache, binding);
+
+        let mut descriptor_allocator = DescriptorAllocator::new();
+
+        let descriptor_sets = match binding {
+            //MAX_TEXTURESのうち実際に使う数だけを確保する
+            TextureBinding::Bindless => vec![descriptor_allocator.allocate(
+                device,
+                descriptor_set_layout,
+                Some(texture_count),
+            )],
+            TextureBinding::PerMaterial => (0..texture_count)
+                .map(|_| descriptor_allocator.allocate(device, descriptor_set_layout, None))
+                .collect(),
+        };
+
+        let sampler_info = [vk::DescriptorImageInfo::builder().sampler(sampler).build()];
+
+        let image_infos = procedural
+            .map(|view| {
+                vk::DescriptorImageInfo::builder()
+                    .image_view(view)
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .build()
+            })
+            .into_iter()
+            .chain(
+                textures
+                    .iter()
+                    .map(|&handle| texture_manager.image_info(handle)),
+            )
+            .collect::<Vec<_>>();
+
+        //Bindlessの場合は配列の0番目から全てのテクスチャを書き込む
+        let image_writes = match binding {
+            TextureBinding::Bindless => vec![(descriptor_sets[0], &image_infos[..])],
+            TextureBinding::PerMaterial => descriptor_sets
+                .iter()
+                .zip(image_infos.chunks(1))
+                .map(|(descriptor_set, image_info)| (*descriptor_set, image_info))
+                .collect(),
+        };
+
+        let descriptor_writes = image_writes
+            .iter()
+            .flat_map(|(descriptor_set, image_info)| {
+                [
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(*descriptor_set)
+                        .dst_binding(0)
+                        .descriptor_type(vk::DescriptorType::SAMPLER)
+                        .image_info(&sampler_info)
+                        .build(),
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(*descriptor_set)
+                        .dst_binding(1)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                        .image_info(image_info)
+                        .build(),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        log::info!(
+            "Material textures: {} ({:?}, {} descriptor sets)",
+            texture_count,
+            binding,
+            descriptor_sets.len()
+        );
+
+        Self {
+            binding,
+            textures,
+            material_count: texture_count,
+            descriptor_set_layout,
+            descriptor_allocator,
+            descriptor_sets,
+        }
+    }
+
+    fn descriptor_set_layout_for(
+        device: &Device,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        binding: TextureBinding,
+    ) -> vk::DescriptorSetLayout {
+        let texture_count = match binding {
+            TextureBinding::Bindless => MAX_TEXTURES,
+            TextureBinding::PerMaterial => 1,
+        };
+
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(texture_count)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        ];
+
+        //使わない要素は書き込まないのでPARTIALLY_BOUNDにする
+        let binding_flags = match binding {
+            TextureBinding::Bindless => vec![
+                vk::DescriptorBindingFlags::empty(),
+                vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                    | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT,
+            ],
+            TextureBinding::PerMaterial => vec![],
+        };
+
+        descriptor_layout_cache.get(device, &bindings, &binding_flags)
+    }
+
+    pub fn binding(&self) -> TextureBinding {
+        self.binding
+    }
+
+    //パイプラインレイアウトのset = 2に使う
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    //オブジェクトの番号から順番にマテリアルを割り当てる
+    pub fn material_index(&self, object_index: usize) -> u32 {
+        (object_index % self.material_count as usize) as u32
+    }
+
+    //Bindlessの場合は全てのテクスチャをset = 2に紐づける、PerMaterialの場合は描画ごとに紐づけるので何もしない
+    pub fn cmd_bind_all(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        if self.binding != TextureBinding::Bindless {
+            return;
+        }
+
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                2,
+                &[self.descriptor_sets[0]],
+                &[],
+            );
+        }
+    }
+
+    //次の描画で使うマテリアルを指定する
+    //Bindlessの場合はインデックスをpush constantで渡すだけでDescriptor Setは紐づけ直さない
+    pub fn cmd_bind_material(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        material_index: u32,
+    ) {
+        unsafe {
+            match self.binding {
+                TextureBinding::Bindless => {
+                    let constants = MaterialConstants {
+                        index: material_index,
+                    };
+
+                    device.cmd_push_constants(
+                        command_buffer,
+                        pipeline_layout,
+                        vk::ShaderStageFlags::FRAGMENT,
+                        0,
+                        std::slice::from_raw_parts(
+                            &constants as *const MaterialConstants as *const u8,
+                            mem::size_of::<MaterialConstants>(),
+                        ),
+                    );
+                }
+                TextureBinding::PerMaterial => device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    2,
+                    &[self.descriptor_sets[material_index as usize]],
+                    &[],
+                ),
+            }
+        }
+    }
+
+    //テクスチャの破棄はTextureManagerに任せる
+    pub fn destroy(&self, device: &Device, texture_manager: &mut TextureManager) {
+        self.descriptor_allocator.destroy(device);
+
+        for &handle in &self.textures {
+            texture_manager.release(handle);
+        }
+    }
+}
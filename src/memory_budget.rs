@@ -0,0 +1,241 @@
+use crate::queue_family::QueueFamilyIndices;
+use ash::prelude::VkResult;
+use ash::{vk, Device, Instance};
+use log::info;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+//使用量が予算のこの割合を超えたら警告する
+const WARNING_RATIO: f64 = 0.9;
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+//allocate_memoryで確保したメモリの用途
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryCategory {
+    Vertex,
+    Index,
+    Uniform,
+    Texture,
+    Attachment,
+    //ステージングやストレージバッファなど
+    Other,
+}
+
+impl MemoryCategory {
+    const ALL: [Self; 6] = [
+        Self::Vertex,
+        Self::Index,
+        Self::Uniform,
+        Self::Texture,
+        Self::Attachment,
+        Self::Other,
+    ];
+
+    //バッファは用途のフラグから決める、複数の用途を持つ場合は先に当てはまったものにする
+    pub fn from_buffer_usage(usage: vk::BufferUsageFlags) -> Self {
+        if usage.contains(vk::BufferUsageFlags::VERTEX_BUFFER) {
+            Self::Vertex
+        } else if usage.contains(vk::BufferUsageFlags::INDEX_BUFFER) {
+            Self::Index
+        } else if usage.contains(vk::BufferUsageFlags::UNIFORM_BUFFER) {
+            Self::Uniform
+        } else {
+            Self::Other
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Vertex => "vertex",
+            Self::Index => "index",
+            Self::Uniform => "uniform",
+            Self::Texture => "texture",
+            Self::Attachment => "attachment",
+            Self::Other => "other",
+        }
+    }
+}
+
+thread_local! {
+    //自前で確保したメモリの用途と大きさ
+    //メモリの確保と解放はイベントループのスレッドでしか行わないのでスレッドごとに持つ
+    static ALLOCATIONS: RefCell<HashMap<vk::DeviceMemory, (MemoryCategory, vk::DeviceSize)>> =
+        RefCell::new(HashMap::new());
+}
+
+//device.allocate_memoryと同じだが、確保した大きさをcategoryごとに記録する
+pub unsafe fn allocate_memory(
+    device: &Device,
+    alloc_info: &vk::MemoryAllocateInfo,
+    category: MemoryCategory,
+) -> VkResult<vk::DeviceMemory> {
+    let memory = device.allocate_memory(alloc_info, None)?;
+
+    ALLOCATIONS.with(|allocations| {
+        allocations
+            .borrow_mut()
+            .insert(memory, (category, alloc_info.allocation_size))
+    });
+
+    Ok(memory)
+}
+
+//device.free_memoryと同じだが、allocate_memoryで記録した大きさも取り除く
+pub unsafe fn free_memory(device: &Device, memory: vk::DeviceMemory) {
+    ALLOCATIONS.with(|allocations| allocations.borrow_mut().remove(&memory));
+
+    device.free_memory(memory, None);
+}
+
+//categoryごとの確保している大きさの合計、確保していないcategoryは含めない
+pub fn tracked_usage() -> Vec<(MemoryCategory, vk::DeviceSize)> {
+    ALLOCATIONS.with(|allocations| {
+        let allocations = allocations.borrow();
+
+        MemoryCategory::ALL
+            .iter()
+            .map(|&category| {
+                let size = allocations
+                    .values()
+                    .filter(|(allocated, _)| *allocated == category)
+                    .map(|(_, size)| size)
+                    .sum();
+
+                (category, size)
+            })
+            .filter(|&(_, size)| size > 0)
+            .collect()
+    })
+}
+
+//メモリヒープの大きさと、VK_EXT_memory_budgetが使える場合はドライバが報告する予算と使用量
+#[derive(Clone, Copy, Debug)]
+pub struct HeapBudget {
+    pub size: vk::DeviceSize,
+    pub device_local: bool,
+    //他のプロセスも含めてこのプロセスが使って良い大きさと、このプロセスの使用量
+    pub budget: Option<(vk::DeviceSize, vk::DeviceSize)>,
+}
+
+//VK_EXT_memory_budgetでヒープごとの予算と使用量を読み、自前で記録した使用量と合わせて報告する
+pub struct MemoryBudget {
+    supported: bool,
+    //予算を超えそうだと警告したヒープ、下回るまでは繰り返し警告しない
+    warned: Vec<bool>,
+}
+
+impl MemoryBudget {
+    //予算の取得にVulkan 1.1のvkGetPhysicalDeviceMemoryProperties2を使う
+    pub fn new(instance: &Instance, physical_device: vk::PhysicalDevice, api_version: u32) -> Self {
+        let supported = api_version >= vk::make_api_version(0, 1, 1, 0)
+            && QueueFamilyIndices::is_device_extension_supported(
+                instance,
+                physical_device,
+                vk::ExtMemoryBudgetFn::name(),
+            );
+
+        if !supported {
+            info!("VK_EXT_memory_budget is not supported, only tracked allocations are reported");
+        }
+
+        Self {
+            supported,
+            warned: vec![],
+        }
+    }
+
+    pub fn heaps(
+        &self,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> Vec<HeapBudget> {
+        let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+
+        let memory_properties = if self.supported {
+            let mut properties = vk::PhysicalDeviceMemoryProperties2::builder()
+                .push_next(&mut budget_properties)
+                .build();
+
+            unsafe {
+                instance.get_physical_device_memory_properties2(physical_device, &mut properties)
+            };
+
+            properties.memory_properties
+        } else {
+            unsafe { instance.get_physical_device_memory_properties(physical_device) }
+        };
+
+        memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+            .iter()
+            .enumerate()
+            .map(|(index, heap)| HeapBudget {
+                size: heap.size,
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                budget: self.supported.then(|| {
+                    (
+                        budget_properties.heap_budget[index],
+                        budget_properties.heap_usage[index],
+                    )
+                }),
+            })
+            .collect()
+    }
+
+    //オーバーレイに表示する複数行の文字列を返し、予算の90%を超えたヒープがあればログで警告する
+    pub fn report(&mut self, instance: &Instance, physical_device: vk::PhysicalDevice) -> String {
+        let heaps = self.heaps(instance, physical_device);
+
+        self.warned.resize(heaps.len(), false);
+
+        let mut lines = vec![];
+
+        for (index, heap) in heaps.iter().enumerate() {
+            let kind = if heap.device_local {
+                "device local"
+            } else {
+                "host"
+            };
+
+            match heap.budget {
+                Some((budget, usage)) => {
+                    lines.push(format!(
+                        "heap {} ({}): {:.1} / {:.1} MiB",
+                        index,
+                        kind,
+                        usage as f64 / MIB,
+                        budget as f64 / MIB
+                    ));
+
+                    let over = usage as f64 > budget as f64 * WARNING_RATIO;
+
+                    if over && !self.warned[index] {
+                        log::warn!(
+                            "Memory heap {} uses {:.1} MiB of its {:.1} MiB budget",
+                            index,
+                            usage as f64 / MIB,
+                            budget as f64 / MIB
+                        );
+                    }
+
+                    self.warned[index] = over;
+                }
+                None => lines.push(format!(
+                    "heap {} ({}): {:.1} MiB",
+                    index,
+                    kind,
+                    heap.size as f64 / MIB
+                )),
+            }
+        }
+
+        let tracked = tracked_usage()
+            .into_iter()
+            .map(|(category, size)| format!("{} {:.1}", category.name(), size as f64 / MIB))
+            .collect::<Vec<_>>();
+
+        lines.push(format!("tracked MiB: {}", tracked.join(", ")));
+
+        lines.join("\n")
+    }
+}
@@ -0,0 +1,417 @@
+use crate::buffer::{self, StagedBuffer};
+use crate::frustum::Aabb;
+use crate::memory_budget;
+use crate::one_time_commands::{OneTimeCommands, Record};
+use crate::synchronization::Synchronization;
+use ash::{vk, Device, Instance};
+use std::mem;
+
+//XY平面の三角形と四角形のtangent、Uは+X向きでVは画像の上が0なので-Y向きになりcross(+Z, +X)と逆になる
+const QUAD_TANGENT: [f32; 4] = [1.0, 0.0, 0.0, -1.0];
+
+//頂点バッファの1頂点分のデータ
+//シェーダー側のmain_vsの入力の順番とlocationを合わせる
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+    //モデル空間の法線、長さは1
+    pub normal: [f32; 3],
+    //テクスチャ座標、Vは画像の上が0
+    pub tex_coord: [f32; 2],
+    //モデル空間でUの増える方向、wはVの増える方向がcross(normal, tangent)と同じ向きなら1、逆なら-1
+    //UVを持たない頂点はNO_TANGENTにし、法線マップを使わずにnormalをそのまま使う
+    pub tangent: [f32; 4],
+}
+
+impl Vertex {
+    pub const NO_TANGENT: [f32; 4] = [0.0; 4];
+
+    //binding = 0に頂点ごとのデータを割り当てる
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(mem::size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+        [
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32B32_SFLOAT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32B32_SFLOAT)
+                .offset(mem::size_of::<[f32; 3]>() as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(2)
+                .format(vk::Format::R32G32B32_SFLOAT)
+                .offset(mem::size_of::<[f32; 6]>() as u32)
+                .build(),
+        ]
+    }
+
+    //--normal-mappingの頂点シェーダーだけが読むテクスチャ座標とtangent
+    //--instanced-gridのInstanceDataもlocation = 3から使うので、attribute_descriptionsには含めない
+    pub fn normal_map_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+        [
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(3)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(mem::size_of::<[f32; 9]>() as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(4)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(mem::size_of::<[f32; 11]>() as u32)
+                .build(),
+        ]
+    }
+}
+
+//頂点バッファとインデックスバッファの組
+pub struct Mesh {
+    vertex_buffer: vk::Buffer,
+    vertex_memory: vk::DeviceMemory,
+    index_buffer: vk::Buffer,
+    index_memory: vk::DeviceMemory,
+    vertex_count: u32,
+    index_count: u32,
+    //モデル空間でのバウンディングボックス、視錐台カリングに使う
+    bounds: Aabb,
+    //SHADER_DEVICE_ADDRESSを付けて作った場合のみSome、(頂点バッファ, インデックスバッファ)のアドレス
+    device_addresses: Option<(vk::DeviceAddress, vk::DeviceAddress)>,
+}
+
+impl Mesh {
+    //頂点とインデックスをステージングバッファ経由でDEVICE_LOCALなメモリに転送する
+    //2つのコピーは1回のsubmitにまとめる
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        vertices: &[Vertex],
+        indices: &[u32],
+    ) -> Self {
+        Self::with_usage(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            vertices,
+            indices,
+            vk::BufferUsageFlags::empty(),
+        )
+    }
+
+    //newの頂点バッファとインデックスバッファにusageを追加する
+    //STORAGE_BUFFERの場合は頂点シェーダーとメッシュシェーダーから、ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHRの場合は加速構造のビルドから読めるようにする
+    //SHADER_DEVICE_ADDRESSの場合はバッファのデバイスアドレスを取得するので、bufferDeviceAddressの機能を有効にしたデバイスでのみ使える
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_usage(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        vertices: &[Vertex],
+        indices: &[u32],
+        usage: vk::BufferUsageFlags,
+    ) -> Self {
+        let vertex_upload = StagedBuffer::new(
+            instance,
+            physical_device,
+            device,
+            vertices,
+            vk::BufferUsageFlags::VERTEX_BUFFER | usage,
+        );
+
+        let index_upload = StagedBuffer::new(
+            instance,
+            physical_device,
+            device,
+            indices,
+            vk::BufferUsageFlags::INDEX_BUFFER | usage,
+        );
+
+        //コピーの書き込みを頂点入力から読めるようにする
+        //頂点シェーダーやメッシュシェーダー、加速構造のビルドで直接読む場合はそちらも待たせる
+        let mut dst_stage_mask =
+            vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT | vk::PipelineStageFlags2::INDEX_INPUT;
+        let mut dst_access_mask =
+            vk::AccessFlags2::VERTEX_ATTRIBUTE_READ | vk::AccessFlags2::INDEX_READ;
+
+        //PRE_RASTERIZATION_SHADERSは頂点シェーダーとタスクシェーダー、メッシュシェーダーを含む
+        if usage.contains(vk::BufferUsageFlags::STORAGE_BUFFER) {
+            dst_stage_mask |= vk::PipelineStageFlags2::PRE_RASTERIZATION_SHADERS;
+            dst_access_mask |= vk::AccessFlags2::SHADER_STORAGE_READ;
+        }
+
+        if usage.contains(vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR) {
+            dst_stage_mask |= vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_KHR;
+            dst_access_mask |= vk::AccessFlags2::SHADER_READ;
+        }
+
+        let memory_barrier = vk::MemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::COPY)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(dst_stage_mask)
+            .dst_access_mask(dst_access_mask)
+            .build();
+
+        let records: Vec<Record> = vec![
+            Box::new(|command_buffer: vk::CommandBuffer| {
+                vertex_upload.cmd_copy(device, command_buffer)
+            }),
+            Box::new(|command_buffer: vk::CommandBuffer| {
+                index_upload.cmd_copy(device, command_buffer)
+            }),
+            Box::new(|command_buffer: vk::CommandBuffer| {
+                synchronization.cmd_pipeline_barrier(
+                    device,
+                    command_buffer,
+                    &[memory_barrier],
+                    &[],
+                    &[],
+                )
+            }),
+        ];
+
+        one_time_commands
+            .run_batch(device, synchronization, records)
+            .expect("Failed to upload mesh");
+
+        let (vertex_buffer, vertex_memory) = vertex_upload.finish(device);
+        let (index_buffer, index_memory) = index_upload.finish(device);
+
+        let device_addresses = usage
+            .contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS)
+            .then(|| {
+                (
+                    buffer::device_address(device, vertex_buffer),
+                    buffer::device_address(device, index_buffer),
+                )
+            });
+
+        Self {
+            vertex_buffer,
+            vertex_memory,
+            index_buffer,
+            index_memory,
+            vertex_count: vertices.len() as u32,
+            index_count: indices.len() as u32,
+            bounds: Aabb::from_vertices(vertices),
+            device_addresses,
+        }
+    }
+
+    //転送先のバッファだけを作り、中身は呼び出し側がTRANSFER_DSTとして書き込む
+    //asset_uploadでフレームをまたいで少しずつ転送する場合に使う
+    pub fn allocate(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        vertices: &[Vertex],
+        indices: &[u32],
+    ) -> Self {
+        let (vertex_buffer, vertex_memory) = buffer::create_buffer(
+            instance,
+            physical_device,
+            device,
+            mem::size_of_val(vertices) as vk::DeviceSize,
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+
+        let (index_buffer, index_memory) = buffer::create_buffer(
+            instance,
+            physical_device,
+            device,
+            mem::size_of_val(indices) as vk::DeviceSize,
+            vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+
+        Self {
+            vertex_buffer,
+            vertex_memory,
+            index_buffer,
+            index_memory,
+            vertex_count: vertices.len() as u32,
+            index_count: indices.len() as u32,
+            bounds: Aabb::from_vertices(vertices),
+            device_addresses: None,
+        }
+    }
+
+    //Y軸が上向きで原点を中心とする三角形
+    //+Z方向から見て表になるので法線は+Z
+    //usageはwith_usageで追加するもの
+    pub fn triangle(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        usage: vk::BufferUsageFlags,
+    ) -> Self {
+        let vertices = [
+            Vertex {
+                position: [0.0, 1.0, 0.0],
+                color: [1.0, 0.0, 0.0],
+                normal: [0.0, 0.0, 1.0],
+                tex_coord: [0.5, 0.0],
+                tangent: QUAD_TANGENT,
+            },
+            Vertex {
+                position: [1.0, -1.0, 0.0],
+                color: [0.0, 1.0, 0.0],
+                normal: [0.0, 0.0, 1.0],
+                tex_coord: [1.0, 1.0],
+                tangent: QUAD_TANGENT,
+            },
+            Vertex {
+                position: [-1.0, -1.0, 0.0],
+                color: [0.0, 0.0, 1.0],
+                normal: [0.0, 0.0, 1.0],
+                tex_coord: [0.0, 1.0],
+                tangent: QUAD_TANGENT,
+            },
+        ];
+
+        Self::with_usage(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            &vertices,
+            &[0, 1, 2],
+            usage,
+        )
+    }
+
+    //XY平面上の一辺が1の四角形、法線は+Z
+    pub fn quad(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        usage: vk::BufferUsageFlags,
+    ) -> Self {
+        let vertices = [
+            Vertex {
+                position: [-0.5, 0.5, 0.0],
+                color: [1.0, 0.0, 0.0],
+                normal: [0.0, 0.0, 1.0],
+                tex_coord: [0.0, 0.0],
+                tangent: QUAD_TANGENT,
+            },
+            Vertex {
+                position: [0.5, 0.5, 0.0],
+                color: [0.0, 1.0, 0.0],
+                normal: [0.0, 0.0, 1.0],
+                tex_coord: [1.0, 0.0],
+                tangent: QUAD_TANGENT,
+            },
+            Vertex {
+                position: [0.5, -0.5, 0.0],
+                color: [0.0, 0.0, 1.0],
+                normal: [0.0, 0.0, 1.0],
+                tex_coord: [1.0, 1.0],
+                tangent: QUAD_TANGENT,
+            },
+            Vertex {
+                position: [-0.5, -0.5, 0.0],
+                color: [1.0, 1.0, 1.0],
+                normal: [0.0, 0.0, 1.0],
+                tex_coord: [0.0, 1.0],
+                tangent: QUAD_TANGENT,
+            },
+        ];
+
+        //画面上で時計回りになるようにする
+        Self::with_usage(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            &vertices,
+            &[0, 1, 2, 2, 3, 0],
+            usage,
+        )
+    }
+
+    pub fn cmd_bind(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffer], &[0]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                self.index_buffer,
+                0,
+                vk::IndexType::UINT32,
+            );
+        }
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.vertex_count
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.index_count
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    //vertex pullingでstorage bufferとして紐づける(頂点バッファ, インデックスバッファ)
+    pub fn buffers(&self) -> (vk::Buffer, vk::Buffer) {
+        (self.vertex_buffer, self.index_buffer)
+    }
+
+    pub fn device_addresses(&self) -> Option<(vk::DeviceAddress, vk::DeviceAddress)> {
+        self.device_addresses
+    }
+
+    //バッファを作らずにメッシュの参照だけが要るテストに使う、destroyを呼んではいけない
+    #[cfg(test)]
+    pub fn placeholder() -> Self {
+        Self {
+            vertex_buffer: vk::Buffer::null(),
+            vertex_memory: vk::DeviceMemory::null(),
+            index_buffer: vk::Buffer::null(),
+            index_memory: vk::DeviceMemory::null(),
+            vertex_count: 0,
+            index_count: 0,
+            bounds: Aabb::from_vertices(&[]),
+            device_addresses: None,
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_buffer(self.vertex_buffer, None);
+            memory_budget::free_memory(device, self.vertex_memory);
+            device.destroy_buffer(self.index_buffer, None);
+            memory_budget::free_memory(device, self.index_memory);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use crate::mesh::Mesh;
+use crate::queue_family::QueueFamilyIndices;
+use ash::extensions::ext::MeshShader;
+use ash::{vk, Device, Instance};
+use std::ffi::CStr;
+
+//shaders/glsl/mesh_shading.taskとmesh_shading.meshの定数と合わせる
+//1つのメッシュシェーダーのワークグループが描画する三角形の数
+pub const MESHLET_TRIANGLES: u32 = 64;
+//1つのタスクシェーダーのワークグループが起動するメッシュシェーダーのワークグループの数
+pub const MESHLETS_PER_TASK: u32 = 32;
+
+//頂点シェーダーの代わりにタスクシェーダーとメッシュシェーダーでメッシュを描画する
+//メッシュシェーダーはvertex pullingと同じくset = 2のstorage bufferから頂点とインデックスを読み、
+//MESHLET_TRIANGLES個ずつの三角形をメッシュレットとして書き出す
+//頂点入力もInput Assemblyも使わないので、パイプラインにはそれらの状態を指定しない
+pub struct MeshShading {
+    loader: MeshShader,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+}
+
+impl MeshShading {
+    //shader_codesはbuild.rsでコンパイルしたタスクシェーダーとメッシュシェーダー
+    //glslcが無く空のファイルになっている場合も使えないものとして扱う
+    pub fn is_supported(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        api_version: u32,
+        shader_codes: [&[u8]; 2],
+    ) -> bool {
+        //VK_EXT_mesh_shaderはSPIR-V 1.4を要求するので、VK_KHR_spirv_1_4を使わずに済むVulkan 1.2以降に限る
+        if api_version < vk::make_api_version(0, 1, 2, 0) {
+            return false;
+        }
+
+        if shader_codes.iter().any(|code| code.is_empty()) {
+            return false;
+        }
+
+        if !QueueFamilyIndices::is_device_extension_supported(
+            instance,
+            physical_device,
+            Self::extension_name(),
+        ) {
+            return false;
+        }
+
+        let mut mesh_shader_features = vk::PhysicalDeviceMeshShaderFeaturesEXT::default();
+        let mut features = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut mesh_shader_features)
+            .build();
+
+        unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+
+        mesh_shader_features.task_shader == vk::TRUE && mesh_shader_features.mesh_shader == vk::TRUE
+    }
+
+    //DeviceCreateInfoで有効にする必要のある拡張
+    pub fn extension_name() -> &'static CStr {
+        MeshShader::name()
+    }
+
+    //meshはMesh::with_usageでSTORAGE_BUFFERを付けて作ったものを渡す
+    pub fn new(instance: &Instance, device: &Device, mesh: &Mesh) -> Self {
+        //タスクシェーダーはインデックスの数からメッシュレットの数を求めるのでインデックスだけを読む
+        let bindings = [0, 1].map(|binding| {
+            let stage_flags = if binding == 1 {
+                vk::ShaderStageFlags::TASK_EXT | vk::ShaderStageFlags::MESH_EXT
+            } else {
+                vk::ShaderStageFlags::MESH_EXT
+            };
+
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(stage_flags)
+                .build()
+        });
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        };
+
+        let pool_sizes = [vk::DescriptorPoolSize::builder()
+            .ty(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(2)
+            .build()];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+
+        let set_layouts = [descriptor_set_layout];
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&set_layouts)
+            .build();
+
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] };
+
+        let (vertex_buffer, index_buffer) = mesh.buffers();
+
+        //シェーダーはインデックスの数をインデックスバッファの長さから求めるので、バッファ全体を渡す
+        let buffer_infos = [vertex_buffer, index_buffer].map(|buffer| {
+            [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE)
+                .build()]
+        });
+
+        let descriptor_writes = buffer_infos
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer_info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(binding as u32)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(buffer_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        log::info!(
+            "Mesh shading: {} triangles per meshlet, {} meshlets per task workgroup",
+            MESHLET_TRIANGLES,
+            MESHLETS_PER_TASK
+        );
+
+        Self {
+            loader: MeshShader::new(instance, device),
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_set,
+        }
+    }
+
+    //パイプラインレイアウトのset = 2に使う
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    pub fn cmd_bind(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                2,
+                &[self.descriptor_set],
+                &[],
+            );
+        }
+    }
+
+    //メッシュシェーダーのパイプラインを紐づけた状態で、index_count個のインデックスの三角形を描画する
+    pub fn cmd_draw(&self, command_buffer: vk::CommandBuffer, index_count: u32) {
+        let task_count = task_group_count(index_count);
+
+        if task_count == 0 {
+            return;
+        }
+
+        unsafe {
+            self.loader
+                .cmd_draw_mesh_tasks(command_buffer, task_count, 1, 1);
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
+
+//index_count個のインデックスの三角形を全て描画するのに必要なタスクシェーダーのワークグループの数
+//最後のワークグループが起動するメッシュレットの数はタスクシェーダーが減らす
+fn task_group_count(index_count: u32) -> u32 {
+    let triangle_count = index_count / 3;
+    let meshlet_count = (triangle_count + MESHLET_TRIANGLES - 1) / MESHLET_TRIANGLES;
+
+    (meshlet_count + MESHLETS_PER_TASK - 1) / MESHLETS_PER_TASK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_empty_mesh_dispatches_nothing() {
+        assert_eq!(task_group_count(0), 0);
+        //三角形にならない余りのインデックスは描画しない
+        assert_eq!(task_group_count(2), 0);
+    }
+
+    #[test]
+    fn the_triangle_is_one_meshlet_in_one_task() {
+        assert_eq!(task_group_count(3), 1);
+    }
+
+    #[test]
+    fn tasks_are_added_per_full_set_of_meshlets() {
+        let triangles_per_task = MESHLET_TRIANGLES * MESHLETS_PER_TASK;
+
+        assert_eq!(task_group_count(triangles_per_task * 3), 1);
+        assert_eq!(task_group_count((triangles_per_task + 1) * 3), 2);
+        assert_eq!(task_group_count(triangles_per_task * 3 * 5), 5);
+    }
+}
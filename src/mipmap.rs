@@ -0,0 +1,670 @@
+use crate::compute;
+use crate::descriptor_allocator::{DescriptorAllocator, DescriptorLayoutCache};
+use crate::one_time_commands::OneTimeCommands;
+use crate::synchronization::Synchronization;
+use crate::texture::{Texture2D, TextureOptions};
+use ash::{vk, Device, Instance};
+use glam::UVec2;
+use std::mem;
+use std::str::FromStr;
+
+//シェーダー側のmain_cs_downsampleのthreadsと合わせる
+const WORKGROUP_SIZE: u32 = 8;
+
+//--compare-mipmapsで両方の方法で作るテクスチャの大きさ
+//奇数の大きさの段ではBlitが2×2の箱フィルターにならないので、全ての段で割り切れる大きさにする
+const COMPARISON_EXTENT: vk::Extent2D = vk::Extent2D {
+    width: 128,
+    height: 32,
+};
+
+//--compare-mipmapsで比べるフォーマット
+pub const COMPARISON_FORMATS: [vk::Format; 2] =
+    [vk::Format::R8G8B8A8_UNORM, vk::Format::R8G8B8A8_SRGB];
+
+//テクスチャの作成時にミップマップを生成する方法
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MipmapMode {
+    //cmd_blit_imageで1つ上の段からLINEARで縮小する、BLIT_SRCとBLIT_DSTが必要
+    Blit,
+    //main_cs_downsampleで1つ上の段から2×2の箱フィルターで縮小する、storage imageとして書き込める必要がある
+    Compute,
+    //0段目だけを作る
+    None,
+}
+
+impl MipmapMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Blit => "blit",
+            Self::Compute => "compute",
+            Self::None => "none",
+        }
+    }
+
+    //formatでこの方法が使えるかどうか
+    //Computeはgeneratorが無い場合は使えない
+    pub fn is_supported(
+        self,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        format: vk::Format,
+        generator: Option<&MipGenerator>,
+    ) -> bool {
+        let features = |format| unsafe {
+            instance
+                .get_physical_device_format_properties(physical_device, format)
+                .optimal_tiling_features
+        };
+
+        match self {
+            MipmapMode::Blit => features(format).contains(
+                vk::FormatFeatureFlags::BLIT_SRC
+                    | vk::FormatFeatureFlags::BLIT_DST
+                    | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+            ),
+            MipmapMode::Compute => generator.map_or(false, |generator| {
+                storage_format(format).map_or(false, |(storage_format, _)| {
+                    (storage_format == format || generator.extended_usage)
+                        && features(storage_format).contains(vk::FormatFeatureFlags::STORAGE_IMAGE)
+                })
+            }),
+            MipmapMode::None => true,
+        }
+    }
+
+    //requestedがformatで使えない場合や指定が無い場合は、Blit、Compute、Noneの順に使えるものを選ぶ
+    pub fn select(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        format: vk::Format,
+        requested: Option<Self>,
+        generator: Option<&MipGenerator>,
+    ) -> Self {
+        let supported =
+            |mode: Self| mode.is_supported(instance, physical_device, format, generator);
+
+        match requested {
+            Some(mode) if supported(mode) => return mode,
+            Some(mode) => log::warn!(
+                "{:?} mipmaps cannot be generated for {:?}, choosing another mode",
+                mode,
+                format
+            ),
+            None => {}
+        }
+
+        [MipmapMode::Blit, MipmapMode::Compute]
+            .into_iter()
+            .find(|&mode| supported(mode))
+            .unwrap_or(MipmapMode::None)
+    }
+}
+
+impl FromStr for MipmapMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "blit" => Ok(Self::Blit),
+            "compute" => Ok(Self::Compute),
+            "none" => Ok(Self::None),
+            _ => Err(format!(
+                "Unknown mipmap mode '{}', expected blit, compute or none",
+                s
+            )),
+        }
+    }
+}
+
+//1×1になるまでの段の数
+pub fn level_count(extent: vk::Extent2D) -> u32 {
+    32 - extent.width.max(extent.height).max(1).leading_zeros()
+}
+
+//level段目の大きさ、0にはならない
+pub fn level_extent(extent: vk::Extent2D, level: u32) -> vk::Extent2D {
+    vk::Extent2D {
+        width: (extent.width >> level).max(1),
+        height: (extent.height >> level).max(1),
+    }
+}
+
+//storage imageとして書き込む時のフォーマットと、シェーダーでsRGBの変換をするかどうか
+//sRGBのフォーマットはstorage imageに使えないので、MUTABLE_FORMATで作ってUNORMのviewで読み書きする
+pub fn storage_format(format: vk::Format) -> Option<(vk::Format, bool)> {
+    match format {
+        vk::Format::R8G8B8A8_UNORM => Some((vk::Format::R8G8B8A8_UNORM, false)),
+        vk::Format::R8G8B8A8_SRGB => Some((vk::Format::R8G8B8A8_UNORM, true)),
+        _ => None,
+    }
+}
+
+//全ての段がTRANSFER_DST_OPTIMALで0段目をコピーで書き込んだ後に呼び、全ての段をSHADER_READ_ONLY_OPTIMALにする
+//i段目をTRANSFER_SRC_OPTIMALにしてi + 1段目へblitし、読み終わった段から順にSHADER_READ_ONLY_OPTIMALにする
+pub fn cmd_generate_blit(
+    device: &Device,
+    synchronization: &Synchronization,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    extent: vk::Extent2D,
+    level_count: u32,
+) {
+    let barrier =
+        |level, (src_stage, src_access, old_layout), (dst_stage, dst_access, new_layout)| {
+            vk::ImageMemoryBarrier2::builder()
+                .src_stage_mask(src_stage)
+                .src_access_mask(src_access)
+                .dst_stage_mask(dst_stage)
+                .dst_access_mask(dst_access)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image)
+                .subresource_range(level_range(level))
+                .build()
+        };
+
+    //0段目はコピーで、それ以外は1つ上の段からのblitで書き込まれている
+    let written = |level| {
+        let stage = if level == 0 {
+            vk::PipelineStageFlags2::COPY
+        } else {
+            vk::PipelineStageFlags2::BLIT
+        };
+        (
+            stage,
+            vk::AccessFlags2::TRANSFER_WRITE,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        )
+    };
+    let blit_source = (
+        vk::PipelineStageFlags2::BLIT,
+        vk::AccessFlags2::TRANSFER_READ,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+    );
+    let shader_read = (
+        vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        vk::AccessFlags2::SHADER_SAMPLED_READ,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    );
+
+    for level in 1..level_count {
+        let src = level_extent(extent, level - 1);
+        let dst = level_extent(extent, level);
+
+        let blit = vk::ImageBlit::builder()
+            .src_subresource(level_layers(level - 1))
+            .src_offsets([
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: src.width as i32,
+                    y: src.height as i32,
+                    z: 1,
+                },
+            ])
+            .dst_subresource(level_layers(level))
+            .dst_offsets([
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: dst.width as i32,
+                    y: dst.height as i32,
+                    z: 1,
+                },
+            ])
+            .build();
+
+        synchronization.cmd_pipeline_barrier(
+            device,
+            command_buffer,
+            &[],
+            &[],
+            &[barrier(level - 1, written(level - 1), blit_source)],
+        );
+
+        unsafe {
+            device.cmd_blit_image(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            );
+        }
+
+        synchronization.cmd_pipeline_barrier(
+            device,
+            command_buffer,
+            &[],
+            &[],
+            &[barrier(level - 1, blit_source, shader_read)],
+        );
+    }
+
+    //最後の段は読まれないままTRANSFER_DST_OPTIMALで残っている
+    let last = level_count - 1;
+    synchronization.cmd_pipeline_barrier(
+        device,
+        command_buffer,
+        &[],
+        &[],
+        &[barrier(last, written(last), shader_read)],
+    );
+}
+
+fn level_range(level: u32) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(level)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build()
+}
+
+fn level_layers(level: u32) -> vk::ImageSubresourceLayers {
+    vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(level)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build()
+}
+
+//シェーダー側のDownsampleConstantsと同じレイアウト
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct DownsampleConstants {
+    src_size: UVec2,
+    dst_size: UVec2,
+    //1の場合はsRGBの値をリニアに戻してから平均し、sRGBに戻して書き込む
+    srgb: u32,
+}
+
+//MipmapMode::Computeで使うコンピュートパイプライン
+//テクスチャを作る間だけ使うので、作り終えたら破棄してよい
+pub struct MipGenerator {
+    //EXTENDED_USAGEはVulkan 1.1のコア機能で、無い場合はsRGBのフォーマットのミップマップを生成できない
+    extended_usage: bool,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl MipGenerator {
+    //Descriptor Set Layoutの破棄はDescriptorLayoutCacheに任せる
+    //api_versionはdevice_info::effective_api_versionで決めたもの
+    pub fn new(
+        device: &Device,
+        api_version: u32,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        pipeline_cache: vk::PipelineCache,
+        shader_module: vk::ShaderModule,
+    ) -> Self {
+        //binding = 0が読む段、binding = 1が書き込む段
+        let bindings = [0, 1].map(|binding| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+        });
+
+        let descriptor_set_layout = descriptor_layout_cache.get(device, &bindings, &[]);
+
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(mem::size_of::<DownsampleConstants>() as u32)
+            .build();
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&[descriptor_set_layout])
+            .push_constant_ranges(&[push_constant_range])
+            .build();
+
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .unwrap()
+        };
+
+        let pipeline = compute::create_compute_pipeline(
+            device,
+            pipeline_cache,
+            shader_module,
+            "main_cs_downsample",
+            pipeline_layout,
+        );
+
+        Self {
+            extended_usage: api_version >= vk::make_api_version(0, 1, 1, 0),
+            descriptor_set_layout,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    //imageの段ごとのstorage_formatのviewと、隣り合う段を紐づけたDescriptor Set
+    //コマンドの実行が終わるまで残しておく必要があるので、記録の前に作って終わった後に破棄する
+    pub fn prepare(
+        &self,
+        device: &Device,
+        image: vk::Image,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        level_count: u32,
+    ) -> ComputeMips {
+        let (storage_format, srgb) =
+            storage_format(format).expect("The format cannot be written as a storage image");
+
+        let views = (0..level_count)
+            .map(|level| {
+                let view_info = vk::ImageViewCreateInfo::builder()
+                    .image(image)
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(storage_format)
+                    .subresource_range(level_range(level))
+                    .build();
+
+                unsafe { device.create_image_view(&view_info, None).unwrap() }
+            })
+            .collect::<Vec<_>>();
+
+        let mut descriptor_allocator = DescriptorAllocator::new();
+
+        let descriptor_sets = views
+            .windows(2)
+            .map(|pair| {
+                let descriptor_set =
+                    descriptor_allocator.allocate(device, self.descriptor_set_layout, None);
+
+                let image_infos = pair
+                    .iter()
+                    .map(|&view| {
+                        [vk::DescriptorImageInfo::builder()
+                            .image_view(view)
+                            .image_layout(vk::ImageLayout::GENERAL)
+                            .build()]
+                    })
+                    .collect::<Vec<_>>();
+
+                let descriptor_writes = image_infos
+                    .iter()
+                    .enumerate()
+                    .map(|(binding, image_info)| {
+                        vk::WriteDescriptorSet::builder()
+                            .dst_set(descriptor_set)
+                            .dst_binding(binding as u32)
+                            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                            .image_info(image_info)
+                            .build()
+                    })
+                    .collect::<Vec<_>>();
+
+                unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+                descriptor_set
+            })
+            .collect();
+
+        ComputeMips {
+            pipeline: self.pipeline,
+            pipeline_layout: self.pipeline_layout,
+            image,
+            extent,
+            srgb,
+            views,
+            descriptor_allocator,
+            descriptor_sets,
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+//MipGenerator::prepareで作った1つのテクスチャ用のリソース
+pub struct ComputeMips {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    image: vk::Image,
+    extent: vk::Extent2D,
+    srgb: bool,
+    //段ごとのstorage imageのview
+    views: Vec<vk::ImageView>,
+    descriptor_allocator: DescriptorAllocator,
+    //i番目がi段目からi + 1段目に縮小するもの
+    descriptor_sets: Vec<vk::DescriptorSet>,
+}
+
+impl ComputeMips {
+    //cmd_generate_blitと同じく、全ての段がTRANSFER_DST_OPTIMALで0段目をコピーで書き込んだ後に呼ぶ
+    //全ての段をGENERALにして上の段から順に縮小し、最後に全ての段をSHADER_READ_ONLY_OPTIMALにする
+    pub fn cmd_generate(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        let level_count = self.views.len() as u32;
+
+        let barrier = |base_level,
+                       level_count,
+                       (src_stage, src_access, old_layout),
+                       (dst_stage, dst_access, new_layout)| {
+            vk::ImageMemoryBarrier2::builder()
+                .src_stage_mask(src_stage)
+                .src_access_mask(src_access)
+                .dst_stage_mask(dst_stage)
+                .dst_access_mask(dst_access)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(self.image)
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .base_mip_level(base_level)
+                        .level_count(level_count)
+                        .base_array_layer(0)
+                        .layer_count(1)
+                        .build(),
+                )
+                .build()
+        };
+
+        let copied = (
+            vk::PipelineStageFlags2::COPY,
+            vk::AccessFlags2::TRANSFER_WRITE,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+        let storage = (
+            vk::PipelineStageFlags2::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            vk::ImageLayout::GENERAL,
+        );
+        let shader_read = (
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            vk::AccessFlags2::SHADER_SAMPLED_READ,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+
+        //1段目以降の内容は全て書き換えるが、まとめて遷移させる
+        synchronization.cmd_pipeline_barrier(
+            device,
+            command_buffer,
+            &[],
+            &[],
+            &[barrier(0, level_count, copied, storage)],
+        );
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+        }
+
+        for (index, &descriptor_set) in self.descriptor_sets.iter().enumerate() {
+            let level = index as u32 + 1;
+            let src = level_extent(self.extent, level - 1);
+            let dst = level_extent(self.extent, level);
+
+            let constants = DownsampleConstants {
+                src_size: UVec2::new(src.width, src.height),
+                dst_size: UVec2::new(dst.width, dst.height),
+                srgb: self.srgb as u32,
+            };
+
+            unsafe {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pipeline_layout,
+                    0,
+                    &[descriptor_set],
+                    &[],
+                );
+                device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    std::slice::from_raw_parts(
+                        &constants as *const DownsampleConstants as *const u8,
+                        mem::size_of::<DownsampleConstants>(),
+                    ),
+                );
+                device.cmd_dispatch(
+                    command_buffer,
+                    (dst.width + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                    (dst.height + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                    1,
+                );
+            }
+
+            //次のディスパッチがこの段を読む前に書き込みを終わらせる
+            synchronization.cmd_pipeline_barrier(
+                device,
+                command_buffer,
+                &[],
+                &[],
+                &[barrier(level, 1, storage, storage)],
+            );
+        }
+
+        synchronization.cmd_pipeline_barrier(
+            device,
+            command_buffer,
+            &[],
+            &[],
+            &[barrier(0, level_count, storage, shader_read)],
+        );
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.descriptor_allocator.destroy(device);
+
+        for &view in &self.views {
+            unsafe { device.destroy_image_view(view, None) };
+        }
+    }
+}
+
+//--compare-mipmapsで使う、段ごとに色が滑らかに変わる模様
+fn comparison_pattern(extent: vk::Extent2D) -> Vec<u8> {
+    (0..extent.height)
+        .flat_map(|y| (0..extent.width).map(move |x| (x, y)))
+        .flat_map(|(x, y)| {
+            [
+                (x * 255 / extent.width) as u8,
+                (y * 255 / extent.height) as u8,
+                ((x * 7 + y * 13) % 256) as u8,
+                if (x / 4 + y / 4) % 2 == 0 { 255 } else { 64 },
+            ]
+        })
+        .collect()
+}
+
+//同じ画像のミップマップをBlitとComputeの両方で生成して読み戻し、段ごとのチャンネルの最大の差を返す
+//フィルターの実装の誤りを見つけるためのもので、丸め方の違いで1か2程度の差は出る
+pub fn compare(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    device: &Device,
+    one_time_commands: &OneTimeCommands,
+    synchronization: &Synchronization,
+    generator: &MipGenerator,
+    format: vk::Format,
+) -> Result<Vec<u8>, String> {
+    for mode in [MipmapMode::Blit, MipmapMode::Compute] {
+        if !mode.is_supported(instance, physical_device, format, Some(generator)) {
+            return Err(format!(
+                "{:?} mipmaps cannot be generated for {:?}",
+                mode, format
+            ));
+        }
+    }
+
+    let pixels = comparison_pattern(COMPARISON_EXTENT);
+
+    let [blit, compute] = [MipmapMode::Blit, MipmapMode::Compute].map(|mode| {
+        Texture2D::with_options(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            format,
+            COMPARISON_EXTENT,
+            &pixels,
+            TextureOptions {
+                mipmap_mode: Some(mode),
+                mip_generator: Some(generator),
+            },
+        )
+    });
+
+    let blit_levels = blit.read_levels(
+        instance,
+        physical_device,
+        device,
+        one_time_commands,
+        synchronization,
+    );
+    let compute_levels = compute.read_levels(
+        instance,
+        physical_device,
+        device,
+        one_time_commands,
+        synchronization,
+    );
+
+    blit.destroy(device);
+    compute.destroy(device);
+
+    let differences = blit_levels?
+        .iter()
+        .zip(&compute_levels?)
+        .map(|(blit, compute)| {
+            blit.iter()
+                .zip(compute)
+                .map(|(a, b)| (*a as i16 - *b as i16).unsigned_abs() as u8)
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    Ok(differences)
+}
@@ -0,0 +1,174 @@
+use crate::buffer;
+use crate::memory_budget::{self, MemoryCategory};
+use ash::{vk, Device, Instance};
+
+//--msaaと設定ファイルで指定できるサンプル数の上限、VkSampleCountFlagsの一番大きいビット
+pub const MAX_SAMPLES: u32 = 64;
+
+//VkSampleCountFlagsのビットと同じく、1から64までの2のべき乗だけを使える
+pub fn is_valid_sample_count(samples: u32) -> bool {
+    samples.is_power_of_two() && samples <= MAX_SAMPLES
+}
+
+//カラーとデプスの両方のアタッチメントで使えるサンプル数
+pub fn supported_sample_counts(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+) -> vk::SampleCountFlags {
+    let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+
+    limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts
+}
+
+//requested以下でsupportedに含まれる一番大きいサンプル数、TYPE_1は必ず使える
+pub fn choose_sample_count(
+    requested: u32,
+    supported: vk::SampleCountFlags,
+) -> vk::SampleCountFlags {
+    let mut samples = requested.clamp(1, MAX_SAMPLES);
+
+    while samples > 1 && !supported.contains(vk::SampleCountFlags::from_raw(samples)) {
+        samples /= 2;
+    }
+
+    vk::SampleCountFlags::from_raw(samples)
+}
+
+//シーンのパスが描画するマルチサンプルのカラーアタッチメント
+//パスの最後にswapchainの画像へresolveするので、中身はパスの外に残さない
+//swapchainの画像を作り直す度に同じ大きさで作り直す
+pub struct MsaaTarget {
+    pub image: vk::Image,
+    memory: vk::DeviceMemory,
+    pub view: vk::ImageView,
+}
+
+impl MsaaTarget {
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        samples: vk::SampleCountFlags,
+    ) -> Self {
+        //resolveした後は読まないのでTRANSIENTにし、対応していればメモリを確保せずに済ませてもらう
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(samples)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+            )
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+
+        let image = unsafe { device.create_image(&image_info, None).unwrap() };
+
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
+
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(buffer::find_memory_type(
+                instance,
+                physical_device,
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ))
+            .build();
+
+        let memory = unsafe {
+            memory_budget::allocate_memory(device, &alloc_info, MemoryCategory::Attachment).unwrap()
+        };
+
+        unsafe { device.bind_image_memory(image, memory, 0).unwrap() };
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .base_mip_level(0)
+                    .level_count(1)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .build();
+
+        let view = unsafe { device.create_image_view(&view_info, None).unwrap() };
+
+        log::info!(
+            "MSAA target: {}x{}, {} samples",
+            extent.width,
+            extent.height,
+            samples.as_raw()
+        );
+
+        Self {
+            image,
+            memory,
+            view,
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+            memory_budget::free_memory(device, self.memory);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_powers_of_two_up_to_64_are_valid() {
+        for samples in [1, 2, 4, 8, 16, 32, 64] {
+            assert!(is_valid_sample_count(samples));
+        }
+
+        for samples in [0, 3, 6, 12, 65, 128] {
+            assert!(!is_valid_sample_count(samples));
+        }
+    }
+
+    #[test]
+    fn unsupported_counts_fall_back_to_the_next_lower_one() {
+        let supported = vk::SampleCountFlags::TYPE_1
+            | vk::SampleCountFlags::TYPE_2
+            | vk::SampleCountFlags::TYPE_4
+            | vk::SampleCountFlags::TYPE_8;
+
+        assert_eq!(
+            choose_sample_count(4, supported),
+            vk::SampleCountFlags::TYPE_4
+        );
+        assert_eq!(
+            choose_sample_count(64, supported),
+            vk::SampleCountFlags::TYPE_8
+        );
+        assert_eq!(
+            choose_sample_count(1, supported),
+            vk::SampleCountFlags::TYPE_1
+        );
+        assert_eq!(
+            choose_sample_count(8, vk::SampleCountFlags::TYPE_1),
+            vk::SampleCountFlags::TYPE_1
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use crate::descriptor_allocator::{DescriptorAllocator, DescriptorLayoutCache};
+use crate::one_time_commands::OneTimeCommands;
+use crate::synchronization::Synchronization;
+use crate::texture::Texture2D;
+use ash::{vk, Device, Instance};
+use std::mem;
+
+//生成する法線マップの1辺のピクセル数
+const TEXTURE_SIZE: u32 = 128;
+//1辺に並べる半球の出っ張りの数
+const BUMP_CELLS: u32 = 4;
+//マスの大きさに対する半球の半径の割合
+const BUMP_RADIUS: f32 = 0.35;
+
+//法線は色ではないのでsRGBにしない
+const FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+//シェーダー側のNormalMapConstantsと同じレイアウト
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct NormalMapConstants {
+    //Mキーで切り替える、0の場合は頂点の法線でライティングする
+    pub enabled: u32,
+}
+
+//--normal-mappingでメッシュに貼る接空間の法線マップ
+//マスごとに半球の出っ張りを並べた模様を生成し、緑がVの増える方向(画像の下向き)の形式で書き込む
+//メインのパイプラインのset = 2で、binding = 0がsampler、binding = 1がテクスチャ
+pub struct NormalMap {
+    texture: Texture2D,
+    //DescriptorLayoutCacheが持つので破棄しない
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_allocator: DescriptorAllocator,
+    descriptor_set: vk::DescriptorSet,
+}
+
+impl NormalMap {
+    //samplerの破棄はSamplerCacheに、Descriptor Set Layoutの破棄はDescriptorLayoutCacheに任せる
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        sampler: vk::Sampler,
+    ) -> Self {
+        let texture = Texture2D::new(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            FORMAT,
+            vk::Extent2D {
+                width: TEXTURE_SIZE,
+                height: TEXTURE_SIZE,
+            },
+            &Self::bumps(),
+        );
+
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        ];
+
+        let descriptor_set_layout = descriptor_layout_cache.get(device, &bindings, &[]);
+
+        let mut descriptor_allocator = DescriptorAllocator::new();
+        let descriptor_set = descriptor_allocator.allocate(device, descriptor_set_layout, None);
+
+        let sampler_info = [vk::DescriptorImageInfo::builder().sampler(sampler).build()];
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .image_view(texture.view())
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()];
+
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .image_info(&sampler_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(&image_info)
+                .build(),
+        ];
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        log::info!(
+            "Normal map: {}x{} with {}x{} bumps",
+            TEXTURE_SIZE,
+            TEXTURE_SIZE,
+            BUMP_CELLS,
+            BUMP_CELLS
+        );
+
+        Self {
+            texture,
+            descriptor_set_layout,
+            descriptor_allocator,
+            descriptor_set,
+        }
+    }
+
+    //パイプラインレイアウトのset = 2に使う
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    //set = 2に法線マップを紐づけ、enabledをpush constantで渡す
+    //パイプラインを紐づけ直した後に毎回呼ぶ
+    pub fn cmd_bind(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        enabled: bool,
+    ) {
+        let constants = NormalMapConstants {
+            enabled: enabled as u32,
+        };
+
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                2,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                std::slice::from_raw_parts(
+                    &constants as *const NormalMapConstants as *const u8,
+                    mem::size_of::<NormalMapConstants>(),
+                ),
+            );
+        }
+    }
+
+    //マスの中心からの距離がBUMP_RADIUSより近い所は半球の外向きの法線、それ以外は(0, 0, 1)
+    //xはUの増える方向、yはVの増える方向で、-1..1を0..255に詰める
+    fn bumps() -> Vec<u8> {
+        let cell_size = (TEXTURE_SIZE / BUMP_CELLS) as f32;
+        let radius = cell_size * BUMP_RADIUS;
+
+        let encode = |value: f32| ((value * 0.5 + 0.5) * 255.0).round() as u8;
+
+        (0..TEXTURE_SIZE)
+            .flat_map(|y| (0..TEXTURE_SIZE).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                //ピクセルの中心からマスの中心までの距離
+                let dx = (x as f32 + 0.5) % cell_size - cell_size / 2.0;
+                let dy = (y as f32 + 0.5) % cell_size - cell_size / 2.0;
+                let distance_squared = dx * dx + dy * dy;
+
+                let [nx, ny, nz] = if distance_squared < radius * radius {
+                    let height = (radius * radius - distance_squared).sqrt();
+                    [dx / radius, dy / radius, height / radius]
+                } else {
+                    [0.0, 0.0, 1.0]
+                };
+
+                [encode(nx), encode(ny), encode(nz), 255]
+            })
+            .collect()
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.descriptor_allocator.destroy(device);
+        self.texture.destroy(device);
+    }
+}
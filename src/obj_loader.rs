@@ -0,0 +1,209 @@
+use crate::mesh::Vertex;
+use glam::{Mat4, Vec2, Vec3};
+use std::path::Path;
+
+//頂点カラーを持たないOBJの色
+const DEFAULT_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+
+//OBJファイルの全てのモデルを1つの頂点配列とインデックス配列にまとめる
+//既存のシーンと同じ大きさで見えるように、バウンディングボックスの中心を原点に移して-1..1に収める
+pub fn load(path: &Path) -> Result<(Vec<Vertex>, Vec<u32>), tobj::LoadError> {
+    //single_indexで位置と法線とテクスチャ座標のインデックスを1つにまとめ、Vertexにそのまま詰められるようにする
+    let options = tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ..Default::default()
+    };
+
+    //マテリアルは使わないのでmtlファイルの読み込みに失敗しても無視する
+    let (models, _) = tobj::load_obj(path, &options)?;
+
+    let mut vertices = vec![];
+    let mut indices = vec![];
+
+    for model in &models {
+        let mesh = &model.mesh;
+        let base = vertices.len() as u32;
+        let vertex_count = mesh.positions.len() / 3;
+
+        let model_vertices = (0..vertex_count).map(|i| Vertex {
+            position: [
+                mesh.positions[3 * i],
+                mesh.positions[3 * i + 1],
+                mesh.positions[3 * i + 2],
+            ],
+            color: if mesh.vertex_color.is_empty() {
+                DEFAULT_COLOR
+            } else {
+                [
+                    mesh.vertex_color[3 * i],
+                    mesh.vertex_color[3 * i + 1],
+                    mesh.vertex_color[3 * i + 2],
+                ]
+            },
+            normal: if mesh.normals.is_empty() {
+                [0.0; 3]
+            } else {
+                [
+                    mesh.normals[3 * i],
+                    mesh.normals[3 * i + 1],
+                    mesh.normals[3 * i + 2],
+                ]
+            },
+            //OBJのVは画像の下が0なので、Vulkanの画像の上が0の向きに反転する
+            tex_coord: if mesh.texcoords.is_empty() {
+                [0.0; 2]
+            } else {
+                [mesh.texcoords[2 * i], 1.0 - mesh.texcoords[2 * i + 1]]
+            },
+            tangent: Vertex::NO_TANGENT,
+        });
+
+        vertices.extend(model_vertices);
+
+        let model_indices = &mesh.indices;
+        let first_index = indices.len();
+        indices.extend(model_indices.iter().map(|index| base + index));
+
+        if mesh.normals.is_empty() {
+            generate_smooth_normals(
+                &mut vertices[base as usize..],
+                &indices[first_index..],
+                base,
+            );
+        }
+
+        //UVが無いモデルはNO_TANGENTのままにして、法線マップを使わずに頂点の法線で描画する
+        if !mesh.texcoords.is_empty() {
+            generate_tangents(
+                &mut vertices[base as usize..],
+                &indices[first_index..],
+                base,
+            );
+        }
+    }
+
+    normalize_bounds(&mut vertices);
+
+    log::info!(
+        "Loaded {}: {} models, {} vertices, {} triangles",
+        path.display(),
+        models.len(),
+        vertices.len(),
+        indices.len() / 3
+    );
+
+    Ok((vertices, indices))
+}
+
+//法線を持たないモデルのために、頂点を共有する面の法線を平均して滑らかな法線を作る
+//外積の長さは三角形の面積の2倍なので、正規化せずに足すと大きい面ほど強く効く面積の重み付けになる
+//indicesはvertices[0]をbaseとしたインデックス
+pub fn generate_smooth_normals(vertices: &mut [Vertex], indices: &[u32], base: u32) {
+    let mut normals = vec![Vec3::ZERO; vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] =
+            [triangle[0], triangle[1], triangle[2]].map(|index| (index - base) as usize);
+
+        let p0 = Vec3::from(vertices[a].position);
+        let p1 = Vec3::from(vertices[b].position);
+        let p2 = Vec3::from(vertices[c].position);
+
+        let face_normal = (p1 - p0).cross(p2 - p0);
+
+        normals[a] += face_normal;
+        normals[b] += face_normal;
+        normals[c] += face_normal;
+    }
+
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        //どの面にも使われていない頂点や潰れた三角形だけに使われている頂点はゼロになる
+        vertex.normal = normal.normalize_or_zero().into();
+    }
+}
+
+//頂点を共有する面のUの増える方向を足し合わせ、法線に直交するように直してtangentにする
+//generate_smooth_normalsと同じく正規化せずに足すので面積の重み付けになる
+//Vの増える方向がcross(normal, tangent)と逆の場合はUVが裏返っているので、tangentのwを-1にする
+//indicesはvertices[0]をbaseとしたインデックス、法線を決めた後に呼ぶ
+fn generate_tangents(vertices: &mut [Vertex], indices: &[u32], base: u32) {
+    let mut tangents = vec![Vec3::ZERO; vertices.len()];
+    let mut bitangents = vec![Vec3::ZERO; vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] =
+            [triangle[0], triangle[1], triangle[2]].map(|index| (index - base) as usize);
+
+        let p0 = Vec3::from(vertices[a].position);
+        let edge1 = Vec3::from(vertices[b].position) - p0;
+        let edge2 = Vec3::from(vertices[c].position) - p0;
+
+        let uv0 = Vec2::from(vertices[a].tex_coord);
+        let delta1 = Vec2::from(vertices[b].tex_coord) - uv0;
+        let delta2 = Vec2::from(vertices[c].tex_coord) - uv0;
+
+        //UVが潰れている三角形は向きが決まらないので使わない
+        let determinant = delta1.x * delta2.y - delta2.x * delta1.y;
+        if determinant.abs() <= f32::EPSILON {
+            continue;
+        }
+
+        let tangent = (edge1 * delta2.y - edge2 * delta1.y) / determinant;
+        let bitangent = (edge2 * delta1.x - edge1 * delta2.x) / determinant;
+
+        for index in [a, b, c] {
+            tangents[index] += tangent;
+            bitangents[index] += bitangent;
+        }
+    }
+
+    for ((vertex, tangent), bitangent) in vertices.iter_mut().zip(tangents).zip(bitangents) {
+        let normal = Vec3::from(vertex.normal);
+
+        //Gram-Schmidtで法線の成分を取り除く
+        let tangent = (tangent - normal * normal.dot(tangent)).normalize_or_zero();
+
+        //UVが潰れた三角形だけに使われている頂点は法線マップを使わない
+        if tangent == Vec3::ZERO {
+            vertex.tangent = Vertex::NO_TANGENT;
+            continue;
+        }
+
+        let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+
+        vertex.tangent = tangent.extend(handedness).into();
+    }
+}
+
+//バウンディングボックスの中心を原点に移して-1..1に収め、頂点に掛けた変換を返す
+//gltf_loaderはこの変換をジョイントの行列にも掛ける
+pub fn normalize_bounds(vertices: &mut [Vertex]) -> Mat4 {
+    let (min, max) = vertices.iter().fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), vertex| {
+            let position = Vec3::from(vertex.position);
+            (min.min(position), max.max(position))
+        },
+    );
+
+    let center = (min + max) * 0.5;
+    let half_extent = ((max - min) * 0.5).max_element();
+
+    //頂点が無いか全て同じ位置にある場合は拡大しない
+    let scale = if half_extent > 0.0 {
+        1.0 / half_extent
+    } else {
+        1.0
+    };
+
+    for vertex in vertices {
+        vertex.position = ((Vec3::from(vertex.position) - center) * scale).into();
+    }
+
+    Mat4::from_scale(Vec3::splat(scale)) * Mat4::from_translation(-center)
+}
@@ -0,0 +1,412 @@
+use crate::buffer;
+use crate::memory_budget;
+use ash::extensions::khr::PushDescriptor;
+use ash::{vk, Device, Instance};
+use glam::{Mat4, Vec4};
+use std::mem;
+
+//シェーダー側のObjectUniformsと同じレイアウト
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct ObjectUniforms {
+    pub model: Mat4,
+    //頂点カラーに掛ける色、アルファは半透明な物の描画でだけ使う
+    pub color: Vec4,
+}
+
+//要素をalignmentの倍数の間隔で並べて書き込む
+//ダイナミックオフセットはmin_uniform_buffer_offset_alignmentの倍数でなければいけない
+pub struct AlignedBufferWriter {
+    stride: usize,
+}
+
+impl AlignedBufferWriter {
+    //alignmentは仕様上2の累乗だが0の場合はアライメントなしとして扱う
+    pub fn new(element_size: usize, alignment: usize) -> Self {
+        Self {
+            stride: Self::aligned_size(element_size, alignment),
+        }
+    }
+
+    pub fn aligned_size(size: usize, alignment: usize) -> usize {
+        if alignment == 0 {
+            return size;
+        }
+
+        (size + alignment - 1) / alignment * alignment
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn offset(&self, index: usize) -> usize {
+        self.stride * index
+    }
+
+    //baseはstride * 要素数以上の大きさを持つマップ済みのメモリを指す
+    pub unsafe fn write<T: Copy>(&self, base: *mut u8, index: usize, value: &T) {
+        (base.add(self.offset(index)) as *mut T).write_unaligned(*value);
+    }
+}
+
+//オブジェクトごとのバッファの範囲をシェーダーに渡す方法
+enum ObjectBinding {
+    //フレームごとのDescriptor Setをプールから確保し、UNIFORM_BUFFER_DYNAMICのオフセットで切り替える
+    DynamicOffset {
+        descriptor_pool: vk::DescriptorPool,
+        descriptor_sets: Vec<vk::DescriptorSet>,
+    },
+    //VK_KHR_push_descriptorで描画ごとにバッファの範囲を直接コマンドバッファに記録する
+    //Descriptor Setの確保も更新も要らない
+    PushDescriptor(PushDescriptor),
+}
+
+//オブジェクトごとのデータを1つの大きなバッファにまとめ、描画ごとに読む範囲を切り替える
+//Descriptor Setをオブジェクトの数だけ用意しなくて良い
+pub struct ObjectBuffers {
+    writer: AlignedBufferWriter,
+    capacity: usize,
+    buffers: Vec<vk::Buffer>,
+    memories: Vec<vk::DeviceMemory>,
+    mapped: Vec<*mut u8>,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    binding: ObjectBinding,
+}
+
+impl ObjectBuffers {
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        frames_in_flight: u32,
+        capacity: usize,
+        //Someの場合はDescriptor Setの代わりにpush descriptorで紐づける
+        push_descriptor: Option<PushDescriptor>,
+        //VERTEXに加えてObjectUniformsを読むステージ、--mesh-shadingではMESH_EXT
+        extra_stages: vk::ShaderStageFlags,
+    ) -> Self {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let alignment = properties.limits.min_uniform_buffer_offset_alignment as usize;
+
+        let writer = AlignedBufferWriter::new(mem::size_of::<ObjectUniforms>(), alignment);
+        let size = (writer.stride() * capacity) as vk::DeviceSize;
+
+        log::info!(
+            "Object buffer: {} objects, stride {} bytes (alignment {}), {}",
+            capacity,
+            writer.stride(),
+            alignment,
+            if push_descriptor.is_some() {
+                "push descriptors"
+            } else {
+                "dynamic offsets"
+            }
+        );
+
+        let descriptor_set_layout =
+            Self::create_descriptor_set_layout(device, push_descriptor.is_some(), extra_stages);
+
+        let mut buffers = vec![];
+        let mut memories = vec![];
+        let mut mapped = vec![];
+
+        for _ in 0..frames_in_flight {
+            let (buffer, memory) = buffer::create_buffer(
+                instance,
+                physical_device,
+                device,
+                size,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            );
+
+            let pointer = unsafe {
+                device
+                    .map_memory(memory, 0, size, vk::MemoryMapFlags::empty())
+                    .unwrap()
+            };
+
+            buffers.push(buffer);
+            memories.push(memory);
+            mapped.push(pointer as *mut u8);
+        }
+
+        let binding = match push_descriptor {
+            Some(push_descriptor) => ObjectBinding::PushDescriptor(push_descriptor),
+            None => Self::allocate_descriptor_sets(
+                device,
+                descriptor_set_layout,
+                &buffers,
+                frames_in_flight,
+            ),
+        };
+
+        Self {
+            writer,
+            capacity,
+            buffers,
+            memories,
+            mapped,
+            descriptor_set_layout,
+            binding,
+        }
+    }
+
+    //フレームごとのバッファを1つずつUNIFORM_BUFFER_DYNAMICとして書き込んだDescriptor Setを作る
+    fn allocate_descriptor_sets(
+        device: &Device,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+        buffers: &[vk::Buffer],
+        frames_in_flight: u32,
+    ) -> ObjectBinding {
+        let pool_size = vk::DescriptorPoolSize::builder()
+            .ty(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .descriptor_count(frames_in_flight)
+            .build();
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&[pool_size])
+            .max_sets(frames_in_flight)
+            .build();
+
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+
+        let layouts = vec![descriptor_set_layout; frames_in_flight as usize];
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&layouts)
+            .build();
+
+        let descriptor_sets = unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() };
+
+        for (descriptor_set, buffer) in descriptor_sets.iter().zip(buffers) {
+            //rangeはバッファ全体ではなく1オブジェクト分で、どこから読むかはバインド時のオフセットで決まる
+            let buffer_info = vk::DescriptorBufferInfo::builder()
+                .buffer(*buffer)
+                .offset(0)
+                .range(mem::size_of::<ObjectUniforms>() as vk::DeviceSize)
+                .build();
+
+            let descriptor_write = vk::WriteDescriptorSet::builder()
+                .dst_set(*descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                .buffer_info(&[buffer_info])
+                .build();
+
+            unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+        }
+
+        ObjectBinding::DynamicOffset {
+            descriptor_pool,
+            descriptor_sets,
+        }
+    }
+
+    //push descriptorのレイアウトにはDYNAMICの種類を使えないので、オフセットはDescriptorBufferInfoで指定する
+    fn create_descriptor_set_layout(
+        device: &Device,
+        push: bool,
+        extra_stages: vk::ShaderStageFlags,
+    ) -> vk::DescriptorSetLayout {
+        let (descriptor_type, flags) = if push {
+            (
+                vk::DescriptorType::UNIFORM_BUFFER,
+                vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR,
+            )
+        } else {
+            (
+                vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                vk::DescriptorSetLayoutCreateFlags::empty(),
+            )
+        };
+
+        let binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(descriptor_type)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | extra_stages)
+            .build();
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .flags(flags)
+            .bindings(&[binding])
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    //セカンダリコマンドバッファに渡すDescriptor Set、push descriptorの場合は作っていない
+    pub fn descriptor_set(&self, frame: usize) -> vk::DescriptorSet {
+        match &self.binding {
+            ObjectBinding::DynamicOffset {
+                descriptor_sets, ..
+            } => descriptor_sets[frame],
+            ObjectBinding::PushDescriptor(_) => {
+                panic!("Object descriptor sets are not allocated with push descriptors")
+            }
+        }
+    }
+
+    //cmd_bind_descriptor_setsに渡すオフセット
+    pub fn dynamic_offset(&self, index: usize) -> u32 {
+        self.writer.offset(index) as u32
+    }
+
+    //pipeline_layoutのset = 1にframeのバッファのindex番目のオブジェクトを紐づける
+    pub fn cmd_bind(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        frame: usize,
+        index: usize,
+    ) {
+        match &self.binding {
+            ObjectBinding::DynamicOffset {
+                descriptor_sets, ..
+            } => unsafe {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    1,
+                    &[descriptor_sets[frame]],
+                    &[self.dynamic_offset(index)],
+                );
+            },
+            ObjectBinding::PushDescriptor(push_descriptor) => {
+                let buffer_info = vk::DescriptorBufferInfo::builder()
+                    .buffer(self.buffers[frame])
+                    .offset(self.writer.offset(index) as vk::DeviceSize)
+                    .range(mem::size_of::<ObjectUniforms>() as vk::DeviceSize)
+                    .build();
+
+                let buffer_infos = [buffer_info];
+
+                //push descriptorではdst_setは無視される
+                let descriptor_write = vk::WriteDescriptorSet::builder()
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&buffer_infos)
+                    .build();
+
+                unsafe {
+                    push_descriptor.cmd_push_descriptor_set(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline_layout,
+                        1,
+                        &[descriptor_write],
+                    );
+                }
+            }
+        }
+    }
+
+    //そのフレームの完了を待った後に呼ぶ
+    pub fn write(&self, frame: usize, index: usize, object: &ObjectUniforms) {
+        assert!(
+            index < self.capacity,
+            "Object index {} is out of capacity",
+            index
+        );
+
+        unsafe { self.writer.write(self.mapped[frame], index, object) };
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            for (buffer, memory) in self.buffers.iter().zip(&self.memories) {
+                device.destroy_buffer(*buffer, None);
+                memory_budget::free_memory(device, *memory);
+            }
+
+            if let ObjectBinding::DynamicOffset {
+                descriptor_pool, ..
+            } = &self.binding
+            {
+                device.destroy_descriptor_pool(*descriptor_pool, None);
+            }
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stride_rounds_up_to_alignment() {
+        //ObjectUniformsは64 + 16 = 80バイト
+        let size = mem::size_of::<ObjectUniforms>();
+        assert_eq!(size, 80);
+
+        assert_eq!(AlignedBufferWriter::new(size, 1).stride(), 80);
+        assert_eq!(AlignedBufferWriter::new(size, 64).stride(), 128);
+        assert_eq!(AlignedBufferWriter::new(size, 256).stride(), 256);
+    }
+
+    #[test]
+    fn zero_alignment_keeps_size() {
+        assert_eq!(AlignedBufferWriter::new(80, 0).stride(), 80);
+    }
+
+    #[test]
+    fn multiples_of_alignment_are_unchanged() {
+        for alignment in [1, 64, 256] {
+            assert_eq!(AlignedBufferWriter::aligned_size(256, alignment), 256);
+            assert_eq!(AlignedBufferWriter::aligned_size(0, alignment), 0);
+        }
+
+        assert_eq!(AlignedBufferWriter::aligned_size(65, 64), 128);
+        assert_eq!(AlignedBufferWriter::aligned_size(257, 256), 512);
+    }
+
+    #[test]
+    fn offsets_are_aligned() {
+        for alignment in [1, 64, 256] {
+            let writer = AlignedBufferWriter::new(80, alignment);
+
+            for index in 0..8 {
+                assert_eq!(writer.offset(index) % alignment, 0);
+                assert_eq!(writer.offset(index), writer.stride() * index);
+            }
+        }
+    }
+
+    #[test]
+    fn write_places_elements_at_their_offsets() {
+        let writer = AlignedBufferWriter::new(mem::size_of::<u32>(), 64);
+        let mut memory = vec![0u8; writer.stride() * 3];
+
+        for index in 0..3 {
+            unsafe { writer.write(memory.as_mut_ptr(), index, &(index as u32 + 1)) };
+        }
+
+        for index in 0..3 {
+            let offset = writer.offset(index);
+            let bytes = memory[offset..offset + 4].try_into().unwrap();
+
+            assert_eq!(u32::from_ne_bytes(bytes), index as u32 + 1);
+        }
+
+        //要素の間の詰め物には書き込まない
+        assert!(memory[4..64].iter().all(|&byte| byte == 0));
+    }
+}
@@ -0,0 +1,220 @@
+use crate::frustum::Aabb;
+use ash::{vk, Device};
+use glam::{Vec3, Vec4};
+use std::mem;
+
+//この回数続けてサンプル数が0だったオブジェクトだけ描画を省く
+//見えるようになった時は1回で描画に戻すので、境界で描画したり省いたりを繰り返さない
+const OCCLUDED_FRAMES_TO_SKIP: u32 = 3;
+
+//シェーダー側のOcclusionConstantsと同じレイアウト
+//ワールド座標のバウンディングボックス、wは使わない
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct OcclusionConstants {
+    pub min: Vec4,
+    pub max: Vec4,
+}
+
+//--occlusion-cullingで不透明なオブジェクトのバウンディングボックスをOCCLUSIONクエリで囲って描画し、
+//次に同じフレーム番号を描画する時にブロックせずに結果を読んで隠れていたオブジェクトの描画を省く
+//クエリはフレームごとにobject_count個ずつ持ち、frame * object_count + iがそのフレームのi番目のクエリ
+pub struct OcclusionCulling {
+    query_pool: vk::QueryPool,
+    object_count: u32,
+    //フレームごとにクエリを発行した(オブジェクトの番号, バウンディングボックス)、i番目のクエリの結果がqueried[frame][i]のもの
+    queried: Vec<Vec<(usize, Aabb)>>,
+    //オブジェクトごとに連続でサンプル数が0だった回数
+    occluded_frames: Vec<u32>,
+}
+
+impl OcclusionCulling {
+    pub fn new(device: &Device, object_count: usize, frames_in_flight: u32) -> Self {
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::OCCLUSION)
+            .query_count(object_count as u32 * frames_in_flight)
+            .build();
+
+        let query_pool = unsafe { device.create_query_pool(&create_info, None).unwrap() };
+
+        log::info!(
+            "Occlusion culling: {} queries per frame, hidden after {} frames",
+            object_count,
+            OCCLUDED_FRAMES_TO_SKIP
+        );
+
+        Self {
+            query_pool,
+            object_count: object_count as u32,
+            queried: vec![vec![]; frames_in_flight as usize],
+            occluded_frames: vec![0; object_count],
+        }
+    }
+
+    //クエリのリセットはレンダーパスの外で行う必要がある
+    pub fn cmd_reset(&self, device: &Device, command_buffer: vk::CommandBuffer, frame: usize) {
+        unsafe {
+            device.cmd_reset_query_pool(
+                command_buffer,
+                self.query_pool,
+                frame as u32 * self.object_count,
+                self.object_count,
+            )
+        };
+    }
+
+    //frameの前回の描画の完了を待った後に呼ぶ
+    //PipelineStatisticsと同じくブロックせず、まだ書き込まれていない結果は使わない
+    pub fn read(&mut self, device: &Device, frame: usize) {
+        let queried = mem::take(&mut self.queried[frame]);
+
+        if queried.is_empty() {
+            return;
+        }
+
+        //サンプル数の後ろに利用可能かどうかの値が入る
+        let mut results = vec![[0u64; 2]; queried.len()];
+
+        let result = unsafe {
+            device.get_query_pool_results(
+                self.query_pool,
+                frame as u32 * self.object_count,
+                queried.len() as u32,
+                &mut results,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY,
+            )
+        };
+
+        match result {
+            Ok(_) | Err(vk::Result::NOT_READY) => {}
+            Err(error) => {
+                log::warn!("Failed to read occlusion queries: {}", error);
+                return;
+            }
+        }
+
+        for (&(object_index, _), [samples, available]) in queried.iter().zip(results) {
+            if available == 0 {
+                continue;
+            }
+
+            let occluded_frames = &mut self.occluded_frames[object_index];
+            *occluded_frames = if samples == 0 {
+                occluded_frames.saturating_add(1)
+            } else {
+                0
+            };
+        }
+    }
+
+    //視錐台カリングを通ったオブジェクトのうち、隠れていなかったものを返し、このフレームでクエリするボックスを決める
+    //frustum_visibleは(オブジェクトの番号, ワールド座標のバウンディングボックス)
+    //視錐台の外にあったオブジェクトは結果が古くなるので、入ってきた時に一度描画するように数え直す
+    //カメラがnearだけ広げたボックスの中にある場合は手前の面がクリップされて0になるので、クエリせずに見えている扱いにする
+    pub fn filter_visible(
+        &mut self,
+        frame: usize,
+        frustum_visible: &[(usize, Aabb)],
+        camera_position: Vec3,
+        near: f32,
+    ) -> Vec<usize> {
+        let mut in_frustum = vec![false; self.occluded_frames.len()];
+        for &(index, _) in frustum_visible {
+            in_frustum[index] = true;
+        }
+
+        for (occluded_frames, in_frustum) in self.occluded_frames.iter_mut().zip(in_frustum) {
+            if !in_frustum {
+                *occluded_frames = 0;
+            }
+        }
+
+        let mut queried = Vec::with_capacity(frustum_visible.len());
+
+        for &(index, aabb) in frustum_visible {
+            let contains_camera = (aabb.min - Vec3::splat(near)).cmple(camera_position).all()
+                && camera_position.cmple(aabb.max + Vec3::splat(near)).all();
+
+            if contains_camera {
+                self.occluded_frames[index] = 0;
+            } else {
+                queried.push((index, aabb));
+            }
+        }
+
+        self.queried[frame] = queried;
+
+        frustum_visible
+            .iter()
+            .map(|&(index, _)| index)
+            .filter(|&index| self.occluded_frames[index] < OCCLUDED_FRAMES_TO_SKIP)
+            .collect()
+    }
+
+    //不透明な物を描画した後、同じレンダーパスの中で呼ぶ
+    //filter_visibleで決めたボックスを、描画を省いたものも含めて全て描画する
+    //pipelineは深度テストだけをして色も深度値も書き込まないもので、set = 0にdescriptor_setを紐づける
+    pub fn cmd_draw_queries(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        (pipeline, pipeline_layout): (vk::Pipeline, vk::PipelineLayout),
+        descriptor_set: vk::DescriptorSet,
+        frame: usize,
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+
+            for (query_index, (_, aabb)) in self.queried[frame].iter().enumerate() {
+                let constants = OcclusionConstants {
+                    min: aabb.min.extend(0.0),
+                    max: aabb.max.extend(0.0),
+                };
+
+                device.cmd_push_constants(
+                    command_buffer,
+                    pipeline_layout,
+                    vk::ShaderStageFlags::VERTEX,
+                    0,
+                    std::slice::from_raw_parts(
+                        &constants as *const OcclusionConstants as *const u8,
+                        mem::size_of::<OcclusionConstants>(),
+                    ),
+                );
+
+                let query = frame as u32 * self.object_count + query_index as u32;
+
+                //PRECISEを指定しないので、0かどうかだけが意味を持つ
+                device.cmd_begin_query(
+                    command_buffer,
+                    self.query_pool,
+                    query,
+                    vk::QueryControlFlags::empty(),
+                );
+                //main_vs_occlusion_boxがvertex_indexから立方体のTRIANGLE_STRIPを作る
+                device.cmd_draw(command_buffer, 14, 1, 0, 0);
+                device.cmd_end_query(command_buffer, self.query_pool, query);
+            }
+        }
+    }
+
+    //filter_visibleで省いた数
+    pub fn occluded_count(&self) -> usize {
+        self.occluded_frames
+            .iter()
+            .filter(|&&frames| frames >= OCCLUDED_FRAMES_TO_SKIP)
+            .count()
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe { device.destroy_query_pool(self.query_pool, None) };
+    }
+}
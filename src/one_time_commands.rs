@@ -0,0 +1,159 @@
+use crate::submit_queue::SubmitQueue;
+use crate::synchronization::Synchronization;
+use ash::{vk, Device};
+
+//一度だけ実行するコマンドの完了を待つ時間(ナノ秒)
+//起動時のアップロードで使うだけなので、これを超える場合はハングしているとみなす
+const TIMEOUT: u64 = 5_000_000_000;
+
+//一度だけ実行するコマンドに記録する処理
+pub type Record<'a> = Box<dyn FnOnce(vk::CommandBuffer) + 'a>;
+
+//バッファのコピーなど一度だけ実行するコマンドを記録してsubmitし、終わるまで待つ
+//device_wait_idleだと他のキューの処理まで待ってしまうのでFenceで待つ
+pub struct OneTimeCommands {
+    command_pool: vk::CommandPool,
+    //描画と同じキューの場合もあるのでSubmitQueueを通してsubmitする
+    queue: SubmitQueue,
+}
+
+impl OneTimeCommands {
+    pub fn new(device: &Device, queue_family_index: u32, queue: SubmitQueue) -> Self {
+        //TRANSIENT: 確保したコマンドバッファがすぐに解放されることをドライバに伝える
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(queue_family_index)
+            .build();
+
+        let command_pool = unsafe { device.create_command_pool(&pool_info, None).unwrap() };
+
+        Self {
+            command_pool,
+            queue,
+        }
+    }
+
+    //recordで記録したコマンドを実行して終わるまで待つ
+    pub fn run<F>(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        record: F,
+    ) -> Result<(), vk::Result>
+    where
+        F: FnOnce(vk::CommandBuffer),
+    {
+        let mut scope = Scope::begin(device, self.command_pool)?;
+
+        record(scope.command_buffer);
+
+        scope.submit(synchronization, &self.queue)
+    }
+
+    //複数の記録を1つのコマンドバッファにまとめて、Fenceを待つのを1回で済ませる
+    //recordsは渡した順番で記録される
+    pub fn run_batch(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        records: Vec<Record>,
+    ) -> Result<(), vk::Result> {
+        self.run(device, synchronization, |command_buffer| {
+            for record in records {
+                record(command_buffer);
+            }
+        })
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe { device.destroy_command_pool(self.command_pool, None) };
+    }
+}
+
+//1回分のコマンドバッファとFence
+//途中でエラーになってもDropで必ず解放する
+struct Scope<'a> {
+    device: &'a Device,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    //submitしたが完了を確認できていない
+    pending: bool,
+}
+
+impl<'a> Scope<'a> {
+    fn begin(device: &'a Device, command_pool: vk::CommandPool) -> Result<Self, vk::Result> {
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1)
+            .build();
+
+        let command_buffer = unsafe { device.allocate_command_buffers(&alloc_info)?[0] };
+
+        let fence = match unsafe { device.create_fence(&vk::FenceCreateInfo::default(), None) } {
+            Ok(fence) => fence,
+            Err(error) => {
+                unsafe { device.free_command_buffers(command_pool, &[command_buffer]) };
+                return Err(error);
+            }
+        };
+
+        //ここから先はDropで解放される
+        let scope = Self {
+            device,
+            command_pool,
+            command_buffer,
+            fence,
+            pending: false,
+        };
+
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+            .build();
+
+        unsafe { device.begin_command_buffer(command_buffer, &begin_info)? };
+
+        Ok(scope)
+    }
+
+    fn submit(
+        &mut self,
+        synchronization: &Synchronization,
+        queue: &SubmitQueue,
+    ) -> Result<(), vk::Result> {
+        unsafe { self.device.end_command_buffer(self.command_buffer)? };
+
+        queue.submit(
+            self.device,
+            synchronization,
+            &[],
+            &[self.command_buffer],
+            &[],
+            self.fence,
+        )?;
+        self.pending = true;
+
+        //TIMEOUTはErr(vk::Result::TIMEOUT)として返ってくる
+        unsafe { self.device.wait_for_fences(&[self.fence], true, TIMEOUT)? };
+        self.pending = false;
+
+        Ok(())
+    }
+}
+
+impl Drop for Scope<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            //実行中のコマンドバッファは解放できないので、タイムアウトした場合は諦めてデバイス全体を待つ
+            if self.pending {
+                log::warn!("One-time commands did not finish in time, waiting for the device");
+                let _ = self.device.device_wait_idle();
+            }
+
+            self.device.destroy_fence(self.fence, None);
+            self.device
+                .free_command_buffers(self.command_pool, &[self.command_buffer]);
+        }
+    }
+}
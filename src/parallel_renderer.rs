@@ -0,0 +1,292 @@
+use crate::command_pool_manager::{CommandPoolManager, ThreadCommandPool};
+use ash::{vk, Device};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+
+//セカンダリコマンドバッファに記録する1オブジェクト分の描画
+#[derive(Clone, Copy, Debug)]
+pub struct ObjectDraw {
+    //set = 1のダイナミックオフセット
+    pub dynamic_offset: u32,
+}
+
+//セカンダリコマンドバッファはプライマリの状態を引き継がないので、全てのバッファでこれを設定し直す
+//記録するスレッドに送るので借用は持たない
+#[derive(Clone, Copy, Debug)]
+pub struct DrawState {
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+    //set = 0
+    pub uniform_descriptor_set: vk::DescriptorSet,
+    //set = 1、オフセットはObjectDrawごとに指定する
+    pub object_descriptor_set: vk::DescriptorSet,
+    //Mesh::buffersの(頂点バッファ, インデックスバッファ)
+    pub mesh_buffers: (vk::Buffer, vk::Buffer),
+    pub index_count: u32,
+    pub viewport: vk::Viewport,
+    pub scissor: vk::Rect2D,
+}
+
+//セカンダリコマンドバッファを実行するrender passのインスタンス
+//inheritance infoで同じものを指定しないとcmd_execute_commandsできない
+#[derive(Clone, Copy, Debug)]
+pub enum SecondaryTarget {
+    //subpass 0で実行する
+    //framebufferは省略できるが指定した方がドライバが最適化しやすい
+    RenderPass {
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+    },
+    //dynamic renderingではrender passの代わりにアタッチメントのフォーマットを指定する
+    Dynamic {
+        color_format: vk::Format,
+        depth_format: vk::Format,
+        samples: vk::SampleCountFlags,
+    },
+}
+
+//記録するスレッドに渡す1フレーム分の仕事
+enum Job {
+    Record {
+        frame: usize,
+        target: SecondaryTarget,
+        state: DrawState,
+        chunk: Vec<ObjectDraw>,
+    },
+    Stop,
+}
+
+//記録するスレッドは最初に作って使い続け、フレームごとにJobを送って記録したコマンドバッファを受け取る
+struct Worker {
+    jobs: Sender<Job>,
+    recorded: Receiver<vk::CommandBuffer>,
+}
+
+//オブジェクトの描画をスレッドごとのセカンダリコマンドバッファに分けて並列に記録する
+//Command Poolはスレッドごと、フレームごとにCommandPoolManagerから受け取り、フレームの始めにまとめてリセットする
+pub struct ParallelRenderer {
+    workers: Vec<Worker>,
+    //フレームごと、スレッドごとのTRANSIENTなプール
+    frame_pools: Arc<Vec<CommandPoolManager>>,
+}
+
+impl ParallelRenderer {
+    pub fn new(
+        device: &Device,
+        queue_family_index: u32,
+        thread_count: usize,
+        frames_in_flight: u32,
+    ) -> Self {
+        //プールごとリセットするので、実行中の他のフレームのバッファを巻き込まないようにフレームごとに分ける
+        let frame_pools = Arc::new(
+            (0..frames_in_flight)
+                .map(|_| CommandPoolManager::new(vk::CommandPoolCreateFlags::TRANSIENT))
+                .collect::<Vec<_>>(),
+        );
+
+        let workers = (0..thread_count)
+            .map(|index| {
+                let (jobs, job_receiver) = mpsc::channel();
+                let (recorded_sender, recorded) = mpsc::channel();
+                let device = device.clone();
+                let frame_pools = Arc::clone(&frame_pools);
+
+                thread::Builder::new()
+                    .name(format!("record-{}", index))
+                    .spawn(move || {
+                        Self::run_worker(
+                            &device,
+                            queue_family_index,
+                            &frame_pools,
+                            job_receiver,
+                            recorded_sender,
+                        )
+                    })
+                    .expect("Failed to spawn a recording thread");
+
+                Worker { jobs, recorded }
+            })
+            .collect();
+
+        log::info!("Recording object draws on {} thread(s)", thread_count);
+
+        Self {
+            workers,
+            frame_pools,
+        }
+    }
+
+    //Job::Stopを受け取るか、ParallelRendererが無くなるまでJobを待つ
+    fn run_worker(
+        device: &Device,
+        queue_family_index: u32,
+        frame_pools: &[CommandPoolManager],
+        jobs: Receiver<Job>,
+        recorded: Sender<vk::CommandBuffer>,
+    ) {
+        //フレームごとのセカンダリコマンドバッファ、プールのリセットの後もそのまま使う
+        let mut command_buffers = vec![None; frame_pools.len()];
+
+        while let Ok(Job::Record {
+            frame,
+            target,
+            state,
+            chunk,
+        }) = jobs.recv()
+        {
+            let pool = frame_pools[frame].pool(device, queue_family_index);
+
+            let command_buffer = *command_buffers[frame].get_or_insert_with(|| {
+                pool.allocate(device, vk::CommandBufferLevel::SECONDARY, 1)[0]
+            });
+
+            Self::record_chunk(device, pool, command_buffer, target, &state, &chunk);
+
+            if recorded.send(command_buffer).is_err() {
+                break;
+            }
+        }
+    }
+
+    //sceneをスレッドの数で分けて記録し、プライマリから実行するセカンダリコマンドバッファを返す
+    //このフレームのコマンドバッファの実行が終わっていることは呼び出し側で保証する
+    pub fn record(
+        &self,
+        device: &Device,
+        frame: usize,
+        target: SecondaryTarget,
+        state: &DrawState,
+        scene: &[ObjectDraw],
+    ) -> Vec<vk::CommandBuffer> {
+        //前回このフレームで記録したスレッドは全て記録を終えて待機している
+        self.frame_pools[frame].reset_all(device).unwrap();
+
+        let chunk_size = ((scene.len() + self.workers.len() - 1) / self.workers.len()).max(1);
+
+        let workers = self
+            .workers
+            .iter()
+            .zip(scene.chunks(chunk_size))
+            .map(|(worker, chunk)| {
+                worker
+                    .jobs
+                    .send(Job::Record {
+                        frame,
+                        target,
+                        state: *state,
+                        chunk: chunk.to_vec(),
+                    })
+                    .expect("Recording thread stopped");
+                worker
+            })
+            .collect::<Vec<_>>();
+
+        //送った順番で受け取るので描画の順番はsceneの順番と一致する
+        workers
+            .into_iter()
+            .map(|worker| worker.recorded.recv().expect("Recording thread panicked"))
+            .collect()
+    }
+
+    fn record_chunk(
+        device: &Device,
+        pool: ThreadCommandPool,
+        command_buffer: vk::CommandBuffer,
+        target: SecondaryTarget,
+        state: &DrawState,
+        chunk: &[ObjectDraw],
+    ) {
+        let (color_attachment_formats, depth_attachment_format, samples) = match target {
+            SecondaryTarget::Dynamic {
+                color_format,
+                depth_format,
+                samples,
+            } => (vec![color_format], depth_format, samples),
+            SecondaryTarget::RenderPass { .. } => {
+                (vec![], vk::Format::UNDEFINED, vk::SampleCountFlags::TYPE_1)
+            }
+        };
+
+        let mut rendering_info = vk::CommandBufferInheritanceRenderingInfo::builder()
+            .color_attachment_formats(&color_attachment_formats)
+            .depth_attachment_format(depth_attachment_format)
+            .rasterization_samples(samples)
+            .build();
+
+        let inheritance_info = match target {
+            SecondaryTarget::RenderPass {
+                render_pass,
+                framebuffer,
+            } => vk::CommandBufferInheritanceInfo::builder()
+                .render_pass(render_pass)
+                .subpass(0)
+                .framebuffer(framebuffer),
+            SecondaryTarget::Dynamic { .. } => {
+                vk::CommandBufferInheritanceInfo::builder().push_next(&mut rendering_info)
+            }
+        }
+        .build();
+
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            //RENDER_PASS_CONTINUE: render passの中で実行されるセカンダリコマンドバッファ
+            //ONE_TIME_SUBMIT: 毎フレーム記録し直す
+            .flags(
+                vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE
+                    | vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            )
+            .inheritance_info(&inheritance_info)
+            .build();
+
+        //フレームの始めにプールごとリセットしている
+        pool.begin(device, command_buffer, &begin_info);
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                state.pipeline,
+            );
+            device.cmd_set_viewport(command_buffer, 0, &[state.viewport]);
+            device.cmd_set_scissor(command_buffer, 0, &[state.scissor]);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                state.pipeline_layout,
+                0,
+                &[state.uniform_descriptor_set],
+                &[],
+            );
+
+            let (vertex_buffer, index_buffer) = state.mesh_buffers;
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[0]);
+            device.cmd_bind_index_buffer(command_buffer, index_buffer, 0, vk::IndexType::UINT32);
+
+            for draw in chunk {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    state.pipeline_layout,
+                    1,
+                    &[state.object_descriptor_set],
+                    &[draw.dynamic_offset],
+                );
+                device.cmd_draw_indexed(command_buffer, state.index_count, 1, 0, 0, 0);
+            }
+
+            device.end_command_buffer(command_buffer).unwrap();
+        }
+    }
+
+    //スレッドが終わるのは待たない、プールはCommandPoolManagerが持っているのでスレッドが残っていても破棄できる
+    pub fn destroy(&self, device: &Device) {
+        for worker in &self.workers {
+            //既に止まっているスレッドには送れないが、それで構わない
+            let _ = worker.jobs.send(Job::Stop);
+        }
+
+        for frame_pools in self.frame_pools.iter() {
+            frame_pools.destroy(device);
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use crate::synchronization::Synchronization;
+use crate::{buffer, compute, memory_budget};
+use ash::{vk, Device, Instance};
+use std::mem;
+
+//シェーダー側のmain_cs_particlesのthreadsと合わせる
+const WORKGROUP_SIZE: u32 = 64;
+
+//シェーダー側のParticleと同じレイアウト
+//storage bufferとして読み書きするのでvec4に揃えておく
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct Particle {
+    pub position: [f32; 4],
+    pub velocity: [f32; 4],
+    pub color: [f32; 4],
+}
+
+impl Particle {
+    //頂点バッファとしてはpositionとcolorだけを読む
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(mem::size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+        [
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(mem::size_of::<[f32; 4]>() as u32 * 2)
+                .build(),
+        ]
+    }
+}
+
+//シェーダー側のParticleConstantsと同じレイアウト
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct ParticleConstants {
+    delta_time: f32,
+    count: u32,
+}
+
+//初期配置を決めるための簡単な疑似乱数(xorshift)
+struct Random(u32);
+
+impl Random {
+    //0.0から1.0の範囲
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next()
+    }
+}
+
+//コンピュートシェーダーで毎フレーム動かし、そのまま頂点バッファとして点で描画するパーティクル
+//フレームごとにバッファを分け、前のフレームのバッファを読んで今のフレームのバッファに書き込む
+//グラフィックスが前のフレームを描画している間に次のフレームのディスパッチを進められる
+pub struct ParticleSystem {
+    buffers: Vec<vk::Buffer>,
+    memories: Vec<vk::DeviceMemory>,
+    count: u32,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    //binding = 0に前のフレームのバッファ、binding = 1にこのフレームのバッファ
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl ParticleSystem {
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        shader_module: vk::ShaderModule,
+        count: u32,
+        frames_in_flight: u32,
+        //グラフィックスとコンピュートのキューファミリーが別の場合は両方を渡す
+        queue_family_indices: &[u32],
+    ) -> Self {
+        let mut random = Random(0x2545_f491);
+
+        let particles = (0..count)
+            .map(|_| Particle {
+                position: [random.range(-1.0, 1.0), random.range(0.0, 1.0), 0.0, 1.0],
+                velocity: [random.range(-0.5, 0.5), random.range(0.0, 1.0), 0.0, 0.0],
+                color: [
+                    random.range(0.2, 1.0),
+                    random.range(0.2, 1.0),
+                    random.range(0.2, 1.0),
+                    1.0,
+                ],
+            })
+            .collect::<Vec<_>>();
+
+        let mut buffers = vec![];
+        let mut memories = vec![];
+
+        //初期値はCPUで書き込み、その後はコンピュートシェーダーだけが書き換える
+        for _ in 0..frames_in_flight {
+            let (buffer, memory) = buffer::create_shared_host_buffer_with_data(
+                instance,
+                physical_device,
+                device,
+                &particles,
+                vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER,
+                queue_family_indices,
+            );
+
+            buffers.push(buffer);
+            memories.push(memory);
+        }
+
+        let bindings = [0, 1].map(|binding| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+        });
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        };
+
+        let pool_size = vk::DescriptorPoolSize::builder()
+            .ty(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(bindings.len() as u32 * frames_in_flight)
+            .build();
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&[pool_size])
+            .max_sets(frames_in_flight)
+            .build();
+
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+
+        let layouts = vec![descriptor_set_layout; frames_in_flight as usize];
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&layouts)
+            .build();
+
+        let descriptor_sets = unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() };
+
+        for (frame, descriptor_set) in descriptor_sets.iter().enumerate() {
+            let previous = (frame + buffers.len() - 1) % buffers.len();
+
+            let buffer_infos = [buffers[previous], buffers[frame]].map(|buffer| {
+                vk::DescriptorBufferInfo::builder()
+                    .buffer(buffer)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .build()
+            });
+
+            let descriptor_writes = [0, 1].map(|binding| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(binding)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&buffer_infos[binding as usize..binding as usize + 1])
+                    .build()
+            });
+
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+
+        //経過時間と個数はPush Constantで渡す
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(mem::size_of::<ParticleConstants>() as u32)
+            .build();
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&[descriptor_set_layout])
+            .push_constant_ranges(&[push_constant_range])
+            .build();
+
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .unwrap()
+        };
+
+        let pipeline = compute::create_compute_pipeline(
+            device,
+            pipeline_cache,
+            shader_module,
+            "main_cs_particles",
+            pipeline_layout,
+        );
+
+        log::info!("Particle system: {} particles", count);
+
+        Self {
+            buffers,
+            memories,
+            count,
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_sets,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    //レンダーパスの外で呼ぶ
+    //on_graphics_queueがtrueの場合は描画と同じコマンドバッファに記録されるので頂点入力とのバリアも張る
+    //falseの場合はコンピュートキューでVERTEX_INPUTステージが使えないのでSemaphoreで待ち合わせる
+    pub fn cmd_update(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        delta_time: f32,
+        on_graphics_queue: bool,
+    ) {
+        let constants = ParticleConstants {
+            delta_time,
+            count: self.count,
+        };
+
+        unsafe {
+            //前のフレームのディスパッチの書き込みをこのディスパッチから読めるようにする
+            //このフレームのバッファを前回描画したフレームはframe_timelineで完了を待っている
+            let memory_barrier = vk::MemoryBarrier2::builder()
+                .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                .dst_access_mask(
+                    vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                )
+                .build();
+
+            synchronization.cmd_pipeline_barrier(
+                device,
+                command_buffer,
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_sets[frame]],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                std::slice::from_raw_parts(
+                    &constants as *const ParticleConstants as *const u8,
+                    mem::size_of::<ParticleConstants>(),
+                ),
+            );
+
+            device.cmd_dispatch(
+                command_buffer,
+                (self.count + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                1,
+                1,
+            );
+
+            if on_graphics_queue {
+                //コンピュートシェーダーの書き込みを頂点入力から読めるようにする
+                let buffer_barrier = vk::BufferMemoryBarrier2::builder()
+                    .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                    .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT)
+                    .dst_access_mask(vk::AccessFlags2::VERTEX_ATTRIBUTE_READ)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .buffer(self.buffers[frame])
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE)
+                    .build();
+
+                synchronization.cmd_pipeline_barrier(
+                    device,
+                    command_buffer,
+                    &[],
+                    &[buffer_barrier],
+                    &[],
+                );
+            }
+        }
+    }
+
+    //PrimitiveTopology::POINT_LISTのパイプラインをバインドした後に呼ぶ
+    pub fn cmd_draw(&self, device: &Device, command_buffer: vk::CommandBuffer, frame: usize) {
+        unsafe {
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.buffers[frame]], &[0]);
+            device.cmd_draw(command_buffer, self.count, 1, 0, 0);
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+
+            for (buffer, memory) in self.buffers.iter().zip(&self.memories) {
+                device.destroy_buffer(*buffer, None);
+                memory_budget::free_memory(device, *memory);
+            }
+        }
+    }
+}
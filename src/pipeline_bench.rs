@@ -0,0 +1,233 @@
+use ash::{vk, Device};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+//1つの設定で作り直す回数、最初の1回はドライバの初期化が入るので平均で比べる
+const ITERATIONS: u32 = 10;
+
+//create_graphics_pipelinesに渡すパイプラインキャッシュ
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CacheMode {
+    //vk::PipelineCache::null()
+    None,
+    //作り直すたびに空のキャッシュを作る
+    Cold,
+    //計測しない1回目の作成で中身を入れたキャッシュ
+    Warm,
+}
+
+impl CacheMode {
+    fn name(self) -> &'static str {
+        match self {
+            CacheMode::None => "no cache",
+            CacheMode::Cold => "cold cache",
+            CacheMode::Warm => "warm cache",
+        }
+    }
+}
+
+//base_infoをALLOW_DERIVATIVESで作り、variant_infosをそれを親にしたDERIVATIVEとして作る
+//derivativesがfalseの場合はどちらのフラグも付けずに同じ順番で作る
+//base_pipeline_handleを使うので1つずつ作り、パイプラインと作成にかかった時間を渡した順番で返す
+pub fn create_with_derivatives(
+    device: &Device,
+    pipeline_cache: vk::PipelineCache,
+    base_info: &vk::GraphicsPipelineCreateInfo,
+    variant_infos: &[vk::GraphicsPipelineCreateInfo],
+    derivatives: bool,
+) -> Vec<(vk::Pipeline, Duration)> {
+    let base_info = vk::GraphicsPipelineCreateInfo {
+        flags: if derivatives {
+            base_info.flags | vk::PipelineCreateFlags::ALLOW_DERIVATIVES
+        } else {
+            base_info.flags
+        },
+        ..*base_info
+    };
+
+    let base = create_one(device, pipeline_cache, &base_info);
+    let base_pipeline = base.0;
+
+    let mut pipelines = vec![base];
+
+    for variant_info in variant_infos {
+        let variant_info = if derivatives {
+            vk::GraphicsPipelineCreateInfo {
+                flags: variant_info.flags | vk::PipelineCreateFlags::DERIVATIVE,
+                base_pipeline_handle: base_pipeline,
+                base_pipeline_index: -1,
+                ..*variant_info
+            }
+        } else {
+            *variant_info
+        };
+
+        pipelines.push(create_one(device, pipeline_cache, &variant_info));
+    }
+
+    pipelines
+}
+
+fn create_one(
+    device: &Device,
+    pipeline_cache: vk::PipelineCache,
+    pipeline_info: &vk::GraphicsPipelineCreateInfo,
+) -> (vk::Pipeline, Duration) {
+    let started_at = Instant::now();
+
+    let pipeline = unsafe {
+        device
+            .create_graphics_pipelines(pipeline_cache, &[*pipeline_info], None)
+            .map_err(|(_, error)| error)
+            .unwrap()
+            .remove(0)
+    };
+
+    (pipeline, started_at.elapsed())
+}
+
+//--bench-pipelinesで表示する、パイプラインごとの設定ごとの平均の作成時間
+pub struct PipelineBench {
+    names: Vec<&'static str>,
+    //(派生を使うか, キャッシュ)ごとに、namesと同じ順番のITERATIONS回の合計
+    columns: Vec<((bool, CacheMode), Vec<Duration>)>,
+}
+
+impl PipelineBench {
+    //namesは[base_info, variant_infos..]の名前
+    //作ったパイプラインは計測した後にすぐ破棄する
+    pub fn run(
+        device: &Device,
+        names: &[&'static str],
+        base_info: &vk::GraphicsPipelineCreateInfo,
+        variant_infos: &[vk::GraphicsPipelineCreateInfo],
+    ) -> Self {
+        assert_eq!(names.len(), variant_infos.len() + 1);
+
+        let columns = [false, true]
+            .into_iter()
+            .flat_map(|derivatives| {
+                [CacheMode::None, CacheMode::Cold, CacheMode::Warm]
+                    .map(|cache_mode| (derivatives, cache_mode))
+            })
+            .map(|(derivatives, cache_mode)| {
+                let totals =
+                    Self::measure(device, base_info, variant_infos, derivatives, cache_mode);
+                ((derivatives, cache_mode), totals)
+            })
+            .collect();
+
+        Self {
+            names: names.to_vec(),
+            columns,
+        }
+    }
+
+    fn measure(
+        device: &Device,
+        base_info: &vk::GraphicsPipelineCreateInfo,
+        variant_infos: &[vk::GraphicsPipelineCreateInfo],
+        derivatives: bool,
+        cache_mode: CacheMode,
+    ) -> Vec<Duration> {
+        let create_cache = || unsafe {
+            device
+                .create_pipeline_cache(&vk::PipelineCacheCreateInfo::default(), None)
+                .unwrap()
+        };
+
+        let destroy_pipelines = |pipelines: Vec<(vk::Pipeline, Duration)>| {
+            for (pipeline, _) in pipelines {
+                unsafe { device.destroy_pipeline(pipeline, None) };
+            }
+        };
+
+        let warm_cache = (cache_mode == CacheMode::Warm).then(|| {
+            let pipeline_cache = create_cache();
+            destroy_pipelines(create_with_derivatives(
+                device,
+                pipeline_cache,
+                base_info,
+                variant_infos,
+                derivatives,
+            ));
+            pipeline_cache
+        });
+
+        let mut totals = vec![Duration::ZERO; variant_infos.len() + 1];
+
+        for _ in 0..ITERATIONS {
+            let pipeline_cache = match cache_mode {
+                CacheMode::None => vk::PipelineCache::null(),
+                CacheMode::Cold => create_cache(),
+                CacheMode::Warm => warm_cache.unwrap(),
+            };
+
+            let pipelines = create_with_derivatives(
+                device,
+                pipeline_cache,
+                base_info,
+                variant_infos,
+                derivatives,
+            );
+
+            for (total, (_, elapsed)) in totals.iter_mut().zip(&pipelines) {
+                *total += *elapsed;
+            }
+
+            destroy_pipelines(pipelines);
+
+            if cache_mode == CacheMode::Cold {
+                unsafe { device.destroy_pipeline_cache(pipeline_cache, None) };
+            }
+        }
+
+        if let Some(pipeline_cache) = warm_cache {
+            unsafe { device.destroy_pipeline_cache(pipeline_cache, None) };
+        }
+
+        totals
+    }
+}
+
+//1行目が設定、2行目以降がパイプラインごとのミリ秒、最後の行が合計
+impl fmt::Display for PipelineBench {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let average_ms = |total: Duration| total.as_secs_f64() * 1000.0 / ITERATIONS as f64;
+
+        writeln!(
+            f,
+            "Pipeline creation time (ms, average of {} runs)",
+            ITERATIONS
+        )?;
+
+        write!(f, "{:<12}", "pipeline")?;
+        for &((derivatives, cache_mode), _) in &self.columns {
+            let derivatives = if derivatives { "derived" } else { "plain" };
+            write!(
+                f,
+                " | {:>20}",
+                format!("{}, {}", derivatives, cache_mode.name())
+            )?;
+        }
+        writeln!(f)?;
+
+        for (row, name) in self.names.iter().enumerate() {
+            write!(f, "{:<12}", name)?;
+            for (_, totals) in &self.columns {
+                write!(f, " | {:>20.3}", average_ms(totals[row]))?;
+            }
+            writeln!(f)?;
+        }
+
+        write!(f, "{:<12}", "total")?;
+        for (_, totals) in &self.columns {
+            write!(
+                f,
+                " | {:>20.3}",
+                average_ms(totals.iter().sum::<Duration>())
+            )?;
+        }
+        writeln!(f)
+    }
+}
@@ -0,0 +1,155 @@
+use ash::{vk, Device, Instance};
+use log::info;
+use std::path::PathBuf;
+use std::time::Instant;
+use std::{env, fs};
+
+//VkPipelineCacheHeaderVersionOneの大きさ
+//header_length, header_version, vendor_id, device_id, pipeline_cache_uuid
+const HEADER_LENGTH: usize = 4 * 4 + vk::UUID_SIZE;
+
+//パイプラインのコンパイル結果をファイルに保存して次回の起動時に再利用する
+pub struct PipelineCache {
+    pipeline_cache: vk::PipelineCache,
+    //キャッシュディレクトリが見つからなかった場合はNoneで保存しない
+    path: Option<PathBuf>,
+}
+
+impl PipelineCache {
+    pub fn new(instance: &Instance, physical_device: vk::PhysicalDevice, device: &Device) -> Self {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+
+        let path = Self::cache_path(&properties);
+
+        let initial_data = path
+            .as_ref()
+            .and_then(|path| fs::read(path).ok())
+            .filter(|data| Self::is_valid_header(data, &properties))
+            .unwrap_or_default();
+
+        let create_info = vk::PipelineCacheCreateInfo::builder()
+            .initial_data(&initial_data)
+            .build();
+
+        let started_at = Instant::now();
+
+        let pipeline_cache = unsafe { device.create_pipeline_cache(&create_info, None).unwrap() };
+
+        if initial_data.is_empty() {
+            info!("Pipeline cache: no valid cache found, starting empty");
+        } else {
+            info!(
+                "Pipeline cache: loaded {} bytes in {:.2} ms",
+                initial_data.len(),
+                started_at.elapsed().as_secs_f64() * 1000.0
+            );
+        }
+
+        Self {
+            pipeline_cache,
+            path,
+        }
+    }
+
+    pub fn handle(&self) -> vk::PipelineCache {
+        self.pipeline_cache
+    }
+
+    //デバイスごとに別のファイルにしておくことで複数のGPUを切り替えても壊れたキャッシュを読まないようにする
+    fn cache_path(properties: &vk::PhysicalDeviceProperties) -> Option<PathBuf> {
+        let uuid = properties
+            .pipeline_cache_uuid
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        let file_name = format!(
+            "pipeline_cache_{:04x}_{:04x}_{}.bin",
+            properties.vendor_id, properties.device_id, uuid
+        );
+
+        Some(Self::cache_dir()?.join("vulkan-tutorial").join(file_name))
+    }
+
+    //プラットフォームごとのキャッシュディレクトリ
+    fn cache_dir() -> Option<PathBuf> {
+        if cfg!(target_os = "windows") {
+            env::var_os("LOCALAPPDATA").map(PathBuf::from)
+        } else if cfg!(target_os = "macos") {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Caches"))
+        } else {
+            env::var_os("XDG_CACHE_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        }
+    }
+
+    //ドライバの更新などで中身が合わなくなったキャッシュは読み込まない
+    //不正なデータを渡しても仕様上は無視されるはずだが、ドライバによってはクラッシュすることがあるので自分で確認する
+    fn is_valid_header(data: &[u8], properties: &vk::PhysicalDeviceProperties) -> bool {
+        if data.len() < HEADER_LENGTH {
+            return false;
+        }
+
+        let read_u32 = |offset: usize| {
+            u32::from_le_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+
+        let header_length = read_u32(0);
+        let header_version = read_u32(4);
+        let vendor_id = read_u32(8);
+        let device_id = read_u32(12);
+        let uuid = &data[16..HEADER_LENGTH];
+
+        let valid = header_length as usize >= HEADER_LENGTH
+            && header_version == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+            && vendor_id == properties.vendor_id
+            && device_id == properties.device_id
+            && uuid == properties.pipeline_cache_uuid;
+
+        if !valid {
+            log::warn!("Pipeline cache: header does not match this device, ignoring");
+        }
+
+        valid
+    }
+
+    //終了時に呼んでキャッシュの内容をファイルに書き出す
+    pub fn save(&self, device: &Device) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+
+        let data = match unsafe { device.get_pipeline_cache_data(self.pipeline_cache) } {
+            Ok(data) => data,
+            Err(error) => {
+                log::warn!("Failed to get pipeline cache data: {}", error);
+                return;
+            }
+        };
+
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(path, &data));
+
+        match result {
+            Ok(_) => info!(
+                "Pipeline cache: saved {} bytes to {}",
+                data.len(),
+                path.display()
+            ),
+            Err(error) => log::warn!("Failed to save pipeline cache: {}", error),
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe { device.destroy_pipeline_cache(self.pipeline_cache, None) };
+    }
+}
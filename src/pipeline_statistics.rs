@@ -0,0 +1,142 @@
+use ash::{vk, Device};
+use std::fmt;
+
+//取得する統計の種類
+//結果はビットの小さい順に書き込まれる
+const STATISTIC_FLAGS: vk::QueryPipelineStatisticFlags = vk::QueryPipelineStatisticFlags::from_raw(
+    vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES.as_raw()
+        | vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES.as_raw()
+        | vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw()
+        | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw(),
+);
+
+//STATISTIC_FLAGSで指定した統計の数
+const STATISTIC_COUNT: usize = 5;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PipelineStatisticsCounters {
+    //Input Assemblyが読み込んだ頂点数
+    pub input_vertices: u64,
+    //Input Assemblyが組み立てたプリミティブ数
+    pub input_primitives: u64,
+    pub vertex_shader_invocations: u64,
+    //クリッピングを通過したプリミティブ数
+    pub clipping_primitives: u64,
+    pub fragment_shader_invocations: u64,
+}
+
+//描画コマンドの前後をPIPELINE_STATISTICSクエリで囲ってシェーダーの呼び出し回数などを取得する
+//pipeline_statistics_queryのデバイス機能が有効な場合のみ作成する
+pub struct PipelineStatistics {
+    query_pool: vk::QueryPool,
+    pending: Vec<bool>,
+    latest: Option<PipelineStatisticsCounters>,
+}
+
+impl PipelineStatistics {
+    pub fn new(device: &Device, frames_in_flight: u32) -> Self {
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::PIPELINE_STATISTICS)
+            .query_count(frames_in_flight)
+            .pipeline_statistics(STATISTIC_FLAGS)
+            .build();
+
+        let query_pool = unsafe { device.create_query_pool(&create_info, None).unwrap() };
+
+        Self {
+            query_pool,
+            pending: vec![false; frames_in_flight as usize],
+            latest: None,
+        }
+    }
+
+    //クエリのリセットはレンダーパスの外で行う必要があるのでcmd_beginとは分けている
+    pub fn cmd_reset(&self, device: &Device, command_buffer: vk::CommandBuffer, frame: usize) {
+        unsafe { device.cmd_reset_query_pool(command_buffer, self.query_pool, frame as u32, 1) };
+    }
+
+    //計測したい描画コマンドの前に呼ぶ
+    //begin_queryとend_queryは同じサブパス内に収める必要がある
+    pub fn cmd_begin(&self, device: &Device, command_buffer: vk::CommandBuffer, frame: usize) {
+        unsafe {
+            device.cmd_begin_query(
+                command_buffer,
+                self.query_pool,
+                frame as u32,
+                vk::QueryControlFlags::empty(),
+            )
+        };
+    }
+
+    pub fn cmd_end(&mut self, device: &Device, command_buffer: vk::CommandBuffer, frame: usize) {
+        unsafe { device.cmd_end_query(command_buffer, self.query_pool, frame as u32) };
+
+        self.pending[frame] = true;
+    }
+
+    //GpuTimerと同じくブロックせずに前回の結果を読み込む
+    pub fn read(&mut self, device: &Device, frame: usize) {
+        if !self.pending[frame] {
+            return;
+        }
+
+        //統計の値の後ろに利用可能かどうかの値が入る
+        let mut results = [[0u64; STATISTIC_COUNT + 1]; 1];
+
+        let result = unsafe {
+            device.get_query_pool_results(
+                self.query_pool,
+                frame as u32,
+                1,
+                &mut results,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY,
+            )
+        };
+
+        match result {
+            Ok(_) | Err(vk::Result::NOT_READY) => {}
+            Err(error) => {
+                log::warn!("Failed to read pipeline statistics: {}", error);
+                return;
+            }
+        }
+
+        let [values] = results;
+
+        if values[STATISTIC_COUNT] == 0 {
+            return;
+        }
+
+        self.pending[frame] = false;
+        self.latest = Some(PipelineStatisticsCounters {
+            input_vertices: values[0],
+            input_primitives: values[1],
+            vertex_shader_invocations: values[2],
+            clipping_primitives: values[3],
+            fragment_shader_invocations: values[4],
+        });
+    }
+
+    pub fn latest(&self) -> Option<PipelineStatisticsCounters> {
+        self.latest
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe { device.destroy_query_pool(self.query_pool, None) };
+    }
+}
+
+impl fmt::Display for PipelineStatisticsCounters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "vertices: {}, primitives: {}, vs invocations: {}, clipped primitives: {}, fs invocations: {}",
+            self.input_vertices,
+            self.input_primitives,
+            self.vertex_shader_invocations,
+            self.clipping_primitives,
+            self.fragment_shader_invocations
+        )
+    }
+}
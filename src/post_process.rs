@@ -0,0 +1,452 @@
+use crate::buffer;
+use crate::color_space::{self, ColorEncoding};
+use crate::depth_buffer::DepthBuffer;
+use crate::memory_budget::{self, MemoryCategory};
+use ash::{vk, Device, Instance};
+
+//フルスクリーンの三角形で前のパスの画像をサンプリングして書き出すエフェクト
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PostEffect {
+    //色を反転する
+    Invert,
+    //画面の端を暗くする
+    Vignette,
+    //何もせずに書き出す、エフェクトが無くてもswapchainに書き出す時のエンコードが必要な場合に使う
+    Passthrough,
+    //明るい部分をbloom::Bloomの縮小した画像でぼかして足し、トーンマッピングする
+    Bloom,
+    //露出を掛けてtonemap::Tonemapperでトーンマッピングする
+    Tonemap,
+}
+
+impl PostEffect {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "invert" => Some(Self::Invert),
+            "vignette" => Some(Self::Vignette),
+            "passthrough" => Some(Self::Passthrough),
+            "bloom" => Some(Self::Bloom),
+            "tonemap" => Some(Self::Tonemap),
+            _ => None,
+        }
+    }
+
+    //outputは書き込む画像が求める値の空間
+    pub fn fragment_entry_point(self, output: ColorEncoding) -> &'static str {
+        match (self, output) {
+            (Self::Invert, ColorEncoding::Linear) => "main_fs_post_invert",
+            (Self::Invert, ColorEncoding::Srgb) => "main_fs_post_invert_encode_srgb",
+            (Self::Invert, ColorEncoding::Pq) => "main_fs_post_invert_encode_pq",
+            (Self::Vignette, ColorEncoding::Linear) => "main_fs_post_vignette",
+            (Self::Vignette, ColorEncoding::Srgb) => "main_fs_post_vignette_encode_srgb",
+            (Self::Vignette, ColorEncoding::Pq) => "main_fs_post_vignette_encode_pq",
+            (Self::Passthrough, ColorEncoding::Linear) => "main_fs_post_passthrough",
+            (Self::Passthrough, ColorEncoding::Srgb) => "main_fs_post_passthrough_encode_srgb",
+            (Self::Passthrough, ColorEncoding::Pq) => "main_fs_post_passthrough_encode_pq",
+            (Self::Bloom, ColorEncoding::Linear) => "main_fs_post_bloom",
+            (Self::Bloom, ColorEncoding::Srgb) => "main_fs_post_bloom_encode_srgb",
+            (Self::Bloom, ColorEncoding::Pq) => "main_fs_post_bloom_encode_pq",
+            (Self::Tonemap, ColorEncoding::Linear) => "main_fs_post_tonemap",
+            (Self::Tonemap, ColorEncoding::Srgb) => "main_fs_post_tonemap_encode_srgb",
+            (Self::Tonemap, ColorEncoding::Pq) => "main_fs_post_tonemap_encode_pq",
+        }
+    }
+}
+
+//シーンやエフェクトの途中結果を書き込み、次のパスでサンプリングする画像
+pub struct OffscreenTarget {
+    pub image: vk::Image,
+    memory: vk::DeviceMemory,
+    pub view: vk::ImageView,
+    //dynamic renderingの場合はnull
+    pub framebuffer: vk::Framebuffer,
+}
+
+impl OffscreenTarget {
+    //GPUが画像を使い終わってから呼ぶ
+    pub fn destroy(self, device: &Device) {
+        unsafe {
+            if self.framebuffer != vk::Framebuffer::null() {
+                device.destroy_framebuffer(self.framebuffer, None);
+            }
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+            memory_budget::free_memory(device, self.memory);
+        }
+    }
+}
+
+//シーンをオフスクリーンの画像に描画し、エフェクトを順番に掛けてからswapchainに書き出す
+//targets[i]はeffects[i]の入力で、シーンはtargets[0]に描画する
+//最後のエフェクトはswapchainに直接書き出すので、画像の数はエフェクトの数と同じになる
+//画像はswapchainのフォーマットに関わらずcolor_space::INTERMEDIATE_FORMATで作る
+pub struct PostProcess {
+    effects: Vec<PostEffect>,
+    //dynamic renderingの場合はnull
+    //フォーマットがswapchainに依存しないので、swapchainを作り直しても同じものを使う
+    //シーンと最後以外のエフェクトのパイプラインはこのrender passで作る
+    render_pass: vk::RenderPass,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    //targetsと同じ順番
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    //samplerの破棄はSamplerCacheに任せる
+    sampler: vk::Sampler,
+    //swapchainの大きさとフォーマットに依存するので作り直す
+    targets: Vec<OffscreenTarget>,
+}
+
+impl PostProcess {
+    //画像はcreate_targetsで作成する
+    //use_render_passがtrueの場合は、depth_formatのデプスバッファも使うrender passを作る
+    pub fn new(
+        device: &Device,
+        effects: Vec<PostEffect>,
+        sampler: vk::Sampler,
+        use_render_pass: bool,
+        depth_format: vk::Format,
+    ) -> Self {
+        let render_pass = if use_render_pass {
+            Self::create_render_pass(device, color_space::INTERMEDIATE_FORMAT, depth_format)
+        } else {
+            vk::RenderPass::null()
+        };
+
+        //シェーダー側でimageとsamplerを別々に受け取る
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        };
+
+        let count = effects.len() as u32;
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(count)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::SAMPLER)
+                .descriptor_count(count)
+                .build(),
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(count)
+            .build();
+
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+
+        //画像を作り直してもDescriptor Setはそのまま書き換えて使う
+        let set_layouts = vec![descriptor_set_layout; effects.len()];
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&set_layouts)
+            .build();
+
+        let descriptor_sets = unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() };
+
+        log::info!("Post process effects: {:?}", effects);
+
+        Self {
+            effects,
+            render_pass,
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_sets,
+            sampler,
+            targets: vec![],
+        }
+    }
+
+    pub fn effects(&self) -> &[PostEffect] {
+        &self.effects
+    }
+
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    //effects[index]の入力
+    pub fn target(&self, index: usize) -> &OffscreenTarget {
+        &self.targets[index]
+    }
+
+    //effects[index]で使うset = 0
+    pub fn descriptor_set(&self, index: usize) -> vk::DescriptorSet {
+        self.descriptor_sets[index]
+    }
+
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    //描画する大きさの画像を作り、Descriptor Setを書き換える
+    //シーンのパスと同じrender passを使うので、エフェクトのパスでもdepth_bufferをアタッチメントにする
+    pub fn create_targets(
+        &mut self,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        extent: vk::Extent2D,
+        depth_buffer: &DepthBuffer,
+    ) {
+        self.targets = (0..self.effects.len())
+            .map(|_| {
+                Self::create_target(
+                    instance,
+                    physical_device,
+                    device,
+                    self.render_pass,
+                    color_space::INTERMEDIATE_FORMAT,
+                    extent,
+                    Some(depth_buffer.view),
+                )
+            })
+            .collect();
+
+        for (target, descriptor_set) in self.targets.iter().zip(&self.descriptor_sets) {
+            let image_info = [vk::DescriptorImageInfo::builder()
+                .image_view(target.view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .build()];
+
+            let sampler_info = [vk::DescriptorImageInfo::builder()
+                .sampler(self.sampler)
+                .build()];
+
+            let descriptor_writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .image_info(&image_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::SAMPLER)
+                    .image_info(&sampler_info)
+                    .build(),
+            ];
+
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+    }
+
+    //GPUが画像を使い終わってから呼ぶ
+    pub fn destroy_targets(&mut self, device: &Device) {
+        for target in self.targets.drain(..) {
+            target.destroy(device);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_targets(device);
+
+        unsafe {
+            if self.render_pass != vk::RenderPass::null() {
+                device.destroy_render_pass(self.render_pass, None);
+            }
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+
+    //swapchainのrender passとの違いはフォーマットとfinal_layoutと前後のパスとの依存関係
+    fn create_render_pass(
+        device: &Device,
+        format: vk::Format,
+        depth_format: vk::Format,
+    ) -> vk::RenderPass {
+        let color_attachment = vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            //次のパスでサンプリングする
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build();
+
+        let color_attachment_refs = [vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build()];
+
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)
+            .depth_stencil_attachment(&depth_attachment_ref)
+            .build();
+
+        let dependencies = [
+            //前のフレームで次のパスがサンプリングし終わってから書き込む
+            //デプスバッファは前のパスの書き込みが終わってからクリアする
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::FRAGMENT_SHADER
+                        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                )
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                )
+                .dst_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .build(),
+            //書き込みが終わってから次のパスのフラグメントシェーダーで読む
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build(),
+        ];
+
+        //MSAAはシーンをswapchainに直接描画する場合だけ使うので、ここのデプスバッファは1サンプル
+        let attachments = [
+            color_attachment,
+            DepthBuffer::attachment_description(depth_format, vk::SampleCountFlags::TYPE_1),
+        ];
+        let subpasses = [subpass];
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies)
+            .build();
+
+        unsafe { device.create_render_pass(&render_pass_info, None).unwrap() }
+    }
+
+    //render_passがnullの場合はframebufferを作らない
+    //depth_viewはrender_passにデプスバッファのアタッチメントがある場合だけSome
+    pub fn create_target(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        render_pass: vk::RenderPass,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        depth_view: Option<vk::ImageView>,
+    ) -> OffscreenTarget {
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            //書き込んだ後に次のパスでサンプリングする
+            .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+
+        let image = unsafe { device.create_image(&image_info, None).unwrap() };
+
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
+
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(buffer::find_memory_type(
+                instance,
+                physical_device,
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ))
+            .build();
+
+        let memory = unsafe {
+            memory_budget::allocate_memory(device, &alloc_info, MemoryCategory::Attachment).unwrap()
+        };
+
+        unsafe { device.bind_image_memory(image, memory, 0).unwrap() };
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .base_mip_level(0)
+                    .level_count(1)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .build();
+
+        let view = unsafe { device.create_image_view(&view_info, None).unwrap() };
+
+        let framebuffer = if render_pass == vk::RenderPass::null() {
+            vk::Framebuffer::null()
+        } else {
+            let attachments = [Some(view), depth_view]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+
+            let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(render_pass)
+                .attachments(&attachments)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1)
+                .build();
+
+            unsafe { device.create_framebuffer(&framebuffer_info, None).unwrap() }
+        };
+
+        OffscreenTarget {
+            image,
+            memory,
+            view,
+            framebuffer,
+        }
+    }
+}
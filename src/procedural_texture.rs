@@ -0,0 +1,326 @@
+use crate::buffer;
+use crate::compute;
+use crate::descriptor_allocator::{DescriptorAllocator, DescriptorLayoutCache};
+use crate::memory_budget::{self, MemoryCategory};
+use crate::one_time_commands::OneTimeCommands;
+use crate::synchronization::Synchronization;
+use ash::{vk, Device, Instance};
+use std::mem;
+
+//テクスチャの1辺のピクセル数
+const SIZE: u32 = 256;
+
+//シェーダー側のmain_cs_procedural_textureのthreadsと合わせる
+const WORKGROUP_SIZE: u32 = 8;
+
+//STORAGE_IMAGEに使えるか調べる順番と、それぞれに書き込むエントリーポイント
+//R8G8B8A8_UNORMはshaderStorageImageExtendedFormatsが無くても使えることが多いが必須ではない
+const FORMATS: [(vk::Format, &str); 2] = [
+    (vk::Format::R8G8B8A8_UNORM, "main_cs_procedural_texture"),
+    (
+        vk::Format::R32G32B32A32_SFLOAT,
+        "main_cs_procedural_texture_rgba32f",
+    ),
+];
+
+//シェーダー側のProceduralConstantsと同じレイアウト
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct ProceduralConstants {
+    time: f32,
+    size: u32,
+}
+
+//--procedural-textureで--texturedの最初のマテリアルに貼る、コンピュートシェーダーが毎フレーム書き込むテクスチャ
+//レンダーパスの前にGENERALでディスパッチし、SHADER_READ_ONLY_OPTIMALに戻してフラグメントシェーダーから読む
+pub struct ProceduralTexture {
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+    descriptor_allocator: DescriptorAllocator,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl ProceduralTexture {
+    //どのフォーマットもSTORAGE_IMAGEとSAMPLED_IMAGEの両方に使えない場合はErrを返す
+    //Descriptor Set Layoutの破棄はDescriptorLayoutCacheに任せる
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        pipeline_cache: vk::PipelineCache,
+        shader_module: vk::ShaderModule,
+    ) -> Result<Self, String> {
+        let required = vk::FormatFeatureFlags::STORAGE_IMAGE
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+
+        let (format, entry_point) = FORMATS
+            .into_iter()
+            .find(|&(format, _)| {
+                let properties = unsafe {
+                    instance.get_physical_device_format_properties(physical_device, format)
+                };
+                properties.optimal_tiling_features.contains(required)
+            })
+            .ok_or_else(|| {
+                "No format supports both storage and sampled images for the procedural texture"
+                    .to_owned()
+            })?;
+
+        if format != FORMATS[0].0 {
+            log::warn!(
+                "{:?} cannot be used as a storage image, falling back to {:?}",
+                FORMATS[0].0,
+                format
+            );
+        }
+
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: SIZE,
+                height: SIZE,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+
+        let image = unsafe { device.create_image(&image_info, None).unwrap() };
+
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
+
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(buffer::find_memory_type(
+                instance,
+                physical_device,
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ))
+            .build();
+
+        let memory = unsafe {
+            memory_budget::allocate_memory(device, &alloc_info, MemoryCategory::Texture).unwrap()
+        };
+
+        unsafe { device.bind_image_memory(image, memory, 0).unwrap() };
+
+        //最初のディスパッチより前に描画されることは無いが、Descriptor Setに書いたレイアウトに揃えておく
+        let to_shader_read = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::NONE)
+            .src_access_mask(vk::AccessFlags2::NONE)
+            .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(Self::subresource_range())
+            .build();
+
+        one_time_commands
+            .run(device, synchronization, |command_buffer| unsafe {
+                synchronization.cmd_pipeline_barrier(
+                    device,
+                    command_buffer,
+                    &[],
+                    &[],
+                    &[to_shader_read],
+                );
+            })
+            .expect("Failed to transition the procedural texture");
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(Self::subresource_range())
+            .build();
+
+        let view = unsafe { device.create_image_view(&view_info, None).unwrap() };
+
+        let binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build();
+
+        let descriptor_set_layout = descriptor_layout_cache.get(device, &[binding], &[]);
+
+        let mut descriptor_allocator = DescriptorAllocator::new();
+        let descriptor_set = descriptor_allocator.allocate(device, descriptor_set_layout, None);
+
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::GENERAL)
+            .build()];
+
+        let descriptor_write = vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .image_info(&image_info)
+            .build();
+
+        unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+
+        //経過時間と大きさはPush Constantで渡す
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(mem::size_of::<ProceduralConstants>() as u32)
+            .build();
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&[descriptor_set_layout])
+            .push_constant_ranges(&[push_constant_range])
+            .build();
+
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .unwrap()
+        };
+
+        let pipeline = compute::create_compute_pipeline(
+            device,
+            pipeline_cache,
+            shader_module,
+            entry_point,
+            pipeline_layout,
+        );
+
+        log::info!("Procedural texture: {}x{} {:?}", SIZE, SIZE, format);
+
+        Ok(Self {
+            image,
+            memory,
+            view,
+            descriptor_allocator,
+            descriptor_set,
+            pipeline_layout,
+            pipeline,
+        })
+    }
+
+    //MaterialTexturesのDescriptor Setに書き込む、描画中は常にSHADER_READ_ONLY_OPTIMAL
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    //レンダーパスの外で呼ぶ
+    //前のフレームの描画で読み終わるまで待ってから書き込み、このフレームのフラグメントシェーダーから読めるようにする
+    pub fn cmd_update(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        command_buffer: vk::CommandBuffer,
+        time: f32,
+    ) {
+        let constants = ProceduralConstants { time, size: SIZE };
+
+        //書き込む前の内容は要らないのでUNDEFINEDから遷移する
+        let to_general = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .src_access_mask(vk::AccessFlags2::NONE)
+            .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(Self::subresource_range())
+            .build();
+
+        let to_shader_read = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ)
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(Self::subresource_range())
+            .build();
+
+        unsafe {
+            synchronization.cmd_pipeline_barrier(device, command_buffer, &[], &[], &[to_general]);
+
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                std::slice::from_raw_parts(
+                    &constants as *const ProceduralConstants as *const u8,
+                    mem::size_of::<ProceduralConstants>(),
+                ),
+            );
+
+            let group_count = (SIZE + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+            device.cmd_dispatch(command_buffer, group_count, group_count, 1);
+
+            synchronization.cmd_pipeline_barrier(
+                device,
+                command_buffer,
+                &[],
+                &[],
+                &[to_shader_read],
+            );
+        }
+    }
+
+    fn subresource_range() -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build()
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.descriptor_allocator.destroy(device);
+
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+            memory_budget::free_memory(device, self.memory);
+        }
+    }
+}
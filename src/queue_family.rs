@@ -1,5 +1,7 @@
+use crate::device_info;
 use crate::required_names::get_required_device_extensions;
 use crate::swap_chain_utils::SwapChainSupportDetails;
+use crate::timeline_semaphore;
 use ash::extensions::khr::Surface;
 use ash::vk::{PhysicalDevice, QueueFlags};
 use ash::{vk, Instance};
@@ -7,10 +9,13 @@ use std::ffi::CStr;
 
 pub struct QueueFamilyIndices {
     //キューファミリーは番号で管理されている
-    //描画コマンドに対応しているかどうか
+    //描画コマンドとコンピュートシェーダーのディスパッチに対応しているかどうか
     pub graphics_family: Option<u32>,
     //プレゼンテーションに対応しているかどうか
     pub present_family: Option<u32>,
+    //コンピュートシェーダーのディスパッチに使うキューファミリー
+    //GRAPHICSに対応していない専用のファミリーを優先し、無い場合はgraphics_familyと同じになる
+    pub compute_family: Option<u32>,
 }
 
 impl QueueFamilyIndices {
@@ -18,6 +23,7 @@ impl QueueFamilyIndices {
         Self {
             graphics_family: None,
             present_family: None,
+            compute_family: None,
         }
     }
 
@@ -36,7 +42,12 @@ impl QueueFamilyIndices {
         //複数個のグラフィックスファミリーキューとプレゼンテーションファミリーキューの組み合わせが存在する？
         for (i, queue) in queue_families.iter().enumerate() {
             //グラフィックスキューファミリーの確認
-            if queue.queue_flags.contains(QueueFlags::GRAPHICS) {
+            //GRAPHICSに対応したデバイスにはGRAPHICSとCOMPUTEの両方に対応したファミリーが必ずあるので
+            //コンピュートシェーダーも同じキューで実行できるようにする
+            if queue
+                .queue_flags
+                .contains(QueueFlags::GRAPHICS | QueueFlags::COMPUTE)
+            {
                 queue_family_indices.graphics_family = Some(i as u32);
             }
 
@@ -54,20 +65,68 @@ impl QueueFamilyIndices {
             }
         }
 
+        //専用のコンピュートキューファミリーを使うとグラフィックスの処理と並列に実行できる
+        queue_family_indices.compute_family = queue_families
+            .iter()
+            .position(|queue| {
+                queue.queue_flags.contains(QueueFlags::COMPUTE)
+                    && !queue.queue_flags.contains(QueueFlags::GRAPHICS)
+            })
+            .map(|i| i as u32)
+            .or(queue_family_indices.graphics_family);
+
         queue_family_indices
     }
 
+    //compute_familyがgraphics_familyとは別のファミリーかどうか
+    pub fn has_dedicated_compute_family(&self) -> bool {
+        self.compute_family.is_some() && self.compute_family != self.graphics_family
+    }
+
+    //論理デバイスの作成時にキューを要求するファミリーの一覧(重複なし)
+    pub fn unique_families(&self) -> Vec<u32> {
+        let mut families = [
+            self.graphics_family,
+            self.present_family,
+            self.compute_family,
+        ]
+        .iter()
+        .flatten()
+        .copied()
+        .collect::<Vec<_>>();
+
+        families.sort_unstable();
+        families.dedup();
+
+        families
+    }
+
     //実行したい動作に対して適しているかどうかを判定
-    #[allow(dead_code)]
     pub fn is_device_suitable(
         instance: &Instance,
         surface: &Surface,
         surface_khr: vk::SurfaceKHR,
         physical_device: vk::PhysicalDevice,
+        instance_api_version: u32,
     ) -> bool {
         let indices = Self::find_queue_families(instance, surface, surface_khr, physical_device);
 
-        let extension_supported = Self::check_device_extension_support(instance, physical_device);
+        let extension_supported = match Self::check_device_extension_support(
+            instance,
+            physical_device,
+            &get_required_device_extensions(instance, physical_device),
+        ) {
+            Ok(()) => true,
+            Err(error) => {
+                let props = unsafe { instance.get_physical_device_properties(physical_device) };
+                log::info!(
+                    "{} is not suitable: {}",
+                    device_info::device_name(&props),
+                    error
+                );
+                false
+            }
+        };
 
         let mut swap_chain_adequate = false;
 
@@ -79,11 +138,20 @@ impl QueueFamilyIndices {
                 && !swap_chain_support_details.present_modes.is_empty();
         }
 
-        indices.is_complete() && extension_supported && swap_chain_adequate
+        //フレームごとのCPUとGPUの同期にタイムラインセマフォを使う
+        let timeline_semaphore_supported = timeline_semaphore::is_supported(
+            instance,
+            physical_device,
+            device_info::effective_api_version(instance, physical_device, instance_api_version),
+        );
+
+        indices.is_complete()
+            && extension_supported
+            && swap_chain_adequate
+            && timeline_semaphore_supported
     }
 
     //is_device_suitableの採点版
-    #[allow(dead_code)]
     pub fn rate_device_suitability(
         instance: &Instance,
         physical_device: vk::PhysicalDevice,
@@ -107,15 +175,31 @@ impl QueueFamilyIndices {
         score
     }
 
+    //present_familyが別のsurfaceにもpresentできるかどうか
+    //サポートはsurfaceごとに異なることがあるので、後から作ったsurfaceに対しても確認する
+    pub fn supports_present(
+        &self,
+        surface: &Surface,
+        surface_khr: vk::SurfaceKHR,
+        physical_device: vk::PhysicalDevice,
+    ) -> bool {
+        self.present_family.map_or(false, |family| unsafe {
+            surface
+                .get_physical_device_surface_support(physical_device, family, surface_khr)
+                .unwrap_or(false)
+        })
+    }
+
     //サポートするキューファミリの存在を確認できたかどうか
     fn is_complete(&self) -> bool {
         self.graphics_family.is_some() && self.present_family.is_some()
     }
 
-    //使用を要求するデバイス拡張の存在確認
-    fn check_device_extension_support(
+    //指定したデバイス拡張がサポートされているかどうか
+    pub fn is_device_extension_supported(
         instance: &Instance,
         physical_device: PhysicalDevice,
+        extension_name: &CStr,
     ) -> bool {
         let extensions = unsafe {
             instance
@@ -123,17 +207,42 @@ impl QueueFamilyIndices {
                 .unwrap()
         };
 
-        for required in get_required_device_extensions().iter() {
-            let found = extensions.iter().any(|ext| {
-                let name = unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) };
-                required == &name
-            });
+        extensions.iter().any(|ext| {
+            let name = unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) };
+            name == extension_name
+        })
+    }
 
-            if !found {
-                return false;
-            }
-        }
+    //使用を要求するデバイス拡張の存在確認、無いものがある場合はその名前を並べたエラーを返す
+    pub fn check_device_extension_support(
+        instance: &Instance,
+        physical_device: PhysicalDevice,
+        required: &[&CStr],
+    ) -> Result<(), String> {
+        let extensions = unsafe {
+            instance
+                .enumerate_device_extension_properties(physical_device)
+                .unwrap()
+        };
 
-        true
+        let missing = required
+            .iter()
+            .filter(|required| {
+                !extensions.iter().any(|ext| {
+                    let name = unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) };
+                    **required == name
+                })
+            })
+            .map(|name| name.to_string_lossy())
+            .collect::<Vec<_>>();
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Required device extensions are not supported: {}",
+                missing.join(", ")
+            ))
+        }
     }
 }
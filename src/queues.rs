@@ -0,0 +1,113 @@
+use crate::queue_family::QueueFamilyIndices;
+use crate::submit_queue::SubmitQueue;
+use ash::{vk, Device, Instance};
+
+//同じファミリーのキューの間でどちらを優先して実行するかのドライバへのヒント
+//描画を優先し、アップロードは空いている時に進めば良い
+pub const RENDER_QUEUE_PRIORITY: f32 = 1.0;
+pub const UPLOAD_QUEUE_PRIORITY: f32 = 0.5;
+
+//キューファミリーごとに要求するキューの優先度
+//1つ目のキューは描画、表示、コンピュートで共有し、graphics_familyの2つ目のキューをアップロードに使う
+pub struct QueueRequests {
+    families: Vec<(u32, Vec<f32>)>,
+    graphics_family: u32,
+}
+
+impl QueueRequests {
+    //ファミリーのqueue_countを超える分は要求しない
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        indices: &QueueFamilyIndices,
+    ) -> Self {
+        let properties =
+            unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+        let graphics_family = indices.graphics_family.unwrap();
+
+        let families = indices
+            .unique_families()
+            .into_iter()
+            .map(|family| {
+                let mut priorities = vec![RENDER_QUEUE_PRIORITY];
+
+                if family == graphics_family {
+                    priorities.push(UPLOAD_QUEUE_PRIORITY);
+                }
+
+                priorities.truncate(properties[family as usize].queue_count as usize);
+
+                (family, priorities)
+            })
+            .collect();
+
+        Self {
+            families,
+            graphics_family,
+        }
+    }
+
+    //DeviceCreateInfoに渡す、selfより長く使ってはいけない
+    pub fn create_infos(&self) -> Vec<vk::DeviceQueueCreateInfo> {
+        self.families
+            .iter()
+            .map(|(family, priorities)| {
+                vk::DeviceQueueCreateInfo::builder()
+                    .queue_family_index(*family)
+                    .queue_priorities(priorities)
+                    .build()
+            })
+            .collect()
+    }
+
+    fn queue_count(&self, family: u32) -> usize {
+        self.families
+            .iter()
+            .find(|(requested, _)| *requested == family)
+            .map_or(0, |(_, priorities)| priorities.len())
+    }
+}
+
+//論理デバイスから取得したキュー
+//同じvk::Queueになったものは同じロックを共有する
+pub struct Queues {
+    pub graphics: SubmitQueue,
+    pub present: SubmitQueue,
+    //専用のファミリーが無い場合はgraphicsと同じキューになる
+    pub compute: SubmitQueue,
+    //one_time_commandsで使うgraphics_familyのキュー
+    //graphics_familyのキューが1つしか無い場合はgraphicsと同じキューになる
+    pub upload: SubmitQueue,
+}
+
+impl Queues {
+    pub fn new(device: &Device, indices: &QueueFamilyIndices, requests: &QueueRequests) -> Self {
+        //引数は必要なキューのキューファミリーの番号とキューインデックス
+        //キューインデックスは複数存在するキューのインデックス
+        let graphics =
+            SubmitQueue::new(unsafe { device.get_device_queue(requests.graphics_family, 0) });
+        let present = SubmitQueue::shared_with(
+            unsafe { device.get_device_queue(indices.present_family.unwrap(), 0) },
+            &[&graphics],
+        );
+        let compute = SubmitQueue::shared_with(
+            unsafe { device.get_device_queue(indices.compute_family.unwrap(), 0) },
+            &[&graphics, &present],
+        );
+
+        let upload = if requests.queue_count(requests.graphics_family) > 1 {
+            SubmitQueue::new(unsafe { device.get_device_queue(requests.graphics_family, 1) })
+        } else {
+            log::info!("Only one graphics queue is available, uploads share it with rendering");
+
+            graphics.clone()
+        };
+
+        Self {
+            graphics,
+            present,
+            compute,
+            upload,
+        }
+    }
+}
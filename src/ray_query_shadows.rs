@@ -0,0 +1,229 @@
+use crate::acceleration_structure::{self, DynamicAccelerationStructures};
+use crate::mesh::Mesh;
+use crate::one_time_commands::OneTimeCommands;
+use crate::queue_family::QueueFamilyIndices;
+use crate::synchronization::Synchronization;
+use ash::{vk, Device, Instance};
+use glam::Mat4;
+use std::ffi::CStr;
+
+//--ray-query-shadowsでシャドウマップの代わりにフラグメントシェーダーからライトへレイを飛ばして影を付ける
+//不透明なオブジェクトをインスタンスにしたTLASをフレームごとにビルドし、メインのパイプラインのset = 3で渡す
+//set = 0からset = 2まではシャドウマップのパイプラインと同じなので、パイプラインを切り替えても紐づけ直さなくて良い
+pub struct RayQueryShadows {
+    acceleration_structures: DynamicAccelerationStructures,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    //フレームごとのTLASを紐づけたもの
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    //falseの場合はシャドウマップで影を付ける
+    enabled: bool,
+}
+
+impl RayQueryShadows {
+    //加速構造のビルドにbufferDeviceAddressが必要になる
+    pub fn is_supported(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        api_version: u32,
+    ) -> bool {
+        if api_version < vk::make_api_version(0, 1, 2, 0) {
+            return false;
+        }
+
+        let extensions_supported = Self::extension_names().iter().all(|name| {
+            QueueFamilyIndices::is_device_extension_supported(instance, physical_device, name)
+        });
+
+        if !extensions_supported {
+            return false;
+        }
+
+        let mut address_features = vk::PhysicalDeviceBufferDeviceAddressFeatures::default();
+        let mut acceleration_structure_features =
+            vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default();
+        let mut ray_query_features = vk::PhysicalDeviceRayQueryFeaturesKHR::default();
+        let mut features = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut address_features)
+            .push_next(&mut acceleration_structure_features)
+            .push_next(&mut ray_query_features)
+            .build();
+
+        unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+
+        [
+            address_features.buffer_device_address,
+            acceleration_structure_features.acceleration_structure,
+            ray_query_features.ray_query,
+        ]
+        .into_iter()
+        .all(|feature| feature == vk::TRUE)
+    }
+
+    //DeviceCreateInfoで有効にする必要のある拡張
+    pub fn extension_names() -> Vec<&'static CStr> {
+        acceleration_structure::extension_names()
+            .into_iter()
+            .chain([vk::KhrRayQueryFn::name()])
+            .collect()
+    }
+
+    //max_instancesはTLASに置けるオブジェクトの数で、cmd_buildに渡すモデル行列の数の上限
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        mesh: &Mesh,
+        frames_in_flight: usize,
+        max_instances: u32,
+    ) -> Result<Self, vk::Result> {
+        let acceleration_structures = DynamicAccelerationStructures::new(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            mesh,
+            frames_in_flight,
+            max_instances,
+        )?;
+
+        let bindings = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build()];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        };
+
+        let pool_sizes = [vk::DescriptorPoolSize::builder()
+            .ty(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+            .descriptor_count(frames_in_flight as u32)
+            .build()];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(frames_in_flight as u32)
+            .build();
+
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+
+        let set_layouts = vec![descriptor_set_layout; frames_in_flight];
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&set_layouts)
+            .build();
+
+        let descriptor_sets = unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() };
+
+        for (frame, descriptor_set) in descriptor_sets.iter().enumerate() {
+            //TLASはpNextで渡すのでdescriptor_countを自分で設定する
+            let top_level = [acceleration_structures.top_level(frame)];
+            let mut acceleration_structure_info =
+                vk::WriteDescriptorSetAccelerationStructureKHR::builder()
+                    .acceleration_structures(&top_level)
+                    .build();
+
+            let mut write = vk::WriteDescriptorSet::builder()
+                .dst_set(*descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+                .push_next(&mut acceleration_structure_info)
+                .build();
+            write.descriptor_count = 1;
+
+            unsafe { device.update_descriptor_sets(&[write], &[]) };
+        }
+
+        Ok(Self {
+            acceleration_structures,
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_sets,
+            enabled: true,
+        })
+    }
+
+    //パイプラインレイアウトのset = 3に使う
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+
+        log::info!(
+            "shadows: {}",
+            if self.enabled {
+                "ray query"
+            } else {
+                "shadow map"
+            }
+        );
+    }
+
+    //modelsは影を落とすオブジェクトのモデル行列
+    //レンダーパスの外で、フラグメントシェーダーが読む前に記録する
+    pub fn cmd_build(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        models: &[Mat4],
+    ) {
+        self.acceleration_structures.cmd_build(
+            device,
+            synchronization,
+            command_buffer,
+            frame,
+            models,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        );
+    }
+
+    pub fn cmd_bind(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        frame: usize,
+    ) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                3,
+                &[self.descriptor_sets[frame]],
+                &[],
+            );
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+
+        self.acceleration_structures.destroy(device);
+    }
+}
@@ -0,0 +1,720 @@
+use crate::acceleration_structure::{self, AccelerationStructures};
+use crate::buffer;
+use crate::color_space::ColorEncoding;
+use crate::memory_budget::{self, MemoryCategory};
+use crate::mesh::Mesh;
+use crate::one_time_commands::OneTimeCommands;
+use crate::queue_family::QueueFamilyIndices;
+use crate::synchronization::Synchronization;
+use ash::extensions::khr;
+use ash::{vk, Device, Instance};
+use glam::Mat4;
+use std::ffi::{CStr, CString};
+use std::mem;
+
+//トレースした結果を書き込む画像のフォーマット
+//swapchainとはチャンネルの順番が違うことがあるのでコピーではなくblitで変換する
+//PQでエンコードした値は8bitだと階調が足りないので、HDR10の10bitより精度の高い16bitにする
+const STORAGE_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+//シェーダー側のOUTPUT_*と同じ値
+const OUTPUT_LINEAR: u32 = 0;
+const OUTPUT_SRGB: u32 = 1;
+const OUTPUT_PQ: u32 = 2;
+
+//シェーダーバインディングテーブルのグループの並び順
+const RAYGEN_GROUP: usize = 0;
+const MISS_GROUP: usize = 1;
+const HIT_GROUP: usize = 2;
+const GROUP_COUNT: usize = 3;
+
+//main_rgenにpush constantで渡すカメラの逆行列
+//シェーダー側のRayTracingConstantsと同じレイアウト
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct RayTracingConstants {
+    pub view_inverse: Mat4,
+    pub proj_inverse: Mat4,
+    //blitは値を変換しないので、swapchainのColorFormat::shader_outputに合わせてシェーダーでエンコードする
+    pub output_encoding: u32,
+    pub _padding: [u32; 3],
+}
+
+impl RayTracingConstants {
+    pub fn new(view: Mat4, proj: Mat4, output: ColorEncoding) -> Self {
+        Self {
+            view_inverse: view.inverse(),
+            proj_inverse: proj.inverse(),
+            output_encoding: match output {
+                ColorEncoding::Linear => OUTPUT_LINEAR,
+                ColorEncoding::Srgb => OUTPUT_SRGB,
+                ColorEncoding::Pq => OUTPUT_PQ,
+            },
+            _padding: [0; 3],
+        }
+    }
+}
+
+//main_rgenが書き込み、swapchainの画像にblitする画像
+//swapchainと同じ大きさなので、swapchainを作り直す度に作り直す
+struct StorageImage {
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+    extent: vk::Extent2D,
+}
+
+//--raytraceでラスタライズの代わりに使うレイトレーシングパイプライン
+//raygenで画素ごとにレイを飛ばし、当たった三角形の重心座標かmissの色を書き込む
+pub struct RayTracer {
+    loader: khr::RayTracingPipeline,
+    acceleration_structures: AccelerationStructures,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    //binding = 0がTLAS、binding = 1がstorage_image
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    shader_binding_table_buffer: vk::Buffer,
+    shader_binding_table_memory: vk::DeviceMemory,
+    //RAYGEN_GROUP, MISS_GROUP, HIT_GROUPの順の領域
+    regions: [vk::StridedDeviceAddressRegionKHR; GROUP_COUNT],
+    storage_image: Option<StorageImage>,
+}
+
+impl RayTracer {
+    //加速構造とレイトレーシングパイプラインに加えて、加速構造のビルドにbufferDeviceAddressが必要になる
+    pub fn is_supported(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        api_version: u32,
+    ) -> bool {
+        if api_version < vk::make_api_version(0, 1, 2, 0) {
+            return false;
+        }
+
+        let extensions_supported = Self::extension_names().iter().all(|name| {
+            QueueFamilyIndices::is_device_extension_supported(instance, physical_device, name)
+        });
+
+        if !extensions_supported {
+            return false;
+        }
+
+        let mut address_features = vk::PhysicalDeviceBufferDeviceAddressFeatures::default();
+        let mut acceleration_structure_features =
+            vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default();
+        let mut ray_tracing_pipeline_features =
+            vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::default();
+        let mut features = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut address_features)
+            .push_next(&mut acceleration_structure_features)
+            .push_next(&mut ray_tracing_pipeline_features)
+            .build();
+
+        unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+
+        [
+            address_features.buffer_device_address,
+            acceleration_structure_features.acceleration_structure,
+            ray_tracing_pipeline_features.ray_tracing_pipeline,
+        ]
+        .into_iter()
+        .all(|feature| feature == vk::TRUE)
+    }
+
+    //DeviceCreateInfoで有効にする必要のある拡張
+    pub fn extension_names() -> Vec<&'static CStr> {
+        acceleration_structure::extension_names()
+            .into_iter()
+            .chain([khr::RayTracingPipeline::name()])
+            .collect()
+    }
+
+    //shader_moduleはmain_rgen, main_rmiss, main_rchitを含むもので、破棄は呼び出し側で行う
+    //失敗した場合はラスタライズで描画できるように作ったものを破棄してErrを返す
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        pipeline_cache: vk::PipelineCache,
+        shader_module: vk::ShaderModule,
+        mesh: &Mesh,
+    ) -> Result<Self, vk::Result> {
+        let acceleration_structures = AccelerationStructures::new(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            mesh,
+        )?;
+
+        let loader = khr::RayTracingPipeline::new(instance, device);
+
+        let descriptor_set_layout = Self::create_descriptor_set_layout(device);
+
+        let push_constant_ranges = [vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR)
+            .offset(0)
+            .size(mem::size_of::<RayTracingConstants>() as u32)
+            .build()];
+
+        let set_layouts = [descriptor_set_layout];
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges)
+            .build();
+
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .unwrap()
+        };
+
+        let pipeline =
+            Self::create_pipeline(&loader, pipeline_cache, shader_module, pipeline_layout);
+
+        let pipeline = match pipeline {
+            Ok(pipeline) => pipeline,
+            Err(error) => {
+                unsafe {
+                    device.destroy_pipeline_layout(pipeline_layout, None);
+                    device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+                }
+                acceleration_structures.destroy(device);
+                return Err(error);
+            }
+        };
+
+        let table =
+            Self::create_shader_binding_table(instance, physical_device, device, &loader, pipeline);
+
+        let (shader_binding_table_buffer, shader_binding_table_memory, regions) = match table {
+            Ok(table) => table,
+            Err(error) => {
+                unsafe {
+                    device.destroy_pipeline(pipeline, None);
+                    device.destroy_pipeline_layout(pipeline_layout, None);
+                    device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+                }
+                acceleration_structures.destroy(device);
+                return Err(error);
+            }
+        };
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+                .descriptor_count(1)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1)
+                .build(),
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&set_layouts)
+            .build();
+
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] };
+
+        //TLASはpNextで渡すのでdescriptor_countを自分で設定する
+        let top_level = [acceleration_structures.top_level()];
+        let mut acceleration_structure_info =
+            vk::WriteDescriptorSetAccelerationStructureKHR::builder()
+                .acceleration_structures(&top_level)
+                .build();
+
+        let mut acceleration_structure_write = vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+            .push_next(&mut acceleration_structure_info)
+            .build();
+        acceleration_structure_write.descriptor_count = 1;
+
+        unsafe { device.update_descriptor_sets(&[acceleration_structure_write], &[]) };
+
+        log::info!("Ray tracing pipeline: {} shader groups", GROUP_COUNT);
+
+        Ok(Self {
+            loader,
+            acceleration_structures,
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_set,
+            pipeline_layout,
+            pipeline,
+            shader_binding_table_buffer,
+            shader_binding_table_memory,
+            regions,
+            storage_image: None,
+        })
+    }
+
+    fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR)
+                .build(),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    //raygen, miss, closest hitをそれぞれ1つのグループにする
+    fn create_pipeline(
+        loader: &khr::RayTracingPipeline,
+        pipeline_cache: vk::PipelineCache,
+        shader_module: vk::ShaderModule,
+        pipeline_layout: vk::PipelineLayout,
+    ) -> Result<vk::Pipeline, vk::Result> {
+        //Lifetimeを確保するために一度変数にしている
+        let entry_points = [
+            (vk::ShaderStageFlags::RAYGEN_KHR, "main_rgen"),
+            (vk::ShaderStageFlags::MISS_KHR, "main_rmiss"),
+            (vk::ShaderStageFlags::CLOSEST_HIT_KHR, "main_rchit"),
+        ]
+        .map(|(stage, name)| (stage, CString::new(name).unwrap()));
+
+        let stages = entry_points
+            .iter()
+            .map(|(stage, name)| {
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(*stage)
+                    .module(shader_module)
+                    .name(name.as_c_str())
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        //ステージの番号はstagesのインデックス
+        let general_group = |stage: u32| {
+            vk::RayTracingShaderGroupCreateInfoKHR::builder()
+                .ty(vk::RayTracingShaderGroupTypeKHR::GENERAL)
+                .general_shader(stage)
+                .closest_hit_shader(vk::SHADER_UNUSED_KHR)
+                .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                .intersection_shader(vk::SHADER_UNUSED_KHR)
+                .build()
+        };
+
+        let groups = [
+            general_group(0),
+            general_group(1),
+            vk::RayTracingShaderGroupCreateInfoKHR::builder()
+                .ty(vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP)
+                .general_shader(vk::SHADER_UNUSED_KHR)
+                .closest_hit_shader(2)
+                .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                .intersection_shader(vk::SHADER_UNUSED_KHR)
+                .build(),
+        ];
+
+        //closest hitからはレイを飛ばさないので再帰の深さは1で良い
+        let pipeline_info = vk::RayTracingPipelineCreateInfoKHR::builder()
+            .stages(&stages)
+            .groups(&groups)
+            .max_pipeline_ray_recursion_depth(1)
+            .layout(pipeline_layout)
+            .build();
+
+        let pipelines = unsafe {
+            loader.create_ray_tracing_pipelines(
+                vk::DeferredOperationKHR::null(),
+                pipeline_cache,
+                &[pipeline_info],
+                None,
+            )?
+        };
+
+        Ok(pipelines[0])
+    }
+
+    //グループのハンドルをshader_group_handle_alignmentごとに並べ、各領域の先頭をshader_group_base_alignmentに揃える
+    fn create_shader_binding_table(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        loader: &khr::RayTracingPipeline,
+        pipeline: vk::Pipeline,
+    ) -> Result<
+        (
+            vk::Buffer,
+            vk::DeviceMemory,
+            [vk::StridedDeviceAddressRegionKHR; GROUP_COUNT],
+        ),
+        vk::Result,
+    > {
+        let properties =
+            unsafe { khr::RayTracingPipeline::get_properties(instance, physical_device) };
+
+        let handle_size = properties.shader_group_handle_size as usize;
+        let handle_stride = buffer::align_up(
+            handle_size as vk::DeviceSize,
+            properties.shader_group_handle_alignment as vk::DeviceSize,
+        );
+        let base_alignment = properties.shader_group_base_alignment as vk::DeviceSize;
+        let region_size = buffer::align_up(handle_stride, base_alignment);
+
+        let handles = unsafe {
+            loader.get_ray_tracing_shader_group_handles(
+                pipeline,
+                0,
+                GROUP_COUNT as u32,
+                GROUP_COUNT * handle_size,
+            )?
+        };
+
+        //バッファのアドレスがbase_alignmentに揃う保証は無いので、余分に確保して先頭をずらす
+        let size = region_size * GROUP_COUNT as vk::DeviceSize + base_alignment;
+
+        let (buffer, memory) = buffer::create_buffer(
+            instance,
+            physical_device,
+            device,
+            size,
+            vk::BufferUsageFlags::SHADER_BINDING_TABLE_KHR
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        );
+
+        let address = buffer::device_address(device, buffer);
+        let offset = buffer::align_up(address, base_alignment) - address;
+
+        //グループごとの領域の先頭のバッファ内でのオフセット
+        let region_offset = |group: usize| offset + group as vk::DeviceSize * region_size;
+
+        unsafe {
+            let pointer = device
+                .map_memory(memory, 0, size, vk::MemoryMapFlags::empty())
+                .unwrap() as *mut u8;
+
+            for (group, handle) in handles.chunks(handle_size).enumerate() {
+                pointer
+                    .add(region_offset(group) as usize)
+                    .copy_from_nonoverlapping(handle.as_ptr(), handle_size);
+            }
+
+            device.unmap_memory(memory);
+        }
+
+        //raygenの領域はsizeとstrideを同じにする必要がある
+        //missとhitはそれぞれ1つだけなのでどちらもハンドル1つ分の大きさ
+        let regions = [RAYGEN_GROUP, MISS_GROUP, HIT_GROUP].map(|group| {
+            vk::StridedDeviceAddressRegionKHR::builder()
+                .device_address(address + region_offset(group))
+                .stride(handle_stride)
+                .size(handle_stride)
+                .build()
+        });
+
+        log::info!(
+            "Shader binding table: handle size {}, handle alignment {}, base alignment {}",
+            handle_size,
+            properties.shader_group_handle_alignment,
+            base_alignment
+        );
+
+        Ok((buffer, memory, regions))
+    }
+
+    //swapchainの大きさでトレース結果の画像を作り、set = 0のbinding = 1に書き込む
+    pub fn create_storage_image(
+        &mut self,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        extent: vk::Extent2D,
+    ) {
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(STORAGE_FORMAT)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+
+        let image = unsafe { device.create_image(&image_info, None).unwrap() };
+
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
+
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(buffer::find_memory_type(
+                instance,
+                physical_device,
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ))
+            .build();
+
+        let memory = unsafe {
+            memory_budget::allocate_memory(device, &alloc_info, MemoryCategory::Attachment).unwrap()
+        };
+
+        unsafe { device.bind_image_memory(image, memory, 0).unwrap() };
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(STORAGE_FORMAT)
+            .subresource_range(Self::subresource_range())
+            .build();
+
+        let view = unsafe { device.create_image_view(&view_info, None).unwrap() };
+
+        //main_rgenが書き込む時のレイアウトはGENERAL
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::GENERAL)
+            .build()];
+
+        let image_write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.descriptor_set)
+            .dst_binding(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .image_info(&image_info)
+            .build();
+
+        unsafe { device.update_descriptor_sets(&[image_write], &[]) };
+
+        self.storage_image = Some(StorageImage {
+            image,
+            memory,
+            view,
+            extent,
+        });
+    }
+
+    pub fn destroy_storage_image(&mut self, device: &Device) {
+        if let Some(storage_image) = self.storage_image.take() {
+            unsafe {
+                device.destroy_image_view(storage_image.view, None);
+                device.destroy_image(storage_image.image, None);
+                memory_budget::free_memory(device, storage_image.memory);
+            }
+        }
+    }
+
+    fn subresource_range() -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build()
+    }
+
+    //画面全体にレイを飛ばし、結果をswap_chain_imageにblitしてPRESENT_SRC_KHRにする
+    //swap_chain_imageはTRANSFER_DSTを付けて作る必要がある
+    pub fn cmd_trace(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        command_buffer: vk::CommandBuffer,
+        constants: &RayTracingConstants,
+        swap_chain_image: vk::Image,
+    ) {
+        let storage_image = self
+            .storage_image
+            .as_ref()
+            .expect("Storage image is not created");
+
+        let extent = storage_image.extent;
+
+        //前のフレームのblitで読み終わってから上書きする、前の内容は使わないのでUNDEFINEDから遷移する
+        let to_general = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::BLIT)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_READ)
+            .dst_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR)
+            .dst_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(storage_image.image)
+            .subresource_range(Self::subresource_range())
+            .build();
+
+        synchronization.cmd_pipeline_barrier(device, command_buffer, &[], &[], &[to_general]);
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::RAY_TRACING_KHR,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::RAY_TRACING_KHR,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::RAYGEN_KHR,
+                0,
+                std::slice::from_raw_parts(
+                    constants as *const RayTracingConstants as *const u8,
+                    mem::size_of::<RayTracingConstants>(),
+                ),
+            );
+
+            //callableシェーダーは使わないので空の領域を渡す
+            self.loader.cmd_trace_rays(
+                command_buffer,
+                &self.regions[RAYGEN_GROUP],
+                &self.regions[MISS_GROUP],
+                &self.regions[HIT_GROUP],
+                &vk::StridedDeviceAddressRegionKHR::default(),
+                extent.width,
+                extent.height,
+                1,
+            );
+        }
+
+        let to_transfer_src = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR)
+            .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::BLIT)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(storage_image.image)
+            .subresource_range(Self::subresource_range())
+            .build();
+
+        //image_available_semaphoreはCOLOR_ATTACHMENT_OUTPUTで待っているので、そこからblitに繋げる
+        let to_transfer_dst = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags2::NONE)
+            .dst_stage_mask(vk::PipelineStageFlags2::BLIT)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(swap_chain_image)
+            .subresource_range(Self::subresource_range())
+            .build();
+
+        synchronization.cmd_pipeline_barrier(
+            device,
+            command_buffer,
+            &[],
+            &[],
+            &[to_transfer_src, to_transfer_dst],
+        );
+
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        let corner = vk::Offset3D {
+            x: extent.width as i32,
+            y: extent.height as i32,
+            z: 1,
+        };
+
+        //同じ大きさなので拡大縮小はしない、フォーマットの変換だけを行う
+        let region = vk::ImageBlit::builder()
+            .src_subresource(subresource)
+            .src_offsets([vk::Offset3D::default(), corner])
+            .dst_subresource(subresource)
+            .dst_offsets([vk::Offset3D::default(), corner])
+            .build();
+
+        unsafe {
+            device.cmd_blit_image(
+                command_buffer,
+                storage_image.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                swap_chain_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+                vk::Filter::NEAREST,
+            );
+        }
+
+        //presentはrender_finished_semaphoreで待つのでdstは何も指定しない
+        let to_present = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::BLIT)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::NONE)
+            .dst_access_mask(vk::AccessFlags2::NONE)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(swap_chain_image)
+            .subresource_range(Self::subresource_range())
+            .build();
+
+        synchronization.cmd_pipeline_barrier(device, command_buffer, &[], &[], &[to_present]);
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_storage_image(device);
+
+        unsafe {
+            device.destroy_buffer(self.shader_binding_table_buffer, None);
+            memory_budget::free_memory(device, self.shader_binding_table_memory);
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+
+        self.acceleration_structures.destroy(device);
+    }
+}
@@ -0,0 +1,372 @@
+use crate::buffer;
+use crate::memory_budget;
+use crate::one_time_commands::OneTimeCommands;
+use crate::synchronization::Synchronization;
+use ash::{vk, Device, Instance};
+
+//読み戻してRGBA8に変換できるフォーマットの1テクセルのバイト数
+pub fn texel_size(format: vk::Format) -> Option<usize> {
+    match format {
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::D32_SFLOAT => Some(4),
+        vk::Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
+
+fn aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D32_SFLOAT => vk::ImageAspectFlags::DEPTH,
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+//imageの0段目の最初のレイヤーをRGBA8としてホストのメモリに読み戻す
+//imageはTRANSFER_SRCを付けて作り、layoutにある必要がある、読み終わったらlayoutに戻す
+#[allow(dead_code, clippy::too_many_arguments)]
+pub fn read_image_to_vec(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    device: &Device,
+    one_time_commands: &OneTimeCommands,
+    synchronization: &Synchronization,
+    image: vk::Image,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    layout: vk::ImageLayout,
+) -> Result<Vec<u8>, String> {
+    read_image_level_to_vec(
+        instance,
+        physical_device,
+        device,
+        one_time_commands,
+        synchronization,
+        image,
+        extent,
+        format,
+        layout,
+        0,
+    )
+}
+
+//read_image_to_vecのmip_level段目を読むもの、extentはその段の大きさ
+#[allow(clippy::too_many_arguments)]
+pub fn read_image_level_to_vec(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    device: &Device,
+    one_time_commands: &OneTimeCommands,
+    synchronization: &Synchronization,
+    image: vk::Image,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    layout: vk::ImageLayout,
+    mip_level: u32,
+) -> Result<Vec<u8>, String> {
+    let texel_size =
+        texel_size(format).ok_or_else(|| format!("Cannot read back {:?} images", format))?;
+
+    //UNDEFINEDは内容が保証されず、戻す先にも使えない
+    if matches!(
+        layout,
+        vk::ImageLayout::UNDEFINED | vk::ImageLayout::PREINITIALIZED
+    ) {
+        return Err(format!("Cannot read back an image in {:?}", layout));
+    }
+
+    let row_pitch = extent.width as usize * texel_size;
+    let size = (row_pitch * extent.height as usize) as vk::DeviceSize;
+
+    let (buffer, memory) = buffer::create_buffer(
+        instance,
+        physical_device,
+        device,
+        size,
+        vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    );
+
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(aspect_mask(format))
+        .base_mip_level(mip_level)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build();
+
+    //直前と直後にimageをどう使うかは分からないので、全てのコマンドと待ち合わせる
+    let barrier = |(src_stage, src_access, old_layout), (dst_stage, dst_access, new_layout)| {
+        vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(src_stage)
+            .src_access_mask(src_access)
+            .dst_stage_mask(dst_stage)
+            .dst_access_mask(dst_access)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build()
+    };
+
+    let original = (
+        vk::PipelineStageFlags2::ALL_COMMANDS,
+        vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE,
+        layout,
+    );
+    let copy_source = (
+        vk::PipelineStageFlags2::COPY,
+        vk::AccessFlags2::TRANSFER_READ,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+    );
+
+    //buffer_row_lengthとbuffer_image_heightが0の場合は隙間無く詰めて書き込まれる
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(
+            vk::ImageSubresourceLayers::builder()
+                .aspect_mask(aspect_mask(format))
+                .mip_level(mip_level)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build(),
+        )
+        .image_offset(vk::Offset3D::default())
+        .image_extent(vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        })
+        .build();
+
+    let result = one_time_commands.run(device, synchronization, |command_buffer| unsafe {
+        synchronization.cmd_pipeline_barrier(
+            device,
+            command_buffer,
+            &[],
+            &[],
+            &[barrier(original, copy_source)],
+        );
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            buffer,
+            &[region],
+        );
+        synchronization.cmd_pipeline_barrier(
+            device,
+            command_buffer,
+            &[],
+            &[],
+            &[barrier(copy_source, original)],
+        );
+    });
+
+    let pixels = result
+        .map(|()| unsafe {
+            let pointer = device
+                .map_memory(memory, 0, size, vk::MemoryMapFlags::empty())
+                .unwrap() as *const u8;
+            let data = std::slice::from_raw_parts(pointer, size as usize);
+            let pixels = convert_to_rgba8(format, extent, row_pitch, data);
+            device.unmap_memory(memory);
+            pixels
+        })
+        .map_err(|error| format!("Failed to read back image: {}", error))
+        .and_then(|pixels| pixels);
+
+    unsafe {
+        device.destroy_buffer(buffer, None);
+        memory_budget::free_memory(device, memory);
+    }
+
+    pixels
+}
+
+//formatのテクセルが1行row_pitchバイトで並んだdataを、隙間無く詰めたRGBA8に変換する
+//sRGBのフォーマットはエンコードされた値のまま、浮動小数点は0.0から1.0に切り詰め、深度は0が黒で1が白のグレースケールにする
+pub fn convert_to_rgba8(
+    format: vk::Format,
+    extent: vk::Extent2D,
+    row_pitch: usize,
+    data: &[u8],
+) -> Result<Vec<u8>, String> {
+    let texel_size =
+        texel_size(format).ok_or_else(|| format!("Cannot convert {:?} to RGBA8", format))?;
+
+    let width = extent.width as usize;
+    let height = extent.height as usize;
+
+    if row_pitch < width * texel_size {
+        return Err(format!(
+            "Row pitch {} is smaller than a row of {} texels",
+            row_pitch, width
+        ));
+    }
+
+    //最後の行は行の長さだけあればよい
+    let required = match height {
+        0 => 0,
+        _ => row_pitch * (height - 1) + width * texel_size,
+    };
+    if data.len() < required {
+        return Err(format!(
+            "{} bytes is too small for a {}x{} {:?} image",
+            data.len(),
+            width,
+            height,
+            format
+        ));
+    }
+
+    let float = |bytes: &[u8]| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let unorm = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+    let mut pixels = Vec::with_capacity(width * height * 4);
+
+    for row in (0..height).map(|y| &data[y * row_pitch..][..width * texel_size]) {
+        for texel in row.chunks_exact(texel_size) {
+            let rgba = match format {
+                vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => {
+                    [texel[0], texel[1], texel[2], texel[3]]
+                }
+                vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => {
+                    [texel[2], texel[1], texel[0], texel[3]]
+                }
+                vk::Format::R32G32B32A32_SFLOAT => [
+                    unorm(float(&texel[0..4])),
+                    unorm(float(&texel[4..8])),
+                    unorm(float(&texel[8..12])),
+                    unorm(float(&texel[12..16])),
+                ],
+                vk::Format::D32_SFLOAT => {
+                    let depth = unorm(float(texel));
+                    [depth, depth, depth, 255]
+                }
+                _ => unreachable!(),
+            };
+
+            pixels.extend_from_slice(&rgba);
+        }
+    }
+
+    Ok(pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(width: u32, height: u32) -> vk::Extent2D {
+        vk::Extent2D { width, height }
+    }
+
+    fn floats(values: &[f32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_ne_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn rgba8_is_copied_as_is() {
+        let data = [1, 2, 3, 4, 5, 6, 7, 8];
+
+        for format in [vk::Format::R8G8B8A8_UNORM, vk::Format::R8G8B8A8_SRGB] {
+            assert_eq!(
+                convert_to_rgba8(format, extent(2, 1), 8, &data).unwrap(),
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn bgra8_swaps_red_and_blue() {
+        let data = [1, 2, 3, 4, 5, 6, 7, 8];
+
+        for format in [vk::Format::B8G8R8A8_UNORM, vk::Format::B8G8R8A8_SRGB] {
+            assert_eq!(
+                convert_to_rgba8(format, extent(2, 1), 8, &data).unwrap(),
+                [3, 2, 1, 4, 7, 6, 5, 8]
+            );
+        }
+    }
+
+    #[test]
+    fn row_pitch_padding_is_skipped() {
+        //1行2テクセルの8バイトに、4バイトの詰め物が付いている
+        let data = [
+            1, 2, 3, 4, 5, 6, 7, 8, 0xEE, 0xEE, 0xEE, 0xEE, //
+            9, 10, 11, 12, 13, 14, 15, 16, 0xEE, 0xEE, 0xEE, 0xEE,
+        ];
+
+        assert_eq!(
+            convert_to_rgba8(vk::Format::R8G8B8A8_UNORM, extent(2, 2), 12, &data).unwrap(),
+            (1..=16).collect::<Vec<u8>>()
+        );
+    }
+
+    #[test]
+    fn the_last_row_needs_no_padding() {
+        let data = [1, 2, 3, 4, 0xEE, 0xEE, 0xEE, 0xEE, 5, 6, 7, 8];
+
+        assert_eq!(
+            convert_to_rgba8(vk::Format::R8G8B8A8_UNORM, extent(1, 2), 8, &data).unwrap(),
+            [1, 2, 3, 4, 5, 6, 7, 8]
+        );
+    }
+
+    #[test]
+    fn float_colors_are_clamped() {
+        let data = floats(&[0.5, 2.0, -1.0, 1.0]);
+
+        assert_eq!(
+            convert_to_rgba8(vk::Format::R32G32B32A32_SFLOAT, extent(1, 1), 16, &data).unwrap(),
+            [128, 255, 0, 255]
+        );
+    }
+
+    #[test]
+    fn depth_becomes_grayscale() {
+        let data = floats(&[0.0, 1.0, 0.25]);
+
+        assert_eq!(
+            convert_to_rgba8(vk::Format::D32_SFLOAT, extent(3, 1), 12, &data).unwrap(),
+            [0, 0, 0, 255, 255, 255, 255, 255, 64, 64, 64, 255]
+        );
+    }
+
+    #[test]
+    fn empty_images_convert_to_nothing() {
+        assert_eq!(
+            convert_to_rgba8(vk::Format::R8G8B8A8_UNORM, extent(4, 0), 16, &[]).unwrap(),
+            Vec::<u8>::new()
+        );
+    }
+
+    #[test]
+    fn small_pitch_and_data_are_errors() {
+        let data = [0; 16];
+
+        assert!(convert_to_rgba8(vk::Format::R8G8B8A8_UNORM, extent(2, 2), 4, &data).is_err());
+        assert!(convert_to_rgba8(vk::Format::R8G8B8A8_UNORM, extent(2, 3), 8, &data).is_err());
+        assert!(
+            convert_to_rgba8(vk::Format::R32G32B32A32_SFLOAT, extent(1, 2), 16, &data).is_err()
+        );
+    }
+
+    #[test]
+    fn unsupported_formats_are_errors() {
+        assert_eq!(texel_size(vk::Format::R16G16B16A16_SFLOAT), None);
+        assert!(
+            convert_to_rgba8(vk::Format::R16G16B16A16_SFLOAT, extent(1, 1), 8, &[0; 8]).is_err()
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use crate::synchronization::Synchronization;
+use ash::{vk, Device};
+
+//書き込みのアクセス、これを含む使い方の後に別の使い方をする場合はメモリを見えるようにする必要がある
+const WRITE_ACCESS: vk::AccessFlags2 = vk::AccessFlags2::from_raw(
+    vk::AccessFlags2::COLOR_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags2::SHADER_STORAGE_WRITE.as_raw()
+        | vk::AccessFlags2::TRANSFER_WRITE.as_raw(),
+);
+
+//パスが画像をどのステージでどうアクセスし、その間どのレイアウトにしておくか
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageUse {
+    pub stage: vk::PipelineStageFlags2,
+    pub access: vk::AccessFlags2,
+    pub layout: vk::ImageLayout,
+    //trueの場合は前の内容をクリアするか上書きするので、UNDEFINEDから遷移して良い
+    pub discard: bool,
+}
+
+impl ImageUse {
+    //クリアしてから描画するカラーアタッチメント
+    pub const COLOR_ATTACHMENT: Self = Self {
+        stage: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        access: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        discard: true,
+    };
+
+    //クリアしてから深度テストに使うデプスアタッチメント
+    pub const DEPTH_ATTACHMENT: Self = Self {
+        stage: vk::PipelineStageFlags2::from_raw(
+            vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS.as_raw()
+                | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS.as_raw(),
+        ),
+        access: vk::AccessFlags2::from_raw(
+            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ.as_raw()
+                | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw(),
+        ),
+        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        discard: true,
+    };
+
+    //フラグメントシェーダーでサンプリングする、カラーでもデプスでも同じレイアウトで良い
+    pub const FRAGMENT_SAMPLED: Self = Self {
+        stage: vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        access: vk::AccessFlags2::SHADER_SAMPLED_READ,
+        layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        discard: false,
+    };
+
+    //全てのパスの後に別の画像へblitする
+    pub const BLIT_SOURCE: Self = Self {
+        stage: vk::PipelineStageFlags2::BLIT,
+        access: vk::AccessFlags2::TRANSFER_READ,
+        layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        discard: false,
+    };
+
+    //presentはrender_finished_semaphoreを待つのでこの後のステージとアクセスは無い
+    pub const PRESENT: Self = Self {
+        stage: vk::PipelineStageFlags2::NONE,
+        access: vk::AccessFlags2::NONE,
+        layout: vk::ImageLayout::PRESENT_SRC_KHR,
+        discard: false,
+    };
+
+    //フレームの始めの状態、前の内容は使わずに前のフレームの最後のアクセスの後で使えるようにする
+    pub fn undefined(stage: vk::PipelineStageFlags2, access: vk::AccessFlags2) -> Self {
+        Self {
+            stage,
+            access,
+            layout: vk::ImageLayout::UNDEFINED,
+            discard: false,
+        }
+    }
+
+    fn writes(&self) -> bool {
+        self.discard || self.access.intersects(WRITE_ACCESS)
+    }
+}
+
+//RenderGraphに登録した画像
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageHandle(usize);
+
+//RenderGraph::compileが返す、実行する順番に並んだパス
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PassId(usize);
+
+struct GraphImage {
+    image: vk::Image,
+    subresource_range: vk::ImageSubresourceRange,
+    //直前のパスでの使われ方、最初はimport_imageで渡した状態
+    current: ImageUse,
+    //全てのパスの後に遷移させる状態
+    final_use: Option<ImageUse>,
+}
+
+struct GraphPass<P> {
+    pass: P,
+    uses: Vec<(ImageHandle, ImageUse)>,
+}
+
+//1フレーム分のパスと、それらが読み書きする画像を登録し、パスの順番とパスの間のバリアを決める
+//Pは呼び出し側がパスを見分けるための値で、パスの中身の記録は呼び出し側が行う
+//画像はフレームの外で作った物を登録するだけで、グラフが作成や破棄をすることはない
+pub struct RenderGraph<P> {
+    images: Vec<GraphImage>,
+    passes: Vec<GraphPass<P>>,
+    //render passを使う場合はsubpass dependencyとinitial_layout, final_layoutが同じ遷移を行うのでバリアを記録しない
+    insert_barriers: bool,
+}
+
+impl<P: Copy> RenderGraph<P> {
+    pub fn new(insert_barriers: bool) -> Self {
+        Self {
+            images: vec![],
+            passes: vec![],
+            insert_barriers,
+        }
+    }
+
+    //initialはこのフレームで最初に使うパスより前の状態
+    pub fn import_image(
+        &mut self,
+        image: vk::Image,
+        subresource_range: vk::ImageSubresourceRange,
+        initial: ImageUse,
+    ) -> ImageHandle {
+        self.images.push(GraphImage {
+            image,
+            subresource_range,
+            current: initial,
+            final_use: None,
+        });
+
+        ImageHandle(self.images.len() - 1)
+    }
+
+    //全てのパスの後にfinal_useへ遷移させる、swapchainの画像をpresentできるようにする場合など
+    pub fn set_final_use(&mut self, handle: ImageHandle, final_use: ImageUse) {
+        self.images[handle.0].final_use = Some(final_use);
+    }
+
+    pub fn add_pass(&mut self, pass: P, uses: &[(ImageHandle, ImageUse)]) {
+        self.passes.push(GraphPass {
+            pass,
+            uses: uses.to_vec(),
+        });
+    }
+
+    pub fn pass(&self, id: PassId) -> P {
+        self.passes[id.0].pass
+    }
+
+    //画像を書き込むパスがその画像を読むパスより先になるように並べる
+    //同じ画像を書き込むパス同士は登録した順番のままにし、順番に制約の無いパスも登録した順番を保つ
+    pub fn compile(&self) -> Vec<PassId> {
+        let pass_count = self.passes.len();
+        let mut dependents = vec![vec![]; pass_count];
+        let mut dependency_counts = vec![0; pass_count];
+
+        for image_index in 0..self.images.len() {
+            let uses = self
+                .passes
+                .iter()
+                .enumerate()
+                .flat_map(|(pass_index, pass)| {
+                    pass.uses
+                        .iter()
+                        .filter(move |(handle, _)| handle.0 == image_index)
+                        .map(move |(_, image_use)| (pass_index, image_use.writes()))
+                })
+                .collect::<Vec<_>>();
+
+            for (i, &(before, before_writes)) in uses.iter().enumerate() {
+                for &(after, after_writes) in &uses[i + 1..] {
+                    let edge = match (before_writes, after_writes) {
+                        (true, _) => Some((before, after)),
+                        //後から登録したパスが書き込む物を先に登録したパスが読む場合は、書き込みを先にする
+                        (false, true) => Some((after, before)),
+                        (false, false) => None,
+                    };
+
+                    if let Some((from, to)) = edge.filter(|(from, to)| from != to) {
+                        dependents[from].push(to);
+                        dependency_counts[to] += 1;
+                    }
+                }
+            }
+        }
+
+        let mut order = Vec::with_capacity(pass_count);
+        let mut scheduled = vec![false; pass_count];
+
+        while order.len() < pass_count {
+            let next = (0..pass_count)
+                .find(|&index| !scheduled[index] && dependency_counts[index] == 0)
+                .expect("Render graph has a cycle between passes");
+
+            scheduled[next] = true;
+            for &dependent in &dependents[next] {
+                dependency_counts[dependent] -= 1;
+            }
+
+            order.push(PassId(next));
+        }
+
+        order
+    }
+
+    //パスの中身を記録する前に呼び、パスで使う画像を前のパスの使い方から遷移させる
+    pub fn cmd_begin_pass(
+        &mut self,
+        device: &Device,
+        synchronization: &Synchronization,
+        command_buffer: vk::CommandBuffer,
+        id: PassId,
+    ) {
+        let uses = self.passes[id.0].uses.clone();
+
+        let image_barriers = uses
+            .into_iter()
+            .filter_map(|(handle, image_use)| self.transition(handle, image_use))
+            .collect::<Vec<_>>();
+
+        self.cmd_barriers(device, synchronization, command_buffer, &image_barriers);
+    }
+
+    //全てのパスを記録した後に呼び、set_final_useした画像を遷移させる
+    pub fn cmd_finish(
+        &mut self,
+        device: &Device,
+        synchronization: &Synchronization,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        let final_uses = self
+            .images
+            .iter()
+            .enumerate()
+            .filter_map(|(index, image)| Some((ImageHandle(index), image.final_use?)))
+            .collect::<Vec<_>>();
+
+        let image_barriers = final_uses
+            .into_iter()
+            .filter_map(|(handle, final_use)| self.transition(handle, final_use))
+            .collect::<Vec<_>>();
+
+        self.cmd_barriers(device, synchronization, command_buffer, &image_barriers);
+    }
+
+    //読み込み同士でレイアウトも変わらない場合はバリアが要らない
+    fn transition(
+        &mut self,
+        handle: ImageHandle,
+        next: ImageUse,
+    ) -> Option<vk::ImageMemoryBarrier2> {
+        let image = &mut self.images[handle.0];
+        let previous = image.current;
+
+        image.current = next;
+
+        if previous.layout == next.layout && !previous.writes() && !next.writes() {
+            return None;
+        }
+
+        let old_layout = if next.discard {
+            vk::ImageLayout::UNDEFINED
+        } else {
+            previous.layout
+        };
+
+        Some(
+            vk::ImageMemoryBarrier2::builder()
+                .src_stage_mask(previous.stage)
+                //読み込みは見えるようにする必要が無いので、前の書き込みだけを待つ
+                .src_access_mask(previous.access & WRITE_ACCESS)
+                .dst_stage_mask(next.stage)
+                .dst_access_mask(next.access)
+                .old_layout(old_layout)
+                .new_layout(next.layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image.image)
+                .subresource_range(image.subresource_range)
+                .build(),
+        )
+    }
+
+    fn cmd_barriers(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        command_buffer: vk::CommandBuffer,
+        image_barriers: &[vk::ImageMemoryBarrier2],
+    ) {
+        if !self.insert_barriers || image_barriers.is_empty() {
+            return;
+        }
+
+        synchronization.cmd_pipeline_barrier(device, command_buffer, &[], &[], image_barriers);
+    }
+}
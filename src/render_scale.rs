@@ -0,0 +1,460 @@
+use crate::buffer;
+use crate::depth_buffer::DepthBuffer;
+use crate::memory_budget::{self, MemoryCategory};
+use crate::synchronization::Synchronization;
+use crate::window_target;
+use ash::{vk, Device, Instance};
+use std::str::FromStr;
+
+//+/-キーで1回に変える倍率
+const SCALE_STEP: f32 = 0.25;
+const MIN_SCALE: f32 = 0.25;
+const MAX_SCALE: f32 = 2.0;
+
+//--render-scale-filterで選ぶ、swapchainの大きさに拡大縮小する時のフィルター
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScaleFilter {
+    Linear,
+    Nearest,
+}
+
+impl ScaleFilter {
+    fn to_vk(self) -> vk::Filter {
+        match self {
+            Self::Linear => vk::Filter::LINEAR,
+            Self::Nearest => vk::Filter::NEAREST,
+        }
+    }
+}
+
+impl FromStr for ScaleFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "linear" => Ok(Self::Linear),
+            "nearest" => Ok(Self::Nearest),
+            _ => Err(format!(
+                "Unknown scale filter '{}', expected linear or nearest",
+                s
+            )),
+        }
+    }
+}
+
+//swapchainの代わりに最後のパスが描画する画像
+pub struct ScaledTarget {
+    pub image: vk::Image,
+    memory: vk::DeviceMemory,
+    pub view: vk::ImageView,
+    //dynamic renderingの場合はnull
+    pub framebuffer: vk::Framebuffer,
+    pub extent: vk::Extent2D,
+}
+
+//swapchainの大きさにscaleを掛けた解像度で描画し、swapchainの画像へblitして拡大縮小する
+//描画する解像度がswapchainと同じ場合は画像を作らず、swapchainに直接描画する
+pub struct RenderScale {
+    scale: f32,
+    filter: ScaleFilter,
+    use_render_pass: bool,
+    //dynamic renderingの場合とtargetがNoneの場合はnull
+    //swapchainのrender passとフォーマットが同じなので、パイプラインはどちらのrender passでも使える
+    render_pass: vk::RenderPass,
+    //swapchainの大きさと倍率に依存するので作り直す
+    target: Option<ScaledTarget>,
+}
+
+impl RenderScale {
+    //画像はcreate_targetで作成する
+    pub fn new(scale: f32, filter: ScaleFilter, use_render_pass: bool) -> Self {
+        let clamped = scale.clamp(MIN_SCALE, MAX_SCALE);
+
+        if clamped != scale {
+            log::warn!(
+                "Render scale {} is out of range, using {} instead",
+                scale,
+                clamped
+            );
+        }
+
+        log::info!("Render scale: {} ({:?})", clamped, filter);
+
+        Self {
+            scale: clamped,
+            filter,
+            use_render_pass,
+            render_pass: vk::RenderPass::null(),
+            target: None,
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    //倍率を変えた後はswapchainと一緒にcreate_targetで作り直す
+    pub fn raise(&mut self) {
+        self.scale = (self.scale + SCALE_STEP).min(MAX_SCALE);
+    }
+
+    pub fn lower(&mut self) {
+        self.scale = (self.scale - SCALE_STEP).max(MIN_SCALE);
+    }
+
+    //swapchainの大きさにscaleを掛けた、シーンを描画する解像度
+    pub fn render_extent(&self, swap_chain_extent: vk::Extent2D) -> vk::Extent2D {
+        vk::Extent2D {
+            width: ((swap_chain_extent.width as f32 * self.scale).round() as u32).max(1),
+            height: ((swap_chain_extent.height as f32 * self.scale).round() as u32).max(1),
+        }
+    }
+
+    //Noneの場合はswapchainに直接描画する
+    pub fn target(&self) -> Option<&ScaledTarget> {
+        self.target.as_ref()
+    }
+
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    //depth_bufferはrender_extentと同じ大きさで作ってあること
+    pub fn create_target(
+        &mut self,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        format: vk::Format,
+        swap_chain_extent: vk::Extent2D,
+        depth_buffer: &DepthBuffer,
+    ) {
+        let extent = self.render_extent(swap_chain_extent);
+
+        if extent == swap_chain_extent {
+            return;
+        }
+
+        if self.use_render_pass {
+            self.render_pass = Self::create_render_pass(device, format, depth_buffer.format);
+        }
+
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            //描画した後にswapchainの画像へblitする
+            .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+
+        let image = unsafe { device.create_image(&image_info, None).unwrap() };
+
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
+
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(buffer::find_memory_type(
+                instance,
+                physical_device,
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ))
+            .build();
+
+        let memory = unsafe {
+            memory_budget::allocate_memory(device, &alloc_info, MemoryCategory::Attachment).unwrap()
+        };
+
+        unsafe { device.bind_image_memory(image, memory, 0).unwrap() };
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(Self::subresource_range())
+            .build();
+
+        let view = unsafe { device.create_image_view(&view_info, None).unwrap() };
+
+        let framebuffer = if self.render_pass == vk::RenderPass::null() {
+            vk::Framebuffer::null()
+        } else {
+            let attachments = [view, depth_buffer.view];
+
+            let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(self.render_pass)
+                .attachments(&attachments)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1)
+                .build();
+
+            unsafe { device.create_framebuffer(&framebuffer_info, None).unwrap() }
+        };
+
+        log::info!(
+            "Render resolution: {}x{} -> {}x{}",
+            extent.width,
+            extent.height,
+            swap_chain_extent.width,
+            swap_chain_extent.height
+        );
+
+        self.target = Some(ScaledTarget {
+            image,
+            memory,
+            view,
+            framebuffer,
+            extent,
+        });
+    }
+
+    //全てのパスの後に呼び、TRANSFER_SRC_OPTIMALになっているtargetをswapchainの画像へblitしてpresentできるようにする
+    //縦横比が変わらないように拡大縮小し、丸めで余った所は黒で塗る
+    pub fn cmd_blit(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        command_buffer: vk::CommandBuffer,
+        swap_chain_image: vk::Image,
+        swap_chain_extent: vk::Extent2D,
+    ) {
+        let target = self.target.as_ref().expect("Scaled target is not created");
+
+        //image_available_semaphoreはCOLOR_ATTACHMENT_OUTPUTで待っているので、そこからクリアに繋げる
+        let to_transfer_dst = Self::barrier(
+            swap_chain_image,
+            (
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags2::NONE,
+            ),
+            (
+                vk::PipelineStageFlags2::CLEAR,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+
+        synchronization.cmd_pipeline_barrier(device, command_buffer, &[], &[], &[to_transfer_dst]);
+
+        let black = vk::ClearColorValue {
+            float32: [0.0, 0.0, 0.0, 1.0],
+        };
+
+        unsafe {
+            device.cmd_clear_color_image(
+                command_buffer,
+                swap_chain_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &black,
+                &[Self::subresource_range()],
+            );
+        }
+
+        let clear_to_blit = Self::barrier(
+            swap_chain_image,
+            (
+                vk::PipelineStageFlags2::CLEAR,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+            (
+                vk::PipelineStageFlags2::BLIT,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+
+        synchronization.cmd_pipeline_barrier(device, command_buffer, &[], &[], &[clear_to_blit]);
+
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        let region = vk::ImageBlit::builder()
+            .src_subresource(subresource)
+            .src_offsets([
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: target.extent.width as i32,
+                    y: target.extent.height as i32,
+                    z: 1,
+                },
+            ])
+            .dst_subresource(subresource)
+            .dst_offsets(window_target::fit_offsets(target.extent, swap_chain_extent))
+            .build();
+
+        unsafe {
+            device.cmd_blit_image(
+                command_buffer,
+                target.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                swap_chain_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+                self.filter.to_vk(),
+            );
+        }
+
+        //presentはrender_finished_semaphoreで待つのでdstは何も指定しない
+        let to_present = Self::barrier(
+            swap_chain_image,
+            (
+                vk::PipelineStageFlags2::BLIT,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+            (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        );
+
+        synchronization.cmd_pipeline_barrier(device, command_buffer, &[], &[], &[to_present]);
+    }
+
+    //GPUが画像を使い終わってから呼ぶ
+    pub fn destroy_target(&mut self, device: &Device) {
+        unsafe {
+            if let Some(target) = self.target.take() {
+                if target.framebuffer != vk::Framebuffer::null() {
+                    device.destroy_framebuffer(target.framebuffer, None);
+                }
+                device.destroy_image_view(target.view, None);
+                device.destroy_image(target.image, None);
+                memory_budget::free_memory(device, target.memory);
+            }
+
+            if self.render_pass != vk::RenderPass::null() {
+                device.destroy_render_pass(self.render_pass, None);
+                self.render_pass = vk::RenderPass::null();
+            }
+        }
+    }
+
+    //swapchainのrender passとの違いはfinal_layoutと、描画の後にblitで読む依存関係だけ
+    fn create_render_pass(
+        device: &Device,
+        format: vk::Format,
+        depth_format: vk::Format,
+    ) -> vk::RenderPass {
+        let color_attachment = vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            //描画した後にswapchainの画像へblitする
+            .final_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .build();
+
+        let color_attachment_refs = [vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build()];
+
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)
+            .depth_stencil_attachment(&depth_attachment_ref)
+            .build();
+
+        let dependencies = [
+            //前のフレームのblitで読み終わってから書き込む
+            //デプスバッファは前のパスの書き込みが終わってからクリアする
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::TRANSFER
+                        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                )
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                )
+                .dst_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .build(),
+            //書き込みが終わってからblitで読む
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .build(),
+        ];
+
+        //MSAAはシーンをswapchainに直接描画する場合だけ使うので、ここのデプスバッファは1サンプル
+        let attachments = [
+            color_attachment,
+            DepthBuffer::attachment_description(depth_format, vk::SampleCountFlags::TYPE_1),
+        ];
+        let subpasses = [subpass];
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies)
+            .build();
+
+        unsafe { device.create_render_pass(&render_pass_info, None).unwrap() }
+    }
+
+    fn barrier(
+        image: vk::Image,
+        (src_stage, src_access): (vk::PipelineStageFlags2, vk::AccessFlags2),
+        (dst_stage, dst_access): (vk::PipelineStageFlags2, vk::AccessFlags2),
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    ) -> vk::ImageMemoryBarrier2 {
+        vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(src_stage)
+            .src_access_mask(src_access)
+            .dst_stage_mask(dst_stage)
+            .dst_access_mask(dst_access)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(Self::subresource_range())
+            .build()
+    }
+
+    fn subresource_range() -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build()
+    }
+}
@@ -1,8 +1,48 @@
+use crate::crash_diagnostics;
+use crate::queue_family::QueueFamilyIndices;
+use ash::extensions::ext::FullScreenExclusive;
+use ash::extensions::google::DisplayTiming;
 use ash::extensions::khr::Swapchain;
+use ash::{vk, Instance};
 use std::ffi::CStr;
 
 //使用を要求するデバイス拡張の名前一覧取得
-pub fn get_required_device_extensions() -> [&'static CStr; 1] {
+//is_device_suitableでの確認と論理デバイスの作成の両方でこれを使い、要求する拡張が食い違わないようにする
+pub fn get_required_device_extensions(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Vec<&'static CStr> {
     // presentation queueのサポートがされていればSwapchainのサポートもされていることになるがそれでも一応確認はしておいたほうが良い
-    [Swapchain::name()]
+    let mut extensions = vec![Swapchain::name()];
+
+    //MoltenVKのようなVulkanの一部だけを実装するデバイスはこの拡張を列挙し、その場合は必ず有効にしなければならない
+    if QueueFamilyIndices::is_device_extension_supported(
+        instance,
+        physical_device,
+        vk::KhrPortabilitySubsetFn::name(),
+    ) {
+        extensions.push(vk::KhrPortabilitySubsetFn::name());
+    }
+
+    extensions
+}
+
+//サポートされている場合のみ有効にするデバイス拡張の名前一覧取得
+pub fn get_optional_device_extensions() -> Vec<&'static CStr> {
+    //表示タイミングの指定とフィードバックの取得
+    //メモリヒープごとの予算と使用量の取得
+    //デバイスロストの原因の取得と、最後に通ったチェックポイントの取得
+    let mut extensions = vec![
+        DisplayTiming::name(),
+        vk::ExtMemoryBudgetFn::name(),
+        crash_diagnostics::device_fault_name(),
+        vk::NvDeviceDiagnosticCheckpointsFn::name(),
+    ];
+
+    //排他フルスクリーンはWindowsでのみ使用できる
+    if cfg!(target_os = "windows") {
+        extensions.push(FullScreenExclusive::name());
+    }
+
+    extensions
 }
@@ -0,0 +1,187 @@
+use crate::device_features::EnabledFeatures;
+use ash::{vk, Device, Instance};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+//テクスチャのサンプラーで異方性フィルタリングに使いたい値
+//デバイスの上限を超える場合は上限に丸める
+pub const DEFAULT_MAX_ANISOTROPY: f32 = 16.0;
+
+//VkSamplerCreateInfoのうちこのアプリで使う項目
+//SamplerCacheのキーになるのでf32はビット列で比較する
+#[derive(Clone, Copy, Debug)]
+pub struct SamplerDesc {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+    pub address_mode_w: vk::SamplerAddressMode,
+    //1.0以下の場合は異方性フィルタリングを使わない
+    pub max_anisotropy: f32,
+    //シャドウマップのような深度の比較をする場合はSome
+    pub compare_op: Option<vk::CompareOp>,
+    pub min_lod: f32,
+    pub max_lod: f32,
+}
+
+impl Default for SamplerDesc {
+    //テクスチャ用の標準的なサンプラー
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            address_mode_w: vk::SamplerAddressMode::REPEAT,
+            max_anisotropy: DEFAULT_MAX_ANISOTROPY,
+            compare_op: None,
+            min_lod: 0.0,
+            //全てのミップレベルを使う
+            max_lod: vk::LOD_CLAMP_NONE,
+        }
+    }
+}
+
+impl SamplerDesc {
+    #[allow(clippy::type_complexity)]
+    fn key(
+        &self,
+    ) -> (
+        vk::Filter,
+        vk::Filter,
+        vk::SamplerMipmapMode,
+        [vk::SamplerAddressMode; 3],
+        u32,
+        Option<vk::CompareOp>,
+        u32,
+        u32,
+    ) {
+        (
+            self.mag_filter,
+            self.min_filter,
+            self.mipmap_mode,
+            [
+                self.address_mode_u,
+                self.address_mode_v,
+                self.address_mode_w,
+            ],
+            self.max_anisotropy.to_bits(),
+            self.compare_op,
+            self.min_lod.to_bits(),
+            self.max_lod.to_bits(),
+        )
+    }
+}
+
+impl PartialEq for SamplerDesc {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for SamplerDesc {}
+
+impl Hash for SamplerDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+//同じSamplerDescのサンプラーを何度も作らないように使い回す
+//サンプラーの数にはmax_sampler_allocation_countの上限がある
+pub struct SamplerCache {
+    samplers: HashMap<SamplerDesc, vk::Sampler>,
+    //sampler_anisotropyが無効な場合はNone
+    max_sampler_anisotropy: Option<f32>,
+}
+
+impl SamplerCache {
+    //enabled_featuresは論理デバイスの作成時に有効にした機能
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        enabled_features: &EnabledFeatures,
+    ) -> Self {
+        let max_sampler_anisotropy = if enabled_features.sampler_anisotropy {
+            let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+            Some(properties.limits.max_sampler_anisotropy)
+        } else {
+            None
+        };
+
+        log::info!(
+            "Sampler anisotropy: {}",
+            match max_sampler_anisotropy {
+                Some(max) => format!("up to {}x", max),
+                None => "unsupported".to_string(),
+            }
+        );
+
+        Self {
+            samplers: HashMap::new(),
+            max_sampler_anisotropy,
+        }
+    }
+
+    //descのサンプラーを返す、無ければ作成する
+    //max_anisotropyはデバイスの上限に丸めてからキーにするので、丸めた結果が同じなら同じサンプラーになる
+    pub fn get(&mut self, device: &Device, desc: &SamplerDesc) -> vk::Sampler {
+        let desc = SamplerDesc {
+            max_anisotropy: self.clamp_anisotropy(desc.max_anisotropy),
+            ..*desc
+        };
+
+        *self
+            .samplers
+            .entry(desc)
+            .or_insert_with(|| Self::create_sampler(device, &desc))
+    }
+
+    //テクスチャに使う標準のサンプラー
+    //異方性フィルタリングが使えない場合は1.0になる
+    #[allow(dead_code)]
+    pub fn default_sampler(&mut self, device: &Device) -> vk::Sampler {
+        self.get(device, &SamplerDesc::default())
+    }
+
+    //sampler_anisotropyが無効なのにanisotropy_enableを立てるとvalidation errorになるので1.0にする
+    fn clamp_anisotropy(&self, max_anisotropy: f32) -> f32 {
+        match self.max_sampler_anisotropy {
+            Some(max) => max_anisotropy.clamp(1.0, max),
+            None => 1.0,
+        }
+    }
+
+    fn create_sampler(device: &Device, desc: &SamplerDesc) -> vk::Sampler {
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(desc.mag_filter)
+            .min_filter(desc.min_filter)
+            .mipmap_mode(desc.mipmap_mode)
+            .address_mode_u(desc.address_mode_u)
+            .address_mode_v(desc.address_mode_v)
+            .address_mode_w(desc.address_mode_w)
+            .mip_lod_bias(0.0)
+            .anisotropy_enable(desc.max_anisotropy > 1.0)
+            .max_anisotropy(desc.max_anisotropy)
+            .compare_enable(desc.compare_op.is_some())
+            .compare_op(desc.compare_op.unwrap_or(vk::CompareOp::ALWAYS))
+            .min_lod(desc.min_lod)
+            .max_lod(desc.max_lod)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            //trueの場合はテクスチャ座標が0からテクスチャの大きさの範囲になる
+            .unnormalized_coordinates(false)
+            .build();
+
+        unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            for sampler in self.samplers.values() {
+                device.destroy_sampler(*sampler, None);
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use crate::camera::{Camera, Projection};
+use crate::debug_view::DebugView;
+use crate::light_manager::Orbit;
+use crate::lighting::{Light, LightKind, LightingMode};
+use crate::tonemap::Tonemapper;
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+//ファイルの形式を変えた時に上げる
+const VERSION: u32 = 2;
+
+//F5で--sceneのファイルに書き出し、F9で読み込んで戻すシーンの状態
+//メッシュはファイルの中身ではなくパスで覚えておき、読み込む時に起動時のメッシュと比べる
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneState {
+    pub camera_position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub projection: Projection,
+    //正射影に切り替えた時の高さ、透視投影のままでも覚えておく
+    pub orthographic_height: f32,
+    pub fov_y: f32,
+    pub near: f32,
+    pub far: f32,
+    //--objで読み込んだメッシュ、無い場合は三角形や四角形
+    pub mesh: Option<PathBuf>,
+    pub model_rotation: f32,
+    pub material: MaterialState,
+    //LightManagerの順番、シェーダーに渡す順番と同じ
+    pub lights: Vec<LightState>,
+    //軌道を持つライトの位置を決めるLightManagerの時間
+    pub light_seconds: f32,
+    pub wireframe: bool,
+    pub show_normals: bool,
+    pub debug_view: DebugView,
+    pub vertex_pulling: bool,
+    pub ray_query_shadows: bool,
+    pub paused: bool,
+}
+
+//マテリアルはオブジェクトの番号でテクスチャを選ぶだけなので、全てのマテリアルで共有するシェーディングの値を持つ
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MaterialState {
+    pub lighting_mode: LightingMode,
+    pub normal_mapping: bool,
+    pub tonemapper: Tonemapper,
+    pub exposure: f32,
+    pub bloom_intensity: f32,
+}
+
+//シェーダーに渡すLightの代わりに、Light::pointやLight::spotに渡す値で書く
+//使わない値もkindによらず全て書くので、kindを書き換えるだけで別の種類のライトにできる
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LightState {
+    pub kind: LightKind,
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub range: f32,
+    //スポットライトの円錐、directionからのラジアン
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+    pub orbit: Option<Orbit>,
+}
+
+impl LightState {
+    pub fn new(light: &Light, orbit: Option<Orbit>) -> Self {
+        Self {
+            kind: light.light_kind(),
+            position: light.position.truncate(),
+            direction: light.direction.truncate(),
+            color: light.color.truncate(),
+            intensity: light.color.w,
+            range: light.position.w,
+            inner_angle: light.cos_inner.clamp(-1.0, 1.0).acos(),
+            outer_angle: light.cos_outer.clamp(-1.0, 1.0).acos(),
+            orbit,
+        }
+    }
+
+    //directionが0の場合はnormalizeできないので真下に向ける
+    pub fn light(&self) -> Light {
+        let direction = self.direction.try_normalize().unwrap_or(Vec3::NEG_Y);
+
+        match self.kind {
+            LightKind::Directional => Light::directional(direction, self.color, self.intensity),
+            LightKind::Point => Light::point(self.position, self.color, self.intensity, self.range),
+            LightKind::Spot => Light::spot(
+                self.position,
+                direction,
+                self.color,
+                self.intensity,
+                self.range,
+                self.inner_angle,
+                self.outer_angle,
+            ),
+        }
+    }
+}
+
+//versionはSceneStateの前に読み、形式が違うファイルはフィールドを読む前に弾く
+#[derive(Serialize, Deserialize)]
+struct SceneFile<S> {
+    version: u32,
+    #[serde(flatten)]
+    scene: S,
+}
+
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+impl SceneState {
+    pub fn apply_camera(&self, camera: &mut Camera) {
+        camera.position = self.camera_position;
+        camera.yaw = self.yaw;
+        camera.pitch = self.pitch;
+        camera.projection = self.projection;
+        camera.fov_y = self.fov_y;
+        camera.near = self.near;
+        camera.far = self.far;
+    }
+
+    //インデントを付けて書くので、2つのファイルをdiffで比べられる
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = self
+            .to_json()
+            .map_err(|error| format!("Failed to serialize the scene: {}", error))?;
+
+        fs::write(path, text)
+            .map_err(|error| format!("Failed to write {}: {}", path.display(), error))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|error| format!("Failed to read {}: {}", path.display(), error))?;
+
+        Self::from_json(&text)
+            .map_err(|error| format!("Invalid scene {}: {}", path.display(), error))
+    }
+
+    fn to_json(&self) -> serde_json::Result<String> {
+        let mut text = serde_json::to_string_pretty(&SceneFile {
+            version: VERSION,
+            scene: self,
+        })?;
+        text.push('\n');
+
+        Ok(text)
+    }
+
+    fn from_json(text: &str) -> Result<Self, String> {
+        let Version { version } = serde_json::from_str(text).map_err(|error| error.to_string())?;
+
+        if version != VERSION {
+            return Err(format!("unsupported version {}", version));
+        }
+
+        serde_json::from_str::<SceneFile<Self>>(text)
+            .map(|file| file.scene)
+            .map_err(|error| error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene() -> SceneState {
+        SceneState {
+            camera_position: Vec3::new(0.5, 1.25, -3.0),
+            yaw: 0.1,
+            pitch: -0.2,
+            projection: Projection::Orthographic { height: 2.5 },
+            orthographic_height: 2.5,
+            fov_y: 45.0,
+            near: 0.1,
+            far: 100.0,
+            mesh: Some(PathBuf::from("models/viking_room.obj")),
+            model_rotation: 1.0 / 3.0,
+            material: MaterialState {
+                lighting_mode: LightingMode::Diffuse,
+                normal_mapping: false,
+                tonemapper: Tonemapper::Reinhard,
+                exposure: -1.5,
+                bloom_intensity: 0.3,
+            },
+            lights: vec![LightState::new(
+                &Light::spot(
+                    Vec3::new(0.0, 3.0, 0.0),
+                    Vec3::NEG_Y,
+                    Vec3::ONE,
+                    6.0,
+                    5.0,
+                    0.25,
+                    0.5,
+                ),
+                Some(Orbit {
+                    center: Vec3::ZERO,
+                    radius: 1.5,
+                    speed: -0.6,
+                    phase: 0.7,
+                }),
+            )],
+            light_seconds: 12.5,
+            wireframe: true,
+            show_normals: false,
+            debug_view: DebugView::LightTiles,
+            vertex_pulling: false,
+            ray_query_shadows: true,
+            paused: true,
+        }
+    }
+
+    #[test]
+    fn a_saved_scene_loads_back_unchanged() {
+        let scene = scene();
+        let text = scene.to_json().unwrap();
+
+        assert!(text.contains("\"version\": 2"));
+        assert!(text.contains("\"type\": \"orthographic\""));
+        assert!(text.contains("\"debug_view\": \"light-tiles\""));
+        assert_eq!(SceneState::from_json(&text).unwrap(), scene);
+    }
+
+    #[test]
+    fn lights_keep_their_kind_and_cone() {
+        let saved = scene().lights[0];
+        let light = saved.light();
+
+        assert_eq!(saved.kind, LightKind::Spot);
+        assert_eq!(light.light_kind(), LightKind::Spot);
+        assert_eq!(light.position.w, 5.0);
+        assert!((light.cos_inner - 0.25f32.cos()).abs() < 1e-6);
+        assert!((light.cos_outer - 0.5f32.cos()).abs() < 1e-6);
+        assert_eq!(
+            LightState::new(&Light::point(Vec3::ONE, Vec3::X, 4.0, 3.0), None).kind,
+            LightKind::Point
+        );
+    }
+
+    #[test]
+    fn other_versions_are_rejected_before_the_fields() {
+        let error = SceneState::from_json("{\"version\": 1, \"yaw\": 0.0}").unwrap_err();
+
+        assert_eq!(error, "unsupported version 1");
+        assert!(SceneState::from_json("{\"version\": 2}").is_err());
+    }
+}
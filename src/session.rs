@@ -0,0 +1,237 @@
+use crate::input::{InputMap, InputSnapshot};
+use crate::json::{self, Json};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use winit::event::{MouseButton, VirtualKeyCode};
+
+//ファイルの形式を変えた時に上げる
+const VERSION: u32 = 1;
+
+//1フレーム分の記録、deltaはFrameClockが進めた時間
+#[derive(Clone, Debug)]
+pub struct RecordedFrame {
+    pub delta: Duration,
+    pub input: InputSnapshot,
+}
+
+//--record PATHで毎フレームの入力とフレーム時間を溜め、終了時にJSONで書き出す
+//InputMapに割り当てられていないキーは何もしないので記録しない
+pub struct SessionRecorder {
+    path: PathBuf,
+    //InputMap::keysと同じもの、snapshotから割り当てのないキーを除く
+    keys: Vec<VirtualKeyCode>,
+    frames: Vec<RecordedFrame>,
+}
+
+impl SessionRecorder {
+    pub fn new(path: PathBuf, input_map: &InputMap) -> Self {
+        log::info!("Recording input to {}", path.display());
+
+        Self {
+            path,
+            keys: input_map.keys().collect(),
+            frames: vec![],
+        }
+    }
+
+    //FrameClock::tickの後、InputState::end_frameの前に1回呼ぶ
+    pub fn record(&mut self, delta: Duration, mut input: InputSnapshot) {
+        for keys in [
+            &mut input.pressed,
+            &mut input.just_pressed,
+            &mut input.just_released,
+        ] {
+            keys.retain(|key| self.keys.contains(key));
+        }
+
+        self.frames.push(RecordedFrame { delta, input });
+    }
+
+    //1フレームを1行にするので、2つの記録をdiffで比べられる
+    pub fn save(&self) {
+        let mut json = format!("{{\n  \"version\": {},\n  \"frames\": [", VERSION);
+
+        for (index, frame) in self.frames.iter().enumerate() {
+            json.push_str(if index == 0 { "\n    " } else { ",\n    " });
+            json.push_str(&frame_to_json(frame));
+        }
+
+        json.push_str("\n  ]\n}\n");
+
+        match fs::write(&self.path, json) {
+            Ok(_) => log::info!(
+                "Recorded {} frames to {}",
+                self.frames.len(),
+                self.path.display()
+            ),
+            Err(error) => log::warn!(
+                "Failed to save the recording to {}: {}",
+                self.path.display(),
+                error
+            ),
+        }
+    }
+}
+
+//--replay PATHで記録したフレームを順番に返す
+//再生する側の速さに関わらず、1回のイベント処理で1フレーム分ずつ進む
+pub struct SessionPlayer {
+    frames: Vec<RecordedFrame>,
+    next: usize,
+}
+
+impl SessionPlayer {
+    //キーの名前はinput_mapに割り当てられたものから探す
+    pub fn load(path: &Path, input_map: &InputMap) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|error| format!("Failed to read {}: {}", path.display(), error))?;
+
+        let frames = parse_session(&text, &input_map.keys().collect::<Vec<_>>())
+            .map_err(|error| format!("Invalid recording {}: {}", path.display(), error))?;
+
+        log::info!("Replaying {} frames from {}", frames.len(), path.display());
+
+        Ok(Self { frames, next: 0 })
+    }
+
+    //最後まで再生した後はNone
+    pub fn next_frame(&mut self) -> Option<&RecordedFrame> {
+        let frame = self.frames.get(self.next)?;
+        self.next += 1;
+        Some(frame)
+    }
+}
+
+fn frame_to_json(frame: &RecordedFrame) -> String {
+    let mut fields = vec![format!("\"delta_ns\": {}", frame.delta.as_nanos())];
+
+    let key_fields = [
+        ("pressed", &frame.input.pressed),
+        ("just_pressed", &frame.input.just_pressed),
+        ("just_released", &frame.input.just_released),
+    ];
+    let button_fields = [
+        ("mouse_pressed", &frame.input.mouse_pressed),
+        ("mouse_just_pressed", &frame.input.mouse_just_pressed),
+        ("mouse_just_released", &frame.input.mouse_just_released),
+    ];
+
+    //HashSetから作ったので順番が毎回変わらないように名前で並べる
+    let names = |names: Vec<String>| {
+        let mut names = names
+            .into_iter()
+            .map(|name| format!("\"{}\"", name))
+            .collect::<Vec<_>>();
+        names.sort();
+        names.join(", ")
+    };
+
+    //空の配列と0の移動量は省き、読む時に補う
+    for (field, keys) in key_fields {
+        if !keys.is_empty() {
+            let keys = keys.iter().map(|key| format!("{:?}", key)).collect();
+            fields.push(format!("\"{}\": [{}]", field, names(keys)));
+        }
+    }
+
+    for (field, buttons) in button_fields {
+        if !buttons.is_empty() {
+            let buttons = buttons.iter().map(|&button| button_name(button)).collect();
+            fields.push(format!("\"{}\": [{}]", field, names(buttons)));
+        }
+    }
+
+    if frame.input.cursor_delta != (0.0, 0.0) {
+        //{:?}はf64を読み戻せる最短の桁数で書く
+        fields.push(format!(
+            "\"cursor_delta\": [{:?}, {:?}]",
+            frame.input.cursor_delta.0, frame.input.cursor_delta.1
+        ));
+    }
+
+    format!("{{{}}}", fields.join(", "))
+}
+
+fn button_name(button: MouseButton) -> String {
+    match button {
+        MouseButton::Left => "Left".to_owned(),
+        MouseButton::Right => "Right".to_owned(),
+        MouseButton::Middle => "Middle".to_owned(),
+        MouseButton::Other(index) => format!("Other{}", index),
+    }
+}
+
+fn parse_button(name: &str) -> Result<MouseButton, String> {
+    match name {
+        "Left" => Ok(MouseButton::Left),
+        "Right" => Ok(MouseButton::Right),
+        "Middle" => Ok(MouseButton::Middle),
+        _ => name
+            .strip_prefix("Other")
+            .and_then(|index| index.parse().ok())
+            .map(MouseButton::Other)
+            .ok_or_else(|| format!("unknown mouse button '{}'", name)),
+    }
+}
+
+fn parse_session(text: &str, keys: &[VirtualKeyCode]) -> Result<Vec<RecordedFrame>, String> {
+    let root = json::parse(text)?;
+
+    let version = root.field("version")?.as_number()?;
+    if version != VERSION as f64 {
+        return Err(format!("unsupported version {}", version));
+    }
+
+    root.field("frames")?
+        .as_array()?
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| {
+            parse_frame(frame, keys).map_err(|error| format!("frame {}: {}", index, error))
+        })
+        .collect()
+}
+
+fn parse_frame(frame: &Json, keys: &[VirtualKeyCode]) -> Result<RecordedFrame, String> {
+    let parse_keys = |field: &str| {
+        frame
+            .optional_array(field)?
+            .iter()
+            .map(|name| {
+                let name = name.as_str()?;
+                keys.iter()
+                    .copied()
+                    .find(|key| format!("{:?}", key) == name)
+                    .ok_or_else(|| format!("key '{}' is not bound to any action", name))
+            })
+            .collect::<Result<Vec<_>, String>>()
+    };
+
+    let parse_buttons = |field: &str| {
+        frame
+            .optional_array(field)?
+            .iter()
+            .map(|name| parse_button(name.as_str()?))
+            .collect::<Result<Vec<_>, String>>()
+    };
+
+    let cursor_delta = match frame.optional_array("cursor_delta")? {
+        [] => (0.0, 0.0),
+        [x, y] => (x.as_number()?, y.as_number()?),
+        _ => return Err("cursor_delta must have 2 values".to_owned()),
+    };
+
+    Ok(RecordedFrame {
+        delta: Duration::from_nanos(frame.field("delta_ns")?.as_number()? as u64),
+        input: InputSnapshot {
+            pressed: parse_keys("pressed")?,
+            just_pressed: parse_keys("just_pressed")?,
+            just_released: parse_keys("just_released")?,
+            mouse_pressed: parse_buttons("mouse_pressed")?,
+            mouse_just_pressed: parse_buttons("mouse_just_pressed")?,
+            mouse_just_released: parse_buttons("mouse_just_released")?,
+            cursor_delta,
+        },
+    })
+}
@@ -0,0 +1,533 @@
+use crate::buffer;
+use crate::depth_buffer::{DepthBuffer, DepthConvention};
+use crate::dynamic_rendering::DynamicRendering;
+use crate::lighting::LIGHT_DIRECTION;
+use crate::memory_budget::{self, MemoryCategory};
+use crate::mesh::{Mesh, Vertex};
+use crate::object_buffer::{ObjectBuffers, ObjectUniforms};
+use crate::one_time_commands::OneTimeCommands;
+use crate::synchronization::Synchronization;
+use ash::{vk, Device, Instance};
+use glam::{Mat4, Vec3, Vec4};
+
+//深度値だけを保持してフラグメントシェーダーでサンプリングするのでステンシルは要らない
+pub const FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+//--shadow-map-sizeを指定しない場合の一辺のテクセル数
+pub const DEFAULT_SIZE: u32 = 2048;
+
+//シャドウアクネを防ぐためにシャドウマップに書き込む深度値を奥にずらす
+//constantは深度値の最小単位の倍数、slopeはライトから見た面の傾きに掛かる
+pub const DEPTH_BIAS_CONSTANT: f32 = 1.25;
+pub const DEPTH_BIAS_SLOPE: f32 = 1.75;
+
+//ライトのview行列の視点を原点からこの距離だけ光の来る方向に離す
+const LIGHT_DISTANCE: f32 = 10.0;
+//正射影で覆う範囲の半分の大きさ、床の対角線が収まるようにする
+const LIGHT_EXTENT: f32 = 4.5;
+
+//影を受ける床の高さと一辺の長さ
+const GROUND_HEIGHT: f32 = -1.5;
+const GROUND_SIZE: f32 = 6.0;
+
+//シェーダー側のShadowConstantsと同じレイアウト
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct ShadowConstants {
+    pub light_view_proj: Mat4,
+}
+
+//ディレクショナルライトから見た深度値を書き込むシャドウマップ
+//全てのフレームで共有するので、前のフレームのサンプリングが終わるのを待ってから書き込む
+//set = 2にシャドウマップのimageと比較用のsamplerを割り当てる
+pub struct ShadowMap {
+    size: u32,
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+    //dynamic renderingの場合はnull
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+}
+
+impl ShadowMap {
+    //samplerはcompare_opを指定したもので、破棄はSamplerCacheに任せる
+    //sizeはデバイスの上限を超える場合は上限に丸める
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        size: u32,
+        sampler: vk::Sampler,
+        use_render_pass: bool,
+    ) -> Self {
+        let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+
+        let size = if size > limits.max_image_dimension2_d {
+            log::warn!(
+                "Shadow map size {} exceeds the device limit {}",
+                size,
+                limits.max_image_dimension2_d
+            );
+            limits.max_image_dimension2_d
+        } else {
+            size
+        };
+
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(FORMAT)
+            .extent(vk::Extent3D {
+                width: size,
+                height: size,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            //深度値を書き込んだ後にメインのパスでサンプリングする
+            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+
+        let image = unsafe { device.create_image(&image_info, None).unwrap() };
+
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
+
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(buffer::find_memory_type(
+                instance,
+                physical_device,
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ))
+            .build();
+
+        let memory = unsafe {
+            memory_budget::allocate_memory(device, &alloc_info, MemoryCategory::Attachment).unwrap()
+        };
+
+        unsafe { device.bind_image_memory(image, memory, 0).unwrap() };
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(FORMAT)
+            .subresource_range(Self::subresource_range())
+            .build();
+
+        let view = unsafe { device.create_image_view(&view_info, None).unwrap() };
+
+        let (render_pass, framebuffer) = if use_render_pass {
+            let render_pass = Self::create_render_pass(device);
+
+            let attachments = [view];
+
+            let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(render_pass)
+                .attachments(&attachments)
+                .width(size)
+                .height(size)
+                .layers(1)
+                .build();
+
+            let framebuffer =
+                unsafe { device.create_framebuffer(&framebuffer_info, None).unwrap() };
+
+            (render_pass, framebuffer)
+        } else {
+            (vk::RenderPass::null(), vk::Framebuffer::null())
+        };
+
+        //シェーダー側でimageとsamplerを別々に受け取る
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        };
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .build(),
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+
+        let set_layouts = [descriptor_set_layout];
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&set_layouts)
+            .build();
+
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] };
+
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()];
+
+        let sampler_info = [vk::DescriptorImageInfo::builder().sampler(sampler).build()];
+
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(&image_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .image_info(&sampler_info)
+                .build(),
+        ];
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        log::info!("Shadow map: {}x{} {:?}", size, size, FORMAT);
+
+        Self {
+            size,
+            image,
+            memory,
+            view,
+            render_pass,
+            framebuffer,
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_set,
+        }
+    }
+
+    //ライトの光が来る方向から原点を見る正射影
+    //glamのorthographic_rhも深度が0から1になる
+    //メインのパスと同じ行列でサンプリングするのでY軸は反転させなくて良い
+    pub fn light_view_proj() -> Mat4 {
+        let direction = Vec3::from(LIGHT_DIRECTION).normalize();
+
+        let view = Mat4::look_at_rh(-direction * LIGHT_DISTANCE, Vec3::ZERO, Vec3::Z);
+        let projection = Mat4::orthographic_rh(
+            -LIGHT_EXTENT,
+            LIGHT_EXTENT,
+            -LIGHT_EXTENT,
+            LIGHT_EXTENT,
+            0.1,
+            LIGHT_DISTANCE * 2.0,
+        );
+
+        projection * view
+    }
+
+    //PCFで隣のテクセルを引くためのuvの間隔
+    pub fn texel_size(&self) -> f32 {
+        1.0 / self.size as f32
+    }
+
+    //dynamic renderingの場合はnull
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    //パイプラインレイアウトのset = 2に使う
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    pub fn descriptor_set(&self) -> vk::DescriptorSet {
+        self.descriptor_set
+    }
+
+    pub fn viewport(&self) -> vk::Viewport {
+        vk::Viewport::builder()
+            .x(0.0)
+            .y(0.0)
+            .width(self.size as f32)
+            .height(self.size as f32)
+            .min_depth(0.0)
+            .max_depth(1.0)
+            .build()
+    }
+
+    pub fn scissor(&self) -> vk::Rect2D {
+        vk::Rect2D::builder()
+            .offset(vk::Offset2D::builder().x(0).y(0).build())
+            .extent(self.extent())
+            .build()
+    }
+
+    //シャドウマップへの描画を始める
+    //dynamic renderingの場合、前のフレームのメインのパスのサンプリングを待つバリアは呼び出し側のrender graphが記録する
+    pub fn cmd_begin(
+        &self,
+        device: &Device,
+        dynamic_rendering: Option<&DynamicRendering>,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        //シーンをReverseで描画する場合もライトの行列の深度値は逆にしない
+        let clear_values = [DepthBuffer::clear_value(DepthConvention::Standard)];
+
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::builder().x(0).y(0).build())
+            .extent(self.extent())
+            .build();
+
+        match dynamic_rendering {
+            Some(dynamic_rendering) => {
+                let depth_attachment = vk::RenderingAttachmentInfo::builder()
+                    .image_view(self.view)
+                    .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                    .load_op(vk::AttachmentLoadOp::CLEAR)
+                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .clear_value(clear_values[0])
+                    .build();
+
+                //カラーアタッチメントは使わない
+                let rendering_info = vk::RenderingInfo::builder()
+                    .render_area(render_area)
+                    .layer_count(1)
+                    .depth_attachment(&depth_attachment)
+                    .build();
+
+                dynamic_rendering.cmd_begin_rendering(device, command_buffer, &rendering_info);
+            }
+            None => unsafe {
+                let render_pass_info = vk::RenderPassBeginInfo::builder()
+                    .render_pass(self.render_pass)
+                    .framebuffer(self.framebuffer)
+                    .render_area(render_area)
+                    .clear_values(&clear_values)
+                    .build();
+
+                device.cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_info,
+                    vk::SubpassContents::INLINE,
+                );
+            },
+        }
+    }
+
+    //render passではfinal_layoutとsubpass dependencyでサンプリングできるようにする
+    //dynamic renderingではメインのパスの前にrender graphがバリアで遷移させる
+    pub fn cmd_end(
+        &self,
+        device: &Device,
+        dynamic_rendering: Option<&DynamicRendering>,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        match dynamic_rendering {
+            Some(dynamic_rendering) => dynamic_rendering.cmd_end_rendering(device, command_buffer),
+            None => unsafe { device.cmd_end_render_pass(command_buffer) },
+        }
+    }
+
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    fn extent(&self) -> vk::Extent2D {
+        vk::Extent2D {
+            width: self.size,
+            height: self.size,
+        }
+    }
+
+    pub fn subresource_range() -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::DEPTH)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build()
+    }
+
+    //デプスアタッチメントだけのrender pass
+    //パスが終わったらメインのパスでサンプリングできるレイアウトにする
+    fn create_render_pass(device: &Device) -> vk::RenderPass {
+        let attachments = [vk::AttachmentDescription::builder()
+            .format(FORMAT)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()];
+
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+
+        let subpasses = [vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .depth_stencil_attachment(&depth_attachment_ref)
+            .build()];
+
+        let dependencies = [
+            //前のフレームのメインのパスのサンプリングが終わってから書き込む
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_stage_mask(
+                    vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                )
+                .dst_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .build(),
+            //書き込みが終わってからメインのパスでサンプリングする
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build(),
+        ];
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies)
+            .build();
+
+        unsafe { device.create_render_pass(&render_pass_info, None).unwrap() }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            if self.render_pass != vk::RenderPass::null() {
+                device.destroy_framebuffer(self.framebuffer, None);
+                device.destroy_render_pass(self.render_pass, None);
+            }
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+            memory_budget::free_memory(device, self.memory);
+        }
+    }
+}
+
+//影を受ける床
+//シャドウマップには描画せず、メインのパスでだけ描画する
+pub struct Ground {
+    mesh: Mesh,
+    //ObjectBuffersのインデックス
+    object_index: usize,
+}
+
+impl Ground {
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        object_index: usize,
+    ) -> Self {
+        //Mesh::quadをX軸周りに倒した向きで、上から見て表になる
+        let half = GROUND_SIZE / 2.0;
+        let vertices =
+            [[-half, half], [half, half], [half, -half], [-half, -half]].map(|[x, y]| Vertex {
+                position: [x, GROUND_HEIGHT, -y],
+                color: [0.8, 0.8, 0.8],
+                normal: [0.0, 1.0, 0.0],
+                tex_coord: [0.0; 2],
+                tangent: Vertex::NO_TANGENT,
+            });
+
+        let mesh = Mesh::new(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            &vertices,
+            &[0, 1, 2, 2, 3, 0],
+        );
+
+        Self { mesh, object_index }
+    }
+
+    //頂点をワールド座標で作っているのでモデル行列は単位行列
+    pub fn write_object(&self, object_buffers: &ObjectBuffers, frame: usize) {
+        let object = ObjectUniforms {
+            model: Mat4::IDENTITY,
+            color: Vec4::ONE,
+        };
+
+        object_buffers.write(frame, self.object_index, &object);
+    }
+
+    //パイプラインとset = 0, 2は呼び出し側で紐づけておく
+    //メッシュを紐づけ直すので、この後に他のメッシュを描画する場合は紐づけ直す必要がある
+    pub fn cmd_draw(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        object_buffers: &ObjectBuffers,
+        frame: usize,
+    ) {
+        self.mesh.cmd_bind(device, command_buffer);
+        object_buffers.cmd_bind(
+            device,
+            command_buffer,
+            pipeline_layout,
+            frame,
+            self.object_index,
+        );
+
+        unsafe {
+            device.cmd_draw_indexed(command_buffer, self.mesh.index_count(), 1, 0, 0, 0);
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.mesh.destroy(device);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::buffer;
+use crate::descriptor_allocator::{DescriptorAllocator, DescriptorLayoutCache};
+use crate::memory_budget;
+use ash::{vk, Device, Instance};
+use glam::Mat4;
+use std::mem;
+
+//スキンメッシュの頂点ごとに影響するジョイントとその重み
+//mesh::Vertexとは別のbinding = 1の頂点バッファにし、main_vs_skinnedのlocation = 3と4で読む
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct SkinVertex {
+    //Skeleton::jointsの番号
+    pub joints: [u32; 4],
+    //合計が1になるように正規化してある、全て0の頂点はジョイントに動かされない
+    pub weights: [f32; 4],
+}
+
+impl SkinVertex {
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(1)
+            .stride(mem::size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+        [
+            vk::VertexInputAttributeDescription::builder()
+                .binding(1)
+                .location(3)
+                .format(vk::Format::R32G32B32A32_UINT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(1)
+                .location(4)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(mem::size_of::<[u32; 4]>() as u32)
+                .build(),
+        ]
+    }
+}
+
+//--gltfのスキンメッシュをmain_vs_skinnedで動かすための頂点バッファとジョイントの行列
+//ジョイントの行列はフレームごとのstorage bufferに書き込み、メインのパイプラインのset = 2に紐づける
+pub struct Skinning {
+    vertex_buffer: vk::Buffer,
+    vertex_memory: vk::DeviceMemory,
+    //HOST_COHERENTなメモリなのでマップしたままにしておいて毎フレーム書き込む
+    palettes: Vec<(vk::Buffer, vk::DeviceMemory, *mut Mat4)>,
+    joint_count: usize,
+    //DescriptorLayoutCacheが持つので破棄しない
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_allocator: DescriptorAllocator,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+}
+
+impl Skinning {
+    //ジョイントの行列がmax_storage_buffer_rangeに収まらない場合はErrを返す
+    //Descriptor Set Layoutの破棄はDescriptorLayoutCacheに任せる
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        frames_in_flight: u32,
+        skin_vertices: &[SkinVertex],
+        joint_count: usize,
+    ) -> Result<Self, String> {
+        let max_range = unsafe {
+            instance
+                .get_physical_device_properties(physical_device)
+                .limits
+                .max_storage_buffer_range
+        };
+
+        if joint_count == 0 {
+            return Err("The skin has no joints".to_owned());
+        }
+
+        let palette_size = (joint_count * mem::size_of::<Mat4>()) as vk::DeviceSize;
+
+        if palette_size > max_range as vk::DeviceSize {
+            return Err(format!(
+                "{} joints need {} bytes, exceeding max_storage_buffer_range ({})",
+                joint_count, palette_size, max_range
+            ));
+        }
+
+        let (vertex_buffer, vertex_memory) = buffer::create_host_buffer_with_data(
+            instance,
+            physical_device,
+            device,
+            skin_vertices,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        );
+
+        let palettes = (0..frames_in_flight)
+            .map(|_| {
+                let (buffer, memory) = buffer::create_buffer(
+                    instance,
+                    physical_device,
+                    device,
+                    palette_size,
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                );
+
+                let pointer = unsafe {
+                    device
+                        .map_memory(memory, 0, palette_size, vk::MemoryMapFlags::empty())
+                        .unwrap() as *mut Mat4
+                };
+
+                //書き込む前のフレームでも頂点が潰れないように初期の姿勢にしておく
+                for joint in 0..joint_count {
+                    unsafe { pointer.add(joint).write(Mat4::IDENTITY) };
+                }
+
+                (buffer, memory, pointer)
+            })
+            .collect::<Vec<_>>();
+
+        let bindings = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .build()];
+
+        let descriptor_set_layout = descriptor_layout_cache.get(device, &bindings, &[]);
+
+        let mut descriptor_allocator = DescriptorAllocator::new();
+        let descriptor_sets = palettes
+            .iter()
+            .map(|_| descriptor_allocator.allocate(device, descriptor_set_layout, None))
+            .collect::<Vec<_>>();
+
+        let buffer_infos = palettes
+            .iter()
+            .map(|&(buffer, _, _)| {
+                [vk::DescriptorBufferInfo::builder()
+                    .buffer(buffer)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .build()]
+            })
+            .collect::<Vec<_>>();
+
+        let descriptor_writes = descriptor_sets
+            .iter()
+            .zip(&buffer_infos)
+            .map(|(&descriptor_set, buffer_info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(buffer_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        log::info!(
+            "Skinning: {} vertices, {} joints ({} bytes per frame, max_storage_buffer_range: {})",
+            skin_vertices.len(),
+            joint_count,
+            palette_size,
+            max_range
+        );
+
+        Ok(Self {
+            vertex_buffer,
+            vertex_memory,
+            palettes,
+            joint_count,
+            descriptor_set_layout,
+            descriptor_allocator,
+            descriptor_sets,
+        })
+    }
+
+    //パイプラインレイアウトのset = 2に使う
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    //frame_timelineを待ってこのフレームのバッファをGPUが読み終えてから呼ぶ
+    //paletteがjoint_countより長い分は書き込まない
+    pub fn write_palette(&self, frame: usize, palette: &[Mat4]) {
+        let (_, _, pointer) = self.palettes[frame];
+        let count = palette.len().min(self.joint_count);
+
+        unsafe { pointer.copy_from_nonoverlapping(palette.as_ptr(), count) };
+    }
+
+    //binding = 1にSkinVertexを、set = 2にこのフレームのジョイントの行列を紐づける
+    pub fn cmd_bind(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        frame: usize,
+    ) {
+        unsafe {
+            device.cmd_bind_vertex_buffers(command_buffer, 1, &[self.vertex_buffer], &[0]);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                2,
+                &[self.descriptor_sets[frame]],
+                &[],
+            );
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.descriptor_allocator.destroy(device);
+
+        unsafe {
+            for &(buffer, memory, _) in &self.palettes {
+                device.destroy_buffer(buffer, None);
+                memory_budget::free_memory(device, memory);
+            }
+
+            device.destroy_buffer(self.vertex_buffer, None);
+            memory_budget::free_memory(device, self.vertex_memory);
+        }
+    }
+}
@@ -0,0 +1,516 @@
+use crate::buffer;
+use crate::memory_budget::{self, MemoryCategory};
+use crate::one_time_commands::OneTimeCommands;
+use crate::synchronization::Synchronization;
+use ash::{vk, Device, Instance};
+use std::fs;
+use std::path::Path;
+
+//main_vs_skyboxがvertex_indexから作る立方体のTRIANGLE_STRIPの頂点数
+const CUBE_VERTEX_COUNT: u32 = 14;
+
+//KTX 1.1のファイル先頭の識別子
+const KTX_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x31, 0x31, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+//KTXのヘッダーの大きさ、識別子の後に13個のu32が続く
+const KTX_HEADER_LENGTH: usize = 12 + 13 * 4;
+
+//GL_UNSIGNED_BYTE, GL_RGBA
+const GL_UNSIGNED_BYTE: u32 = 0x1401;
+const GL_RGBA: u32 = 0x1908;
+//GL_RGBA8, GL_SRGB8_ALPHA8
+const GL_RGBA8: u32 = 0x8058;
+const GL_SRGB8_ALPHA8: u32 = 0x8C43;
+
+//キューブマップ6面分のRGBA8の画素
+//面は+X, -X, +Y, -Y, +Z, -Zの順番で、array layerの順番と一致する
+pub struct CubemapFaces {
+    //1面の1辺のピクセル数
+    size: u32,
+    format: vk::Format,
+    pixels: Vec<u8>,
+}
+
+impl CubemapFaces {
+    //上が青く下が地面の色になる空を生成する
+    //ワールド座標ではY軸が上向き
+    pub fn generate(size: u32) -> Self {
+        let mut pixels = Vec::with_capacity((size * size * 4 * 6) as usize);
+
+        for face in 0..6 {
+            for y in 0..size {
+                for x in 0..size {
+                    //ピクセルの中心をキューブマップの面上の-1.0から1.0の座標にする
+                    let s = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                    let t = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+
+                    let [dx, dy, dz] = Self::face_direction(face, s, t);
+                    let length = (dx * dx + dy * dy + dz * dz).sqrt();
+                    pixels.extend_from_slice(&Self::sky_color(dy / length));
+                }
+            }
+        }
+
+        Self {
+            size,
+            format: vk::Format::R8G8B8A8_SRGB,
+            pixels,
+        }
+    }
+
+    //非圧縮のRGBA8でミップマップの無いキューブマップのKTX 1.1ファイルを読む
+    //2つ目以降のミップレベルは無視する
+    pub fn load_ktx(path: &Path) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|error| error.to_string())?;
+
+        if data.len() < KTX_HEADER_LENGTH || data[..12] != KTX_IDENTIFIER {
+            return Err("not a KTX 1.1 file".to_string());
+        }
+
+        let read_u32 = |offset: usize| -> Result<u32, String> {
+            data.get(offset..offset + 4)
+                .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .ok_or_else(|| "unexpected end of file".to_string())
+        };
+
+        //ヘッダーの値は識別子の後に順番に並んでいる
+        let header = |index: usize| read_u32(12 + index * 4);
+
+        if header(0)? != 0x04030201 {
+            return Err("big endian KTX files are not supported".to_string());
+        }
+
+        let (gl_type, gl_format, gl_internal_format) = (header(1)?, header(3)?, header(4)?);
+        let (width, height, depth) = (header(6)?, header(7)?, header(8)?);
+        let (array_elements, faces) = (header(9)?, header(10)?);
+        let key_value_length = header(12)? as usize;
+
+        //色のテクスチャは画像編集ツールで作られたsRGBの値なので、GL_RGBA8でもSRGBフォーマットで読み込む
+        //UNORMで読み込むとサンプリングした値がリニアにならず、ライティングの計算がずれる
+        let format = match (gl_type, gl_format, gl_internal_format) {
+            (GL_UNSIGNED_BYTE, GL_RGBA, GL_RGBA8 | GL_SRGB8_ALPHA8) => vk::Format::R8G8B8A8_SRGB,
+            _ => return Err("only uncompressed RGBA8 is supported".to_string()),
+        };
+
+        if faces != 6 || array_elements != 0 || depth != 0 || width != height || width == 0 {
+            return Err("not a square cubemap".to_string());
+        }
+
+        //ミップレベル0のimageSizeは1面分の大きさ
+        let image_size_offset = KTX_HEADER_LENGTH + key_value_length;
+        let face_size = read_u32(image_size_offset)? as usize;
+
+        if face_size != (width * height * 4) as usize {
+            return Err("unexpected image size".to_string());
+        }
+
+        //RGBA8の面は4バイトの倍数なので面の間にパディングは入らない
+        let pixels_offset = image_size_offset + 4;
+        let pixels = data
+            .get(pixels_offset..pixels_offset + face_size * 6)
+            .ok_or_else(|| "unexpected end of file".to_string())?
+            .to_vec();
+
+        Ok(Self {
+            size: width,
+            format,
+            pixels,
+        })
+    }
+
+    //キューブマップの面faceの座標(s, t)が指す方向
+    //Vulkanの仕様のキューブマップの面の選択の逆変換
+    fn face_direction(face: u32, s: f32, t: f32) -> [f32; 3] {
+        match face {
+            0 => [1.0, -t, -s],
+            1 => [-1.0, -t, s],
+            2 => [s, 1.0, t],
+            3 => [s, -1.0, -t],
+            4 => [s, -t, 1.0],
+            _ => [-s, -t, -1.0],
+        }
+    }
+
+    //heightは正規化した方向のY成分で、1.0が真上
+    fn sky_color(height: f32) -> [u8; 4] {
+        let lerp = |from: [f32; 3], to: [f32; 3], t: f32| -> [f32; 3] {
+            [
+                from[0] + (to[0] - from[0]) * t,
+                from[1] + (to[1] - from[1]) * t,
+                from[2] + (to[2] - from[2]) * t,
+            ]
+        };
+
+        let color = if height >= 0.0 {
+            //地平線から天頂に向かって濃い青にする
+            lerp([0.85, 0.9, 1.0], [0.2, 0.45, 0.9], height)
+        } else {
+            lerp([0.45, 0.4, 0.35], [0.2, 0.18, 0.15], -height)
+        };
+
+        [
+            (color[0] * 255.0) as u8,
+            (color[1] * 255.0) as u8,
+            (color[2] * 255.0) as u8,
+            255,
+        ]
+    }
+}
+
+//6つのarray layerを持つCUBE_COMPATIBLEなimageとCUBEのimage view
+struct Cubemap {
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+}
+
+impl Cubemap {
+    fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        faces: &CubemapFaces,
+    ) -> Self {
+        let image_info = vk::ImageCreateInfo::builder()
+            //CUBE_COMPATIBLEを付けないとCUBEのimage viewを作れない
+            .flags(vk::ImageCreateFlags::CUBE_COMPATIBLE)
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(faces.format)
+            .extent(vk::Extent3D {
+                width: faces.size,
+                height: faces.size,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(6)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+
+        let image = unsafe { device.create_image(&image_info, None).unwrap() };
+
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
+
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(buffer::find_memory_type(
+                instance,
+                physical_device,
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ))
+            .build();
+
+        let memory = unsafe {
+            memory_budget::allocate_memory(device, &alloc_info, MemoryCategory::Texture).unwrap()
+        };
+
+        unsafe { device.bind_image_memory(image, memory, 0).unwrap() };
+
+        Self::upload(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            image,
+            faces,
+        );
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::CUBE)
+            .format(faces.format)
+            .subresource_range(Self::subresource_range())
+            .build();
+
+        let view = unsafe { device.create_image_view(&view_info, None).unwrap() };
+
+        Self {
+            image,
+            memory,
+            view,
+        }
+    }
+
+    //ステージングバッファから6面をまとめてコピーし、シェーダーから読めるレイアウトにする
+    fn upload(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        image: vk::Image,
+        faces: &CubemapFaces,
+    ) {
+        let (staging_buffer, staging_memory) = buffer::create_host_buffer_with_data(
+            instance,
+            physical_device,
+            device,
+            &faces.pixels,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+        );
+
+        let to_transfer_dst = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::NONE)
+            .src_access_mask(vk::AccessFlags2::NONE)
+            .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(Self::subresource_range())
+            .build();
+
+        //array layerはバッファ上で面ごとに詰めて並んでいるので1つのリージョンで6面をコピーできる
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            //0の場合はimage_extentに詰まっているとみなされる
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(6)
+                    .build(),
+            )
+            .image_offset(vk::Offset3D::default())
+            .image_extent(vk::Extent3D {
+                width: faces.size,
+                height: faces.size,
+                depth: 1,
+            })
+            .build();
+
+        let to_shader_read = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::COPY)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(Self::subresource_range())
+            .build();
+
+        one_time_commands
+            .run(device, synchronization, |command_buffer| unsafe {
+                synchronization.cmd_pipeline_barrier(
+                    device,
+                    command_buffer,
+                    &[],
+                    &[],
+                    &[to_transfer_dst],
+                );
+                device.cmd_copy_buffer_to_image(
+                    command_buffer,
+                    staging_buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[region],
+                );
+                synchronization.cmd_pipeline_barrier(
+                    device,
+                    command_buffer,
+                    &[],
+                    &[],
+                    &[to_shader_read],
+                );
+            })
+            .expect("Failed to upload cubemap");
+
+        unsafe {
+            device.destroy_buffer(staging_buffer, None);
+            memory_budget::free_memory(device, staging_memory);
+        }
+    }
+
+    fn subresource_range() -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(6)
+            .build()
+    }
+
+    fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+            memory_budget::free_memory(device, self.memory);
+        }
+    }
+}
+
+//カメラの向きだけを使ってキューブマップを背景として描画する
+//set = 0はUniform Buffer、set = 1にキューブマップのimageとsamplerを割り当てる
+pub struct Skybox {
+    cubemap: Cubemap,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+}
+
+impl Skybox {
+    //samplerの破棄はSamplerCacheに任せる
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        faces: &CubemapFaces,
+        sampler: vk::Sampler,
+    ) -> Self {
+        let cubemap = Cubemap::new(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            faces,
+        );
+
+        //シェーダー側でimageとsamplerを別々に受け取る
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        };
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .build(),
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+
+        let set_layouts = [descriptor_set_layout];
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&set_layouts)
+            .build();
+
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] };
+
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .image_view(cubemap.view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()];
+
+        let sampler_info = [vk::DescriptorImageInfo::builder().sampler(sampler).build()];
+
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(&image_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .image_info(&sampler_info)
+                .build(),
+        ];
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        log::info!(
+            "Skybox: {}x{} cubemap ({} bytes)",
+            faces.size,
+            faces.size,
+            faces.pixels.len()
+        );
+
+        Self {
+            cubemap,
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_set,
+        }
+    }
+
+    //パイプラインレイアウトのset = 1に使う
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    //viewportとscissorは呼び出し側で設定しておく
+    pub fn cmd_draw(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        (pipeline, pipeline_layout): (vk::Pipeline, vk::PipelineLayout),
+        uniform_descriptor_set: vk::DescriptorSet,
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &[uniform_descriptor_set, self.descriptor_set],
+                &[],
+            );
+            //頂点はシェーダー側でvertex_indexから作るので頂点バッファは使わない
+            device.cmd_draw(command_buffer, CUBE_VERTEX_COUNT, 1, 0, 0);
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+        self.cubemap.destroy(device);
+    }
+}
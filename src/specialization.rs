@@ -0,0 +1,158 @@
+use ash::vk;
+
+//SpecializationInfoのデータに詰められる値
+//SPIR-Vのboolの特殊化定数はVkBool32として4バイトで渡す
+pub trait SpecConstantValue {
+    fn to_bytes(self) -> [u8; 4];
+}
+
+impl SpecConstantValue for u32 {
+    fn to_bytes(self) -> [u8; 4] {
+        self.to_ne_bytes()
+    }
+}
+
+impl SpecConstantValue for i32 {
+    fn to_bytes(self) -> [u8; 4] {
+        self.to_ne_bytes()
+    }
+}
+
+impl SpecConstantValue for f32 {
+    fn to_bytes(self) -> [u8; 4] {
+        self.to_ne_bytes()
+    }
+}
+
+impl SpecConstantValue for bool {
+    fn to_bytes(self) -> [u8; 4] {
+        (if self { vk::TRUE } else { vk::FALSE }).to_ne_bytes()
+    }
+}
+
+//シェーダーの特殊化定数のidと値を並べ、SpecializationMapEntryのoffsetとsizeを自動で決める
+//SpecConstants::new().set(0, 4u32).set(1, true)のように使う
+#[derive(Clone, Debug, Default)]
+pub struct SpecConstants {
+    entries: Vec<vk::SpecializationMapEntry>,
+    //entriesのoffsetから値が詰めて並ぶ
+    data: Vec<u8>,
+}
+
+impl SpecConstants {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //同じidを2回設定した場合は後の値で上書きする
+    pub fn set<T: SpecConstantValue>(mut self, constant_id: u32, value: T) -> Self {
+        let bytes = value.to_bytes();
+
+        match self
+            .entries
+            .iter()
+            .find(|entry| entry.constant_id == constant_id)
+        {
+            Some(entry) => {
+                let offset = entry.offset as usize;
+                self.data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+            }
+            None => {
+                self.entries.push(vk::SpecializationMapEntry {
+                    constant_id,
+                    offset: self.data.len() as u32,
+                    size: bytes.len(),
+                });
+                self.data.extend_from_slice(&bytes);
+            }
+        }
+
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    //返り値はselfのentriesとdataを指すので、パイプラインの作成が終わるまでselfを残しておく
+    pub fn info(&self) -> vk::SpecializationInfo {
+        vk::SpecializationInfo::builder()
+            .map_entries(&self.entries)
+            .data(&self.data)
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(constants: &SpecConstants, constant_id: u32) -> (u32, usize) {
+        let entry = constants
+            .entries
+            .iter()
+            .find(|entry| entry.constant_id == constant_id)
+            .unwrap();
+
+        (entry.offset, entry.size)
+    }
+
+    fn value(constants: &SpecConstants, constant_id: u32) -> [u8; 4] {
+        let (offset, size) = entry(constants, constant_id);
+        let offset = offset as usize;
+
+        constants.data[offset..offset + size].try_into().unwrap()
+    }
+
+    #[test]
+    fn entries_are_packed_in_set_order() {
+        let constants = SpecConstants::new()
+            .set(3, 7u32)
+            .set(0, -2i32)
+            .set(9, 0.5f32);
+
+        assert_eq!(entry(&constants, 3), (0, 4));
+        assert_eq!(entry(&constants, 0), (4, 4));
+        assert_eq!(entry(&constants, 9), (8, 4));
+        assert_eq!(constants.data.len(), 12);
+
+        assert_eq!(value(&constants, 3), 7u32.to_ne_bytes());
+        assert_eq!(value(&constants, 0), (-2i32).to_ne_bytes());
+        assert_eq!(value(&constants, 9), 0.5f32.to_ne_bytes());
+    }
+
+    #[test]
+    fn bool_is_a_four_byte_vk_bool32() {
+        let constants = SpecConstants::new().set(0, true).set(1, false);
+
+        assert_eq!(entry(&constants, 0), (0, 4));
+        assert_eq!(entry(&constants, 1), (4, 4));
+        assert_eq!(value(&constants, 0), vk::TRUE.to_ne_bytes());
+        assert_eq!(value(&constants, 1), vk::FALSE.to_ne_bytes());
+    }
+
+    #[test]
+    fn setting_an_existing_id_overwrites_in_place() {
+        let constants = SpecConstants::new().set(0, 1u32).set(1, 2u32).set(0, 5u32);
+
+        assert_eq!(constants.entries.len(), 2);
+        assert_eq!(constants.data.len(), 8);
+        assert_eq!(entry(&constants, 0), (0, 4));
+        assert_eq!(value(&constants, 0), 5u32.to_ne_bytes());
+        assert_eq!(value(&constants, 1), 2u32.to_ne_bytes());
+    }
+
+    #[test]
+    fn info_points_at_entries_and_data() {
+        let empty = SpecConstants::new();
+        assert!(empty.is_empty());
+
+        let constants = SpecConstants::new().set(0, 1u32).set(1, true);
+        let info = constants.info();
+
+        assert!(!constants.is_empty());
+        assert_eq!(info.map_entry_count, 2);
+        assert_eq!(info.data_size, 8);
+        assert_eq!(info.p_data, constants.data.as_ptr().cast());
+    }
+}
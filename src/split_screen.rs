@@ -0,0 +1,110 @@
+use crate::camera::Camera;
+use crate::uniform_buffer::UniformBuffers;
+use ash::{vk, Device, Instance};
+use glam::Vec3;
+
+//右半分のカメラが原点の周りを回る軌道
+const ORBIT_RADIUS: f32 = 3.0;
+const ORBIT_HEIGHT: f32 = 1.0;
+//1秒あたりに回る角度(ラジアン)
+const ORBIT_SPEED: f32 = 0.5;
+
+//--split-screenで画面を左右に分け、左に操作するカメラ、右に自動で回るカメラから見たシーンを描画する
+//同じレンダーパスの中でviewportとscissorを切り替えて2回描画するので、深度バッファやMSAAの画像は2つで共有する
+//1回の描画で複数のviewportを使うわけではないのでmulti_viewportの機能は要らない
+pub struct SplitScreen {
+    //右半分のカメラのUniform Buffer、レイアウトはメインのものと同じ
+    uniform_buffers: UniformBuffers,
+    camera: Camera,
+}
+
+impl SplitScreen {
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        frames_in_flight: u32,
+        max_lights: u32,
+        //メインのUniformBuffersと同じDescriptor Set Layoutにするので、同じステージを渡す
+        extra_stages: vk::ShaderStageFlags,
+    ) -> Self {
+        log::info!(
+            "Split screen: the right camera orbits the origin at radius {}",
+            ORBIT_RADIUS
+        );
+
+        Self {
+            uniform_buffers: UniformBuffers::new(
+                instance,
+                physical_device,
+                device,
+                frames_in_flight,
+                max_lights,
+                extra_stages,
+            ),
+            camera: Camera::new(Vec3::new(0.0, ORBIT_HEIGHT, ORBIT_RADIUS)),
+        }
+    }
+
+    //elapsed_seconds秒での軌道上の位置に動かして原点を向かせる
+    //射影はmainのカメラに合わせる
+    pub fn update_camera(&mut self, main: &Camera, elapsed_seconds: f32) {
+        let angle = elapsed_seconds * ORBIT_SPEED;
+        let position = Vec3::new(
+            angle.sin() * ORBIT_RADIUS,
+            ORBIT_HEIGHT,
+            angle.cos() * ORBIT_RADIUS,
+        );
+
+        self.camera.look_at(position, Vec3::ZERO);
+        self.camera.projection = main.projection;
+        self.camera.fov_y = main.fov_y;
+        self.camera.near = main.near;
+        self.camera.far = main.far;
+        self.camera.depth = main.depth;
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn uniform_buffers(&self) -> &UniformBuffers {
+        &self.uniform_buffers
+    }
+
+    //extentを左右の半分に分けたviewportとscissor、0番目が左
+    //幅が奇数の場合は右の方が1ピクセル広い
+    pub fn viewports(extent: vk::Extent2D) -> [(vk::Viewport, vk::Rect2D); 2] {
+        let left_width = extent.width / 2;
+
+        [(0, left_width), (left_width, extent.width - left_width)].map(|(x, width)| {
+            let viewport = vk::Viewport::builder()
+                .x(x as f32)
+                .y(0.0)
+                .width(width as f32)
+                .height(extent.height as f32)
+                .min_depth(0.0)
+                .max_depth(1.0)
+                .build();
+
+            let scissor = vk::Rect2D::builder()
+                .offset(vk::Offset2D { x: x as i32, y: 0 })
+                .extent(vk::Extent2D {
+                    width,
+                    height: extent.height,
+                })
+                .build();
+
+            (viewport, scissor)
+        })
+    }
+
+    //左右の半分のアスペクト比
+    pub fn aspect_ratio(extent: vk::Extent2D) -> f32 {
+        (extent.width / 2).max(1) as f32 / extent.height as f32
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.uniform_buffers.destroy(device);
+    }
+}
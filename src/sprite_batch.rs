@@ -0,0 +1,373 @@
+use crate::buffer;
+use crate::descriptor_allocator::{DescriptorAllocator, DescriptorLayoutCache};
+use crate::memory_budget;
+use crate::one_time_commands::OneTimeCommands;
+use crate::staging_ring::StagingRing;
+use crate::synchronization::Synchronization;
+use crate::texture::Texture2D;
+use crate::texture_atlas::UvRect;
+use ash::{vk, Device, Instance};
+use glam::{Vec2, Vec4};
+use std::mem;
+
+//1フレームに描画できるスプライトの数、超えた分は描画しない
+//1枚が4頂点なのでインデックスはu16に収まる
+const MAX_SPRITES: usize = 8192;
+
+//スプライトのテクスチャは画像編集ツールで作ったsRGBの色として読む
+const TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+//SpriteBatch::add_textureで登録したテクスチャ
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TextureId(usize);
+
+//ワールド座標のXY平面に置く四角形
+#[derive(Clone, Copy, Debug)]
+pub struct Sprite {
+    //四角形の中心
+    pub position: Vec2,
+    pub size: Vec2,
+    //中心を軸にした反時計回りの回転(ラジアン)
+    pub rotation: f32,
+    pub uv_rect: UvRect,
+    //テクスチャの色に掛けるリニアの色とアルファ
+    pub tint: Vec4,
+    pub texture: TextureId,
+}
+
+//シェーダー側のmain_vs_spriteの入力の順番とlocationを合わせる
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct SpriteVertex {
+    pub position: [f32; 2],
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl SpriteVertex {
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(mem::size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+        [
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(mem::size_of::<[f32; 2]>() as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(2)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(mem::size_of::<[f32; 4]>() as u32)
+                .build(),
+        ]
+    }
+}
+
+//テクスチャとそれをset = 1に割り当てたDescriptor Set
+struct SpriteTexture {
+    texture: Texture2D,
+    descriptor_set: vk::DescriptorSet,
+}
+
+//同じテクスチャのスプライトをまとめた1回分の描画
+#[derive(Clone, Copy, Debug)]
+struct SpriteDraw {
+    texture: TextureId,
+    first_index: u32,
+    index_count: u32,
+}
+
+//drawで置いたスプライトをテクスチャごとにまとめ、テクスチャ1枚につき1回の描画でアルファブレンドする
+//set = 0はUniform Bufferでカメラの行列を使い、set = 1にテクスチャのimageとsamplerを割り当てる
+//深度テストはしないので、登録した順番が後のテクスチャのスプライトが上に重なり、同じテクスチャの中ではdrawした順番に重なる
+pub struct SpriteBatch {
+    textures: Vec<SpriteTexture>,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_allocator: DescriptorAllocator,
+    sampler: vk::Sampler,
+    //フレームごとにStagingRingに書き込んだ頂点の(バッファ, オフセット)
+    vertex_bindings: Vec<(vk::Buffer, vk::DeviceSize)>,
+    //スプライトごとの四角形のインデックスは変わらないので1つだけ作る
+    index_buffer: vk::Buffer,
+    index_memory: vk::DeviceMemory,
+    //次のuploadまでにdrawで置いたスプライト
+    sprites: Vec<Sprite>,
+    //フレームごとの頂点バッファに書き込んだスプライトの描画
+    draws: Vec<Vec<SpriteDraw>>,
+}
+
+impl SpriteBatch {
+    //samplerの破棄はSamplerCacheに、Descriptor Set Layoutの破棄はDescriptorLayoutCacheに任せる
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        sampler: vk::Sampler,
+        frames_in_flight: u32,
+    ) -> Self {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        ];
+
+        let descriptor_set_layout = descriptor_layout_cache.get(device, &bindings, &[]);
+
+        //左下、右下、右上、左上の順番の4頂点を2つの三角形にする
+        let indices = (0..MAX_SPRITES as u16)
+            .flat_map(|sprite| {
+                let base = sprite * 4;
+                [base, base + 1, base + 2, base + 2, base + 3, base]
+            })
+            .collect::<Vec<_>>();
+
+        //書き換えないが小さいので、ステージングせずにCPUから見えるメモリに置く
+        let (index_buffer, index_memory) = buffer::create_host_buffer_with_data(
+            instance,
+            physical_device,
+            device,
+            &indices,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+        );
+
+        Self {
+            textures: vec![],
+            descriptor_set_layout,
+            descriptor_allocator: DescriptorAllocator::new(),
+            sampler,
+            vertex_bindings: vec![(vk::Buffer::null(), 0); frames_in_flight as usize],
+            index_buffer,
+            index_memory,
+            sprites: vec![],
+            draws: vec![vec![]; frames_in_flight as usize],
+        }
+    }
+
+    //pixelsはwidth x heightのRGBA8
+    //TextureAtlasでまとめた画像を登録すると、アトラスの中の画像を使うスプライトが1回の描画にまとまる
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_texture(
+        &mut self,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+    ) -> TextureId {
+        let texture = Texture2D::new(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            TEXTURE_FORMAT,
+            vk::Extent2D { width, height },
+            pixels,
+        );
+
+        let descriptor_set =
+            self.descriptor_allocator
+                .allocate(device, self.descriptor_set_layout, None);
+
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .image_view(texture.view())
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()];
+
+        let sampler_info = [vk::DescriptorImageInfo::builder()
+            .sampler(self.sampler)
+            .build()];
+
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(&image_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .image_info(&sampler_info)
+                .build(),
+        ];
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        self.textures.push(SpriteTexture {
+            texture,
+            descriptor_set,
+        });
+
+        TextureId(self.textures.len() - 1)
+    }
+
+    //パイプラインレイアウトのset = 1に使う
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    //次のuploadまでの全てのdrawがまとめて1フレームに描画される
+    pub fn draw(&mut self, sprite: Sprite) {
+        //描画されない分を溜め続けないようにする
+        if self.sprites.len() < MAX_SPRITES {
+            self.sprites.push(sprite);
+        }
+    }
+
+    //drawで置いたスプライトをテクスチャの順番に並べて頂点をstaging_ringに書き込み、置いたスプライトを消す
+    //staging_ringのbegin_frameでframeを始めた後に呼ぶ
+    pub fn upload(&mut self, device: &Device, staging_ring: &mut StagingRing, frame: usize) {
+        //sort_by_keyは安定ソートなので同じテクスチャのスプライトはdrawした順番のまま
+        self.sprites.sort_by_key(|sprite| sprite.texture);
+
+        let mut vertices = Vec::with_capacity(self.sprites.len() * 4);
+        let draws = &mut self.draws[frame];
+        draws.clear();
+
+        for sprite in self.sprites.drain(..) {
+            match draws.last_mut() {
+                Some(draw) if draw.texture == sprite.texture => draw.index_count += 6,
+                _ => draws.push(SpriteDraw {
+                    texture: sprite.texture,
+                    first_index: vertices.len() as u32 / 4 * 6,
+                    index_count: 6,
+                }),
+            }
+
+            vertices.extend_from_slice(&Self::vertices(&sprite));
+        }
+
+        let allocation = staging_ring.write(device, &vertices);
+        self.vertex_bindings[frame] = (allocation.buffer, allocation.offset);
+    }
+
+    //中心からの4つの角を回転させてワールド座標にする
+    //uvは画像の上が1行目なので、ワールド座標で上の角にuv_rect.minのvを割り当てる
+    fn vertices(sprite: &Sprite) -> [SpriteVertex; 4] {
+        let half = sprite.size * 0.5;
+        let (sin, cos) = sprite.rotation.sin_cos();
+        let rotate = |corner: Vec2| {
+            Vec2::new(
+                corner.x * cos - corner.y * sin,
+                corner.x * sin + corner.y * cos,
+            )
+        };
+
+        let UvRect { min, max } = sprite.uv_rect;
+        let color = sprite.tint.to_array();
+
+        [
+            (Vec2::new(-half.x, -half.y), [min.x, max.y]),
+            (Vec2::new(half.x, -half.y), [max.x, max.y]),
+            (Vec2::new(half.x, half.y), [max.x, min.y]),
+            (Vec2::new(-half.x, half.y), [min.x, min.y]),
+        ]
+        .map(|(corner, uv)| SpriteVertex {
+            position: (sprite.position + rotate(corner)).to_array(),
+            uv,
+            color,
+        })
+    }
+
+    //他の描画の後に、描画先のパスの中で呼ぶ
+    //viewportとscissorは呼び出し側で設定しておく
+    pub fn cmd_draw(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        (pipeline, pipeline_layout): (vk::Pipeline, vk::PipelineLayout),
+        uniform_descriptor_set: vk::DescriptorSet,
+        frame: usize,
+    ) {
+        let draws = &self.draws[frame];
+        let (vertex_buffer, vertex_offset) = self.vertex_bindings[frame];
+
+        if draws.is_empty() {
+            return;
+        }
+
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &[uniform_descriptor_set],
+                &[],
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[vertex_offset]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                self.index_buffer,
+                0,
+                vk::IndexType::UINT16,
+            );
+
+            for draw in draws {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    1,
+                    &[self.textures[draw.texture.0].descriptor_set],
+                    &[],
+                );
+                device.cmd_draw_indexed(
+                    command_buffer,
+                    draw.index_count,
+                    1,
+                    draw.first_index,
+                    0,
+                    0,
+                );
+            }
+        }
+    }
+
+    //テクスチャの数は描画の回数の上限になる
+    pub fn texture_count(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_buffer(self.index_buffer, None);
+            memory_budget::free_memory(device, self.index_memory);
+        }
+        self.descriptor_allocator.destroy(device);
+
+        for texture in &self.textures {
+            texture.texture.destroy(device);
+        }
+    }
+}
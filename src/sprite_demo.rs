@@ -0,0 +1,245 @@
+use crate::one_time_commands::OneTimeCommands;
+use crate::sprite_batch::{Sprite, SpriteBatch, TextureId};
+use crate::synchronization::Synchronization;
+use crate::texture_atlas::{TextureAtlas, UvRect};
+use ash::{vk, Device, Instance};
+use glam::{Vec2, Vec4};
+
+//--spritesの正射影のカメラが映すワールド座標の高さ
+pub const VIEW_HEIGHT: f32 = 10.0;
+
+//アトラスに並べる画像の1辺のピクセル数
+const SHAPE_SIZE: u32 = 32;
+const ATLAS_SIZE: u32 = 128;
+//アトラスとは別のテクスチャにする背景のチェッカー模様
+const CHECKER_SIZE: u32 = 64;
+const CHECKER_CELLS: u32 = 8;
+
+//スプライトの1辺のワールド座標での長さの範囲
+const MIN_SPRITE_SIZE: f32 = 0.2;
+const MAX_SPRITE_SIZE: f32 = 0.6;
+//1秒あたりの移動量の最大値
+const MAX_SPEED: f32 = 3.0;
+//1秒あたりの回転量の最大値(ラジアン)
+const MAX_SPIN: f32 = 3.0;
+
+//アトラスに描く図形
+#[derive(Clone, Copy, Debug)]
+enum Shape {
+    Circle,
+    Ring,
+    Diamond,
+    Square,
+}
+
+impl Shape {
+    const ALL: [Shape; 4] = [Shape::Circle, Shape::Ring, Shape::Diamond, Shape::Square];
+
+    //白い図形で、外側は透明
+    //色はSpriteのtintで付ける
+    fn pixels(self) -> Vec<u8> {
+        let half = SHAPE_SIZE as f32 / 2.0;
+
+        (0..SHAPE_SIZE)
+            .flat_map(|y| (0..SHAPE_SIZE).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                //ピクセルの中心を-1.0から1.0の座標にする
+                let u = (x as f32 + 0.5 - half) / half;
+                let v = (y as f32 + 0.5 - half) / half;
+                let distance = (u * u + v * v).sqrt();
+
+                let inside = match self {
+                    Shape::Circle => distance <= 1.0,
+                    Shape::Ring => (0.6..=1.0).contains(&distance),
+                    Shape::Diamond => u.abs() + v.abs() <= 1.0,
+                    Shape::Square => u.abs() <= 0.8 && v.abs() <= 0.8,
+                };
+
+                if inside {
+                    [255, 255, 255, 255]
+                } else {
+                    [0, 0, 0, 0]
+                }
+            })
+            .collect()
+    }
+}
+
+//xorshiftの疑似乱数、デモの配置を毎回同じにする
+struct Random(u32);
+
+impl Random {
+    //0.0から1.0
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next()
+    }
+}
+
+//動き回るスプライト
+struct MovingSprite {
+    sprite: Sprite,
+    velocity: Vec2,
+    spin: f32,
+}
+
+//--sprites Nのデモ、N個のスプライトが画面の端で跳ね返りながら回転する
+//図形はアトラスにまとめ、背景のチェッカー模様だけ別のテクスチャにするので、描画は2回になる
+pub struct SpriteDemo {
+    sprites: Vec<MovingSprite>,
+    background: Sprite,
+}
+
+impl SpriteDemo {
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        sprite_batch: &mut SpriteBatch,
+        count: u32,
+    ) -> Self {
+        let mut atlas = TextureAtlas::new(ATLAS_SIZE, ATLAS_SIZE);
+
+        let shapes = Shape::ALL
+            .iter()
+            .map(|shape| {
+                atlas
+                    .add(SHAPE_SIZE, SHAPE_SIZE, &shape.pixels())
+                    .expect("Sprite shapes do not fit in the atlas")
+            })
+            .collect::<Vec<_>>();
+
+        let mut add_texture = |width, height, pixels: &[u8]| -> TextureId {
+            sprite_batch.add_texture(
+                instance,
+                physical_device,
+                device,
+                one_time_commands,
+                synchronization,
+                width,
+                height,
+                pixels,
+            )
+        };
+
+        //SpriteBatchは登録した順番にテクスチャを描画するので、背景を先に登録して図形の後ろにする
+        let checker_texture = add_texture(CHECKER_SIZE, CHECKER_SIZE, &Self::checker());
+        let atlas_texture = add_texture(atlas.width(), atlas.height(), atlas.pixels());
+
+        let mut random = Random(0x2545_f491);
+        let half_height = VIEW_HEIGHT / 2.0;
+
+        let sprites = (0..count)
+            .map(|index| {
+                let size = random.range(MIN_SPRITE_SIZE, MAX_SPRITE_SIZE);
+                let angle = random.range(0.0, std::f32::consts::TAU);
+                let speed = random.range(0.5, 1.0) * MAX_SPEED;
+
+                MovingSprite {
+                    sprite: Sprite {
+                        position: Vec2::new(
+                            random.range(-half_height, half_height),
+                            random.range(-half_height, half_height),
+                        ),
+                        size: Vec2::splat(size),
+                        rotation: angle,
+                        uv_rect: shapes[index as usize % shapes.len()],
+                        tint: Vec4::new(
+                            random.range(0.2, 1.0),
+                            random.range(0.2, 1.0),
+                            random.range(0.2, 1.0),
+                            random.range(0.6, 1.0),
+                        ),
+                        texture: atlas_texture,
+                    },
+                    velocity: Vec2::new(angle.cos(), angle.sin()) * speed,
+                    spin: random.range(-MAX_SPIN, MAX_SPIN),
+                }
+            })
+            .collect();
+
+        //チェッカー模様を引き伸ばさずに繰り返すために、uvを1.0より大きくしてREPEATのサンプラーで読む
+        let background = Sprite {
+            position: Vec2::ZERO,
+            size: Vec2::new(VIEW_HEIGHT * 4.0, VIEW_HEIGHT * 2.0),
+            rotation: 0.0,
+            uv_rect: UvRect {
+                min: Vec2::ZERO,
+                max: Vec2::new(8.0, 4.0),
+            },
+            tint: Vec4::new(0.3, 0.3, 0.35, 1.0),
+            texture: checker_texture,
+        };
+
+        log::info!(
+            "Sprite demo: {} sprites, {} textures",
+            count,
+            sprite_batch.texture_count()
+        );
+
+        Self {
+            sprites,
+            background,
+        }
+    }
+
+    //灰色と白のチェッカー模様のRGBA8
+    fn checker() -> Vec<u8> {
+        let cell_size = CHECKER_SIZE / CHECKER_CELLS;
+
+        (0..CHECKER_SIZE)
+            .flat_map(|y| (0..CHECKER_SIZE).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                if (x / cell_size + y / cell_size) % 2 == 0 {
+                    [160, 160, 160, 255]
+                } else {
+                    [255, 255, 255, 255]
+                }
+            })
+            .collect()
+    }
+
+    //half_extentsは画面に映るワールド座標の範囲の半分、端に着いたスプライトはそこで跳ね返る
+    pub fn update(&mut self, delta_seconds: f32, half_extents: Vec2) {
+        for moving in &mut self.sprites {
+            let sprite = &mut moving.sprite;
+            let half_size = sprite.size * 0.5;
+
+            sprite.position += moving.velocity * delta_seconds;
+            sprite.rotation += moving.spin * delta_seconds;
+
+            if sprite.position.x.abs() + half_size.x > half_extents.x {
+                moving.velocity.x = -moving.velocity.x.abs() * sprite.position.x.signum();
+                sprite.position.x = sprite.position.x.clamp(
+                    -(half_extents.x - half_size.x).max(0.0),
+                    (half_extents.x - half_size.x).max(0.0),
+                );
+            }
+
+            if sprite.position.y.abs() + half_size.y > half_extents.y {
+                moving.velocity.y = -moving.velocity.y.abs() * sprite.position.y.signum();
+                sprite.position.y = sprite.position.y.clamp(
+                    -(half_extents.y - half_size.y).max(0.0),
+                    (half_extents.y - half_size.y).max(0.0),
+                );
+            }
+        }
+    }
+
+    pub fn draw(&self, sprite_batch: &mut SpriteBatch) {
+        sprite_batch.draw(self.background);
+
+        for moving in &self.sprites {
+            sprite_batch.draw(moving.sprite);
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use crate::buffer;
+use crate::memory_budget::{self, MemoryCategory};
+use ash::{vk, Device, Instance};
+use std::mem;
+
+//切り出す領域の最小のアラインメント、インデックスバッファのオフセットの制約も満たす
+const MIN_ALIGNMENT: vk::DeviceSize = 16;
+
+//StagingRing::allocateで切り出した領域
+//bufferのoffsetから書き込んだものをこのフレームのコマンドから読む
+#[derive(Clone, Copy, Debug)]
+pub struct StagingAllocation {
+    pub buffer: vk::Buffer,
+    pub offset: vk::DeviceSize,
+    //offsetの位置を指す、メモリはHOST_COHERENTなのでフラッシュは要らない
+    pub mapped: *mut u8,
+}
+
+//作成時からずっとマップしておくバッファ
+struct Chunk {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped: *mut u8,
+}
+
+impl Chunk {
+    fn new(
+        device: &Device,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        memory_type_index: u32,
+    ) -> Self {
+        let buffer_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .build();
+
+        let buffer = unsafe { device.create_buffer(&buffer_info, None).unwrap() };
+
+        let requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index)
+            .build();
+
+        let memory = unsafe {
+            memory_budget::allocate_memory(
+                device,
+                &alloc_info,
+                MemoryCategory::from_buffer_usage(usage),
+            )
+            .unwrap()
+        };
+
+        let mapped = unsafe {
+            device.bind_buffer_memory(buffer, memory, 0).unwrap();
+            device
+                .map_memory(memory, 0, size, vk::MemoryMapFlags::empty())
+                .unwrap()
+        };
+
+        Self {
+            buffer,
+            memory,
+            mapped: mapped as *mut u8,
+        }
+    }
+
+    fn allocation(&self, offset: vk::DeviceSize) -> StagingAllocation {
+        StagingAllocation {
+            buffer: self.buffer,
+            offset,
+            mapped: unsafe { self.mapped.add(offset as usize) },
+        }
+    }
+
+    fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_buffer(self.buffer, None);
+            memory_budget::free_memory(device, self.memory);
+        }
+    }
+}
+
+//FrameArenaのうちVulkanを使わない、どこまで切り出したかの記録
+#[derive(Default)]
+struct Cursor {
+    //次に切り出す位置
+    offset: vk::DeviceSize,
+    //このフレームで要求された大きさの合計、溢れた時にどれだけ足りないかを報告する
+    requested: vk::DeviceSize,
+}
+
+impl Cursor {
+    //capacityに入る場合は切り出した先頭を返す、入らなかった分もrequestedには足す
+    fn take(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+        capacity: vk::DeviceSize,
+    ) -> Option<vk::DeviceSize> {
+        self.requested += size;
+
+        let (start, end) = bump(self.offset, size, alignment.max(MIN_ALIGNMENT), capacity)?;
+        self.offset = end;
+
+        Some(start)
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+//1フレーム分の領域
+struct FrameArena {
+    chunk: Chunk,
+    cursor: Cursor,
+    //capacityに入らなかった分のために作ったバッファ、このフレームの次のbegin_frameで破棄する
+    overflow: Vec<Chunk>,
+}
+
+//デバッグ用の文字やスプライトの頂点など、毎フレームCPUから書き直すデータを置く
+//フレームごとにcapacityのHOST_VISIBLEなバッファを1つ持ち、先頭から順に切り出す
+//begin_frameでそのフレームの前回の描画が終わった後に先頭に戻す
+pub struct StagingRing {
+    frames: Vec<FrameArena>,
+    capacity: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+    //溢れた時に作るバッファにも同じメモリタイプを使う
+    memory_type_index: u32,
+    current: usize,
+}
+
+impl StagingRing {
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        capacity: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        frames_in_flight: u32,
+    ) -> Self {
+        let properties =
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+
+        //同じusageで作ったバッファのmemory_type_bitsは全て同じになる
+        let probe_info = vk::BufferCreateInfo::builder()
+            .size(capacity)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .build();
+
+        let memory_type_index = unsafe {
+            let probe = device.create_buffer(&probe_info, None).unwrap();
+            let requirements = device.get_buffer_memory_requirements(probe);
+            device.destroy_buffer(probe, None);
+
+            buffer::find_memory_type(
+                instance,
+                physical_device,
+                requirements.memory_type_bits,
+                properties,
+            )
+        };
+
+        let frames = (0..frames_in_flight)
+            .map(|_| FrameArena {
+                chunk: Chunk::new(device, capacity, usage, memory_type_index),
+                cursor: Cursor::default(),
+                overflow: vec![],
+            })
+            .collect();
+
+        Self {
+            frames,
+            capacity,
+            usage,
+            memory_type_index,
+            current: 0,
+        }
+    }
+
+    //frameの前回の描画の完了を待ってから、そのフレームで最初にallocateするより前に呼ぶ
+    pub fn begin_frame(&mut self, device: &Device, frame: usize) {
+        let arena = &mut self.frames[frame];
+
+        if !arena.overflow.is_empty() {
+            log::warn!(
+                "Staging ring overflowed: frame {} needed {} bytes but the capacity is {} bytes",
+                frame,
+                arena.cursor.requested,
+                self.capacity
+            );
+
+            for chunk in arena.overflow.drain(..) {
+                chunk.destroy(device);
+            }
+        }
+
+        arena.cursor.reset();
+        self.current = frame;
+    }
+
+    //alignmentは2の累乗
+    //capacityに入らない場合はこのフレームだけのバッファを作って返す
+    pub fn allocate(
+        &mut self,
+        device: &Device,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+    ) -> StagingAllocation {
+        let arena = &mut self.frames[self.current];
+
+        match arena.cursor.take(size, alignment, self.capacity) {
+            Some(start) => arena.chunk.allocation(start),
+            None => {
+                //大きさ0のバッファは作れない
+                let chunk = Chunk::new(device, size.max(1), self.usage, self.memory_type_index);
+                let allocation = chunk.allocation(0);
+                arena.overflow.push(chunk);
+
+                allocation
+            }
+        }
+    }
+
+    //dataを切り出した領域にコピーする
+    pub fn write<T: Copy>(&mut self, device: &Device, data: &[T]) -> StagingAllocation {
+        let allocation = self.allocate(
+            device,
+            mem::size_of_val(data) as vk::DeviceSize,
+            mem::align_of::<T>() as vk::DeviceSize,
+        );
+
+        unsafe {
+            allocation
+                .mapped
+                .cast::<T>()
+                .copy_from_nonoverlapping(data.as_ptr(), data.len());
+        }
+
+        allocation
+    }
+
+    //GPUが全てのフレームを使い終わってから呼ぶ
+    pub fn destroy(&self, device: &Device) {
+        for arena in &self.frames {
+            arena.chunk.destroy(device);
+
+            for chunk in &arena.overflow {
+                chunk.destroy(device);
+            }
+        }
+    }
+}
+
+//offsetの後ろにalignmentに揃えてsizeを切り出せる場合は(先頭, 次のoffset)を返す
+fn bump(
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    alignment: vk::DeviceSize,
+    capacity: vk::DeviceSize,
+) -> Option<(vk::DeviceSize, vk::DeviceSize)> {
+    let start = buffer::align_up(offset, alignment);
+    let end = start.checked_add(size)?;
+
+    (end <= capacity).then(|| (start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bump_aligns_the_start() {
+        assert_eq!(bump(0, 10, 16, 256), Some((0, 10)));
+        assert_eq!(bump(10, 10, 16, 256), Some((16, 26)));
+        assert_eq!(bump(17, 4, 64, 256), Some((64, 68)));
+        assert_eq!(bump(64, 4, 256, 512), Some((256, 260)));
+        //既に揃っている場合はそのまま
+        assert_eq!(bump(32, 8, 16, 256), Some((32, 40)));
+    }
+
+    #[test]
+    fn bump_fails_past_the_capacity() {
+        //ちょうど埋まる場合は入る
+        assert_eq!(bump(0, 256, 16, 256), Some((0, 256)));
+        assert_eq!(bump(240, 16, 16, 256), Some((240, 256)));
+        //揃えた分で溢れる
+        assert_eq!(bump(241, 8, 16, 256), None);
+        assert_eq!(bump(0, 257, 16, 256), None);
+        assert_eq!(bump(16, vk::DeviceSize::MAX, 16, 256), None);
+    }
+
+    #[test]
+    fn cursor_uses_the_minimum_alignment() {
+        let mut cursor = Cursor::default();
+
+        assert_eq!(cursor.take(3, 1, 256), Some(0));
+        assert_eq!(cursor.take(3, 4, 256), Some(MIN_ALIGNMENT));
+        assert_eq!(cursor.take(3, 64, 256), Some(64));
+        assert_eq!(cursor.offset, 67);
+    }
+
+    #[test]
+    fn cursor_overflows_and_keeps_counting() {
+        let mut cursor = Cursor::default();
+
+        assert_eq!(cursor.take(200, 16, 256), Some(0));
+        //溢れた場合はoffsetを進めず、後の小さい要求はまだ入る
+        assert_eq!(cursor.take(100, 16, 256), None);
+        assert_eq!(cursor.offset, 200);
+        assert_eq!(cursor.take(40, 16, 256), Some(208));
+        assert_eq!(cursor.requested, 340);
+    }
+
+    #[test]
+    fn reset_wraps_to_the_start_for_the_next_frame() {
+        let mut cursor = Cursor::default();
+
+        for _ in 0..3 {
+            assert_eq!(cursor.take(100, 16, 256), Some(0));
+            assert_eq!(cursor.take(100, 16, 256), Some(112));
+            assert_eq!(cursor.take(100, 16, 256), None);
+            assert_eq!(cursor.requested, 300);
+
+            cursor.reset();
+
+            assert_eq!(cursor.offset, 0);
+            assert_eq!(cursor.requested, 0);
+        }
+    }
+}
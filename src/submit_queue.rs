@@ -0,0 +1,154 @@
+use crate::synchronization::Synchronization;
+use ash::extensions::khr::Swapchain;
+use ash::prelude::VkResult;
+use ash::{vk, Device};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+
+//presentをイベントループのスレッドから呼ばなければならないプラットフォーム
+//macOSのCAMetalLayerはメインスレッド以外から表示すると描画が乱れることがある
+const PRESENT_ON_EVENT_LOOP_THREAD: bool = cfg!(target_os = "macos");
+
+//vkQueueSubmitとvkQueuePresentKHRはキューを外部で同期する必要があるので、キューとMutexをまとめて持つ
+//同じvk::Queueを指すものはcloneしてMutexを共有する
+#[derive(Clone)]
+pub struct SubmitQueue {
+    handle: vk::Queue,
+    lock: Arc<Mutex<()>>,
+    //作ったスレッド、イベントループのスレッドで作ることを前提にしている
+    owner_thread: ThreadId,
+}
+
+impl SubmitQueue {
+    pub fn new(handle: vk::Queue) -> Self {
+        Self {
+            handle,
+            lock: Arc::new(Mutex::new(())),
+            owner_thread: thread::current().id(),
+        }
+    }
+
+    pub fn handle(&self) -> vk::Queue {
+        self.handle
+    }
+
+    //sharedの中に同じvk::Queueを指すものがあればそれと共有する
+    pub fn shared_with(handle: vk::Queue, shared: &[&SubmitQueue]) -> Self {
+        match shared.iter().find(|queue| queue.handle == handle) {
+            Some(queue) => (*queue).clone(),
+            None => Self::new(handle),
+        }
+    }
+
+    //Synchronization::queue_submitをロックした間に呼ぶ
+    pub fn submit(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        wait_semaphores: &[vk::SemaphoreSubmitInfo],
+        command_buffers: &[vk::CommandBuffer],
+        signal_semaphores: &[vk::SemaphoreSubmitInfo],
+        fence: vk::Fence,
+    ) -> Result<(), vk::Result> {
+        self.locked(|handle| {
+            synchronization.queue_submit(
+                device,
+                handle,
+                wait_semaphores,
+                command_buffers,
+                signal_semaphores,
+                fence,
+            )
+        })
+    }
+
+    //Okの値はsuboptimalかどうか
+    pub fn present(
+        &self,
+        swap_chain: &Swapchain,
+        present_info: &vk::PresentInfoKHR,
+    ) -> VkResult<bool> {
+        debug_assert!(
+            !PRESENT_ON_EVENT_LOOP_THREAD || thread::current().id() == self.owner_thread,
+            "present must be called from the event loop thread on this platform"
+        );
+
+        self.locked(|handle| unsafe { swap_chain.queue_present(handle, present_info) })
+    }
+
+    //同じvk::Queueを使う他の呼び出しと重ならないようにfを呼ぶ
+    fn locked<R>(&self, f: impl FnOnce(vk::Queue) -> R) -> R {
+        let _guard = self.lock.lock().unwrap();
+
+        f(self.handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    const THREADS: usize = 8;
+    const SUBMITS_PER_THREAD: usize = 1000;
+
+    //vkQueueSubmitを呼ぶにはデバイスが要るので、ロックの中で同じキューに他のスレッドが入っていないかだけを確かめる
+    #[test]
+    fn submits_from_many_threads_never_overlap() {
+        let queue = SubmitQueue::new(vk::Queue::from_raw(1));
+        let other = SubmitQueue::new(vk::Queue::from_raw(2));
+        let shared = SubmitQueue::shared_with(vk::Queue::from_raw(1), &[&other, &queue]);
+        let inside = Arc::new(AtomicBool::new(false));
+        let submitted = Arc::new(AtomicUsize::new(0));
+
+        let threads = (0..THREADS)
+            .map(|index| {
+                //半分は共有したクローンから呼ぶ
+                let queue = if index % 2 == 0 {
+                    queue.clone()
+                } else {
+                    shared.clone()
+                };
+                let inside = inside.clone();
+                let submitted = submitted.clone();
+
+                thread::spawn(move || {
+                    for _ in 0..SUBMITS_PER_THREAD {
+                        queue.locked(|handle| {
+                            assert_eq!(handle.as_raw(), 1);
+                            assert!(!inside.swap(true, Ordering::SeqCst), "Submits overlapped");
+                            thread::yield_now();
+                            submitted.fetch_add(1, Ordering::Relaxed);
+                            inside.store(false, Ordering::SeqCst);
+                        })
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(
+            submitted.load(Ordering::Relaxed),
+            THREADS * SUBMITS_PER_THREAD
+        );
+    }
+
+    #[test]
+    fn shared_with_reuses_the_lock_of_the_same_queue() {
+        let graphics = SubmitQueue::new(vk::Queue::from_raw(1));
+        let compute = SubmitQueue::new(vk::Queue::from_raw(2));
+
+        let present = SubmitQueue::shared_with(vk::Queue::from_raw(1), &[&compute, &graphics]);
+        let transfer = SubmitQueue::shared_with(vk::Queue::from_raw(3), &[&compute, &graphics]);
+
+        assert!(Arc::ptr_eq(&present.lock, &graphics.lock));
+        assert!(!Arc::ptr_eq(&present.lock, &compute.lock));
+        assert!(!Arc::ptr_eq(&transfer.lock, &graphics.lock));
+        assert!(!Arc::ptr_eq(&transfer.lock, &compute.lock));
+        assert_eq!(transfer.handle().as_raw(), 3);
+    }
+}
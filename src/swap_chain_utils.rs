@@ -1,4 +1,188 @@
 use ash::vk;
+use std::str::FromStr;
+
+//どのPresentModeを優先して選ぶか
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PresentModePreference {
+    //FIFOは垂直同期に相当し、全ての実装でサポートされていることが保証されている
+    Vsync,
+    //MAILBOXは垂直同期しつつ待機中の画像を新しいもので置き換えるので遅延が少ない
+    LowLatency,
+    //IMMEDIATEは垂直同期を待たずに表示するのでティアリングが起こりうる
+    Uncapped,
+}
+
+impl PresentModePreference {
+    //優先度の高い順に並べた候補
+    fn candidates(self) -> &'static [vk::PresentModeKHR] {
+        match self {
+            Self::Vsync => &[vk::PresentModeKHR::FIFO],
+            Self::LowLatency => &[
+                vk::PresentModeKHR::MAILBOX,
+                vk::PresentModeKHR::IMMEDIATE,
+                vk::PresentModeKHR::FIFO,
+            ],
+            Self::Uncapped => &[
+                vk::PresentModeKHR::IMMEDIATE,
+                vk::PresentModeKHR::MAILBOX,
+                vk::PresentModeKHR::FIFO,
+            ],
+        }
+    }
+
+    //設定ファイルに書き出す時の名前、from_strで読める
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Vsync => "vsync",
+            Self::LowLatency => "low-latency",
+            Self::Uncapped => "uncapped",
+        }
+    }
+
+    //実行中に切り替えるときの次の設定
+    pub fn next(self) -> Self {
+        match self {
+            Self::Vsync => Self::LowLatency,
+            Self::LowLatency => Self::Uncapped,
+            Self::Uncapped => Self::Vsync,
+        }
+    }
+}
+
+impl FromStr for PresentModePreference {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "vsync" | "fifo" => Ok(Self::Vsync),
+            "low-latency" | "mailbox" => Ok(Self::LowLatency),
+            "uncapped" | "immediate" => Ok(Self::Uncapped),
+            _ => Err(format!(
+                "Unknown present mode '{}', expected vsync, low-latency or uncapped",
+                s
+            )),
+        }
+    }
+}
+
+//swapchainの画像フォーマットをどの候補から選ぶか
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SurfaceFormatPreference {
+    //SRGBフォーマットに書き込むとハードウェアがガンマ補正を行う
+    Srgb,
+    //UNORMフォーマットではシェーダー側でガンマ補正を行う必要がある
+    Unorm,
+    //10bitのHDR10出力、対応していない場合はSDRにフォールバックする
+    Hdr10,
+    //16bit浮動小数点のscRGB出力、リニアの値をそのまま書き込む
+    ScRgb,
+    //--hdr、HDR10、scRGBの順に探し、どちらも無ければSDRにフォールバックする
+    Hdr,
+}
+
+impl SurfaceFormatPreference {
+    //優先度の高い順に並べた候補
+    pub fn formats(self) -> Vec<vk::SurfaceFormatKHR> {
+        let hdr10 = surface_format(
+            vk::Format::A2B10G10R10_UNORM_PACK32,
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+        );
+        let scrgb = surface_format(
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+        );
+        let srgb = [
+            //Windowsのドライバの多くはB8G8R8A8を先頭で返す
+            surface_format(vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+            surface_format(vk::Format::R8G8B8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+        ];
+
+        match self {
+            Self::Srgb => srgb.to_vec(),
+            Self::Unorm => vec![
+                surface_format(
+                    vk::Format::B8G8R8A8_UNORM,
+                    vk::ColorSpaceKHR::SRGB_NONLINEAR,
+                ),
+                surface_format(
+                    vk::Format::R8G8B8A8_UNORM,
+                    vk::ColorSpaceKHR::SRGB_NONLINEAR,
+                ),
+            ],
+            Self::Hdr10 => [
+                hdr10,
+                surface_format(
+                    vk::Format::A2B10G10R10_UNORM_PACK32,
+                    vk::ColorSpaceKHR::SRGB_NONLINEAR,
+                ),
+            ]
+            .into_iter()
+            .chain(srgb)
+            .collect(),
+            Self::ScRgb => [scrgb].into_iter().chain(srgb).collect(),
+            Self::Hdr => [hdr10, scrgb].into_iter().chain(srgb).collect(),
+        }
+    }
+
+    //設定ファイルに書き出す時の名前、from_strで読める
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Srgb => "srgb",
+            Self::Unorm => "unorm",
+            Self::Hdr10 => "hdr10",
+            Self::ScRgb => "scrgb",
+            Self::Hdr => "hdr",
+        }
+    }
+}
+
+impl FromStr for SurfaceFormatPreference {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "srgb" => Ok(Self::Srgb),
+            "unorm" => Ok(Self::Unorm),
+            "hdr10" => Ok(Self::Hdr10),
+            "scrgb" => Ok(Self::ScRgb),
+            "hdr" => Ok(Self::Hdr),
+            _ => Err(format!(
+                "Unknown surface format '{}', expected srgb, unorm, hdr10, scrgb or hdr",
+                s
+            )),
+        }
+    }
+}
+
+//VK_EXT_swapchain_colorspaceで追加されるHDRの色空間
+//拡張が有効でなければサーフェイスはこれらを返さない
+fn is_hdr_color_space(color_space: vk::ColorSpaceKHR) -> bool {
+    matches!(
+        color_space,
+        vk::ColorSpaceKHR::HDR10_ST2084_EXT
+            | vk::ColorSpaceKHR::HDR10_HLG_EXT
+            | vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT
+            | vk::ColorSpaceKHR::BT2020_LINEAR_EXT
+    )
+}
+
+fn surface_format(format: vk::Format, color_space: vk::ColorSpaceKHR) -> vk::SurfaceFormatKHR {
+    vk::SurfaceFormatKHR {
+        format,
+        color_space,
+    }
+}
+
+//swapchainの作成時に使用する設定
+pub struct SwapChainSettings {
+    pub present_mode: PresentModePreference,
+    //swapchainのフォーマットの候補、優先度の高い順
+    pub surface_formats: Vec<vk::SurfaceFormatKHR>,
+    //swapchainに要求する画像の枚数、Noneの場合はmin_image_count + 1
+    pub image_count: Option<u32>,
+    //Someの場合はそのモニターでの排他フルスクリーンを要求する(Windowsのみ)
+    pub full_screen_exclusive_monitor: Option<vk::HMONITOR>,
+}
 
 pub struct SwapChainSupportDetails {
     //サポートされる機能一覧を取得できる
@@ -41,28 +225,80 @@ impl SwapChainSupportDetails {
         }
     }
 
-    pub fn choose_swap_surface_format(&self) -> vk::SurfaceFormatKHR {
-        for available_format in self.formats.iter() {
-            if available_format.format == vk::Format::R8G8B8A8_SRGB
-                && available_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-            {
-                return *available_format;
+    //preferencesの先頭から順にサポートされているものを探し、無ければ最初に列挙されたものを使う
+    //HDRの候補があるのにSDRになった場合はログに残す
+    pub fn choose_swap_surface_format(
+        &self,
+        preferences: &[vk::SurfaceFormatKHR],
+    ) -> vk::SurfaceFormatKHR {
+        let chosen = self.find_surface_format(preferences);
+
+        if preferences
+            .iter()
+            .any(|preferred| is_hdr_color_space(preferred.color_space))
+            && !is_hdr_color_space(chosen.color_space)
+        {
+            log::info!(
+                "The surface offers no HDR color space, falling back to SDR {:?} {:?}",
+                chosen.format,
+                chosen.color_space
+            );
+        }
+
+        chosen
+    }
+
+    fn find_surface_format(&self, preferences: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR {
+        //UNDEFINEDが1つだけ返ってくる場合はどのフォーマットでも使用できることを意味する
+        if let [only] = self.formats.as_slice() {
+            if only.format == vk::Format::UNDEFINED {
+                if let Some(preferred) = preferences.first() {
+                    return *preferred;
+                }
+            }
+        }
+
+        for preferred in preferences {
+            if self.formats.iter().any(|available| {
+                available.format == preferred.format
+                    && available.color_space == preferred.color_space
+            }) {
+                return *preferred;
             }
         }
 
         *self.formats.first().unwrap()
     }
 
-    pub fn choose_swap_present_mode(&self) -> vk::PresentModeKHR {
-        for available_present_mode in self.present_modes.clone() {
-            if available_present_mode == vk::PresentModeKHR::MAILBOX {
-                return available_present_mode;
+    pub fn choose_swap_present_mode(
+        &self,
+        preference: PresentModePreference,
+    ) -> vk::PresentModeKHR {
+        for candidate in preference.candidates() {
+            if self.present_modes.contains(candidate) {
+                return *candidate;
             }
         }
 
+        //FIFOはサポートが保証されているのでリストに無くても選んで良い
         vk::PresentModeKHR::FIFO
     }
 
+    //swapchainに含められる画像の枚数を決める
+    //少なすぎると空き容量がなくてレンダリングが止まってしまう
+    //desiredが指定されていない場合はmin_image_count + 1を使う
+    pub fn choose_image_count(&self, desired: Option<u32>) -> u32 {
+        let min = self.capabilities.min_image_count;
+        let image_count = desired.unwrap_or(min + 1).max(min);
+
+        //max_image_countが0の場合は上限が存在しないという意味
+        if self.capabilities.max_image_count > 0 {
+            image_count.min(self.capabilities.max_image_count)
+        } else {
+            image_count
+        }
+    }
+
     pub fn choose_swap_extent(&self, width: u32, height: u32) -> vk::Extent2D {
         if self.capabilities.current_extent.width != u32::MAX {
             return self.capabilities.current_extent;
@@ -76,3 +312,343 @@ impl SwapChainSupportDetails {
         vk::Extent2D { width, height }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn support_details(
+        formats: Vec<vk::SurfaceFormatKHR>,
+        present_modes: Vec<vk::PresentModeKHR>,
+    ) -> SwapChainSupportDetails {
+        SwapChainSupportDetails {
+            capabilities: vk::SurfaceCapabilitiesKHR::default(),
+            formats,
+            present_modes,
+        }
+    }
+
+    fn present_mode(
+        preference: PresentModePreference,
+        present_modes: &[vk::PresentModeKHR],
+    ) -> vk::PresentModeKHR {
+        support_details(vec![], present_modes.to_vec()).choose_swap_present_mode(preference)
+    }
+
+    #[test]
+    fn present_mode_with_every_mode() {
+        let all = [
+            vk::PresentModeKHR::FIFO,
+            vk::PresentModeKHR::MAILBOX,
+            vk::PresentModeKHR::IMMEDIATE,
+        ];
+
+        assert_eq!(
+            present_mode(PresentModePreference::Vsync, &all),
+            vk::PresentModeKHR::FIFO
+        );
+        assert_eq!(
+            present_mode(PresentModePreference::LowLatency, &all),
+            vk::PresentModeKHR::MAILBOX
+        );
+        assert_eq!(
+            present_mode(PresentModePreference::Uncapped, &all),
+            vk::PresentModeKHR::IMMEDIATE
+        );
+    }
+
+    #[test]
+    fn present_mode_without_mailbox() {
+        let modes = [vk::PresentModeKHR::FIFO, vk::PresentModeKHR::IMMEDIATE];
+
+        assert_eq!(
+            present_mode(PresentModePreference::Vsync, &modes),
+            vk::PresentModeKHR::FIFO
+        );
+        assert_eq!(
+            present_mode(PresentModePreference::LowLatency, &modes),
+            vk::PresentModeKHR::IMMEDIATE
+        );
+        assert_eq!(
+            present_mode(PresentModePreference::Uncapped, &modes),
+            vk::PresentModeKHR::IMMEDIATE
+        );
+    }
+
+    #[test]
+    fn present_mode_without_immediate() {
+        let modes = [vk::PresentModeKHR::FIFO, vk::PresentModeKHR::MAILBOX];
+
+        assert_eq!(
+            present_mode(PresentModePreference::Uncapped, &modes),
+            vk::PresentModeKHR::MAILBOX
+        );
+    }
+
+    #[test]
+    fn present_mode_with_only_fifo() {
+        for preference in [
+            PresentModePreference::Vsync,
+            PresentModePreference::LowLatency,
+            PresentModePreference::Uncapped,
+        ] {
+            assert_eq!(
+                present_mode(preference, &[vk::PresentModeKHR::FIFO]),
+                vk::PresentModeKHR::FIFO
+            );
+        }
+    }
+
+    #[test]
+    fn present_mode_with_empty_list_falls_back_to_fifo() {
+        for preference in [
+            PresentModePreference::Vsync,
+            PresentModePreference::LowLatency,
+            PresentModePreference::Uncapped,
+        ] {
+            assert_eq!(present_mode(preference, &[]), vk::PresentModeKHR::FIFO);
+        }
+    }
+
+    fn srgb_nonlinear(format: vk::Format) -> vk::SurfaceFormatKHR {
+        surface_format(format, vk::ColorSpaceKHR::SRGB_NONLINEAR)
+    }
+
+    #[test]
+    fn single_undefined_format_accepts_first_preference() {
+        let details = support_details(vec![srgb_nonlinear(vk::Format::UNDEFINED)], vec![]);
+        let preferences = [
+            srgb_nonlinear(vk::Format::R8G8B8A8_SRGB),
+            srgb_nonlinear(vk::Format::B8G8R8A8_SRGB),
+        ];
+
+        assert_eq!(
+            details.find_surface_format(&preferences),
+            srgb_nonlinear(vk::Format::R8G8B8A8_SRGB)
+        );
+    }
+
+    #[test]
+    fn preferences_are_tried_in_order() {
+        let details = support_details(
+            vec![
+                srgb_nonlinear(vk::Format::B8G8R8A8_UNORM),
+                srgb_nonlinear(vk::Format::R8G8B8A8_SRGB),
+                srgb_nonlinear(vk::Format::B8G8R8A8_SRGB),
+            ],
+            vec![],
+        );
+
+        //サーフェイスが列挙した順ではなく、preferencesの順で選ぶ
+        let preferences = [
+            srgb_nonlinear(vk::Format::B8G8R8A8_SRGB),
+            srgb_nonlinear(vk::Format::R8G8B8A8_SRGB),
+        ];
+        assert_eq!(
+            details.find_surface_format(&preferences),
+            srgb_nonlinear(vk::Format::B8G8R8A8_SRGB)
+        );
+
+        let preferences = [
+            srgb_nonlinear(vk::Format::A2B10G10R10_UNORM_PACK32),
+            srgb_nonlinear(vk::Format::R8G8B8A8_SRGB),
+        ];
+        assert_eq!(
+            details.find_surface_format(&preferences),
+            srgb_nonlinear(vk::Format::R8G8B8A8_SRGB)
+        );
+    }
+
+    #[test]
+    fn color_space_must_match() {
+        let details = support_details(
+            vec![
+                srgb_nonlinear(vk::Format::B8G8R8A8_UNORM),
+                surface_format(
+                    vk::Format::B8G8R8A8_SRGB,
+                    vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT,
+                ),
+                srgb_nonlinear(vk::Format::R8G8B8A8_SRGB),
+            ],
+            vec![],
+        );
+        let preferences = [
+            srgb_nonlinear(vk::Format::B8G8R8A8_SRGB),
+            srgb_nonlinear(vk::Format::R8G8B8A8_SRGB),
+        ];
+
+        assert_eq!(
+            details.find_surface_format(&preferences),
+            srgb_nonlinear(vk::Format::R8G8B8A8_SRGB)
+        );
+    }
+
+    #[test]
+    fn no_match_falls_back_to_first_format() {
+        let details = support_details(
+            vec![
+                srgb_nonlinear(vk::Format::B8G8R8A8_UNORM),
+                srgb_nonlinear(vk::Format::R8G8B8A8_UNORM),
+            ],
+            vec![],
+        );
+
+        assert_eq!(
+            details.find_surface_format(&SurfaceFormatPreference::Srgb.formats()),
+            srgb_nonlinear(vk::Format::B8G8R8A8_UNORM)
+        );
+        assert_eq!(
+            details.find_surface_format(&[]),
+            srgb_nonlinear(vk::Format::B8G8R8A8_UNORM)
+        );
+    }
+
+    //HDRを有効にしたWindowsのNVIDIAのドライバが返す一覧
+    fn windows_hdr_formats() -> Vec<vk::SurfaceFormatKHR> {
+        vec![
+            srgb_nonlinear(vk::Format::B8G8R8A8_UNORM),
+            srgb_nonlinear(vk::Format::B8G8R8A8_SRGB),
+            srgb_nonlinear(vk::Format::A2B10G10R10_UNORM_PACK32),
+            surface_format(
+                vk::Format::R16G16B16A16_SFLOAT,
+                vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+            ),
+            surface_format(
+                vk::Format::A2B10G10R10_UNORM_PACK32,
+                vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+            ),
+        ]
+    }
+
+    //X11のMesaのドライバが返す一覧、HDRの色空間は無い
+    fn mesa_x11_formats() -> Vec<vk::SurfaceFormatKHR> {
+        vec![
+            srgb_nonlinear(vk::Format::B8G8R8A8_SRGB),
+            srgb_nonlinear(vk::Format::B8G8R8A8_UNORM),
+        ]
+    }
+
+    //AndroidのドライバはR8G8B8A8を先に返す
+    fn android_formats() -> Vec<vk::SurfaceFormatKHR> {
+        vec![
+            srgb_nonlinear(vk::Format::R8G8B8A8_UNORM),
+            srgb_nonlinear(vk::Format::R8G8B8A8_SRGB),
+            srgb_nonlinear(vk::Format::R5G6B5_UNORM_PACK16),
+        ]
+    }
+
+    fn chosen(
+        formats: Vec<vk::SurfaceFormatKHR>,
+        preference: SurfaceFormatPreference,
+    ) -> vk::SurfaceFormatKHR {
+        support_details(formats, vec![]).choose_swap_surface_format(&preference.formats())
+    }
+
+    #[test]
+    fn every_preference_ends_with_the_srgb_fallbacks() {
+        let srgb = SurfaceFormatPreference::Srgb.formats();
+
+        for preference in [
+            SurfaceFormatPreference::Srgb,
+            SurfaceFormatPreference::Hdr10,
+            SurfaceFormatPreference::ScRgb,
+            SurfaceFormatPreference::Hdr,
+        ] {
+            assert!(preference.formats().ends_with(&srgb), "{:?}", preference);
+        }
+    }
+
+    #[test]
+    fn hdr_preferences_on_windows() {
+        let hdr10 = surface_format(
+            vk::Format::A2B10G10R10_UNORM_PACK32,
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+        );
+        let scrgb = surface_format(
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+        );
+
+        assert_eq!(
+            chosen(windows_hdr_formats(), SurfaceFormatPreference::Hdr),
+            hdr10
+        );
+        assert_eq!(
+            chosen(windows_hdr_formats(), SurfaceFormatPreference::Hdr10),
+            hdr10
+        );
+        assert_eq!(
+            chosen(windows_hdr_formats(), SurfaceFormatPreference::ScRgb),
+            scrgb
+        );
+        assert_eq!(
+            chosen(windows_hdr_formats(), SurfaceFormatPreference::Srgb),
+            srgb_nonlinear(vk::Format::B8G8R8A8_SRGB)
+        );
+        assert_eq!(
+            chosen(windows_hdr_formats(), SurfaceFormatPreference::Unorm),
+            srgb_nonlinear(vk::Format::B8G8R8A8_UNORM)
+        );
+    }
+
+    #[test]
+    fn hdr_preferences_fall_back_to_srgb_without_hdr_color_spaces() {
+        for preference in [
+            SurfaceFormatPreference::Hdr10,
+            SurfaceFormatPreference::ScRgb,
+            SurfaceFormatPreference::Hdr,
+        ] {
+            assert_eq!(
+                chosen(mesa_x11_formats(), preference),
+                srgb_nonlinear(vk::Format::B8G8R8A8_SRGB),
+                "{:?}",
+                preference
+            );
+            assert_eq!(
+                chosen(android_formats(), preference),
+                srgb_nonlinear(vk::Format::R8G8B8A8_SRGB),
+                "{:?}",
+                preference
+            );
+        }
+    }
+
+    #[test]
+    fn sdr_preferences_on_android() {
+        assert_eq!(
+            chosen(android_formats(), SurfaceFormatPreference::Srgb),
+            srgb_nonlinear(vk::Format::R8G8B8A8_SRGB)
+        );
+        assert_eq!(
+            chosen(android_formats(), SurfaceFormatPreference::Unorm),
+            srgb_nonlinear(vk::Format::R8G8B8A8_UNORM)
+        );
+    }
+
+    #[test]
+    fn chosen_formats_map_to_the_shader_output() {
+        use crate::color_space::{ColorEncoding, ColorFormat};
+
+        let output = |formats, preference| {
+            ColorFormat::from_surface_format(chosen(formats, preference)).shader_output()
+        };
+
+        //--raytraceのblitもこの値でエンコードするので、HDR10ではPQになっている必要がある
+        assert_eq!(
+            output(windows_hdr_formats(), SurfaceFormatPreference::Hdr),
+            ColorEncoding::Pq
+        );
+        assert_eq!(
+            output(windows_hdr_formats(), SurfaceFormatPreference::ScRgb),
+            ColorEncoding::Linear
+        );
+        assert_eq!(
+            output(windows_hdr_formats(), SurfaceFormatPreference::Unorm),
+            ColorEncoding::Srgb
+        );
+        assert_eq!(
+            output(mesa_x11_formats(), SurfaceFormatPreference::Hdr),
+            ColorEncoding::Linear
+        );
+    }
+}
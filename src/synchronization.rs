@@ -0,0 +1,366 @@
+use crate::queue_family::QueueFamilyIndices;
+use ash::extensions::khr::Synchronization2;
+use ash::{vk, Device, Instance};
+use std::ffi::CStr;
+
+//デバイスがsynchronization2をどの形で使えるか
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Synchronization2Support {
+    //Vulkan 1.3のコア機能
+    Core,
+    //VK_KHR_synchronization2
+    Extension,
+    Unsupported,
+}
+
+impl Synchronization2Support {
+    //api_versionはdevice_info::effective_api_versionで決めたもの
+    pub fn query(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        api_version: u32,
+    ) -> Self {
+        //インスタンスが1.3でもデバイスが1.2以下ならコアの関数は使えない
+        let is_core = api_version >= vk::make_api_version(0, 1, 3, 0);
+        //機能の確認にVulkan 1.1のvkGetPhysicalDeviceFeatures2を使う
+        let is_extension = !is_core
+            && api_version >= vk::make_api_version(0, 1, 1, 0)
+            && QueueFamilyIndices::is_device_extension_supported(
+                instance,
+                physical_device,
+                Synchronization2::name(),
+            );
+
+        if !(is_core || is_extension) {
+            return Self::Unsupported;
+        }
+
+        let mut synchronization2_features = vk::PhysicalDeviceSynchronization2Features::default();
+        let mut features = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut synchronization2_features)
+            .build();
+
+        unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+
+        if synchronization2_features.synchronization2 != vk::TRUE {
+            Self::Unsupported
+        } else if is_core {
+            Self::Core
+        } else {
+            Self::Extension
+        }
+    }
+
+    //DeviceCreateInfoで有効にする必要のある拡張
+    pub fn extension_name(self) -> Option<&'static CStr> {
+        match self {
+            Self::Extension => Some(Synchronization2::name()),
+            _ => None,
+        }
+    }
+}
+
+enum Backend {
+    Core,
+    Extension(Synchronization2),
+    //vkCmdPipelineBarrierとvkQueueSubmitに変換する
+    Legacy,
+}
+
+//バリアとsubmitの呼び出しをまとめる
+//呼び出し側は常にsynchronization2の細かいステージとアクセスを指定し、使えないデバイスでは古いフラグに丸める
+pub struct Synchronization {
+    backend: Backend,
+}
+
+impl Synchronization {
+    pub fn new(instance: &Instance, device: &Device, support: Synchronization2Support) -> Self {
+        let backend = match support {
+            Synchronization2Support::Core => Backend::Core,
+            Synchronization2Support::Extension => {
+                Backend::Extension(Synchronization2::new(instance, device))
+            }
+            Synchronization2Support::Unsupported => Backend::Legacy,
+        };
+
+        Self { backend }
+    }
+
+    pub fn is_synchronization2(&self) -> bool {
+        !matches!(self.backend, Backend::Legacy)
+    }
+
+    pub fn cmd_pipeline_barrier(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        memory_barriers: &[vk::MemoryBarrier2],
+        buffer_barriers: &[vk::BufferMemoryBarrier2],
+        image_barriers: &[vk::ImageMemoryBarrier2],
+    ) {
+        let dependency_info = vk::DependencyInfo::builder()
+            .memory_barriers(memory_barriers)
+            .buffer_memory_barriers(buffer_barriers)
+            .image_memory_barriers(image_barriers)
+            .build();
+
+        match &self.backend {
+            Backend::Core => unsafe {
+                device.cmd_pipeline_barrier2(command_buffer, &dependency_info)
+            },
+            Backend::Extension(synchronization2) => unsafe {
+                synchronization2.cmd_pipeline_barrier2(command_buffer, &dependency_info)
+            },
+            Backend::Legacy => {
+                Self::cmd_legacy_pipeline_barrier(
+                    device,
+                    command_buffer,
+                    memory_barriers,
+                    buffer_barriers,
+                    image_barriers,
+                );
+            }
+        }
+    }
+
+    //古いAPIではバリアごとではなくコマンドに対してステージを1組だけ指定するので全てのバリアのステージをまとめる
+    fn cmd_legacy_pipeline_barrier(
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        memory_barriers: &[vk::MemoryBarrier2],
+        buffer_barriers: &[vk::BufferMemoryBarrier2],
+        image_barriers: &[vk::ImageMemoryBarrier2],
+    ) {
+        let mut src_stage_mask = vk::PipelineStageFlags2::NONE;
+        let mut dst_stage_mask = vk::PipelineStageFlags2::NONE;
+
+        let memory_barriers = memory_barriers
+            .iter()
+            .map(|barrier| {
+                src_stage_mask |= barrier.src_stage_mask;
+                dst_stage_mask |= barrier.dst_stage_mask;
+
+                vk::MemoryBarrier::builder()
+                    .src_access_mask(legacy_access(barrier.src_access_mask))
+                    .dst_access_mask(legacy_access(barrier.dst_access_mask))
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let buffer_barriers = buffer_barriers
+            .iter()
+            .map(|barrier| {
+                src_stage_mask |= barrier.src_stage_mask;
+                dst_stage_mask |= barrier.dst_stage_mask;
+
+                vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(legacy_access(barrier.src_access_mask))
+                    .dst_access_mask(legacy_access(barrier.dst_access_mask))
+                    .src_queue_family_index(barrier.src_queue_family_index)
+                    .dst_queue_family_index(barrier.dst_queue_family_index)
+                    .buffer(barrier.buffer)
+                    .offset(barrier.offset)
+                    .size(barrier.size)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let image_barriers = image_barriers
+            .iter()
+            .map(|barrier| {
+                src_stage_mask |= barrier.src_stage_mask;
+                dst_stage_mask |= barrier.dst_stage_mask;
+
+                vk::ImageMemoryBarrier::builder()
+                    .src_access_mask(legacy_access(barrier.src_access_mask))
+                    .dst_access_mask(legacy_access(barrier.dst_access_mask))
+                    .old_layout(barrier.old_layout)
+                    .new_layout(barrier.new_layout)
+                    .src_queue_family_index(barrier.src_queue_family_index)
+                    .dst_queue_family_index(barrier.dst_queue_family_index)
+                    .image(barrier.image)
+                    .subresource_range(barrier.subresource_range)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        //古いAPIでは0を指定できないのでsynchronization2のNONEと同じ意味になるステージにする
+        let src_stage_mask = match legacy_stage(src_stage_mask) {
+            stage if stage.is_empty() => vk::PipelineStageFlags::TOP_OF_PIPE,
+            stage => stage,
+        };
+        let dst_stage_mask = match legacy_stage(dst_stage_mask) {
+            stage if stage.is_empty() => vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            stage => stage,
+        };
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                src_stage_mask,
+                dst_stage_mask,
+                vk::DependencyFlags::empty(),
+                &memory_barriers,
+                &buffer_barriers,
+                &image_barriers,
+            );
+        }
+    }
+
+    //1つのsubmitを投げる
+    //タイムラインセマフォの値はSemaphoreSubmitInfoのvalueで指定し、バイナリセマフォでは無視される
+    pub fn queue_submit(
+        &self,
+        device: &Device,
+        queue: vk::Queue,
+        wait_semaphores: &[vk::SemaphoreSubmitInfo],
+        command_buffers: &[vk::CommandBuffer],
+        signal_semaphores: &[vk::SemaphoreSubmitInfo],
+        fence: vk::Fence,
+    ) -> Result<(), vk::Result> {
+        let synchronization2 = match &self.backend {
+            Backend::Core => None,
+            Backend::Extension(synchronization2) => Some(synchronization2),
+            Backend::Legacy => {
+                return Self::legacy_queue_submit(
+                    device,
+                    queue,
+                    wait_semaphores,
+                    command_buffers,
+                    signal_semaphores,
+                    fence,
+                );
+            }
+        };
+
+        let command_buffer_infos = command_buffers
+            .iter()
+            .map(|command_buffer| {
+                vk::CommandBufferSubmitInfo::builder()
+                    .command_buffer(*command_buffer)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let submit_info = vk::SubmitInfo2::builder()
+            .wait_semaphore_infos(wait_semaphores)
+            .command_buffer_infos(&command_buffer_infos)
+            .signal_semaphore_infos(signal_semaphores)
+            .build();
+
+        unsafe {
+            match synchronization2 {
+                Some(synchronization2) => {
+                    synchronization2.queue_submit2(queue, &[submit_info], fence)
+                }
+                None => device.queue_submit2(queue, &[submit_info], fence),
+            }
+        }
+    }
+
+    fn legacy_queue_submit(
+        device: &Device,
+        queue: vk::Queue,
+        wait_semaphores: &[vk::SemaphoreSubmitInfo],
+        command_buffers: &[vk::CommandBuffer],
+        signal_semaphores: &[vk::SemaphoreSubmitInfo],
+        fence: vk::Fence,
+    ) -> Result<(), vk::Result> {
+        let wait_handles = wait_semaphores
+            .iter()
+            .map(|info| info.semaphore)
+            .collect::<Vec<_>>();
+        let wait_values = wait_semaphores
+            .iter()
+            .map(|info| info.value)
+            .collect::<Vec<_>>();
+        let wait_stages = wait_semaphores
+            .iter()
+            .map(|info| legacy_stage(info.stage_mask))
+            .collect::<Vec<_>>();
+        //古いAPIではsubmitの全てのコマンドが終わった時にシグナルされるのでステージは使わない
+        let signal_handles = signal_semaphores
+            .iter()
+            .map(|info| info.semaphore)
+            .collect::<Vec<_>>();
+        let signal_values = signal_semaphores
+            .iter()
+            .map(|info| info.value)
+            .collect::<Vec<_>>();
+
+        //タイムラインセマフォが含まれていなくても付けて良い
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .wait_semaphore_values(&wait_values)
+            .signal_semaphore_values(&signal_values)
+            .build();
+
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_handles)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(command_buffers)
+            .signal_semaphores(&signal_handles)
+            .push_next(&mut timeline_info)
+            .build();
+
+        unsafe { device.queue_submit(queue, &[submit_info], fence) }
+    }
+}
+
+//queue_submitに渡すセマフォ
+//バイナリセマフォの場合はvalueに0を入れる
+pub fn semaphore_submit_info(
+    semaphore: vk::Semaphore,
+    value: u64,
+    stage_mask: vk::PipelineStageFlags2,
+) -> vk::SemaphoreSubmitInfo {
+    vk::SemaphoreSubmitInfo::builder()
+        .semaphore(semaphore)
+        .value(value)
+        .stage_mask(stage_mask)
+        .build()
+}
+
+//synchronization2で細かく分かれたステージを、それを含む古いステージに丸める
+//古いステージのビットはsynchronization2でも同じ値なので下位32bitはそのまま使える
+fn legacy_stage(stage_mask: vk::PipelineStageFlags2) -> vk::PipelineStageFlags {
+    let mut stage_mask = stage_mask;
+
+    if stage_mask.intersects(
+        vk::PipelineStageFlags2::COPY
+            | vk::PipelineStageFlags2::RESOLVE
+            | vk::PipelineStageFlags2::BLIT
+            | vk::PipelineStageFlags2::CLEAR,
+    ) {
+        stage_mask |= vk::PipelineStageFlags2::TRANSFER;
+    }
+
+    if stage_mask.intersects(
+        vk::PipelineStageFlags2::INDEX_INPUT | vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT,
+    ) {
+        stage_mask |= vk::PipelineStageFlags2::VERTEX_INPUT;
+    }
+
+    //テッセレーションとジオメトリシェーダーは機能を有効にしていないとステージに指定できないので頂点シェーダーだけにする
+    if stage_mask.contains(vk::PipelineStageFlags2::PRE_RASTERIZATION_SHADERS) {
+        stage_mask |= vk::PipelineStageFlags2::VERTEX_SHADER;
+    }
+
+    vk::PipelineStageFlags::from_raw(stage_mask.as_raw() as u32)
+}
+
+//アクセスもステージと同様に丸める
+fn legacy_access(access_mask: vk::AccessFlags2) -> vk::AccessFlags {
+    let mut access_mask = access_mask;
+
+    if access_mask
+        .intersects(vk::AccessFlags2::SHADER_SAMPLED_READ | vk::AccessFlags2::SHADER_STORAGE_READ)
+    {
+        access_mask |= vk::AccessFlags2::SHADER_READ;
+    }
+
+    if access_mask.contains(vk::AccessFlags2::SHADER_STORAGE_WRITE) {
+        access_mask |= vk::AccessFlags2::SHADER_WRITE;
+    }
+
+    vk::AccessFlags::from_raw(access_mask.as_raw() as u32)
+}
@@ -0,0 +1,149 @@
+use crate::mesh::{Mesh, Vertex};
+use crate::one_time_commands::OneTimeCommands;
+use crate::synchronization::Synchronization;
+use ash::{vk, Device, Instance};
+use std::mem;
+
+//パッチ1つの制御点の数、四角形の4隅
+pub const PATCH_CONTROL_POINTS: u32 = 4;
+
+//平面の大きさと高さ、高さの関数の変位はシェーダー側で足す
+const PLANE_SIZE: f32 = 4.0;
+const PLANE_HEIGHT: f32 = -1.0;
+//1辺あたりのパッチの数
+const PATCHES_PER_SIDE: u32 = 4;
+
+//起動時の分割数と、1回のキー入力で掛ける倍率
+const INITIAL_LEVEL: f32 = 8.0;
+const LEVEL_STEP: f32 = 2.0;
+
+//シェーダー側のTessellationConstantsと同じレイアウト
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct TessellationConstants {
+    pub level: f32,
+}
+
+//--tessellationでパッチの四角形を並べた平面を、評価シェーダーで高さの関数に沿って変位させて描画する
+//分割数はpush constantで渡すので、キー入力で変えてもパイプラインを作り直さなくて良い
+pub struct TessellatedPlane {
+    //PATCH_CONTROL_POINTSずつのインデックスが1つのパッチになる
+    mesh: Mesh,
+    level: f32,
+    //デバイスのmax_tessellation_generation_level
+    max_level: f32,
+}
+
+impl TessellatedPlane {
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+    ) -> Self {
+        let props = unsafe { instance.get_physical_device_properties(physical_device) };
+        let max_level = props.limits.max_tessellation_generation_level as f32;
+
+        let patch_size = PLANE_SIZE / PATCHES_PER_SIDE as f32;
+        let side = PATCHES_PER_SIDE + 1;
+
+        //colorとnormalは評価シェーダーで作り直すので使わない
+        let vertices = (0..side)
+            .flat_map(|z| (0..side).map(move |x| (x, z)))
+            .map(|(x, z)| Vertex {
+                position: [
+                    x as f32 * patch_size - PLANE_SIZE / 2.0,
+                    PLANE_HEIGHT,
+                    z as f32 * patch_size - PLANE_SIZE / 2.0,
+                ],
+                color: [1.0, 1.0, 1.0],
+                normal: [0.0, 1.0, 0.0],
+                tex_coord: [0.0; 2],
+                tangent: Vertex::NO_TANGENT,
+            })
+            .collect::<Vec<_>>();
+
+        //評価シェーダーのtess_coordに合わせて(0, 0), (1, 0), (1, 1), (0, 1)の順に並べる
+        let indices = (0..PATCHES_PER_SIDE)
+            .flat_map(|z| (0..PATCHES_PER_SIDE).map(move |x| (x, z)))
+            .flat_map(|(x, z)| {
+                let corner = z * side + x;
+                [corner, corner + 1, corner + side + 1, corner + side]
+            })
+            .collect::<Vec<_>>();
+
+        let mesh = Mesh::new(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            &vertices,
+            &indices,
+        );
+
+        Self {
+            mesh,
+            level: INITIAL_LEVEL.min(max_level),
+            max_level,
+        }
+    }
+
+    pub fn raise_level(&mut self) {
+        self.set_level(self.level * LEVEL_STEP);
+    }
+
+    pub fn lower_level(&mut self) {
+        self.set_level(self.level / LEVEL_STEP);
+    }
+
+    fn set_level(&mut self, level: f32) {
+        self.level = level.clamp(1.0, self.max_level);
+
+        log::info!("tessellation level: {}", self.level);
+    }
+
+    //パイプラインとset = 0をここで紐づけるので、この後に他の物を描画する場合は紐づけ直す必要がある
+    pub fn cmd_draw(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        (pipeline, pipeline_layout): (vk::Pipeline, vk::PipelineLayout),
+        uniform_descriptor_set: vk::DescriptorSet,
+    ) {
+        let constants = TessellationConstants { level: self.level };
+
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &[uniform_descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline_layout,
+                vk::ShaderStageFlags::TESSELLATION_CONTROL,
+                0,
+                std::slice::from_raw_parts(
+                    &constants as *const TessellationConstants as *const u8,
+                    mem::size_of::<TessellationConstants>(),
+                ),
+            );
+        }
+
+        self.mesh.cmd_bind(device, command_buffer);
+
+        unsafe {
+            device.cmd_draw_indexed(command_buffer, self.mesh.index_count(), 1, 0, 0, 0);
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.mesh.destroy(device);
+    }
+}
@@ -0,0 +1,414 @@
+use crate::buffer;
+use crate::memory_budget::{self, MemoryCategory};
+use crate::mipmap::{self, MipGenerator, MipmapMode};
+use crate::one_time_commands::OneTimeCommands;
+use crate::readback;
+use crate::synchronization::Synchronization;
+use ash::{vk, Device, Instance};
+
+//ミップマップの1段分のデータがdataのどこにあるか
+//圧縮フォーマットの場合もextentはブロックではなく画素数
+#[derive(Clone, Copy, Debug)]
+pub struct MipLevel {
+    pub extent: vk::Extent2D,
+    pub offset: vk::DeviceSize,
+}
+
+//Texture2D::with_optionsで0段目からミップマップをどう作るか
+#[derive(Clone, Copy, Default)]
+pub struct TextureOptions<'a> {
+    //Noneの場合や指定した方法がフォーマットで使えない場合はMipmapMode::selectで選ぶ
+    pub mipmap_mode: Option<MipmapMode>,
+    //MipmapMode::Computeに使う、Noneの場合はComputeを選ばない
+    pub mip_generator: Option<&'a MipGenerator>,
+}
+
+//2Dのテクスチャ
+//作成時に全てのミップマップを転送し、SHADER_READ_ONLY_OPTIMALにしておく
+pub struct Texture2D {
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+    format: vk::Format,
+    //0段目の大きさ
+    extent: vk::Extent2D,
+    level_count: u32,
+    //確保したメモリの大きさ、ログに出す
+    memory_size: vk::DeviceSize,
+}
+
+impl Texture2D {
+    //ミップマップの無いテクスチャ
+    //pixelsはformatの画素がextentの大きさに詰めて並んだもの
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        pixels: &[u8],
+    ) -> Self {
+        Self::with_mip_levels(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            format,
+            &[MipLevel { extent, offset: 0 }],
+            pixels,
+        )
+    }
+
+    //levels[0]が一番大きいミップマップで、ミップマップの生成はせずにlevelsをそのまま転送する
+    //圧縮フォーマットの場合、各段のoffsetはブロックの大きさの倍数である必要がある
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_mip_levels(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        format: vk::Format,
+        levels: &[MipLevel],
+        data: &[u8],
+    ) -> Self {
+        Self::create(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            format,
+            levels,
+            data,
+            levels.len() as u32,
+            MipmapMode::None,
+            None,
+        )
+    }
+
+    //pixelsを0段目として転送し、残りの段をoptionsの方法でGPU上で生成する
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_options(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        pixels: &[u8],
+        options: TextureOptions,
+    ) -> Self {
+        let mipmap_mode = MipmapMode::select(
+            instance,
+            physical_device,
+            format,
+            options.mipmap_mode,
+            options.mip_generator,
+        );
+
+        let level_count = match mipmap_mode {
+            MipmapMode::None => 1,
+            _ => mipmap::level_count(extent),
+        };
+
+        log::debug!(
+            "Texture {}x{} {:?}: {} mip levels ({})",
+            extent.width,
+            extent.height,
+            format,
+            level_count,
+            mipmap_mode.name()
+        );
+
+        Self::create(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            format,
+            &[MipLevel { extent, offset: 0 }],
+            pixels,
+            level_count,
+            mipmap_mode,
+            options.mip_generator,
+        )
+    }
+
+    //levelsを転送し、level_countまでの残りの段をmipmap_modeで生成する
+    //MipmapMode::Noneの場合はlevelsが全ての段を持つ
+    #[allow(clippy::too_many_arguments)]
+    fn create(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        format: vk::Format,
+        levels: &[MipLevel],
+        data: &[u8],
+        level_count: u32,
+        mipmap_mode: MipmapMode,
+        mip_generator: Option<&MipGenerator>,
+    ) -> Self {
+        let extent = vk::Extent3D {
+            width: levels[0].extent.width,
+            height: levels[0].extent.height,
+            depth: 1,
+        };
+
+        //生成する場合は読み戻せるようにTRANSFER_SRCも付ける
+        let usage = vk::ImageUsageFlags::TRANSFER_DST
+            | vk::ImageUsageFlags::SAMPLED
+            | match mipmap_mode {
+                MipmapMode::Blit => vk::ImageUsageFlags::TRANSFER_SRC,
+                MipmapMode::Compute => {
+                    vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::STORAGE
+                }
+                MipmapMode::None => vk::ImageUsageFlags::empty(),
+            };
+
+        //sRGBのフォーマットはSTORAGEに使えないので、UNORMのviewでだけ使うことを示す
+        let flags = match mipmap::storage_format(format) {
+            Some((storage_format, _))
+                if mipmap_mode == MipmapMode::Compute && storage_format != format =>
+            {
+                vk::ImageCreateFlags::MUTABLE_FORMAT | vk::ImageCreateFlags::EXTENDED_USAGE
+            }
+            _ => vk::ImageCreateFlags::empty(),
+        };
+
+        let image_info = vk::ImageCreateInfo::builder()
+            .flags(flags)
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(extent)
+            .mip_levels(level_count)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+
+        let image = unsafe { device.create_image(&image_info, None).unwrap() };
+
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
+
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(buffer::find_memory_type(
+                instance,
+                physical_device,
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ))
+            .build();
+
+        let memory = unsafe {
+            memory_budget::allocate_memory(device, &alloc_info, MemoryCategory::Texture).unwrap()
+        };
+
+        unsafe { device.bind_image_memory(image, memory, 0).unwrap() };
+
+        let (staging_buffer, staging_memory) = buffer::create_host_buffer_with_data(
+            instance,
+            physical_device,
+            device,
+            data,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+        );
+
+        let to_transfer_dst = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::NONE)
+            .src_access_mask(vk::AccessFlags2::NONE)
+            .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(Self::subresource_range(level_count))
+            .build();
+
+        let regions = levels
+            .iter()
+            .enumerate()
+            .map(|(mip_level, level)| {
+                vk::BufferImageCopy::builder()
+                    .buffer_offset(level.offset)
+                    .buffer_row_length(0)
+                    .buffer_image_height(0)
+                    .image_subresource(
+                        vk::ImageSubresourceLayers::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .mip_level(mip_level as u32)
+                            .base_array_layer(0)
+                            .layer_count(1)
+                            .build(),
+                    )
+                    .image_offset(vk::Offset3D::default())
+                    .image_extent(vk::Extent3D {
+                        width: level.extent.width,
+                        height: level.extent.height,
+                        depth: 1,
+                    })
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let to_shader_read = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::COPY)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(Self::subresource_range(level_count))
+            .build();
+
+        //コマンドの実行が終わるまでviewとDescriptor Setを残しておく
+        let compute_mips = match mipmap_mode {
+            MipmapMode::Compute => Some(
+                mip_generator
+                    .expect("MipmapMode::Compute requires a MipGenerator")
+                    .prepare(device, image, format, levels[0].extent, level_count),
+            ),
+            _ => None,
+        };
+
+        one_time_commands
+            .run(device, synchronization, |command_buffer| unsafe {
+                synchronization.cmd_pipeline_barrier(
+                    device,
+                    command_buffer,
+                    &[],
+                    &[],
+                    &[to_transfer_dst],
+                );
+                device.cmd_copy_buffer_to_image(
+                    command_buffer,
+                    staging_buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &regions,
+                );
+
+                //どの方法も最後に全ての段をSHADER_READ_ONLY_OPTIMALにする
+                match (mipmap_mode, &compute_mips) {
+                    (MipmapMode::Blit, _) => mipmap::cmd_generate_blit(
+                        device,
+                        synchronization,
+                        command_buffer,
+                        image,
+                        levels[0].extent,
+                        level_count,
+                    ),
+                    (MipmapMode::Compute, Some(compute_mips)) => {
+                        compute_mips.cmd_generate(device, synchronization, command_buffer)
+                    }
+                    _ => synchronization.cmd_pipeline_barrier(
+                        device,
+                        command_buffer,
+                        &[],
+                        &[],
+                        &[to_shader_read],
+                    ),
+                }
+            })
+            .expect("Failed to upload texture");
+
+        unsafe {
+            device.destroy_buffer(staging_buffer, None);
+            memory_budget::free_memory(device, staging_memory);
+        }
+
+        if let Some(compute_mips) = compute_mips {
+            compute_mips.destroy(device);
+        }
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(Self::subresource_range(level_count))
+            .build();
+
+        let view = unsafe { device.create_image_view(&view_info, None).unwrap() };
+
+        Self {
+            image,
+            memory,
+            view,
+            format,
+            extent: levels[0].extent,
+            level_count,
+            memory_size: requirements.size,
+        }
+    }
+
+    //全ての段をRGBA8としてホストのメモリに読み戻す、--compare-mipmapsで使う
+    //ミップマップを生成したテクスチャのみで、TRANSFER_SRCが付いていない場合は読めない
+    pub fn read_levels(
+        &self,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+    ) -> Result<Vec<Vec<u8>>, String> {
+        (0..self.level_count)
+            .map(|level| {
+                readback::read_image_level_to_vec(
+                    instance,
+                    physical_device,
+                    device,
+                    one_time_commands,
+                    synchronization,
+                    self.image,
+                    mipmap::level_extent(self.extent, level),
+                    self.format,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    level,
+                )
+            })
+            .collect()
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    pub fn memory_size(&self) -> vk::DeviceSize {
+        self.memory_size
+    }
+
+    fn subresource_range(level_count: u32) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(level_count)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build()
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+            memory_budget::free_memory(device, self.memory);
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use crate::buffer;
+use crate::descriptor_allocator::{DescriptorAllocator, DescriptorLayoutCache};
+use crate::memory_budget::{self, MemoryCategory};
+use crate::one_time_commands::OneTimeCommands;
+use crate::synchronization::Synchronization;
+use ash::{vk, Device, Instance};
+
+//各レイヤーの1辺のピクセル数
+const LAYER_SIZE: u32 = 64;
+//チェッカー模様の1辺のマス目の数
+const LAYER_CELLS: u32 = 4;
+
+const FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+//--texture-array Nで--instanced-gridのインスタンスごとに選ぶN枚のレイヤーを持つテクスチャ
+//レイヤーごとに色を変えたチェッカー模様を生成し、TYPE_2D_ARRAYのimage viewで1つのテクスチャとして読む
+//メインのパイプラインのset = 2で、binding = 0がsampler、binding = 1がテクスチャ
+pub struct TextureArray {
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+    //DescriptorLayoutCacheが持つので破棄しない
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_allocator: DescriptorAllocator,
+    descriptor_set: vk::DescriptorSet,
+}
+
+impl TextureArray {
+    //layer_countがmax_image_array_layersを超える場合はErrを返す
+    //samplerの破棄はSamplerCacheに、Descriptor Set Layoutの破棄はDescriptorLayoutCacheに任せる
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        descriptor_layout_cache: &mut DescriptorLayoutCache,
+        sampler: vk::Sampler,
+        layer_count: u32,
+    ) -> Result<Self, String> {
+        let max_layers = unsafe {
+            instance
+                .get_physical_device_properties(physical_device)
+                .limits
+                .max_image_array_layers
+        };
+
+        if layer_count > max_layers {
+            return Err(format!(
+                "{} texture array layers exceed max_image_array_layers ({})",
+                layer_count, max_layers
+            ));
+        }
+
+        let extent = vk::Extent3D {
+            width: LAYER_SIZE,
+            height: LAYER_SIZE,
+            depth: 1,
+        };
+
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(FORMAT)
+            .extent(extent)
+            .mip_levels(1)
+            .array_layers(layer_count)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+
+        let image = unsafe { device.create_image(&image_info, None).unwrap() };
+
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
+
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(buffer::find_memory_type(
+                instance,
+                physical_device,
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ))
+            .build();
+
+        let memory = unsafe {
+            memory_budget::allocate_memory(device, &alloc_info, MemoryCategory::Texture).unwrap()
+        };
+
+        unsafe { device.bind_image_memory(image, memory, 0).unwrap() };
+
+        //レイヤーごとに詰めて並べる
+        let pixels = (0..layer_count)
+            .flat_map(|layer| Self::checker(Self::layer_color(layer, layer_count)))
+            .collect::<Vec<_>>();
+        let layer_bytes = (pixels.len() / layer_count as usize) as vk::DeviceSize;
+
+        let (staging_buffer, staging_memory) = buffer::create_host_buffer_with_data(
+            instance,
+            physical_device,
+            device,
+            &pixels,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+        );
+
+        let to_transfer_dst = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::NONE)
+            .src_access_mask(vk::AccessFlags2::NONE)
+            .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(Self::subresource_range(layer_count))
+            .build();
+
+        //レイヤーごとに1つのリージョンでコピーする
+        let regions = (0..layer_count)
+            .map(|layer| {
+                vk::BufferImageCopy::builder()
+                    .buffer_offset(layer as vk::DeviceSize * layer_bytes)
+                    .buffer_row_length(0)
+                    .buffer_image_height(0)
+                    .image_subresource(
+                        vk::ImageSubresourceLayers::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .mip_level(0)
+                            .base_array_layer(layer)
+                            .layer_count(1)
+                            .build(),
+                    )
+                    .image_offset(vk::Offset3D::default())
+                    .image_extent(extent)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let to_shader_read = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::COPY)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(Self::subresource_range(layer_count))
+            .build();
+
+        one_time_commands
+            .run(device, synchronization, |command_buffer| unsafe {
+                synchronization.cmd_pipeline_barrier(
+                    device,
+                    command_buffer,
+                    &[],
+                    &[],
+                    &[to_transfer_dst],
+                );
+                device.cmd_copy_buffer_to_image(
+                    command_buffer,
+                    staging_buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &regions,
+                );
+                synchronization.cmd_pipeline_barrier(
+                    device,
+                    command_buffer,
+                    &[],
+                    &[],
+                    &[to_shader_read],
+                );
+            })
+            .expect("Failed to upload the texture array");
+
+        unsafe {
+            device.destroy_buffer(staging_buffer, None);
+            memory_budget::free_memory(device, staging_memory);
+        }
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D_ARRAY)
+            .format(FORMAT)
+            .subresource_range(Self::subresource_range(layer_count))
+            .build();
+
+        let view = unsafe { device.create_image_view(&view_info, None).unwrap() };
+
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        ];
+
+        let descriptor_set_layout = descriptor_layout_cache.get(device, &bindings, &[]);
+
+        let mut descriptor_allocator = DescriptorAllocator::new();
+        let descriptor_set = descriptor_allocator.allocate(device, descriptor_set_layout, None);
+
+        let sampler_info = [vk::DescriptorImageInfo::builder().sampler(sampler).build()];
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()];
+
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .image_info(&sampler_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(&image_info)
+                .build(),
+        ];
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        log::info!(
+            "Texture array: {} layers of {}x{} (max_image_array_layers: {})",
+            layer_count,
+            LAYER_SIZE,
+            LAYER_SIZE,
+            max_layers
+        );
+
+        Ok(Self {
+            image,
+            memory,
+            view,
+            descriptor_set_layout,
+            descriptor_allocator,
+            descriptor_set,
+        })
+    }
+
+    //パイプラインレイアウトのset = 2に使う
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    pub fn cmd_bind(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                2,
+                &[self.descriptor_set],
+                &[],
+            );
+        }
+    }
+
+    //色相をレイヤーの数で等分する
+    fn layer_color(layer: u32, layer_count: u32) -> [u8; 3] {
+        let hue = layer as f32 / layer_count as f32 * 6.0;
+        let x = 1.0 - (hue % 2.0 - 1.0).abs();
+
+        let [r, g, b] = match hue as u32 {
+            0 => [1.0, x, 0.0],
+            1 => [x, 1.0, 0.0],
+            2 => [0.0, 1.0, x],
+            3 => [0.0, x, 1.0],
+            4 => [x, 0.0, 1.0],
+            _ => [1.0, 0.0, x],
+        };
+
+        [(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8]
+    }
+
+    //白とcolorのチェッカー模様
+    fn checker(color: [u8; 3]) -> Vec<u8> {
+        let cell_size = LAYER_SIZE / LAYER_CELLS;
+
+        (0..LAYER_SIZE)
+            .flat_map(|y| (0..LAYER_SIZE).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                let [r, g, b] = if (x / cell_size + y / cell_size) % 2 == 0 {
+                    color
+                } else {
+                    [255, 255, 255]
+                };
+                [r, g, b, 255]
+            })
+            .collect()
+    }
+
+    fn subresource_range(layer_count: u32) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(layer_count)
+            .build()
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.descriptor_allocator.destroy(device);
+
+        unsafe {
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+            memory_budget::free_memory(device, self.memory);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use glam::Vec2;
+
+//隣の画像の色がフィルタリングで滲まないように画像の間に空けるピクセル数
+const PADDING: u32 = 1;
+
+//テクスチャの中の長方形、uvは0.0から1.0
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UvRect {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl UvRect {
+    //テクスチャ全体
+    pub const FULL: Self = Self {
+        min: Vec2::ZERO,
+        max: Vec2::ONE,
+    };
+}
+
+//いくつもの小さなRGBA8の画像を1枚の画像に詰め、それぞれのUvRectを返す
+//同じテクスチャを使うスプライトはSpriteBatchで1回の描画にまとまるので、画像をまとめると描画の回数が減る
+//左上から右に並べ、行に入らなくなったらそれまでの最も高い画像の下に次の行を始める
+pub struct TextureAtlas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    //次に置く位置と今の行の高さ
+    cursor_x: u32,
+    cursor_y: u32,
+    row_height: u32,
+}
+
+impl TextureAtlas {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+            cursor_x: 0,
+            cursor_y: 0,
+            row_height: 0,
+        }
+    }
+
+    //pixelsはwidth x heightのRGBA8
+    //アトラスに入りきらない場合はNone
+    pub fn add(&mut self, width: u32, height: u32, pixels: &[u8]) -> Option<UvRect> {
+        assert_eq!(pixels.len(), (width * height * 4) as usize);
+
+        if self.cursor_x + width > self.width {
+            self.cursor_x = 0;
+            self.cursor_y += self.row_height + PADDING;
+            self.row_height = 0;
+        }
+
+        if self.cursor_x + width > self.width || self.cursor_y + height > self.height {
+            return None;
+        }
+
+        let (x, y) = (self.cursor_x, self.cursor_y);
+
+        for row in 0..height {
+            let source = (row * width * 4) as usize;
+            let destination = (((y + row) * self.width + x) * 4) as usize;
+            let length = (width * 4) as usize;
+
+            self.pixels[destination..destination + length]
+                .copy_from_slice(&pixels[source..source + length]);
+        }
+
+        self.cursor_x += width + PADDING;
+        self.row_height = self.row_height.max(height);
+
+        let size = Vec2::new(self.width as f32, self.height as f32);
+
+        Some(UvRect {
+            min: Vec2::new(x as f32, y as f32) / size,
+            max: Vec2::new((x + width) as f32, (y + height) as f32) / size,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+}
@@ -0,0 +1,494 @@
+use ash::vk;
+
+//デバイスでサンプリングできないフォーマットをCPUでRGBA8に展開する
+//BC1からBC5のブロック圧縮と、3バイトのRGBに対応する
+//BC6H、BC7、ETC2、ASTCはここでは展開できない
+
+//展開した結果を入れるフォーマット、sRGBかどうかは元のフォーマットに合わせる
+pub fn rgba8_format(format: vk::Format) -> Option<vk::Format> {
+    let srgb = match format {
+        vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::R8G8B8_SRGB
+        | vk::Format::B8G8R8_SRGB => true,
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::R8G8B8_UNORM
+        | vk::Format::B8G8R8_UNORM => false,
+        _ => return None,
+    };
+
+    Some(if srgb {
+        vk::Format::R8G8B8A8_SRGB
+    } else {
+        vk::Format::R8G8B8A8_UNORM
+    })
+}
+
+//1ブロックのバイト数と、ブロックの一辺のテクセル数
+fn block_layout(format: vk::Format) -> Option<(usize, usize)> {
+    match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK => Some((8, 4)),
+        vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK => Some((16, 4)),
+        vk::Format::R8G8B8_UNORM
+        | vk::Format::R8G8B8_SRGB
+        | vk::Format::B8G8R8_UNORM
+        | vk::Format::B8G8R8_SRGB => Some((3, 1)),
+        _ => None,
+    }
+}
+
+//extentの大きさのformatの画像1段分のバイト数
+//ブロック圧縮では端の半端なテクセルもブロック1つ分の大きさを持つ
+pub fn level_size(format: vk::Format, extent: vk::Extent2D) -> Option<usize> {
+    let (block_size, block_extent) = block_layout(format)?;
+    let columns = (extent.width as usize + block_extent - 1) / block_extent;
+    let rows = (extent.height as usize + block_extent - 1) / block_extent;
+
+    Some(columns * rows * block_size)
+}
+
+//dataに入ったformatの画像1段分を、隙間無く詰めたRGBA8に展開する
+//sRGBのフォーマットはエンコードされた値のまま返すので、rgba8_formatのフォーマットで使う
+pub fn decode_to_rgba8(
+    format: vk::Format,
+    extent: vk::Extent2D,
+    data: &[u8],
+) -> Result<Vec<u8>, String> {
+    let (block_size, block_extent) =
+        block_layout(format).ok_or_else(|| format!("Cannot decode {:?} to RGBA8", format))?;
+    let required = level_size(format, extent).unwrap();
+
+    if data.len() < required {
+        return Err(format!(
+            "{} bytes is too small for a {}x{} {:?} image",
+            data.len(),
+            extent.width,
+            extent.height,
+            format
+        ));
+    }
+
+    let width = extent.width as usize;
+    let height = extent.height as usize;
+    let columns = (width + block_extent - 1) / block_extent;
+
+    let mut pixels = vec![0; width * height * 4];
+
+    for (index, block) in data[..required].chunks_exact(block_size).enumerate() {
+        let texels = decode_block(format, block);
+        let block_x = index % columns * block_extent;
+        let block_y = index / columns * block_extent;
+
+        //画像の端ではブロックの一部だけを使う
+        for y in 0..block_extent.min(height - block_y) {
+            for x in 0..block_extent.min(width - block_x) {
+                let pixel = ((block_y + y) * width + block_x + x) * 4;
+                pixels[pixel..pixel + 4].copy_from_slice(&texels[y * block_extent + x]);
+            }
+        }
+    }
+
+    Ok(pixels)
+}
+
+//ブロック1つをテクセルの左上から行ごとの順に展開する、1テクセルのフォーマットは1要素になる
+fn decode_block(format: vk::Format, block: &[u8]) -> Vec<[u8; 4]> {
+    match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK | vk::Format::BC1_RGB_SRGB_BLOCK => {
+            decode_bc1_color(block, ColorMode::Bc1 { alpha: false }).to_vec()
+        }
+        vk::Format::BC1_RGBA_UNORM_BLOCK | vk::Format::BC1_RGBA_SRGB_BLOCK => {
+            decode_bc1_color(block, ColorMode::Bc1 { alpha: true }).to_vec()
+        }
+        vk::Format::BC2_UNORM_BLOCK | vk::Format::BC2_SRGB_BLOCK => {
+            let mut texels = decode_bc1_color(&block[8..], ColorMode::FourColor);
+            let alpha = read_u64(&block[..8]);
+            for (i, texel) in texels.iter_mut().enumerate() {
+                //4bitを0から255に広げる
+                texel[3] = ((alpha >> (i * 4)) & 0xF) as u8 * 17;
+            }
+            texels.to_vec()
+        }
+        vk::Format::BC3_UNORM_BLOCK | vk::Format::BC3_SRGB_BLOCK => {
+            let mut texels = decode_bc1_color(&block[8..], ColorMode::FourColor);
+            for (texel, alpha) in texels.iter_mut().zip(decode_bc4_channel(&block[..8])) {
+                texel[3] = alpha;
+            }
+            texels.to_vec()
+        }
+        //BC4とBC5をサンプリングすると無いチャンネルは0、アルファは1になる
+        vk::Format::BC4_UNORM_BLOCK => decode_bc4_channel(block)
+            .iter()
+            .map(|&red| [red, 0, 0, 255])
+            .collect(),
+        vk::Format::BC5_UNORM_BLOCK => decode_bc4_channel(&block[..8])
+            .iter()
+            .zip(decode_bc4_channel(&block[8..]))
+            .map(|(&red, green)| [red, green, 0, 255])
+            .collect(),
+        vk::Format::R8G8B8_UNORM | vk::Format::R8G8B8_SRGB => {
+            vec![[block[0], block[1], block[2], 255]]
+        }
+        vk::Format::B8G8R8_UNORM | vk::Format::B8G8R8_SRGB => {
+            vec![[block[2], block[1], block[0], 255]]
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ColorMode {
+    //color0 <= color1の場合は3色と黒、alphaがtrueなら黒は透明になる
+    Bc1 { alpha: bool },
+    //BC2とBC3の色のブロックは常に4色で補間する
+    FourColor,
+}
+
+//BC1の8バイトのブロック、RGB565の端点2つと1テクセル2bitのインデックス
+fn decode_bc1_color(block: &[u8], mode: ColorMode) -> [[u8; 4]; 16] {
+    let color0 = u16::from_le_bytes([block[0], block[1]]);
+    let color1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+
+    let endpoint0 = rgb565(color0);
+    let endpoint1 = rgb565(color1);
+    let mix = |weight0: u32, weight1: u32| {
+        let channel = |c: usize| {
+            let sum = endpoint0[c] as u32 * weight0 + endpoint1[c] as u32 * weight1;
+            let total = weight0 + weight1;
+            ((sum + total / 2) / total) as u8
+        };
+        [channel(0), channel(1), channel(2), 255]
+    };
+
+    let palette = match mode {
+        ColorMode::Bc1 { alpha } if color0 <= color1 => [
+            endpoint0,
+            endpoint1,
+            mix(1, 1),
+            [0, 0, 0, if alpha { 0 } else { 255 }],
+        ],
+        _ => [endpoint0, endpoint1, mix(2, 1), mix(1, 2)],
+    };
+
+    let mut texels = [[0; 4]; 16];
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[(indices >> (i * 2)) as usize & 0x3];
+    }
+    texels
+}
+
+//5bitと6bitのチャンネルは上位ビットを下に繰り返して8bitに広げる
+fn rgb565(color: u16) -> [u8; 4] {
+    let red = (color >> 11) as u8 & 0x1F;
+    let green = (color >> 5) as u8 & 0x3F;
+    let blue = color as u8 & 0x1F;
+
+    [
+        (red << 3) | (red >> 2),
+        (green << 2) | (green >> 4),
+        (blue << 3) | (blue >> 2),
+        255,
+    ]
+}
+
+//BC4の8バイトのブロック、8bitの端点2つと1テクセル3bitのインデックス
+//BC3のアルファとBC5の各チャンネルも同じ形をしている
+fn decode_bc4_channel(block: &[u8]) -> [u8; 16] {
+    let value0 = block[0] as u32;
+    let value1 = block[1] as u32;
+    //下位2バイトは端点なので捨てる
+    let indices = read_u64(block) >> 16;
+
+    let mix = |weight0: u32, weight1: u32| {
+        let total = weight0 + weight1;
+        ((value0 * weight0 + value1 * weight1 + total / 2) / total) as u8
+    };
+
+    let palette = if value0 > value1 {
+        [
+            value0 as u8,
+            value1 as u8,
+            mix(6, 1),
+            mix(5, 2),
+            mix(4, 3),
+            mix(3, 4),
+            mix(2, 5),
+            mix(1, 6),
+        ]
+    } else {
+        //端点の間を4つに補間し、残りの2つは0と255に固定する
+        [
+            value0 as u8,
+            value1 as u8,
+            mix(4, 1),
+            mix(3, 2),
+            mix(2, 3),
+            mix(1, 4),
+            0,
+            255,
+        ]
+    };
+
+    let mut values = [0; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[(indices >> (i * 3)) as usize & 0x7];
+    }
+    values
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(width: u32, height: u32) -> vk::Extent2D {
+        vk::Extent2D { width, height }
+    }
+
+    //全てのテクセルがindexの色を使うBC1のブロック
+    fn bc1_block(color0: u16, color1: u16, index: u32) -> [u8; 8] {
+        let indices = (0..16).fold(0u32, |indices, i| indices | index << (i * 2));
+        let mut block = [0; 8];
+        block[..2].copy_from_slice(&color0.to_le_bytes());
+        block[2..4].copy_from_slice(&color1.to_le_bytes());
+        block[4..].copy_from_slice(&indices.to_le_bytes());
+        block
+    }
+
+    //全てのテクセルがindexの値を使うBC4のブロック
+    fn bc4_block(value0: u8, value1: u8, index: u64) -> [u8; 8] {
+        let indices = (0..16).fold(0u64, |indices, i| indices | index << (i * 3));
+        let mut block = [0; 8];
+        block[0] = value0;
+        block[1] = value1;
+        block[2..].copy_from_slice(&indices.to_le_bytes()[..6]);
+        block
+    }
+
+    fn single_texel(format: vk::Format, block: &[u8]) -> [u8; 4] {
+        let pixels = decode_to_rgba8(format, extent(1, 1), block).unwrap();
+        [pixels[0], pixels[1], pixels[2], pixels[3]]
+    }
+
+    const RED: u16 = 0xF800;
+    const BLUE: u16 = 0x001F;
+
+    #[test]
+    fn rgb565_expands_to_the_full_range() {
+        assert_eq!(rgb565(0xFFFF), [255, 255, 255, 255]);
+        assert_eq!(rgb565(0x0000), [0, 0, 0, 255]);
+        assert_eq!(rgb565(RED), [255, 0, 0, 255]);
+        assert_eq!(rgb565(0x07E0), [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn bc1_four_color_mode() {
+        let format = vk::Format::BC1_RGBA_UNORM_BLOCK;
+
+        assert_eq!(
+            single_texel(format, &bc1_block(RED, BLUE, 0)),
+            [255, 0, 0, 255]
+        );
+        assert_eq!(
+            single_texel(format, &bc1_block(RED, BLUE, 1)),
+            [0, 0, 255, 255]
+        );
+        assert_eq!(
+            single_texel(format, &bc1_block(RED, BLUE, 2)),
+            [170, 0, 85, 255]
+        );
+        assert_eq!(
+            single_texel(format, &bc1_block(RED, BLUE, 3)),
+            [85, 0, 170, 255]
+        );
+    }
+
+    #[test]
+    fn bc1_three_color_mode_has_transparent_black() {
+        assert_eq!(
+            single_texel(vk::Format::BC1_RGBA_UNORM_BLOCK, &bc1_block(BLUE, RED, 2)),
+            [128, 0, 128, 255]
+        );
+        assert_eq!(
+            single_texel(vk::Format::BC1_RGBA_UNORM_BLOCK, &bc1_block(BLUE, RED, 3)),
+            [0, 0, 0, 0]
+        );
+        //BC1_RGBではアルファが無いので不透明な黒になる
+        assert_eq!(
+            single_texel(vk::Format::BC1_RGB_UNORM_BLOCK, &bc1_block(BLUE, RED, 3)),
+            [0, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn bc2_and_bc3_colors_always_use_four_colors() {
+        //color0 <= color1でもBC1の3色モードにはならない
+        let mut block = [0xFF; 16];
+        block[8..].copy_from_slice(&bc1_block(BLUE, RED, 3));
+
+        assert_eq!(
+            single_texel(vk::Format::BC2_UNORM_BLOCK, &block),
+            [170, 0, 85, 255]
+        );
+    }
+
+    #[test]
+    fn bc2_alpha_is_four_bits_per_texel() {
+        let mut block = [0; 16];
+        //1番目のテクセルのアルファが0x8、2番目が0xF
+        block[0] = 0xF8;
+        block[8..].copy_from_slice(&bc1_block(RED, BLUE, 0));
+
+        let pixels = decode_to_rgba8(vk::Format::BC2_UNORM_BLOCK, extent(4, 4), &block).unwrap();
+
+        assert_eq!(pixels[3], 136);
+        assert_eq!(pixels[7], 255);
+        assert_eq!(pixels[11], 0);
+    }
+
+    #[test]
+    fn bc4_eight_value_mode() {
+        let format = vk::Format::BC4_UNORM_BLOCK;
+
+        assert_eq!(
+            single_texel(format, &bc4_block(210, 70, 0)),
+            [210, 0, 0, 255]
+        );
+        assert_eq!(
+            single_texel(format, &bc4_block(210, 70, 1)),
+            [70, 0, 0, 255]
+        );
+        assert_eq!(
+            single_texel(format, &bc4_block(210, 70, 2)),
+            [190, 0, 0, 255]
+        );
+        assert_eq!(
+            single_texel(format, &bc4_block(210, 70, 7)),
+            [90, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn bc4_six_value_mode_has_zero_and_one() {
+        let format = vk::Format::BC4_UNORM_BLOCK;
+
+        assert_eq!(
+            single_texel(format, &bc4_block(50, 100, 2)),
+            [60, 0, 0, 255]
+        );
+        assert_eq!(
+            single_texel(format, &bc4_block(50, 100, 5)),
+            [90, 0, 0, 255]
+        );
+        assert_eq!(single_texel(format, &bc4_block(50, 100, 6)), [0, 0, 0, 255]);
+        assert_eq!(
+            single_texel(format, &bc4_block(50, 100, 7)),
+            [255, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn bc3_alpha_and_bc5_channels_use_the_bc4_block() {
+        let mut bc3 = [0; 16];
+        bc3[..8].copy_from_slice(&bc4_block(210, 70, 2));
+        bc3[8..].copy_from_slice(&bc1_block(RED, BLUE, 1));
+        assert_eq!(
+            single_texel(vk::Format::BC3_UNORM_BLOCK, &bc3),
+            [0, 0, 255, 190]
+        );
+
+        let mut bc5 = [0; 16];
+        bc5[..8].copy_from_slice(&bc4_block(210, 70, 1));
+        bc5[8..].copy_from_slice(&bc4_block(50, 100, 7));
+        assert_eq!(
+            single_texel(vk::Format::BC5_UNORM_BLOCK, &bc5),
+            [70, 255, 0, 255]
+        );
+    }
+
+    #[test]
+    fn partial_blocks_are_cropped() {
+        //6x5の画像は2x2ブロックで、右と下のブロックは一部だけを使う
+        let blocks = [
+            bc1_block(RED, BLUE, 0),
+            bc1_block(RED, BLUE, 1),
+            bc1_block(0xFFFF, 0, 0),
+            bc1_block(0, 0xFFFF, 0),
+        ]
+        .concat();
+        let format = vk::Format::BC1_RGB_UNORM_BLOCK;
+
+        assert_eq!(level_size(format, extent(6, 5)), Some(32));
+
+        let pixels = decode_to_rgba8(format, extent(6, 5), &blocks).unwrap();
+        let texel = |x: usize, y: usize| &pixels[(y * 6 + x) * 4..][..4];
+
+        assert_eq!(pixels.len(), 6 * 5 * 4);
+        assert_eq!(texel(3, 3), [255, 0, 0, 255]);
+        assert_eq!(texel(4, 3), [0, 0, 255, 255]);
+        assert_eq!(texel(5, 0), [0, 0, 255, 255]);
+        assert_eq!(texel(0, 4), [255, 255, 255, 255]);
+        assert_eq!(texel(5, 4), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn small_mip_levels_still_take_a_block() {
+        let format = vk::Format::BC3_UNORM_BLOCK;
+
+        assert_eq!(level_size(format, extent(1, 1)), Some(16));
+        assert_eq!(level_size(format, extent(2, 1)), Some(16));
+        assert_eq!(level_size(format, extent(8, 8)), Some(64));
+    }
+
+    #[test]
+    fn rgb_texels_get_opaque_alpha() {
+        let data = [1, 2, 3, 4, 5, 6];
+
+        assert_eq!(
+            decode_to_rgba8(vk::Format::R8G8B8_UNORM, extent(2, 1), &data).unwrap(),
+            [1, 2, 3, 255, 4, 5, 6, 255]
+        );
+        assert_eq!(
+            decode_to_rgba8(vk::Format::B8G8R8_SRGB, extent(2, 1), &data).unwrap(),
+            [3, 2, 1, 255, 6, 5, 4, 255]
+        );
+    }
+
+    #[test]
+    fn srgb_is_kept_in_the_rgba8_format() {
+        assert_eq!(
+            rgba8_format(vk::Format::BC1_RGB_SRGB_BLOCK),
+            Some(vk::Format::R8G8B8A8_SRGB)
+        );
+        assert_eq!(
+            rgba8_format(vk::Format::BC5_UNORM_BLOCK),
+            Some(vk::Format::R8G8B8A8_UNORM)
+        );
+        assert_eq!(rgba8_format(vk::Format::BC7_SRGB_BLOCK), None);
+    }
+
+    #[test]
+    fn truncated_and_unsupported_data_is_an_error() {
+        assert!(decode_to_rgba8(vk::Format::BC1_RGB_UNORM_BLOCK, extent(8, 4), &[0; 8]).is_err());
+        assert!(decode_to_rgba8(vk::Format::ASTC_4X4_SRGB_BLOCK, extent(4, 4), &[0; 16]).is_err());
+    }
+}
@@ -0,0 +1,363 @@
+use crate::device_features::EnabledFeatures;
+use crate::ktx2_texture;
+use crate::one_time_commands::OneTimeCommands;
+use crate::synchronization::Synchronization;
+use crate::texture::Texture2D;
+use ash::{vk, Device, Instance};
+use std::collections::HashMap;
+use std::path::Path;
+
+//TextureManagerが持つテクスチャを指す
+//unloadされたテクスチャのハンドルを使うとpanicする
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureHandle(usize);
+
+struct Entry<T> {
+    value: T,
+    key: String,
+    //acquireしたマテリアルの数、0でなければunloadできない
+    references: u32,
+}
+
+//キーごとに1つだけ値を持ち、参照の数を数える
+//TextureManagerのうちVulkanを使わない部分で、値はテクスチャに限らない
+struct Registry<T> {
+    //ハンドルの番号、removeした所はNoneにして番号を使い回さない
+    entries: Vec<Option<Entry<T>>>,
+    by_key: HashMap<String, TextureHandle>,
+}
+
+impl<T> Registry<T> {
+    fn new() -> Self {
+        Self {
+            entries: vec![],
+            by_key: HashMap::new(),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<TextureHandle> {
+        self.by_key.get(key).copied()
+    }
+
+    fn get_or_insert_with(&mut self, key: &str, create: impl FnOnce() -> T) -> TextureHandle {
+        if let Some(handle) = self.get(key) {
+            return handle;
+        }
+
+        let handle = TextureHandle(self.entries.len());
+
+        self.entries.push(Some(Entry {
+            value: create(),
+            key: key.to_owned(),
+            references: 0,
+        }));
+        self.by_key.insert(key.to_owned(), handle);
+
+        handle
+    }
+
+    fn acquire(&mut self, handle: TextureHandle) {
+        self.entry_mut(handle).references += 1;
+    }
+
+    fn release(&mut self, handle: TextureHandle) {
+        let entry = self.entry_mut(handle);
+
+        debug_assert!(
+            entry.references > 0,
+            "{} was released more times than acquired",
+            entry.key
+        );
+        entry.references = entry.references.saturating_sub(1);
+    }
+
+    //参照が残っている場合はErrを返して何もしない
+    //removeしたハンドルは使えなくなり、同じキーで入れ直すと別のハンドルになる
+    fn remove(&mut self, handle: TextureHandle) -> Result<T, String> {
+        let entry = self.entry(handle);
+        if entry.references > 0 {
+            return Err(format!(
+                "{} is still referenced by {} materials",
+                entry.key, entry.references
+            ));
+        }
+
+        let entry = self.entries[handle.0].take().unwrap();
+        self.by_key.remove(&entry.key);
+
+        Ok(entry.value)
+    }
+
+    fn value(&self, handle: TextureHandle) -> &T {
+        &self.entry(handle).value
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Entry<T>> {
+        self.entries.iter().flatten()
+    }
+
+    fn entry(&self, handle: TextureHandle) -> &Entry<T> {
+        self.entries[handle.0]
+            .as_ref()
+            .expect("The texture has been unloaded")
+    }
+
+    fn entry_mut(&mut self, handle: TextureHandle) -> &mut Entry<T> {
+        self.entries[handle.0]
+            .as_mut()
+            .expect("The texture has been unloaded")
+    }
+}
+
+//読み込んだ全てのテクスチャをキーごとに1つだけ持つ
+//キーはファイルのパスか、生成したテクスチャの場合はその内容を表す文字列
+//組み込みの1×1のテクスチャは起動時に作り、unloadできない
+pub struct TextureManager {
+    textures: Registry<Texture2D>,
+    white: TextureHandle,
+    black: TextureHandle,
+    flat_normal: TextureHandle,
+}
+
+impl TextureManager {
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+    ) -> Self {
+        let mut manager = Self {
+            textures: Registry::new(),
+            white: TextureHandle(0),
+            black: TextureHandle(0),
+            flat_normal: TextureHandle(0),
+        };
+
+        let mut builtin = |key: &str, format, pixel: [u8; 4]| {
+            manager.get_or_insert_with(key, || {
+                Texture2D::new(
+                    instance,
+                    physical_device,
+                    device,
+                    one_time_commands,
+                    synchronization,
+                    format,
+                    vk::Extent2D {
+                        width: 1,
+                        height: 1,
+                    },
+                    &pixel,
+                )
+            })
+        };
+
+        let white = builtin(
+            "builtin:white",
+            vk::Format::R8G8B8A8_SRGB,
+            [255, 255, 255, 255],
+        );
+        let black = builtin("builtin:black", vk::Format::R8G8B8A8_SRGB, [0, 0, 0, 255]);
+        //接線空間の(0, 0, 1)、法線マップはsRGBとして扱わない
+        let flat_normal = builtin(
+            "builtin:flat_normal",
+            vk::Format::R8G8B8A8_UNORM,
+            [128, 128, 255, 255],
+        );
+
+        manager.white = white;
+        manager.black = black;
+        manager.flat_normal = flat_normal;
+        manager
+    }
+
+    //テクスチャが無いマテリアルのアルベドに使う
+    #[allow(dead_code)]
+    pub fn white(&self) -> TextureHandle {
+        self.white
+    }
+
+    #[allow(dead_code)]
+    pub fn black(&self) -> TextureHandle {
+        self.black
+    }
+
+    //法線マップが無いマテリアルに使う
+    #[allow(dead_code)]
+    pub fn flat_normal(&self) -> TextureHandle {
+        self.flat_normal
+    }
+
+    //keyのテクスチャが既にあればそれを返し、無ければcreateで作って登録する
+    pub fn get_or_insert_with(
+        &mut self,
+        key: &str,
+        create: impl FnOnce() -> Texture2D,
+    ) -> TextureHandle {
+        self.textures.get_or_insert_with(key, create)
+    }
+
+    //同じパスのファイルは2回目以降は読み込まずに同じハンドルを返す
+    #[allow(clippy::too_many_arguments)]
+    pub fn load_ktx2(
+        &mut self,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        enabled_features: &EnabledFeatures,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        path: &Path,
+    ) -> Result<TextureHandle, String> {
+        //別の書き方をした同じファイルも同じキーになるようにする
+        let key = path
+            .canonicalize()
+            .unwrap_or_else(|_| path.to_owned())
+            .display()
+            .to_string();
+
+        if let Some(handle) = self.textures.get(&key) {
+            return Ok(handle);
+        }
+
+        let texture = ktx2_texture::load_texture(
+            instance,
+            physical_device,
+            device,
+            enabled_features,
+            one_time_commands,
+            synchronization,
+            path,
+        )?;
+
+        Ok(self.get_or_insert_with(&key, || texture))
+    }
+
+    //マテリアルがhandleを使い始める時に呼ぶ
+    pub fn acquire(&mut self, handle: TextureHandle) {
+        self.textures.acquire(handle);
+    }
+
+    //acquireしたマテリアルが使い終わった時に呼ぶ
+    pub fn release(&mut self, handle: TextureHandle) {
+        self.textures.release(handle);
+    }
+
+    //参照しているマテリアルが残っている場合と組み込みのテクスチャの場合は破棄しない
+    //GPUがテクスチャを使い終わってから呼ぶ
+    #[allow(dead_code)]
+    pub fn unload(&mut self, device: &Device, handle: TextureHandle) -> Result<(), String> {
+        if [self.white, self.black, self.flat_normal].contains(&handle) {
+            return Err("Built-in textures cannot be unloaded".to_owned());
+        }
+
+        self.textures.remove(handle)?.destroy(device);
+
+        Ok(())
+    }
+
+    //Descriptor Setに書き込む時に使う
+    pub fn image_info(&self, handle: TextureHandle) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo::builder()
+            .image_view(self.textures.value(handle).view())
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()
+    }
+
+    //テクスチャを使う全てのDescriptor Setを破棄した後、デバイスの破棄の前に呼ぶ
+    pub fn destroy(&self, device: &Device) {
+        for entry in self.textures.iter() {
+            if entry.references > 0 {
+                log::warn!(
+                    "{} is destroyed with {} references left",
+                    entry.key,
+                    entry.references
+                );
+            }
+
+            entry.value.destroy(device);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn the_same_key_is_created_once() {
+        let mut registry = Registry::new();
+        let created = Cell::new(0);
+        let create = |value| {
+            created.set(created.get() + 1);
+            value
+        };
+
+        let first = registry.get_or_insert_with("a.ktx2", || create(1));
+        let again = registry.get_or_insert_with("a.ktx2", || create(2));
+        let other = registry.get_or_insert_with("b.ktx2", || create(3));
+
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+        assert_eq!(created.get(), 2);
+        assert_eq!(*registry.value(first), 1);
+        assert_eq!(registry.get("a.ktx2"), Some(first));
+        assert_eq!(registry.get("c.ktx2"), None);
+    }
+
+    #[test]
+    fn referenced_values_cannot_be_removed() {
+        let mut registry = Registry::new();
+        let handle = registry.get_or_insert_with("a.ktx2", || 1);
+
+        registry.acquire(handle);
+        registry.acquire(handle);
+        assert!(registry.remove(handle).is_err());
+
+        registry.release(handle);
+        assert!(registry.remove(handle).is_err());
+
+        registry.release(handle);
+        assert_eq!(registry.remove(handle), Ok(1));
+        assert_eq!(registry.iter().count(), 0);
+    }
+
+    #[test]
+    fn removed_handles_are_not_reused() {
+        let mut registry = Registry::new();
+        let first = registry.get_or_insert_with("a.ktx2", || 1);
+        let other = registry.get_or_insert_with("b.ktx2", || 2);
+
+        registry.remove(first).unwrap();
+        assert_eq!(registry.get("a.ktx2"), None);
+
+        //同じキーで入れ直すと新しいハンドルになり、他のハンドルは変わらない
+        let reloaded = registry.get_or_insert_with("a.ktx2", || 3);
+        assert_ne!(reloaded, first);
+        assert_eq!(*registry.value(reloaded), 3);
+        assert_eq!(*registry.value(other), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "has been unloaded")]
+    fn removed_handles_panic() {
+        let mut registry = Registry::new();
+        let handle = registry.get_or_insert_with("a.ktx2", || 1);
+
+        registry.remove(handle).unwrap();
+        registry.acquire(handle);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "released more times than acquired")]
+    fn releasing_more_than_acquired_panics_in_debug() {
+        let mut registry = Registry::new();
+        let handle = registry.get_or_insert_with("a.ktx2", || 1);
+
+        registry.acquire(handle);
+        registry.release(handle);
+        registry.release(handle);
+    }
+}
@@ -0,0 +1,84 @@
+use ash::{vk, Device, Instance};
+
+//値が単調に増えていくSemaphore、Vulkan 1.2からコアに入った
+//CPUからもsignalとwaitができるのでFenceの代わりとして使える
+//swapchainのacquireとpresentはバイナリSemaphoreしか受け付けないのでそちらには使えない
+pub struct TimelineSemaphore {
+    semaphore: vk::Semaphore,
+}
+
+impl TimelineSemaphore {
+    pub fn new(device: &Device, initial_value: u64) -> Self {
+        let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(initial_value)
+            .build();
+
+        let semaphore_info = vk::SemaphoreCreateInfo::builder()
+            .push_next(&mut type_info)
+            .build();
+
+        let semaphore = unsafe { device.create_semaphore(&semaphore_info, None).unwrap() };
+
+        Self { semaphore }
+    }
+
+    pub fn handle(&self) -> vk::Semaphore {
+        self.semaphore
+    }
+
+    //CPU側から値を進める
+    //現在の値より大きい値でなければいけない
+    #[allow(dead_code)]
+    pub fn signal(&self, device: &Device, value: u64) -> Result<(), vk::Result> {
+        let signal_info = vk::SemaphoreSignalInfo::builder()
+            .semaphore(self.semaphore)
+            .value(value)
+            .build();
+
+        unsafe { device.signal_semaphore(&signal_info) }
+    }
+
+    //値がvalue以上になるまで待つ
+    //TIMEOUTは成功コードだがashではErr(vk::Result::TIMEOUT)として返ってくる
+    pub fn wait(&self, device: &Device, value: u64, timeout: u64) -> Result<(), vk::Result> {
+        let semaphores = [self.semaphore];
+        let values = [value];
+
+        let wait_info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(&semaphores)
+            .values(&values)
+            .build();
+
+        unsafe { device.wait_semaphores(&wait_info, timeout) }
+    }
+
+    pub fn value(&self, device: &Device) -> Result<u64, vk::Result> {
+        unsafe { device.get_semaphore_counter_value(self.semaphore) }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe { device.destroy_semaphore(self.semaphore, None) };
+    }
+}
+
+//Vulkan 1.2以降のデバイスでは必ずサポートされているが、1.1のデバイスでは拡張次第になる
+//waitやsignalはコアの関数を呼ぶので、api_versionが1.2未満の場合は拡張があっても使わない
+pub fn is_supported(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    api_version: u32,
+) -> bool {
+    if api_version < vk::make_api_version(0, 1, 2, 0) {
+        return false;
+    }
+
+    let mut timeline_features = vk::PhysicalDeviceTimelineSemaphoreFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut timeline_features)
+        .build();
+
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+
+    timeline_features.timeline_semaphore == vk::TRUE
+}
@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+//--exposureと設定ファイルが無い場合の露出、EV(2の指数)で0.0は明るさをそのままにする
+pub const DEFAULT_EXPOSURE: f32 = 0.0;
+
+//Home/Endで変える1回分の量と、上下の範囲
+pub const EXPOSURE_STEP: f32 = 0.5;
+pub const MAX_EXPOSURE: f32 = 8.0;
+
+//Tキーで切り替える、1.0より明るい値を表示できる範囲に収める方法
+//シェーダーのTONEMAP_*と同じ値にする
+//シーンのファイルにはnameと同じ名前で書く
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tonemapper {
+    //ACESのフィルミックカーブの近似、暗部を締めて明部をなだらかに潰す
+    Aces = 0,
+    //c / (1 + c)、色相を保ったまま全体を圧縮する
+    Reinhard = 1,
+    //露出を掛けて1.0で切り詰めるだけ、他の方法と比べるために使う
+    Exposure = 2,
+}
+
+impl Tonemapper {
+    pub fn name(self) -> &'static str {
+        match self {
+            Tonemapper::Aces => "aces",
+            Tonemapper::Reinhard => "reinhard",
+            Tonemapper::Exposure => "exposure",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Tonemapper::Aces => Tonemapper::Reinhard,
+            Tonemapper::Reinhard => Tonemapper::Exposure,
+            Tonemapper::Exposure => Tonemapper::Aces,
+        }
+    }
+}
+
+impl FromStr for Tonemapper {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "aces" => Ok(Self::Aces),
+            "reinhard" => Ok(Self::Reinhard),
+            "exposure" => Ok(Self::Exposure),
+            _ => Err(format!(
+                "Unknown tonemapper '{}', expected aces, reinhard or exposure",
+                s
+            )),
+        }
+    }
+}
+
+//シェーダー側のTonemapConstantsと同じレイアウト
+//push constantで渡すので、パイプラインを作り直さずにフレームごとに切り替えられる
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct TonemapConstants {
+    //色に掛ける倍率、EVから計算しておく
+    pub exposure: f32,
+    //Tonemapperの値
+    pub operator: u32,
+}
+
+impl TonemapConstants {
+    pub fn new(tonemapper: Tonemapper, exposure: f32) -> Self {
+        Self {
+            exposure: exposure.exp2(),
+            operator: tonemapper as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //シェーダーのTONEMAP_*と同じ値で、exposureはEVから倍率にしてから渡す
+    #[test]
+    fn constants_match_the_shader_layout() {
+        let constants = TonemapConstants::new(Tonemapper::Reinhard, 1.0);
+        assert_eq!(constants.exposure, 2.0);
+        assert_eq!(constants.operator, 1);
+
+        assert_eq!(TonemapConstants::new(Tonemapper::Aces, -1.0).exposure, 0.5);
+        assert_eq!(Tonemapper::Aces as u32, 0);
+        assert_eq!(Tonemapper::Exposure as u32, 2);
+        assert_eq!(std::mem::size_of::<TonemapConstants>(), 8);
+    }
+
+    #[test]
+    fn next_cycles_through_every_operator_by_name() {
+        let mut tonemapper = Tonemapper::Aces;
+
+        for expected in ["reinhard", "exposure", "aces"] {
+            tonemapper = tonemapper.next();
+            assert_eq!(tonemapper.name(), expected);
+            assert_eq!(expected.parse::<Tonemapper>(), Ok(tonemapper));
+        }
+
+        assert_eq!("ACES".parse::<Tonemapper>(), Ok(Tonemapper::Aces));
+        assert!("filmic".parse::<Tonemapper>().is_err());
+    }
+}
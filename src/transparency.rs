@@ -0,0 +1,134 @@
+use crate::mesh::{Mesh, Vertex};
+use crate::object_buffer::{ObjectBuffers, ObjectUniforms};
+use crate::one_time_commands::OneTimeCommands;
+use crate::synchronization::Synchronization;
+use ash::{vk, Device, Instance};
+use glam::{Mat4, Vec3, Vec4};
+use std::cmp::Ordering;
+
+//--transparent-quadsで並べる四角形の中心と色(アルファ付き)
+//Z方向にずらして重ね、どの方向から見ても重なりが分かるようにXYも少しずつずらす
+const QUADS: [([f32; 3], [f32; 4]); 4] = [
+    ([-0.3, -0.2, -0.75], [1.0, 0.2, 0.2, 0.5]),
+    ([-0.1, 0.0, -0.25], [0.2, 1.0, 0.2, 0.5]),
+    ([0.1, 0.2, 0.25], [0.2, 0.4, 1.0, 0.5]),
+    ([0.3, 0.0, 0.75], [1.0, 1.0, 0.2, 0.5]),
+];
+
+//四角形の一辺の長さ
+const QUAD_SIZE: f32 = 1.2;
+
+//描画ごとに深度値の書き込みとブレンドをどうするか
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    //深度値を書き込み、ブレンドしない
+    Opaque,
+    //深度値を書き込まずに、書き込み済みの色にアルファで重ねる
+    Transparent,
+}
+
+//1回分の描画
+#[derive(Clone, Copy, Debug)]
+pub struct DrawCall {
+    pub blend_mode: BlendMode,
+    //ObjectBuffersのインデックス
+    pub object_index: usize,
+    //ビュー空間でのオブジェクトの中心のz
+    pub view_depth: f32,
+}
+
+//不透明な物を先に描画し、半透明な物はその後で奥から手前の順に描画する
+//半透明な物は深度値を書き込まないので、手前の物を先に描画すると奥の物が上に重なってしまう
+//不透明な物は深度テストで前後関係が決まるので元の順番のままにする
+pub fn sort_draw_calls(draw_calls: &mut [DrawCall]) {
+    //sort_byは安定ソートなので等しいものは元の順番が保たれる
+    draw_calls.sort_by(|a, b| match (a.blend_mode, b.blend_mode) {
+        (BlendMode::Opaque, BlendMode::Transparent) => Ordering::Less,
+        (BlendMode::Transparent, BlendMode::Opaque) => Ordering::Greater,
+        (BlendMode::Opaque, BlendMode::Opaque) => Ordering::Equal,
+        //カメラは-Z方向を向いているのでzが小さいほど遠い
+        (BlendMode::Transparent, BlendMode::Transparent) => a
+            .view_depth
+            .partial_cmp(&b.view_depth)
+            .unwrap_or(Ordering::Equal),
+    });
+}
+
+//色付きの半透明な四角形を重ねて並べるデモ
+//ObjectBuffersのfirst_objectから四角形の数だけを使う
+pub struct TransparentQuads {
+    mesh: Mesh,
+    first_object: usize,
+}
+
+impl TransparentQuads {
+    pub const COUNT: usize = QUADS.len();
+
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        one_time_commands: &OneTimeCommands,
+        synchronization: &Synchronization,
+        first_object: usize,
+    ) -> Self {
+        //色はObjectUniformsで付けるので頂点カラーは白にする
+        let vertices = [[-0.5, 0.5], [0.5, 0.5], [0.5, -0.5], [-0.5, -0.5]].map(|[x, y]| Vertex {
+            position: [x, y, 0.0],
+            color: [1.0, 1.0, 1.0],
+            normal: [0.0, 0.0, 1.0],
+            tex_coord: [0.0; 2],
+            tangent: Vertex::NO_TANGENT,
+        });
+
+        let mesh = Mesh::new(
+            instance,
+            physical_device,
+            device,
+            one_time_commands,
+            synchronization,
+            &vertices,
+            &[0, 1, 2, 2, 3, 0],
+        );
+
+        log::info!("Transparent quads: {}", Self::COUNT);
+
+        Self { mesh, first_object }
+    }
+
+    pub fn mesh(&self) -> &Mesh {
+        &self.mesh
+    }
+
+    fn model(center: [f32; 3]) -> Mat4 {
+        Mat4::from_translation(Vec3::from(center)) * Mat4::from_scale(Vec3::splat(QUAD_SIZE))
+    }
+
+    //四角形ごとのモデル行列と色を書き込む
+    pub fn write_objects(&self, object_buffers: &ObjectBuffers, frame: usize) {
+        for (index, (center, color)) in QUADS.iter().enumerate() {
+            let object = ObjectUniforms {
+                model: Self::model(*center),
+                color: Vec4::from(*color),
+            };
+
+            object_buffers.write(frame, self.first_object + index, &object);
+        }
+    }
+
+    //四角形ごとの描画、viewはこのフレームのビュー行列
+    pub fn draw_calls(&self, view: Mat4) -> impl Iterator<Item = DrawCall> + '_ {
+        QUADS
+            .iter()
+            .enumerate()
+            .map(move |(index, (center, _))| DrawCall {
+                blend_mode: BlendMode::Transparent,
+                object_index: self.first_object + index,
+                view_depth: view.transform_point3(Vec3::from(*center)).z,
+            })
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.mesh.destroy(device);
+    }
+}
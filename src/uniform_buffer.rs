@@ -0,0 +1,582 @@
+use crate::buffer;
+use crate::lighting::{Light, LightUniforms};
+use crate::memory_budget;
+use ash::{vk, Device, Instance};
+use glam::Mat4;
+use std::mem;
+
+//実際に使うのは先頭のframe_indexの4バイトだけ
+const READBACK_SIZE: vk::DeviceSize = 16;
+
+//シェーダー側のUniformBufferObjectと同じレイアウトにする
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+//モデル行列はオブジェクトごとに変わるのでobject_buffer::ObjectUniformsに持たせる
+pub struct UniformBufferObject {
+    pub view: Mat4,
+    pub proj: Mat4,
+    //--shadowsの時にシャドウマップを引くためのライトの行列
+    pub light_view_proj: Mat4,
+    //--ubo-stressの時に書き込むフレーム番号、0は未使用
+    pub frame_index: u32,
+    //シャドウマップの1テクセル分のuvの大きさ、シャドウマップが無い場合は0.0
+    pub shadow_texel_size: f32,
+    //デバッグ表示で深度を0.0から1.0にするためのカメラのnearとfar
+    //std140ではstructの大きさが16バイトの倍数になるので、paddingだった場所に置いている
+    pub near: f32,
+    pub far: f32,
+    //DepthConvention::far_depth、スカイボックスを一番遠い深度値で描画するのに使う
+    pub far_depth: f32,
+    pub _padding: [u32; 3],
+}
+
+//フレームごとのUniform Bufferとそれを参照するDescriptor Set
+//GPUが前のフレームのバッファを読んでいる間に書き換えないようにframes_in_flight個用意する
+pub struct UniformBuffers {
+    buffers: Vec<vk::Buffer>,
+    memories: Vec<vk::DeviceMemory>,
+    //HOST_COHERENTなメモリなのでマップしたままにしておいて毎フレーム書き込む
+    mapped: Vec<*mut UniformBufferObject>,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    //main_vs_ubo_stressがGPUから見えたframe_indexを書き込むstorage buffer
+    readback_buffers: Vec<vk::Buffer>,
+    readback_memories: Vec<vk::DeviceMemory>,
+    readback_mapped: Vec<*mut u32>,
+    //フラグメントシェーダーが読むライトの情報、binding = 2
+    light_buffers: Vec<vk::Buffer>,
+    light_memories: Vec<vk::DeviceMemory>,
+    light_mapped: Vec<*mut LightUniforms>,
+    //LightUniforms::light_count個だけ読まれるLightの配列、binding = 3
+    lights_buffers: Vec<vk::Buffer>,
+    lights_memories: Vec<vk::DeviceMemory>,
+    lights_mapped: Vec<*mut Light>,
+    max_lights: u32,
+    //light_culling::LightCullingが書き込むタイルごとのライトの番号、binding = 4
+    //描画する大きさに依存するので、resize_light_tilesで作り直す
+    light_tiles: Vec<(vk::Buffer, vk::DeviceMemory)>,
+}
+
+impl UniformBuffers {
+    pub fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        frames_in_flight: u32,
+        max_lights: u32,
+        //binding = 0のUniformBufferObjectを読むステージに足す、--mesh-shadingではMESH_EXT
+        extra_stages: vk::ShaderStageFlags,
+    ) -> Self {
+        let descriptor_set_layout = Self::create_descriptor_set_layout(device, extra_stages);
+
+        let size = mem::size_of::<UniformBufferObject>() as vk::DeviceSize;
+        let light_size = mem::size_of::<LightUniforms>() as vk::DeviceSize;
+        //大きさ0のバッファは作れないので、ライトが0個でも1個分は確保する
+        let lights_size =
+            mem::size_of::<Light>() as vk::DeviceSize * max_lights.max(1) as vk::DeviceSize;
+
+        let mut buffers = vec![];
+        let mut memories = vec![];
+        let mut mapped = vec![];
+        let mut readback_buffers = vec![];
+        let mut readback_memories = vec![];
+        let mut readback_mapped = vec![];
+        let mut light_buffers = vec![];
+        let mut light_memories = vec![];
+        let mut light_mapped = vec![];
+        let mut lights_buffers = vec![];
+        let mut lights_memories = vec![];
+        let mut lights_mapped = vec![];
+
+        for _ in 0..frames_in_flight {
+            let (buffer, memory) = buffer::create_buffer(
+                instance,
+                physical_device,
+                device,
+                size,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            );
+
+            let pointer = unsafe {
+                device
+                    .map_memory(memory, 0, size, vk::MemoryMapFlags::empty())
+                    .unwrap()
+            };
+
+            buffers.push(buffer);
+            memories.push(memory);
+            mapped.push(pointer as *mut UniformBufferObject);
+
+            let (readback_buffer, readback_memory) = buffer::create_buffer(
+                instance,
+                physical_device,
+                device,
+                READBACK_SIZE,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            );
+
+            let readback_pointer = unsafe {
+                device
+                    .map_memory(
+                        readback_memory,
+                        0,
+                        READBACK_SIZE,
+                        vk::MemoryMapFlags::empty(),
+                    )
+                    .unwrap() as *mut u32
+            };
+
+            //まだ一度も描画していないことを示す
+            unsafe { readback_pointer.write(0) };
+
+            readback_buffers.push(readback_buffer);
+            readback_memories.push(readback_memory);
+            readback_mapped.push(readback_pointer);
+
+            let (light_buffer, light_memory) = buffer::create_buffer(
+                instance,
+                physical_device,
+                device,
+                light_size,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            );
+
+            let light_pointer = unsafe {
+                device
+                    .map_memory(light_memory, 0, light_size, vk::MemoryMapFlags::empty())
+                    .unwrap()
+            };
+
+            light_buffers.push(light_buffer);
+            light_memories.push(light_memory);
+            light_mapped.push(light_pointer as *mut LightUniforms);
+
+            let (lights_buffer, lights_memory) = buffer::create_buffer(
+                instance,
+                physical_device,
+                device,
+                lights_size,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            );
+
+            let lights_pointer = unsafe {
+                device
+                    .map_memory(lights_memory, 0, lights_size, vk::MemoryMapFlags::empty())
+                    .unwrap()
+            };
+
+            lights_buffers.push(lights_buffer);
+            lights_memories.push(lights_memory);
+            lights_mapped.push(lights_pointer as *mut Light);
+        }
+
+        //--light-cullingを使わない場合も読まれないだけでDescriptorは要るので、1タイル分だけ作っておく
+        let light_tiles = Self::create_light_tiles(
+            instance,
+            physical_device,
+            device,
+            frames_in_flight,
+            max_lights,
+            1,
+        );
+
+        let descriptor_pool = Self::create_descriptor_pool(device, frames_in_flight);
+
+        let descriptor_sets = Self::create_descriptor_sets(
+            device,
+            descriptor_pool,
+            descriptor_set_layout,
+            &buffers,
+            size,
+            &readback_buffers,
+            (&light_buffers, light_size),
+            (&lights_buffers, lights_size),
+            &light_tiles,
+        );
+
+        Self {
+            buffers,
+            memories,
+            mapped,
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_sets,
+            readback_buffers,
+            readback_memories,
+            readback_mapped,
+            light_buffers,
+            light_memories,
+            light_mapped,
+            lights_buffers,
+            lights_memories,
+            lights_mapped,
+            max_lights,
+            light_tiles,
+        }
+    }
+
+    fn create_light_tiles(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        frames_in_flight: u32,
+        max_lights: u32,
+        tile_count: u32,
+    ) -> Vec<(vk::Buffer, vk::DeviceMemory)> {
+        let size = (1 + max_lights) as vk::DeviceSize
+            * tile_count.max(1) as vk::DeviceSize
+            * mem::size_of::<u32>() as vk::DeviceSize;
+
+        (0..frames_in_flight)
+            .map(|_| {
+                buffer::create_buffer(
+                    instance,
+                    physical_device,
+                    device,
+                    size,
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                )
+            })
+            .collect()
+    }
+
+    fn create_descriptor_set_layout(
+        device: &Device,
+        extra_stages: vk::ShaderStageFlags,
+    ) -> vk::DescriptorSetLayout {
+        let ubo_layout_binding = vk::DescriptorSetLayoutBinding::builder()
+            //シェーダー側のbinding = 0に対応
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            //配列にすることも出来るが今回は1つ
+            .descriptor_count(1)
+            //main_fs_shadowedがshadow_texel_sizeを読み、main_teseがview行列とproj行列を読む
+            //main_cs_light_cullingはタイルの視錐台を作るのにnearとfarを読む
+            .stage_flags(
+                vk::ShaderStageFlags::VERTEX
+                    | vk::ShaderStageFlags::TESSELLATION_EVALUATION
+                    | vk::ShaderStageFlags::FRAGMENT
+                    | vk::ShaderStageFlags::COMPUTE
+                    | extra_stages,
+            )
+            .build();
+
+        //main_vs_ubo_stressでのみ使用する
+        let readback_layout_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .build();
+
+        //main_fs系のライティングとmain_cs_light_cullingで使用する
+        let light_layout_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(2)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
+            .build();
+
+        //main_fs系のライティングとmain_cs_light_cullingでLightUniforms::light_count個だけ読む
+        let lights_layout_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(3)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
+            .build();
+
+        //main_cs_light_cullingが書き込み、main_fs系のライティングとmain_fs_light_tiles_heatmapが読む
+        let light_tiles_layout_binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(4)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
+            .build();
+
+        let bindings = [
+            ubo_layout_binding,
+            readback_layout_binding,
+            light_layout_binding,
+            lights_layout_binding,
+            light_tiles_layout_binding,
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        }
+    }
+
+    fn create_descriptor_pool(device: &Device, frames_in_flight: u32) -> vk::DescriptorPool {
+        let pool_sizes = [
+            vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::UNIFORM_BUFFER)
+                //UniformBufferObjectとLightUniformsの2つ
+                .descriptor_count(frames_in_flight * 2)
+                .build(),
+            vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::STORAGE_BUFFER)
+                //readbackとLightの配列とタイルの3つ
+                .descriptor_count(frames_in_flight * 3)
+                .build(),
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(frames_in_flight)
+            .build();
+
+        unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() }
+    }
+
+    //Descriptor SetはPoolが破棄されるときに一緒に破棄される
+    #[allow(clippy::too_many_arguments)]
+    fn create_descriptor_sets(
+        device: &Device,
+        descriptor_pool: vk::DescriptorPool,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+        buffers: &[vk::Buffer],
+        size: vk::DeviceSize,
+        readback_buffers: &[vk::Buffer],
+        (light_buffers, light_size): (&[vk::Buffer], vk::DeviceSize),
+        (lights_buffers, lights_size): (&[vk::Buffer], vk::DeviceSize),
+        light_tiles: &[(vk::Buffer, vk::DeviceMemory)],
+    ) -> Vec<vk::DescriptorSet> {
+        let layouts = vec![descriptor_set_layout; buffers.len()];
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&layouts)
+            .build();
+
+        let descriptor_sets = unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap() };
+
+        for ((((descriptor_set, buffer), readback_buffer), light_buffer), lights_buffer) in
+            descriptor_sets
+                .iter()
+                .zip(buffers)
+                .zip(readback_buffers)
+                .zip(light_buffers)
+                .zip(lights_buffers)
+        {
+            let buffer_info = [vk::DescriptorBufferInfo::builder()
+                .buffer(*buffer)
+                .offset(0)
+                .range(size)
+                .build()];
+
+            let readback_info = [vk::DescriptorBufferInfo::builder()
+                .buffer(*readback_buffer)
+                .offset(0)
+                .range(READBACK_SIZE)
+                .build()];
+
+            let light_info = [vk::DescriptorBufferInfo::builder()
+                .buffer(*light_buffer)
+                .offset(0)
+                .range(light_size)
+                .build()];
+
+            let lights_info = [vk::DescriptorBufferInfo::builder()
+                .buffer(*lights_buffer)
+                .offset(0)
+                .range(lights_size)
+                .build()];
+
+            let descriptor_writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(0)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&buffer_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(1)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&readback_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(2)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&light_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(3)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&lights_info)
+                    .build(),
+            ];
+
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+        }
+
+        Self::write_light_tiles(device, &descriptor_sets, light_tiles);
+
+        descriptor_sets
+    }
+
+    fn write_light_tiles(
+        device: &Device,
+        descriptor_sets: &[vk::DescriptorSet],
+        light_tiles: &[(vk::Buffer, vk::DeviceMemory)],
+    ) {
+        let buffer_infos = light_tiles
+            .iter()
+            .map(|&(buffer, _)| {
+                [vk::DescriptorBufferInfo::builder()
+                    .buffer(buffer)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .build()]
+            })
+            .collect::<Vec<_>>();
+
+        let descriptor_writes = descriptor_sets
+            .iter()
+            .zip(&buffer_infos)
+            .map(|(&descriptor_set, buffer_info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(4)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(buffer_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+    }
+
+    //swapchainを作り直した後、GPUの完了を待ってから呼ぶ
+    //tile_countはlight_culling::LightCulling::tile_countの横と縦の積
+    pub fn resize_light_tiles(
+        &mut self,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &Device,
+        tile_count: u32,
+    ) {
+        self.destroy_light_tiles(device);
+
+        self.light_tiles = Self::create_light_tiles(
+            instance,
+            physical_device,
+            device,
+            self.descriptor_sets.len() as u32,
+            self.max_lights,
+            tile_count,
+        );
+
+        Self::write_light_tiles(device, &self.descriptor_sets, &self.light_tiles);
+    }
+
+    fn destroy_light_tiles(&mut self, device: &Device) {
+        for (buffer, memory) in self.light_tiles.drain(..) {
+            unsafe {
+                device.destroy_buffer(buffer, None);
+                memory_budget::free_memory(device, memory);
+            }
+        }
+    }
+
+    pub fn light_tiles_buffer(&self, frame: usize) -> vk::Buffer {
+        self.light_tiles[frame].0
+    }
+
+    //LightUniforms::tile_strideに入れる値
+    pub fn tile_stride(&self) -> u32 {
+        1 + self.max_lights
+    }
+
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    pub fn descriptor_set(&self, frame: usize) -> vk::DescriptorSet {
+        self.descriptor_sets[frame]
+    }
+
+    //そのフレームの完了を待った後に呼ぶ
+    pub fn update(&self, frame: usize, ubo: &UniformBufferObject) {
+        unsafe { self.mapped[frame].write(*ubo) };
+    }
+
+    //updateと同じくそのフレームの完了を待った後に呼ぶ
+    //max_lightsより後ろのlightsは書き込まず、light.light_countもmax_lightsまでに切り詰める
+    pub fn update_light(&self, frame: usize, light: &LightUniforms, lights: &[Light]) {
+        let count = lights.len().min(self.max_lights as usize);
+
+        let light = LightUniforms {
+            light_count: light.light_count.min(count as u32),
+            ..*light
+        };
+
+        unsafe {
+            self.light_mapped[frame].write(light);
+            std::ptr::copy_nonoverlapping(lights.as_ptr(), self.lights_mapped[frame], count);
+        }
+    }
+
+    //そのフレームの完了を待った後に呼び、前回そのフレームでCPUが書き込んだframe_indexとGPUが読んだ値を返す
+    //一度も描画していない場合はNone
+    pub fn read_back(&self, frame: usize) -> Option<(u32, u32)> {
+        let seen_by_gpu = unsafe { self.readback_mapped[frame].read() };
+
+        if seen_by_gpu == 0 {
+            return None;
+        }
+
+        let written = unsafe { (*self.mapped[frame]).frame_index };
+
+        Some((written, seen_by_gpu))
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.destroy_light_tiles(device);
+
+        unsafe {
+            for (buffer, memory) in self.lights_buffers.iter().zip(&self.lights_memories) {
+                device.destroy_buffer(*buffer, None);
+                memory_budget::free_memory(device, *memory);
+            }
+
+            for (buffer, memory) in self.light_buffers.iter().zip(&self.light_memories) {
+                device.destroy_buffer(*buffer, None);
+                memory_budget::free_memory(device, *memory);
+            }
+
+            for (buffer, memory) in self.readback_buffers.iter().zip(&self.readback_memories) {
+                device.destroy_buffer(*buffer, None);
+                memory_budget::free_memory(device, *memory);
+            }
+
+            for (buffer, memory) in self.buffers.iter().zip(&self.memories) {
+                device.destroy_buffer(*buffer, None);
+                //unmapはfree_memoryで暗黙的に行われる
+                memory_budget::free_memory(device, *memory);
+            }
+
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use crate::mesh::Mesh;
+use ash::{vk, Device};
+
+//頂点入力を使わずに頂点シェーダーがメッシュのバッファを直接読む描画
+//rust-gpuではPhysicalStorageBufferのポインタを作れずbufferDeviceAddressのアドレスから読めないので、
+//シェーダーはstorage bufferのDescriptor Set経由でバッファを読む
+//メインのパイプラインのset = 2で、binding = 0が頂点、binding = 1がインデックス
+pub struct VertexPulling {
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    //trueの場合は頂点入力のパイプラインの代わりに使う
+    enabled: bool,
+    //切り替える直前に計測したGPU時間の平均(ミリ秒)、(頂点入力, vertex pulling)
+    gpu_average_ms: (Option<f64>, Option<f64>),
+}
+
+impl VertexPulling {
+    //meshはMesh::with_usageでSTORAGE_BUFFERを付けて作ったものを渡す
+    pub fn new(device: &Device, mesh: &Mesh) -> Self {
+        let bindings = [0, 1].map(|binding| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .build()
+        });
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings)
+            .build();
+
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .unwrap()
+        };
+
+        let pool_sizes = [vk::DescriptorPoolSize::builder()
+            .ty(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(2)
+            .build()];
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+
+        let set_layouts = [descriptor_set_layout];
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&set_layouts)
+            .build();
+
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] };
+
+        let (vertex_buffer, index_buffer) = mesh.buffers();
+
+        let buffer_infos = [vertex_buffer, index_buffer].map(|buffer| {
+            [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE)
+                .build()]
+        });
+
+        let descriptor_writes = buffer_infos
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer_info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(binding as u32)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(buffer_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        Self {
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_set,
+            enabled: true,
+            gpu_average_ms: (None, None),
+        }
+    }
+
+    //パイプラインレイアウトのset = 2に使う
+    pub fn descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    //gpu_average_msは切り替える前の描画で計測したGPU時間の平均
+    //切り替えた後は計測をやり直すので、両方の描画の値が揃っていれば比較を出力する
+    pub fn toggle(&mut self, gpu_average_ms: Option<f64>) {
+        if self.enabled {
+            self.gpu_average_ms.1 = gpu_average_ms;
+        } else {
+            self.gpu_average_ms.0 = gpu_average_ms;
+        }
+
+        self.enabled = !self.enabled;
+
+        log::info!("vertex pulling: {}", self.enabled);
+
+        if let (Some(vertex_input_ms), Some(pulling_ms)) = self.gpu_average_ms {
+            log::info!(
+                "gpu time: {:.3} ms vertex input | {:.3} ms vertex pulling",
+                vertex_input_ms,
+                pulling_ms
+            );
+        }
+    }
+
+    pub fn cmd_bind(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                2,
+                &[self.descriptor_set],
+                &[],
+            );
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
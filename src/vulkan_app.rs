@@ -1,55 +1,822 @@
+use crate::animation::{self, Animator};
+use crate::asset_upload::AssetUploader;
+use crate::benchmark::Benchmark;
+use crate::bloom::{self, Bloom, BloomConstants, BloomPass, BloomStage};
+use crate::camera::{Camera, Projection};
+use crate::clear_color::ClearColor;
+use crate::cli::{AppConfig, DemoConfig};
+use crate::color_space::{self, ColorEncoding, ColorFormat};
+use crate::compute_queue::ComputeQueue;
+use crate::crash_diagnostics::{self, Checkpoint, CrashDiagnostics};
+use crate::debug::ValidationSeverity;
+use crate::debug_text::{DebugText, TextVertex};
+use crate::debug_view::{DebugView, OverdrawCounters};
+use crate::depth_buffer::{DepthBuffer, DepthConvention};
+use crate::descriptor_allocator::DescriptorLayoutCache;
+use crate::device_features::{DeviceFeature, DeviceFeatureRequest, EnabledFeatures};
+use crate::display_surface::{DisplayChoice, DisplayMode};
+use crate::display_timing::FramePacer;
+use crate::drawable::{self, BindState, Drawable, Material};
+use crate::dynamic_rendering::{DynamicRendering, DynamicRenderingSupport};
+use crate::egui_overlay::{EguiOverlay, EguiVertex, OverlayInfo, OverlaySettings};
+use crate::frame_clock::FrameClock;
+use crate::frame_limiter::{AnimationTimer, FrameLimiter};
+use crate::frame_stats::FrameStats;
+use crate::frustum::Frustum;
+use crate::gamepad::Gamepads;
+use crate::gpu_timer::GpuTimer;
+use crate::host_alloc::HostAllocTracker;
+use crate::input::{Action, InputMap, InputState};
+use crate::instance_config::InstanceConfig;
+use crate::instancing::{InstanceData, InstancedGrid};
+use crate::light_culling::LightCulling;
+use crate::light_manager::LightManager;
+use crate::lighting::{Light, LightUniforms, LightingMode};
+use crate::material_textures::{
+    DescriptorIndexingSupport, MaterialConstants, MaterialTextures, TextureBinding,
+};
+use crate::memory_budget::MemoryBudget;
+use crate::mesh::{Mesh, Vertex};
+use crate::mesh_shading::MeshShading;
+use crate::mipmap::{self, MipGenerator};
+use crate::msaa::{self, MsaaTarget};
+use crate::normal_map::{NormalMap, NormalMapConstants};
+use crate::object_buffer::{ObjectBuffers, ObjectUniforms};
+use crate::occlusion_culling::{OcclusionConstants, OcclusionCulling};
+use crate::one_time_commands::OneTimeCommands;
+use crate::parallel_renderer::{DrawState, ObjectDraw, ParallelRenderer, SecondaryTarget};
+use crate::particles::{Particle, ParticleSystem};
+use crate::pipeline_bench::{self, PipelineBench};
+use crate::pipeline_cache::PipelineCache;
+use crate::pipeline_statistics::PipelineStatistics;
+use crate::post_process::{OffscreenTarget, PostEffect, PostProcess};
+use crate::procedural_texture::ProceduralTexture;
 use crate::queue_family::QueueFamilyIndices;
-use crate::required_names::get_required_device_extensions;
-use crate::swap_chain_utils::SwapChainSupportDetails;
-use crate::{debug, khr_util, WindowHandlers};
-use ash::extensions::khr::{Surface, Swapchain};
+use crate::queues::{QueueRequests, Queues};
+use crate::ray_query_shadows::RayQueryShadows;
+use crate::ray_tracing::{RayTracer, RayTracingConstants};
+use crate::render_graph::{ImageUse, RenderGraph};
+use crate::render_scale::{RenderScale, ScaleFilter};
+use crate::required_names::{get_optional_device_extensions, get_required_device_extensions};
+use crate::sampler::{SamplerCache, SamplerDesc};
+use crate::scene_file::{LightState, MaterialState, SceneState};
+use crate::session::{SessionPlayer, SessionRecorder};
+use crate::shadow_map::{self, Ground, ShadowConstants, ShadowMap};
+use crate::skinning::{SkinVertex, Skinning};
+use crate::skybox::{CubemapFaces, Skybox};
+use crate::specialization::SpecConstants;
+use crate::split_screen::SplitScreen;
+use crate::sprite_batch::{SpriteBatch, SpriteVertex};
+use crate::sprite_demo::{self, SpriteDemo};
+use crate::staging_ring::StagingRing;
+use crate::submit_queue::SubmitQueue;
+use crate::swap_chain_utils::{
+    PresentModePreference, SurfaceFormatPreference, SwapChainSettings, SwapChainSupportDetails,
+};
+use crate::synchronization::{self, Synchronization, Synchronization2Support};
+use crate::tessellation::{self, TessellatedPlane, TessellationConstants};
+use crate::texture::TextureOptions;
+use crate::texture_array::TextureArray;
+use crate::texture_manager::TextureManager;
+use crate::timeline_semaphore::TimelineSemaphore;
+use crate::tonemap::{self, TonemapConstants, Tonemapper};
+use crate::transparency::{self, BlendMode, DrawCall, TransparentQuads};
+use crate::uniform_buffer::{UniformBufferObject, UniformBuffers};
+use crate::vertex_pulling::VertexPulling;
+use crate::window_target::{MirrorSwapChain, WindowTarget};
+use crate::{
+    compute, debug, device_info, display_surface, gltf_loader, khr_util, obj_loader, WindowHandlers,
+};
+use ash::extensions::ext::FullScreenExclusive;
+use ash::extensions::google::DisplayTiming;
+use ash::extensions::khr::{PushDescriptor, Surface, Swapchain};
 use ash::vk::{
     CommandPool, DebugUtilsMessengerCreateInfoEXT, DebugUtilsMessengerEXT, Format, PhysicalDevice,
-    Pipeline, Queue, SharingMode, SurfaceKHR, SwapchainKHR,
+    Pipeline, SharingMode, SurfaceKHR, SwapchainKHR,
 };
 use ash::{extensions::ext::DebugUtils, vk, Device, Entry, Instance};
+use glam::{Mat4, Vec3, Vec4};
 use log::{debug, info};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 use std::{
     error::Error,
     ffi::{c_void, CStr, CString},
+    mem,
     result::Result,
 };
-use winit::dpi::LogicalSize;
-use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use winit::dpi::{LogicalSize, PhysicalSize};
+use winit::event::{Event, MouseButton, StartCause, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::Window;
-
-#[cfg(debug_assertions)]
-const ENABLE_VALIDATION_LAYERS: bool = true;
-
-#[cfg(not(debug_assertions))]
-const ENABLE_VALIDATION_LAYERS: bool = false;
+use winit::window::{Fullscreen, Window, WindowId};
 
 ///Validation Layerで必要な機能一覧
 ///今のAshだともっと良いやり方がある、Swapchainのやり方はその一例
 pub const REQUIRED_LAYERS: [&str; 1] = ["VK_LAYER_KHRONOS_validation"];
 
-//同時にレンダリングできるフレーム数を指定
+//instanceを作る時に要求するVulkanのバージョン、ローダーがこれより古い場合はローダーのバージョンにする
+const API_VERSION: u32 = vk::make_api_version(0, 1, 3, 0);
+
+//Vulkanのローダーが見つからない場合に表示する、インストールするものの案内
+const LOADER_HINT: &str = if cfg!(target_os = "windows") {
+    "Install or update the GPU driver from the vendor, vulkan-1.dll is installed with it"
+} else if cfg!(target_os = "macos") {
+    "Install the Vulkan SDK or MoltenVK so that libvulkan.dylib can be found"
+} else {
+    "Install the Vulkan loader (libvulkan1 or vulkan-loader) and a driver such as mesa-vulkan-drivers"
+};
+
+//同時にレンダリングできるフレーム数を指定、設定ファイルの[renderer] frames_in_flightで変えられる
 //2という数字を選んだのはCPUがGPUに対して選考しすぎないようにするため
 //ここらへんの設定やFenceなどが垂直同期に対して関わってくるのだと思う
-pub const MAX_FRAMES_IN_FLIGHT: u32 = 2;
+pub const DEFAULT_FRAMES_IN_FLIGHT: u32 = 2;
+
+//frames_in_flightの上限、これより多くしても入力から表示までが遅れるだけになる
+pub const MAX_FRAMES_IN_FLIGHT: u32 = 4;
+
+//毎フレーム書き直す頂点を置くStagingRingの1フレーム分の大きさ
+//--debug-textと--spritesの上限まで置いても収まるようにしておく
+const STAGING_RING_CAPACITY: vk::DeviceSize = 2 * 1024 * 1024;
+
+//シミュレーションを進める固定間隔(120Hz)
+const FIXED_TIMESTEP: Duration = Duration::from_nanos(1_000_000_000 / 120);
+
+//モデルの1秒あたりの回転量(ラジアン)
+const MODEL_ROTATION_SPEED: f32 = std::f32::consts::FRAC_PI_4;
+
+//ここの環境変数はrust-gpu側が設定をしてくれる
+const SHADER_PATH: &str = env!("rust_shader.spv");
+const SHADER_CODE: &[u8] = include_bytes!(env!("rust_shader.spv"));
+//main_rgen, main_rmiss, main_rchitを含む別のモジュール
+const RAY_TRACING_SHADER_CODE: &[u8] = include_bytes!(env!("ray_tracing_shader.spv"));
+//main_fs_ray_query_shadowedを含む別のモジュール
+const RAY_QUERY_SHADER_CODE: &[u8] = include_bytes!(env!("ray_query_shader.spv"));
+//main_vs_patch, main_tesc, main_teseを含む別のモジュール
+const TESSELLATION_SHADER_CODE: &[u8] = include_bytes!(env!("tessellation_shader.spv"));
+//main_vs_normals, main_gs_normalsを含む別のモジュール
+const GEOMETRY_SHADER_CODE: &[u8] = include_bytes!(env!("geometry_shader.spv"));
+//shaders/glsl/mesh_shading.taskとmesh_shading.meshをbuild.rsでglslcがコンパイルしたもの、glslcが無い場合は空
+const MESH_SHADING_TASK_CODE: &[u8] = include_bytes!(env!("mesh_shading_task.spv"));
+const MESH_SHADING_MESH_CODE: &[u8] = include_bytes!(env!("mesh_shading_mesh.spv"));
+
+//main_fsの特殊化定数のid、シェーダー側のspec_constantと同じ
+//DebugView::spec_constantの値で、ライティングの代わりに法線か深度を色にする
+const DEBUG_VIEW_CONSTANT_ID: u32 = 0;
+
+//キャプチャツールで表示するシャドウマップのパスのラベル
+const SHADOW_PASS_LABEL: &[u8] = b"Shadow pass\0";
+const SHADOW_PASS_LABEL_COLOR: [f32; 4] = [0.4, 0.4, 0.8, 1.0];
+
+//draw_frameで失われたことが分かったリソース
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum LostResource {
+    Surface,
+    Device,
+}
+
+//グラフィックスパイプラインで使う頂点シェーダーと頂点入力の組み合わせ
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum VertexStage {
+    //meshの頂点とset = 1のモデル行列で描画する
+    Mesh,
+    //Meshに加えてGPUが読んだframe_indexを書き出す
+    UboStress,
+    //binding = 1にインスタンスごとのデータを追加する
+    Instanced,
+    //Instancedに加えて、インスタンスごとのレイヤーでset = 2のテクスチャ配列を貼る
+    InstancedTextureArray,
+    //パーティクルのバッファを頂点バッファとして点で描画する
+    Particles,
+    //頂点バッファを使わずにvertex_indexから立方体を作り、キューブマップを描画する
+    Skybox,
+    //フルスクリーンの三角形で前のパスの画像にエフェクトを掛ける
+    PostProcess(PostEffect),
+    //フルスクリーンの三角形でBloomの縮小した画像を読み、1段分縮めるか広げる
+    Bloom(BloomStage),
+    //Meshと同じ頂点とモデル行列に加えて、ObjectUniformsの色とアルファで半透明に描画する
+    Transparent,
+    //Meshと同じ頂点とモデル行列をpush constantのライトの行列で変換し、シャドウマップに深度値だけを書き込む
+    ShadowDepth,
+    //頂点バッファを使わずにpush constantのバウンディングボックスの立方体を描画し、OCCLUSIONクエリで深度テストだけをする
+    OcclusionBox,
+    //Meshに加えてset = 2のシャドウマップで影を付ける
+    Shadowed,
+    //Shadowedと同じ頂点シェーダーで、シャドウマップの代わりにset = 3のTLASへのray queryで影を付ける
+    RayQueryShadowed,
+    //Meshに加えてset = 2のマテリアルのテクスチャを貼る
+    Textured(TextureBinding),
+    //Meshに加えて頂点のテクスチャ座標とtangentを読み、set = 2の法線マップで法線を変える
+    NormalMapped,
+    //Meshに加えてbinding = 1のジョイントと重みを読み、set = 2のジョイントの行列で頂点と法線を動かす
+    Skinned,
+    //頂点入力を使わずにset = 2のstorage bufferからメッシュの頂点を読む
+    Pulled,
+    //頂点シェーダーの代わりにタスクシェーダーとメッシュシェーダーで、Pulledと同じset = 2のバッファから描画する
+    MeshShaded,
+    //四角形のパッチをテッセレーションで分割し、評価シェーダーで高さの関数に沿って変位させる
+    Tessellated,
+    //Meshと同じ頂点とモデル行列を点で読み、ジオメトリシェーダーで法線の線にする
+    Normals,
+    //クリップ座標の四角形にset = 0のフォントのアトラスで文字を描画する
+    Text,
+    //ワールド座標のXY平面の四角形にset = 1のテクスチャを貼り、頂点の色を掛けて半透明に描画する
+    Sprite,
+    //クリップ座標のeguiのメッシュにset = 0のテクスチャを貼り、アルファを掛け済みの頂点の色を掛けて重ねる
+    Egui,
+    //Meshと同じ頂点とモデル行列で、色を書き込まずにset = 2のstorage bufferへピクセルごとのフラグメントの数を足す
+    Overdraw,
+    //フルスクリーンの三角形でset = 0のOverdrawCountersのオーバードローの数を色にする
+    OverdrawHeatmap,
+    //フルスクリーンの三角形でset = 0のUniformBuffersのタイルごとのライトの数を色にして重ねる
+    LightTilesHeatmap,
+}
+
+impl VertexStage {
+    fn entry_point(self) -> &'static str {
+        match self {
+            VertexStage::Mesh | VertexStage::Overdraw => "main_vs",
+            VertexStage::UboStress => "main_vs_ubo_stress",
+            VertexStage::Instanced => "main_vs_instanced",
+            VertexStage::InstancedTextureArray => "main_vs_instanced_array",
+            VertexStage::Particles => "main_vs_particle",
+            VertexStage::Skybox => "main_vs_skybox",
+            VertexStage::PostProcess(_)
+            | VertexStage::Bloom(_)
+            | VertexStage::OverdrawHeatmap
+            | VertexStage::LightTilesHeatmap => "main_vs_fullscreen",
+            VertexStage::Transparent => "main_vs_transparent",
+            VertexStage::ShadowDepth => "main_vs_shadow",
+            VertexStage::OcclusionBox => "main_vs_occlusion_box",
+            VertexStage::Shadowed | VertexStage::RayQueryShadowed => "main_vs_shadowed",
+            VertexStage::Textured(_) => "main_vs_textured",
+            VertexStage::NormalMapped => "main_vs_normal_mapped",
+            VertexStage::Skinned => "main_vs_skinned",
+            VertexStage::Pulled => "main_vs_pulled",
+            //GLSLのモジュールなのでmain
+            VertexStage::MeshShaded => "main",
+            VertexStage::Tessellated => "main_vs_patch",
+            VertexStage::Normals => "main_vs_normals",
+            VertexStage::Text => "main_vs_text",
+            VertexStage::Sprite => "main_vs_sprite",
+            VertexStage::Egui => "main_vs_egui",
+        }
+    }
+
+    //シェーダーの計算はリニアで行い、outputがSrgbかPqの場合だけ書き込む前にエンコードする
+    //Pqに書き込むのはポストプロセスの最後のパスと、その上に重ねる文字とeguiだけ
+    fn fragment_entry_point(self, output: ColorEncoding) -> &'static str {
+        match (self, output) {
+            (VertexStage::PostProcess(effect), _) => effect.fragment_entry_point(output),
+            //BLOOM_FORMATの画像に書き込むのでエンコードしない
+            (VertexStage::Bloom(stage), _) => stage.fragment_entry_point(),
+            (VertexStage::Text, ColorEncoding::Pq) => "main_fs_text_encode_pq",
+            (VertexStage::Text, _) => "main_fs_text",
+            (VertexStage::Egui, ColorEncoding::Linear) => "main_fs_egui",
+            (VertexStage::Egui, ColorEncoding::Srgb) => "main_fs_egui_encode_srgb",
+            (VertexStage::Egui, ColorEncoding::Pq) => "main_fs_egui_encode_pq",
+            //数えるだけで色は書き込まないのでエンコードしない
+            (VertexStage::Overdraw, _) => "main_fs_overdraw",
+            (VertexStage::OverdrawHeatmap, ColorEncoding::Linear) => "main_fs_overdraw_heatmap",
+            (VertexStage::OverdrawHeatmap, ColorEncoding::Srgb) => {
+                "main_fs_overdraw_heatmap_encode_srgb"
+            }
+            (VertexStage::LightTilesHeatmap, ColorEncoding::Linear) => {
+                "main_fs_light_tiles_heatmap"
+            }
+            (VertexStage::LightTilesHeatmap, ColorEncoding::Srgb) => {
+                "main_fs_light_tiles_heatmap_encode_srgb"
+            }
+            (VertexStage::InstancedTextureArray, ColorEncoding::Linear) => "main_fs_texture_array",
+            (VertexStage::InstancedTextureArray, ColorEncoding::Srgb) => {
+                "main_fs_texture_array_encode_srgb"
+            }
+            (VertexStage::Sprite, ColorEncoding::Linear) => "main_fs_sprite",
+            (VertexStage::Sprite, ColorEncoding::Srgb) => "main_fs_sprite_encode_srgb",
+            (VertexStage::Skybox, ColorEncoding::Linear) => "main_fs_skybox",
+            (VertexStage::Skybox, ColorEncoding::Srgb) => "main_fs_skybox_encode_srgb",
+            (VertexStage::Transparent, ColorEncoding::Linear) => "main_fs_transparent",
+            (VertexStage::Transparent, ColorEncoding::Srgb) => "main_fs_transparent_encode_srgb",
+            (VertexStage::Shadowed, ColorEncoding::Linear) => "main_fs_shadowed",
+            (VertexStage::Shadowed, ColorEncoding::Srgb) => "main_fs_shadowed_encode_srgb",
+            (VertexStage::RayQueryShadowed, ColorEncoding::Linear) => "main_fs_ray_query_shadowed",
+            (VertexStage::RayQueryShadowed, ColorEncoding::Srgb) => {
+                "main_fs_ray_query_shadowed_encode_srgb"
+            }
+            (VertexStage::Textured(TextureBinding::Bindless), ColorEncoding::Linear) => {
+                "main_fs_bindless"
+            }
+            (VertexStage::Textured(TextureBinding::Bindless), ColorEncoding::Srgb) => {
+                "main_fs_bindless_encode_srgb"
+            }
+            (VertexStage::Textured(TextureBinding::PerMaterial), ColorEncoding::Linear) => {
+                "main_fs_textured"
+            }
+            (VertexStage::Textured(TextureBinding::PerMaterial), ColorEncoding::Srgb) => {
+                "main_fs_textured_encode_srgb"
+            }
+            (VertexStage::NormalMapped, ColorEncoding::Linear) => "main_fs_normal_mapped",
+            (VertexStage::NormalMapped, ColorEncoding::Srgb) => "main_fs_normal_mapped_encode_srgb",
+            //パーティクルは法線を持たず、法線の線はライティングすると見づらいのでライティングしない
+            (VertexStage::Particles | VertexStage::Normals, ColorEncoding::Linear) => {
+                "main_fs_unlit"
+            }
+            (VertexStage::Particles | VertexStage::Normals, ColorEncoding::Srgb) => {
+                "main_fs_unlit_encode_srgb"
+            }
+            (_, ColorEncoding::Linear) => "main_fs",
+            (_, ColorEncoding::Srgb) => "main_fs_encode_srgb",
+            (stage, ColorEncoding::Pq) => {
+                unreachable!(
+                    "{:?} draws to an intermediate image on a PQ swapchain",
+                    stage
+                )
+            }
+        }
+    }
+
+    fn vertex_input(
+        self,
+    ) -> (
+        Vec<vk::VertexInputBindingDescription>,
+        Vec<vk::VertexInputAttributeDescription>,
+    ) {
+        match self {
+            VertexStage::Mesh
+            | VertexStage::UboStress
+            | VertexStage::Transparent
+            | VertexStage::ShadowDepth
+            | VertexStage::Shadowed
+            | VertexStage::RayQueryShadowed
+            | VertexStage::Textured(_)
+            | VertexStage::Tessellated
+            | VertexStage::Normals
+            | VertexStage::Overdraw => (
+                vec![Vertex::binding_description()],
+                Vertex::attribute_descriptions().to_vec(),
+            ),
+            VertexStage::NormalMapped => (
+                vec![Vertex::binding_description()],
+                [
+                    &Vertex::attribute_descriptions()[..],
+                    &Vertex::normal_map_attribute_descriptions()[..],
+                ]
+                .concat(),
+            ),
+            VertexStage::Skinned => (
+                vec![
+                    Vertex::binding_description(),
+                    SkinVertex::binding_description(),
+                ],
+                [
+                    &Vertex::attribute_descriptions()[..],
+                    &SkinVertex::attribute_descriptions()[..],
+                ]
+                .concat(),
+            ),
+            VertexStage::Instanced | VertexStage::InstancedTextureArray => (
+                vec![
+                    Vertex::binding_description(),
+                    InstanceData::binding_description(),
+                ],
+                [
+                    Vertex::attribute_descriptions(),
+                    InstanceData::attribute_descriptions(),
+                ]
+                .concat(),
+            ),
+            VertexStage::Particles => (
+                vec![Particle::binding_description()],
+                Particle::attribute_descriptions().to_vec(),
+            ),
+            VertexStage::Text => (
+                vec![TextVertex::binding_description()],
+                TextVertex::attribute_descriptions().to_vec(),
+            ),
+            VertexStage::Sprite => (
+                vec![SpriteVertex::binding_description()],
+                SpriteVertex::attribute_descriptions().to_vec(),
+            ),
+            VertexStage::Egui => (
+                vec![EguiVertex::binding_description()],
+                EguiVertex::attribute_descriptions().to_vec(),
+            ),
+            VertexStage::Skybox
+            | VertexStage::OcclusionBox
+            | VertexStage::PostProcess(_)
+            | VertexStage::Bloom(_)
+            | VertexStage::OverdrawHeatmap
+            | VertexStage::LightTilesHeatmap
+            | VertexStage::Pulled
+            | VertexStage::MeshShaded => (vec![], vec![]),
+        }
+    }
+
+    fn topology(self) -> vk::PrimitiveTopology {
+        match self {
+            VertexStage::Particles | VertexStage::Normals => vk::PrimitiveTopology::POINT_LIST,
+            VertexStage::Skybox | VertexStage::OcclusionBox => {
+                vk::PrimitiveTopology::TRIANGLE_STRIP
+            }
+            VertexStage::Tessellated => vk::PrimitiveTopology::PATCH_LIST,
+            _ => vk::PrimitiveTopology::TRIANGLE_LIST,
+        }
+    }
+
+    //スカイボックスは立方体の内側から見るうえにSTRIPで三角形の向きが交互になるのでカリングしない
+    //フルスクリーンの三角形は向きを気にしなくて良いようにカリングしない
+    //半透明の四角形は裏側からも見えるようにカリングしない
+    //シャドウマップはライトの行列でY軸を反転させていないので三角形の向きが逆になるうえ、裏側からも影を落とすのでカリングしない
+    //テッセレーションの平面は変位させた波の裏側も見えるのでカリングしない
+    //文字の四角形とeguiのメッシュは向きを気にしなくて良いようにカリングしない
+    //スプライトは回転や負の大きさで裏返っても描画する
+    //オクルージョンのボックスはSTRIPで三角形の向きが交互になるうえ、奥の面だけが見えている場合も数える
+    fn cull_mode(self) -> vk::CullModeFlags {
+        match self {
+            VertexStage::Skybox
+            | VertexStage::OcclusionBox
+            | VertexStage::PostProcess(_)
+            | VertexStage::Bloom(_)
+            | VertexStage::OverdrawHeatmap
+            | VertexStage::LightTilesHeatmap
+            | VertexStage::Transparent
+            | VertexStage::ShadowDepth
+            | VertexStage::Tessellated
+            | VertexStage::Text
+            | VertexStage::Sprite
+            | VertexStage::Egui => vk::CullModeFlags::NONE,
+            _ => vk::CullModeFlags::BACK,
+        }
+    }
+
+    //不透明な物は深度テストをして深度値を書き込む
+    //半透明な物は不透明な物に隠れる部分だけ省き、後ろの半透明な物が消えないように深度値は書き込まない
+    //スカイボックスは一番遠い深度値で描画するのでクリアした値と等しくても通す
+    //スプライトは深度ではなく描画した順番で重ねる
+    //オクルージョンのボックスは不透明な物の面と重なる部分も数えるので等しい場合も通し、深度値は書き込まない
+    //オーバードローは隠れるフラグメントも数えるので深度テストをしない
+    //reverse-Zでは手前ほど深度値が大きいので、シャドウマップ以外は比較の向きを逆にする
+    fn depth_stencil_state(
+        self,
+        depth: DepthConvention,
+    ) -> vk::PipelineDepthStencilStateCreateInfo {
+        let (test, write, compare_op) = match self {
+            VertexStage::PostProcess(_)
+            | VertexStage::Bloom(_)
+            | VertexStage::Text
+            | VertexStage::Sprite
+            | VertexStage::Egui
+            | VertexStage::Overdraw
+            | VertexStage::OverdrawHeatmap
+            | VertexStage::LightTilesHeatmap => (false, false, vk::CompareOp::ALWAYS),
+            VertexStage::Skybox | VertexStage::OcclusionBox => {
+                (true, false, vk::CompareOp::LESS_OR_EQUAL)
+            }
+            VertexStage::Transparent => (true, false, vk::CompareOp::LESS),
+            _ => (true, true, vk::CompareOp::LESS),
+        };
+
+        let compare_op = match self {
+            VertexStage::ShadowDepth => compare_op,
+            _ => depth.compare_op(compare_op),
+        };
+
+        vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(test)
+            .depth_write_enable(write)
+            .depth_compare_op(compare_op)
+            //指定した範囲の深度値のフラグメントだけを残す機能だが使わない
+            .depth_bounds_test_enable(false)
+            .stencil_test_enable(false)
+            .build()
+    }
+
+    //ray queryのフラグメントシェーダーはRayQueryKHRのcapabilityが要るのでRAY_QUERY_SHADER_CODEにある
+    fn uses_ray_query(self) -> bool {
+        self == VertexStage::RayQueryShadowed
+    }
+
+    //main_fsとmain_fs_normal_mappedと、それぞれのencode_srgbだけがDEBUG_VIEW_CONSTANT_IDの特殊化定数を読む
+    fn has_debug_view(self) -> bool {
+        matches!(
+            self.fragment_entry_point(ColorEncoding::Linear),
+            "main_fs" | "main_fs_normal_mapped"
+        )
+    }
+
+    //テッセレーションとジオメトリシェーダーはcapabilityが要るので、頂点シェーダーと一緒に別のモジュールにある
+    fn stage_shader_code(self) -> Option<&'static [u8]> {
+        match self {
+            VertexStage::Tessellated => Some(TESSELLATION_SHADER_CODE),
+            VertexStage::Normals => Some(GEOMETRY_SHADER_CODE),
+            _ => None,
+        }
+    }
+
+    //タスクシェーダーとメッシュシェーダーは頂点シェーダーの代わりに使う、どちらもエントリーポイントはentry_point
+    fn mesh_shader_code(self) -> Option<[&'static [u8]; 2]> {
+        match self {
+            VertexStage::MeshShaded => Some([MESH_SHADING_TASK_CODE, MESH_SHADING_MESH_CODE]),
+            _ => None,
+        }
+    }
+
+    //頂点シェーダーとフラグメントシェーダーの間で動くステージと、stage_shader_codeでのエントリーポイント
+    fn extra_stages(self) -> &'static [(vk::ShaderStageFlags, &'static str)] {
+        match self {
+            VertexStage::Tessellated => &[
+                (vk::ShaderStageFlags::TESSELLATION_CONTROL, "main_tesc"),
+                (vk::ShaderStageFlags::TESSELLATION_EVALUATION, "main_tese"),
+            ],
+            VertexStage::Normals => &[(vk::ShaderStageFlags::GEOMETRY, "main_gs_normals")],
+            _ => &[],
+        }
+    }
+
+    //半透明な物と文字とスプライトとeguiとライトのタイルは書き込み済みの色にアルファで重ねる
+    fn blend_enable(self) -> bool {
+        matches!(
+            self,
+            VertexStage::Transparent
+                | VertexStage::Text
+                | VertexStage::Sprite
+                | VertexStage::Egui
+                | VertexStage::LightTilesHeatmap
+        )
+    }
+
+    //eguiの色はアルファを掛け済みなので、新しい色にはアルファを掛けずに足す
+    fn premultiplied_alpha(self) -> bool {
+        self == VertexStage::Egui
+    }
+
+    //シャドウマップへの描画はカラーアタッチメントもフラグメントシェーダーも使わない
+    fn writes_color(self) -> bool {
+        self != VertexStage::ShadowDepth
+    }
+
+    //オクルージョンのボックスはカラーアタッチメントのあるパスで描画するが、深度テストだけなのでフラグメントシェーダーは要らない
+    fn has_fragment_shader(self) -> bool {
+        self.writes_color() && self != VertexStage::OcclusionBox
+    }
+
+    fn color_write_mask(self) -> vk::ColorComponentFlags {
+        match self {
+            VertexStage::OcclusionBox | VertexStage::Overdraw => vk::ColorComponentFlags::empty(),
+            _ => {
+                vk::ColorComponentFlags::R
+                    | vk::ColorComponentFlags::G
+                    | vk::ColorComponentFlags::B
+                    | vk::ColorComponentFlags::A
+            }
+        }
+    }
+
+    //シャドウマップに書き込む深度値に掛けるバイアスの(constant, slope)
+    fn depth_bias(self) -> Option<(f32, f32)> {
+        (self == VertexStage::ShadowDepth).then(|| {
+            (
+                shadow_map::DEPTH_BIAS_CONSTANT,
+                shadow_map::DEPTH_BIAS_SLOPE,
+            )
+        })
+    }
+
+    //シャドウマップへの描画ではライトの行列を、bindlessのテクスチャではマテリアルの番号を、テッセレーションでは分割数をpush constantで渡す
+    //ブルームでは読む画像のテクセルの大きさと、しきい値と足す割合を渡す
+    //トーンマッピングでは露出と方法を渡し、ブルームのエフェクトではそれをBloomConstantsの後ろに置く
+    //法線マップでは使うかどうかを渡す
+    fn push_constant_ranges(self) -> Vec<vk::PushConstantRange> {
+        match self {
+            VertexStage::ShadowDepth => vec![vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .offset(0)
+                .size(mem::size_of::<ShadowConstants>() as u32)
+                .build()],
+            VertexStage::OcclusionBox => vec![vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .offset(0)
+                .size(mem::size_of::<OcclusionConstants>() as u32)
+                .build()],
+            VertexStage::Textured(TextureBinding::Bindless) => {
+                vec![vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .offset(0)
+                    .size(mem::size_of::<MaterialConstants>() as u32)
+                    .build()]
+            }
+            VertexStage::NormalMapped => vec![vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .offset(0)
+                .size(mem::size_of::<NormalMapConstants>() as u32)
+                .build()],
+            VertexStage::Tessellated => vec![vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::TESSELLATION_CONTROL)
+                .offset(0)
+                .size(mem::size_of::<TessellationConstants>() as u32)
+                .build()],
+            VertexStage::Bloom(_) => vec![vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .offset(0)
+                .size(mem::size_of::<BloomConstants>() as u32)
+                .build()],
+            VertexStage::PostProcess(PostEffect::Bloom) => vec![vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .offset(0)
+                .size(
+                    (mem::size_of::<BloomConstants>() + mem::size_of::<TonemapConstants>()) as u32,
+                )
+                .build()],
+            VertexStage::PostProcess(PostEffect::Tonemap) => vec![vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .offset(0)
+                .size(mem::size_of::<TonemapConstants>() as u32)
+                .build()],
+            _ => vec![],
+        }
+    }
+}
+
+//1つのパスの描画先
+#[derive(Clone, Copy, Debug)]
+struct PassTarget {
+    //dynamic renderingの場合はnullでimage_viewに直接描画する
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    image_view: vk::ImageView,
+    //MSAAの場合はここに描画し、パスの最後にimage_viewへresolveする、使わない場合はnull
+    msaa_view: vk::ImageView,
+}
+
+//frame_graphに登録するパス
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FramePass {
+    Shadow,
+    Scene,
+    //オーバードローの表示でシーンの描画先をヒートマップで上書きする
+    DebugView,
+    //post_process_pipelinesのインデックス
+    PostProcess(usize),
+    //PostEffect::Bloomのパスの前に、Bloomの画像を1段分縮めるか広げる
+    Bloom(BloomPass),
+}
+
+//グラフィックスパイプラインの描画先と、そのデプスバッファの深度値の向き
+//深度テストの比較はDepthConventionに合わせて入れ替える
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum RenderTarget {
+    //render passのsubpass 0
+    RenderPass(vk::RenderPass, DepthConvention, vk::SampleCountFlags),
+    //dynamic renderingではアタッチメントのフォーマットだけを指定する
+    Dynamic {
+        color_format: Format,
+        depth_format: Format,
+        depth: DepthConvention,
+        samples: vk::SampleCountFlags,
+    },
+}
+
+impl RenderTarget {
+    fn depth(self) -> DepthConvention {
+        match self {
+            RenderTarget::RenderPass(_, depth, _) | RenderTarget::Dynamic { depth, .. } => depth,
+        }
+    }
+
+    //パイプラインのrasterization_samplesはアタッチメントのサンプル数と合わせる
+    fn samples(self) -> vk::SampleCountFlags {
+        match self {
+            RenderTarget::RenderPass(_, _, samples) | RenderTarget::Dynamic { samples, .. } => {
+                samples
+            }
+        }
+    }
+}
+
+//ベースのパイプラインから設定を1つだけ変えて一緒に作るパイプライン
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum PipelineVariant {
+    //PolygonMode::LINE
+    Wireframe,
+    //アルファブレンドを有効にしたもの、--bench-pipelinesでだけ作る
+    Blended,
+}
+
+impl PipelineVariant {
+    fn name(self) -> &'static str {
+        match self {
+            PipelineVariant::Wireframe => "wireframe",
+            PipelineVariant::Blended => "blended",
+        }
+    }
+}
+
+//swapchainの再作成時にどこまで作り直すか
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum RecreateScope {
+    //swapchain, image view, framebufferのみ
+    SwapchainOnly,
+    //render passとpipelineも作り直す
+    Full,
+}
+
+//GPUが存在しないCIやコンテナ上で動かすためにソフトウェアラスタライザを選択するかどうか
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SoftwareRendering {
+    Disabled,
+    //CPUデバイスがあればそちらを優先する
+    Prefer,
+    //CPUデバイスが無ければエラーにする
+    Require,
+}
+
+//3Dのシーンを正射影で映す場合の画面の縦のワールド座標の長さ
+const ORTHOGRAPHIC_HEIGHT: f32 = 3.0;
+
+//キューブマップを生成する場合の1面の大きさ
+const GENERATED_SKYBOX_SIZE: u32 = 256;
+
+//--skybox で生成した空を、--skybox-ktx PATH でKTXファイルのキューブマップを背景に描画する
+fn skybox_faces(demo: &DemoConfig) -> Option<CubemapFaces> {
+    if let Some(path) = &demo.skybox_ktx {
+        match CubemapFaces::load_ktx(path) {
+            Ok(faces) => return Some(faces),
+            Err(error) => {
+                log::warn!(
+                    "Failed to load skybox '{}': {}, using a generated sky",
+                    path.display(),
+                    error
+                );
+                return Some(CubemapFaces::generate(GENERATED_SKYBOX_SIZE));
+            }
+        }
+    }
+
+    demo.skybox
+        .then(|| CubemapFaces::generate(GENERATED_SKYBOX_SIZE))
+}
+
+//イベントループの動かし方
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RunMode {
+    //ControlFlow::Pollで毎フレーム描画し続ける
+    //後の章でアニメーションさせるのでこちらがデフォルト
+    Continuous,
+    //ControlFlow::Waitでイベントが来るまでスレッドを止め、再描画が必要な時だけ描画する
+    //静止した画面でCPUを使い続けないので省電力
+    OnDemand {
+        //Someの場合はこの間隔で定期的に再描画する
+        animation_interval: Option<Duration>,
+    },
+}
+
+//メインのswapchainを作るsurfaceをどこから作るか
+#[derive(Clone, Copy)]
+pub enum SurfaceSource<'a> {
+    Window(&'a Window),
+    //VK_KHR_displayでディスプレイのプレーンに直接表示する、winitのウィンドウは作らない
+    Display(DisplayChoice),
+}
+
+//ウィンドウの表示状態
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum FullscreenMode {
+    Windowed,
+    //ウィンドウをモニター全体に広げるだけなのでモードの切り替えが速い
+    Borderless,
+    //VK_EXT_full_screen_exclusiveでディスプレイを占有する
+    Exclusive,
+}
+
+//排他フルスクリーンで使用するモニターのハンドルを取得する
+#[cfg(target_os = "windows")]
+fn current_hmonitor(window: &Window) -> Option<vk::HMONITOR> {
+    use winit::platform::windows::MonitorHandleExtWindows;
+
+    window.current_monitor().map(|monitor| monitor.hmonitor())
+}
 
-//初期サイズ
-const WIDTH: u32 = 800;
-const HEIGHT: u32 = 800;
+#[cfg(not(target_os = "windows"))]
+fn current_hmonitor(_window: &Window) -> Option<vk::HMONITOR> {
+    None
+}
 
 //メンバをOption<>地獄にしないためにはnewに関する関数をメソッドではなく関連関数にすることで回避する
 pub struct VulkanApp {
     entry: Entry,
     instance: Instance,
+    //instanceとdeviceの作成、破棄に渡すコールバックが指す先なので、それらを破棄するまで残す
+    host_alloc_tracker: Option<Box<HostAllocTracker>>,
     debug_utils: Option<DebugUtils>,
     debug_utils_messenger_ext: Option<DebugUtilsMessengerEXT>,
     //物理デバイス
     physical_device: PhysicalDevice,
     //倫理デバイス
     device: Device,
-    graphics_queue: Queue,
-    present_queue: Queue,
+    //one_time_commandsと同じキューの場合もあるので、submitとpresentはSubmitQueueを通す
+    graphics_queue: SubmitQueue,
+    present_queue: SubmitQueue,
     //SurfaceKHRはハンドラ本体でSurfaceはラッパー？
     surface: Surface,
     surface_khr: SurfaceKHR,
@@ -57,1225 +824,8900 @@ pub struct VulkanApp {
     swap_chain_khr: SwapchainKHR,
     swap_chain_images: Vec<vk::Image>,
     swap_chain_image_format: Format,
+    //swap_chain_image_formatに書き込む値の空間を付けたもの
+    swap_chain_color_format: ColorFormat,
     swap_chain_extent: vk::Extent2D,
+    //全てのパスのattachment 1
+    depth_buffer: DepthBuffer,
+    //--msaaで決めたシーンのパスのサンプル数、使えない組み合わせの場合はTYPE_1
+    msaa_samples: vk::SampleCountFlags,
+    //msaa_samplesがTYPE_1でない場合のみSome、swapchainと同じ大きさで作り直す
+    msaa_target: Option<MsaaTarget>,
     swap_chain_image_views: Vec<vk::ImageView>,
+    //dynamic renderingの場合はnull
     render_pass: vk::RenderPass,
+    //Noneの場合はrender passとframebufferで描画する
+    dynamic_rendering: Option<DynamicRendering>,
     pipeline_layout: vk::PipelineLayout,
     pipeline: Pipeline,
+    //fill_mode_non_solidが無効な場合はNone
+    wireframe_pipeline: Option<Pipeline>,
+    //コマンドの記録時にwireframe_pipelineを使うかどうか
+    wireframe: bool,
+    enabled_features: EnabledFeatures,
+    pipeline_cache: PipelineCache,
+    //--raytraceで対応している場合のみSome、Someの場合はラスタライズの代わりに使う
+    ray_tracer: Option<RayTracer>,
+    sampler_cache: SamplerCache,
+    //同じbindingのDescriptor Set Layoutを使い回す
+    descriptor_layout_cache: DescriptorLayoutCache,
+    clear_color: ClearColor,
+    //trueの場合はclear_colorを無視して色相を時間で変化させる
+    animate_clear_color: bool,
+    //Lキーで切り替える
+    lighting_mode: LightingMode,
+    //シェーダーに渡すライト、起動時はLIGHT_DIRECTIONのディレクショナルライトだけ
+    light_manager: LightManager,
+    //Cキーで固定した時点の視錐台、Noneの場合は毎フレームのカメラでカリングする
+    frozen_frustum: Option<Frustum>,
+    //draw_frameでSurfaceやDeviceが失われた場合に次のrenderで復帰させる
+    lost: Option<LostResource>,
+    //これまでに描画したフレーム数
+    frame_count: u64,
+    simulate_device_lost_at: Option<u64>,
+    //destroyを呼んだ後はDropで二重に破棄しないようにする
+    destroyed: bool,
+    //GPUが読んだUniform Bufferの値が書き込んだ値と一致するか毎フレーム確認する
+    ubo_stress: bool,
     swap_chain_frame_buffers: Vec<vk::Framebuffer>,
     command_pool: CommandPool,
     command_buffers: Vec<vk::CommandBuffer>,
+    //アップロードなど一度だけ実行するコマンド用
+    one_time_commands: OneTimeCommands,
     current_frame: usize,
     resize: Option<(u32, u32)>,
+    //RunMode::OnDemandの時に次のイベント処理後に描画するかどうか
+    needs_redraw: bool,
+    //RunMode::OnDemandでanimation_intervalがある場合のみSome
+    animation_timer: Option<AnimationTimer>,
+    swap_chain_settings: SwapChainSettings,
+    fullscreen_mode: FullscreenMode,
+    //VK_EXT_full_screen_exclusiveが有効な場合のみSome
+    full_screen_exclusive: Option<FullScreenExclusive>,
+    //acquire_full_screen_exclusive_modeに成功してまだreleaseしていないかどうか
+    full_screen_exclusive_acquired: bool,
+    frame_stats: FrameStats,
+    frame_limiter: FrameLimiter,
+    input: InputState,
+    input_map: InputMap,
+    //ウィンドウに描画する場合のみSome、設定ファイルで無効にした場合とgilrsを使えない場合もNone
+    gamepads: Option<Gamepads>,
+    //--recordの場合のみSome、Dropでファイルに書き出す
+    session_recorder: Option<SessionRecorder>,
+    //--replayの場合のみSome、ウィンドウの入力のイベントは使わない
+    session_player: Option<SessionPlayer>,
+    //F5とF9で使う--sceneのファイル
+    scene_path: PathBuf,
+    //起動時に読み込んだ--objのパス、読み込めなかった場合はNone
+    mesh_path: Option<PathBuf>,
+    camera: Camera,
+    frame_clock: FrameClock,
+    //--benchの場合のみSome
+    benchmark: Option<Benchmark>,
+    //一時停止中に.キーが押された、次のupdateでシミュレーションを1回分進めて消費する
+    frame_advance_requested: bool,
+    //固定間隔のupdateで回しているモデルのY軸周りの角度(ラジアン)
+    model_rotation: f32,
+    //マウスで視点を回転させている間はカーソルをウィンドウ内に固定する
+    cursor_grabbed: bool,
+    uniform_buffers: UniformBuffers,
+    //--quad-gridの一辺の数、Noneの場合は三角形を1つだけ描画する
+    quad_grid: Option<u32>,
+    mesh: Mesh,
+    //--async-assetsで--objを指定した場合のみSome、転送し終えるまでmeshはプレースホルダーになる
+    asset_uploader: Option<AssetUploader>,
+    //meshを使うsubmitが待つasset_uploaderのタイムラインの値、0の場合は待たない
+    mesh_upload_value: u64,
+    //差し替えたメッシュと、それを最後に使ったフレームがframe_timelineにシグナルする値
+    retired_meshes: Vec<(Mesh, u64)>,
+    //オブジェクトごとのモデル行列をダイナミックオフセットで切り替える
+    object_buffers: ObjectBuffers,
+    //--instanced-gridが指定された場合のみSome、meshをインスタンス描画する
+    instanced_grid: Option<InstancedGrid>,
+    //pipelineで使う頂点シェーダー、pipelineを作り直す時にも使う
+    vertex_stage: VertexStage,
+    //--particlesが指定された場合のみSome
+    particles: Option<ParticleSystem>,
+    //particlesを点で描画するパイプライン、particlesがSomeの場合のみSome
+    particle_pipeline: Option<(vk::Pipeline, vk::PipelineLayout)>,
+    //--skybox, --skybox-ktxの場合のみSome
+    skybox: Option<Skybox>,
+    skybox_pipeline: Option<(vk::Pipeline, vk::PipelineLayout)>,
+    //--split-screenの場合のみSome、右半分のカメラとUniform Bufferを持つ
+    split_screen: Option<SplitScreen>,
+    //記録中のシーンのビュー、0がself.cameraで1がsplit_screenのカメラ
+    current_view: usize,
+    //--occlusion-cullingの場合のみSome
+    occlusion_culling: Option<OcclusionCulling>,
+    //バウンディングボックスをクエリで囲って描画するパイプライン、occlusion_cullingがSomeの場合のみSome
+    occlusion_pipeline: Option<(vk::Pipeline, vk::PipelineLayout)>,
+    //debug_textとegui_overlayとsprite_batchがフレームごとに書き直す頂点を置く
+    staging_ring: StagingRing,
+    //--debug-textの場合のみSome、swapchainに描画するパスの最後に重ねる
+    debug_text: Option<DebugText>,
+    debug_text_pipeline: Option<(vk::Pipeline, vk::PipelineLayout)>,
+    //ウィンドウに描画する場合のみSome、F1で表示を切り替えてdebug_textの上に重ねる
+    egui_overlay: Option<EguiOverlay>,
+    egui_pipeline: Option<(vk::Pipeline, vk::PipelineLayout)>,
+    //debug_textに表示する、最後にframe_statsが出したレポート
+    frame_report_text: String,
+    //--spritesの場合のみSome、メインのパスの最後にスプライトを描画する
+    sprite_batch: Option<SpriteBatch>,
+    sprite_pipeline: Option<(vk::Pipeline, vk::PipelineLayout)>,
+    //sprite_batchに毎フレームスプライトを置くデモ
+    sprite_demo: Option<SpriteDemo>,
+    //正射影に切り替えた時に画面の縦に映すワールド座標の長さ
+    orthographic_height: f32,
+    //ObjectBuffersの先頭から不透明なオブジェクトのモデル行列が並ぶ
+    opaque_object_count: usize,
+    //--transparent-quadsの場合のみSome、ObjectBuffersのopaque_object_countから後ろを使う
+    transparent_quads: Option<TransparentQuads>,
+    transparent_pipeline: Option<(vk::Pipeline, vk::PipelineLayout)>,
+    //--shadowsの場合のみSome、pipelineのset = 2に紐づける
+    shadow_map: Option<ShadowMap>,
+    //シャドウマップに不透明なオブジェクトを描画するパイプライン、shadow_mapがSomeの場合のみSome
+    shadow_pipeline: Option<(vk::Pipeline, vk::PipelineLayout)>,
+    //shadow_mapがSomeの場合のみSome、ObjectBuffersの最後を使う
+    ground: Option<Ground>,
+    //読み込んだテクスチャと組み込みの1×1のテクスチャを持つ
+    texture_manager: TextureManager,
+    //--texturedの場合のみSome、pipelineのset = 2に紐づける
+    material_textures: Option<MaterialTextures>,
+    //--texturedと--procedural-textureの場合のみSome、material_texturesの最初のマテリアルに使う
+    procedural_texture: Option<ProceduralTexture>,
+    //--instanced-gridと--texture-arrayの場合のみSome、pipelineのset = 2に紐づける
+    texture_array: Option<TextureArray>,
+    //--normal-mappingでset = 2を他に使わない場合のみSome、pipelineのset = 2に紐づける
+    normal_map: Option<NormalMap>,
+    //Mキーで切り替える、falseの場合はnormal_mapがあっても頂点の法線で比べられるようにする
+    normal_mapping_enabled: bool,
+    //--gltfでset = 2を他に使わない場合のみSome、pipelineのset = 2に紐づける
+    skinning: Option<Skinning>,
+    //skinningと同時にSome、シミュレーションで進めてフレームごとにskinningへジョイントの行列を書き込む
+    animator: Option<Animator>,
+    //--vertex-pullingの場合のみSome、pipelineのset = 2に紐づける
+    vertex_pulling: Option<VertexPulling>,
+    //main_vs_pulledで描画するパイプライン、vertex_pullingがSomeの場合のみSome
+    //pipelineと同じDescriptor Set Layoutから作るので、pipeline_layoutで紐づけたDescriptor Setをそのまま使える
+    pulling_pipeline: Option<(vk::Pipeline, vk::PipelineLayout)>,
+    //--mesh-shadingでVK_EXT_mesh_shaderを有効にできた場合のみSome、pipelineのset = 2に紐づける
+    //pipelineはタスクシェーダーとメッシュシェーダーのパイプラインになる
+    mesh_shading: Option<MeshShading>,
+    //--ray-query-shadowsで対応している場合のみSome、pipelineのset = 3に紐づける
+    ray_query_shadows: Option<RayQueryShadows>,
+    //main_fs_ray_query_shadowedで描画するパイプライン、ray_query_shadowsがSomeの場合のみSome
+    //pipelineと同じDescriptor Set Layoutから作るので、pipeline_layoutで紐づけたDescriptor Setをそのまま使える
+    ray_query_pipeline: Option<(vk::Pipeline, vk::PipelineLayout)>,
+    //--tessellationでtessellation_shaderを有効にできた場合のみSome
+    tessellated_plane: Option<TessellatedPlane>,
+    //tessellated_planeを描画するパイプラインとワイヤーフレーム用のパイプライン、tessellated_planeがSomeの場合のみSome
+    tessellation_pipeline: Option<(vk::Pipeline, Option<vk::Pipeline>, vk::PipelineLayout)>,
+    //pipelineと同じシェーダーをDEBUG_VIEW_CONSTANT_IDで特殊化したもの、vertex_stageがmain_fsを使う場合のみ空ではない
+    //pipelineと同じDescriptor Set Layoutから作るので、pipeline_layoutで紐づけたDescriptor Setをそのまま使える
+    debug_view_pipelines: Vec<(DebugView, vk::Pipeline, vk::PipelineLayout)>,
+    //コマンドの記録時に表示するもの
+    debug_view: DebugView,
+    //vertex_stageがMeshでset = 2を他に使わず、fragment_stores_and_atomicsを有効にできた場合のみSome
+    overdraw_counters: Option<OverdrawCounters>,
+    //(数えるパイプライン, ヒートマップのパイプライン)、overdraw_countersがSomeの場合のみSome
+    overdraw_pipelines: Option<(
+        (vk::Pipeline, vk::PipelineLayout),
+        (vk::Pipeline, vk::PipelineLayout),
+    )>,
+    //--light-cullingの場合のみSome、uniform_buffersのbinding = 4にタイルごとのライトを書き込む
+    light_culling: Option<LightCulling>,
+    //main_fs_light_tiles_heatmapでタイルのライトの数をシーンに重ねる、light_cullingがSomeの場合のみSome
+    light_tiles_pipeline: Option<(vk::Pipeline, vk::PipelineLayout)>,
+    //geometry_shaderを有効にできた場合のみSome、不透明なメッシュの法線を線で描画する
+    normals_pipeline: Option<(vk::Pipeline, vk::PipelineLayout)>,
+    //コマンドの記録時にnormals_pipelineで法線を重ねるかどうか
+    show_normals: bool,
+    //--post-effectの場合のみSome
+    post_process: Option<PostProcess>,
+    //post_process.effects()と同じ順番
+    post_process_pipelines: Vec<(vk::Pipeline, vk::PipelineLayout)>,
+    //--post-effectにbloomがある場合のみSome、PostEffect::Bloomのパスで足すぼかした画像を作る
+    bloom: Option<Bloom>,
+    //BloomStageごとのパイプライン、bloomがSomeの場合のみ空ではない
+    bloom_pipelines: Vec<(BloomStage, vk::Pipeline, vk::PipelineLayout)>,
+    //PageUp/PageDownで変える、起動時はAppConfig::bloom_intensity
+    bloom_intensity: f32,
+    //ブルームとトーンマッピングのエフェクトでpush constantとして渡すので、パイプラインを作り直さずに切り替えられる
+    //Tで方法を、Home/Endで露出(EV)を変える
+    tonemapper: Tonemapper,
+    exposure: f32,
+    //swapchainの画像にblitできない場合と--raytraceの場合はNone
+    //--render-scaleを指定しなくても+/-で倍率を変えられるように、倍率1.0で持っておく
+    render_scale: Option<RenderScale>,
+    //専用のコンピュートキューファミリーがあり、particlesがSomeの場合のみSome
+    compute_queue: Option<ComputeQueue>,
+    //--record-threadsの場合のみSome、オブジェクトの描画をセカンダリコマンドバッファに記録する
+    parallel_renderer: Option<ParallelRenderer>,
+    //parallel_rendererに渡すオブジェクトごとの描画
+    object_draws: Vec<ObjectDraw>,
+    //直前に読んだグラフィックスのタイムスタンプ、コンピュートと重なっていた時間の計算に使う
+    previous_graphics_range: Option<(f64, f64)>,
+    //VK_GOOGLE_display_timingがサポートされている場合のみSome
+    frame_pacer: Option<FramePacer>,
+    //VK_EXT_memory_budgetがサポートされていない場合は自前で記録した使用量だけを報告する
+    memory_budget: MemoryBudget,
+    //VK_EXT_device_faultもVK_NV_device_diagnostic_checkpointsも無いデバイスでは何もしない
+    crash_diagnostics: CrashDiagnostics,
+    //タイムスタンプクエリに対応していないデバイスではNone
+    gpu_timer: Option<GpuTimer>,
+    //pipeline_statistics_queryのデバイス機能が無い場合はNone
+    pipeline_statistics: Option<PipelineStatistics>,
 
     //これ移行がVecになっているのは複数のフレームを同時にレンダリングするときに複数必要になるため
     //swapchainからimageを取得してレンダリングの準備ができたことを知らせるSemaphore
     image_available_semaphores: Vec<vk::Semaphore>,
     //レンダリングが終了してPresentationの準備ができたことを知らせるSemaphore
     render_finished_semaphores: Vec<vk::Semaphore>,
-    //一度に1フレームしかレンダリングしないようにCPU側で止めるためのFence
-    in_flight_fences: Vec<vk::Fence>,
+    //synchronization2が使えるかどうかでバリアとsubmitの呼び出し方を切り替える
+    synchronization: Synchronization,
+    //graphics_queueへのsubmitごとに値を1つ進めるタイムラインセマフォ
+    //CPU側はこの値を待つことで同時にレンダリングするフレームをframes_in_flightまでに抑える
+    frame_timeline: TimelineSemaphore,
+    //最後にsubmitしたフレームがframe_timelineにシグナルする値
+    submitted_frames: u64,
+    //swapchainの画像ごとにその画像を最後に使ったフレームがframe_timelineにシグナルする値を持つ
+    //画像の枚数はドライバが実際に作成した枚数なのでframes_in_flightと一致するとは限らない
+    images_in_flight: Vec<u64>,
+    //開いている全てのウィンドウ、メインのウィンドウもmirrorを持たないWindowTargetとして含む
+    //--mirror-windowsで開いたウィンドウにはメインのswapchainの画像を毎フレームblitして映す
+    //メインのウィンドウのsurfaceとswapchainは上のフィールドが持つ
+    //全てのウィンドウが閉じられた時に終了する
+    window_targets: HashMap<WindowId, WindowTarget>,
+    //入力を受け取り、swapchainに描画するウィンドウ、--displayではNone
+    //閉じられた時にmirrorのウィンドウが残っていれば、そのうちの1つがメインのウィンドウになる
+    primary_window: Option<WindowId>,
+    //最後に閉じられたメインのウィンドウ、surfaceとswapchainはdestroyで破棄してからウィンドウを閉じる
+    last_window: Option<Rc<Window>>,
+    //--displayの場合のみSome、surfaceが失われた場合やデバイスロストで同じディスプレイから作り直す
+    display: Option<DisplayChoice>,
+    //ウィンドウの論理ピクセルあたりの物理ピクセル数、ScaleFactorChangedで更新する
+    //--displayでは物理ピクセルをそのまま使うので1.0
+    scale_factor: f64,
+    //起動時にコマンドラインと環境変数から読んだ設定、デバイスロストで作り直す時にも使う
+    config: AppConfig,
 }
 
 impl VulkanApp {
-    pub fn new(window: &Window) -> Result<Self, Box<dyn Error>> {
+    pub fn new(source: SurfaceSource, config: AppConfig) -> Result<Self, Box<dyn Error>> {
         debug!("Creating application");
 
-        let entry = unsafe { Entry::load().expect("Failed to create entry.") };
-        let instance = Self::create_instance(&entry)?;
+        let (entry, instance_api_version) = Self::load_entry()?;
+        //Noneの場合は検証レイヤーを有効にしない
+        let validation = config.validation.then(|| config.validation_severity);
+        let demo = &config.demo;
+
+        let host_alloc_tracker = demo.track_host_allocations.then(HostAllocTracker::new);
+        let allocation_callbacks = host_alloc_tracker
+            .as_ref()
+            .map(|tracker| tracker.callbacks());
+
+        let instance = Self::create_instance(
+            &entry,
+            instance_api_version,
+            matches!(source, SurfaceSource::Display(_)),
+            validation,
+            allocation_callbacks.as_ref(),
+        )?;
 
         let mut debug_utils = None;
         let mut debug_utils_messenger_ext = None;
 
-        if ENABLE_VALIDATION_LAYERS {
+        if let Some(severity) = validation {
             let _debug_utils = DebugUtils::new(&entry, &instance);
 
             debug_utils_messenger_ext = Some(
-                debug::setup_debug_utils_messenger_ext(&_debug_utils)
+                debug::setup_debug_utils_messenger_ext(&_debug_utils, severity)
                     .unwrap_or_else(|e| panic!("{}", e)),
             );
 
             debug_utils = Some(_debug_utils);
         }
 
-        let (surface, surface_khr) = Self::create_surface(&instance, &entry, window);
+        let (surface, surface_khr, display_mode) =
+            Self::create_source_surface(&instance, &entry, source)?;
+
+        //swapchainの大きさは物理ピクセルで決める
+        //ウィンドウは論理ピクセルで作っているので、拡大率が1.0でないモニターでは作った時の大きさと異なる
+        let (surface_size, scale_factor) = match (source, display_mode) {
+            (_, Some(mode)) => ((mode.extent.width, mode.extent.height), 1.0),
+            (SurfaceSource::Window(window), None) => {
+                Self::log_window_size(window.inner_size(), window.scale_factor());
+                (window.inner_size().into(), window.scale_factor())
+            }
+            (SurfaceSource::Display(_), None) => unreachable!("A display surface has no mode"),
+        };
+
+        let physical_device = Self::pick_physical_device(
+            &instance,
+            &surface,
+            surface_khr,
+            config.software_rendering,
+            instance_api_version,
+        )?;
+
+        let api_version =
+            device_info::effective_api_version(&instance, physical_device, instance_api_version);
+        info!(
+            "Effective API version: {}",
+            device_info::api_version_string(api_version)
+        );
+
+        let synchronization2_support = if demo.legacy_sync {
+            Synchronization2Support::Unsupported
+        } else {
+            Synchronization2Support::query(&instance, physical_device, api_version)
+        };
+
+        //指定されていない場合は拡張も機能も有効にしない
+        let dynamic_rendering_support = if demo.dynamic_rendering {
+            let support = DynamicRenderingSupport::query(&instance, physical_device, api_version);
+
+            if support == DynamicRenderingSupport::Unsupported {
+                log::warn!("Dynamic rendering is not supported, falling back to render passes");
+            }
+
+            support
+        } else {
+            DynamicRenderingSupport::Unsupported
+        };
+
+        //--texturedを指定しない場合は拡張も機能も有効にしない
+        let descriptor_indexing_support = if demo.textured && !demo.no_bindless {
+            DescriptorIndexingSupport::query(&instance, physical_device, api_version)
+        } else {
+            DescriptorIndexingSupport::Unsupported
+        };
+
+        //main_vs_pulledはmain_vsの代わりなので、他の頂点シェーダーを使う場合は有効にしない
+        let pulls_vertices = if !demo.vertex_pulling {
+            false
+        } else if demo.shadow_map_size.is_some()
+            || demo.textured
+            || demo.instanced_grid.is_some()
+            || demo.ubo_stress
+            || demo.record_threads.is_some()
+        {
+            log::warn!(
+                "--vertex-pulling is ignored with --shadows, --textured, --instanced-grid, --ubo-stress or --record-threads"
+            );
+            false
+        } else {
+            true
+        };
+
+        //タスクシェーダーとメッシュシェーダーはmain_vsの代わりなので、vertex pullingと同じく他の頂点シェーダーを使う場合は有効にしない
+        let mesh_shading = if !demo.mesh_shading {
+            false
+        } else if demo.shadow_map_size.is_some()
+            || demo.textured
+            || demo.instanced_grid.is_some()
+            || demo.ubo_stress
+            || demo.record_threads.is_some()
+            || pulls_vertices
+        {
+            log::warn!(
+                "--mesh-shading is ignored with --shadows, --textured, --instanced-grid, --ubo-stress, --record-threads or --vertex-pulling"
+            );
+            false
+        } else if MeshShading::is_supported(
+            &instance,
+            physical_device,
+            api_version,
+            [MESH_SHADING_TASK_CODE, MESH_SHADING_MESH_CODE],
+        ) {
+            true
+        } else {
+            log::warn!("VK_EXT_mesh_shader is not supported or the shaders were not compiled, using the vertex shader");
+            false
+        };
+
+        let ray_tracing = if !demo.raytrace {
+            false
+        } else if !SwapChainSupportDetails::new(physical_device, &surface, surface_khr)
+            .capabilities
+            .supported_usage_flags
+            .contains(vk::ImageUsageFlags::TRANSFER_DST)
+        {
+            log::warn!("Swapchain images cannot be blitted to, falling back to rasterization");
+            false
+        } else if RayTracer::is_supported(&instance, physical_device, api_version) {
+            true
+        } else {
+            log::warn!("Ray tracing is not supported, falling back to rasterization");
+            false
+        };
+
+        //main_fs_ray_query_shadowedはmain_fs_shadowedの代わりなので、シャドウマップで描画する場合だけ使う
+        let ray_query = if !demo.ray_query_shadows {
+            false
+        } else if demo.shadow_map_size.is_none()
+            || demo.instanced_grid.is_some()
+            || demo.ubo_stress
+            || ray_tracing
+        {
+            log::warn!(
+                "--ray-query-shadows is ignored without --shadows or with --instanced-grid, --ubo-stress or --raytrace"
+            );
+            false
+        } else if RayQueryShadows::is_supported(&instance, physical_device, api_version) {
+            true
+        } else {
+            log::warn!("Ray query is not supported, using the shadow map");
+            false
+        };
+
+        //セカンダリコマンドバッファではDescriptor Setを紐づけるので、--record-threadsでは使わない
+        let push_descriptors = if !demo.push_descriptors {
+            false
+        } else if demo.record_threads.is_some() {
+            log::warn!("--push-descriptors is ignored with --record-threads");
+            false
+        } else if QueueFamilyIndices::is_device_extension_supported(
+            &instance,
+            physical_device,
+            PushDescriptor::name(),
+        ) {
+            true
+        } else {
+            log::warn!("VK_KHR_push_descriptor is not supported, using pooled descriptor sets");
+            false
+        };
 
-        let physical_device = Self::pick_physical_device(&instance, &surface, surface_khr);
+        let (device, queues, enabled_features) = Self::create_logical_device_and_queue(
+            &instance,
+            &surface,
+            surface_khr,
+            physical_device,
+            synchronization2_support,
+            dynamic_rendering_support,
+            descriptor_indexing_support,
+            ray_tracing,
+            ray_query,
+            push_descriptors,
+            mesh_shading,
+            validation.is_some(),
+            allocation_callbacks.as_ref(),
+        )?;
+
+        let Queues {
+            graphics: graphics_queue,
+            present: present_queue,
+            compute: compute_queue,
+            upload: upload_queue,
+        } = queues;
+
+        let dynamic_rendering =
+            DynamicRendering::new(&instance, &device, dynamic_rendering_support);
+
+        let synchronization = Synchronization::new(&instance, &device, synchronization2_support);
+
+        info!(
+            "synchronization2: {:?}{}",
+            synchronization2_support,
+            if synchronization.is_synchronization2() {
+                ""
+            } else {
+                " (using legacy barriers and submits)"
+            }
+        );
 
-        let (device, graphics_queue, present_queue) = Self::create_logical_device_and_queue(
+        let queue_family_indices = QueueFamilyIndices::find_queue_families(
             &instance,
             &surface,
             surface_khr,
             physical_device,
         );
 
-        let (swap_chain, swap_chain_khr, swap_chain_image_format, swap_chain_extent) =
+        let swap_chain_settings = SwapChainSettings {
+            present_mode: config.present_mode,
+            surface_formats: config.surface_format.formats(),
+            image_count: config.image_count,
+            full_screen_exclusive_monitor: None,
+        };
+
+        let full_screen_exclusive = if QueueFamilyIndices::is_device_extension_supported(
+            &instance,
+            physical_device,
+            FullScreenExclusive::name(),
+        ) && cfg!(target_os = "windows")
+        {
+            Some(FullScreenExclusive::new(&instance, &device))
+        } else {
+            None
+        };
+
+        let (swap_chain, swap_chain_khr, swap_chain_color_format, swap_chain_extent) =
             Self::create_swap_chain(
                 &instance,
                 &device,
                 physical_device,
                 &surface,
                 surface_khr,
-                (WIDTH, HEIGHT),
+                surface_size,
+                &swap_chain_settings,
             );
 
+        let swap_chain_image_format = swap_chain_color_format.format();
+
         //imageのLifetimeはswapchainに紐づいているので明示的にDestoryする必要はない
         let swap_chain_images = Self::get_swap_chain_images(&swap_chain, swap_chain_khr);
 
         let swap_chain_image_views =
             Self::create_image_views(&device, &swap_chain_images, swap_chain_image_format);
 
-        let render_pass = Self::create_render_pass(&device, swap_chain_image_format);
+        let msaa_samples = Self::choose_msaa_samples(
+            &instance,
+            physical_device,
+            &config,
+            swap_chain_color_format,
+            ray_tracing,
+        );
+
+        let mut render_scale = Self::create_render_scale(
+            &instance,
+            physical_device,
+            &surface,
+            surface_khr,
+            swap_chain_image_format,
+            demo,
+            ray_tracing,
+            msaa_samples != vk::SampleCountFlags::TYPE_1,
+            dynamic_rendering.is_none(),
+        );
 
-        let (pipeline, pipeline_layout) =
-            Self::create_graphics_pipeline(&device, swap_chain_extent, render_pass);
+        let render_extent = render_scale
+            .as_ref()
+            .map_or(swap_chain_extent, |render_scale| {
+                render_scale.render_extent(swap_chain_extent)
+            });
 
-        let swap_chain_frame_buffers = Self::create_frame_buffers(
+        //デプスバッファはシーンを描画する解像度に合わせる
+        let depth_buffer = DepthBuffer::new(
+            &instance,
+            physical_device,
             &device,
-            render_pass,
-            //Cloneして大丈夫？
-            swap_chain_image_views.clone(),
-            swap_chain_extent,
+            DepthBuffer::find_format(&instance, physical_device),
+            render_extent,
+            msaa_samples,
         );
 
-        let command_pool =
-            Self::create_command_pool(&instance, &surface, surface_khr, physical_device, &device);
+        let msaa_target = (msaa_samples != vk::SampleCountFlags::TYPE_1).then(|| {
+            MsaaTarget::new(
+                &instance,
+                physical_device,
+                &device,
+                swap_chain_image_format,
+                swap_chain_extent,
+                msaa_samples,
+            )
+        });
 
-        let command_buffers =
-            Self::create_command_buffers(&device, command_pool, MAX_FRAMES_IN_FLIGHT);
+        log::info!("Depth convention: {}", config.depth.name());
 
-        let (image_available_semaphores, render_finished_semaphores, in_flight_fences) =
-            Self::create_sync_objects(&device, MAX_FRAMES_IN_FLIGHT);
+        //reverse-Zは浮動小数点の深度値で精度が上がるので、D24では遠くの精度はあまり変わらない
+        if config.depth.is_reversed() && depth_buffer.format == vk::Format::D24_UNORM_S8_UINT {
+            log::warn!(
+                "Reverse-Z depth with {:?} gains little precision",
+                depth_buffer.format
+            );
+        }
 
-        Ok(Self {
-            entry,
-            instance,
-            debug_utils,
-            debug_utils_messenger_ext,
-            physical_device,
-            device,
-            graphics_queue,
-            present_queue,
-            surface,
-            surface_khr,
-            swap_chain,
-            swap_chain_khr,
-            swap_chain_images,
-            swap_chain_image_format,
-            swap_chain_extent,
-            swap_chain_image_views,
-            render_pass,
-            pipeline_layout,
-            pipeline,
-            swap_chain_frame_buffers,
-            command_pool,
-            command_buffers,
-            current_frame: 0,
-            resize: None,
-            image_available_semaphores,
-            render_finished_semaphores,
-            in_flight_fences,
-        })
-    }
+        if let Some(render_scale) = &mut render_scale {
+            render_scale.create_target(
+                &instance,
+                physical_device,
+                &device,
+                swap_chain_image_format,
+                swap_chain_extent,
+                &depth_buffer,
+            );
+        }
 
-    fn draw_frame(&mut self, frame_size: usize) {
-        //フレームに対して書き込むために使用するCommandBufferやSemaphoreやFenceを取得する
-        let command_buffer = *self.command_buffers.get(self.current_frame).unwrap();
-        let image_available_semaphore = *self
-            .image_available_semaphores
-            .get(self.current_frame)
-            .unwrap();
-        let render_finished_semaphore = *self
-            .render_finished_semaphores
-            .get(self.current_frame)
-            .unwrap();
-        let in_flight_fence = *self.in_flight_fences.get(self.current_frame).unwrap();
+        let (render_pass, render_target) = match dynamic_rendering {
+            Some(_) => (
+                vk::RenderPass::null(),
+                RenderTarget::Dynamic {
+                    color_format: swap_chain_image_format,
+                    depth_format: depth_buffer.format,
+                    depth: config.depth,
+                    samples: msaa_samples,
+                },
+            ),
+            None => {
+                let render_pass = Self::create_render_pass(
+                    &device,
+                    swap_chain_image_format,
+                    depth_buffer.format,
+                    msaa_samples,
+                );
+                (
+                    render_pass,
+                    RenderTarget::RenderPass(render_pass, config.depth, msaa_samples),
+                )
+            }
+        };
 
-        unsafe {
-            //Fenceの待機
-            //第二引数は配列で受け取った全てのFenceを待つかどうか
-            self.device
-                .wait_for_fences(&[in_flight_fence], true, u64::MAX)
-                .unwrap();
+        //--max-lights 0の場合は環境光だけになる
+        let mut light_manager = LightManager::new(demo.max_lights);
+        light_manager.add(Light::sun());
 
-            //swapchainからImageを取得する
-            //.0はswap_chain_imagesの配列のIndexが帰ってくる
-            //.1はVK_SUBOPTIMAL_KHRかどうかが帰ってくる
-            //https://www.khronos.org/registry/vulkan/specs/1.3-extensions/man/html/VkResult.html
-            let result = self.swap_chain.acquire_next_image(
-                self.swap_chain_khr,
-                //画像が利用可能になるまでの待機時間のタイムアウトをナノ秒で指定
-                //MAXを入れるとタイムアウトを無効にできる
-                u64::MAX,
-                //このセマフォはシグナルが送られる
-                image_available_semaphore,
-                vk::Fence::null(),
-            );
+        if demo.light_demo {
+            light_manager.add_demo_lights();
+        }
 
-            let image_index = match result {
-                Ok((image_index, _)) => image_index,
-                //ERROR_OUT_OF_DATE_KHR
-                //swapchainとsurfaceの互換がなくなった時に呼ばれる、ウィンドウのリサイズ時など
-                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                    self.recreate_swap_chain();
-                    return;
-                }
-                Err(error) => {
-                    panic!("{}", error);
-                }
-            };
+        //メッシュシェーダーはset = 0のview行列とproj行列と、set = 1のモデル行列を読む
+        let mesh_shader_stages = if mesh_shading {
+            vk::ShaderStageFlags::MESH_EXT
+        } else {
+            vk::ShaderStageFlags::empty()
+        };
 
-            //リセットをこの位置に置くことでrecreate_swap_chainのタイミングでreturnすることによるデッドロックを回避することが出来る
-            //リセットしてるのにsignalを送る人がいないという状況を回避する
-            self.device.reset_fences(&[in_flight_fence]).unwrap();
+        let mut uniform_buffers = UniformBuffers::new(
+            &instance,
+            physical_device,
+            &device,
+            config.frames_in_flight,
+            light_manager.max_lights(),
+            mesh_shader_stages,
+        );
 
-            //コマンドバッファをリセットする
-            self.device
-                .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
-                .unwrap();
+        let instanced_grid_size = demo.instanced_grid;
 
-            //コマンドバッファを記録する
-            self.record_command_buffer(image_index as usize);
-
-            //キューをGPUにSubmitする
-            let submit_info = vk::SubmitInfo::builder()
-                //どのセマフォを使用して待機するか
-                .wait_semaphores(&[image_available_semaphore])
-                //どのステージで待機するかを指定
-                //今回は画像が利用可能になるまで待ちたいのでCOLOR_ATTACHMENT_OUTPUTを使用
-                //この配列はインデックスで上記のsemaphoreの配列と対応する
-                //ここのセマフォを設定せずに行うと理論的には画像が利用可能でない状態でバーテックスシェーダを使用することなどが可能
-                .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
-                //実行するコマンドバッファを指定
-                .command_buffers(&[command_buffer])
-                //ここで指定したセマフォに対してこのsubmitが終了した時にシグナルを送る
-                .signal_semaphores(&[render_finished_semaphore])
-                .build();
+        let texture_array_layers = match demo.texture_array {
+            Some(_) if instanced_grid_size.is_none() => {
+                log::warn!("--texture-array is ignored without --instanced-grid");
+                None
+            }
+            layers => layers,
+        };
 
-            //graphics_queueをsubmitする
-            //in_flight_fenceに対してシグナルを送るように
-            self.device
-                //queueへのsubmitは非常に処理として重たいので複数のsubmit_infoを一回で渡せるようになっている
-                .queue_submit(self.graphics_queue, &[submit_info], in_flight_fence)
-                .unwrap();
+        //インスタンス描画ではモデル行列を使わないので四角形のグリッドとは同時に使えない
+        let quad_grid = match demo.quad_grid {
+            Some(_) if instanced_grid_size.is_some() => {
+                log::warn!("--quad-grid is ignored when --instanced-grid is specified");
+                None
+            }
+            quad_grid => quad_grid,
+        };
 
-            //Presentation
+        //グラフィックスキューファミリーはTRANSFERとCOMPUTEにも対応している
+        let one_time_commands = OneTimeCommands::new(
+            &device,
+            queue_family_indices.graphics_family.unwrap(),
+            upload_queue.clone(),
+        );
 
-            let present_info = vk::PresentInfoKHR::builder()
-                //待機するセマフォを指定
-                .wait_semaphores(&[render_finished_semaphore])
-                .swapchains(&[self.swap_chain_khr])
-                //swapchainに対するimageを指定
-                .image_indices(&[image_index])
-                //このメソッドはPresentationが成功したかどうかを受け取れる
-                //引数が配列になっているのは各swapchainに対してそれぞれResultが返ってくるため
-                //今回はswapchainが１つしか存在しないのでpresent用の関数の戻り値を参照すれば良い
-                //swapchainが複数存在するとき用？
-                //.results()
-                .build();
+        //vertex pullingやメッシュシェーダー、加速構造、インスタンス描画は起動時のメッシュから作るので、その場合は起動時に読み込む
+        let async_obj_path = demo
+            .obj_path
+            .clone()
+            .filter(|_| demo.async_assets)
+            .filter(|_| {
+                let supported = !(pulls_vertices
+                    || mesh_shading
+                    || ray_tracing
+                    || ray_query
+                    || instanced_grid_size.is_some());
+
+                if !supported {
+                    log::warn!(
+                    "--async-assets is ignored with vertex pulling, mesh shading, ray tracing or --instanced-grid"
+                );
+                }
 
-            let result = self
-                .swap_chain
-                .queue_present(self.present_queue, &present_info);
+                supported
+            });
 
-            match result {
-                Ok(is_suboptimal) if is_suboptimal => {
-                    self.recreate_swap_chain();
-                }
-                Ok(_) => {}
-                //SUBOPTIMAL_KHR
-                //swapchainはsurfaceに正常にpresentすることは出来るが、プロパティは完全に一致していない
-                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                    self.recreate_swap_chain();
+        //読み込めなかった場合は指定しなかった場合と同じメッシュにする
+        let obj_model = match async_obj_path {
+            Some(_) => None,
+            None => demo
+                .obj_path
+                .as_ref()
+                .and_then(|path| match obj_loader::load(path) {
+                    Ok(model) => Some(model),
+                    Err(error) => {
+                        log::warn!("Failed to load {}: {}", path.display(), error);
+                        None
+                    }
+                }),
+        };
+
+        //読み込めなかった場合は--objと同じく指定しなかった場合のメッシュにする
+        let gltf_model = demo
+            .gltf_path
+            .as_ref()
+            .filter(|_| {
+                let ignored = demo.obj_path.is_some();
+
+                if ignored {
+                    log::warn!("--gltf is ignored with --obj");
                 }
+
+                !ignored
+            })
+            .and_then(|path| match gltf_loader::load(path) {
+                Ok(model) => Some(model),
                 Err(error) => {
-                    panic!("{}", error);
+                    log::warn!("Failed to load {}: {}", path.display(), error);
+                    None
                 }
-            }
+            });
 
-            //二回recreate_swap_chainが呼ばれることになりそう
-            if self.resize.is_some() {
-                self.recreate_swap_chain();
-            }
-        }
+        //--async-assetsの場合は読み込み終える前でも読み込むメッシュとして扱う
+        let mesh_path = demo
+            .obj_path
+            .clone()
+            .filter(|_| obj_model.is_some() || async_obj_path.is_some());
 
-        self.current_frame = (self.current_frame + 1) % frame_size;
-    }
+        let asset_uploader = async_obj_path.map(|path| {
+            AssetUploader::new(
+                &instance,
+                physical_device,
+                &device,
+                upload_queue,
+                queue_family_indices.graphics_family.unwrap(),
+                vec![path],
+                demo.upload_budget,
+            )
+        });
 
-    pub fn run(mut self, window_handlers: WindowHandlers) {
-        info!("Running application");
+        //vertex pullingとメッシュシェーダーではstorage bufferとして読み、レイトレーシングでは加速構造のビルドの入力にする
+        let mut mesh_usage = vk::BufferUsageFlags::empty();
 
-        window_handlers
-            .event_loop
-            .run(move |event, _, control_flow| {
-                *control_flow = ControlFlow::Poll;
-                self.resize = None;
+        if pulls_vertices || mesh_shading {
+            mesh_usage |= vk::BufferUsageFlags::STORAGE_BUFFER;
+        }
 
-                if let Event::WindowEvent { event, .. } = event {
-                    match event {
-                        WindowEvent::CloseRequested => {
-                            unsafe { self.device.device_wait_idle().unwrap() };
-                            *control_flow = ControlFlow::Exit;
-                        }
-                        WindowEvent::Resized(physical_size) => {
-                            self.resize = Some((physical_size.width, physical_size.height));
-                        }
-                        WindowEvent::KeyboardInput {
-                            input:
-                                KeyboardInput {
-                                    virtual_keycode: Some(VirtualKeyCode::Space),
-                                    state: ElementState::Released,
-                                    ..
-                                },
-                            ..
-                        } => {
-                            info!("Space!");
-                        }
-                        _ => (),
-                    }
-                }
+        if ray_tracing || ray_query {
+            mesh_usage |= vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR;
+        }
 
-                self.draw_frame(MAX_FRAMES_IN_FLIGHT as usize);
-            });
-    }
+        let model_geometry = match (&obj_model, &gltf_model) {
+            (Some((vertices, indices)), _) => Some((&vertices[..], &indices[..])),
+            (None, Some(model)) => Some((&model.vertices[..], &model.indices[..])),
+            (None, None) => None,
+        };
 
-    pub fn recreate_swap_chain(&mut self) {
-        //最小化対応
-        //最小化時にここで待機させることによって対応させる
-        //今の構成だと出来ない気もする
-        // if let Some(size) = self.resize {
-        //     while size.0 == 0 || size.1 == 0 {
-        //         self.run();
-        //     }
-        // }
+        let mesh = match (model_geometry, quad_grid) {
+            (Some((vertices, indices)), _) => Mesh::with_usage(
+                &instance,
+                physical_device,
+                &device,
+                &one_time_commands,
+                &synchronization,
+                vertices,
+                indices,
+                mesh_usage,
+            ),
+            (None, Some(_)) => Mesh::quad(
+                &instance,
+                physical_device,
+                &device,
+                &one_time_commands,
+                &synchronization,
+                mesh_usage,
+            ),
+            (None, None) => Mesh::triangle(
+                &instance,
+                physical_device,
+                &device,
+                &one_time_commands,
+                &synchronization,
+                mesh_usage,
+            ),
+        };
 
-        //swapchainが使用されている時に触るのは良くないのでdeviceがidle状態になるのを待つ
-        unsafe { self.device.device_wait_idle().unwrap() };
+        let vertex_pulling = pulls_vertices.then(|| VertexPulling::new(&device, &mesh));
+        let mesh_shading = mesh_shading.then(|| MeshShading::new(&instance, &device, &mesh));
 
-        self.cleanup_swap_chain();
+        let object_count = quad_grid.map_or(1, |size| (size * size) as usize);
 
-        let (width, height) = self
-            .resize
-            .unwrap_or((self.swap_chain_extent.width, self.swap_chain_extent.height));
+        let instanced_grid = instanced_grid_size.map(|size| {
+            InstancedGrid::new(
+                &instance,
+                physical_device,
+                &device,
+                size,
+                demo.grid_draw_mode,
+                mesh.index_count(),
+                enabled_features.multi_draw_indirect,
+                texture_array_layers.unwrap_or(1),
+            )
+        });
 
-        info!("width: {}, height: {}", width, height);
+        //半透明な四角形は不透明なオブジェクトの後ろに置く
+        let transparent_quads = demo.transparent_quads.then(|| {
+            TransparentQuads::new(
+                &instance,
+                physical_device,
+                &device,
+                &one_time_commands,
+                &synchronization,
+                object_count,
+            )
+        });
 
-        let (swap_chain, swap_chain_khr, swap_chain_image_format, swap_chain_extent) =
-            Self::create_swap_chain(
-                &self.instance,
-                &self.device,
-                self.physical_device,
-                &self.surface,
-                self.surface_khr,
-                (width, height),
-            );
+        //main_vs_instancedとmain_vs_ubo_stressにはシャドウマップを引く版が無い
+        let shadow_map_size = match demo.shadow_map_size {
+            Some(_) if instanced_grid.is_some() || demo.ubo_stress => {
+                log::warn!("--shadows is ignored with --instanced-grid or --ubo-stress");
+                None
+            }
+            size => size,
+        };
 
-        self.swap_chain = swap_chain;
-        self.swap_chain_khr = swap_chain_khr;
-        self.swap_chain_image_format = swap_chain_image_format;
-        self.swap_chain_extent = swap_chain_extent;
+        //床は半透明な四角形の後ろに置く
+        let ground_object = object_count
+            + transparent_quads
+                .as_ref()
+                .map_or(0, |_| TransparentQuads::COUNT);
 
-        self.swap_chain_images = Self::get_swap_chain_images(&self.swap_chain, self.swap_chain_khr);
+        let ground = shadow_map_size.map(|_| {
+            Ground::new(
+                &instance,
+                physical_device,
+                &device,
+                &one_time_commands,
+                &synchronization,
+                ground_object,
+            )
+        });
 
-        //image_viewはswapchainに紐づいているので再作成しなければいけない
-        self.swap_chain_image_views = Self::create_image_views(
-            &self.device,
-            &self.swap_chain_images,
-            self.swap_chain_image_format,
+        let object_buffers = ObjectBuffers::new(
+            &instance,
+            physical_device,
+            &device,
+            config.frames_in_flight,
+            ground_object + ground.as_ref().map_or(0, |_| 1),
+            push_descriptors.then(|| PushDescriptor::new(&instance, &device)),
+            mesh_shader_stages,
         );
 
-        //swapchain imageのformatに依存するため再作成
-        self.render_pass = Self::create_render_pass(&self.device, self.swap_chain_image_format);
+        let pipeline_cache = PipelineCache::new(&instance, physical_device, &device);
+
+        //ビルドやパイプラインの作成に失敗した場合もラスタライズで描画を続ける
+        let ray_tracer = ray_tracing
+            .then(|| {
+                let shader_module =
+                    Self::create_shader_module(&device, RAY_TRACING_SHADER_CODE);
+
+                let ray_tracer = RayTracer::new(
+                    &instance,
+                    physical_device,
+                    &device,
+                    &one_time_commands,
+                    &synchronization,
+                    pipeline_cache.handle(),
+                    shader_module,
+                    &mesh,
+                );
 
-        //viewportとscissor rectがpipelineの作成時に指定されるので再作成
-        //ただし再作成をしなくてもdynamic stateを使用すれば良い
-        let (pipeline, pipeline_layout) =
-            Self::create_graphics_pipeline(&self.device, self.swap_chain_extent, self.render_pass);
+                unsafe { device.destroy_shader_module(shader_module, None) };
 
-        self.pipeline = pipeline;
-        self.pipeline_layout = pipeline_layout;
+                ray_tracer
+                    .map_err(|error| {
+                        log::warn!(
+                            "Failed to create the ray tracing pipeline, falling back to rasterization: {}",
+                            error
+                        );
+                    })
+                    .ok()
+            })
+            .flatten()
+            .map(|mut ray_tracer| {
+                ray_tracer.create_storage_image(
+                    &instance,
+                    physical_device,
+                    &device,
+                    swap_chain_extent,
+                );
+                ray_tracer
+            });
 
-        //swapchainに依存するので再作成
-        self.swap_chain_frame_buffers = Self::create_frame_buffers(
-            &self.device,
-            self.render_pass,
-            self.swap_chain_image_views.clone(),
-            self.swap_chain_extent,
+        let mut sampler_cache = SamplerCache::new(&instance, physical_device, &enabled_features);
+        let mut descriptor_layout_cache = DescriptorLayoutCache::new();
+
+        let skybox = skybox_faces(demo).map(|faces| {
+            //ミップマップを作らないのでmax_lodは0.0、面の境目が見えないようにCLAMP_TO_EDGEにする
+            let sampler = sampler_cache.get(
+                &device,
+                &SamplerDesc {
+                    address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    max_lod: 0.0,
+                    ..SamplerDesc::default()
+                },
+            );
+
+            Skybox::new(
+                &instance,
+                physical_device,
+                &device,
+                &one_time_commands,
+                &synchronization,
+                &faces,
+                sampler,
+            )
+        });
+
+        //レイトレーシングではパスを使わずにswapchainへblitするので、重ねて描画する場所が無い
+        let staging_ring = StagingRing::new(
+            &instance,
+            physical_device,
+            &device,
+            STAGING_RING_CAPACITY,
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER,
+            config.frames_in_flight,
         );
-    }
 
-    //swapchainをcleanupする
-    fn cleanup_swap_chain(&mut self) {
-        unsafe {
-            for framebuffer in self.swap_chain_frame_buffers.clone() {
-                self.device.destroy_framebuffer(framebuffer, None);
+        let mut debug_text = match (demo.debug_text, &ray_tracer) {
+            (true, Some(_)) => {
+                log::warn!("--debug-text is ignored with --raytrace");
+                None
             }
+            (true, None) => {
+                //アトラスのピクセルをぼかさずに拡大する
+                let sampler = sampler_cache.get(
+                    &device,
+                    &SamplerDesc {
+                        mag_filter: vk::Filter::NEAREST,
+                        min_filter: vk::Filter::NEAREST,
+                        mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+                        address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                        address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                        address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                        max_anisotropy: 1.0,
+                        max_lod: 0.0,
+                        ..SamplerDesc::default()
+                    },
+                );
 
-            self.device.destroy_pipeline(self.pipeline, None);
-            self.device
-                .destroy_pipeline_layout(self.pipeline_layout, None);
-            self.device.destroy_render_pass(self.render_pass, None);
-
-            for image_view in self.swap_chain_image_views.clone() {
-                self.device.destroy_image_view(image_view, None);
+                Some(DebugText::new(
+                    &instance,
+                    physical_device,
+                    &device,
+                    &one_time_commands,
+                    &synchronization,
+                    sampler,
+                    config.frames_in_flight,
+                ))
             }
+            (false, _) => None,
+        };
 
-            self.swap_chain.destroy_swapchain(self.swap_chain_khr, None);
+        if let Some(debug_text) = &mut debug_text {
+            debug_text.set_scale_factor(scale_factor);
         }
-    }
-
-    fn create_instance(entry: &Entry) -> Result<Instance, Box<dyn Error>> {
-        let app_info = vk::ApplicationInfo::builder()
-            .application_name(CString::new("vulkan app")?.as_c_str())
-            .application_version(0)
-            .engine_name(CString::new("No Engine")?.as_c_str()) //エンジン名を入力するとそれが既知なエンジンだったらそれように最適化をする
-            .engine_version(0)
-            .api_version(vk::make_api_version(0, 1, 3, 0)) //Vulkan自体のバージョン
-            .build();
 
-        let mut extension_names = khr_util::require_extension_names(); //本家チュートリアルではgetRequiredExtensions(glfwGetRequiredInstanceExtensions)
+        //--raytraceではパスを使わずにswapchainへblitし、--record-threadsではパスの中をセカンダリコマンドバッファで描画するので重ねられない
+        let egui_overlay = match (source, &ray_tracer) {
+            (SurfaceSource::Window(_), None) if demo.record_threads.is_none() => {
+                //フォントのアトラスは論理ピクセルの大きさで作られるので、拡大率に合わせて滑らかに拡大する
+                let sampler = sampler_cache.get(
+                    &device,
+                    &SamplerDesc {
+                        mag_filter: vk::Filter::LINEAR,
+                        min_filter: vk::Filter::LINEAR,
+                        mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+                        address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                        address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                        address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                        max_anisotropy: 1.0,
+                        max_lod: 0.0,
+                        ..SamplerDesc::default()
+                    },
+                );
+                let properties =
+                    unsafe { instance.get_physical_device_properties(physical_device) };
+
+                Some(EguiOverlay::new(
+                    &device,
+                    sampler,
+                    config.frames_in_flight,
+                    scale_factor,
+                    &properties,
+                    device_info::device_name(&properties),
+                    msaa::supported_sample_counts(&instance, physical_device),
+                ))
+            }
+            _ => None,
+        };
 
-        //検証レイヤーでのデバック時にコールバックを設定できるように拡張機能を有効にする
-        if ENABLE_VALIDATION_LAYERS {
-            //DebugUtils::name()がVK_EXT_DEBUG_UTILS_EXTENSION_NAME
-            extension_names.push(DebugUtils::name().as_ptr());
-        }
+        //デモのスプライトは画面の端で跳ね返るので、LINEARでもミップマップは要らない
+        //背景のチェッカー模様はuvを1.0より大きくして繰り返すのでREPEATのままにする
+        let (sprite_batch, sprite_demo) = match demo.sprite_count {
+            Some(count) => {
+                let sampler = sampler_cache.get(
+                    &device,
+                    &SamplerDesc {
+                        max_lod: 0.0,
+                        ..SamplerDesc::default()
+                    },
+                );
 
-        let layer_names = REQUIRED_LAYERS
-            .iter()
-            .map(|name| CString::new(*name).expect("Failed to build CString"))
-            .collect::<Vec<_>>();
-        let layer_names_ptrs = layer_names
-            .iter()
-            .map(|name| name.as_ptr())
-            .collect::<Vec<_>>();
+                let mut sprite_batch = SpriteBatch::new(
+                    &instance,
+                    physical_device,
+                    &device,
+                    &mut descriptor_layout_cache,
+                    sampler,
+                    config.frames_in_flight,
+                );
 
-        let mut instance_create_info = vk::InstanceCreateInfo::builder()
-            .application_info(&app_info)
-            .enabled_extension_names(&extension_names);
+                let sprite_demo = SpriteDemo::new(
+                    &instance,
+                    physical_device,
+                    &device,
+                    &one_time_commands,
+                    &synchronization,
+                    &mut sprite_batch,
+                    count,
+                );
 
-        if ENABLE_VALIDATION_LAYERS {
-            debug::check_validation_layer_support(entry);
+                (Some(sprite_batch), Some(sprite_demo))
+            }
+            None => (None, None),
+        };
 
-            let debug_create_info = debug::populate_debug_messenger_create_info();
+        //--spritesのデモは画面の縦がsprite_demo::VIEW_HEIGHTになる正射影で映す
+        let orthographic_height = if sprite_demo.is_some() {
+            sprite_demo::VIEW_HEIGHT
+        } else {
+            ORTHOGRAPHIC_HEIGHT
+        };
 
-            //enabled_layer_countのセットはenabled_layer_namesの中に入っている
-            instance_create_info = instance_create_info.enabled_layer_names(&layer_names_ptrs);
-            //勉強のために型の変換の遷移を書いているが as *const _ as _;でも可
-            instance_create_info.p_next =
-                &debug_create_info as *const DebugUtilsMessengerCreateInfoEXT as *const c_void;
+        let mut camera = Camera::new(Vec3::new(0.0, 0.0, 2.0));
+        camera.depth = config.depth;
+        if sprite_demo.is_some() || demo.orthographic {
+            camera.projection = Projection::Orthographic {
+                height: orthographic_height,
+            };
         }
 
-        unsafe { Ok(entry.create_instance(&instance_create_info, None)?) } //基本的に本家で返り値がVkResultなものはResult型で値が包まれて返ってくるので引数も減る
-    }
+        let shadow_map = shadow_map_size.map(|size| {
+            //深度値の比較をサンプラーで行い、LINEARで周囲のテクセルの比較結果も補間させる
+            let sampler = sampler_cache.get(
+                &device,
+                &SamplerDesc {
+                    mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+                    address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    max_anisotropy: 1.0,
+                    compare_op: Some(vk::CompareOp::LESS_OR_EQUAL),
+                    max_lod: 0.0,
+                    ..SamplerDesc::default()
+                },
+            );
 
-    fn pick_physical_device(
-        instance: &Instance,
-        surface: &Surface,
-        surface_khr: SurfaceKHR,
-    ) -> PhysicalDevice {
-        let physical_devices = unsafe {
-            instance
-                .enumerate_physical_devices()
-                .expect("物理デバイスが取得できませんでした")
-        };
+            ShadowMap::new(
+                &instance,
+                physical_device,
+                &device,
+                size,
+                sampler,
+                dynamic_rendering.is_none(),
+            )
+        });
 
-        let physical_device = physical_devices
-            .into_iter()
-            .find(|physical_device| {
-                QueueFamilyIndices::is_device_suitable(
-                    instance,
-                    surface,
-                    surface_khr,
-                    *physical_device,
+        let mut texture_manager = TextureManager::new(
+            &instance,
+            physical_device,
+            &device,
+            &one_time_commands,
+            &synchronization,
+        );
+
+        //シャドウマップと同じset = 2を使い、main_vs_instancedとmain_vs_ubo_stressにはテクスチャ座標が無い
+        let (material_textures, procedural_texture) = if !demo.textured {
+            if demo.procedural_texture {
+                log::warn!("--procedural-texture is ignored without --textured");
+            }
+            (None, None)
+        } else if shadow_map.is_some() || instanced_grid.is_some() || demo.ubo_stress {
+            log::warn!("--textured is ignored with --shadows, --instanced-grid or --ubo-stress");
+            (None, None)
+        } else {
+            //使えない場合はマテリアルごとのDescriptor Setにフォールバックする
+            let binding = match descriptor_indexing_support {
+                DescriptorIndexingSupport::Unsupported => TextureBinding::PerMaterial,
+                _ => TextureBinding::Bindless,
+            };
+
+            info!(
+                "descriptor indexing: {:?}{}",
+                descriptor_indexing_support,
+                if demo.no_bindless {
+                    " (--no-bindless)"
+                } else {
+                    ""
+                }
+            );
+
+            let sampler = sampler_cache.default_sampler(&device);
+
+            //作れない場合は最初のマテリアルもチェッカー模様にする
+            let procedural_texture = if demo.procedural_texture {
+                let shader_module = Self::create_shader_module(&device, SHADER_CODE);
+
+                let procedural_texture = ProceduralTexture::new(
+                    &instance,
+                    physical_device,
+                    &device,
+                    &one_time_commands,
+                    &synchronization,
+                    &mut descriptor_layout_cache,
+                    pipeline_cache.handle(),
+                    shader_module,
                 )
+                .map_err(|error| log::warn!("{}, --procedural-texture is disabled", error))
+                .ok();
+
+                unsafe { device.destroy_shader_module(shader_module, None) };
+
+                procedural_texture
+            } else {
+                None
+            };
+
+            //テクスチャを作り終えたら使わないので破棄する
+            let shader_module = Self::create_shader_module(&device, SHADER_CODE);
+
+            let mip_generator = MipGenerator::new(
+                &device,
+                api_version,
+                &mut descriptor_layout_cache,
+                pipeline_cache.handle(),
+                shader_module,
+            );
+
+            unsafe { device.destroy_shader_module(shader_module, None) };
+
+            let material_textures = MaterialTextures::new(
+                &instance,
+                physical_device,
+                &device,
+                &one_time_commands,
+                &synchronization,
+                &mut descriptor_layout_cache,
+                &mut texture_manager,
+                sampler,
+                binding,
+                demo.ktx2_texture.as_deref(),
+                procedural_texture.as_ref().map(ProceduralTexture::view),
+                &enabled_features,
+                TextureOptions {
+                    mipmap_mode: demo.mipmaps,
+                    mip_generator: Some(&mip_generator),
+                },
+            );
+
+            mip_generator.destroy(&device);
+
+            (Some(material_textures), procedural_texture)
+        };
+
+        //作れない場合はテクスチャを貼らずにインスタンスの色で描画する
+        let texture_array = texture_array_layers.and_then(|layers| {
+            let sampler = sampler_cache.default_sampler(&device);
+
+            TextureArray::new(
+                &instance,
+                physical_device,
+                &device,
+                &one_time_commands,
+                &synchronization,
+                &mut descriptor_layout_cache,
+                sampler,
+                layers,
+            )
+            .map_err(|error| log::warn!("{}, --texture-array is disabled", error))
+            .ok()
+        });
+
+        let ubo_stress = if !demo.ubo_stress {
+            false
+        } else if instanced_grid.is_some() {
+            log::warn!("UBO stress mode is not supported with --instanced-grid");
+            false
+        } else if enabled_features.vertex_pipeline_stores_and_atomics {
+            info!("UBO stress mode enabled");
+            true
+        } else {
+            log::warn!(
+                "vertex_pipeline_stores_and_atomics is not supported, UBO stress mode is disabled"
+            );
+            false
+        };
+
+        let vertex_stage = if instanced_grid.is_some() && texture_array.is_some() {
+            VertexStage::InstancedTextureArray
+        } else if instanced_grid.is_some() {
+            VertexStage::Instanced
+        } else if ubo_stress {
+            VertexStage::UboStress
+        } else if shadow_map.is_some() {
+            VertexStage::Shadowed
+        } else if let Some(material_textures) = &material_textures {
+            VertexStage::Textured(material_textures.binding())
+        } else if mesh_shading.is_some() {
+            VertexStage::MeshShaded
+        } else {
+            VertexStage::Mesh
+        };
+
+        //main_vs_skinnedはジョイントの行列をset = 2で読むので、set = 2を他に使わない場合だけ作る
+        //作れない場合は--gltfのメッシュを動かさずに初期の姿勢で描画する
+        let (skinning, animator) = match gltf_model {
+            None => (None, None),
+            Some(_) if vertex_stage != VertexStage::Mesh || vertex_pulling.is_some() => {
+                log::warn!(
+                    "--gltf skinning is ignored with shadows, textures, vertex pulling, mesh shading or --instanced-grid"
+                );
+                (None, None)
+            }
+            Some(model) => match Skinning::new(
+                &instance,
+                physical_device,
+                &device,
+                &mut descriptor_layout_cache,
+                config.frames_in_flight,
+                &model.skin_vertices,
+                model.skeleton.joints.len(),
+            ) {
+                Ok(skinning) => (
+                    Some(skinning),
+                    Some(Animator::new(model.skeleton, model.animations)),
+                ),
+                Err(error) => {
+                    log::warn!("{}, --gltf skinning is disabled", error);
+                    (None, None)
+                }
+            },
+        };
+
+        //main_vsの代わりに使うので、set = 2を他に使わない場合だけ作る
+        let normal_map = if !demo.normal_mapping {
+            None
+        } else if vertex_stage != VertexStage::Mesh
+            || vertex_pulling.is_some()
+            || skinning.is_some()
+        {
+            log::warn!(
+                "--normal-mapping is ignored with shadows, textures, vertex pulling, mesh shading, --instanced-grid or --gltf"
+            );
+            None
+        } else {
+            let sampler = sampler_cache.default_sampler(&device);
+
+            Some(NormalMap::new(
+                &instance,
+                physical_device,
+                &device,
+                &one_time_commands,
+                &synchronization,
+                &mut descriptor_layout_cache,
+                sampler,
+            ))
+        };
+
+        let vertex_stage = if skinning.is_some() {
+            VertexStage::Skinned
+        } else if normal_map.is_some() {
+            VertexStage::NormalMapped
+        } else {
+            vertex_stage
+        };
+
+        //オブジェクトは全て影を落とすので、TLASにはself.meshのオブジェクトを全て置く
+        //ビルドに失敗した場合はシャドウマップで影を付ける
+        let ray_query_shadows = if ray_query && vertex_stage == VertexStage::Shadowed {
+            RayQueryShadows::new(
+                &instance,
+                physical_device,
+                &device,
+                &one_time_commands,
+                &synchronization,
+                &mesh,
+                config.frames_in_flight,
+                object_count as u32,
+            )
+            .map_err(|error| {
+                log::warn!(
+                    "Failed to create acceleration structures, using the shadow map: {}",
+                    error
+                );
             })
-            .expect("最適なPhysical Deviceが存在しません");
+            .ok()
+        } else {
+            None
+        };
 
-        let props = unsafe { instance.get_physical_device_properties(physical_device) };
+        let mut post_effects = demo.post_effects.clone();
+
+        if swap_chain_color_format.requires_post_process() && post_effects.is_empty() {
+            log::info!("Adding a passthrough post process pass to encode the PQ swapchain");
+            post_effects.push(PostEffect::Passthrough);
+        }
+
+        let scene_color_format =
+            Self::scene_color_format(swap_chain_color_format, !post_effects.is_empty());
+
+        let post_process = (!post_effects.is_empty()).then(|| {
+            //同じ大きさの画像をサンプリングするのでフィルタリングもミップマップも要らない
+            let sampler = sampler_cache.get(
+                &device,
+                &SamplerDesc {
+                    mag_filter: vk::Filter::NEAREST,
+                    min_filter: vk::Filter::NEAREST,
+                    mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+                    address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    max_anisotropy: 1.0,
+                    max_lod: 0.0,
+                    ..SamplerDesc::default()
+                },
+            );
+
+            let mut post_process = PostProcess::new(
+                &device,
+                post_effects,
+                sampler,
+                dynamic_rendering.is_none(),
+                depth_buffer.format,
+            );
+
+            post_process.create_targets(
+                &instance,
+                physical_device,
+                &device,
+                render_extent,
+                &depth_buffer,
+            );
+
+            post_process
+        });
+
+        let scene_render_target = Self::scene_render_target(render_target, post_process.as_ref());
+
+        //数えるパイプラインはmain_vsの頂点入力で描画するので、set = 2を他に使わない場合だけ作る
+        let overdraw_counters = if vertex_stage != VertexStage::Mesh || vertex_pulling.is_some() {
+            None
+        } else if enabled_features.fragment_stores_and_atomics {
+            let mut overdraw_counters = OverdrawCounters::new(&device, config.frames_in_flight);
+            overdraw_counters.create_buffers(&instance, physical_device, &device, render_extent);
+            Some(overdraw_counters)
+        } else {
+            log::info!(
+                "fragment_stores_and_atomics is not supported, the overdraw debug view is unavailable"
+            );
+            None
+        };
+
+        let scene_descriptor_set_layouts = Self::scene_descriptor_set_layouts(
+            &uniform_buffers,
+            &object_buffers,
+            shadow_map.as_ref(),
+            ray_query_shadows.as_ref(),
+            material_textures.as_ref(),
+            vertex_pulling.as_ref(),
+            mesh_shading.as_ref(),
+            texture_array.as_ref(),
+            normal_map.as_ref(),
+            skinning.as_ref(),
+            overdraw_counters.as_ref(),
+        );
+
+        let (pipeline, wireframe_pipeline, pipeline_layout) = Self::create_graphics_pipeline(
+            &device,
+            pipeline_cache.handle(),
+            scene_render_target,
+            &scene_descriptor_set_layouts,
+            enabled_features.fill_mode_non_solid,
+            vertex_stage,
+            scene_color_format.shader_output(),
+        );
+
+        let debug_view_pipelines = Self::create_debug_view_pipelines(
+            &device,
+            pipeline_cache.handle(),
+            scene_render_target,
+            &scene_descriptor_set_layouts,
+            vertex_stage,
+            scene_color_format.shader_output(),
+        );
+
+        let overdraw_pipelines = overdraw_counters.as_ref().map(|overdraw_counters| {
+            Self::create_overdraw_pipelines(
+                &device,
+                pipeline_cache.handle(),
+                scene_render_target,
+                &scene_descriptor_set_layouts,
+                overdraw_counters,
+                scene_color_format.shader_output(),
+            )
+        });
+
+        let pulling_pipeline = vertex_pulling.as_ref().map(|_| {
+            Self::create_pulling_pipeline(
+                &device,
+                pipeline_cache.handle(),
+                scene_render_target,
+                &scene_descriptor_set_layouts,
+                scene_color_format.shader_output(),
+            )
+        });
+
+        let ray_query_pipeline = ray_query_shadows.as_ref().map(|_| {
+            Self::create_ray_query_pipeline(
+                &device,
+                pipeline_cache.handle(),
+                scene_render_target,
+                &scene_descriptor_set_layouts,
+                scene_color_format.shader_output(),
+            )
+        });
+
+        let shadow_pipeline = shadow_map.as_ref().map(|shadow_map| {
+            Self::create_shadow_pipeline(
+                &device,
+                pipeline_cache.handle(),
+                shadow_map,
+                [
+                    uniform_buffers.descriptor_set_layout(),
+                    object_buffers.descriptor_set_layout(),
+                ],
+            )
+        });
+
+        //専用のコンピュートキューファミリーが無い場合はグラフィックスキューでディスパッチする
+        let async_compute_family = if queue_family_indices.has_dedicated_compute_family() {
+            queue_family_indices.compute_family
+        } else {
+            None
+        };
+
+        let particles = demo.particle_count.map(|count| {
+            let shader_module = Self::create_shader_module(&device, SHADER_CODE);
+
+            //コンピュートキューとグラフィックスキューの両方から使う場合はCONCURRENTで作成する
+            let queue_families = match async_compute_family {
+                Some(compute_family) => {
+                    vec![
+                        queue_family_indices.graphics_family.unwrap(),
+                        compute_family,
+                    ]
+                }
+                None => vec![],
+            };
+
+            let particles = ParticleSystem::new(
+                &instance,
+                physical_device,
+                &device,
+                pipeline_cache.handle(),
+                shader_module,
+                count,
+                config.frames_in_flight,
+                &queue_families,
+            );
+
+            unsafe { device.destroy_shader_module(shader_module, None) };
+
+            particles
+        });
+
+        let compute_queue = match async_compute_family {
+            Some(compute_family) if particles.is_some() => Some(ComputeQueue::new(
+                &instance,
+                physical_device,
+                &device,
+                compute_queue,
+                compute_family,
+                config.frames_in_flight,
+            )),
+            _ => None,
+        };
+
+        let particle_pipeline = particles.as_ref().map(|_| {
+            Self::create_particle_pipeline(
+                &device,
+                pipeline_cache.handle(),
+                scene_render_target,
+                uniform_buffers.descriptor_set_layout(),
+                scene_color_format.shader_output(),
+            )
+        });
+
+        let tessellated_plane = if !demo.tessellation {
+            None
+        } else if enabled_features.tessellation_shader {
+            Some(TessellatedPlane::new(
+                &instance,
+                physical_device,
+                &device,
+                &one_time_commands,
+                &synchronization,
+            ))
+        } else {
+            log::warn!("tessellation_shader is not supported, the tessellated plane is disabled");
+            None
+        };
+
+        let tessellation_pipeline = tessellated_plane.as_ref().map(|_| {
+            Self::create_graphics_pipeline(
+                &device,
+                pipeline_cache.handle(),
+                scene_render_target,
+                &[uniform_buffers.descriptor_set_layout()],
+                enabled_features.fill_mode_non_solid,
+                VertexStage::Tessellated,
+                scene_color_format.shader_output(),
+            )
+        });
+
+        //geometry_shaderを有効にできなかった場合(MoltenVKなど)は法線を表示できない
+        let normals_pipeline = if enabled_features.geometry_shader {
+            Some(Self::create_normals_pipeline(
+                &device,
+                pipeline_cache.handle(),
+                scene_render_target,
+                [
+                    uniform_buffers.descriptor_set_layout(),
+                    object_buffers.descriptor_set_layout(),
+                ],
+                scene_color_format.shader_output(),
+            ))
+        } else {
+            log::warn!("geometry_shader is not supported, normal visualization is disabled");
+            None
+        };
+
+        let post_process_pipelines = Self::create_post_process_pipelines(
+            &device,
+            pipeline_cache.handle(),
+            render_target,
+            scene_render_target,
+            post_process.as_ref(),
+            swap_chain_color_format,
+        );
+
+        let bloom = post_process
+            .as_ref()
+            .filter(|post_process| post_process.effects().contains(&PostEffect::Bloom))
+            .map(|post_process| {
+                //縮めた画像を広げる時に間を補間する
+                let sampler = sampler_cache.get(
+                    &device,
+                    &SamplerDesc {
+                        mag_filter: vk::Filter::LINEAR,
+                        min_filter: vk::Filter::LINEAR,
+                        mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+                        address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                        address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                        address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                        max_anisotropy: 1.0,
+                        max_lod: 0.0,
+                        ..SamplerDesc::default()
+                    },
+                );
+
+                let mut bloom = Bloom::new(
+                    &device,
+                    post_process.descriptor_set_layout(),
+                    sampler,
+                    dynamic_rendering.is_none(),
+                );
+
+                bloom.create_targets(&instance, physical_device, &device, render_extent);
+
+                bloom
+            });
+
+        let bloom_pipelines = Self::create_bloom_pipelines(
+            &device,
+            pipeline_cache.handle(),
+            bloom.as_ref(),
+            post_process.as_ref(),
+        );
+
+        let skybox_pipeline = skybox.as_ref().map(|skybox| {
+            Self::create_skybox_pipeline(
+                &device,
+                pipeline_cache.handle(),
+                scene_render_target,
+                uniform_buffers.descriptor_set_layout(),
+                skybox.descriptor_set_layout(),
+                scene_color_format.shader_output(),
+            )
+        });
+
+        let debug_text_pipeline = debug_text.as_ref().map(|debug_text| {
+            Self::create_debug_text_pipeline(
+                &device,
+                pipeline_cache.handle(),
+                render_target,
+                debug_text.descriptor_set_layout(),
+                swap_chain_color_format.shader_output(),
+            )
+        });
+
+        let egui_pipeline = egui_overlay.as_ref().map(|egui_overlay| {
+            Self::create_egui_pipeline(
+                &device,
+                pipeline_cache.handle(),
+                render_target,
+                egui_overlay.descriptor_set_layout(),
+                swap_chain_color_format.shader_output(),
+            )
+        });
+
+        let sprite_pipeline = sprite_batch.as_ref().map(|sprite_batch| {
+            Self::create_sprite_pipeline(
+                &device,
+                pipeline_cache.handle(),
+                scene_render_target,
+                uniform_buffers.descriptor_set_layout(),
+                sprite_batch.descriptor_set_layout(),
+                scene_color_format.shader_output(),
+            )
+        });
+
+        let transparent_pipeline = transparent_quads.as_ref().map(|_| {
+            Self::create_transparent_pipeline(
+                &device,
+                pipeline_cache.handle(),
+                scene_render_target,
+                [
+                    uniform_buffers.descriptor_set_layout(),
+                    object_buffers.descriptor_set_layout(),
+                ],
+                scene_color_format.shader_output(),
+            )
+        });
+
+        //dynamic renderingではimage viewに直接描画するのでframebufferは作らない
+        //render_scaleのtargetに描画する場合はswapchainの画像にはblitするだけなので作らない
+        let scaled = render_scale
+            .as_ref()
+            .map_or(false, |render_scale| render_scale.target().is_some());
+
+        let swap_chain_frame_buffers = match dynamic_rendering {
+            Some(_) => vec![],
+            None if scaled => vec![],
+            None => Self::create_frame_buffers(
+                &device,
+                render_pass,
+                //Cloneして大丈夫？
+                swap_chain_image_views.clone(),
+                depth_buffer.view,
+                msaa_target.as_ref().map(|msaa_target| msaa_target.view),
+                swap_chain_extent,
+            ),
+        };
+
+        let command_pool =
+            Self::create_command_pool(&instance, &surface, surface_khr, physical_device, &device);
+
+        let command_buffers =
+            Self::create_command_buffers(&device, command_pool, config.frames_in_flight);
+
+        if demo.compute_test {
+            let shader_module = Self::create_shader_module(&device, SHADER_CODE);
+
+            //one_time_commandsはグラフィックスキューでディスパッチする
+            let result = compute::run_self_test(
+                &instance,
+                physical_device,
+                &device,
+                &one_time_commands,
+                &synchronization,
+                shader_module,
+            );
+
+            unsafe { device.destroy_shader_module(shader_module, None) };
+
+            match result {
+                Ok(()) => info!("Compute self test passed"),
+                Err(error) => panic!("Compute self test failed: {}", error),
+            }
+        }
+
+        if demo.compare_mipmaps {
+            let shader_module = Self::create_shader_module(&device, SHADER_CODE);
+
+            let mip_generator = MipGenerator::new(
+                &device,
+                api_version,
+                &mut descriptor_layout_cache,
+                pipeline_cache.handle(),
+                shader_module,
+            );
+
+            unsafe { device.destroy_shader_module(shader_module, None) };
+
+            for format in mipmap::COMPARISON_FORMATS {
+                match mipmap::compare(
+                    &instance,
+                    physical_device,
+                    &device,
+                    &one_time_commands,
+                    &synchronization,
+                    &mip_generator,
+                    format,
+                ) {
+                    Ok(differences) => info!(
+                        "Mipmap blit/compute max difference for {:?}: {:?}",
+                        format, differences
+                    ),
+                    Err(error) => log::warn!("Mipmap comparison skipped: {}", error),
+                }
+            }
+
+            mip_generator.destroy(&device);
+        }
+
+        let (image_available_semaphores, render_finished_semaphores) =
+            Self::create_sync_objects(&device, config.frames_in_flight);
+
+        //まだ何もsubmitしていないので0から始める
+        let frame_timeline = TimelineSemaphore::new(&device, 0);
+
+        //0は待たずに済む値なので画像を使ったフレームが無い状態を表せる
+        let images_in_flight = vec![0; swap_chain_images.len()];
+
+        let gpu_timer = GpuTimer::new(
+            &instance,
+            physical_device,
+            &device,
+            queue_family_indices.graphics_family.unwrap(),
+            config.frames_in_flight,
+        );
+
+        #[cfg(feature = "tracy")]
+        let gpu_timer = gpu_timer.map(|mut gpu_timer| {
+            gpu_timer.connect_tracy(&device, &one_time_commands, &synchronization);
+            gpu_timer
+        });
+
+        let crash_diagnostics = CrashDiagnostics::new(
+            &instance,
+            physical_device,
+            &device,
+            enabled_features.device_fault,
+        );
+
+        //render pass内の描画を全てセカンダリコマンドバッファで行うので、インスタンス描画とパーティクルには対応しない
+        let parallel_renderer = match demo.record_threads {
+            Some(_)
+                if instanced_grid.is_some()
+                    || particles.is_some()
+                    || skybox.is_some()
+                    || transparent_quads.is_some()
+                    || shadow_map.is_some()
+                    || material_textures.is_some()
+                    || tessellated_plane.is_some()
+                    || debug_text.is_some()
+                    || sprite_batch.is_some() =>
+            {
+                log::warn!(
+                    "--record-threads is ignored with --instanced-grid, --particles, --skybox, --transparent-quads, --shadows, --textured, --tessellation, --debug-text or --sprites"
+                );
+                None
+            }
+            Some(thread_count) => Some(ParallelRenderer::new(
+                &device,
+                queue_family_indices.graphics_family.unwrap(),
+                thread_count,
+                config.frames_in_flight,
+            )),
+            None => None,
+        };
+
+        //セカンダリコマンドバッファにはviewportとscissorを1つずつしか渡していない
+        let split_screen = if !demo.split_screen {
+            None
+        } else if parallel_renderer.is_some() {
+            log::warn!("--split-screen is ignored with --record-threads");
+            None
+        } else {
+            Some(SplitScreen::new(
+                &instance,
+                physical_device,
+                &device,
+                config.frames_in_flight,
+                light_manager.max_lights(),
+                mesh_shader_stages,
+            ))
+        };
+
+        //self.meshのオブジェクトを描画する時だけ使い、セカンダリコマンドバッファの中ではクエリを使えない
+        //--split-screenではビューごとに隠れる物が違ううえ、同じクエリを2回発行することになる
+        let occlusion_culling = if !demo.occlusion_culling {
+            None
+        } else if parallel_renderer.is_some()
+            || split_screen.is_some()
+            || instanced_grid.is_some()
+            || sprite_demo.is_some()
+            || ubo_stress()
+        {
+            log::warn!(
+                "--occlusion-culling is ignored with --record-threads, --split-screen, --instanced-grid, --sprites or --ubo-stress"
+            );
+            None
+        } else {
+            Some(OcclusionCulling::new(
+                &device,
+                object_count,
+                config.frames_in_flight,
+            ))
+        };
+
+        let occlusion_pipeline = occlusion_culling.as_ref().map(|_| {
+            Self::create_occlusion_pipeline(
+                &device,
+                pipeline_cache.handle(),
+                scene_render_target,
+                uniform_buffers.descriptor_set_layout(),
+                scene_color_format.shader_output(),
+            )
+        });
+
+        //タイルはuniform_buffersの1つ分しか無く、--split-screenの右半分のビューには使えない
+        let light_culling = if !demo.light_culling {
+            None
+        } else if split_screen.is_some() {
+            log::warn!("--light-culling is ignored with --split-screen");
+            None
+        } else {
+            let shader_module = Self::create_shader_module(&device, SHADER_CODE);
+
+            let light_culling = LightCulling::new(
+                &device,
+                pipeline_cache.handle(),
+                shader_module,
+                uniform_buffers.descriptor_set_layout(),
+            );
+
+            unsafe { device.destroy_shader_module(shader_module, None) };
+
+            let (tile_count_x, tile_count_y) = LightCulling::tile_count(render_extent);
+            uniform_buffers.resize_light_tiles(
+                &instance,
+                physical_device,
+                &device,
+                tile_count_x * tile_count_y,
+            );
+
+            Some(light_culling)
+        };
+
+        let light_tiles_pipeline = light_culling.as_ref().map(|_| {
+            Self::create_light_tiles_pipeline(
+                &device,
+                pipeline_cache.handle(),
+                scene_render_target,
+                uniform_buffers.descriptor_set_layout(),
+                scene_color_format.shader_output(),
+            )
+        });
+
+        let object_draws = (0..object_buffers.capacity())
+            .map(|index| ObjectDraw {
+                dynamic_offset: object_buffers.dynamic_offset(index),
+            })
+            .collect();
+
+        let pipeline_statistics = if parallel_renderer.is_some() {
+            //inherited_queriesを有効にしないとクエリの途中でセカンダリコマンドバッファを実行できない
+            log::warn!("Pipeline statistics are disabled with --record-threads");
+            None
+        } else if enabled_features.pipeline_statistics_query {
+            Some(PipelineStatistics::new(&device, config.frames_in_flight))
+        } else {
+            log::warn!(
+                "pipeline_statistics_query is not supported, pipeline statistics are disabled"
+            );
+            None
+        };
+
+        //ディスプレイに直接表示する場合はイベントループが無いので、指定が無ければ表示モードのリフレッシュレートで描画する
+        let frame_limiter = FrameLimiter::new(
+            config
+                .target_fps
+                .or_else(|| display_mode.and_then(|mode| mode.refresh_rate_hz())),
+        );
+
+        let frame_pacer = if QueueFamilyIndices::is_device_extension_supported(
+            &instance,
+            physical_device,
+            DisplayTiming::name(),
+        ) {
+            Some(FramePacer::new(&instance, &device, swap_chain_khr))
+        } else {
+            None
+        };
+
+        let mut frame_stats = FrameStats::new();
+        frame_stats.set_target_frame_time(frame_limiter.target_frame_time());
+
+        let animation_timer = match config.run_mode {
+            RunMode::OnDemand {
+                animation_interval: Some(interval),
+            } => Some(AnimationTimer::new(interval, Instant::now())),
+            _ => None,
+        };
+
+        let input_map = InputMap::new();
+
+        //--benchはカメラとフレーム時間を自分で決めるので、記録も再生もしない
+        //--on-demandではイベントが来るまでフレームが進まないので、記録したフレームと揃わない
+        let session_player = match &demo.replay_session {
+            Some(_) if config.bench_frames.is_some() => {
+                log::warn!("--replay is ignored with --bench");
+                None
+            }
+            Some(_) if config.run_mode != RunMode::Continuous => {
+                log::warn!("--replay is ignored with --on-demand");
+                None
+            }
+            Some(path) => Some(SessionPlayer::load(path, &input_map)?),
+            None => None,
+        };
+
+        //--replayではウィンドウの入力と同じくゲームパッドも使わない
+        let gamepads = match source {
+            SurfaceSource::Window(_) if session_player.is_none() => Gamepads::new(config.gamepad),
+            _ => None,
+        };
+
+        let session_recorder = match demo.record_session.clone() {
+            Some(_) if config.bench_frames.is_some() => {
+                log::warn!("--record is ignored with --bench");
+                None
+            }
+            Some(path) => Some(SessionRecorder::new(path, &input_map)),
+            None => None,
+        };
+
+        //--benchでは描画の速さに関わらず同じ画面の列になるように、時間を固定間隔で進める
+        let mut frame_clock = FrameClock::new();
+        let benchmark = config.bench_frames.map(|frames| {
+            frame_stats.keep_history();
+            frame_clock.set_fixed_delta(Some(FIXED_TIMESTEP));
+            Benchmark::new(frames)
+        });
+
+        Ok(Self {
+            entry,
+            instance,
+            host_alloc_tracker,
+            debug_utils,
+            debug_utils_messenger_ext,
+            physical_device,
+            device,
+            graphics_queue,
+            present_queue,
+            surface,
+            surface_khr,
+            swap_chain,
+            swap_chain_khr,
+            swap_chain_images,
+            swap_chain_image_format,
+            swap_chain_color_format,
+            swap_chain_extent,
+            depth_buffer,
+            msaa_samples,
+            msaa_target,
+            swap_chain_image_views,
+            render_pass,
+            dynamic_rendering,
+            pipeline_layout,
+            pipeline,
+            wireframe_pipeline,
+            wireframe: false,
+            enabled_features,
+            pipeline_cache,
+            ray_tracer,
+            sampler_cache,
+            descriptor_layout_cache,
+            clear_color: config.clear_color,
+            animate_clear_color: demo.animate_clear_color,
+            lighting_mode: LightingMode::Full,
+            light_manager,
+            frozen_frustum: None,
+            lost: None,
+            frame_count: 0,
+            simulate_device_lost_at: demo.simulate_device_lost_at,
+            destroyed: false,
+            ubo_stress,
+            swap_chain_frame_buffers,
+            command_pool,
+            command_buffers,
+            one_time_commands,
+            current_frame: 0,
+            resize: None,
+            needs_redraw: true,
+            animation_timer,
+            swap_chain_settings,
+            fullscreen_mode: FullscreenMode::Windowed,
+            full_screen_exclusive,
+            full_screen_exclusive_acquired: false,
+            frame_stats,
+            frame_limiter,
+            input: InputState::new(),
+            input_map,
+            gamepads,
+            session_recorder,
+            session_player,
+            scene_path: demo.scene_path.clone(),
+            mesh_path,
+            camera,
+            frame_clock,
+            benchmark,
+            frame_advance_requested: false,
+            model_rotation: 0.0,
+            cursor_grabbed: false,
+            uniform_buffers,
+            quad_grid,
+            mesh,
+            asset_uploader,
+            mesh_upload_value: 0,
+            retired_meshes: vec![],
+            object_buffers,
+            instanced_grid,
+            vertex_stage,
+            particles,
+            particle_pipeline,
+            skybox,
+            skybox_pipeline,
+            split_screen,
+            current_view: 0,
+            occlusion_culling,
+            occlusion_pipeline,
+            staging_ring,
+            debug_text,
+            debug_text_pipeline,
+            egui_overlay,
+            egui_pipeline,
+            frame_report_text: String::new(),
+            sprite_batch,
+            sprite_pipeline,
+            sprite_demo,
+            orthographic_height,
+            opaque_object_count: object_count,
+            transparent_quads,
+            transparent_pipeline,
+            shadow_map,
+            shadow_pipeline,
+            ground,
+            texture_manager,
+            material_textures,
+            procedural_texture,
+            texture_array,
+            normal_map,
+            normal_mapping_enabled: true,
+            skinning,
+            animator,
+            vertex_pulling,
+            pulling_pipeline,
+            mesh_shading,
+            ray_query_shadows,
+            ray_query_pipeline,
+            tessellated_plane,
+            tessellation_pipeline,
+            debug_view_pipelines,
+            debug_view: DebugView::Shading,
+            overdraw_counters,
+            overdraw_pipelines,
+            light_culling,
+            light_tiles_pipeline,
+            normals_pipeline,
+            show_normals: false,
+            post_process,
+            post_process_pipelines,
+            bloom,
+            bloom_pipelines,
+            bloom_intensity: config.bloom_intensity,
+            tonemapper: config.tonemapper,
+            exposure: config.exposure,
+            render_scale,
+            compute_queue,
+            parallel_renderer,
+            object_draws,
+            previous_graphics_range: None,
+            frame_pacer,
+            memory_budget: MemoryBudget::new(&instance, physical_device, api_version),
+            crash_diagnostics,
+            gpu_timer,
+            pipeline_statistics,
+            image_available_semaphores,
+            render_finished_semaphores,
+            synchronization,
+            frame_timeline,
+            submitted_frames: 0,
+            images_in_flight,
+            window_targets: HashMap::new(),
+            primary_window: None,
+            last_window: None,
+            display: match source {
+                SurfaceSource::Display(choice) => Some(choice),
+                SurfaceSource::Window(_) => None,
+            },
+            scale_factor,
+            config,
+        })
+    }
+
+    fn draw_frame(&mut self, frame_size: usize) {
+        //フレームに対して書き込むために使用するCommandBufferやSemaphoreを取得する
+        let command_buffer = *self.command_buffers.get(self.current_frame).unwrap();
+        let image_available_semaphore = *self
+            .image_available_semaphores
+            .get(self.current_frame)
+            .unwrap();
+        let render_finished_semaphore = *self
+            .render_finished_semaphores
+            .get(self.current_frame)
+            .unwrap();
+        //このフレームのsubmitがframe_timelineにシグナルする値
+        let frame_value = self.submitted_frames + 1;
+
+        unsafe {
+            //frame_size個前のフレームの完了を待つ
+            //そのフレームが同じcurrent_frameのコマンドバッファやUniform Bufferを使っていた
+            //最初のframe_size個のフレームでは0を待つことになるのですぐに返ってくる
+            //TDRなどでデバイスが失われた場合はここでERROR_DEVICE_LOSTが返ってくる
+            if let Err(error) = self.frame_timeline.wait(
+                &self.device,
+                frame_value.saturating_sub(frame_size as u64),
+                u64::MAX,
+            ) {
+                self.on_lost_error(error);
+                return;
+            }
+
+            //待機した後なのでこのフレーム番号で前回記録したタイムスタンプは書き込み済みのはず
+            //グラフィックスのsubmitはコンピュートのSemaphoreを待っているのでコンピュートの方も書き込み済み
+            self.read_gpu_timings();
+
+            if let Some(pipeline_statistics) = &mut self.pipeline_statistics {
+                pipeline_statistics.read(&self.device, self.current_frame);
+            }
+
+            if let Some(occlusion_culling) = &mut self.occlusion_culling {
+                occlusion_culling.read(&self.device, self.current_frame);
+            }
+
+            //このフレームの前回の描画は完了しているのでStagingRingの領域を先頭から使い直せる
+            self.staging_ring
+                .begin_frame(&self.device, self.current_frame);
+
+            //アップロードのsubmitは同じフレームのグラフィックスのsubmitより前に行う
+            if let Err(error) = self.pump_asset_uploads() {
+                self.on_lost_error(error);
+                return;
+            }
+
+            //フレームごとにUniform Bufferが分かれていればGPUが読んでいる最中にCPUが書き換えることはない
+            if self.ubo_stress {
+                if let Some((written, seen_by_gpu)) =
+                    self.uniform_buffers.read_back(self.current_frame)
+                {
+                    assert_eq!(
+                        written, seen_by_gpu,
+                        "Uniform buffer for frame {} was overwritten while in flight",
+                        self.current_frame
+                    );
+                }
+            }
+
+            //swapchainからImageを取得する
+            //.0はswap_chain_imagesの配列のIndexが帰ってくる
+            //.1はVK_SUBOPTIMAL_KHRかどうかが帰ってくる
+            //https://www.khronos.org/registry/vulkan/specs/1.3-extensions/man/html/VkResult.html
+            let result = self.swap_chain.acquire_next_image(
+                self.swap_chain_khr,
+                //画像が利用可能になるまでの待機時間のタイムアウトをナノ秒で指定
+                //MAXを入れるとタイムアウトを無効にできる
+                u64::MAX,
+                //このセマフォはシグナルが送られる
+                image_available_semaphore,
+                vk::Fence::null(),
+            );
+
+            //SUBOPTIMAL_KHRは成功コードなのでashではboolとして返ってくる
+            //画像は取得できていてセマフォにもシグナルが送られるので、ここで作り直すとそのフレームを捨てることになる
+            //なのでこのフレームは描画してpresentの後にまとめて作り直す
+            let (image_index, acquired_suboptimal) = match result {
+                Ok((image_index, is_suboptimal)) => (image_index, is_suboptimal),
+                //ERROR_OUT_OF_DATE_KHR
+                //swapchainとsurfaceの互換がなくなった時に呼ばれる、ウィンドウのリサイズ時など
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                    self.recreate_swap_chain();
+                    return;
+                }
+                //Alt+Tabなどで排他フルスクリーンが失われた場合
+                Err(vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT) => {
+                    self.on_full_screen_exclusive_lost();
+                    return;
+                }
+                Err(error) => {
+                    self.on_lost_error(error);
+                    return;
+                }
+            };
+
+            //前のフレームがまだこの画像を使用している場合はそのフレームの完了を待つ
+            //Fenceと違ってリセットが無いので、ここでreturnしても次のフレームが待ち続けることはない
+            if let Err(error) = self.frame_timeline.wait(
+                &self.device,
+                self.images_in_flight[image_index as usize],
+                u64::MAX,
+            ) {
+                self.on_lost_error(error);
+                return;
+            }
+            self.images_in_flight[image_index as usize] = frame_value;
+
+            //mirrorのウィンドウの画像はメインの画像を取得できた後に取得する
+            //取得できた画像のSemaphoreはこのフレームのsubmitで待機する
+            let frame = self.current_frame;
+            self.recreate_window_targets();
+            let mirrors = match self.acquire_window_targets(frame) {
+                Ok(mirrors) => mirrors,
+                Err(error) => {
+                    self.on_lost_error(error);
+                    return;
+                }
+            };
+
+            //コマンドバッファをリセットする
+            self.device
+                .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+                .unwrap();
+
+            //frame_timelineを待った後なのでこのフレームのUniform Bufferを書き換えても良い
+            {
+                #[cfg(feature = "profiling")]
+                profiling::scope!("update uniforms");
+                self.update_uniform_buffer(&self.uniform_buffers, &self.camera);
+
+                if let Some(split_screen) = &mut self.split_screen {
+                    split_screen.update_camera(&self.camera, self.frame_clock.elapsed_seconds());
+                }
+
+                if let Some(split_screen) = &self.split_screen {
+                    self.update_uniform_buffer(
+                        split_screen.uniform_buffers(),
+                        split_screen.camera(),
+                    );
+                }
+                self.update_object_buffer(self.current_frame);
+
+                if let (Some(skinning), Some(animator)) = (&self.skinning, &self.animator) {
+                    skinning.write_palette(self.current_frame, &animator.joint_palette());
+                }
+            }
+
+            //コマンドバッファを記録する
+            let record_started_at = Instant::now();
+            {
+                #[cfg(feature = "profiling")]
+                profiling::scope!("record commands");
+                self.record_command_buffer(command_buffer, image_index as usize, &mirrors);
+            }
+            self.frame_stats
+                .record_command_time(record_started_at.elapsed());
+
+            //--simulate-device-lostでデバイスロストからの復帰を試せるようにする
+            if self.simulate_device_lost_at == Some(self.frame_count) {
+                log::warn!("Simulating device lost at frame {}", self.frame_count);
+                self.on_lost_error(vk::Result::ERROR_DEVICE_LOST);
+                return;
+            }
+
+            //パーティクルの更新を専用のコンピュートキューに投げる
+            //シグナルされたSemaphoreを待たずに残さないように、グラフィックスのsubmitの直前に行う
+            let compute_finished_semaphore = match self.submit_async_compute() {
+                Ok(semaphore) => semaphore,
+                Err(error) => {
+                    self.on_lost_error(error);
+                    return;
+                }
+            };
+
+            //どのセマフォを使用してどのステージで待機するか
+            //今回は画像が利用可能になるまで待ちたいのでCOLOR_ATTACHMENT_OUTPUTを使用
+            //ここのセマフォを設定せずに行うと理論的には画像が利用可能でない状態でバーテックスシェーダを使用することなどが可能
+            //バイナリセマフォの値は無視されるので0を入れておく
+            let mut wait_semaphores = vec![synchronization::semaphore_submit_info(
+                image_available_semaphore,
+                0,
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            )];
+
+            //パーティクルを頂点バッファとして読む前にコンピュートの完了を待つ
+            if let Some(semaphore) = compute_finished_semaphore {
+                wait_semaphores.push(synchronization::semaphore_submit_info(
+                    semaphore,
+                    0,
+                    vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT,
+                ));
+            }
+
+            //--async-assetsで差し替えたメッシュは頂点入力の前にアップロードの完了を待つ
+            //待機の対象は同じsubmitのコマンドだけなので、シグナル済みになった後も毎フレーム指定する
+            if let Some(asset_uploader) = self
+                .asset_uploader
+                .as_ref()
+                .filter(|_| self.mesh_upload_value > 0)
+            {
+                wait_semaphores.push(synchronization::semaphore_submit_info(
+                    asset_uploader.timeline_handle(),
+                    self.mesh_upload_value,
+                    vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT
+                        | vk::PipelineStageFlags2::INDEX_INPUT,
+                ));
+            }
+
+            //mirrorのウィンドウの画像はクリアとblitの前に待つ
+            wait_semaphores.extend(
+                mirrors
+                    .iter()
+                    .map(|(id, _)| self.mirror_swap_chain(id).image_available_wait_info(frame)),
+            );
+
+            //presentが待つバイナリセマフォと、CPUが待つタイムラインセマフォの両方にシグナルを送る
+            //CPUはframe_timelineを待った後にreadbackやタイムスタンプを読むので全てのコマンドの完了でシグナルする
+            //mirrorのウィンドウのpresentはそれぞれのバイナリセマフォを待つ
+            let mut signal_semaphores = vec![
+                synchronization::semaphore_submit_info(
+                    render_finished_semaphore,
+                    0,
+                    vk::PipelineStageFlags2::ALL_COMMANDS,
+                ),
+                synchronization::semaphore_submit_info(
+                    self.frame_timeline.handle(),
+                    frame_value,
+                    vk::PipelineStageFlags2::ALL_COMMANDS,
+                ),
+            ];
+            signal_semaphores.extend(mirrors.iter().map(|(id, _)| {
+                self.mirror_swap_chain(id)
+                    .render_finished_signal_info(frame)
+            }));
+
+            //graphics_queueをsubmitする
+            //完了はframe_timelineで分かるのでFenceは渡さない
+            let submitted = {
+                #[cfg(feature = "profiling")]
+                profiling::scope!("submit");
+                self.graphics_queue.submit(
+                    &self.device,
+                    &self.synchronization,
+                    &wait_semaphores,
+                    &[command_buffer],
+                    &signal_semaphores,
+                    vk::Fence::null(),
+                )
+            };
+
+            if let Err(error) = submitted {
+                self.on_lost_error(error);
+                return;
+            }
+
+            //frame_timelineの値とcurrent_frameがずれないように、submitしたらすぐにフレームを進める
+            //この後presentが失敗してreturnしても次のフレームは別のcurrent_frameを使う
+            self.submitted_frames = frame_value;
+            self.current_frame = (self.current_frame + 1) % frame_size;
+
+            //mirrorのウィンドウを先にpresentしておくと、メインのpresentが失敗してreturnしてもシグナルしたSemaphoreが残らない
+            if let Err(error) = self.present_window_targets(frame, &mirrors) {
+                self.on_lost_error(error);
+                return;
+            }
+
+            //Presentation
+
+            //VK_GOOGLE_display_timingが使える場合は表示してほしい時刻を指定する
+            let present_times = self
+                .frame_pacer
+                .as_mut()
+                .map(|frame_pacer| frame_pacer.next_present_time())
+                .into_iter()
+                .collect::<Vec<_>>();
+
+            let mut present_times_info = vk::PresentTimesInfoGOOGLE::builder()
+                .times(&present_times)
+                .build();
+
+            //builderをpush_nextのために文をまたいで使うので配列は変数に束縛しておく
+            let wait_semaphores = [render_finished_semaphore];
+            let swap_chains = [self.swap_chain_khr];
+            let image_indices = [image_index];
+
+            //このメソッドはPresentationが成功したかどうかを受け取れる
+            //引数が配列になっているのは各swapchainに対してそれぞれResultが返ってくるため
+            //今回はswapchainが１つしか存在しないのでpresent用の関数の戻り値を参照すれば良い
+            //swapchainが複数存在するとき用？
+            //.results()
+            let mut present_info = vk::PresentInfoKHR::builder()
+                //待機するセマフォを指定
+                .wait_semaphores(&wait_semaphores)
+                .swapchains(&swap_chains)
+                //swapchainに対するimageを指定
+                .image_indices(&image_indices);
+
+            if !present_times.is_empty() {
+                present_info = present_info.push_next(&mut present_times_info);
+            }
+
+            let result = {
+                #[cfg(feature = "profiling")]
+                profiling::scope!("present");
+                self.present_queue
+                    .present(&self.swap_chain, &present_info.build())
+            };
+
+            if let Some(frame_pacer) = &mut self.frame_pacer {
+                frame_pacer.collect_feedback(self.swap_chain_khr);
+            }
+
+            let presented_suboptimal = match result {
+                //SUBOPTIMAL_KHR
+                //swapchainはsurfaceに正常にpresentすることは出来るが、プロパティは完全に一致していない
+                //ウィンドウのリサイズ直後にコンポジタが古い大きさのまま受け付けている場合などに返ってくる
+                Ok(is_suboptimal) => is_suboptimal,
+                //ERROR_OUT_OF_DATE_KHR
+                //このフレームは表示されていないが次のフレームからは新しいswapchainで描画する
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => true,
+                //on_full_screen_exclusive_lostの中で作り直される
+                Err(vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT) => {
+                    self.on_full_screen_exclusive_lost();
+                    false
+                }
+                Err(error) => {
+                    self.on_lost_error(error);
+                    return;
+                }
+            };
+
+            //Resizedイベントとsuboptimalが同じフレームで起きても作り直すのは一回だけにする
+            if presented_suboptimal || acquired_suboptimal || self.resize.is_some() {
+                self.recreate_swap_chain();
+            }
+        }
+
+        //TracyとpuffinはここでCPUのフレームを区切る
+        #[cfg(feature = "profiling")]
+        profiling::finish_frame!();
+
+        self.frame_count += 1;
+    }
+
+    //SurfaceやDeviceが失われた場合は次のrenderで復帰させる
+    //復帰にはウィンドウが必要なのでここでは記録するだけ
+    fn on_lost_error(&mut self, error: vk::Result) {
+        match error {
+            vk::Result::ERROR_SURFACE_LOST_KHR => {
+                log::warn!("Surface lost, recreating surface and swapchain");
+                self.lost = Some(LostResource::Surface);
+            }
+            vk::Result::ERROR_DEVICE_LOST => {
+                //--simulate-device-lostではデバイスは失われていないので、失われた後にしか使えない関数は呼ばない
+                if self.simulate_device_lost_at != Some(self.frame_count) {
+                    self.log_device_lost();
+                }
+                log::error!("Device lost, reinitializing device and all device resources");
+                self.lost = Some(LostResource::Device);
+            }
+            error => panic!("{}", error),
+        }
+    }
+
+    //どのフレームのどのパスで失われたかを絞り込めるように、分かることを全てログに出す
+    fn log_device_lost(&self) {
+        match self.frame_timeline.value(&self.device) {
+            Ok(completed) => log::error!(
+                "Device lost: last submitted frame {}, last completed frame {}",
+                self.submitted_frames,
+                completed
+            ),
+            Err(_) => log::error!(
+                "Device lost: last submitted frame {}",
+                self.submitted_frames
+            ),
+        }
+
+        let mut queues = vec![("graphics", self.graphics_queue.handle())];
+        if let Some(compute_queue) = &self.compute_queue {
+            queues.push(("compute", compute_queue.queue_handle()));
+        }
+
+        self.crash_diagnostics.report(&self.device, &queues);
+    }
+
+    //SurfaceKHRをウィンドウから作り直し、swapchainも作り直す
+    fn recover_surface(&mut self, window: Option<&Window>) {
+        //Surfaceが失われていてもデバイスは生きているのでGPUの処理が終わるのを待つ
+        unsafe { self.device.device_wait_idle().unwrap() };
+
+        //swapchainはSurfaceより先に破棄する必要がある
+        self.cleanup_swap_chain();
+
+        unsafe { self.surface.destroy_surface(self.surface_khr, None) };
+
+        let (surface, surface_khr, display_mode) =
+            Self::create_source_surface(&self.instance, &self.entry, self.surface_source(window))
+                .unwrap_or_else(|error| panic!("Failed to recover from surface lost: {}", error));
+        self.surface = surface;
+        self.surface_khr = surface_khr;
+
+        self.resize = Some(match (window, display_mode) {
+            (_, Some(mode)) => (mode.extent.width, mode.extent.height),
+            (Some(window), None) => window.inner_size().into(),
+            (None, None) => unreachable!("A window surface was created without a window"),
+        });
+        self.create_swap_chain_after_cleanup(RecreateScope::SwapchainOnly);
+        self.resize = None;
+
+        info!("Recovered from surface lost");
+    }
+
+    //デバイスに紐づく全てのリソースを破棄してVulkanAppを作り直す
+    //カメラや表示設定など利用者が変更した状態は引き継ぐ
+    fn recover_device(&mut self, window: Option<&Window>) {
+        //ウィンドウは閉じずに新しいデバイスでsurfaceとswapchainを作り直す
+        let primary_window = self.primary_window;
+        let windows = self
+            .window_targets
+            .drain()
+            .map(|(_, target)| target.destroy(&self.device))
+            .collect::<Vec<_>>();
+
+        //失われたデバイスでもリソースの破棄は行える
+        self.destroy();
+
+        //同じウィンドウに対してswapchainを作るので古いものを破棄した後に作成する
+        let mut app = match VulkanApp::new(self.surface_source(window), self.config.clone()) {
+            Ok(app) => app,
+            Err(error) => panic!("Failed to recover from device lost: {}", error),
+        };
+
+        app.camera = std::mem::replace(&mut self.camera, Camera::new(Vec3::ZERO));
+        app.frame_clock = std::mem::replace(&mut self.frame_clock, FrameClock::new());
+        //記録と再生は途中から続ける
+        app.session_recorder = self.session_recorder.take();
+        app.session_player = self.session_player.take();
+        //--benchの途中で失われた場合は、それまでの計測に続けて数える
+        if self.benchmark.is_some() {
+            app.benchmark = self.benchmark.take();
+            app.frame_stats = std::mem::replace(&mut self.frame_stats, FrameStats::new());
+        }
+        app.model_rotation = self.model_rotation;
+        app.wireframe = self.wireframe && app.wireframe_pipeline.is_some();
+        app.clear_color = self.clear_color;
+        app.animate_clear_color = self.animate_clear_color;
+        //同じ状況で何度も失われないように一度だけにする
+        app.simulate_device_lost_at = None;
+
+        if app.swap_chain_settings.present_mode != self.swap_chain_settings.present_mode {
+            app.swap_chain_settings.present_mode = self.swap_chain_settings.present_mode;
+            app.recreate_swap_chain();
+        }
+
+        //排他フルスクリーンは新しいデバイスで取り直す必要があるのでボーダーレスとして復帰する
+        if self.fullscreen_mode != FullscreenMode::Windowed {
+            app.fullscreen_mode = FullscreenMode::Borderless;
+        }
+
+        //古いVulkanAppはdestroy済みなのでDropでは何もしない
+        *self = app;
+        self.primary_window = primary_window;
+
+        for window in windows {
+            if Some(window.id()) == primary_window {
+                self.window_targets
+                    .insert(window.id(), WindowTarget::primary(window));
+            } else {
+                self.add_window_target(window);
+            }
+        }
+
+        info!("Recovered from device lost");
+    }
+
+    //windowがNoneの場合は--displayで選んだディスプレイ
+    fn surface_source<'a>(&self, window: Option<&'a Window>) -> SurfaceSource<'a> {
+        match (window, self.display) {
+            (_, Some(choice)) => SurfaceSource::Display(choice),
+            (Some(window), None) => SurfaceSource::Window(window),
+            (None, None) => panic!("No window or display to create a surface for"),
+        }
+    }
+
+    //--displayではwinitのイベントループが無いので、入力は受け取らずに更新と描画を繰り返す
+    //フレームの間隔はframe_limiterとpresent modeで決まり、--bench以外では終了はプロセスを止める
+    pub fn run_display(mut self) {
+        info!("Running application on a display");
+
+        loop {
+            self.update(None);
+            self.input.end_frame();
+            self.render(None);
+
+            if self.finish_benchmark() {
+                unsafe { self.device.device_wait_idle().unwrap() };
+                return;
+            }
+        }
+    }
+
+    //--bench-pipelinesではイベントループを回さず、メインのパイプラインとその派生の作成時間を表示して終わる
+    //ワイヤーフレームとアルファブレンドのものを派生として作り、派生とキャッシュの有無の組み合わせごとに比べる
+    pub fn run_pipeline_bench(self) {
+        let render_target = match self.dynamic_rendering {
+            Some(_) => RenderTarget::Dynamic {
+                color_format: self.swap_chain_image_format,
+                depth_format: self.depth_buffer.format,
+                depth: self.config.depth,
+                samples: self.msaa_samples,
+            },
+            None => {
+                RenderTarget::RenderPass(self.render_pass, self.config.depth, self.msaa_samples)
+            }
+        };
+
+        let scene_color_format =
+            Self::scene_color_format(self.swap_chain_color_format, self.post_process.is_some());
+        let scene_render_target =
+            Self::scene_render_target(render_target, self.post_process.as_ref());
+
+        let scene_descriptor_set_layouts = Self::scene_descriptor_set_layouts(
+            &self.uniform_buffers,
+            &self.object_buffers,
+            self.shadow_map.as_ref(),
+            self.ray_query_shadows.as_ref(),
+            self.material_textures.as_ref(),
+            self.vertex_pulling.as_ref(),
+            self.mesh_shading.as_ref(),
+            self.texture_array.as_ref(),
+            self.normal_map.as_ref(),
+            self.skinning.as_ref(),
+            self.overdraw_counters.as_ref(),
+        );
+
+        //PolygonMode::LINEはfill_mode_non_solidが無いと作れない
+        let variants = if self.enabled_features.fill_mode_non_solid {
+            vec![PipelineVariant::Wireframe, PipelineVariant::Blended]
+        } else {
+            vec![PipelineVariant::Blended]
+        };
+
+        let names = ["base"]
+            .into_iter()
+            .chain(variants.iter().map(|variant| variant.name()))
+            .collect::<Vec<_>>();
+
+        let (bench, pipeline_layout) = Self::build_pipelines(
+            &self.device,
+            scene_render_target,
+            &scene_descriptor_set_layouts,
+            self.vertex_stage,
+            scene_color_format.shader_output(),
+            &SpecConstants::new(),
+            &variants,
+            |base_info, variant_infos| {
+                PipelineBench::run(&self.device, &names, base_info, variant_infos)
+            },
+        );
+
+        unsafe { self.device.destroy_pipeline_layout(pipeline_layout, None) };
+
+        print!("{}", bench);
+    }
+
+    pub fn run(mut self, window_handlers: WindowHandlers) {
+        let run_mode = self.config.run_mode;
+
+        info!("Running application ({:?})", run_mode);
+
+        //event_loop.runでevent_loopの所有権が消費されるのでウィンドウはVulkanAppに移しておく
+        let WindowHandlers {
+            event_loop,
+            window,
+            mirror_windows,
+        } = window_handlers;
+
+        let window = Rc::new(window);
+        self.primary_window = Some(window.id());
+        self.window_targets
+            .insert(window.id(), WindowTarget::primary(window));
+
+        for mirror_window in mirror_windows {
+            self.add_window_target(Rc::new(mirror_window));
+        }
+
+        event_loop.run(move |event, _, control_flow| {
+            //selfを可変で借りたままウィンドウを渡せるように、イベントごとにメインのウィンドウを取り出す
+            //全てのウィンドウが閉じられた後に届いたイベントは無視する
+            let window = match self.primary_window() {
+                Some(window) => window,
+                None => {
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+            };
+
+            //タイマーの期限はイベントの度ではなく満了した時だけ進める
+            *control_flow = match run_mode {
+                RunMode::Continuous => ControlFlow::Poll,
+                RunMode::OnDemand { .. } => self.on_demand_control_flow(),
+            };
+
+            match event {
+                //アニメーション用のタイマーが満了したか、ゲームパッドを読む時刻になった
+                Event::NewEvents(StartCause::ResumeTimeReached { .. }) => {
+                    let now = Instant::now();
+
+                    if let Some(animation_timer) = &mut self.animation_timer {
+                        if animation_timer.deadline() <= now {
+                            animation_timer.fire(now);
+                            self.request_redraw();
+                        }
+                    }
+
+                    *control_flow = self.on_demand_control_flow();
+                }
+                //どのウィンドウが閉じられた場合も、最後の1つが閉じられるまでは終了しない
+                Event::WindowEvent {
+                    window_id,
+                    event: WindowEvent::CloseRequested,
+                } => {
+                    self.close_window_target(window_id);
+
+                    if self.window_targets.is_empty() {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+                //mirrorのウィンドウへのイベントはそのウィンドウのWindowTargetだけが受け取る
+                //入力はメインのウィンドウのものだけを使う
+                Event::WindowEvent { window_id, event } if window_id != window.id() => {
+                    self.handle_window_target_event(window_id, &event);
+                }
+                Event::WindowEvent { event, .. } => {
+                    //eguiのウィンドウの上で押したボタンやキーはカメラなどの操作に渡さない
+                    let consumed = match &mut self.egui_overlay {
+                        Some(egui_overlay) if egui_overlay.is_visible() => {
+                            self.needs_redraw = true;
+                            egui_overlay.on_window_event(&event)
+                        }
+                        _ => false,
+                    };
+
+                    if self.session_player.is_none() && !consumed {
+                        self.input.handle_window_event(&event);
+                    }
+
+                    match event {
+                        WindowEvent::Resized(physical_size) => {
+                            self.resize = Some((physical_size.width, physical_size.height));
+                            self.request_redraw();
+                        }
+                        //DPIの違うモニターに移った場合など、論理ピクセルの大きさは同じでも物理ピクセルの大きさが変わる
+                        WindowEvent::ScaleFactorChanged {
+                            scale_factor,
+                            new_inner_size,
+                        } => {
+                            self.on_scale_factor_changed(scale_factor, *new_inner_size);
+                        }
+                        WindowEvent::Focused(_) => {
+                            self.request_redraw();
+                        }
+                        _ => (),
+                    }
+                }
+                Event::DeviceEvent { event, .. } if self.session_player.is_none() => {
+                    self.input.handle_device_event(&event)
+                }
+                //イベントを全て処理し終えたタイミング
+                Event::MainEventsCleared => {
+                    let quit = {
+                        #[cfg(feature = "profiling")]
+                        profiling::scope!("handle events");
+                        if let Some(gamepads) = &mut self.gamepads {
+                            gamepads.poll(&mut self.input);
+                        }
+                        //--replayの最後のフレームを再生し終えた場合も終了する
+                        let quit = self.replay_input() || self.handle_actions(&window);
+                        self.run_overlay(&window);
+                        self.update(Some(&*window));
+                        self.record_input();
+                        quit
+                    };
+                    self.input.end_frame();
+
+                    if quit {
+                        unsafe { self.device.device_wait_idle().unwrap() };
+                        *control_flow = ControlFlow::Exit;
+                        return;
+                    }
+
+                    match run_mode {
+                        //最小化中はswapchainを作れないので描画せず、Resizedが来るまで待機する
+                        RunMode::Continuous if Self::is_minimized(&window) => {
+                            *control_flow = ControlFlow::Wait;
+                        }
+                        //毎回1フレーム描画する
+                        RunMode::Continuous => {
+                            self.render(Some(&*window));
+
+                            if self.finish_benchmark() {
+                                unsafe { self.device.device_wait_idle().unwrap() };
+                                *control_flow = ControlFlow::Exit;
+                            }
+                        }
+                        //再描画が必要な場合はRedrawRequestedを発行してもらう
+                        RunMode::OnDemand { .. } => {
+                            if self.needs_redraw {
+                                window.request_redraw();
+                            }
+                        }
+                    }
+                }
+                //OSからウィンドウの再描画を要求された場合もここに来る
+                Event::RedrawRequested(_) if matches!(run_mode, RunMode::OnDemand { .. }) => {
+                    self.render(Some(&*window));
+                    self.needs_redraw = false;
+                }
+                _ => (),
+            }
+        });
+    }
+
+    //--on-demandでイベントを待つ間のControlFlow
+    //アニメーション用のタイマーの期限とゲームパッドを読む時刻の早い方で起きる
+    fn on_demand_control_flow(&self) -> ControlFlow {
+        let deadline = [
+            self.animation_timer.as_ref().map(AnimationTimer::deadline),
+            self.gamepads.as_ref().map(Gamepads::next_poll),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+
+        match deadline {
+            Some(deadline) => ControlFlow::WaitUntil(deadline),
+            None => ControlFlow::Wait,
+        }
+    }
+
+    //新しい物理ピクセルの大きさでswapchainを作り直し、文字の大きさも合わせる
+    fn on_scale_factor_changed(&mut self, scale_factor: f64, physical_size: PhysicalSize<u32>) {
+        Self::log_window_size(physical_size, scale_factor);
+
+        self.scale_factor = scale_factor;
+
+        if let Some(debug_text) = &mut self.debug_text {
+            debug_text.set_scale_factor(scale_factor);
+        }
+        if let Some(egui_overlay) = &mut self.egui_overlay {
+            egui_overlay.set_scale_factor(scale_factor);
+        }
+
+        self.resize = Some((physical_size.width, physical_size.height));
+        self.request_redraw();
+    }
+
+    fn log_window_size(physical_size: PhysicalSize<u32>, scale_factor: f64) {
+        let logical_size = physical_size.to_logical::<f64>(scale_factor);
+
+        info!(
+            "window size: logical {:.0}x{:.0}, physical {}x{}, scale factor {}",
+            logical_size.width,
+            logical_size.height,
+            physical_size.width,
+            physical_size.height,
+            scale_factor
+        );
+    }
+
+    fn handle_window_target_event(&mut self, window_id: WindowId, event: &WindowEvent) {
+        match event {
+            WindowEvent::Resized(_) | WindowEvent::ScaleFactorChanged { .. } => {
+                if let Some(target) = self.window_targets.get_mut(&window_id) {
+                    target.mark_out_of_date();
+                }
+                self.request_redraw();
+            }
+            _ => (),
+        }
+    }
+
+    //--mirror-windowsで開いたウィンドウのsurfaceとswapchainを作って登録する
+    //メインのウィンドウの画像を映せない場合は警告を出してウィンドウを閉じる
+    fn add_window_target(&mut self, window: Rc<Window>) {
+        match self.create_window_target(window) {
+            Ok(target) => {
+                info!("Mirror window: {:?}", target.id());
+                self.window_targets.insert(target.id(), target);
+            }
+            Err(reason) => log::warn!("{}, closing the mirror window", reason),
+        }
+    }
+
+    fn create_window_target(&self, window: Rc<Window>) -> Result<WindowTarget, String> {
+        let settings = self.window_target_settings();
+
+        let source_usage =
+            SwapChainSupportDetails::new(self.physical_device, &self.surface, self.surface_khr)
+                .capabilities
+                .supported_usage_flags;
+
+        if !source_usage.contains(vk::ImageUsageFlags::TRANSFER_SRC)
+            || !self.supports_format_feature(
+                self.swap_chain_image_format,
+                vk::FormatFeatureFlags::BLIT_SRC,
+            )
+        {
+            return Err("Swapchain images cannot be blitted from".to_string());
+        }
+
+        let (surface, surface_khr) = Self::create_surface(&self.instance, &self.entry, &window);
+
+        //present_queueはメインのsurfaceに対して選んだファミリーのキューなので、このsurfaceにもpresentできるか確認する
+        let indices = QueueFamilyIndices::find_queue_families(
+            &self.instance,
+            &self.surface,
+            self.surface_khr,
+            self.physical_device,
+        );
+        let target_support =
+            SwapChainSupportDetails::new(self.physical_device, &surface, surface_khr);
+        let target_format = target_support
+            .choose_swap_surface_format(&settings.surface_formats)
+            .format;
+
+        let error = if !indices.supports_present(&surface, surface_khr, self.physical_device) {
+            Some("The present queue family cannot present to the mirror window")
+        } else if !target_support
+            .capabilities
+            .supported_usage_flags
+            .contains(vk::ImageUsageFlags::TRANSFER_DST)
+            || !self.supports_format_feature(target_format, vk::FormatFeatureFlags::BLIT_DST)
+        {
+            Some("Mirror window swapchain images cannot be blitted to")
+        } else {
+            None
+        };
+
+        if let Some(error) = error {
+            unsafe { surface.destroy_surface(surface_khr, None) };
+            return Err(error.to_string());
+        }
+
+        let (swap_chain, swap_chain_khr, _, extent) = Self::create_swap_chain(
+            &self.instance,
+            &self.device,
+            self.physical_device,
+            &surface,
+            surface_khr,
+            window.inner_size().into(),
+            &settings,
+        );
+
+        Ok(WindowTarget::mirror(
+            window,
+            MirrorSwapChain::new(
+                (surface, surface_khr),
+                (swap_chain, swap_chain_khr, extent),
+                Self::create_sync_objects(&self.device, self.config.frames_in_flight),
+            ),
+        ))
+    }
+
+    //mirrorのウィンドウでは排他フルスクリーンを使わない
+    //FIFOだと2つ目のウィンドウのacquireでも垂直同期を待つことがあるのでメインのフレームレートを落とさないようにする
+    fn window_target_settings(&self) -> SwapChainSettings {
+        SwapChainSettings {
+            present_mode: PresentModePreference::LowLatency,
+            surface_formats: self.swap_chain_settings.surface_formats.clone(),
+            image_count: self.swap_chain_settings.image_count,
+            full_screen_exclusive_monitor: None,
+        }
+    }
+
+    fn supports_format_feature(&self, format: vk::Format, feature: vk::FormatFeatureFlags) -> bool {
+        unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.physical_device, format)
+        }
+        .optimal_tiling_features
+        .contains(feature)
+    }
+
+    //このウィンドウのリソースだけを破棄し、返り値のウィンドウをdropして閉じる
+    //メインのウィンドウの場合はmirrorのウィンドウを1つメインに変え、そのsurfaceにswapchainを作り直す
+    fn close_window_target(&mut self, window_id: WindowId) {
+        let target = match self.window_targets.remove(&window_id) {
+            Some(target) => target,
+            None => return,
+        };
+
+        //このウィンドウの画像やSemaphoreを使っているフレームが終わるのを待つ
+        unsafe { self.device.device_wait_idle().unwrap() };
+
+        if !target.is_primary() {
+            target.destroy(&self.device);
+            info!("Mirror window closed, {} left", self.window_targets.len());
+            return;
+        }
+
+        self.primary_window = None;
+
+        if self.window_targets.is_empty() {
+            info!("Main window closed");
+            self.last_window = Some(target.destroy(&self.device));
+            return;
+        }
+
+        //メインのswapchainはsurfaceより先に破棄し、ウィンドウはsurfaceを破棄してから閉じる
+        self.cleanup_swap_chain();
+        unsafe { self.surface.destroy_surface(self.surface_khr, None) };
+        drop(target.destroy(&self.device));
+
+        let next = self.window_targets.values_mut().next().unwrap();
+        let surface_khr = next.promote(&self.device).unwrap();
+        let size = next.window().inner_size();
+        let scale_factor = next.window().scale_factor();
+        let next_id = next.id();
+
+        self.surface_khr = surface_khr;
+        self.primary_window = Some(next_id);
+
+        //新しいメインのウィンドウが別のモニターにある場合に文字の大きさを合わせる
+        self.scale_factor = scale_factor;
+        if let Some(debug_text) = &mut self.debug_text {
+            debug_text.set_scale_factor(scale_factor);
+        }
+        if let Some(egui_overlay) = &mut self.egui_overlay {
+            egui_overlay.set_scale_factor(scale_factor);
+        }
+
+        //mirrorのswapchainを作った時と同じsurfaceなのでフォーマットが変わることはほぼ無いが、
+        //変わった場合はcreate_swap_chain_after_cleanupが作り直す範囲を広げる
+        self.resize = Some((size.width, size.height));
+        self.create_swap_chain_after_cleanup(RecreateScope::SwapchainOnly);
+        self.resize = None;
+
+        info!(
+            "Main window closed, {:?} is the main window now, {} left",
+            next_id,
+            self.window_targets.len()
+        );
+        self.request_redraw();
+    }
+
+    //入力を受け取り、swapchainに描画するウィンドウ
+    fn primary_window(&self) -> Option<Rc<Window>> {
+        self.primary_window
+            .and_then(|id| self.window_targets.get(&id))
+            .map(|target| Rc::clone(target.window()))
+    }
+
+    //acquire_window_targetsで画像を取得できたmirrorのウィンドウのswapchain
+    fn mirror_swap_chain(&self, id: &WindowId) -> &MirrorSwapChain {
+        self.window_targets[id]
+            .mirror_swap_chain()
+            .expect("The main window has no mirror swapchain")
+    }
+
+    fn mirror_swap_chain_mut(&mut self, id: &WindowId) -> &mut MirrorSwapChain {
+        self.window_targets
+            .get_mut(id)
+            .and_then(WindowTarget::mirror_swap_chain_mut)
+            .expect("The main window has no mirror swapchain")
+    }
+
+    //Resizedやsuboptimalになったmirrorのウィンドウのswapchainを作り直す
+    fn recreate_window_targets(&mut self) {
+        let ids = self
+            .window_targets
+            .values()
+            .filter(|target| target.needs_recreate())
+            .map(WindowTarget::id)
+            .collect::<Vec<_>>();
+
+        if ids.is_empty() {
+            return;
+        }
+
+        //古いswapchainの画像を使っているフレームが終わるのを待つ
+        unsafe { self.device.device_wait_idle().unwrap() };
+
+        let settings = self.window_target_settings();
+
+        for id in ids {
+            let target = self.window_targets.get_mut(&id).unwrap();
+            let size = target.window().inner_size();
+            let mirror = target.mirror_swap_chain_mut().unwrap();
+            mirror.destroy_swap_chain();
+
+            let (swap_chain, swap_chain_khr, _, extent) = Self::create_swap_chain(
+                &self.instance,
+                &self.device,
+                self.physical_device,
+                &self.surface,
+                mirror.surface_khr(),
+                size.into(),
+                &settings,
+            );
+
+            mirror.set_swap_chain((swap_chain, swap_chain_khr, extent));
+        }
+    }
+
+    //mirrorのウィンドウの画像を取得し、取得できたウィンドウとその画像のindexを返す
+    //surfaceが失われたウィンドウはそのウィンドウだけを閉じる
+    fn acquire_window_targets(&mut self, frame: usize) -> Result<Vec<(WindowId, u32)>, vk::Result> {
+        let ids = self
+            .window_targets
+            .values()
+            .filter(|target| target.is_presentable())
+            .map(WindowTarget::id)
+            .collect::<Vec<_>>();
+
+        let mut acquired = vec![];
+
+        for id in ids {
+            match self.mirror_swap_chain_mut(&id).acquire(frame) {
+                Ok(Some(image_index)) => acquired.push((id, image_index)),
+                Ok(None) => {}
+                Err(vk::Result::ERROR_SURFACE_LOST_KHR) => {
+                    log::warn!("Mirror window surface lost, closing the window");
+                    self.close_window_target(id);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(acquired)
+    }
+
+    fn present_window_targets(
+        &mut self,
+        frame: usize,
+        mirrors: &[(WindowId, u32)],
+    ) -> Result<(), vk::Result> {
+        for &(id, image_index) in mirrors {
+            let present_queue = &self.present_queue;
+            let result = self
+                .window_targets
+                .get_mut(&id)
+                .and_then(WindowTarget::mirror_swap_chain_mut)
+                .expect("The main window has no mirror swapchain")
+                .present(present_queue, frame, image_index);
+
+            match result {
+                Ok(()) => {}
+                Err(vk::Result::ERROR_SURFACE_LOST_KHR) => {
+                    log::warn!("Mirror window surface lost, closing the window");
+                    self.close_window_target(id);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(())
+    }
+
+    //このフレームで押されたActionを処理する
+    //Action::Quitが押された場合はtrueを返す
+    fn handle_actions(&mut self, window: &Window) -> bool {
+        if self.input_map.is_triggered(&self.input, Action::Quit) {
+            return true;
+        }
+
+        let actions = self
+            .input_map
+            .triggered_actions(&self.input)
+            .collect::<Vec<_>>();
+
+        for action in actions {
+            match action {
+                Action::Quit => {}
+                Action::Info => {
+                    info!("Space!");
+                    self.request_redraw();
+                }
+                Action::CyclePresentMode => {
+                    //PresentModeはswapchainの作成時に決まるので再作成する
+                    self.swap_chain_settings.present_mode =
+                        self.swap_chain_settings.present_mode.next();
+                    self.recreate_swap_chain();
+                    self.request_redraw();
+                }
+                Action::ToggleFullscreen => {
+                    self.toggle_fullscreen(window);
+                    self.request_redraw();
+                }
+                Action::ToggleWireframe => {
+                    if self.wireframe_pipeline.is_some() {
+                        self.wireframe = !self.wireframe;
+                        info!("wireframe: {}", self.wireframe);
+                        self.request_redraw();
+                    } else {
+                        log::warn!(
+                            "fill_mode_non_solid is not supported, wireframe is unavailable"
+                        );
+                    }
+                }
+                Action::ToggleGammaMode => {
+                    self.toggle_gamma_mode();
+                    self.request_redraw();
+                }
+                Action::CycleLightingMode => {
+                    //LightUniformsは毎フレーム書き込むのでパイプラインは作り直さなくて良い
+                    self.lighting_mode = self.lighting_mode.next();
+                    info!("lighting mode: {:?}", self.lighting_mode);
+                    self.request_redraw();
+                }
+                Action::ToggleFrustumFreeze => {
+                    self.frozen_frustum = match self.frozen_frustum {
+                        Some(_) => None,
+                        None => Some(self.camera_frustum()),
+                    };
+                    info!("frustum frozen: {}", self.frozen_frustum.is_some());
+                    self.request_redraw();
+                }
+                Action::ToggleVertexPulling => {
+                    if let Some(vertex_pulling) = &mut self.vertex_pulling {
+                        //コマンドバッファは毎フレーム記録し直すのでパイプラインは作り直さなくて良い
+                        vertex_pulling.toggle(self.frame_stats.gpu_average_ms());
+                        self.frame_stats.reset_gpu_times();
+                        self.request_redraw();
+                    } else {
+                        log::warn!("Vertex pulling is unavailable without --vertex-pulling");
+                    }
+                }
+                Action::ToggleRayQueryShadows => {
+                    if let Some(ray_query_shadows) = &mut self.ray_query_shadows {
+                        ray_query_shadows.toggle();
+                        self.request_redraw();
+                    } else {
+                        log::warn!(
+                            "Ray query shadows are unavailable without --shadows --ray-query-shadows"
+                        );
+                    }
+                }
+                Action::CycleDebugView => {
+                    //使えない表示は飛ばす、Shadingはいつでも使えるので必ず止まる
+                    let mut debug_view = self.debug_view.next();
+                    while !self.has_debug_view(debug_view) {
+                        debug_view = debug_view.next();
+                    }
+
+                    if debug_view != self.debug_view {
+                        self.debug_view = debug_view;
+                        info!("debug view: {}", debug_view.name());
+                        self.request_redraw();
+                    } else {
+                        log::warn!(
+                            "Debug views are only available for pipelines using main_fs or main_vs"
+                        );
+                    }
+                }
+                Action::ToggleNormals => {
+                    if self.normals_pipeline.is_some() {
+                        self.show_normals = !self.show_normals;
+                        info!("normals: {}", self.show_normals);
+                        self.request_redraw();
+                    } else {
+                        log::warn!("geometry_shader is not supported, normals are unavailable");
+                    }
+                }
+                Action::ToggleProjection => {
+                    self.camera.projection = match self.camera.projection {
+                        Projection::Perspective => Projection::Orthographic {
+                            height: self.orthographic_height,
+                        },
+                        Projection::Orthographic { .. } => Projection::Perspective,
+                    };
+                    info!("projection: {:?}", self.camera.projection);
+                    self.request_redraw();
+                }
+                Action::RaiseTessellationLevel | Action::LowerTessellationLevel => {
+                    if let Some(tessellated_plane) = &mut self.tessellated_plane {
+                        if action == Action::RaiseTessellationLevel {
+                            tessellated_plane.raise_level();
+                        } else {
+                            tessellated_plane.lower_level();
+                        }
+                        self.request_redraw();
+                    } else {
+                        log::warn!("Tessellation level is unavailable without --tessellation");
+                    }
+                }
+                Action::RaiseRenderScale | Action::LowerRenderScale => {
+                    let changed = match &mut self.render_scale {
+                        //オーバーレイでMSAAを有効にした場合も、swapchainの画像へresolveするので倍率を変えられない
+                        Some(_) if self.msaa_samples != vk::SampleCountFlags::TYPE_1 => {
+                            log::warn!("Render scale is unavailable while MSAA is enabled");
+                            false
+                        }
+                        Some(render_scale) => {
+                            let previous = render_scale.scale();
+
+                            if action == Action::RaiseRenderScale {
+                                render_scale.raise();
+                            } else {
+                                render_scale.lower();
+                            }
+
+                            info!("render scale: {}", render_scale.scale());
+                            render_scale.scale() != previous
+                        }
+                        None => {
+                            log::warn!(
+                                "Render scale is unavailable with --raytrace, --msaa or when swapchain images cannot be blitted to"
+                            );
+                            false
+                        }
+                    };
+
+                    //描画する画像とデプスバッファ、ポストプロセスの画像の大きさが変わるのでswapchainと一緒に作り直す
+                    if changed {
+                        self.recreate_swap_chain();
+                        self.request_redraw();
+                    }
+                }
+                Action::RaiseBloomIntensity | Action::LowerBloomIntensity => {
+                    if self.bloom.is_some() {
+                        let step = if action == Action::RaiseBloomIntensity {
+                            bloom::INTENSITY_STEP
+                        } else {
+                            -bloom::INTENSITY_STEP
+                        };
+
+                        self.bloom_intensity =
+                            (self.bloom_intensity + step).clamp(0.0, bloom::MAX_INTENSITY);
+                        info!("bloom intensity: {:.1}", self.bloom_intensity);
+                        self.request_redraw();
+                    } else {
+                        log::warn!("Bloom intensity is unavailable without --post-effect bloom");
+                    }
+                }
+                Action::CycleTonemapper => {
+                    if self.has_tonemapping() {
+                        self.tonemapper = self.tonemapper.next();
+                        info!("tonemapper: {}", self.tonemapper.name());
+                        self.request_redraw();
+                    } else {
+                        log::warn!(
+                            "Tonemapping is unavailable without --post-effect tonemap or bloom"
+                        );
+                    }
+                }
+                Action::RaiseExposure | Action::LowerExposure => {
+                    if self.has_tonemapping() {
+                        let step = if action == Action::RaiseExposure {
+                            tonemap::EXPOSURE_STEP
+                        } else {
+                            -tonemap::EXPOSURE_STEP
+                        };
+
+                        self.exposure = (self.exposure + step)
+                            .clamp(-tonemap::MAX_EXPOSURE, tonemap::MAX_EXPOSURE);
+                        info!("exposure: {:+.1} EV", self.exposure);
+                        self.request_redraw();
+                    } else {
+                        log::warn!(
+                            "Exposure is unavailable without --post-effect tonemap or bloom"
+                        );
+                    }
+                }
+                Action::ToggleNormalMapping => {
+                    if self.normal_map.is_some() {
+                        self.normal_mapping_enabled = !self.normal_mapping_enabled;
+                        info!("normal mapping: {}", self.normal_mapping_enabled);
+                        self.request_redraw();
+                    } else {
+                        log::warn!("Normal mapping is unavailable without --normal-mapping");
+                    }
+                }
+                Action::ToggleAnimation
+                | Action::CycleAnimation
+                | Action::RaiseAnimationSpeed
+                | Action::LowerAnimationSpeed => {
+                    if let Some(animator) = &mut self.animator {
+                        match action {
+                            Action::ToggleAnimation => animator.toggle_playing(),
+                            Action::CycleAnimation => animator.next_animation(),
+                            Action::RaiseAnimationSpeed => {
+                                animator.change_speed(animation::SPEED_STEP)
+                            }
+                            _ => animator.change_speed(-animation::SPEED_STEP),
+                        }
+
+                        info!(
+                            "animation: {} x{:.2}{}",
+                            animator
+                                .animation()
+                                .map_or("none", |animation| animation.name.as_str()),
+                            animator.speed(),
+                            if animator.is_playing() {
+                                ""
+                            } else {
+                                " (paused)"
+                            }
+                        );
+                        self.request_redraw();
+                    } else {
+                        log::warn!("Animation is unavailable without --gltf");
+                    }
+                }
+                Action::RaiseFrameLimit | Action::LowerFrameLimit => {
+                    if action == Action::RaiseFrameLimit {
+                        self.frame_limiter.raise();
+                    } else {
+                        self.frame_limiter.lower();
+                    }
+
+                    self.frame_stats
+                        .set_target_frame_time(self.frame_limiter.target_frame_time());
+
+                    info!("target fps: {:?}", self.frame_limiter.target_fps());
+                }
+                Action::TogglePause => {
+                    let paused = !self.frame_clock.is_paused();
+                    self.frame_clock.set_paused(paused);
+                    self.frame_advance_requested = false;
+                    info!("paused: {}", paused);
+                    self.request_redraw();
+                }
+                Action::AdvanceFrame => {
+                    if self.frame_clock.is_paused() {
+                        self.frame_advance_requested = true;
+                        self.request_redraw();
+                    }
+                }
+                Action::SaveScene => self.save_scene(),
+                Action::LoadScene => self.load_scene(),
+                Action::ToggleOverlay => match &mut self.egui_overlay {
+                    Some(egui_overlay) => {
+                        egui_overlay.toggle();
+                        self.request_redraw();
+                    }
+                    None => log::warn!(
+                        "The overlay is unavailable with --raytrace, --record-threads or --display"
+                    ),
+                },
+            }
+        }
+
+        false
+    }
+
+    //入力をもとにカメラを動かし、固定間隔でシミュレーションを進める
+    //cursor_deltaはend_frameで消えるのでその前に呼ぶ
+    //ディスプレイに直接表示している場合はwindowがNone
+    fn update(&mut self, window: Option<&Window>) {
+        self.frame_clock.tick();
+
+        //一時停止中はシミュレーションの時間が進まないので、要求された時だけ1回分進める
+        if std::mem::take(&mut self.frame_advance_requested) {
+            self.frame_clock.step(FIXED_TIMESTEP);
+        }
+
+        if let Some(window) = window {
+            self.update_cursor_grab(window);
+        }
+
+        //カメラは入力に対する応答性を優先して可変のフレーム時間で動かす
+        //一時停止中も止めた場面を見て回れるように動かす
+        //--benchでは入力を使わず、フレーム番号で決まる軌道に置く
+        if let Some(benchmark) = &mut self.benchmark {
+            benchmark.update_camera(&mut self.camera);
+        } else if self.camera.update(
+            &self.input,
+            &self.input_map,
+            self.frame_clock.delta_seconds(),
+        ) {
+            self.request_redraw();
+        }
+
+        self.simulate();
+    }
+
+    //固定間隔でモデルとスプライトを動かす、一時停止中はFrameClockが時間を進めないので何もしない
+    fn simulate(&mut self) {
+        //スプライトは正射影で画面に映る範囲の端で跳ね返る
+        let aspect_ratio =
+            self.swap_chain_extent.width as f32 / self.swap_chain_extent.height as f32;
+        let sprite_bounds =
+            Camera::orthographic_half_extents(self.orthographic_height, aspect_ratio);
+
+        self.frame_clock.update(FIXED_TIMESTEP, |dt| {
+            self.model_rotation += MODEL_ROTATION_SPEED * dt.as_secs_f32();
+            self.light_manager.animate(dt.as_secs_f32());
+
+            if let Some(animator) = &mut self.animator {
+                animator.advance(dt.as_secs_f32());
+            }
+
+            if let Some(sprite_demo) = &mut self.sprite_demo {
+                sprite_demo.update(dt.as_secs_f32(), sprite_bounds);
+            }
+        });
+    }
+
+    //右ボタンを押している間はカーソルを隠してウィンドウに固定する
+    //フォーカスを失った場合もInputStateがボタンを離したことにするのでここで解放される
+    fn update_cursor_grab(&mut self, window: &Window) {
+        let grab = if self.input.is_mouse_just_pressed(MouseButton::Right) {
+            true
+        } else if self.input.is_mouse_just_released(MouseButton::Right) {
+            false
+        } else {
+            return;
+        };
+
+        if grab == self.cursor_grabbed {
+            return;
+        }
+
+        //プラットフォームによってはカーソルの固定に対応していないがマウスの移動量は取れるので無視する
+        if let Err(error) = window.set_cursor_grab(grab) {
+            log::warn!("Failed to set cursor grab: {}", error);
+        }
+        window.set_cursor_visible(!grab);
+
+        self.cursor_grabbed = grab;
+    }
+
+    //uniform_buffersのこのフレームのバッファにcameraから見た行列を書き込む
+    fn update_uniform_buffer(&self, uniform_buffers: &UniformBuffers, camera: &Camera) {
+        let current_frame = self.current_frame;
+        let aspect_ratio = self.view_aspect_ratio();
+
+        let ubo = UniformBufferObject {
+            view: camera.view_matrix(),
+            proj: camera.projection_matrix(aspect_ratio),
+            //0は未使用を表すので1から始める
+            light_view_proj: ShadowMap::light_view_proj(),
+            frame_index: (self.frame_count as u32).wrapping_add(1).max(1),
+            shadow_texel_size: self.shadow_map.as_ref().map_or(0.0, ShadowMap::texel_size),
+            near: camera.near,
+            far: camera.far,
+            far_depth: camera.depth.far_depth(),
+            _padding: [0; 3],
+        };
+
+        uniform_buffers.update(current_frame, &ubo);
+
+        let lights = self.light_manager.lights();
+        let light = LightUniforms::new(camera.position, self.lighting_mode, lights.len() as u32);
+
+        //light_cullingはsplit_screenの場合に作らないので、ここに来るのはself.uniform_buffersだけ
+        let light = match &self.light_culling {
+            Some(_) => LightUniforms {
+                tile_count_x: LightCulling::tile_count(self.render_extent()).0,
+                tile_stride: uniform_buffers.tile_stride(),
+                ..light
+            },
+            None => light,
+        };
+
+        uniform_buffers.update_light(current_frame, &light, lights);
+    }
+
+    //グラフィックスとコンピュートのタイムスタンプを読んでFrameStatsに記録する
+    fn read_gpu_timings(&mut self) {
+        let graphics_range = self
+            .gpu_timer
+            .as_mut()
+            .and_then(|gpu_timer| gpu_timer.read_range_ns(&self.device, self.current_frame));
+
+        if let Some((begin, end)) = graphics_range {
+            self.frame_stats
+                .record_gpu_time((end - begin) / 1_000_000.0);
+        }
+
+        if let Some(compute_queue) = &mut self.compute_queue {
+            if let Some((begin, end)) =
+                compute_queue.read_range_ns(&self.device, self.current_frame)
+            {
+                //コンピュートは1つ前のフレームの描画と並列に動くので、前後どちらのフレームとも重なりうる
+                let overlap = [self.previous_graphics_range, graphics_range]
+                    .iter()
+                    .flatten()
+                    .map(|(graphics_begin, graphics_end)| {
+                        (end.min(*graphics_end) - begin.max(*graphics_begin)).max(0.0)
+                    })
+                    .sum::<f64>();
+
+                self.frame_stats
+                    .record_compute_time((end - begin) / 1_000_000.0, overlap / 1_000_000.0);
+            }
+        }
+
+        if graphics_range.is_some() {
+            self.previous_graphics_range = graphics_range;
+        }
+    }
+
+    //専用のコンピュートキューがある場合はパーティクルの更新をsubmitし、グラフィックスで待つSemaphoreを返す
+    fn submit_async_compute(&mut self) -> Result<Option<vk::Semaphore>, vk::Result> {
+        let (compute_queue, particles) = match (&mut self.compute_queue, &self.particles) {
+            (Some(compute_queue), Some(particles)) => (compute_queue, particles),
+            _ => return Ok(None),
+        };
+
+        let command_buffer = compute_queue.begin(&self.device, self.current_frame);
+
+        self.crash_diagnostics
+            .cmd_checkpoint(command_buffer, Checkpoint::Compute);
+
+        particles.cmd_update(
+            &self.device,
+            &self.synchronization,
+            command_buffer,
+            self.current_frame,
+            self.frame_clock.simulation_delta_seconds(),
+            false,
+        );
+
+        compute_queue
+            .submit(&self.device, &self.synchronization, self.current_frame)
+            .map(Some)
+    }
+
+    //オブジェクトごとのモデル行列を書き込む
+    //--quad-gridの場合は四角形をXY平面上に並べ、それぞれ少しずつずらした角度で回転させる
+    fn update_object_buffer(&self, current_frame: usize) {
+        if let Some(transparent_quads) = &self.transparent_quads {
+            transparent_quads.write_objects(&self.object_buffers, current_frame);
+        }
+
+        if let Some(ground) = &self.ground {
+            ground.write_object(&self.object_buffers, current_frame);
+        }
+
+        for index in 0..self.opaque_object_count {
+            let object = ObjectUniforms {
+                model: self.object_model(index),
+                color: Vec4::ONE,
+            };
+
+            self.object_buffers.write(current_frame, index, &object);
+        }
+    }
+
+    //self.meshを描画するindex番目のオブジェクトのモデル行列
+    fn object_model(&self, index: usize) -> Mat4 {
+        let size = match self.quad_grid {
+            Some(size) => size as usize,
+            None => return Mat4::from_rotation_y(self.model_rotation),
+        };
+
+        //グリッド全体が-1.0から1.0の範囲に収まるようにする
+        let spacing = 2.0 / size as f32;
+        let (x, y) = (index % size, index / size);
+
+        let translation = Vec3::new(
+            -1.0 + spacing * (x as f32 + 0.5),
+            -1.0 + spacing * (y as f32 + 0.5),
+            0.0,
+        );
+        let rotation = self.model_rotation + index as f32 * 0.1;
+
+        Mat4::from_translation(translation)
+            * Mat4::from_rotation_z(rotation)
+            * Mat4::from_scale(Vec3::splat(spacing * 0.8))
+    }
+
+    fn camera_frustum(&self) -> Frustum {
+        Self::frustum_of(&self.camera, self.view_aspect_ratio())
+    }
+
+    fn frustum_of(camera: &Camera, aspect_ratio: f32) -> Frustum {
+        Frustum::from_view_proj(
+            camera.culling_projection_matrix(aspect_ratio) * camera.view_matrix(),
+        )
+    }
+
+    //--split-screenでは1つのビューが画面の半分になる
+    fn view_aspect_ratio(&self) -> f32 {
+        match self.split_screen {
+            Some(_) => SplitScreen::aspect_ratio(self.swap_chain_extent),
+            None => self.swap_chain_extent.width as f32 / self.swap_chain_extent.height as f32,
+        }
+    }
+
+    //記録中のビューのカメラ
+    fn view_camera(&self) -> &Camera {
+        match (&self.split_screen, self.current_view) {
+            (Some(split_screen), 1) => split_screen.camera(),
+            _ => &self.camera,
+        }
+    }
+
+    //記録中のビューのこのフレームのUniform BufferのDescriptor Set、パイプラインのset = 0に紐づける
+    fn view_descriptor_set(&self) -> vk::DescriptorSet {
+        match (&self.split_screen, self.current_view) {
+            (Some(split_screen), 1) => split_screen
+                .uniform_buffers()
+                .descriptor_set(self.current_frame),
+            _ => self.uniform_buffers.descriptor_set(self.current_frame),
+        }
+    }
+
+    //シーンを描画するviewportとscissor、--split-screenでは左右の半分の2つ
+    fn scene_views(
+        &self,
+        viewport: vk::Viewport,
+        scissor: vk::Rect2D,
+    ) -> Vec<(vk::Viewport, vk::Rect2D)> {
+        match self.split_screen {
+            Some(_) => SplitScreen::viewports(scissor.extent).to_vec(),
+            None => vec![(viewport, scissor)],
+        }
+    }
+
+    //self.meshを描画するオブジェクトのうち視錐台と重なるもののインデックス
+    //床と半透明な四角形は数が少ないのでカリングしない
+    //--ubo-stressでは描画しないとGPUがframe_indexを書き込まずに確認が失敗するので全て残す
+    fn visible_objects(&self) -> Vec<usize> {
+        if self.ubo_stress {
+            return (0..self.opaque_object_count).collect();
+        }
+
+        let frustum = self.frozen_frustum.unwrap_or_else(|| self.camera_frustum());
+        //--split-screenではどちらかのビューに映る物を両方のビューで描画する
+        let split_frustum = self
+            .split_screen
+            .as_ref()
+            .map(|split_screen| Self::frustum_of(split_screen.camera(), self.view_aspect_ratio()));
+        let bounds = self.mesh.bounds();
+
+        (0..self.opaque_object_count)
+            .filter(|&index| {
+                let aabb = bounds.transform(self.object_model(index));
+                frustum.intersects_aabb(&aabb)
+                    || split_frustum.map_or(false, |frustum| frustum.intersects_aabb(&aabb))
+            })
+            .collect()
+    }
+
+    //RunMode::OnDemandの時に次のイベント処理後に1フレーム描画させる
+    pub fn request_redraw(&mut self) {
+        self.needs_redraw = true;
+    }
+
+    //--replayで次のフレームの入力とフレーム時間に置き換える、最後まで再生した場合はtrueを返す
+    //記録した時と同じフレーム数で同じ入力になるように、経過時間ではなくフレームの番号で進める
+    fn replay_input(&mut self) -> bool {
+        let session_player = match &mut self.session_player {
+            Some(session_player) => session_player,
+            None => return false,
+        };
+
+        match session_player.next_frame() {
+            Some(frame) => {
+                self.input.restore(&frame.input);
+                self.frame_clock.set_fixed_delta(Some(frame.delta));
+                false
+            }
+            None => {
+                info!("Replay finished");
+                true
+            }
+        }
+    }
+
+    //--recordでupdateの後、InputState::end_frameの前に呼ぶ
+    fn record_input(&mut self) {
+        if let Some(session_recorder) = &mut self.session_recorder {
+            session_recorder.record(self.frame_clock.delta(), self.input.snapshot());
+        }
+    }
+
+    //--benchで最後のフレームを描画し終えた場合は、集計を表とCSVで表示してtrueを返す
+    fn finish_benchmark(&mut self) -> bool {
+        let benchmark = match &mut self.benchmark {
+            Some(benchmark) => benchmark,
+            None => return false,
+        };
+
+        if !benchmark.end_frame() {
+            return false;
+        }
+
+        let report = benchmark.report(self.frame_stats.history().unwrap());
+        print!("{}\n{}", report, report.csv());
+
+        true
+    }
+
+    //1フレーム描画して計測結果をウィンドウのタイトルに反映する
+    //ディスプレイに直接表示している場合はwindowがNone
+    fn render(&mut self, window: Option<&Window>) {
+        match self.lost.take() {
+            Some(LostResource::Surface) => self.recover_surface(window),
+            Some(LostResource::Device) => self.recover_device(window),
+            None => {}
+        }
+
+        //最小化から戻った直後に遅れを取り戻そうと連続でフレームを出さないようにする
+        if window.map_or(false, Self::is_minimized) {
+            self.frame_limiter.reset();
+            self.frame_stats.reset_interval();
+            return;
+        }
+
+        self.frame_stats.begin_frame();
+        self.print_debug_text();
+        self.draw_sprites();
+        self.draw_frame(self.config.frames_in_flight as usize);
+        self.resize = None;
+
+        if let Some(report) = self.frame_stats.end_frame() {
+            //ディスプレイに直接表示している場合はタイトルバーが無いのでログに出す
+            let paused = if self.frame_clock.is_paused() {
+                " | PAUSED"
+            } else {
+                ""
+            };
+
+            match window {
+                Some(window) => window.set_title(&format!(
+                    "{}{} | {}",
+                    self.config.window_title, paused, report
+                )),
+                None => info!("{}{}", report, paused),
+            }
+
+            //ウィンドウタイトルでは1行に並べている項目を1行ずつにする
+            //メモリの使用量は行が多いのでオーバーレイにだけ表示する
+            self.frame_report_text = format!(
+                "{}\n{}",
+                report.to_string().replace(" | ", "\n"),
+                self.memory_budget
+                    .report(&self.instance, self.physical_device)
+            );
+
+            if let Some(counters) = self
+                .pipeline_statistics
+                .as_ref()
+                .and_then(|statistics| statistics.latest())
+            {
+                info!("Pipeline statistics: {}", counters);
+            }
+
+            if let Some(frame_pacer) = &self.frame_pacer {
+                info!(
+                    "Display timing: {} late, {} dropped frames",
+                    frame_pacer.late_frames(),
+                    frame_pacer.dropped_frames()
+                );
+            }
+        }
+
+        //CPUのフレーム時間に含めないようにend_frameの後で待機する
+        self.frame_limiter.wait();
+    }
+
+    //--async-assetsで転送し終えたメッシュに差し替え、GPUが使い終わった古いメッシュを破棄する
+    fn pump_asset_uploads(&mut self) -> Result<(), vk::Result> {
+        let asset_uploader = match &mut self.asset_uploader {
+            Some(asset_uploader) => asset_uploader,
+            None => return Ok(()),
+        };
+
+        let completed_frames = self.frame_timeline.value(&self.device)?;
+        let device = &self.device;
+        self.retired_meshes.retain(|(mesh, last_frame)| {
+            let unused = *last_frame <= completed_frames;
+
+            if unused {
+                mesh.destroy(device);
+            }
+
+            !unused
+        });
+
+        if let Some((mesh, value)) = asset_uploader.pump(
+            &self.instance,
+            self.physical_device,
+            &self.device,
+            &self.synchronization,
+        )? {
+            //まだsubmitしていないこのフレームからは新しいメッシュを使う
+            let placeholder = std::mem::replace(&mut self.mesh, mesh);
+            self.retired_meshes
+                .push((placeholder, self.submitted_frames));
+            self.mesh_upload_value = value;
+        }
+
+        Ok(())
+    }
+
+    //F1のオーバーレイのUIを組み立て、変えられた設定を反映する
+    //テクスチャの更新でGPUを待つことがあるので、コマンドの記録を始める前に呼ぶ
+    fn run_overlay(&mut self, window: &Window) {
+        if !self
+            .egui_overlay
+            .as_ref()
+            .map_or(false, EguiOverlay::is_visible)
+        {
+            return;
+        }
+
+        let interval_ms = self.frame_stats.average_interval_ms();
+        let info = OverlayInfo {
+            fps: if interval_ms > 0.0 {
+                1000.0 / interval_ms
+            } else {
+                0.0
+            },
+            gpu_ms: self.frame_stats.gpu_average_ms(),
+            msaa_available: self.is_msaa_adjustable(),
+            wireframe_available: self.wireframe_pipeline.is_some(),
+        };
+
+        let [r, g, b, _] = self.clear_color.rgba;
+        let current = OverlaySettings {
+            clear_color: [r, g, b].map(|value| (value * 255.0).round() as u8),
+            present_mode: self.swap_chain_settings.present_mode,
+            msaa_samples: self.msaa_samples.as_raw(),
+            wireframe: self.wireframe,
+        };
+        let mut settings = current;
+
+        let egui_overlay = self.egui_overlay.as_mut().unwrap();
+        let needs_repaint = egui_overlay.run(window, self.cursor_grabbed, &info, &mut settings);
+        egui_overlay.update_textures(
+            &self.instance,
+            self.physical_device,
+            &self.device,
+            &self.one_time_commands,
+            &self.synchronization,
+        );
+
+        if needs_repaint {
+            self.request_redraw();
+        }
+
+        if settings.clear_color != current.clear_color {
+            let [r, g, b] = settings.clear_color.map(|value| value as f32 / 255.0);
+            self.set_clear_color(ClearColor {
+                rgba: [r, g, b, self.clear_color.rgba[3]],
+            });
+        }
+
+        //PresentModeはswapchainの作成時に決まるので再作成する
+        if settings.present_mode != current.present_mode {
+            self.swap_chain_settings.present_mode = settings.present_mode;
+            self.recreate_swap_chain();
+            self.request_redraw();
+        }
+
+        if settings.msaa_samples != current.msaa_samples {
+            self.set_msaa_samples(settings.msaa_samples);
+        }
+
+        if settings.wireframe != current.wireframe {
+            self.wireframe = settings.wireframe;
+            info!("wireframe: {}", self.wireframe);
+            self.request_redraw();
+        }
+    }
+
+    //MSAAはシーンをswapchainの画像に直接描画する場合だけ使えるので、choose_msaa_samplesと同じ条件で変えられなくする
+    //render_scaleの倍率を変えている間もswapchainとは別の画像に描画している
+    fn is_msaa_adjustable(&self) -> bool {
+        self.ray_tracer.is_none() && self.post_process.is_none() && !self.is_render_scaled()
+    }
+
+    //デプスバッファとMSAAの画像、render passとpipelineのサンプル数が変わるので全て作り直す
+    fn set_msaa_samples(&mut self, requested: u32) {
+        if !self.is_msaa_adjustable() {
+            log::warn!("MSAA cannot be changed with post processing or a render scale");
+            return;
+        }
+
+        self.msaa_samples = msaa::choose_sample_count(
+            requested,
+            msaa::supported_sample_counts(&self.instance, self.physical_device),
+        );
+        info!("MSAA: {} samples", self.msaa_samples.as_raw());
+
+        self.recreate_swap_chain_with_scope(RecreateScope::Full);
+        self.request_redraw();
+    }
+
+    //このフレームのdebug_textに表示する文字を置く
+    fn print_debug_text(&mut self) {
+        //debug_textを借りている間はselfのメソッドを呼べないので先に決める
+        let has_tonemapping = self.has_tonemapping();
+
+        let debug_text = match &mut self.debug_text {
+            Some(debug_text) => debug_text,
+            None => return,
+        };
+
+        //余白も論理ピクセルで決めておく
+        let margin = (8.0 * self.scale_factor) as f32;
+
+        //--async-assetsの読み込みと転送の進捗は終わるまで表示する
+        let asset_progress = self
+            .asset_uploader
+            .as_ref()
+            .and_then(|asset_uploader| asset_uploader.progress())
+            .map_or(String::new(), |progress| format!("{}\n", progress));
+
+        //PageUp/PageDownで変えた値を確かめられるようにする
+        let bloom = match &self.bloom {
+            Some(bloom) => format!(
+                "bloom {:.1} ({} levels)\n",
+                self.bloom_intensity,
+                bloom.levels()
+            ),
+            None => String::new(),
+        };
+
+        let tonemap = if has_tonemapping {
+            format!(
+                "tonemap {} {:+.1} EV\n",
+                self.tonemapper.name(),
+                self.exposure
+            )
+        } else {
+            String::new()
+        };
+
+        //Mキーで切り替えた状態を確かめられるようにする
+        let normal_mapping = match (&self.normal_map, self.normal_mapping_enabled) {
+            (Some(_), true) => "normal mapping on\n",
+            (Some(_), false) => "normal mapping off\n",
+            (None, _) => "",
+        };
+
+        //アニメーションを持たないモデルは初期の姿勢のまま
+        let animation = match &self.animator {
+            Some(animator) => format!(
+                "animation {} ({} total) x{:.2}{}\n",
+                animator
+                    .animation()
+                    .map_or("none", |animation| animation.name.as_str()),
+                animator.animation_count(),
+                animator.speed(),
+                if animator.is_playing() { "" } else { " paused" }
+            ),
+            None => String::new(),
+        };
+
+        debug_text.print(
+            margin,
+            margin,
+            &format!(
+                "{}x{} {:?}\n{}{}{}{}{}{}",
+                self.swap_chain_extent.width,
+                self.swap_chain_extent.height,
+                self.swap_chain_color_format,
+                asset_progress,
+                bloom,
+                tonemap,
+                normal_mapping,
+                animation,
+                self.frame_report_text
+            ),
+        );
+    }
+
+    //このフレームのsprite_batchに描画するスプライトを置く
+    fn draw_sprites(&mut self) {
+        if let (Some(sprite_batch), Some(sprite_demo)) = (&mut self.sprite_batch, &self.sprite_demo)
+        {
+            sprite_demo.draw(sprite_batch);
+        }
+    }
+
+    //最小化されているとウィンドウの大きさが0になる
+    fn is_minimized(window: &Window) -> bool {
+        let size = window.inner_size();
+
+        size.width == 0 || size.height == 0
+    }
+
+    //次のフレームから反映される
+    pub fn set_clear_color(&mut self, clear_color: ClearColor) {
+        self.clear_color = clear_color;
+        self.animate_clear_color = false;
+        self.request_redraw();
+    }
+
+    //swapchainのフォーマットの候補を変更する
+    //次回のswapchainの再作成から反映される
+    pub fn set_surface_format_preferences(&mut self, preferences: Vec<vk::SurfaceFormatKHR>) {
+        self.swap_chain_settings.surface_formats = preferences;
+    }
+
+    //HDRの切り替えなどformatが変わる可能性がある変更をすぐに反映する
+    pub fn rebuild_swap_chain(&mut self) {
+        self.recreate_swap_chain_with_scope(RecreateScope::Full);
+    }
+
+    //SRGBフォーマットでハードウェアにエンコードさせるか、UNORMフォーマットでシェーダーでエンコードするかを切り替える
+    //正しく動いていれば見た目は変わらず、どちらかがずれている場合は明るさが変わって見える
+    fn toggle_gamma_mode(&mut self) {
+        let preference = match self.swap_chain_color_format {
+            ColorFormat::ManualSrgb(_) => SurfaceFormatPreference::Srgb,
+            ColorFormat::Srgb(_) | ColorFormat::Linear(_) | ColorFormat::Pq(_) => {
+                SurfaceFormatPreference::Unorm
+            }
+        };
+
+        let previous_format = self.swap_chain_color_format;
+
+        self.set_surface_format_preferences(preference.formats());
+        self.rebuild_swap_chain();
+
+        if self.swap_chain_color_format == previous_format {
+            log::warn!(
+                "{:?} surface formats are not supported, gamma mode is unchanged",
+                preference
+            );
+        } else {
+            info!("swapchain color format: {:?}", self.swap_chain_color_format);
+        }
+    }
+
+    //ウィンドウ表示とフルスクリーンを切り替える
+    //VK_EXT_full_screen_exclusiveが使える場合は排他フルスクリーン、使えない場合はボーダーレスにする
+    fn toggle_fullscreen(&mut self, window: &Window) {
+        if self.fullscreen_mode != FullscreenMode::Windowed {
+            self.release_full_screen_exclusive();
+            self.swap_chain_settings.full_screen_exclusive_monitor = None;
+
+            window.set_fullscreen(None);
+            self.fullscreen_mode = FullscreenMode::Windowed;
+            self.recreate_swap_chain_with_size(window.inner_size().into());
+
+            info!("fullscreen mode: {:?}", self.fullscreen_mode);
+            return;
+        }
+
+        //排他フルスクリーンでもSurfaceとウィンドウの大きさを一致させる必要があるので先にボーダーレスにする
+        window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+        self.fullscreen_mode = FullscreenMode::Borderless;
+
+        let exclusive_monitor = self
+            .full_screen_exclusive
+            .as_ref()
+            .and(current_hmonitor(window));
+
+        if let Some(monitor) = exclusive_monitor {
+            self.swap_chain_settings.full_screen_exclusive_monitor = Some(monitor);
+            self.recreate_swap_chain_with_size(window.inner_size().into());
+
+            let result = unsafe {
+                self.full_screen_exclusive
+                    .as_ref()
+                    .unwrap()
+                    .acquire_full_screen_exclusive_mode(self.swap_chain_khr)
+            };
+
+            match result {
+                Ok(_) => {
+                    self.full_screen_exclusive_acquired = true;
+                    self.fullscreen_mode = FullscreenMode::Exclusive;
+                }
+                Err(error) => {
+                    //排他モードの取得に失敗した場合は通常のswapchainに戻してボーダーレスのままにする
+                    log::warn!(
+                        "Failed to acquire full screen exclusive mode, using borderless: {}",
+                        error
+                    );
+                    self.swap_chain_settings.full_screen_exclusive_monitor = None;
+                    self.recreate_swap_chain_with_size(window.inner_size().into());
+                }
+            }
+        } else {
+            self.recreate_swap_chain_with_size(window.inner_size().into());
+        }
+
+        info!("fullscreen mode: {:?}", self.fullscreen_mode);
+    }
+
+    //排他フルスクリーンを取得していれば解放する
+    fn release_full_screen_exclusive(&mut self) {
+        if !self.full_screen_exclusive_acquired {
+            return;
+        }
+
+        if let Some(full_screen_exclusive) = &self.full_screen_exclusive {
+            if let Err(error) = unsafe {
+                full_screen_exclusive.release_full_screen_exclusive_mode(self.swap_chain_khr)
+            } {
+                log::warn!("Failed to release full screen exclusive mode: {}", error);
+            }
+        }
+
+        self.full_screen_exclusive_acquired = false;
+    }
+
+    //排他フルスクリーンが失われた場合はボーダーレスとして描画を続ける
+    fn on_full_screen_exclusive_lost(&mut self) {
+        log::warn!("Full screen exclusive mode lost, falling back to borderless");
+
+        self.full_screen_exclusive_acquired = false;
+        self.swap_chain_settings.full_screen_exclusive_monitor = None;
+        self.fullscreen_mode = FullscreenMode::Borderless;
+        self.recreate_swap_chain();
+    }
+
+    //ウィンドウの大きさが変わった直後はResizedイベントより先にswapchainを作り直したいので大きさを指定する
+    fn recreate_swap_chain_with_size(&mut self, size: (u32, u32)) {
+        self.resize = Some(size);
+        self.recreate_swap_chain();
+    }
+
+    pub fn recreate_swap_chain(&mut self) {
+        self.recreate_swap_chain_with_scope(RecreateScope::SwapchainOnly);
+    }
+
+    //scopeは最低限作り直す範囲で、swapchainのformatが変わった場合はFullに広げる
+    fn recreate_swap_chain_with_scope(&mut self, scope: RecreateScope) {
+        //最小化対応
+        //最小化時にここで待機させることによって対応させる
+        //今の構成だと出来ない気もする
+        // if let Some(size) = self.resize {
+        //     while size.0 == 0 || size.1 == 0 {
+        //         self.run();
+        //     }
+        // }
+
+        //swapchainが使用されている時に触るのは良くないのでdeviceがidle状態になるのを待つ
+        unsafe { self.device.device_wait_idle().unwrap() };
+
+        let started_at = Instant::now();
+
+        self.cleanup_swap_chain();
+        let scope = self.create_swap_chain_after_cleanup(scope);
+
+        info!(
+            "Recreated swapchain ({:?}) in {:.2} ms",
+            scope,
+            started_at.elapsed().as_secs_f64() * 1000.0
+        );
+    }
+
+    //cleanup_swap_chainの後にswapchainとそれに依存するものを作り直す
+    //実際に作り直した範囲を返す
+    fn create_swap_chain_after_cleanup(&mut self, scope: RecreateScope) -> RecreateScope {
+        let previous_format = self.swap_chain_color_format;
+
+        self.create_swap_chain_resources();
+
+        //フォーマットが同じでも値の空間が変わるとシェーダーを選び直す必要がある
+        let scope = if self.swap_chain_color_format != previous_format {
+            RecreateScope::Full
+        } else {
+            scope
+        };
+
+        //viewportとscissor rectはdynamic stateなのでリサイズではpipelineを作り直さなくて良い
+        //render passはswapchain imageのformatに依存するのでformatが変わった場合だけ再作成する
+        if scope == RecreateScope::Full {
+            self.cleanup_pipelines();
+            self.create_pipelines();
+        }
+
+        let render_extent = self.render_extent();
+
+        if let Some(post_process) = &mut self.post_process {
+            post_process.create_targets(
+                &self.instance,
+                self.physical_device,
+                &self.device,
+                render_extent,
+                &self.depth_buffer,
+            );
+        }
+
+        if let Some(bloom) = &mut self.bloom {
+            bloom.create_targets(
+                &self.instance,
+                self.physical_device,
+                &self.device,
+                render_extent,
+            );
+        }
+
+        if let Some(overdraw_counters) = &mut self.overdraw_counters {
+            overdraw_counters.create_buffers(
+                &self.instance,
+                self.physical_device,
+                &self.device,
+                render_extent,
+            );
+        }
+
+        if self.light_culling.is_some() {
+            let (tile_count_x, tile_count_y) = LightCulling::tile_count(render_extent);
+            self.uniform_buffers.resize_light_tiles(
+                &self.instance,
+                self.physical_device,
+                &self.device,
+                tile_count_x * tile_count_y,
+            );
+        }
+
+        if let Some(render_scale) = &mut self.render_scale {
+            render_scale.create_target(
+                &self.instance,
+                self.physical_device,
+                &self.device,
+                self.swap_chain_image_format,
+                self.swap_chain_extent,
+                &self.depth_buffer,
+            );
+        }
+
+        if let Some(ray_tracer) = &mut self.ray_tracer {
+            ray_tracer.create_storage_image(
+                &self.instance,
+                self.physical_device,
+                &self.device,
+                self.swap_chain_extent,
+            );
+        }
+
+        //swapchainに依存するので再作成
+        //render_scaleのtargetに描画する場合はswapchainの画像にblitするだけなので作らない
+        if self.dynamic_rendering.is_none() && !self.is_render_scaled() {
+            self.swap_chain_frame_buffers = Self::create_frame_buffers(
+                &self.device,
+                self.render_pass,
+                self.swap_chain_image_views.clone(),
+                self.depth_buffer.view,
+                self.msaa_target
+                    .as_ref()
+                    .map(|msaa_target| msaa_target.view),
+                self.swap_chain_extent,
+            );
+        }
+
+        scope
+    }
+
+    //swapchainとそれに紐づくimageとimage_viewを作成する
+    fn create_swap_chain_resources(&mut self) {
+        let (width, height) = self
+            .resize
+            .unwrap_or((self.swap_chain_extent.width, self.swap_chain_extent.height));
+
+        info!("width: {}, height: {}", width, height);
+
+        let (swap_chain, swap_chain_khr, swap_chain_color_format, swap_chain_extent) =
+            Self::create_swap_chain(
+                &self.instance,
+                &self.device,
+                self.physical_device,
+                &self.surface,
+                self.surface_khr,
+                (width, height),
+                &self.swap_chain_settings,
+            );
+
+        self.swap_chain = swap_chain;
+        self.swap_chain_khr = swap_chain_khr;
+        self.swap_chain_image_format = swap_chain_color_format.format();
+        self.swap_chain_color_format = swap_chain_color_format;
+        self.swap_chain_extent = swap_chain_extent;
+
+        if let Some(frame_pacer) = &mut self.frame_pacer {
+            frame_pacer.on_swap_chain_created(self.swap_chain_khr);
+        }
+
+        self.swap_chain_images = Self::get_swap_chain_images(&self.swap_chain, self.swap_chain_khr);
+        self.images_in_flight = vec![0; self.swap_chain_images.len()];
+
+        //image_viewはswapchainに紐づいているので再作成しなければいけない
+        self.swap_chain_image_views = Self::create_image_views(
+            &self.device,
+            &self.swap_chain_images,
+            self.swap_chain_image_format,
+        );
+
+        //デプスバッファはシーンを描画する解像度に合わせる
+        self.depth_buffer = DepthBuffer::new(
+            &self.instance,
+            self.physical_device,
+            &self.device,
+            self.depth_buffer.format,
+            self.render_extent(),
+            self.msaa_samples,
+        );
+
+        //resolve先のswapchainの画像と同じフォーマットと大きさにする
+        self.msaa_target = (self.msaa_samples != vk::SampleCountFlags::TYPE_1).then(|| {
+            MsaaTarget::new(
+                &self.instance,
+                self.physical_device,
+                &self.device,
+                self.swap_chain_image_format,
+                self.swap_chain_extent,
+                self.msaa_samples,
+            )
+        });
+    }
+
+    //swapchainの大きさにrender_scaleの倍率を掛けた、シーンを描画する解像度
+    fn render_extent(&self) -> vk::Extent2D {
+        self.render_scale
+            .as_ref()
+            .map_or(self.swap_chain_extent, |render_scale| {
+                render_scale.render_extent(self.swap_chain_extent)
+            })
+    }
+
+    //swapchainの代わりにrender_scaleのtargetに描画しているかどうか
+    fn is_render_scaled(&self) -> bool {
+        self.render_scale
+            .as_ref()
+            .map_or(false, |render_scale| render_scale.target().is_some())
+    }
+
+    //render passとそれに依存するpipelineを作成する
+    fn create_pipelines(&mut self) {
+        let render_target = match self.dynamic_rendering {
+            Some(_) => RenderTarget::Dynamic {
+                color_format: self.swap_chain_image_format,
+                depth_format: self.depth_buffer.format,
+                depth: self.config.depth,
+                samples: self.msaa_samples,
+            },
+            None => {
+                self.render_pass = Self::create_render_pass(
+                    &self.device,
+                    self.swap_chain_image_format,
+                    self.depth_buffer.format,
+                    self.msaa_samples,
+                );
+                RenderTarget::RenderPass(self.render_pass, self.config.depth, self.msaa_samples)
+            }
+        };
+
+        let scene_color_format =
+            Self::scene_color_format(self.swap_chain_color_format, self.post_process.is_some());
+        let scene_render_target =
+            Self::scene_render_target(render_target, self.post_process.as_ref());
+
+        let scene_descriptor_set_layouts = Self::scene_descriptor_set_layouts(
+            &self.uniform_buffers,
+            &self.object_buffers,
+            self.shadow_map.as_ref(),
+            self.ray_query_shadows.as_ref(),
+            self.material_textures.as_ref(),
+            self.vertex_pulling.as_ref(),
+            self.mesh_shading.as_ref(),
+            self.texture_array.as_ref(),
+            self.normal_map.as_ref(),
+            self.skinning.as_ref(),
+            self.overdraw_counters.as_ref(),
+        );
+
+        let (pipeline, wireframe_pipeline, pipeline_layout) = Self::create_graphics_pipeline(
+            &self.device,
+            self.pipeline_cache.handle(),
+            scene_render_target,
+            &scene_descriptor_set_layouts,
+            self.enabled_features.fill_mode_non_solid,
+            self.vertex_stage,
+            scene_color_format.shader_output(),
+        );
+
+        self.pipeline = pipeline;
+        self.wireframe_pipeline = wireframe_pipeline;
+        self.pipeline_layout = pipeline_layout;
+
+        self.debug_view_pipelines = Self::create_debug_view_pipelines(
+            &self.device,
+            self.pipeline_cache.handle(),
+            scene_render_target,
+            &scene_descriptor_set_layouts,
+            self.vertex_stage,
+            scene_color_format.shader_output(),
+        );
+
+        self.overdraw_pipelines = self.overdraw_counters.as_ref().map(|overdraw_counters| {
+            Self::create_overdraw_pipelines(
+                &self.device,
+                self.pipeline_cache.handle(),
+                scene_render_target,
+                &scene_descriptor_set_layouts,
+                overdraw_counters,
+                scene_color_format.shader_output(),
+            )
+        });
+
+        self.light_tiles_pipeline = self.light_culling.as_ref().map(|_| {
+            Self::create_light_tiles_pipeline(
+                &self.device,
+                self.pipeline_cache.handle(),
+                scene_render_target,
+                self.uniform_buffers.descriptor_set_layout(),
+                scene_color_format.shader_output(),
+            )
+        });
+
+        self.pulling_pipeline = self.vertex_pulling.as_ref().map(|_| {
+            Self::create_pulling_pipeline(
+                &self.device,
+                self.pipeline_cache.handle(),
+                scene_render_target,
+                &scene_descriptor_set_layouts,
+                scene_color_format.shader_output(),
+            )
+        });
+
+        self.ray_query_pipeline = self.ray_query_shadows.as_ref().map(|_| {
+            Self::create_ray_query_pipeline(
+                &self.device,
+                self.pipeline_cache.handle(),
+                scene_render_target,
+                &scene_descriptor_set_layouts,
+                scene_color_format.shader_output(),
+            )
+        });
+
+        self.tessellation_pipeline = self.tessellated_plane.as_ref().map(|_| {
+            Self::create_graphics_pipeline(
+                &self.device,
+                self.pipeline_cache.handle(),
+                scene_render_target,
+                &[self.uniform_buffers.descriptor_set_layout()],
+                self.enabled_features.fill_mode_non_solid,
+                VertexStage::Tessellated,
+                scene_color_format.shader_output(),
+            )
+        });
+
+        if self.enabled_features.geometry_shader {
+            self.normals_pipeline = Some(Self::create_normals_pipeline(
+                &self.device,
+                self.pipeline_cache.handle(),
+                scene_render_target,
+                [
+                    self.uniform_buffers.descriptor_set_layout(),
+                    self.object_buffers.descriptor_set_layout(),
+                ],
+                scene_color_format.shader_output(),
+            ));
+        }
+
+        self.particle_pipeline = self.particles.as_ref().map(|_| {
+            Self::create_particle_pipeline(
+                &self.device,
+                self.pipeline_cache.handle(),
+                scene_render_target,
+                self.uniform_buffers.descriptor_set_layout(),
+                scene_color_format.shader_output(),
+            )
+        });
+
+        self.post_process_pipelines = Self::create_post_process_pipelines(
+            &self.device,
+            self.pipeline_cache.handle(),
+            render_target,
+            scene_render_target,
+            self.post_process.as_ref(),
+            self.swap_chain_color_format,
+        );
+
+        self.bloom_pipelines = Self::create_bloom_pipelines(
+            &self.device,
+            self.pipeline_cache.handle(),
+            self.bloom.as_ref(),
+            self.post_process.as_ref(),
+        );
+
+        self.occlusion_pipeline = self.occlusion_culling.as_ref().map(|_| {
+            Self::create_occlusion_pipeline(
+                &self.device,
+                self.pipeline_cache.handle(),
+                scene_render_target,
+                self.uniform_buffers.descriptor_set_layout(),
+                scene_color_format.shader_output(),
+            )
+        });
+
+        self.skybox_pipeline = self.skybox.as_ref().map(|skybox| {
+            Self::create_skybox_pipeline(
+                &self.device,
+                self.pipeline_cache.handle(),
+                scene_render_target,
+                self.uniform_buffers.descriptor_set_layout(),
+                skybox.descriptor_set_layout(),
+                scene_color_format.shader_output(),
+            )
+        });
+
+        self.debug_text_pipeline = self.debug_text.as_ref().map(|debug_text| {
+            Self::create_debug_text_pipeline(
+                &self.device,
+                self.pipeline_cache.handle(),
+                render_target,
+                debug_text.descriptor_set_layout(),
+                self.swap_chain_color_format.shader_output(),
+            )
+        });
+
+        self.egui_pipeline = self.egui_overlay.as_ref().map(|egui_overlay| {
+            Self::create_egui_pipeline(
+                &self.device,
+                self.pipeline_cache.handle(),
+                render_target,
+                egui_overlay.descriptor_set_layout(),
+                self.swap_chain_color_format.shader_output(),
+            )
+        });
+
+        self.sprite_pipeline = self.sprite_batch.as_ref().map(|sprite_batch| {
+            Self::create_sprite_pipeline(
+                &self.device,
+                self.pipeline_cache.handle(),
+                scene_render_target,
+                self.uniform_buffers.descriptor_set_layout(),
+                sprite_batch.descriptor_set_layout(),
+                scene_color_format.shader_output(),
+            )
+        });
+
+        self.transparent_pipeline = self.transparent_quads.as_ref().map(|_| {
+            Self::create_transparent_pipeline(
+                &self.device,
+                self.pipeline_cache.handle(),
+                scene_render_target,
+                [
+                    self.uniform_buffers.descriptor_set_layout(),
+                    self.object_buffers.descriptor_set_layout(),
+                ],
+                scene_color_format.shader_output(),
+            )
+        });
+
+        //シャドウマップのrender passはswapchainに依存しないが、他のパイプラインと一緒に作り直す
+        self.shadow_pipeline = self.shadow_map.as_ref().map(|shadow_map| {
+            Self::create_shadow_pipeline(
+                &self.device,
+                self.pipeline_cache.handle(),
+                shadow_map,
+                [
+                    self.uniform_buffers.descriptor_set_layout(),
+                    self.object_buffers.descriptor_set_layout(),
+                ],
+            )
+        });
+    }
+
+    //メインのパイプラインのset = 0から順番のレイアウト
+    //シャドウマップ、マテリアルのテクスチャ、vertex pulling、法線マップのどれかを使う場合はset = 2に追加する、同時に使うことはない
+    //ray queryの影はシャドウマップのset = 3に追加し、シャドウマップのパイプラインとray queryのパイプラインで同じレイアウトを使う
+    //オーバードローを数えるバッファは他のset = 2を使わない場合だけset = 2に追加し、数えるパイプラインと同じレイアウトにする
+    #[allow(clippy::too_many_arguments)]
+    fn scene_descriptor_set_layouts(
+        uniform_buffers: &UniformBuffers,
+        object_buffers: &ObjectBuffers,
+        shadow_map: Option<&ShadowMap>,
+        ray_query_shadows: Option<&RayQueryShadows>,
+        material_textures: Option<&MaterialTextures>,
+        vertex_pulling: Option<&VertexPulling>,
+        mesh_shading: Option<&MeshShading>,
+        texture_array: Option<&TextureArray>,
+        normal_map: Option<&NormalMap>,
+        skinning: Option<&Skinning>,
+        overdraw_counters: Option<&OverdrawCounters>,
+    ) -> Vec<vk::DescriptorSetLayout> {
+        [
+            uniform_buffers.descriptor_set_layout(),
+            object_buffers.descriptor_set_layout(),
+        ]
+        .into_iter()
+        .chain(shadow_map.map(ShadowMap::descriptor_set_layout))
+        .chain(ray_query_shadows.map(RayQueryShadows::descriptor_set_layout))
+        .chain(material_textures.map(MaterialTextures::descriptor_set_layout))
+        .chain(vertex_pulling.map(VertexPulling::descriptor_set_layout))
+        .chain(mesh_shading.map(MeshShading::descriptor_set_layout))
+        .chain(texture_array.map(TextureArray::descriptor_set_layout))
+        .chain(normal_map.map(NormalMap::descriptor_set_layout))
+        .chain(skinning.map(Skinning::descriptor_set_layout))
+        .chain(overdraw_counters.map(OverdrawCounters::descriptor_set_layout))
+        .collect()
+    }
+
+    //メインのパイプラインと同じSPIR-Vから、main_fsの特殊化定数だけを変えたパイプラインを特殊化定数を使うDebugViewごとに作る
+    //ワイヤーフレームには対応しない
+    fn create_debug_view_pipelines(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        render_target: RenderTarget,
+        descriptor_set_layouts: &[vk::DescriptorSetLayout],
+        vertex_stage: VertexStage,
+        output: ColorEncoding,
+    ) -> Vec<(DebugView, vk::Pipeline, vk::PipelineLayout)> {
+        if !vertex_stage.has_debug_view() {
+            return vec![];
+        }
+
+        [DebugView::Normals, DebugView::Depth]
+            .into_iter()
+            .filter_map(|debug_view| Some((debug_view, debug_view.spec_constant()?)))
+            .map(|(debug_view, value)| {
+                let (pipeline, _, pipeline_layout) = Self::create_specialized_pipeline(
+                    device,
+                    pipeline_cache,
+                    render_target,
+                    descriptor_set_layouts,
+                    false,
+                    vertex_stage,
+                    output,
+                    &SpecConstants::new().set(DEBUG_VIEW_CONSTANT_ID, value),
+                );
+
+                (debug_view, pipeline, pipeline_layout)
+            })
+            .collect()
+    }
+
+    //(main_fs_overdrawで数えるパイプライン, main_fs_overdraw_heatmapで表示するパイプライン)
+    //数える方はpipelineと同じDescriptor Set Layoutから作るので、pipeline_layoutで紐づけたDescriptor Setをそのまま使える
+    //ワイヤーフレームには対応しない
+    fn create_overdraw_pipelines(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        render_target: RenderTarget,
+        descriptor_set_layouts: &[vk::DescriptorSetLayout],
+        overdraw_counters: &OverdrawCounters,
+        output: ColorEncoding,
+    ) -> (
+        (vk::Pipeline, vk::PipelineLayout),
+        (vk::Pipeline, vk::PipelineLayout),
+    ) {
+        let (count_pipeline, _, count_pipeline_layout) = Self::create_graphics_pipeline(
+            device,
+            pipeline_cache,
+            render_target,
+            descriptor_set_layouts,
+            false,
+            VertexStage::Overdraw,
+            output,
+        );
+
+        let (heatmap_pipeline, _, heatmap_pipeline_layout) = Self::create_graphics_pipeline(
+            device,
+            pipeline_cache,
+            render_target,
+            &[overdraw_counters.descriptor_set_layout()],
+            false,
+            VertexStage::OverdrawHeatmap,
+            output,
+        );
+
+        (
+            (count_pipeline, count_pipeline_layout),
+            (heatmap_pipeline, heatmap_pipeline_layout),
+        )
+    }
+
+    //main_fs_light_tiles_heatmapでシーンのパスの最後にタイルごとのライトの数を重ねる
+    //UniformBuffersのDescriptor Setだけを使うので、set = 0にそのまま紐づける
+    fn create_light_tiles_pipeline(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        render_target: RenderTarget,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+        output: ColorEncoding,
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        let (pipeline, _, pipeline_layout) = Self::create_graphics_pipeline(
+            device,
+            pipeline_cache,
+            render_target,
+            &[descriptor_set_layout],
+            false,
+            VertexStage::LightTilesHeatmap,
+            output,
+        );
+
+        (pipeline, pipeline_layout)
+    }
+
+    //main_vs_pulledでメインのパイプラインと同じ物を描画する
+    //ワイヤーフレームには対応しない
+    fn create_pulling_pipeline(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        render_target: RenderTarget,
+        descriptor_set_layouts: &[vk::DescriptorSetLayout],
+        output: ColorEncoding,
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        let (pipeline, _, pipeline_layout) = Self::create_graphics_pipeline(
+            device,
+            pipeline_cache,
+            render_target,
+            descriptor_set_layouts,
+            false,
+            VertexStage::Pulled,
+            output,
+        );
+
+        (pipeline, pipeline_layout)
+    }
+
+    //main_fs_ray_query_shadowedでシャドウマップのパイプラインと同じ物を描画する
+    //ワイヤーフレームには対応しない
+    fn create_ray_query_pipeline(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        render_target: RenderTarget,
+        descriptor_set_layouts: &[vk::DescriptorSetLayout],
+        output: ColorEncoding,
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        let (pipeline, _, pipeline_layout) = Self::create_graphics_pipeline(
+            device,
+            pipeline_cache,
+            render_target,
+            descriptor_set_layouts,
+            false,
+            VertexStage::RayQueryShadowed,
+            output,
+        );
+
+        (pipeline, pipeline_layout)
+    }
+
+    //set = 0のUniform Bufferとset = 1のモデル行列で、不透明なメッシュの頂点ごとに法線の線を描画する
+    fn create_normals_pipeline(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        render_target: RenderTarget,
+        descriptor_set_layouts: [vk::DescriptorSetLayout; 2],
+        output: ColorEncoding,
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        let (pipeline, _, pipeline_layout) = Self::create_graphics_pipeline(
+            device,
+            pipeline_cache,
+            render_target,
+            &descriptor_set_layouts,
+            false,
+            VertexStage::Normals,
+            output,
+        );
+
+        (pipeline, pipeline_layout)
+    }
+
+    //シーンを描画する画像のColorFormat
+    //ポストプロセスを使う場合はswapchainではなくオフスクリーンの中間画像に描画する
+    fn scene_color_format(swap_chain_color_format: ColorFormat, post_process: bool) -> ColorFormat {
+        if post_process {
+            swap_chain_color_format.intermediate()
+        } else {
+            swap_chain_color_format
+        }
+    }
+
+    //シーンを描画するパイプラインのRenderTarget、render_targetはswapchainに描画する場合のもの
+    //中間画像はswapchainとフォーマットが違うので、PostProcessのrender passかフォーマットで作る
+    //ポストプロセスをする場合はMSAAを使わないので1サンプル
+    fn scene_render_target(
+        render_target: RenderTarget,
+        post_process: Option<&PostProcess>,
+    ) -> RenderTarget {
+        match (post_process, render_target) {
+            (None, _) => render_target,
+            (
+                Some(_),
+                RenderTarget::Dynamic {
+                    depth_format,
+                    depth,
+                    ..
+                },
+            ) => RenderTarget::Dynamic {
+                color_format: color_space::INTERMEDIATE_FORMAT,
+                depth_format,
+                depth,
+                samples: vk::SampleCountFlags::TYPE_1,
+            },
+            (Some(post_process), RenderTarget::RenderPass(_, depth, _)) => {
+                RenderTarget::RenderPass(
+                    post_process.render_pass(),
+                    depth,
+                    vk::SampleCountFlags::TYPE_1,
+                )
+            }
+        }
+    }
+
+    //set = 0のUniform Bufferだけを使い、パーティクルを点で描画する
+    fn create_particle_pipeline(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        render_target: RenderTarget,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+        output: ColorEncoding,
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        let (pipeline, _, pipeline_layout) = Self::create_graphics_pipeline(
+            device,
+            pipeline_cache,
+            render_target,
+            &[descriptor_set_layout],
+            false,
+            VertexStage::Particles,
+            output,
+        );
+
+        (pipeline, pipeline_layout)
+    }
+
+    //set = 0のUniform Bufferのview行列から平行移動を除いて、set = 1のキューブマップを描画する
+    //一番遠い深度値で、等しい場合も通す深度テストをするので、不透明な物の後に描画して隠れる部分を省く
+    fn create_skybox_pipeline(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        render_target: RenderTarget,
+        uniform_descriptor_set_layout: vk::DescriptorSetLayout,
+        skybox_descriptor_set_layout: vk::DescriptorSetLayout,
+        output: ColorEncoding,
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        let (pipeline, _, pipeline_layout) = Self::create_graphics_pipeline(
+            device,
+            pipeline_cache,
+            render_target,
+            &[uniform_descriptor_set_layout, skybox_descriptor_set_layout],
+            false,
+            VertexStage::Skybox,
+            output,
+        );
+
+        (pipeline, pipeline_layout)
+    }
+
+    //set = 0のUniform Bufferのカメラの行列でpush constantのバウンディングボックスを描画する
+    //色も深度値も書き込まないので、OCCLUSIONクエリで数えるだけで描画結果には影響しない
+    fn create_occlusion_pipeline(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        render_target: RenderTarget,
+        uniform_descriptor_set_layout: vk::DescriptorSetLayout,
+        output: ColorEncoding,
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        let (pipeline, _, pipeline_layout) = Self::create_graphics_pipeline(
+            device,
+            pipeline_cache,
+            render_target,
+            &[uniform_descriptor_set_layout],
+            false,
+            VertexStage::OcclusionBox,
+            output,
+        );
+
+        (pipeline, pipeline_layout)
+    }
+
+    //set = 0のフォントのアトラスで、深度テストをせずにアルファブレンドする
+    //swapchainに描画するパスの中で使うのでoutputはswapchainのエンコード
+    fn create_debug_text_pipeline(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        render_target: RenderTarget,
+        debug_text_descriptor_set_layout: vk::DescriptorSetLayout,
+        output: ColorEncoding,
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        let (pipeline, _, pipeline_layout) = Self::create_graphics_pipeline(
+            device,
+            pipeline_cache,
+            render_target,
+            &[debug_text_descriptor_set_layout],
+            false,
+            VertexStage::Text,
+            output,
+        );
+
+        (pipeline, pipeline_layout)
+    }
+
+    //set = 0のeguiのテクスチャで、深度テストをせずにアルファを掛け済みの色で重ねる
+    //debug_textと同じくswapchainに描画するパスの中で使う
+    fn create_egui_pipeline(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        render_target: RenderTarget,
+        egui_descriptor_set_layout: vk::DescriptorSetLayout,
+        output: ColorEncoding,
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        let (pipeline, _, pipeline_layout) = Self::create_graphics_pipeline(
+            device,
+            pipeline_cache,
+            render_target,
+            &[egui_descriptor_set_layout],
+            false,
+            VertexStage::Egui,
+            output,
+        );
+
+        (pipeline, pipeline_layout)
+    }
+
+    //set = 0のUniform Bufferのカメラの行列でスプライトを映し、set = 1のテクスチャを貼る
+    //深度テストをせずにアルファブレンドする
+    fn create_sprite_pipeline(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        render_target: RenderTarget,
+        uniform_descriptor_set_layout: vk::DescriptorSetLayout,
+        sprite_descriptor_set_layout: vk::DescriptorSetLayout,
+        output: ColorEncoding,
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        let (pipeline, _, pipeline_layout) = Self::create_graphics_pipeline(
+            device,
+            pipeline_cache,
+            render_target,
+            &[uniform_descriptor_set_layout, sprite_descriptor_set_layout],
+            false,
+            VertexStage::Sprite,
+            output,
+        );
+
+        (pipeline, pipeline_layout)
+    }
+
+    //メインのパイプラインと同じset = 0とset = 1を使い、深度値を書き込まずにアルファブレンドする
+    fn create_transparent_pipeline(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        render_target: RenderTarget,
+        descriptor_set_layouts: [vk::DescriptorSetLayout; 2],
+        output: ColorEncoding,
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        let (pipeline, _, pipeline_layout) = Self::create_graphics_pipeline(
+            device,
+            pipeline_cache,
+            render_target,
+            &descriptor_set_layouts,
+            false,
+            VertexStage::Transparent,
+            output,
+        );
+
+        (pipeline, pipeline_layout)
+    }
+
+    //メインのパイプラインと同じset = 1のモデル行列とpush constantのライトの行列で深度値だけを書き込む
+    //set = 0は参照しないがメインのパイプラインと番号を揃えるために含める
+    fn create_shadow_pipeline(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        shadow_map: &ShadowMap,
+        descriptor_set_layouts: [vk::DescriptorSetLayout; 2],
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        //シーンの深度値の向きによらず、ライトの行列では通常の向きで描画する
+        let render_target = if shadow_map.render_pass() == vk::RenderPass::null() {
+            RenderTarget::Dynamic {
+                color_format: vk::Format::UNDEFINED,
+                depth_format: shadow_map::FORMAT,
+                depth: DepthConvention::Standard,
+                samples: vk::SampleCountFlags::TYPE_1,
+            }
+        } else {
+            RenderTarget::RenderPass(
+                shadow_map.render_pass(),
+                DepthConvention::Standard,
+                vk::SampleCountFlags::TYPE_1,
+            )
+        };
+
+        let (pipeline, _, pipeline_layout) = Self::create_graphics_pipeline(
+            device,
+            pipeline_cache,
+            render_target,
+            &descriptor_set_layouts,
+            false,
+            VertexStage::ShadowDepth,
+            //フラグメントシェーダーを使わないので関係ない
+            ColorEncoding::Linear,
+        );
+
+        (pipeline, pipeline_layout)
+    }
+
+    //effectごとにset = 0で前のパスの画像をサンプリングするパイプラインを作る
+    //最後のエフェクトだけがswapchainに書き込み、それ以外は中間画像に書き込む
+    //render_targetはswapchain、scene_render_targetは中間画像に描画する場合のもの
+    fn create_post_process_pipelines(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        render_target: RenderTarget,
+        scene_render_target: RenderTarget,
+        post_process: Option<&PostProcess>,
+        swap_chain_color_format: ColorFormat,
+    ) -> Vec<(vk::Pipeline, vk::PipelineLayout)> {
+        let post_process = match post_process {
+            Some(post_process) => post_process,
+            None => return vec![],
+        };
+
+        let count = post_process.effects().len();
+
+        post_process
+            .effects()
+            .iter()
+            .enumerate()
+            .map(|(index, &effect)| {
+                let (render_target, output) = if index + 1 == count {
+                    (render_target, swap_chain_color_format)
+                } else {
+                    (scene_render_target, swap_chain_color_format.intermediate())
+                };
+
+                //ブルームはset = 1でBloomのぼかした画像も読む
+                let descriptor_set_layouts = match effect {
+                    PostEffect::Bloom => vec![post_process.descriptor_set_layout(); 2],
+                    _ => vec![post_process.descriptor_set_layout()],
+                };
+
+                let (pipeline, _, pipeline_layout) = Self::create_graphics_pipeline(
+                    device,
+                    pipeline_cache,
+                    render_target,
+                    &descriptor_set_layouts,
+                    false,
+                    VertexStage::PostProcess(effect),
+                    output.shader_output(),
+                );
+
+                (pipeline, pipeline_layout)
+            })
+            .collect()
+    }
+
+    //BloomStageごとに、PostProcessと同じDescriptor Set Layoutで縮めた画像をサンプリングするパイプラインを作る
+    //Bloomの画像はswapchainと関係なくBLOOM_FORMATで、デプスバッファも使わない
+    fn create_bloom_pipelines(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        bloom: Option<&Bloom>,
+        post_process: Option<&PostProcess>,
+    ) -> Vec<(BloomStage, vk::Pipeline, vk::PipelineLayout)> {
+        let (bloom, post_process) = match (bloom, post_process) {
+            (Some(bloom), Some(post_process)) => (bloom, post_process),
+            _ => return vec![],
+        };
+
+        //デプスバッファを使わないので深度値の向きは関係ない
+        let render_target = if bloom.render_pass() == vk::RenderPass::null() {
+            RenderTarget::Dynamic {
+                color_format: bloom::BLOOM_FORMAT,
+                depth_format: vk::Format::UNDEFINED,
+                depth: DepthConvention::Standard,
+                samples: vk::SampleCountFlags::TYPE_1,
+            }
+        } else {
+            RenderTarget::RenderPass(
+                bloom.render_pass(),
+                DepthConvention::Standard,
+                vk::SampleCountFlags::TYPE_1,
+            )
+        };
+
+        BloomStage::ALL
+            .iter()
+            .map(|&stage| {
+                let descriptor_set_layouts =
+                    vec![post_process.descriptor_set_layout(); stage.descriptor_set_count()];
+
+                let (pipeline, _, pipeline_layout) = Self::create_graphics_pipeline(
+                    device,
+                    pipeline_cache,
+                    render_target,
+                    &descriptor_set_layouts,
+                    false,
+                    VertexStage::Bloom(stage),
+                    ColorEncoding::Linear,
+                );
+
+                (stage, pipeline, pipeline_layout)
+            })
+            .collect()
+    }
+
+    //swapchainをcleanupする
+    fn cleanup_swap_chain(&mut self) {
+        //排他モードはswapchainに紐づいているので破棄する前に解放する
+        self.release_full_screen_exclusive();
+
+        unsafe {
+            for framebuffer in self.swap_chain_frame_buffers.clone() {
+                self.device.destroy_framebuffer(framebuffer, None);
+            }
+
+            for image_view in self.swap_chain_image_views.clone() {
+                self.device.destroy_image_view(image_view, None);
+            }
+
+            self.swap_chain.destroy_swapchain(self.swap_chain_khr, None);
+        }
+
+        self.depth_buffer.destroy(&self.device);
+
+        if let Some(msaa_target) = self.msaa_target.take() {
+            msaa_target.destroy(&self.device);
+        }
+
+        //オフスクリーンの画像はswapchainと同じ大きさなので一緒に作り直す
+        if let Some(post_process) = &mut self.post_process {
+            post_process.destroy_targets(&self.device);
+        }
+
+        if let Some(bloom) = &mut self.bloom {
+            bloom.destroy_targets(&self.device);
+        }
+
+        if let Some(overdraw_counters) = &mut self.overdraw_counters {
+            overdraw_counters.destroy_buffers(&self.device);
+        }
+
+        if let Some(ray_tracer) = &mut self.ray_tracer {
+            ray_tracer.destroy_storage_image(&self.device);
+        }
+
+        if let Some(render_scale) = &mut self.render_scale {
+            render_scale.destroy_target(&self.device);
+        }
+    }
+
+    //render passとそれに依存するpipelineを破棄する
+    fn cleanup_pipelines(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
+            if let Some(wireframe_pipeline) = self.wireframe_pipeline {
+                self.device.destroy_pipeline(wireframe_pipeline, None);
+            }
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            if let Some((pipeline, pipeline_layout)) = self.particle_pipeline.take() {
+                self.device.destroy_pipeline(pipeline, None);
+                self.device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            if let Some((pipeline, pipeline_layout)) = self.occlusion_pipeline.take() {
+                self.device.destroy_pipeline(pipeline, None);
+                self.device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            if let Some((pipeline, pipeline_layout)) = self.skybox_pipeline.take() {
+                self.device.destroy_pipeline(pipeline, None);
+                self.device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            if let Some((pipeline, pipeline_layout)) = self.debug_text_pipeline.take() {
+                self.device.destroy_pipeline(pipeline, None);
+                self.device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            if let Some((pipeline, pipeline_layout)) = self.egui_pipeline.take() {
+                self.device.destroy_pipeline(pipeline, None);
+                self.device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            if let Some((pipeline, pipeline_layout)) = self.sprite_pipeline.take() {
+                self.device.destroy_pipeline(pipeline, None);
+                self.device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            if let Some((pipeline, pipeline_layout)) = self.transparent_pipeline.take() {
+                self.device.destroy_pipeline(pipeline, None);
+                self.device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            if let Some((pipeline, pipeline_layout)) = self.shadow_pipeline.take() {
+                self.device.destroy_pipeline(pipeline, None);
+                self.device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            if let Some((pipeline, pipeline_layout)) = self.pulling_pipeline.take() {
+                self.device.destroy_pipeline(pipeline, None);
+                self.device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            if let Some((pipeline, pipeline_layout)) = self.ray_query_pipeline.take() {
+                self.device.destroy_pipeline(pipeline, None);
+                self.device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            for (_, pipeline, pipeline_layout) in self.debug_view_pipelines.drain(..) {
+                self.device.destroy_pipeline(pipeline, None);
+                self.device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            if let Some((count_pipeline, heatmap_pipeline)) = self.overdraw_pipelines.take() {
+                for (pipeline, pipeline_layout) in [count_pipeline, heatmap_pipeline] {
+                    self.device.destroy_pipeline(pipeline, None);
+                    self.device.destroy_pipeline_layout(pipeline_layout, None);
+                }
+            }
+            if let Some((pipeline, pipeline_layout)) = self.light_tiles_pipeline.take() {
+                self.device.destroy_pipeline(pipeline, None);
+                self.device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            if let Some((pipeline, pipeline_layout)) = self.normals_pipeline.take() {
+                self.device.destroy_pipeline(pipeline, None);
+                self.device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            if let Some((pipeline, wireframe_pipeline, pipeline_layout)) =
+                self.tessellation_pipeline.take()
+            {
+                self.device.destroy_pipeline(pipeline, None);
+                if let Some(wireframe_pipeline) = wireframe_pipeline {
+                    self.device.destroy_pipeline(wireframe_pipeline, None);
+                }
+                self.device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            for (pipeline, pipeline_layout) in self.post_process_pipelines.drain(..) {
+                self.device.destroy_pipeline(pipeline, None);
+                self.device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            for (_, pipeline, pipeline_layout) in self.bloom_pipelines.drain(..) {
+                self.device.destroy_pipeline(pipeline, None);
+                self.device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            if self.render_pass != vk::RenderPass::null() {
+                self.device.destroy_render_pass(self.render_pass, None);
+            }
+        }
+    }
+
+    //Vulkanのローダーを読み込み、instanceを作る時に要求するバージョンと一緒に返す
+    //--infoでも同じように読み込む
+    pub fn load_entry() -> Result<(Entry, u32), Box<dyn Error>> {
+        let entry = unsafe { Entry::load() }.map_err(|error| {
+            format!(
+                "Failed to load the Vulkan loader: {}. {}",
+                error, LOADER_HINT
+            )
+        })?;
+
+        //vkEnumerateInstanceVersionが無いローダーはVulkan 1.0にしか対応していない
+        let loader_version = entry
+            .try_enumerate_instance_version()?
+            .unwrap_or_else(|| vk::make_api_version(0, 1, 0, 0));
+
+        info!(
+            "Vulkan loader version: {}",
+            device_info::api_version_string(loader_version)
+        );
+
+        //1.0のローダーはより新しいバージョンを要求するとERROR_INCOMPATIBLE_DRIVERになる
+        if loader_version < API_VERSION {
+            log::warn!(
+                "The Vulkan loader supports {}, requesting it instead of {}",
+                device_info::api_version_string(loader_version),
+                device_info::api_version_string(API_VERSION)
+            );
+        }
+
+        Ok((entry, loader_version.min(API_VERSION)))
+    }
+
+    //--infoでもウィンドウを作らずに同じinstanceを作る
+    pub fn create_instance(
+        entry: &Entry,
+        //load_entryが返したバージョン
+        api_version: u32,
+        display: bool,
+        //Noneの場合は検証レイヤーとDebugUtilsを有効にしない
+        validation: Option<ValidationSeverity>,
+        allocation_callbacks: Option<&vk::AllocationCallbacks>,
+    ) -> Result<Instance, Box<dyn Error>> {
+        let app_info = vk::ApplicationInfo::builder()
+            .application_name(CString::new("vulkan app")?.as_c_str())
+            .application_version(0)
+            .engine_name(CString::new("No Engine")?.as_c_str()) //エンジン名を入力するとそれが既知なエンジンだったらそれように最適化をする
+            .engine_version(0)
+            .api_version(api_version) //Vulkan自体のバージョン
+            .build();
+
+        //本家チュートリアルではgetRequiredExtensions(glfwGetRequiredInstanceExtensions)
+        let mut instance_config = khr_util::require_extension_names(display)
+            .into_iter()
+            .fold(InstanceConfig::new(), InstanceConfig::require_extension)
+            //FPSなどをウィンドウのタイトルに表示するレイヤー
+            .optional_layer("VK_LAYER_LUNARG_monitor")
+            //sRGB以外の色空間のsurfaceフォーマットを列挙できるようにする
+            .optional_extension(vk::ExtSwapchainColorspaceFn::name());
+
+        //検証レイヤーでのデバック時にコールバックを設定できるように拡張機能を有効にする
+        if validation.is_some() {
+            instance_config = REQUIRED_LAYERS
+                .iter()
+                .fold(instance_config, |config, name| config.require_layer(name))
+                //DebugUtils::name()がVK_EXT_DEBUG_UTILS_EXTENSION_NAME
+                .require_extension(DebugUtils::name());
+        }
+
+        let enabled_names = instance_config.resolve(entry)?;
+        let layer_names_ptrs = enabled_names.layer_ptrs();
+        let extension_names_ptrs = enabled_names.extension_ptrs();
+
+        //enabled_layer_countのセットはenabled_layer_namesの中に入っている
+        let mut instance_create_info = vk::InstanceCreateInfo::builder()
+            .application_info(&app_info)
+            .enabled_layer_names(&layer_names_ptrs)
+            .enabled_extension_names(&extension_names_ptrs);
+
+        //instanceの作成と破棄の間もメッセージを受け取れるようにする
+        //create_instanceを呼ぶまでp_nextが指す先を残しておく
+        let debug_create_info = validation.map(debug::populate_debug_messenger_create_info);
+
+        if let Some(debug_create_info) = &debug_create_info {
+            //勉強のために型の変換の遷移を書いているが as *const _ as _;でも可
+            instance_create_info.p_next =
+                debug_create_info as *const DebugUtilsMessengerCreateInfoEXT as *const c_void;
+        }
+
+        //要求したバージョンに対応するドライバが無い場合はERROR_INCOMPATIBLE_DRIVERになる
+        match unsafe { entry.create_instance(&instance_create_info, allocation_callbacks) } {
+            Ok(instance) => Ok(instance),
+            Err(vk::Result::ERROR_INCOMPATIBLE_DRIVER) => Err(format!(
+                "No Vulkan driver supports the requested API version {}. {}",
+                device_info::api_version_string(api_version),
+                LOADER_HINT
+            )
+            .into()),
+            Err(error) => Err(error.into()),
+        }
+        //基本的に本家で返り値がVkResultなものはResult型で値が包まれて返ってくるので引数も減る
+    }
+
+    fn pick_physical_device(
+        instance: &Instance,
+        surface: &Surface,
+        surface_khr: SurfaceKHR,
+        software_rendering: SoftwareRendering,
+        instance_api_version: u32,
+    ) -> Result<PhysicalDevice, Box<dyn Error>> {
+        let physical_devices = unsafe {
+            instance
+                .enumerate_physical_devices()
+                .expect("物理デバイスが取得できませんでした")
+        };
+
+        let suitable_devices = physical_devices
+            .into_iter()
+            .filter(|physical_device| {
+                QueueFamilyIndices::is_device_suitable(
+                    instance,
+                    surface,
+                    surface_khr,
+                    *physical_device,
+                    instance_api_version,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        //lavapipeやSwiftShaderのようなソフトウェアラスタライザはdevice_typeがCPUとして列挙される
+        let software_device = suitable_devices.iter().copied().find(|physical_device| {
+            let props = unsafe { instance.get_physical_device_properties(*physical_device) };
+            props.device_type == vk::PhysicalDeviceType::CPU
+        });
+
+        let physical_device = match (software_rendering, software_device) {
+            (SoftwareRendering::Prefer | SoftwareRendering::Require, Some(device)) => device,
+            (SoftwareRendering::Require, None) => {
+                return Err(
+                    "Software rendering was required but no CPU Vulkan device was found. \
+                     Install lavapipe (e.g. `apt install mesa-vulkan-drivers` on Debian/Ubuntu) \
+                     or point VK_ICD_FILENAMES at a SwiftShader ICD."
+                        .into(),
+                );
+            }
+            (mode, _) => {
+                if mode == SoftwareRendering::Prefer {
+                    log::warn!("No CPU Vulkan device found, falling back to a hardware device");
+                }
+
+                *suitable_devices
+                    .first()
+                    .ok_or("最適なPhysical Deviceが存在しません")?
+            }
+        };
+
+        let props = unsafe { instance.get_physical_device_properties(physical_device) };
+
+        info!(
+            "Selected physical device: {} ({:?}, vendor: {:#06x}, driver: {}, api: {})",
+            device_info::device_name(&props),
+            props.device_type,
+            props.vendor_id,
+            device_info::driver_version_string(&props),
+            device_info::api_version_string(props.api_version),
+        );
+
+        Ok(physical_device)
+    }
+
+    //論理デバイスを取得
+    #[allow(clippy::too_many_arguments)]
+    fn create_logical_device_and_queue(
+        instance: &Instance,
+        surface: &Surface,
+        surface_khr: SurfaceKHR,
+        physical_device: PhysicalDevice,
+        synchronization2_support: Synchronization2Support,
+        dynamic_rendering_support: DynamicRenderingSupport,
+        descriptor_indexing_support: DescriptorIndexingSupport,
+        //trueの場合はRayTracerの拡張と機能を有効にする
+        ray_tracing: bool,
+        //trueの場合はRayQueryShadowsの拡張と機能を有効にする、ray_tracingと同時にtrueにはならない
+        ray_query: bool,
+        //trueの場合はVK_KHR_push_descriptorを有効にする
+        push_descriptors: bool,
+        //trueの場合はMeshShadingの拡張と機能を有効にする
+        mesh_shading: bool,
+        //trueの場合は古い実装のためにデバイスにも検証レイヤーを指定する
+        validation: bool,
+        allocation_callbacks: Option<&vk::AllocationCallbacks>,
+    ) -> Result<(ash::Device, Queues, EnabledFeatures), Box<dyn Error>> {
+        let indices = QueueFamilyIndices::find_queue_families(
+            instance,
+            surface,
+            surface_khr,
+            physical_device,
+        );
+
+        //倫理デバイスが対応しているキューを取得する
+        //同じキューファミリーに対して複数のDeviceQueueCreateInfoを渡すことはできないのでまとめる
+        let queue_requests = QueueRequests::new(instance, physical_device, &indices);
+        let queue_create_info = queue_requests.create_infos();
+
+        //必須ではない機能はサポートされている場合のみ有効にする
+        let mut feature_request = DeviceFeatureRequest::new()
+            //is_device_suitableでサポートを確認している
+            .require(DeviceFeature::TimelineSemaphore)
+            .optional(DeviceFeature::PipelineStatisticsQuery)
+            //ワイヤーフレーム表示に使うPolygonMode::LINEに必要
+            .optional(DeviceFeature::FillModeNonSolid)
+            //法線を線で表示するジオメトリシェーダーに必要、MoltenVKなどでは対応していない
+            .optional(DeviceFeature::GeometryShader)
+            //--tessellationでテッセレーションの制御シェーダーと評価シェーダーを使うのに必要
+            .optional(DeviceFeature::TessellationShader)
+            //--ubo-stressで頂点シェーダーからstorage bufferに書き込むのに必要
+            .optional(DeviceFeature::VertexPipelineStoresAndAtomics)
+            //オーバードローの表示でフラグメントシェーダーからstorage bufferの数を増やすのに必要
+            .optional(DeviceFeature::FragmentStoresAndAtomics)
+            //--indirectで1回のcmd_draw_indexed_indirectに複数のドローをまとめるのに必要
+            .optional(DeviceFeature::MultiDrawIndirect)
+            //異方性フィルタリングに必要、無効な場合はSamplerCacheでmax_anisotropyを1.0にする
+            .optional(DeviceFeature::SamplerAnisotropy)
+            //--ktx2でBC7やASTCで圧縮されたテクスチャをそのまま使うのに必要
+            .optional(DeviceFeature::TextureCompressionBc)
+            .optional(DeviceFeature::TextureCompressionAstcLdr);
+
+        //以下は呼び出し側でサポートを確認してから有効にすると決めたもの
+        //Vulkan 1.3のコアでも機能として有効にする必要がある
+        if synchronization2_support != Synchronization2Support::Unsupported {
+            feature_request = feature_request.require(DeviceFeature::Synchronization2);
+        }
+
+        if dynamic_rendering_support != DynamicRenderingSupport::Unsupported {
+            feature_request = feature_request.require(DeviceFeature::DynamicRendering);
+        }
+
+        //main_fs_bindlessでpush constantの番号からテクスチャの配列を引くのに必要
+        if descriptor_indexing_support != DescriptorIndexingSupport::Unsupported {
+            feature_request = feature_request.require(DeviceFeature::DescriptorIndexing);
+        }
+
+        //SHADER_DEVICE_ADDRESSを付けたバッファのアドレスを取得するのに必要
+        if ray_tracing || ray_query {
+            feature_request = feature_request.require(DeviceFeature::BufferDeviceAddress);
+        }
+
+        if ray_tracing || ray_query {
+            feature_request = feature_request.require(DeviceFeature::AccelerationStructure);
+        }
+
+        if ray_tracing {
+            feature_request = feature_request.require(DeviceFeature::RayTracingPipeline);
+        }
+
+        if ray_query {
+            feature_request = feature_request.require(DeviceFeature::RayQuery);
+        }
+
+        if mesh_shading {
+            feature_request = feature_request.require(DeviceFeature::MeshShader);
+        }
+
+        //拡張はget_optional_device_extensionsで有効にするので、機能も拡張がある場合だけ要求する
+        if QueueFamilyIndices::is_device_extension_supported(
+            instance,
+            physical_device,
+            crash_diagnostics::device_fault_name(),
+        ) {
+            feature_request = feature_request.optional(DeviceFeature::DeviceFault);
+        }
+
+        let mut feature_chain = feature_request.resolve(instance, physical_device)?;
+        let enabled_features = feature_chain.enabled();
+
+        //任意のデバイス拡張はサポートされているものだけを有効にする
+        let optional_extensions = get_optional_device_extensions().into_iter().filter(|name| {
+            QueueFamilyIndices::is_device_extension_supported(instance, physical_device, name)
+        });
+
+        //どちらも加速構造の拡張を含むので、同時に有効にすると重複する
+        let ray_tracing_extensions = if ray_tracing {
+            RayTracer::extension_names()
+        } else if ray_query {
+            RayQueryShadows::extension_names()
+        } else {
+            Vec::new()
+        };
+
+        //呼び出し側で使うと決めた機能の拡張も、無い場合はデバイスを作らずにエラーにする
+        let required_extensions = get_required_device_extensions(instance, physical_device)
+            .into_iter()
+            .chain(synchronization2_support.extension_name())
+            .chain(dynamic_rendering_support.extension_name())
+            .chain(descriptor_indexing_support.extension_name())
+            .chain(ray_tracing_extensions)
+            .chain(push_descriptors.then(PushDescriptor::name))
+            .chain(mesh_shading.then(MeshShading::extension_name))
+            .collect::<Vec<_>>();
+
+        QueueFamilyIndices::check_device_extension_support(
+            instance,
+            physical_device,
+            &required_extensions,
+        )?;
+
+        let extension_names_ptr = required_extensions
+            .into_iter()
+            .chain(optional_extensions)
+            .map(|name| name.as_ptr())
+            .collect::<Vec<_>>();
+
+        //機能はPhysicalDeviceFeatures2で渡すので、enabled_featuresは指定しない
+        let mut create_info = vk::DeviceCreateInfo::builder()
+            .queue_create_infos(&queue_create_info)
+            .enabled_extension_names(&extension_names_ptr)
+            .push_next(feature_chain.features2());
+
+        let layer_names = REQUIRED_LAYERS
+            .iter()
+            .map(|name| CString::new(*name).expect("Failed to build CString"))
+            .collect::<Vec<_>>();
+        let layer_names_ptrs = layer_names
+            .iter()
+            .map(|name| name.as_ptr())
+            .collect::<Vec<_>>();
+
+        if validation {
+            create_info = create_info.enabled_layer_names(&layer_names_ptrs);
+        }
+
+        //存在しなかったりサポートされていない機能を有効にしようとするとエラーが出る
+        let device =
+            unsafe { instance.create_device(physical_device, &create_info, allocation_callbacks)? };
+
+        //論理デバイスからキューを作成
+        let queues = Queues::new(&device, &indices, &queue_requests);
+
+        Ok((device, queues, enabled_features))
+    }
+
+    //ディスプレイの場合は選んだ表示モードも返す
+    fn create_source_surface(
+        instance: &Instance,
+        entry: &Entry,
+        source: SurfaceSource,
+    ) -> Result<(Surface, SurfaceKHR, Option<DisplayMode>), Box<dyn Error>> {
+        match source {
+            SurfaceSource::Window(window) => {
+                let (surface, surface_khr) = Self::create_surface(instance, entry, window);
+                Ok((surface, surface_khr, None))
+            }
+            SurfaceSource::Display(choice) => {
+                let (surface_khr, mode) =
+                    display_surface::create_display_surface(entry, instance, choice)?;
+
+                info!(
+                    "display surface: {:?} ({}x{}, {} mHz)",
+                    surface_khr, mode.extent.width, mode.extent.height, mode.refresh_rate
+                );
+
+                Ok((Surface::new(entry, instance), surface_khr, Some(mode)))
+            }
+        }
+    }
+
+    fn create_surface(
+        instance: &Instance,
+        entry: &Entry,
+        window: &Window,
+    ) -> (Surface, SurfaceKHR) {
+        let surface = Surface::new(entry, instance);
+        let surface_khr =
+            unsafe { ash_window::create_surface(entry, instance, window, None).unwrap() };
+
+        info!("surface: {:?}", surface_khr);
+
+        (surface, surface_khr)
+    }
+
+    fn create_swap_chain(
+        instance: &Instance,
+        device: &Device,
+        physical_device: PhysicalDevice,
+        surface: &Surface,
+        surface_khr: SurfaceKHR,
+        window_size: (u32, u32),
+        settings: &SwapChainSettings,
+    ) -> (Swapchain, SwapchainKHR, ColorFormat, vk::Extent2D) {
+        let swap_chain_support =
+            SwapChainSupportDetails::new(physical_device, surface, surface_khr);
+
+        let surface_format =
+            swap_chain_support.choose_swap_surface_format(&settings.surface_formats);
+
+        info!(
+            "surface format: {:?}, color space: {:?}",
+            surface_format.format, surface_format.color_space
+        );
+        let present_mode = swap_chain_support.choose_swap_present_mode(settings.present_mode);
+
+        info!(
+            "present mode: {:?} (preference: {:?})",
+            present_mode, settings.present_mode
+        );
+        let extent = swap_chain_support.choose_swap_extent(window_size.0, window_size.1);
+
+        //2はダブルバッファリングで遅延が少なく、3はトリプルバッファリングでスループットが良い
+        let image_count = swap_chain_support.choose_image_count(settings.image_count);
+
+        info!(
+            "requested swapchain image count: {} (min: {}, max: {})",
+            image_count,
+            swap_chain_support.capabilities.min_image_count,
+            swap_chain_support.capabilities.max_image_count
+        );
+
+        //排他フルスクリーンの設定
+        //pNextに繋ぐためcreate_infoより長く生存させる
+        let mut full_screen_exclusive_info = vk::SurfaceFullScreenExclusiveInfoEXT::builder()
+            //APPLICATION_CONTROLLEDではacquire_full_screen_exclusive_modeを呼んだタイミングで排他モードになる
+            .full_screen_exclusive(vk::FullScreenExclusiveEXT::APPLICATION_CONTROLLED)
+            .build();
+        let mut full_screen_exclusive_win32_info =
+            vk::SurfaceFullScreenExclusiveWin32InfoEXT::builder()
+                .hmonitor(
+                    settings
+                        .full_screen_exclusive_monitor
+                        .unwrap_or(std::ptr::null_mut()),
+                )
+                .build();
+
+        let mut create_info = vk::SwapchainCreateInfoKHR::builder()
+            .surface(surface_khr)
+            .min_image_count(image_count)
+            .image_format(surface_format.format)
+            .image_color_space(surface_format.color_space)
+            .image_extent(extent)
+            //各画像が持つレイヤの数
+            //ステレオコピックアプリケーションなどを作成する時に使用
+            //スタン時の演出とかにも使える？
+            .image_array_layers(1)
+            //Swapchain内の画像をどのように扱うかを指定
+            //今回は直接レンダリングするのでCOLOR_ATTACHMENTを採用
+            //別の場所に画像をレンダリングしてあとからメモリ操作などで送信するTRANSFER_DSTなどもある
+            //RayTracerはstorage imageからblitするので、対応していればTRANSFER_DSTも付ける
+            //mirrorのウィンドウへはメインのswapchainの画像からblitするので、対応していればTRANSFER_SRCも付ける
+            .image_usage(
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | (swap_chain_support.capabilities.supported_usage_flags
+                        & (vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC)),
+            );
+
+        let indices = QueueFamilyIndices::find_queue_families(
+            instance,
+            surface,
+            surface_khr,
+            physical_device,
+        );
+
+        //キューファミリーのindexを配列に
+        let queue_family_indices = [
+            indices.graphics_family.unwrap(),
+            indices.present_family.unwrap(),
+        ];
+
+        //SwapChainが扱う画像が複数の種類のキューファミリーがまたがって使用するかどうかの設定
+        //今回の場合はグラフィックスファミリーとプレゼンテーションファミリーが同一のキューかどうかを調べてそれぞれ設定を確認する
+        if indices.graphics_family.unwrap() != indices.present_family.unwrap() {
+            //CONCURRENTは画像の所有権の移動なしに複数のキューファミリーをまたがって使用することができる
+            create_info = create_info
+                .image_sharing_mode(SharingMode::CONCURRENT)
+                //CONCURRENTではどのキューファミリー間で所有権を共有するかを事前にしているする必要がある
+                .queue_family_indices(&queue_family_indices);
+        } else {
+            //EXCLUSIVEは１つのキューファミリが所有権を持ち、複数のキューファミリーをまたがって使用する場合は明示的に所有権を移動しなければならない
+            //パフォーマンス的には最高
+            create_info = create_info.image_sharing_mode(SharingMode::EXCLUSIVE);
+        }
+
+        if settings.full_screen_exclusive_monitor.is_some() {
+            create_info = create_info
+                .push_next(&mut full_screen_exclusive_info)
+                .push_next(&mut full_screen_exclusive_win32_info);
+        }
+
+        let create_info = create_info
+            //swapchain内の画像に対して90度時計回りなどのtransformの変換を指定できる
+            //今回の場合は何もしない
+            .pre_transform(swap_chain_support.capabilities.current_transform)
+            //ほかウィンドウとのブレンドをどうするか指定
+            //OPAQUEはアルファを無視
+            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+            .present_mode(present_mode)
+            //他ウィンドウに隠れたピクセルをクリップするかどうか
+            .clipped(true)
+            //Vulkanではアプリケーションの実行中にスワップチェンが無効または最適化されなくなる可能性がある
+            //その場合はスワップチェーンを0から再度作らなければいけないため、その場合の古いSwapChainの参照を渡す
+            //今回はSwapChainは一つしか作らないことを仮定
+            .old_swapchain(vk::SwapchainKHR::null())
+            .build();
+
+        let swap_chain = Swapchain::new(instance, device);
+        let swap_chain_khr = unsafe { swap_chain.create_swapchain(&create_info, None).unwrap() };
+
+        info!("swapchain: {:?}", swap_chain_khr);
+
+        (
+            swap_chain,
+            swap_chain_khr,
+            ColorFormat::from_surface_format(surface_format),
+            extent,
+        )
+    }
+
+    //swapchainの画像へblitできる場合のみSome、--render-scaleを指定しなかった場合は倍率1.0にする
+    //linearで拡大縮小するにはフォーマットがSAMPLED_IMAGE_FILTER_LINEARに対応している必要がある
+    #[allow(clippy::too_many_arguments)]
+    //--msaaのサンプル数は、シーンをswapchainの画像に直接描画する場合だけ使う
+    //ポストプロセスとrender_scaleの中間画像とレイトレーシングの画像は1サンプルなので、その場合は警告して無視する
+    //デバイスが対応していない場合は対応している一番大きいサンプル数に減らす
+    fn choose_msaa_samples(
+        instance: &Instance,
+        physical_device: PhysicalDevice,
+        config: &AppConfig,
+        swap_chain_color_format: ColorFormat,
+        ray_tracing: bool,
+    ) -> vk::SampleCountFlags {
+        let requested = config.msaa_samples;
+        let demo = &config.demo;
+
+        if requested <= 1 {
+            return vk::SampleCountFlags::TYPE_1;
+        }
+
+        let conflict = if ray_tracing {
+            Some("--raytrace")
+        } else if !demo.post_effects.is_empty() || swap_chain_color_format.requires_post_process() {
+            Some("post processing")
+        } else if demo.render_scale.is_some() {
+            Some("--render-scale")
+        } else {
+            None
+        };
+
+        if let Some(conflict) = conflict {
+            log::warn!("--msaa is ignored with {}", conflict);
+            return vk::SampleCountFlags::TYPE_1;
+        }
+
+        let samples = msaa::choose_sample_count(
+            requested,
+            msaa::supported_sample_counts(instance, physical_device),
+        );
+
+        if samples.as_raw() < requested {
+            log::warn!(
+                "{} MSAA samples are not supported, using {}",
+                requested,
+                samples.as_raw()
+            );
+        }
+
+        info!("MSAA: {} samples", samples.as_raw());
+
+        samples
+    }
+
+    fn create_render_scale(
+        instance: &Instance,
+        physical_device: PhysicalDevice,
+        surface: &Surface,
+        surface_khr: SurfaceKHR,
+        format: Format,
+        demo: &DemoConfig,
+        ray_tracing: bool,
+        multisampled: bool,
+        use_render_pass: bool,
+    ) -> Option<RenderScale> {
+        let requested = demo.render_scale;
+
+        if ray_tracing {
+            if requested.is_some() {
+                log::warn!("--render-scale is ignored with --raytrace");
+            }
+            return None;
+        }
+
+        //MSAAはswapchainの画像へresolveするので、+/-で倍率を変えられるようにもしない
+        //--render-scaleを指定した場合はchoose_msaa_samplesがMSAAの方を無視している
+        if multisampled {
+            return None;
+        }
+
+        let features =
+            unsafe { instance.get_physical_device_format_properties(physical_device, format) }
+                .optimal_tiling_features;
+
+        if !SwapChainSupportDetails::new(physical_device, surface, surface_khr)
+            .capabilities
+            .supported_usage_flags
+            .contains(vk::ImageUsageFlags::TRANSFER_DST)
+            || !features
+                .contains(vk::FormatFeatureFlags::BLIT_SRC | vk::FormatFeatureFlags::BLIT_DST)
+        {
+            if requested.is_some() {
+                log::warn!("Swapchain images cannot be blitted to, --render-scale is ignored");
+            }
+            return None;
+        }
+
+        let filter = match demo.render_scale_filter {
+            ScaleFilter::Linear
+                if !features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR) =>
+            {
+                log::warn!(
+                    "{:?} does not support linear filtering, using nearest",
+                    format
+                );
+                ScaleFilter::Nearest
+            }
+            filter => filter,
+        };
+
+        Some(RenderScale::new(
+            requested.unwrap_or(1.0),
+            filter,
+            use_render_pass,
+        ))
+    }
+
+    fn get_swap_chain_images(
+        swap_chain: &Swapchain,
+        swap_chain_khr: SwapchainKHR,
+    ) -> Vec<vk::Image> {
+        //swapchainで保持している画像のハンドルを取得する
+        let images = unsafe { swap_chain.get_swapchain_images(swap_chain_khr) }.unwrap();
+
+        //ドライバはmin_image_countより多くの画像を作成することがあるので実際の枚数を確認する
+        info!("actual swapchain image count: {}", images.len());
+
+        images
+    }
+
+    //ドライバが実際に作成したswapchainの画像の枚数
+    #[allow(dead_code)]
+    pub fn swap_chain_image_count(&self) -> usize {
+        self.swap_chain_images.len()
+    }
+
+    fn create_image_views(
+        device: &Device,
+        swap_chain_images: &Vec<vk::Image>,
+        swap_chain_image_format: vk::Format,
+    ) -> Vec<vk::ImageView> {
+        let mut swap_chain_image_views = vec![];
+
+        for image in swap_chain_images {
+            let create_info = vk::ImageViewCreateInfo::builder()
+                .image(*image)
+                //画像を1Dテクスチャ、2Dテクスチャ、3Dテクスチャ、キューマップとして扱うことができる
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(swap_chain_image_format)
+                .components(
+                    //swizzleなマッピングをすることができる
+                    //例えばモノクロなテクスチャを全て赤色に割り当てて出力したりなど
+                    //今回はすべてデフォルトで行う
+                    vk::ComponentMapping::builder()
+                        .r(vk::ComponentSwizzle::IDENTITY)
+                        .g(vk::ComponentSwizzle::IDENTITY)
+                        .b(vk::ComponentSwizzle::IDENTITY)
+                        .a(vk::ComponentSwizzle::IDENTITY)
+                        .build(),
+                )
+                .subresource_range(
+                    //画像自体の目的が何であるか
+                    //画像の土の部分にアクセスすべきかを書くことができる
+                    //今回はミップマップレベルやマルチレイヤーは無しで設定
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .base_mip_level(0)
+                        .level_count(1)
+                        .base_array_layer(0)
+                        .layer_count(1)
+                        .build(),
+                )
+                .build();
+
+            swap_chain_image_views
+                .push(unsafe { device.create_image_view(&create_info, None).unwrap() });
+        }
+
+        info!("Create SwapChain Image View");
+
+        //テクスチャとして使う分には準備できているが、レンダーターゲットとしてはまだ設定が必要
+        //その設定とはフレームバッファと呼ばれるもう一段回のインダイレクトが必要だがこれを用意するのにまずグラフィックスパイプラインを設定する必要がある
+        swap_chain_image_views
+    }
+
+    fn create_graphics_pipeline(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        render_target: RenderTarget,
+        //set = 0から順番に割り当てる
+        descriptor_set_layouts: &[vk::DescriptorSetLayout],
+        //trueの場合はPolygonMode::LINEのパイプラインも一緒に作成する
+        with_wireframe: bool,
+        vertex_stage: VertexStage,
+        //書き込む画像のColorFormat::shader_output
+        output: ColorEncoding,
+    ) -> (vk::Pipeline, Option<vk::Pipeline>, vk::PipelineLayout) {
+        Self::create_specialized_pipeline(
+            device,
+            pipeline_cache,
+            render_target,
+            descriptor_set_layouts,
+            with_wireframe,
+            vertex_stage,
+            output,
+            &SpecConstants::new(),
+        )
+    }
+
+    //create_graphics_pipelineにフラグメントシェーダーの特殊化定数を加えたもの
+    //同じSPIR-Vから定数の値だけが違うパイプラインを作れる
+    #[allow(clippy::too_many_arguments)]
+    fn create_specialized_pipeline(
+        device: &Device,
+        pipeline_cache: vk::PipelineCache,
+        render_target: RenderTarget,
+        descriptor_set_layouts: &[vk::DescriptorSetLayout],
+        with_wireframe: bool,
+        vertex_stage: VertexStage,
+        output: ColorEncoding,
+        fragment_constants: &SpecConstants,
+    ) -> (vk::Pipeline, Option<vk::Pipeline>, vk::PipelineLayout) {
+        let variants = if with_wireframe {
+            vec![PipelineVariant::Wireframe]
+        } else {
+            vec![]
+        };
+
+        let (mut pipelines, pipeline_layout) = Self::build_pipelines(
+            device,
+            render_target,
+            descriptor_set_layouts,
+            vertex_stage,
+            output,
+            fragment_constants,
+            &variants,
+            |base_info, variant_infos| {
+                let started_at = Instant::now();
+
+                //ワイヤーフレームはベースのパイプラインの派生として作る
+                //第一引数のPipelineCacheはcreate_graphics_pipelinesを複数回呼び出しするときやキャッシュがファイルに保存されている時にパイプラインに関するデータを再利用することができる
+                let pipelines = pipeline_bench::create_with_derivatives(
+                    device,
+                    pipeline_cache,
+                    base_info,
+                    variant_infos,
+                    true,
+                );
+
+                info!(
+                    "Created {} pipeline(s) in {:.2} ms",
+                    pipelines.len(),
+                    started_at.elapsed().as_secs_f64() * 1000.0
+                );
+
+                pipelines
+            },
+        );
+
+        //渡した順番で返ってくる
+        let wireframe_pipeline = if with_wireframe {
+            pipelines.pop().map(|(pipeline, _)| pipeline)
+        } else {
+            None
+        };
+        let (pipeline, _) = pipelines.pop().unwrap();
+
+        (pipeline, wireframe_pipeline, pipeline_layout)
+    }
+
+    //パイプラインレイアウトを作り、ベースのパイプラインとvariantsのCreateInfoをcreateに渡す
+    //CreateInfoはこの関数の中の変数を指しているのでcreateの中でしか使えない、シェーダーモジュールはcreateの後に破棄する
+    #[allow(clippy::too_many_arguments)]
+    fn build_pipelines<R>(
+        device: &Device,
+        render_target: RenderTarget,
+        descriptor_set_layouts: &[vk::DescriptorSetLayout],
+        vertex_stage: VertexStage,
+        output: ColorEncoding,
+        fragment_constants: &SpecConstants,
+        variants: &[PipelineVariant],
+        create: impl FnOnce(&vk::GraphicsPipelineCreateInfo, &[vk::GraphicsPipelineCreateInfo]) -> R,
+    ) -> (R, vk::PipelineLayout) {
+        //プログラマブルステージの設定
+
+        //Create Shader Module
+
+        info!("Shader Path: {}", SHADER_PATH);
+        info!("Shader Length: {}", SHADER_CODE.len());
+
+        let shader_module = Self::create_shader_module(device, SHADER_CODE);
+        let fragment_shader_module = if vertex_stage.uses_ray_query() {
+            Self::create_shader_module(device, RAY_QUERY_SHADER_CODE)
+        } else {
+            shader_module
+        };
+        let stage_shader_module = vertex_stage
+            .stage_shader_code()
+            .map(|code| Self::create_shader_module(device, code));
+        let mesh_shader_modules = vertex_stage
+            .mesh_shader_code()
+            .map(|codes| codes.map(|code| Self::create_shader_module(device, code)));
+
+        //Lifetimeを確保するために一度変数にしている
+        let main_vs = CString::new(vertex_stage.entry_point()).unwrap();
+        let main_fs = CString::new(vertex_stage.fragment_entry_point(output)).unwrap();
+        let extra_stage_names = vertex_stage
+            .extra_stages()
+            .iter()
+            .map(|(_, name)| CString::new(*name).unwrap())
+            .collect::<Vec<_>>();
+
+        let vert_shader_stage_info = vk::PipelineShaderStageCreateInfo::builder()
+            //fragmentやvertexまたgeometryなどのどこのシェーダーステージの物なのかを指定する
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(stage_shader_module.unwrap_or(shader_module))
+            .name(main_vs.as_c_str())
+            //これはシェーダ内で定数を設定する時に外部から設定できるのでそのときに使用するもの
+            //.specialization_info()
+            .build();
+
+        //fragment_constantsが空の場合は特殊化せず、シェーダー側のdefaultの値を使う
+        let specialization_info = fragment_constants.info();
+
+        let mut frag_shader_stage_info = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(fragment_shader_module)
+            .name(main_fs.as_c_str());
+
+        if !fragment_constants.is_empty() {
+            frag_shader_stage_info =
+                frag_shader_stage_info.specialization_info(&specialization_info);
+        }
+
+        let frag_shader_stage_info = frag_shader_stage_info.build();
+
+        //メッシュシェーダーを使うパイプラインには頂点シェーダーを含めてはいけない
+        let first_stages = match mesh_shader_modules {
+            Some([task_shader_module, mesh_shader_module]) => [
+                (vk::ShaderStageFlags::TASK_EXT, task_shader_module),
+                (vk::ShaderStageFlags::MESH_EXT, mesh_shader_module),
+            ]
+            .into_iter()
+            .map(|(stage, module)| {
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(stage)
+                    .module(module)
+                    .name(main_vs.as_c_str())
+                    .build()
+            })
+            .collect(),
+            None => vec![vert_shader_stage_info],
+        };
+
+        let mut shader_stages = if vertex_stage.has_fragment_shader() {
+            [first_stages, vec![frag_shader_stage_info]].concat()
+        } else {
+            first_stages
+        };
+
+        //テッセレーションの制御シェーダーと評価シェーダー、ジオメトリシェーダーはstage_shader_moduleにある
+        if let Some(stage_shader_module) = stage_shader_module {
+            shader_stages.extend(
+                vertex_stage
+                    .extra_stages()
+                    .iter()
+                    .zip(&extra_stage_names)
+                    .map(|((stage, _), name)| {
+                        vk::PipelineShaderStageCreateInfo::builder()
+                            .stage(*stage)
+                            .module(stage_shader_module)
+                            .name(name.as_c_str())
+                            .build()
+                    }),
+            );
+        }
+
+        //Vertex Input
+
+        //頂点シェーダーに渡される頂点データの形式を指定
+        let (binding_descriptions, attribute_descriptions) = vertex_stage.vertex_input();
+
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
+            //バインディングとはデータ感の間隔やデータが頂点ごとかインスタンスごとかの指定など
+            .vertex_binding_descriptions(&binding_descriptions)
+            //頂点シェーダーに渡される属性の指定またどのバインディングからロードするかやどのオフセットでロードするかなど
+            .vertex_attribute_descriptions(&attribute_descriptions)
+            .build();
+
+        //固定機能ステージの設定
+
+        //Input Assembly
+        //入力された頂点からどのようなトポロジでプリミティブを作成するかを設定
+
+        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            //トポロジの設定
+            //パーティクル以外は3つずつ頂点を読み込んで描画
+            .topology(vertex_stage.topology())
+            //トポロジの設定でSTRIP系の設定をしていると全てのプリミティブがつながってしまうので
+            //trueにすることでそのつながり部分を一度断ち切るようなindex値を設定できる
+            .primitive_restart_enable(false)
+            .build();
+
+        //Tessellation
+        //PATCH_LISTの場合に何個の頂点を1つのパッチにするか
+        let tessellation_state = vk::PipelineTessellationStateCreateInfo::builder()
+            .patch_control_points(tessellation::PATCH_CONTROL_POINTS)
+            .build();
+
+        //Viewport, Scissor Rectangle
+
+        //viewportとscissor rectangleはdynamic stateにしてrecord_command_buffer内で設定する
+        //ここでは数だけを指定する
+        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+            //GPUによっては複数のviewportとscissor rectangleを使用することができる
+            .viewport_count(1)
+            .scissor_count(1)
+            .build();
+
+        let depth_bias = vertex_stage.depth_bias();
+        let (depth_bias_constant_factor, depth_bias_slope_factor) =
+            depth_bias.unwrap_or((0.0, 0.0));
+
+        let rasterizer = vk::PipelineRasterizationStateCreateInfo::builder()
+            //trueを設定した場合nearとfarを超えたフラグメントはカリングされるのではなくclampされる
+            //シャドウマップなどに有効
+            //GPUの機能を有効にする必要あり
+            .depth_clamp_enable(false)
+            //trueを設定した場合ラスタライザステージをスキップする
+            .rasterizer_discard_enable(false)
+            //フラグメントの生成方法
+            //input assemblyは実際に塗るかどうかの設定だが、これはフラグメントを作成するかどうかの判断(?)
+            //例えばFILLの場合はポリゴンの領域をフラグメントで埋める
+            //GPUの機能を有効にする必要あり
+            .polygon_mode(vk::PolygonMode::FILL)
+            //線の太さを設定
+            //最大値はGPUに依存する
+            //1.0以上を指定したい場合はwideLinesというGPUの機能を有効にする必要あり
+            .line_width(1.0)
+            //カリングの種類を指定
+            .cull_mode(vertex_stage.cull_mode())
+            //Vulkanは右回りが表面？
+            .front_face(vk::FrontFace::CLOCKWISE)
+            //深度値の設定
+            //フラグメントの偏りに基づいてバイアスを掛けたりして深度地を変更することができる
+            //これらはシャドウマッピングなどで使用される
+            .depth_bias_enable(depth_bias.is_some())
+            .depth_bias_constant_factor(depth_bias_constant_factor)
+            //0.0以外を指定するにはdepth_bias_clampというGPUの機能を有効にする必要あり
+            .depth_bias_clamp(0.0)
+            .depth_bias_slope_factor(depth_bias_slope_factor)
+            .build();
+
+        //Multisampling
+
+        //マルチサンプリングはアンチエイリアスの方法の１つ
+        //サンプル数は--msaaで決めた描画先のアタッチメントと合わせる
+        let multisampling = vk::PipelineMultisampleStateCreateInfo::builder()
+            //サンプルごとにフラグメントシェーダーを動かすにはGPUの機能を有効にする必要があるので無効化
+            .sample_shading_enable(false)
+            .rasterization_samples(render_target.samples())
+            .min_sample_shading(1.0)
+            .alpha_to_coverage_enable(false)
+            .alpha_to_one_enable(false)
+            .build();
+
+        //Depth Stencil
+        let depth_stencil = vertex_stage.depth_stencil_state(render_target.depth());
+
+        //Color blending
+
+        //フレームバッファごとの設定
+        //現在はフレームバッファは１つしか存在しない
+        //SRGBフォーマットではブレンドもリニアで行われるが、ManualSrgbの場合はエンコード済みの値で混ざる
+        let color_blend_attachment = |blend_enable: bool| {
+            let (src_color_blend_factor, dst_color_blend_factor, dst_alpha_blend_factor) =
+                if blend_enable && vertex_stage.premultiplied_alpha() {
+                    //new_color + old_color * (1 - alpha)
+                    (
+                        vk::BlendFactor::ONE,
+                        vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                        vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                    )
+                } else if blend_enable {
+                    //new_color * alpha + old_color * (1 - alpha)
+                    (
+                        vk::BlendFactor::SRC_ALPHA,
+                        vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                        vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                    )
+                } else {
+                    (
+                        vk::BlendFactor::ONE,
+                        vk::BlendFactor::ZERO,
+                        vk::BlendFactor::ZERO,
+                    )
+                };
+
+            vk::PipelineColorBlendAttachmentState::builder()
+                .color_write_mask(vertex_stage.color_write_mask())
+                //新しい色と古い色を混ぜるかどうか
+                //falseの場合はフラグメントシェーダーからの新しい色をそのまま使用する
+                .blend_enable(blend_enable)
+                //新しく来た色の寄与の割合(src_color_blend_factor * new_color的な感じ)
+                .src_color_blend_factor(src_color_blend_factor)
+                //もとから存在した色の寄与の割合(dst_color_blend_factor * old_color的な感じ)
+                .dst_color_blend_factor(dst_color_blend_factor)
+                //色を混ぜるときの演算子
+                .color_blend_op(vk::BlendOp::ADD)
+                //上記のalpha版
+                .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                .dst_alpha_blend_factor(dst_alpha_blend_factor)
+                .alpha_blend_op(vk::BlendOp::ADD)
+                .build()
+        };
+
+        //深度値だけを書き込む場合はカラーアタッチメントが無い
+        let color_blend_attachments = |blend_enable: bool| {
+            if vertex_stage.writes_color() {
+                vec![color_blend_attachment(blend_enable)]
+            } else {
+                vec![]
+            }
+        };
+
+        let color_blend_attachments_opaque = color_blend_attachments(vertex_stage.blend_enable());
+        let color_blend_attachments_blended = color_blend_attachments(true);
+
+        //全てのフレームバッファ構造体の設定
+        let color_blend = vk::PipelineColorBlendStateCreateInfo::builder()
+            //2つ目のブレンド方法
+            //ビット単位でのブレンドの演算を行うことができる
+            //これを有効にするとVkPipelineColorBlendAttachmentStateで有効にしたblend設定は無効になってしまうので注意
+            //vkPipelineColorBlendAttachmentStateで設定したcolor_write_maskは個々でも使用される
+            .logic_op_enable(false)
+            //ビット演算の演算子指定
+            .logic_op(vk::LogicOp::COPY)
+            .attachments(&color_blend_attachments_opaque)
+            .blend_constants([0.0, 0.0, 0.0, 0.0])
+            .build();
+
+        //PipelineVariant::Blended用はアタッチメント以外は同じ
+        let blended_color_blend = vk::PipelineColorBlendStateCreateInfo {
+            attachment_count: color_blend_attachments_blended.len() as u32,
+            p_attachments: color_blend_attachments_blended.as_ptr(),
+            ..color_blend
+        };
+
+        //Dynamic State
+
+        //一度パイプラインの作成をしたあとに再作成をなしに変更できる値を設定
+        //ここではビューポートとシザーの矩形
+        //これによってウィンドウのリサイズ時にpipelineを作り直さなくて良くなる
+        //線の幅はwide_linesを有効にしていないので1.0以外は設定できず、dynamicにする意味がない
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&dynamic_states)
+            .build();
+
+        //Pipeline layout
+
+        let push_constant_ranges = vertex_stage.push_constant_ranges();
+
+        //この構造体はVertex Shaderに変換行列を渡したり、フラグメントシェーダーでテクスチャサンプラーを作成するために使用する
+        //これによってシェーダーを一回一回ビルドしなくても定数を外部から変えることで柔軟性を持たせることができる
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            //set = 0にUniform Buffer、set = 1にオブジェクトごとのDynamic Uniform Bufferを割り当てる
+            .set_layouts(descriptor_set_layouts)
+            .push_constant_ranges(&push_constant_ranges)
+            .build();
+
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .unwrap()
+        };
+
+        //Pipeline
+
+        //dynamic renderingではrender passの代わりにアタッチメントのフォーマットをpNextで渡す
+        let (color_attachment_formats, depth_attachment_format) = match render_target {
+            RenderTarget::Dynamic {
+                color_format,
+                depth_format,
+                ..
+            } if vertex_stage.writes_color() => (vec![color_format], depth_format),
+            RenderTarget::Dynamic { depth_format, .. } => (vec![], depth_format),
+            RenderTarget::RenderPass(..) => (vec![], vk::Format::UNDEFINED),
+        };
+
+        let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+            .color_attachment_formats(&color_attachment_formats)
+            .depth_attachment_format(depth_attachment_format)
+            .build();
+
+        let mut pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_input_info)
+            .input_assembly_state(&input_assembly_info)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterizer)
+            .multisample_state(&multisampling)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&color_blend)
+            .dynamic_state(&dynamic_state)
+            .layout(pipeline_layout)
+            //パイプラインの派生をする時に使用する
+            //パイプラインの派生とは既存のパイプラインと多くの機能が共通している場合に設定にコストをかけずに素早く切り替えることができる機能
+            //Handleで既存のパイプラインを指定するか
+            //派生させる場合はpipeline_bench::create_with_derivativesが設定する
+            .base_pipeline_handle(vk::Pipeline::null())
+            //パイプラインのIndexで指定するかのどちらか
+            .base_pipeline_index(-1);
+
+        if vertex_stage.topology() == vk::PrimitiveTopology::PATCH_LIST {
+            pipeline_info = pipeline_info.tessellation_state(&tessellation_state);
+        }
+
+        pipeline_info = match render_target {
+            RenderTarget::RenderPass(render_pass, ..) => {
+                pipeline_info.render_pass(render_pass).subpass(0)
+            }
+            RenderTarget::Dynamic { .. } => pipeline_info.push_next(&mut rendering_info),
+        };
+
+        let mut pipeline_info = pipeline_info.build();
+
+        //メッシュシェーダーが頂点とプリミティブを直接書き出すので、頂点入力とInput Assemblyの状態は指定しない
+        if mesh_shader_modules.is_some() {
+            pipeline_info.p_vertex_input_state = std::ptr::null();
+            pipeline_info.p_input_assembly_state = std::ptr::null();
+        }
+
+        //ワイヤーフレーム用はラスタライザの設定以外は同じ
+        let wireframe_rasterizer = vk::PipelineRasterizationStateCreateInfo {
+            polygon_mode: vk::PolygonMode::LINE,
+            ..rasterizer
+        };
+
+        let variant_infos = variants
+            .iter()
+            .map(|variant| match variant {
+                PipelineVariant::Wireframe => vk::GraphicsPipelineCreateInfo {
+                    p_rasterization_state: &wireframe_rasterizer,
+                    ..pipeline_info
+                },
+                PipelineVariant::Blended => vk::GraphicsPipelineCreateInfo {
+                    p_color_blend_state: &blended_color_blend,
+                    ..pipeline_info
+                },
+            })
+            .collect::<Vec<_>>();
+
+        let created = create(&pipeline_info, &variant_infos);
+
+        unsafe {
+            //パイプラインの作成が終了したらモジュールはすぐに破棄して良い
+            device.destroy_shader_module(shader_module, None);
+            if fragment_shader_module != shader_module {
+                device.destroy_shader_module(fragment_shader_module, None);
+            }
+            if let Some(stage_shader_module) = stage_shader_module {
+                device.destroy_shader_module(stage_shader_module, None);
+            }
+            for mesh_shader_module in mesh_shader_modules.into_iter().flatten() {
+                device.destroy_shader_module(mesh_shader_module, None);
+            }
+        }
+
+        (created, pipeline_layout)
+    }
+
+    fn create_shader_module(device: &Device, spirv_code: &[u8]) -> vk::ShaderModule {
+        info!("create shader module");
+
+        let create_info = vk::ShaderModuleCreateInfo {
+            s_type: vk::StructureType::SHADER_MODULE_CREATE_INFO,
+            p_next: std::ptr::null(),
+            flags: vk::ShaderModuleCreateFlags::empty(),
+            code_size: spirv_code.len(),
+            p_code: spirv_code.as_ptr() as *const u32,
+        };
+
+        unsafe { device.create_shader_module(&create_info, None).unwrap() }
+    }
+
+    //samplesがTYPE_1でない場合はattachment 0をマルチサンプルの画像にし、attachment 2のswapchainの画像へresolveする
+    fn create_render_pass(
+        device: &Device,
+        format: Format,
+        depth_format: Format,
+        samples: vk::SampleCountFlags,
+    ) -> vk::RenderPass {
+        info!("create render pass");
+
+        let multisampled = samples != vk::SampleCountFlags::TYPE_1;
+
+        //Subpass周り諸々
+
+        //subpass同士でやり取りするデータをAttachmentと呼ぶ
+        let color_attachment = vk::AttachmentDescription::builder()
+            //swapchainのフォーマットと同じものを使用
+            .format(format)
+            //マルチサンプリングの設定
+            .samples(samples)
+            //loadOpとstoreOpはレンダリング前と後のデータをどうするか決める
+            //load
+            //CLEARは開始時に定数で値をクリアする
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            //レンダリングされたコンテンツをメモリ上に保存する
+            //マルチサンプルの画像はresolveした後は使わないので保存しない
+            .store_op(if multisampled {
+                vk::AttachmentStoreOp::DONT_CARE
+            } else {
+                vk::AttachmentStoreOp::STORE
+            })
+            //上記２つのStencil版
+            //現在は使用していないので特に考慮する必要がないというDONT_CAREを割り当てる
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            //画像の特定のピクセルフォーマットはVkImageで保持されるがそのピクセルごとのメモリレイアウトの設定はここで行われる
+            //レイアウトはそれぞれその画像が何をするための物なのかを示すもの
+            //initialLayoutはレンダリングパスが始まる前に画像が持つレイアウトを指定する
+            //UNDEFINEDは画像のレイアウト
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            //PRESENT_SRC_KHRはスワップチェーンで提示される画像となる
+            .final_layout(if multisampled {
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+            } else {
+                vk::ImageLayout::PRESENT_SRC_KHR
+            })
+            .build();
+
+        //マルチサンプルの画像をsubpassの最後に平均して書き込むswapchainの画像
+        //全てのピクセルを上書きするので前の内容は読まない
+        let resolve_attachment = vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .build();
+
+        let resolve_attachment_refs = [vk::AttachmentReference::builder()
+            .attachment(2)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build()];
+
+        //Subpass用の設定構造体
+        //Lifetimeを確保するために配列を一度変数にしている
+        let color_attachment_refs = [vk::AttachmentReference::builder()
+            //Subpassは複数のAttachmentを持つことがあるためこうなっている
+            //参照するVkAttachmentDescriptionのインデックスを指定する
+            .attachment(0)
+            //attachmentのレイアウトを指定
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build()];
+
+        //デプスアタッチメントはsubpassに1つしか持てないので配列ではない
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+
+        let mut subpass = vk::SubpassDescription::builder()
+            //Vulkanは将来的にCompute系のsubpassもサポートする可能性が存在するためGRAPHICSを指定してあげる
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            //ここでindexを0番に設定したためフラグメントシェーダーから`layout(location = 0) out vec4 outColor`で参照できる
+            .color_attachments(&color_attachment_refs)
+            .depth_stencil_attachment(&depth_attachment_ref);
+
+        //resolve_attachmentsはcolor_attachmentsと同じ数にする
+        if multisampled {
+            subpass = subpass.resolve_attachments(&resolve_attachment_refs);
+        }
+
+        let subpass = subpass.build();
+
+        //Render passのSubpass Dependencyはdraw_frameのImageが利用可能にならないと(セマフォでいうとimage_available_semaphore)設定できないので待機する
+        //今回の方法はRender passを途中でVK_PIPELINE_STAGE_COLOR_ATTACHMENT_OUTPUT_BITまで待機させることで可能にしているが
+        //imageAvailableSemaphoreのwaitStagesをVK_PIPELINE_STAGE_TOP_OF_PIPE_BITに変更してRender pass自体を開始しないようにすることもできる
+
+        //srcとdstの２つのsubpassを指定して紐づける
+        let dependency = vk::SubpassDependency::builder()
+            //subpassの依存関係を記述
+            //SUBPAS_EXTERNALはdst_subpassがどう指定されているかに応じてレンダーパスの前後の暗黙のsubpassを参照する
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            //subpassのindexを指定
+            .dst_subpass(0)
+            //次の２つは待機する操作とその操作が発生するステージを指定
+            //ステージ指定
+            //デプスバッファは全てのフレームで共有するので、前のフレームの深度テストでの書き込みも待つ
+            .src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            //待機操作
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            //デプスバッファのクリアはEARLY_FRAGMENT_TESTSで行われる
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .build();
+
+        //RenderPass
+
+        let mut attachments = vec![
+            color_attachment,
+            DepthBuffer::attachment_description(depth_format, samples),
+        ];
+
+        if multisampled {
+            attachments.push(resolve_attachment);
+        }
+
+        let subpasses = [subpass];
+        let dependencies = [dependency];
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies)
+            .build();
+
+        unsafe { device.create_render_pass(&render_pass_info, None).unwrap() }
+    }
+
+    //デプスバッファは全てのframebufferで共有する
+    //msaa_viewがある場合はそこに描画し、swapchainの画像はresolve先のattachment 2になる
+    fn create_frame_buffers(
+        device: &Device,
+        render_pass: vk::RenderPass,
+        swap_chain_image_views: Vec<vk::ImageView>,
+        depth_view: vk::ImageView,
+        msaa_view: Option<vk::ImageView>,
+        swap_chain_extent: vk::Extent2D,
+    ) -> Vec<vk::Framebuffer> {
+        let mut swap_chain_frame_buffers = vec![];
+
+        //vkImagesに割り当てていく
+        for image_view in swap_chain_image_views {
+            let attachments = match msaa_view {
+                Some(msaa_view) => vec![msaa_view, depth_view, image_view],
+                None => vec![image_view, depth_view],
+            };
+
+            let frame_buffer_info = vk::FramebufferCreateInfo::builder()
+                //FrameBufferがどのRender passと互換性を持つかを指定
+                //FrameBufferは互換性のあるレンダーパスでのみ使用できる
+                .render_pass(render_pass)
+                //RenderPassのpAttachment配列内のそれぞれのAttachmentに対してどのImageViewが紐づくべきかを指定
+                .attachments(&attachments)
+                .width(swap_chain_extent.width)
+                .height(swap_chain_extent.height)
+                //画像配列のレイヤー数を指定
+                .layers(1)
+                .build();
 
-        info!("Selected physical device: {:?}", unsafe {
-            CStr::from_ptr(props.device_name.as_ptr())
-        });
+            swap_chain_frame_buffers
+                .push(unsafe { device.create_framebuffer(&frame_buffer_info, None).unwrap() });
+        }
 
-        physical_device
+        swap_chain_frame_buffers
     }
 
-    //論理デバイスを取得
-    fn create_logical_device_and_queue(
+    fn create_command_pool(
         instance: &Instance,
         surface: &Surface,
         surface_khr: SurfaceKHR,
         physical_device: PhysicalDevice,
-    ) -> (ash::Device, Queue, Queue) {
-        let indices = QueueFamilyIndices::find_queue_families(
+        device: &Device,
+    ) -> vk::CommandPool {
+        let queue_family_indices = QueueFamilyIndices::find_queue_families(
             instance,
             surface,
             surface_khr,
             physical_device,
         );
 
-        //倫理デバイスが対応しているキューを取得する
-        let queue_create_info = [vk::DeviceQueueCreateInfo::builder()
-            .queue_family_index(indices.graphics_family.expect("値が存在しません"))
-            .queue_priorities(&[1.0f32])
-            .build()];
+        //Command Bufferのメモリ管理をするためのCommand Pool
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            //flagは二種類存在し、
+            //VK_COMMAND_POOL_CREATE_TRANSIENT_BITはプールが割り当てたコマンドバッファが短命であることを指定
+            //VK_COMMAND_POOL_CREATE_RESET_COMMAND_BUFFER_BITはそのコマンドバッファをコマンドを積む際にResetして使い回すことを指定
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            //Command Poolは単一のキューファミリータイプに対して作られる
+            //今回はグラフィックスキューファミリーを選択
+            .queue_family_index(queue_family_indices.graphics_family.unwrap())
+            .build();
 
-        //queue_family.rsで検索したgeometry shaderのような機能を使用できるかどうかを検索する時に使用する
-        let device_features = vk::PhysicalDeviceFeatures::builder().build();
+        unsafe { device.create_command_pool(&pool_info, None).unwrap() }
+    }
 
-        let extension_names_ptr = get_required_device_extensions()
-            .iter()
-            .map(|name| name.as_ptr())
-            .collect::<Vec<_>>();
+    //Command Bufferは所属するCommand Poolが破棄されるタイミングで自動的に破棄される
+    fn create_command_buffers(
+        device: &Device,
+        command_pool: CommandPool,
+        size: u32,
+    ) -> Vec<vk::CommandBuffer> {
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            //コマンドバッファがプライマリなのかセカンダリなのを指定
+            //PRIMARY: 直接キューに対してサブミットすることができる
+            //SECONDARY: 直接キューに対してサブミットすることは出来ないがプライマリコマンドバッファから間接的に呼び出すことができる
+            //SECONDARYは共通の操作をまとめて再利用したりする時に便利
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(size)
+            .build();
 
-        let mut create_info = vk::DeviceCreateInfo::builder()
-            .queue_create_infos(&queue_create_info)
-            .enabled_extension_names(&extension_names_ptr)
-            .enabled_features(&device_features);
+        unsafe { device.allocate_command_buffers(&alloc_info).unwrap() }
+    }
 
-        let layer_names = REQUIRED_LAYERS
-            .iter()
-            .map(|name| CString::new(*name).expect("Failed to build CString"))
-            .collect::<Vec<_>>();
-        let layer_names_ptrs = layer_names
+    //command_bufferはdraw_frameで現在のフレーム用に取得したものを受け取る
+    //mirrorsはこのフレームで画像を取得できたmirrorのウィンドウとその画像のindex
+    fn record_command_buffer(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        mirrors: &[(WindowId, u32)],
+    ) {
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            //コマンドバッファの使用方法を指定
+            //ONE_TIME_SUBMIT: コマンドバッファを一度ジック押したらまたすぐに再記録する
+            //PASS_CONTINUE: 一回のレンダリングパスの中で完結するSECONDARYコマンドバッファ
+            //SIMULTANEOUS_USE: コマンドバッファを実行または保留中に再度Submitすることができる
+            .flags(vk::CommandBufferUsageFlags::empty())
+            //この値はSECONDARYコマンドバッファに対してのみ適用される
+            //これはPRIMARYなコマンドバッファからどのように状態を継承するかを指定する
+            //.inheritance_info()
+            .build();
+
+        unsafe {
+            self.device
+                //コマンドバッファの記録を開始する
+                //一度記録されたコマンドバッファに対してもう一度このメソッドを呼び出すと、リセットが暗黙的に走る
+                .begin_command_buffer(command_buffer, &begin_info)
+                .unwrap()
+        };
+
+        //デバイスが失われた時に、どのパスの途中だったかをCrashDiagnosticsで読めるようにする
+        self.crash_diagnostics
+            .cmd_checkpoint(command_buffer, Checkpoint::FrameBegin);
+
+        //コマンドバッファは毎フレーム記録し直しているのでクリア値もフレームごとに変えられる
+        let clear_color = if self.animate_clear_color {
+            //10秒で色相が一周する
+            ClearColor::from_hue(self.frame_clock.elapsed_seconds() / 10.0)
+        } else {
+            self.clear_color
+        }
+        .to_clear_value(Self::scene_color_format(
+            self.swap_chain_color_format,
+            self.post_process.is_some(),
+        ));
+
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            gpu_timer.cmd_begin(&self.device, command_buffer, self.current_frame);
+        }
+
+        if let Some(pipeline_statistics) = &self.pipeline_statistics {
+            pipeline_statistics.cmd_reset(&self.device, command_buffer, self.current_frame);
+        }
+
+        if let Some(occlusion_culling) = &self.occlusion_culling {
+            occlusion_culling.cmd_reset(&self.device, command_buffer, self.current_frame);
+        }
+
+        //render_scaleのtargetに描画する場合はswapchainとは解像度が違う
+        let render_extent = self.render_extent();
+
+        //draw_frameでこのフレームのstaging_ringを始めてから呼ばれる
+        if let Some(debug_text) = &mut self.debug_text {
+            debug_text.upload(
+                &self.device,
+                &mut self.staging_ring,
+                self.current_frame,
+                render_extent,
+            );
+        }
+
+        if let Some(egui_overlay) = &mut self.egui_overlay {
+            egui_overlay.upload(
+                &self.device,
+                &mut self.staging_ring,
+                self.current_frame,
+                render_extent,
+            );
+        }
+
+        if let Some(sprite_batch) = &mut self.sprite_batch {
+            sprite_batch.upload(&self.device, &mut self.staging_ring, self.current_frame);
+        }
+
+        //レイトレーシングではレンダーパスを使わずにswapchainの画像へ直接blitする
+        if let Some(ray_tracer) = &self.ray_tracer {
+            let aspect_ratio =
+                self.swap_chain_extent.width as f32 / self.swap_chain_extent.height as f32;
+
+            //デプスバッファを使わないので、光線の向きは通常の向きの行列から求める
+            let constants = RayTracingConstants::new(
+                self.camera.view_matrix(),
+                self.camera.culling_projection_matrix(aspect_ratio),
+                self.swap_chain_color_format.shader_output(),
+            );
+
+            self.crash_diagnostics
+                .cmd_checkpoint(command_buffer, Checkpoint::RayTracing);
+
+            ray_tracer.cmd_trace(
+                &self.device,
+                &self.synchronization,
+                command_buffer,
+                &constants,
+                self.swap_chain_images[image_index],
+            );
+
+            self.cmd_mirror_window_targets(command_buffer, image_index, mirrors);
+
+            self.crash_diagnostics
+                .cmd_checkpoint(command_buffer, Checkpoint::FrameEnd);
+
+            if let Some(gpu_timer) = &mut self.gpu_timer {
+                gpu_timer.cmd_end(&self.device, command_buffer, self.current_frame);
+            }
+
+            unsafe { self.device.end_command_buffer(command_buffer).unwrap() };
+
+            return;
+        }
+
+        //ディスパッチはレンダーパスの中では行えないので先に記録する
+        //専用のコンピュートキューがある場合はsubmit_async_computeで別に記録する
+        if let (Some(particles), None) = (&self.particles, &self.compute_queue) {
+            self.crash_diagnostics
+                .cmd_checkpoint(command_buffer, Checkpoint::Compute);
+
+            particles.cmd_update(
+                &self.device,
+                &self.synchronization,
+                command_buffer,
+                self.current_frame,
+                self.frame_clock.simulation_delta_seconds(),
+                true,
+            );
+        }
+
+        //同じキューの前のフレームの描画がテクスチャを読み終わってから書き込む
+        if let Some(procedural_texture) = &self.procedural_texture {
+            procedural_texture.cmd_update(
+                &self.device,
+                &self.synchronization,
+                command_buffer,
+                self.frame_clock.elapsed_seconds(),
+            );
+        }
+
+        //カメラから見えないオブジェクトの描画は記録しない
+        //インスタンス描画ではself.meshのオブジェクトを使わないのでカリングしない
+        //--spritesではメッシュのオブジェクトの代わりにスプライトを描画する
+        let visible_objects = if self.instanced_grid.is_some() || self.sprite_demo.is_some() {
+            vec![]
+        } else {
+            let visible_objects = self.visible_objects();
+            self.frame_stats.record_culling(
+                self.opaque_object_count - visible_objects.len(),
+                self.opaque_object_count,
+            );
+            visible_objects
+        };
+
+        //隠れていたオブジェクトも次のフレームのためにバウンディングボックスはクエリで描画する
+        let visible_objects = if self.occlusion_culling.is_some() {
+            let bounds = self.mesh.bounds();
+            let boxes = visible_objects
+                .iter()
+                .map(|&index| (index, bounds.transform(self.object_model(index))))
+                .collect::<Vec<_>>();
+
+            let occlusion_culling = self.occlusion_culling.as_mut().unwrap();
+            let visible_objects = occlusion_culling.filter_visible(
+                self.current_frame,
+                &boxes,
+                self.camera.position,
+                self.camera.near,
+            );
+            self.frame_stats
+                .record_occlusion(occlusion_culling.occluded_count(), self.opaque_object_count);
+            visible_objects
+        } else {
+            visible_objects
+        };
+
+        //TLASのビルドはレンダーパスの中では行えないので先に記録する
+        //シャドウマップと同じく視野の外のオブジェクトも影を落とすのでカリングしない
+        if let Some(ray_query_shadows) = &self.ray_query_shadows {
+            if ray_query_shadows.is_enabled() {
+                let models = (0..self.opaque_object_count)
+                    .map(|index| self.object_model(index))
+                    .collect::<Vec<_>>();
+
+                ray_query_shadows.cmd_build(
+                    &self.device,
+                    &self.synchronization,
+                    command_buffer,
+                    self.current_frame,
+                    &models,
+                );
+            }
+        }
+
+        //オーバードローの数はレンダーパスの外でクリアする
+        if let (Some(overdraw_counters), true) = (&self.overdraw_counters, self.shows_overdraw()) {
+            overdraw_counters.cmd_reset(
+                &self.device,
+                &self.synchronization,
+                command_buffer,
+                self.current_frame,
+            );
+        }
+
+        //update_uniform_bufferで書き込んだライトをシーンのパスより前にタイルに振り分ける
+        if let Some(light_culling) = &self.light_culling {
+            self.crash_diagnostics
+                .cmd_checkpoint(command_buffer, Checkpoint::LightCulling);
+
+            let inverse_projection = self
+                .camera
+                .culling_projection_matrix(self.view_aspect_ratio())
+                .inverse();
+
+            light_culling.cmd_cull(
+                &self.device,
+                &self.synchronization,
+                command_buffer,
+                self.uniform_buffers.descriptor_set(self.current_frame),
+                self.uniform_buffers.light_tiles_buffer(self.current_frame),
+                render_extent,
+                inverse_projection,
+            );
+        }
+
+        //ポストプロセスをする場合はシーンをオフスクリーンの画像に描画する
+        let scene_target = match &self.post_process {
+            Some(post_process) => {
+                Self::offscreen_pass_target(post_process.target(0), post_process.render_pass())
+            }
+            None => self.output_pass_target(image_index),
+        };
+
+        //Viewport
+        //dynamic stateなのでpipelineを紐づけた後に毎回設定する必要がある
+        //セカンダリコマンドバッファでも同じ値を設定する
+        let viewport = vk::Viewport::builder()
+            //出力がレンダリングするフレームバッファの領域を指定
+            //x, yはスタート位置
+            .x(0.0)
+            .y(0.0)
+            //縦横のサイズ
+            .width(render_extent.width as _)
+            .height(render_extent.height as _)
+            .min_depth(0.0)
+            .max_depth(1.0)
+            .build();
+
+        //Scissor Rectangle
+        //Viewportはレンダリングされた画像をフレームバッファに対してどの位置に描画をするのか設定するものに対して
+        //Scissor Rectangleはレンダリングされた画像のどのピクセルを使用するかを指定
+        //https://vulkan-tutorial.com/images/viewports_scissors.png
+        let scissor = vk::Rect2D::builder()
+            .offset(vk::Offset2D::builder().x(0).y(0).build())
+            .extent(render_extent)
+            .build();
+
+        //シャドウマップはメインのパスでサンプリングし、メインのパスの画像はポストプロセスでサンプリングする
+        //パスの順番とパスの間のレイアウトの遷移はrender graphが決める
+        let mut graph = self.frame_graph(image_index);
+
+        for id in graph.compile() {
+            graph.cmd_begin_pass(&self.device, &self.synchronization, command_buffer, id);
+
+            let checkpoint = match graph.pass(id) {
+                FramePass::Shadow => Checkpoint::ShadowPass,
+                FramePass::Scene => Checkpoint::ScenePass,
+                FramePass::DebugView => Checkpoint::DebugViewPass,
+                FramePass::PostProcess(_) | FramePass::Bloom(_) => Checkpoint::PostProcessPass,
+            };
+            self.crash_diagnostics
+                .cmd_checkpoint(command_buffer, checkpoint);
+
+            match graph.pass(id) {
+                //視野の外のオブジェクトも影を落とすのでシャドウパスではカリングしない
+                FramePass::Shadow => {
+                    if let (Some(shadow_map), Some(shadow_pipeline)) =
+                        (&self.shadow_map, self.shadow_pipeline)
+                    {
+                        self.cmd_shadow_pass(command_buffer, shadow_map, shadow_pipeline);
+                    }
+                }
+                FramePass::Scene => self.cmd_scene_pass(
+                    command_buffer,
+                    scene_target,
+                    clear_color,
+                    viewport,
+                    scissor,
+                    &visible_objects,
+                ),
+                FramePass::DebugView => self.cmd_debug_view_pass(
+                    command_buffer,
+                    scene_target,
+                    clear_color,
+                    viewport,
+                    scissor,
+                ),
+                FramePass::PostProcess(index) => self.cmd_post_process_pass(
+                    command_buffer,
+                    index,
+                    image_index,
+                    clear_color,
+                    viewport,
+                    scissor,
+                ),
+                FramePass::Bloom(pass) => self.cmd_bloom_pass(command_buffer, pass),
+            }
+        }
+
+        graph.cmd_finish(&self.device, &self.synchronization, command_buffer);
+
+        //render_scaleのtargetに描画した場合は、swapchainの大きさに拡大縮小してblitしpresentできるようにする
+        if let Some(render_scale) = &self.render_scale {
+            if render_scale.target().is_some() {
+                render_scale.cmd_blit(
+                    &self.device,
+                    &self.synchronization,
+                    command_buffer,
+                    self.swap_chain_images[image_index],
+                    self.swap_chain_extent,
+                );
+            }
+
+            self.frame_stats.record_resolution(
+                (render_extent.width, render_extent.height),
+                (self.swap_chain_extent.width, self.swap_chain_extent.height),
+            );
+        }
+
+        self.cmd_mirror_window_targets(command_buffer, image_index, mirrors);
+
+        //頂点シェーダーが書き込んだreadbackの値をframe_timelineの待機後にCPUから読めるようにする
+        if self.ubo_stress {
+            let memory_barrier = vk::MemoryBarrier2::builder()
+                .src_stage_mask(vk::PipelineStageFlags2::VERTEX_SHADER)
+                .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+                .dst_access_mask(vk::AccessFlags2::HOST_READ)
+                .build();
+
+            self.synchronization.cmd_pipeline_barrier(
+                &self.device,
+                command_buffer,
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+        }
+
+        self.crash_diagnostics
+            .cmd_checkpoint(command_buffer, Checkpoint::FrameEnd);
+
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            gpu_timer.cmd_end(&self.device, command_buffer, self.current_frame);
+        }
+
+        unsafe { self.device.end_command_buffer(command_buffer).unwrap() };
+    }
+
+    //全てのパスを記録した後のメインのswapchainの画像をmirrorのウィンドウの画像にblitする
+    fn cmd_mirror_window_targets(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        mirrors: &[(WindowId, u32)],
+    ) {
+        for (id, target_image_index) in mirrors {
+            self.mirror_swap_chain(id).cmd_mirror(
+                &self.device,
+                &self.synchronization,
+                command_buffer,
+                self.swap_chain_images[image_index],
+                self.swap_chain_extent,
+                *target_image_index,
+            );
+        }
+    }
+
+    //メインのパス、不透明な物と床と半透明な物などをtargetに描画する
+    //visible_objectsはカリングした後の不透明なオブジェクト
+    fn cmd_scene_pass(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        target: PassTarget,
+        clear_color: vk::ClearValue,
+        viewport: vk::Viewport,
+        scissor: vk::Rect2D,
+        visible_objects: &[usize],
+    ) {
+        self.cmd_begin_pass(
+            command_buffer,
+            target,
+            clear_color,
+            self.parallel_renderer.is_some(),
+        );
+
+        //コマンドバッファは毎フレーム記録し直しているので切り替えはすぐに反映される
+        //vertex pullingとray queryのパイプラインにはワイヤーフレームの版が無いので優先する
+        //オーバードローはワイヤーフレームでは数えないので優先し、法線と深度の表示はワイヤーフレームより後にする
+        let overdraw_pipeline = self
+            .overdraw_pipelines
+            .filter(|_| self.shows_overdraw())
+            .map(|((pipeline, _), _)| pipeline);
+        let debug_view_pipeline = self
+            .debug_view_pipelines
             .iter()
-            .map(|name| name.as_ptr())
-            .collect::<Vec<_>>();
+            .find(|&&(debug_view, _, _)| debug_view == self.debug_view)
+            .map(|&(_, pipeline, _)| pipeline);
+
+        let pipeline = match (
+            self.pulling_pipeline,
+            self.ray_query_pipeline,
+            overdraw_pipeline,
+            self.wireframe_pipeline,
+            debug_view_pipeline,
+        ) {
+            (Some((pulling_pipeline, _)), _, _, _, _) if self.pulls_vertices() => pulling_pipeline,
+            (_, Some((ray_query_pipeline, _)), _, _, _) if self.traces_shadows() => {
+                ray_query_pipeline
+            }
+            (_, _, Some(overdraw_pipeline), _, _) => overdraw_pipeline,
+            (_, _, _, Some(wireframe_pipeline), _) if self.wireframe => wireframe_pipeline,
+            (_, _, _, _, Some(debug_view_pipeline)) => debug_view_pipeline,
+            _ => self.pipeline,
+        };
+
+        unsafe {
+            match &self.parallel_renderer {
+                Some(parallel_renderer) => {
+                    let target = match self.dynamic_rendering {
+                        Some(_) => SecondaryTarget::Dynamic {
+                            color_format: Self::scene_color_format(
+                                self.swap_chain_color_format,
+                                self.post_process.is_some(),
+                            )
+                            .format(),
+                            depth_format: self.depth_buffer.format,
+                            samples: self.msaa_samples,
+                        },
+                        None => SecondaryTarget::RenderPass {
+                            render_pass: target.render_pass,
+                            framebuffer: target.framebuffer,
+                        },
+                    };
+
+                    let state = DrawState {
+                        pipeline,
+                        pipeline_layout: self.pipeline_layout,
+                        uniform_descriptor_set: self
+                            .uniform_buffers
+                            .descriptor_set(self.current_frame),
+                        object_descriptor_set: self
+                            .object_buffers
+                            .descriptor_set(self.current_frame),
+                        mesh_buffers: self.mesh.buffers(),
+                        index_count: self.mesh.index_count(),
+                        viewport,
+                        scissor,
+                    };
+
+                    let object_draws = visible_objects
+                        .iter()
+                        .map(|&index| self.object_draws[index])
+                        .collect::<Vec<_>>();
+
+                    let secondary_command_buffers = parallel_renderer.record(
+                        &self.device,
+                        self.current_frame,
+                        target,
+                        &state,
+                        &object_draws,
+                    );
+
+                    //SECONDARY_COMMAND_BUFFERSで始めたrender passの中ではこれ以外のコマンドは記録できない
+                    self.device
+                        .cmd_execute_commands(command_buffer, &secondary_command_buffers);
+                }
+                None => {
+                    //2つのビューの描画をまとめて1つのクエリで数える
+                    if let Some(pipeline_statistics) = &self.pipeline_statistics {
+                        pipeline_statistics.cmd_begin(
+                            &self.device,
+                            command_buffer,
+                            self.current_frame,
+                        );
+                    }
+
+                    let mut avoided_binds = 0;
+
+                    //--split-screenの場合は左右のviewportとscissorでシーンを2回描画する
+                    //viewportとscissorはどのパイプラインでもdynamic stateなので、パイプラインを切り替えても引き継がれる
+                    for (view, (viewport, scissor)) in
+                        self.scene_views(viewport, scissor).into_iter().enumerate()
+                    {
+                        self.device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+                        self.device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+
+                        //self.view_descriptor_setがこのビューのUniform Bufferを返すようにする
+                        self.current_view = view;
+
+                        //不透明な物が先でマテリアルごとにまとまり、半透明な物はその後ろに奥から順に並んでいる
+                        let draw_calls = self.draw_calls(visible_objects);
+                        let drawables = self.drawables(pipeline, &draw_calls);
+                        let (opaque_drawables, transparent_drawables) =
+                            drawables.split_at(drawables.partition_point(|drawable| {
+                                drawable.draw_call.blend_mode == BlendMode::Opaque
+                            }));
+
+                        //Graphics Pipelineとset = 0のこのフレームのUniform Bufferをコマンドバッファに対して紐づける
+                        let mut opaque_binds = BindState::default();
+                        opaque_binds.bind_material(
+                            &self.device,
+                            command_buffer,
+                            self.default_material(pipeline),
+                        );
+                        self.cmd_bind_shadow_map(command_buffer);
+                        self.cmd_bind_ray_query_shadows(command_buffer);
+                        self.cmd_bind_material_textures(command_buffer);
+                        self.cmd_bind_vertex_pulling(command_buffer);
+                        self.cmd_bind_mesh_shading(command_buffer);
+                        self.cmd_bind_texture_array(command_buffer);
+                        self.cmd_bind_normal_map(command_buffer);
+                        self.cmd_bind_skinning(command_buffer);
+                        self.cmd_bind_overdraw_counters(command_buffer);
+
+                        opaque_binds.bind_mesh(&self.device, command_buffer, &self.mesh);
+
+                        if let Some(instanced_grid) = &self.instanced_grid {
+                            instanced_grid.cmd_draw(
+                                &self.device,
+                                command_buffer,
+                                self.mesh.index_count(),
+                            );
+                        }
+
+                        self.cmd_drawables(command_buffer, &mut opaque_binds, opaque_drawables);
+
+                        //床はメインのパイプラインのまま描画する
+                        if let Some(ground) = &self.ground {
+                            ground.cmd_draw(
+                                &self.device,
+                                command_buffer,
+                                self.pipeline_layout,
+                                &self.object_buffers,
+                                self.current_frame,
+                            );
+                        }
+
+                        //不透明な物の深度値と比べるので、メッシュと床の後に描画する
+                        //パイプラインレイアウトが違うので、この後の描画はset = 0から紐づけ直す
+                        if let (Some(occlusion_culling), Some(occlusion_pipeline)) =
+                            (&self.occlusion_culling, self.occlusion_pipeline)
+                        {
+                            occlusion_culling.cmd_draw_queries(
+                                &self.device,
+                                command_buffer,
+                                occlusion_pipeline,
+                                self.view_descriptor_set(),
+                                self.current_frame,
+                            );
+                        }
+
+                        //不透明なメッシュの上に重ねるので、メッシュと床の後に描画する
+                        if let (Some(normals_pipeline), true) =
+                            (self.normals_pipeline, self.show_normals)
+                        {
+                            self.cmd_draw_normals(
+                                command_buffer,
+                                normals_pipeline,
+                                opaque_drawables,
+                            );
+                        }
+
+                        //パイプラインレイアウトが違うので、この後の描画はset = 0から紐づけ直す
+                        if let (
+                            Some(tessellated_plane),
+                            Some((pipeline, wireframe_pipeline, pipeline_layout)),
+                        ) = (&self.tessellated_plane, self.tessellation_pipeline)
+                        {
+                            let pipeline = match wireframe_pipeline {
+                                Some(wireframe_pipeline) if self.wireframe => wireframe_pipeline,
+                                _ => pipeline,
+                            };
+
+                            tessellated_plane.cmd_draw(
+                                &self.device,
+                                command_buffer,
+                                (pipeline, pipeline_layout),
+                                self.view_descriptor_set(),
+                            );
+                        }
+
+                        if let (Some(particles), Some((pipeline, pipeline_layout))) =
+                            (&self.particles, self.particle_pipeline)
+                        {
+                            self.device.cmd_bind_pipeline(
+                                command_buffer,
+                                vk::PipelineBindPoint::GRAPHICS,
+                                pipeline,
+                            );
+                            self.device.cmd_bind_descriptor_sets(
+                                command_buffer,
+                                vk::PipelineBindPoint::GRAPHICS,
+                                pipeline_layout,
+                                0,
+                                &[self.view_descriptor_set()],
+                                &[],
+                            );
+
+                            particles.cmd_draw(&self.device, command_buffer, self.current_frame);
+                        }
+
+                        //一番遠い深度値で描画するので、不透明な物の後に描画すれば隠れる部分のフラグメントを省ける
+                        if let (Some(skybox), Some(skybox_pipeline)) =
+                            (&self.skybox, self.skybox_pipeline)
+                        {
+                            skybox.cmd_draw(
+                                &self.device,
+                                command_buffer,
+                                skybox_pipeline,
+                                self.view_descriptor_set(),
+                            );
+                        }
+
+                        //半透明な物は後ろにある物と混ぜるので、スカイボックスも含めて全て描画してから重ねる
+                        //床やスカイボックスなどが紐づけを変えているので、最初の描画で紐づけ直す
+                        let mut transparent_binds = BindState::default();
+                        self.cmd_drawables(
+                            command_buffer,
+                            &mut transparent_binds,
+                            transparent_drawables,
+                        );
+
+                        //スプライトは深度値を持たないので、3Dの物を全て描画した後に重ねる
+                        if let (Some(sprite_batch), Some(sprite_pipeline)) =
+                            (&self.sprite_batch, self.sprite_pipeline)
+                        {
+                            sprite_batch.cmd_draw(
+                                &self.device,
+                                command_buffer,
+                                sprite_pipeline,
+                                self.view_descriptor_set(),
+                                self.current_frame,
+                            );
+                        }
+
+                        //BindStateはdrawablesを通してselfを借りているので先に数を取り出す
+                        avoided_binds +=
+                            opaque_binds.avoided_binds() + transparent_binds.avoided_binds();
+                    }
+
+                    self.current_view = 0;
+                    self.frame_stats.record_avoided_binds(avoided_binds);
+
+                    //全てのビューを描画した後、文字の下に重ねる
+                    if let (Some((pipeline, pipeline_layout)), true) =
+                        (self.light_tiles_pipeline, self.shows_light_tiles())
+                    {
+                        self.device.cmd_bind_pipeline(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            pipeline,
+                        );
+                        self.device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+                        self.device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+                        self.device.cmd_bind_descriptor_sets(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            pipeline_layout,
+                            0,
+                            &[self.uniform_buffers.descriptor_set(self.current_frame)],
+                            &[],
+                        );
+                        //頂点はシェーダー側でvertex_indexから作る
+                        self.device.cmd_draw(command_buffer, 3, 1, 0, 0);
+                    }
+
+                    if let Some(pipeline_statistics) = &mut self.pipeline_statistics {
+                        pipeline_statistics.cmd_end(
+                            &self.device,
+                            command_buffer,
+                            self.current_frame,
+                        );
+                    }
+                }
+            }
+        }
+
+        //ポストプロセスをしない場合はこのパスがswapchainに描画する
+        //オーバードローの表示ではヒートマップで上書きされるので、そのパスで重ねる
+        if self.post_process.is_none() && !self.shows_overdraw() {
+            self.cmd_draw_overlays(command_buffer);
+        }
+
+        //render_pass系コマンドの終わり
+        self.cmd_end_pass(command_buffer);
+    }
+
+    //targetに描画するパスを始める
+    //secondaryがtrueの場合はパスの中身をセカンダリコマンドバッファで記録する
+    fn cmd_begin_pass(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        target: PassTarget,
+        clear_color: vk::ClearValue,
+        secondary: bool,
+    ) {
+        match &self.dynamic_rendering {
+            Some(dynamic_rendering) => self.cmd_begin_dynamic_rendering(
+                dynamic_rendering,
+                command_buffer,
+                target,
+                clear_color,
+                secondary,
+            ),
+            None => unsafe {
+                //attachmentsと同じ順番
+                let clear_values = [clear_color, DepthBuffer::clear_value(self.config.depth)];
+
+                let subpass_contents = if secondary {
+                    vk::SubpassContents::SECONDARY_COMMAND_BUFFERS
+                } else {
+                    vk::SubpassContents::INLINE
+                };
+
+                let render_pass_info = vk::RenderPassBeginInfo::builder()
+                    //レンダーパスとカラーアタッチメントとして登録されたframebufferを紐づけ
+                    .render_pass(target.render_pass)
+                    .framebuffer(target.framebuffer)
+                    .render_area(
+                        //レンダリング領域の大きさを指定
+                        //レンダリング領域とはシェーダのロードとストアが行われる場所
+                        //この領域外のピクセルの値は未定義となる
+                        vk::Rect2D::builder()
+                            .offset(vk::Offset2D::builder().x(0).y(0).build())
+                            .extent(self.render_extent())
+                            .build(),
+                    )
+                    //color_attachmentの定義時に指定したLOAD_OP_CLEARに使用するクリア値の設定
+                    .clear_values(&clear_values)
+                    .build();
+
+                //コマンドを記録するすべての関数はprefixとしてcmd(本家だとvkCmd)がつく
+                //基本的にこれらの関数の実行時にはコマンドを記録しているだけで実際に実行しているわけではないので、返り値がResultになっていない
+                //個のコマンドを使用することで描画が始まる
+                self.device.cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_info,
+                    //render_pass内の描画コマンドをどのように提供するかを指定
+                    //INLINE: render_pass内のコマンドはPRIMARYなコマンドバッファ自体に埋め込まれSECODARYは実行されない
+                    //SECONDARY_COMMAND_BUFFER: render_pass内のコマンドはSECONDARYなコマンドバッファから実行される
+                    subpass_contents,
+                );
+            },
+        }
+    }
+
+    //render passのfinal_layoutで行っていたpresentやサンプリングのための遷移は、dynamic renderingの場合はframe_graphが次のパスの前に行う
+    fn cmd_end_pass(&self, command_buffer: vk::CommandBuffer) {
+        match &self.dynamic_rendering {
+            Some(dynamic_rendering) => {
+                dynamic_rendering.cmd_end_rendering(&self.device, command_buffer)
+            }
+            None => unsafe { self.device.cmd_end_render_pass(command_buffer) },
+        }
+    }
+
+    //presentするswapchainの画像
+    fn swap_chain_pass_target(&self, image_index: usize) -> PassTarget {
+        let framebuffer = match self.dynamic_rendering {
+            Some(_) => vk::Framebuffer::null(),
+            None => self.swap_chain_frame_buffers[image_index],
+        };
+
+        PassTarget {
+            render_pass: self.render_pass,
+            framebuffer,
+            image_view: self.swap_chain_image_views[image_index],
+            msaa_view: self
+                .msaa_target
+                .as_ref()
+                .map_or(vk::ImageView::null(), |msaa_target| msaa_target.view),
+        }
+    }
+
+    //最後のパスの描画先、render_scaleのtargetがある場合はswapchainの代わりにそこへ描画する
+    fn output_pass_target(&self, image_index: usize) -> PassTarget {
+        if let Some(render_scale) = &self.render_scale {
+            if let Some(target) = render_scale.target() {
+                return PassTarget {
+                    render_pass: render_scale.render_pass(),
+                    framebuffer: target.framebuffer,
+                    image_view: target.view,
+                    msaa_view: vk::ImageView::null(),
+                };
+            }
+        }
 
-        if ENABLE_VALIDATION_LAYERS {
-            create_info = create_info.enabled_layer_names(&layer_names_ptrs);
+        self.swap_chain_pass_target(image_index)
+    }
+
+    //次のパスでサンプリングするオフスクリーンの画像
+    fn offscreen_pass_target(target: &OffscreenTarget, render_pass: vk::RenderPass) -> PassTarget {
+        PassTarget {
+            render_pass,
+            framebuffer: target.framebuffer,
+            image_view: target.view,
+            msaa_view: vk::ImageView::null(),
         }
+    }
 
-        //存在しなかったりサポートされていない機能を有効にしようとするとエラーが出る
-        let device =
-            unsafe { instance.create_device(physical_device, &create_info, None) }.unwrap();
+    //シーンのパスが数えたオーバードローを、フルスクリーンの三角形でシーンの描画先にヒートマップとして上書きする
+    fn cmd_debug_view_pass(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        target: PassTarget,
+        clear_color: vk::ClearValue,
+        viewport: vk::Viewport,
+        scissor: vk::Rect2D,
+    ) {
+        let overdraw_counters = self.overdraw_counters.as_ref().unwrap();
+        let (_, (pipeline, pipeline_layout)) = self.overdraw_pipelines.unwrap();
+
+        //render passの中ではシーンのパスの書き込みを待てないので、パスを始める前にバリアを置く
+        overdraw_counters.cmd_wait_counts(
+            &self.device,
+            &self.synchronization,
+            command_buffer,
+            self.current_frame,
+        );
 
-        //論理デバイスからキューを作成、
-        //引数は必要なキューのキューファミリーの番号とキューインデックス
-        //キューインデックスは複数存在するキューのインデックス
+        //全画面を上書きするのでクリア値は使われない
+        self.cmd_begin_pass(command_buffer, target, clear_color, false);
 
-        //グラフィックスファミリーキューインデックス
-        let graphics_queue =
-            unsafe { device.get_device_queue(indices.graphics_family.unwrap(), 0) };
+        unsafe {
+            self.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline,
+            );
+            self.device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            self.device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+            overdraw_counters.cmd_bind(
+                &self.device,
+                command_buffer,
+                pipeline_layout,
+                0,
+                self.current_frame,
+            );
+            //頂点はシェーダー側でvertex_indexから作る
+            self.device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
 
-        //
-        let present_queue = unsafe { device.get_device_queue(indices.present_family.unwrap(), 0) };
+        //ポストプロセスをしない場合はこのパスがswapchainに描画する
+        if self.post_process.is_none() {
+            self.cmd_draw_overlays(command_buffer);
+        }
 
-        (device, graphics_queue, present_queue)
+        self.cmd_end_pass(command_buffer);
     }
 
-    fn create_surface(
-        instance: &Instance,
-        entry: &Entry,
-        window: &Window,
-    ) -> (Surface, SurfaceKHR) {
-        let surface = Surface::new(entry, instance);
-        let surface_khr =
-            unsafe { ash_window::create_surface(entry, instance, window, None).unwrap() };
+    //index番目のエフェクトを掛ける、最後のエフェクトはswapchainかrender_scaleのtargetに書き出す
+    //各エフェクトは前のパスの画像をサンプリングしてフルスクリーンの三角形を描画する
+    fn cmd_post_process_pass(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        index: usize,
+        image_index: usize,
+        clear_color: vk::ClearValue,
+        viewport: vk::Viewport,
+        scissor: vk::Rect2D,
+    ) {
+        let post_process = self.post_process.as_ref().unwrap();
+        let (pipeline, pipeline_layout) = self.post_process_pipelines[index];
+
+        let is_last = index + 1 == post_process.effects().len();
+
+        let target = if is_last {
+            self.output_pass_target(image_index)
+        } else {
+            Self::offscreen_pass_target(post_process.target(index + 1), post_process.render_pass())
+        };
 
-        info!("surface: {:?}", surface_khr);
+        //全画面を上書きするのでクリア値は使われない
+        self.cmd_begin_pass(command_buffer, target, clear_color, false);
 
-        (surface, surface_khr)
-    }
+        unsafe {
+            self.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline,
+            );
+            self.device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            self.device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &[post_process.descriptor_set(index)],
+                &[],
+            );
 
-    fn create_swap_chain(
-        instance: &Instance,
-        device: &Device,
-        physical_device: PhysicalDevice,
-        surface: &Surface,
-        surface_khr: SurfaceKHR,
-        window_size: (u32, u32),
-    ) -> (Swapchain, SwapchainKHR, vk::Format, vk::Extent2D) {
-        let swap_chain_support =
-            SwapChainSupportDetails::new(physical_device, surface, surface_khr);
+            //set = 1にはFramePass::Bloomのパスでぼかした画像を紐づける
+            if let Some(bloom) = self.bloom.as_ref().filter(|_| self.is_bloom_effect(index)) {
+                self.device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    1,
+                    &[bloom.result_descriptor_set()],
+                    &[],
+                );
+                self.cmd_push_bloom_constants(command_buffer, pipeline_layout, [0.0, 0.0]);
+                self.cmd_push_tonemap_constants(
+                    command_buffer,
+                    pipeline_layout,
+                    mem::size_of::<BloomConstants>() as u32,
+                );
+            }
 
-        let surface_format = swap_chain_support.choose_swap_surface_format();
-        let present_mode = swap_chain_support.choose_swap_present_mode();
-        let extent = swap_chain_support.choose_swap_extent(window_size.0, window_size.1);
+            if post_process.effects()[index] == PostEffect::Tonemap {
+                self.cmd_push_tonemap_constants(command_buffer, pipeline_layout, 0);
+            }
 
-        //swapchainに含められる画像の枚数を決める
-        //少なすぎると空き容量がなくてレンダリングが止まってしまう
-        let mut image_count = swap_chain_support.capabilities.min_image_count + 1;
+            //頂点はシェーダー側でvertex_indexから作る
+            self.device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
 
-        //max_image_countが0の場合は上限が存在しないという意味
-        if swap_chain_support.capabilities.max_image_count > 0
-            && image_count > swap_chain_support.capabilities.max_image_count
-        {
-            image_count = swap_chain_support.capabilities.max_image_count;
+        //エフェクトを掛けないように、最後のエフェクトの後に重ねる
+        if is_last {
+            self.cmd_draw_overlays(command_buffer);
         }
 
-        let mut create_info = vk::SwapchainCreateInfoKHR::builder()
-            .surface(surface_khr)
-            .min_image_count(image_count)
-            .image_format(surface_format.format)
-            .image_color_space(surface_format.color_space)
-            .image_extent(extent)
-            //各画像が持つレイヤの数
-            //ステレオコピックアプリケーションなどを作成する時に使用
-            //スタン時の演出とかにも使える？
-            .image_array_layers(1)
-            //Swapchain内の画像をどのように扱うかを指定
-            //今回は直接レンダリングするのでCOLOR_ATTACHMENTを採用
-            //別の場所に画像をレンダリングしてあとからメモリ操作などで送信するTRANSFER_DSTなどもある
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT);
+        self.cmd_end_pass(command_buffer);
+    }
 
-        let indices = QueueFamilyIndices::find_queue_families(
-            instance,
-            surface,
-            surface_khr,
-            physical_device,
-        );
+    //post_process.effects()[index]がブルームで、bloomの画像を足すかどうか
+    fn is_bloom_effect(&self, index: usize) -> bool {
+        self.post_process.as_ref().map_or(false, |post_process| {
+            post_process.effects()[index] == PostEffect::Bloom
+        })
+    }
 
-        //キューファミリーのindexを配列に
-        let queue_family_indices = [
-            indices.graphics_family.unwrap(),
-            indices.present_family.unwrap(),
-        ];
+    //Bloomの1段分のパス、描画する大きさは段ごとに違うのでviewportとscissorもここで決める
+    //Extractはブルームのエフェクトの入力を、PostProcessのDescriptor Setでそのまま読む
+    fn cmd_bloom_pass(&self, command_buffer: vk::CommandBuffer, pass: BloomPass) {
+        let bloom = self.bloom.as_ref().unwrap();
+        let post_process = self.post_process.as_ref().unwrap();
 
-        //SwapChainが扱う画像が複数の種類のキューファミリーがまたがって使用するかどうかの設定
-        //今回の場合はグラフィックスファミリーとプレゼンテーションファミリーが同一のキューかどうかを調べてそれぞれ設定を確認する
-        if indices.graphics_family.unwrap() != indices.present_family.unwrap() {
-            //CONCURRENTは画像の所有権の移動なしに複数のキューファミリーをまたがって使用することができる
-            create_info = create_info
-                .image_sharing_mode(SharingMode::CONCURRENT)
-                //CONCURRENTではどのキューファミリー間で所有権を共有するかを事前にしているする必要がある
-                .queue_family_indices(&queue_family_indices);
-        } else {
-            //EXCLUSIVEは１つのキューファミリが所有権を持ち、複数のキューファミリーをまたがって使用する場合は明示的に所有権を移動しなければならない
-            //パフォーマンス的には最高
-            create_info = create_info.image_sharing_mode(SharingMode::EXCLUSIVE);
-        }
+        let source_index = post_process
+            .effects()
+            .iter()
+            .position(|&effect| effect == PostEffect::Bloom)
+            .unwrap();
 
-        let create_info = create_info
-            //swapchain内の画像に対して90度時計回りなどのtransformの変換を指定できる
-            //今回の場合は何もしない
-            .pre_transform(swap_chain_support.capabilities.current_transform)
-            //ほかウィンドウとのブレンドをどうするか指定
-            //OPAQUEはアルファを無視
-            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-            .present_mode(present_mode)
-            //他ウィンドウに隠れたピクセルをクリップするかどうか
-            .clipped(true)
-            //Vulkanではアプリケーションの実行中にスワップチェンが無効または最適化されなくなる可能性がある
-            //その場合はスワップチェーンを0から再度作らなければいけないため、その場合の古いSwapChainの参照を渡す
-            //今回はSwapChainは一つしか作らないことを仮定
-            .old_swapchain(vk::SwapchainKHR::null())
+        let &(_, pipeline, pipeline_layout) = self
+            .bloom_pipelines
+            .iter()
+            .find(|(stage, _, _)| *stage == pass.stage())
+            .unwrap();
+
+        let extent = bloom.extent(pass);
+
+        let viewport = vk::Viewport::builder()
+            .x(0.0)
+            .y(0.0)
+            .width(extent.width as _)
+            .height(extent.height as _)
+            .min_depth(0.0)
+            .max_depth(1.0)
             .build();
 
-        let swap_chain = Swapchain::new(instance, device);
-        let swap_chain_khr = unsafe { swap_chain.create_swapchain(&create_info, None).unwrap() };
+        let scissor = vk::Rect2D::builder()
+            .offset(vk::Offset2D::builder().x(0).y(0).build())
+            .extent(extent)
+            .build();
 
-        info!("swapchain: {:?}", swap_chain_khr);
+        bloom.cmd_begin_pass(
+            &self.device,
+            self.dynamic_rendering.as_ref(),
+            command_buffer,
+            pass,
+        );
 
-        (swap_chain, swap_chain_khr, surface_format.format, extent)
-    }
+        unsafe {
+            self.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline,
+            );
+            self.device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            self.device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &bloom.descriptor_sets(pass, post_process.descriptor_set(source_index)),
+                &[],
+            );
+        }
 
-    fn get_swap_chain_images(
-        swap_chain: &Swapchain,
-        swap_chain_khr: SwapchainKHR,
-    ) -> Vec<vk::Image> {
-        //swapchainで保持している画像のハンドルを取得する
-        unsafe { swap_chain.get_swapchain_images(swap_chain_khr) }.unwrap()
-    }
+        self.cmd_push_bloom_constants(command_buffer, pipeline_layout, bloom.texel_size(pass));
 
-    fn create_image_views(
-        device: &Device,
-        swap_chain_images: &Vec<vk::Image>,
-        swap_chain_image_format: vk::Format,
-    ) -> Vec<vk::ImageView> {
-        let mut swap_chain_image_views = vec![];
+        unsafe { self.device.cmd_draw(command_buffer, 3, 1, 0, 0) };
 
-        for image in swap_chain_images {
-            let create_info = vk::ImageViewCreateInfo::builder()
-                .image(*image)
-                //画像を1Dテクスチャ、2Dテクスチャ、3Dテクスチャ、キューマップとして扱うことができる
-                .view_type(vk::ImageViewType::TYPE_2D)
-                .format(swap_chain_image_format)
-                .components(
-                    //swizzleなマッピングをすることができる
-                    //例えばモノクロなテクスチャを全て赤色に割り当てて出力したりなど
-                    //今回はすべてデフォルトで行う
-                    vk::ComponentMapping::builder()
-                        .r(vk::ComponentSwizzle::IDENTITY)
-                        .g(vk::ComponentSwizzle::IDENTITY)
-                        .b(vk::ComponentSwizzle::IDENTITY)
-                        .a(vk::ComponentSwizzle::IDENTITY)
-                        .build(),
-                )
-                .subresource_range(
-                    //画像自体の目的が何であるか
-                    //画像の土の部分にアクセスすべきかを書くことができる
-                    //今回はミップマップレベルやマルチレイヤーは無しで設定
-                    vk::ImageSubresourceRange::builder()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .base_mip_level(0)
-                        .level_count(1)
-                        .base_array_layer(0)
-                        .layer_count(1)
-                        .build(),
-                )
-                .build();
+        bloom.cmd_end_pass(
+            &self.device,
+            self.dynamic_rendering.as_ref(),
+            command_buffer,
+        );
+    }
 
-            swap_chain_image_views
-                .push(unsafe { device.create_image_view(&create_info, None).unwrap() });
+    //ブルームの全てのパスとエフェクトのパスで同じBloomConstantsを渡す
+    fn cmd_push_bloom_constants(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        texel_size: [f32; 2],
+    ) {
+        let constants = BloomConstants {
+            texel_size,
+            threshold: bloom::THRESHOLD,
+            intensity: self.bloom_intensity,
+        };
+
+        unsafe {
+            self.device.cmd_push_constants(
+                command_buffer,
+                pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                std::slice::from_raw_parts(
+                    &constants as *const BloomConstants as *const u8,
+                    mem::size_of::<BloomConstants>(),
+                ),
+            );
         }
+    }
 
-        info!("Create SwapChain Image View");
+    //offsetはpush constantの範囲の中でTonemapConstantsを置く位置
+    fn cmd_push_tonemap_constants(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        offset: u32,
+    ) {
+        let constants = TonemapConstants::new(self.tonemapper, self.exposure);
 
-        //テクスチャとして使う分には準備できているが、レンダーターゲットとしてはまだ設定が必要
-        //その設定とはフレームバッファと呼ばれるもう一段回のインダイレクトが必要だがこれを用意するのにまずグラフィックスパイプラインを設定する必要がある
-        swap_chain_image_views
+        unsafe {
+            self.device.cmd_push_constants(
+                command_buffer,
+                pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                offset,
+                std::slice::from_raw_parts(
+                    &constants as *const TonemapConstants as *const u8,
+                    mem::size_of::<TonemapConstants>(),
+                ),
+            );
+        }
     }
 
-    fn create_graphics_pipeline(
-        device: &Device,
-        swap_chain_extent: vk::Extent2D,
-        render_pass: vk::RenderPass,
-    ) -> (vk::Pipeline, vk::PipelineLayout) {
-        //プログラマブルステージの設定
+    //ブルームかトーンマッピングのエフェクトがあり、tonemapperとexposureを使うかどうか
+    fn has_tonemapping(&self) -> bool {
+        self.post_process.as_ref().map_or(false, |post_process| {
+            post_process
+                .effects()
+                .iter()
+                .any(|&effect| matches!(effect, PostEffect::Bloom | PostEffect::Tonemap))
+        })
+    }
 
-        //Create Shader Module
+    //swapchainに描画するパスの最後に、他の全ての描画の上にdebug_textとeguiの順で重ねる
+    fn cmd_draw_overlays(&self, command_buffer: vk::CommandBuffer) {
+        if let (Some(debug_text), Some(debug_text_pipeline)) =
+            (&self.debug_text, self.debug_text_pipeline)
+        {
+            debug_text.cmd_draw(
+                &self.device,
+                command_buffer,
+                debug_text_pipeline,
+                self.current_frame,
+            );
+        }
 
-        //ここの環境変数はrust-gpu側が設定をしてくれる
-        const SHADER_PATH: &str = env!("rust_shader.spv");
-        const SHADER_CODE: &[u8] = include_bytes!(env!("rust_shader.spv"));
+        if let (Some(egui_overlay), Some(egui_pipeline)) = (&self.egui_overlay, self.egui_pipeline)
+        {
+            egui_overlay.cmd_draw(
+                &self.device,
+                command_buffer,
+                egui_pipeline,
+                self.current_frame,
+                self.render_extent(),
+            );
+        }
+    }
 
-        info!("Shader Path: {}", SHADER_PATH);
-        info!("Shader Length: {}", SHADER_CODE.len());
+    //このフレームのパスと、パスの間で受け渡す画像を登録する
+    //画像はどれも前のフレームの内容を使わないので、前のフレームの最後のアクセスが終わるのを待ってUNDEFINEDから遷移する
+    //render passを使う場合はrender pass自身が同じ遷移を行うので、グラフはパスの順番だけを決める
+    fn frame_graph(&self, image_index: usize) -> RenderGraph<FramePass> {
+        let mut graph = RenderGraph::new(self.dynamic_rendering.is_some());
+
+        //最後のパスの描画先
+        //render_scaleのtargetに描画する場合は、前のフレームのblitで読み終わってから書き込み、全てのパスの後にblitで読めるようにする
+        //swapchainの画像へのblitとpresentへの遷移はRenderScale::cmd_blitが行う
+        let output_image = match self.render_scale.as_ref().and_then(RenderScale::target) {
+            Some(target) => {
+                let image = graph.import_image(
+                    target.image,
+                    Self::color_subresource_range(),
+                    ImageUse::undefined(vk::PipelineStageFlags2::BLIT, vk::AccessFlags2::NONE),
+                );
+                graph.set_final_use(image, ImageUse::BLIT_SOURCE);
+                image
+            }
+            //acquireのSemaphoreはCOLOR_ATTACHMENT_OUTPUTで待っているのでそこから始める
+            None => {
+                let image = graph.import_image(
+                    self.swap_chain_images[image_index],
+                    Self::color_subresource_range(),
+                    ImageUse::undefined(
+                        vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                        vk::AccessFlags2::NONE,
+                    ),
+                );
+                graph.set_final_use(image, ImageUse::PRESENT);
+                image
+            }
+        };
 
-        let shader_module = Self::create_shader_module(device, SHADER_CODE);
+        //デプスバッファは全てのパスで使い回すので、前のパスの深度テストでの書き込みが終わってから使う
+        let depth_buffer = graph.import_image(
+            self.depth_buffer.image,
+            self.depth_buffer.subresource_range(),
+            ImageUse::undefined(
+                vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+                vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+        );
 
-        //Lifetimeを確保するために一度変数にしている
-        let main_vs = CString::new("main_vs").unwrap();
-        let main_fs = CString::new("main_fs").unwrap();
+        //パスごとの描画先、ポストプロセスをする場合はシーンとエフェクトがオフスクリーンの画像に描画し、最後のエフェクトだけがswapchainに描画する
+        //オフスクリーンの画像は前のフレームで次のパスがサンプリングし終わるのを待つ
+        let mut color_targets = self
+            .post_process
+            .iter()
+            .flat_map(|post_process| {
+                (0..post_process.effects().len()).map(|index| post_process.target(index).image)
+            })
+            .map(|image| {
+                graph.import_image(
+                    image,
+                    Self::color_subresource_range(),
+                    ImageUse::undefined(
+                        vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                        vk::AccessFlags2::NONE,
+                    ),
+                )
+            })
+            .collect::<Vec<_>>();
+        color_targets.push(output_image);
 
-        let vert_shader_stage_info = vk::PipelineShaderStageCreateInfo::builder()
-            //fragmentやvertexまたgeometryなどのどこのシェーダーステージの物なのかを指定する
-            .stage(vk::ShaderStageFlags::VERTEX)
-            .module(shader_module)
-            .name(main_vs.as_c_str())
-            //これはシェーダ内で定数を設定する時に外部から設定できるのでそのときに使用するもの
-            //.specialization_info()
-            .build();
+        //ブルームの画像も前のフレームで次のパスがサンプリングし終わるのを待つ
+        let bloom_images = self
+            .bloom
+            .iter()
+            .flat_map(Bloom::images)
+            .map(|image| {
+                let handle = graph.import_image(
+                    image,
+                    Self::color_subresource_range(),
+                    ImageUse::undefined(
+                        vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                        vk::AccessFlags2::NONE,
+                    ),
+                );
+                (image, handle)
+            })
+            .collect::<Vec<_>>();
+        let bloom_image = |image: vk::Image| {
+            bloom_images
+                .iter()
+                .find(|&&(other, _)| other == image)
+                .map(|&(_, handle)| handle)
+                .unwrap()
+        };
 
-        let frag_shader_stage_info = vk::PipelineShaderStageCreateInfo::builder()
-            .stage(vk::ShaderStageFlags::FRAGMENT)
-            .module(shader_module)
-            .name(main_fs.as_c_str())
-            .build();
+        //MSAAの画像もデプスバッファと同じく、前のフレームのresolveが終わってから書き込む
+        let msaa_image = self.msaa_target.as_ref().map(|msaa_target| {
+            graph.import_image(
+                msaa_target.image,
+                Self::color_subresource_range(),
+                ImageUse::undefined(
+                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                    vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                ),
+            )
+        });
 
-        let shader_stages = [vert_shader_stage_info, frag_shader_stage_info];
+        let mut scene_uses = vec![
+            (color_targets[0], ImageUse::COLOR_ATTACHMENT),
+            (depth_buffer, ImageUse::DEPTH_ATTACHMENT),
+        ];
+        scene_uses.extend(msaa_image.map(|image| (image, ImageUse::COLOR_ATTACHMENT)));
+
+        //前のフレームのメインのパスがサンプリングし終わってから、クリアして書き込む
+        if let (Some(shadow_map), Some(_)) = (&self.shadow_map, self.shadow_pipeline) {
+            let shadow_image = graph.import_image(
+                shadow_map.image(),
+                ShadowMap::subresource_range(),
+                ImageUse::undefined(
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    vk::AccessFlags2::NONE,
+                ),
+            );
 
-        //Vertex Input
+            graph.add_pass(
+                FramePass::Shadow,
+                &[(shadow_image, ImageUse::DEPTH_ATTACHMENT)],
+            );
+            scene_uses.push((shadow_image, ImageUse::FRAGMENT_SAMPLED));
+        }
 
-        //頂点シェーダーに渡される頂点データの形式を指定
-        //今回は三角形の頂点データがシェーダーにハードコードされているので何も設定しなくて良い
-        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
-            //バインディングとはデータ感の間隔やデータが頂点ごとかインスタンスごとかの指定など
-            //.vertex_binding_descriptions()
-            //頂点シェーダーに渡される属性の指定またどのバインディングからロードするかやどのオフセットでロードするかなど
-            //.vertex_attribute_description_count()
-            .build();
+        graph.add_pass(FramePass::Scene, &scene_uses);
 
-        //固定機能ステージの設定
+        //ヒートマップはシーンの描画先に上書きするので、シーンのパスの後でエフェクトより前になる
+        if self.shows_overdraw() {
+            let mut uses = vec![
+                (color_targets[0], ImageUse::COLOR_ATTACHMENT),
+                (depth_buffer, ImageUse::DEPTH_ATTACHMENT),
+            ];
+            uses.extend(msaa_image.map(|image| (image, ImageUse::COLOR_ATTACHMENT)));
 
-        //Input Assembly
-        //入力された頂点からどのようなトポロジでプリミティブを作成するかを設定
+            graph.add_pass(FramePass::DebugView, &uses);
+        }
 
-        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
-            //トポロジの設定
-            //今回は3つずつ頂点を読み込んで描画
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
-            //トポロジの設定でSTRIP系の設定をしていると全てのプリミティブがつながってしまうので
-            //trueにすることでそのつながり部分を一度断ち切るようなindex値を設定できる
-            .primitive_restart_enable(false)
-            .build();
+        //dynamic renderingではエフェクトのパスにもデプスバッファを付けている
+        for index in 0..color_targets.len() - 1 {
+            let mut uses = vec![
+                (color_targets[index], ImageUse::FRAGMENT_SAMPLED),
+                (color_targets[index + 1], ImageUse::COLOR_ATTACHMENT),
+                (depth_buffer, ImageUse::DEPTH_ATTACHMENT),
+            ];
+
+            //ブルームはエフェクトの入力を縮めてぼかしてから、エフェクトのパスでぼかした画像を足す
+            if let Some(bloom) = self.bloom.as_ref().filter(|_| self.is_bloom_effect(index)) {
+                for pass in bloom.passes() {
+                    let mut bloom_uses = vec![(
+                        bloom_image(bloom.target(pass).image),
+                        ImageUse::COLOR_ATTACHMENT,
+                    )];
+
+                    if pass == BloomPass::Extract {
+                        bloom_uses.push((color_targets[index], ImageUse::FRAGMENT_SAMPLED));
+                    }
 
-        //Viewport
+                    bloom_uses.extend(
+                        bloom
+                            .input_images(pass)
+                            .into_iter()
+                            .map(|image| (bloom_image(image), ImageUse::FRAGMENT_SAMPLED)),
+                    );
 
-        let viewport = vk::Viewport::builder()
-            //出力がレンダリングするフレームバッファの領域を指定
-            //x, yはスタート位置
-            .x(0.0)
-            .y(0.0)
-            //縦横のサイズ
-            .width(swap_chain_extent.width as _)
-            .height(swap_chain_extent.height as _)
-            .min_depth(0.0)
-            .max_depth(1.0)
-            .build();
+                    graph.add_pass(FramePass::Bloom(pass), &bloom_uses);
+                }
 
-        //Scissor Rectangle
+                uses.push((
+                    bloom_image(bloom.result_image()),
+                    ImageUse::FRAGMENT_SAMPLED,
+                ));
+            }
 
-        //Viewportはレンダリングされた画像をフレームバッファに対してどの位置に描画をするのか設定するものに対して
-        //Scissor Rectangleはレンダリングされた画像のどのピクセルを使用するかを指定
-        //https://vulkan-tutorial.com/images/viewports_scissors.png
-        let scissor = vk::Rect2D::builder()
-            .offset(vk::Offset2D::builder().x(0).y(0).build())
-            .extent(swap_chain_extent)
-            .build();
+            graph.add_pass(FramePass::PostProcess(index), &uses);
+        }
 
-        //viewportとscissor rectangleを統合
-        //あとあと使う？
-        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
-            //GPUによっては複数のviewportとscissor rectangleを使用することができる
-            .viewports(&[viewport])
-            .scissors(&[scissor])
-            .build();
+        graph
+    }
 
-        let rasterizer = vk::PipelineRasterizationStateCreateInfo::builder()
-            //trueを設定した場合nearとfarを超えたフラグメントはカリングされるのではなくclampされる
-            //シャドウマップなどに有効
-            //GPUの機能を有効にする必要あり
-            .depth_clamp_enable(false)
-            //trueを設定した場合ラスタライザステージをスキップする
-            .rasterizer_discard_enable(false)
-            //フラグメントの生成方法
-            //input assemblyは実際に塗るかどうかの設定だが、これはフラグメントを作成するかどうかの判断(?)
-            //例えばFILLの場合はポリゴンの領域をフラグメントで埋める
-            //GPUの機能を有効にする必要あり
-            .polygon_mode(vk::PolygonMode::FILL)
-            //線の太さを設定
-            //最大値はGPUに依存する
-            //1.0以上を指定したい場合はwideLinesというGPUの機能を有効にする必要あり
-            .line_width(1.0)
-            //カリングの種類を指定
-            .cull_mode(vk::CullModeFlags::BACK)
-            //Vulkanは右回りが表面？
-            .front_face(vk::FrontFace::CLOCKWISE)
-            //深度値の設定
-            //フラグメントの偏りに基づいてバイアスを掛けたりして深度地を変更することができる
-            //これらはシャドウマッピングなどで使用される
-            .depth_bias_enable(false)
-            .depth_bias_constant_factor(0.0)
-            .depth_bias_clamp(0.0)
-            .depth_bias_slope_factor(0.0)
+    //image viewに直接描画する
+    //render passのinitial_layoutとsubpass dependencyで行っていた遷移はframe_graphのバリアで行う
+    fn cmd_begin_dynamic_rendering(
+        &self,
+        dynamic_rendering: &DynamicRendering,
+        command_buffer: vk::CommandBuffer,
+        target: PassTarget,
+        clear_color: vk::ClearValue,
+        secondary: bool,
+    ) {
+        let color_attachment = vk::RenderingAttachmentInfo::builder()
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .clear_value(clear_color);
+
+        //MSAAの場合はマルチサンプルの画像に描画し、レンダリングの最後に平均してimage_viewに書き込む
+        let color_attachments = [if target.msaa_view == vk::ImageView::null() {
+            color_attachment
+                .image_view(target.image_view)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .build()
+        } else {
+            color_attachment
+                .image_view(target.msaa_view)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+                .resolve_image_view(target.image_view)
+                .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .build()
+        }];
+
+        let depth_attachment = vk::RenderingAttachmentInfo::builder()
+            .image_view(self.depth_buffer.view)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .clear_value(DepthBuffer::clear_value(self.config.depth))
             .build();
 
-        //Multisampling
+        //render passのSubpassContentsと同じく、中身をセカンダリコマンドバッファで記録する場合はフラグが必要
+        let flags = if secondary {
+            vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS
+        } else {
+            vk::RenderingFlags::empty()
+        };
 
-        //マルチサンプリングはアンチエイリアスの方法の１つ
-        //GPUの機能を有効にする必要がある
-        let multisampling = vk::PipelineMultisampleStateCreateInfo::builder()
-            //今は無効化
-            .sample_shading_enable(false)
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
-            .min_sample_shading(1.0)
-            .alpha_to_coverage_enable(false)
-            .alpha_to_one_enable(false)
+        let rendering_info = vk::RenderingInfo::builder()
+            .flags(flags)
+            .render_area(
+                vk::Rect2D::builder()
+                    .offset(vk::Offset2D::builder().x(0).y(0).build())
+                    .extent(self.render_extent())
+                    .build(),
+            )
+            .layer_count(1)
+            .color_attachments(&color_attachments)
+            .depth_attachment(&depth_attachment)
             .build();
 
-        //Depth Stencil
-        //今はスキップ
+        dynamic_rendering.cmd_begin_rendering(&self.device, command_buffer, &rendering_info);
+    }
 
-        //Color blending
+    //swapchainの画像はミップマップもレイヤーも1つだけ
+    fn color_subresource_range() -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build()
+    }
 
-        //フレームバッファごとの設定
-        //現在はフレームバッファは１つしか存在しない
-        let color_blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
-            .color_write_mask(
-                vk::ColorComponentFlags::R
-                    | vk::ColorComponentFlags::G
-                    | vk::ColorComponentFlags::B
-                    | vk::ColorComponentFlags::A,
+    //このフレームで記録するオブジェクトの描画を、不透明な物の後に半透明な物を奥から順に並べて返す
+    //visible_objectsはカリングした後の不透明なオブジェクトで、インスタンス描画の場合はInstancedGridがまとめて描画するので空になる
+    fn draw_calls(&self, visible_objects: &[usize]) -> Vec<DrawCall> {
+        let view = self.view_camera().view_matrix();
+
+        let mut draw_calls = visible_objects
+            .iter()
+            .map(|&object_index| DrawCall {
+                blend_mode: BlendMode::Opaque,
+                object_index,
+                //不透明な物は並べ替えないので使わない
+                view_depth: 0.0,
+            })
+            .chain(
+                self.transparent_quads
+                    .iter()
+                    .flat_map(|transparent_quads| transparent_quads.draw_calls(view)),
             )
-            //新しい色と古い色を混ぜるかどうか
-            //falseの場合はフラグメントシェーダーからの新しい色をそのまま使用する
-            .blend_enable(false)
-            //新しく来た色の寄与の割合(src_color_blend_factor * new_color的な感じ)
-            .src_color_blend_factor(vk::BlendFactor::ONE)
-            //もとから存在した色の寄与の割合(dst_color_blend_factor * old_color的な感じ)
-            .dst_color_blend_factor(vk::BlendFactor::ZERO)
-            //色を混ぜるときの演算子
-            .color_blend_op(vk::BlendOp::ADD)
-            //上記のalpha版
-            .src_alpha_blend_factor(vk::BlendFactor::ONE)
-            .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
-            .alpha_blend_op(vk::BlendOp::ADD)
-            .build();
+            .collect::<Vec<_>>();
 
-        //全てのフレームバッファ構造体の設定
-        let color_blend = vk::PipelineColorBlendStateCreateInfo::builder()
-            //2つ目のブレンド方法
-            //ビット単位でのブレンドの演算を行うことができる
-            //これを有効にするとVkPipelineColorBlendAttachmentStateで有効にしたblend設定は無効になってしまうので注意
-            //vkPipelineColorBlendAttachmentStateで設定したcolor_write_maskは個々でも使用される
-            .logic_op_enable(false)
-            //ビット演算の演算子指定
-            .logic_op(vk::LogicOp::COPY)
-            .attachments(&[color_blend_attachment])
-            .blend_constants([0.0, 0.0, 0.0, 0.0])
-            .build();
+        transparency::sort_draw_calls(&mut draw_calls);
 
-        //Dynamic State
+        draw_calls
+    }
 
-        //一度パイプラインの作成をしたあとに再作成をなしに変更できる値を設定
-        //ここではビューポートのサイズと線の幅
-        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::LINE_WIDTH];
+    //不透明な物を描画するメインのパイプラインのマテリアル
+    fn default_material(&self, pipeline: vk::Pipeline) -> Material {
+        Material {
+            pipeline,
+            pipeline_layout: self.pipeline_layout,
+            descriptor_set: self.view_descriptor_set(),
+        }
+    }
 
-        //dynamic_stateは今後の章で扱うので今回は作るだけ作っておいて実際に設定する部分にはnullを入れておく
-        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
-            .dynamic_states(&dynamic_states)
-            .build();
+    //描画ごとにマテリアルとメッシュを決め、不透明な物を同じマテリアル同士で並べ替えて返す
+    //不透明な物はopaque_pipelineのデフォルトのマテリアルで描画する
+    fn drawables(&self, opaque_pipeline: vk::Pipeline, draw_calls: &[DrawCall]) -> Vec<Drawable> {
+        let mut drawables = draw_calls
+            .iter()
+            .map(|&draw_call| {
+                let (material, mesh) = match draw_call.blend_mode {
+                    BlendMode::Opaque => (self.default_material(opaque_pipeline), &self.mesh),
+                    BlendMode::Transparent => {
+                        let (pipeline, pipeline_layout) = self.transparent_pipeline.unwrap();
+                        let material = Material {
+                            pipeline,
+                            pipeline_layout,
+                            descriptor_set: self.view_descriptor_set(),
+                        };
+                        (material, self.transparent_quads.as_ref().unwrap().mesh())
+                    }
+                };
 
-        //Pipeline layout
+                Drawable {
+                    mesh,
+                    material,
+                    draw_call,
+                }
+            })
+            .collect::<Vec<_>>();
 
-        //この構造体はVertex Shaderに変換行列を渡したり、フラグメントシェーダーでテクスチャサンプラーを作成するために使用する
-        //これによってシェーダーを一回一回ビルドしなくても定数を外部から変えることで柔軟性を持たせることができる
-        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
-            //.set_layouts()
-            //.push_constant_ranges()
-            .build();
+        drawable::group_by_material(&mut drawables);
 
-        let pipeline_layout = unsafe {
-            device
-                .create_pipeline_layout(&pipeline_layout_info, None)
-                .unwrap()
-        };
+        drawables
+    }
 
-        //Pipeline
+    //オブジェクトごとにset = 1のダイナミックオフセットだけを変えて描画する
+    //パイプラインとset = 0とメッシュはbind_stateに紐づいている物から変わった時だけ紐づけ直す
+    fn cmd_drawables<'a>(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        bind_state: &mut BindState<'a>,
+        drawables: &[Drawable<'a>],
+    ) {
+        for drawable in drawables {
+            let draw_call = drawable.draw_call;
+            let pipeline_layout = drawable.material.pipeline_layout;
+            let mesh = drawable.mesh;
+
+            let rebound = bind_state.bind_material(&self.device, command_buffer, drawable.material);
+            if rebound && draw_call.blend_mode == BlendMode::Opaque {
+                self.cmd_bind_shadow_map(command_buffer);
+                self.cmd_bind_ray_query_shadows(command_buffer);
+                self.cmd_bind_material_textures(command_buffer);
+                self.cmd_bind_vertex_pulling(command_buffer);
+                self.cmd_bind_mesh_shading(command_buffer);
+                self.cmd_bind_texture_array(command_buffer);
+                self.cmd_bind_normal_map(command_buffer);
+                self.cmd_bind_skinning(command_buffer);
+                self.cmd_bind_overdraw_counters(command_buffer);
+            }
+            bind_state.bind_mesh(&self.device, command_buffer, mesh);
+
+            unsafe {
+                self.object_buffers.cmd_bind(
+                    &self.device,
+                    command_buffer,
+                    pipeline_layout,
+                    self.current_frame,
+                    draw_call.object_index,
+                );
 
-        let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
-            .stages(&shader_stages)
-            .vertex_input_state(&vertex_input_info)
-            .input_assembly_state(&input_assembly_info)
-            .viewport_state(&viewport_state)
-            .rasterization_state(&rasterizer)
-            .multisample_state(&multisampling)
-            //.depth_stencil_state()
-            .color_blend_state(&color_blend)
-            //.dynamic_state()
-            .layout(pipeline_layout)
-            .render_pass(render_pass)
-            .subpass(0)
-            //パイプラインの派生をする時に使用する
-            //パイプラインの派生とは既存のパイプラインと多くの機能が共通している場合に設定にコストをかけずに素早く切り替えることができる機能
-            //Handleで既存のパイプラインを指定するか
-            .base_pipeline_handle(vk::Pipeline::null())
-            //パイプラインのIndexで指定するかのどちらか
-            .base_pipeline_index(-1)
-            .build();
+                if let (Some(material_textures), BlendMode::Opaque) =
+                    (&self.material_textures, draw_call.blend_mode)
+                {
+                    material_textures.cmd_bind_material(
+                        &self.device,
+                        command_buffer,
+                        pipeline_layout,
+                        material_textures.material_index(draw_call.object_index),
+                    );
+                }
 
-        let pipeline = unsafe {
-            device
-                //第一引数のPipelineCacheはcreate_graphics_pipelinesを複数回呼び出しするときやキャッシュがファイルに保存されている時にパイプラインに関するデータを再利用することができる
-                //第二引数は一気にpipelineを作成できるようにするために引数は配列を受け取れるようになっている
-                .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
-                .unwrap()
-                //ここのpopは帰ってくる配列の要素が１つであることがわかっているため
-                .pop()
-                .unwrap()
-        };
+                //main_vs_pulledはvertex_indexでインデックスバッファを引くのでインデックスの数だけ頂点を描画する
+                if draw_call.blend_mode == BlendMode::Opaque && self.pulls_vertices() {
+                    self.device
+                        .cmd_draw(command_buffer, mesh.index_count(), 1, 0, 0);
+                    continue;
+                }
 
+                //メッシュシェーダーのパイプラインは頂点入力を使わないので、cmd_draw系ではなくタスクシェーダーを起動する
+                if let (Some(mesh_shading), BlendMode::Opaque, false) = (
+                    &self.mesh_shading,
+                    draw_call.blend_mode,
+                    self.shows_overdraw(),
+                ) {
+                    mesh_shading.cmd_draw(command_buffer, mesh.index_count());
+                    continue;
+                }
+
+                self.device.cmd_draw_indexed(
+                    command_buffer,
+                    //インデックスの数
+                    mesh.index_count(),
+                    //インスタンス数
+                    1,
+                    //インデックスバッファのオフセット
+                    0,
+                    //インデックスに加算される値
+                    0,
+                    //インスタンスのオフセットでgl_InstanceIndexの最小値となる
+                    0,
+                );
+            }
+        }
+    }
+
+    //不透明なメッシュの頂点を点で描画し、ジオメトリシェーダーで法線の線にする
+    //パイプラインレイアウトが違うので、この後の描画はset = 0から紐づけ直す必要がある
+    fn cmd_draw_normals(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        (pipeline, pipeline_layout): (vk::Pipeline, vk::PipelineLayout),
+        drawables: &[Drawable],
+    ) {
         unsafe {
-            //パイプラインの作成が終了したらモジュールはすぐに破棄して良い
-            device.destroy_shader_module(shader_module, None);
+            self.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline,
+            );
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &[self.view_descriptor_set()],
+                &[],
+            );
+            self.mesh.cmd_bind(&self.device, command_buffer);
+
+            for drawable in drawables {
+                self.object_buffers.cmd_bind(
+                    &self.device,
+                    command_buffer,
+                    pipeline_layout,
+                    self.current_frame,
+                    drawable.draw_call.object_index,
+                );
+                //インデックスを使うと共有している頂点の線が重なるので、頂点バッファをそのまま読む
+                self.device
+                    .cmd_draw(command_buffer, self.mesh.vertex_count(), 1, 0, 0);
+            }
         }
+    }
 
-        (pipeline, pipeline_layout)
+    //不透明なメッシュをpulling_pipelineで描画するかどうか
+    fn pulls_vertices(&self) -> bool {
+        self.vertex_pulling
+            .as_ref()
+            .map_or(false, VertexPulling::is_enabled)
     }
 
-    fn create_shader_module(device: &Device, spirv_code: &[u8]) -> vk::ShaderModule {
-        info!("create shader module");
+    //不透明なメッシュと床をray_query_pipelineで描画するかどうか
+    fn traces_shadows(&self) -> bool {
+        self.ray_query_shadows
+            .as_ref()
+            .map_or(false, RayQueryShadows::is_enabled)
+    }
 
-        let create_info = vk::ShaderModuleCreateInfo {
-            s_type: vk::StructureType::SHADER_MODULE_CREATE_INFO,
-            p_next: std::ptr::null(),
-            flags: vk::ShaderModuleCreateFlags::empty(),
-            code_size: spirv_code.len(),
-            p_code: spirv_code.as_ptr() as *const u32,
-        };
+    //メインのパイプラインのset = 3にこのフレームのTLASを紐づける、ray_query_shadowsが無い場合は何もしない
+    //シャドウマップのパイプラインはset = 3を参照しないので、切り替えても紐づけ直さなくて良い
+    fn cmd_bind_ray_query_shadows(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(ray_query_shadows) = &self.ray_query_shadows {
+            ray_query_shadows.cmd_bind(
+                &self.device,
+                command_buffer,
+                self.pipeline_layout,
+                self.current_frame,
+            );
+        }
+    }
 
-        unsafe { device.create_shader_module(&create_info, None).unwrap() }
+    //メインのパイプラインのset = 2にメッシュのバッファを紐づける、--vertex-pullingでない場合は何もしない
+    //頂点入力のパイプラインはset = 2を参照しないので、切り替えても紐づけ直さなくて良い
+    fn cmd_bind_vertex_pulling(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(vertex_pulling) = &self.vertex_pulling {
+            vertex_pulling.cmd_bind(&self.device, command_buffer, self.pipeline_layout);
+        }
     }
 
-    fn create_render_pass(device: &Device, format: Format) -> vk::RenderPass {
-        info!("create render pass");
+    fn cmd_bind_mesh_shading(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(mesh_shading) = &self.mesh_shading {
+            mesh_shading.cmd_bind(&self.device, command_buffer, self.pipeline_layout);
+        }
+    }
 
-        //Subpass周り諸々
+    //push constantのenabledも一緒に渡すので、パイプラインを紐づけ直す度に呼ぶ
+    fn cmd_bind_normal_map(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(normal_map) = &self.normal_map {
+            normal_map.cmd_bind(
+                &self.device,
+                command_buffer,
+                self.pipeline_layout,
+                self.normal_mapping_enabled,
+            );
+        }
+    }
 
-        //subpass同士でやり取りするデータをAttachmentと呼ぶ
-        let color_attachment = vk::AttachmentDescription::builder()
-            //swapchainのフォーマットと同じものを使用
-            .format(format)
-            //マルチサンプリングの設定
-            .samples(vk::SampleCountFlags::TYPE_1)
-            //loadOpとstoreOpはレンダリング前と後のデータをどうするか決める
-            //load
-            //CLEARは開始時に定数で値をクリアする
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            //レンダリングされたコンテンツをメモリ上に保存する
-            .store_op(vk::AttachmentStoreOp::STORE)
-            //上記２つのStencil版
-            //現在は使用していないので特に考慮する必要がないというDONT_CAREを割り当てる
-            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            //画像の特定のピクセルフォーマットはVkImageで保持されるがそのピクセルごとのメモリレイアウトの設定はここで行われる
-            //レイアウトはそれぞれその画像が何をするための物なのかを示すもの
-            //initialLayoutはレンダリングパスが始まる前に画像が持つレイアウトを指定する
-            //UNDEFINEDは画像のレイアウト
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            //PRESENT_SRC_KHRはスワップチェーンで提示される画像となる
-            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-            .build();
+    //SkinVertexの頂点バッファと、このフレームのジョイントの行列を紐づける
+    fn cmd_bind_skinning(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(skinning) = &self.skinning {
+            skinning.cmd_bind(
+                &self.device,
+                command_buffer,
+                self.pipeline_layout,
+                self.current_frame,
+            );
+        }
+    }
 
-        //Subpass用の設定構造体
-        let color_attachment_ref = vk::AttachmentReference::builder()
-            //Subpassは複数のAttachmentを持つことがあるためこうなっている
-            //参照するVkAttachmentDescriptionのインデックスを指定する
-            .attachment(0)
-            //attachmentのレイアウトを指定
-            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-            .build();
+    fn cmd_bind_texture_array(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(texture_array) = &self.texture_array {
+            texture_array.cmd_bind(&self.device, command_buffer, self.pipeline_layout);
+        }
+    }
+
+    //オーバードローを数える場合はメインのパイプラインのset = 2にこのフレームのバッファを紐づける
+    fn cmd_bind_overdraw_counters(&self, command_buffer: vk::CommandBuffer) {
+        if let (Some(overdraw_counters), true) = (&self.overdraw_counters, self.shows_overdraw()) {
+            overdraw_counters.cmd_bind(
+                &self.device,
+                command_buffer,
+                self.pipeline_layout,
+                2,
+                self.current_frame,
+            );
+        }
+    }
+
+    //今のパイプラインでdebug_viewを表示できるかどうか
+    //オーバードローはセカンダリコマンドバッファの描画では数えない
+    fn save_scene(&self) {
+        let scene = SceneState {
+            camera_position: self.camera.position,
+            yaw: self.camera.yaw,
+            pitch: self.camera.pitch,
+            projection: self.camera.projection,
+            orthographic_height: self.orthographic_height,
+            fov_y: self.camera.fov_y,
+            near: self.camera.near,
+            far: self.camera.far,
+            mesh: self.mesh_path.clone(),
+            model_rotation: self.model_rotation,
+            material: MaterialState {
+                lighting_mode: self.lighting_mode,
+                normal_mapping: self.normal_map.is_some() && self.normal_mapping_enabled,
+                tonemapper: self.tonemapper,
+                exposure: self.exposure,
+                bloom_intensity: self.bloom_intensity,
+            },
+            lights: self
+                .light_manager
+                .entries()
+                .map(|(light, orbit)| LightState::new(light, orbit))
+                .collect(),
+            light_seconds: self.light_manager.seconds(),
+            wireframe: self.wireframe,
+            show_normals: self.show_normals,
+            debug_view: self.debug_view,
+            vertex_pulling: self
+                .vertex_pulling
+                .as_ref()
+                .map_or(false, |vertex_pulling| vertex_pulling.is_enabled()),
+            ray_query_shadows: self
+                .ray_query_shadows
+                .as_ref()
+                .map_or(false, |ray_query_shadows| ray_query_shadows.is_enabled()),
+            paused: self.frame_clock.is_paused(),
+        };
+
+        match scene.save(&self.scene_path) {
+            Ok(()) => info!("Saved scene to {}", self.scene_path.display()),
+            Err(error) => log::warn!("{}", error),
+        }
+    }
 
-        let subpass = vk::SubpassDescription::builder()
-            //Vulkanは将来的にCompute系のsubpassもサポートする可能性が存在するためGRAPHICSを指定してあげる
-            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-            //ここでindexを0番に設定したためフラグメントシェーダーから`layout(location = 0) out vec4 outColor`で参照できる
-            .color_attachments(&[color_attachment_ref])
-            .build();
+    //メッシュや機能は起動時の引数で決まるので、ファイルと合わないものは飛ばして残りを戻す
+    fn load_scene(&mut self) {
+        let scene = match SceneState::load(&self.scene_path) {
+            Ok(scene) => scene,
+            Err(error) => {
+                log::warn!("{}", error);
+                return;
+            }
+        };
 
-        //Render passのSubpass Dependencyはdraw_frameのImageが利用可能にならないと(セマフォでいうとimage_available_semaphore)設定できないので待機する
-        //今回の方法はRender passを途中でVK_PIPELINE_STAGE_COLOR_ATTACHMENT_OUTPUT_BITまで待機させることで可能にしているが
-        //imageAvailableSemaphoreのwaitStagesをVK_PIPELINE_STAGE_TOP_OF_PIPE_BITに変更してRender pass自体を開始しないようにすることもできる
+        scene.apply_camera(&mut self.camera);
+        self.orthographic_height = scene.orthographic_height;
 
-        //srcとdstの２つのsubpassを指定して紐づける
-        let dependency = vk::SubpassDependency::builder()
-            //subpassの依存関係を記述
-            //SUBPAS_EXTERNALはdst_subpassがどう指定されているかに応じてレンダーパスの前後の暗黙のsubpassを参照する
-            .src_subpass(vk::SUBPASS_EXTERNAL)
-            //subpassのindexを指定
-            .dst_subpass(0)
-            //次の２つは待機する操作とその操作が発生するステージを指定
-            //ステージ指定
-            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-            //待機操作
-            .src_access_mask(vk::AccessFlags::empty())
-            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
-            .build();
+        //--max-lightsより多い分はLightManagerが警告して飛ばす
+        self.light_manager.restore(
+            scene
+                .lights
+                .iter()
+                .map(|light| (light.light(), light.orbit)),
+            scene.light_seconds,
+        );
 
-        //RenderPass
+        let material = scene.material;
+        self.lighting_mode = material.lighting_mode;
 
-        let render_pass_info = vk::RenderPassCreateInfo::builder()
-            .attachments(&[color_attachment])
-            .subpasses(&[subpass])
-            .dependencies(&[dependency])
-            .build();
+        //キーで切り替える時と同じく、使えない機能の値は今の値のままにする
+        if self.normal_map.is_some() {
+            self.normal_mapping_enabled = material.normal_mapping;
+        } else if material.normal_mapping {
+            log::warn!("Normal mapping is unavailable without --normal-mapping");
+        }
 
-        unsafe { device.create_render_pass(&render_pass_info, None).unwrap() }
-    }
+        if self.has_tonemapping() {
+            self.tonemapper = material.tonemapper;
+            self.exposure = material
+                .exposure
+                .clamp(-tonemap::MAX_EXPOSURE, tonemap::MAX_EXPOSURE);
+        }
 
-    fn create_frame_buffers(
-        device: &Device,
-        render_pass: vk::RenderPass,
-        swap_chain_image_views: Vec<vk::ImageView>,
-        swap_chain_extent: vk::Extent2D,
-    ) -> Vec<vk::Framebuffer> {
-        let mut swap_chain_frame_buffers = vec![];
+        if self.bloom.is_some() {
+            self.bloom_intensity = material.bloom_intensity.clamp(0.0, bloom::MAX_INTENSITY);
+        }
 
-        //vkImagesに割り当てていく
-        for image_view in swap_chain_image_views {
-            let frame_buffer_info = vk::FramebufferCreateInfo::builder()
-                //FrameBufferがどのRender passと互換性を持つかを指定
-                //FrameBufferは互換性のあるレンダーパスでのみ使用できる
-                .render_pass(render_pass)
-                //RenderPassのpAttachment配列内のそれぞれのAttachmentに対してどのImageViewが紐づくべきかを指定
-                .attachments(&[image_view])
-                .width(swap_chain_extent.width)
-                .height(swap_chain_extent.height)
-                //画像配列のレイヤー数を指定
-                .layers(1)
-                .build();
+        //モデルの角度は同じメッシュを描画している場合だけ意味がある
+        if scene.mesh == self.mesh_path {
+            self.model_rotation = scene.model_rotation;
+        } else {
+            match &scene.mesh {
+                Some(mesh) if !mesh.exists() => {
+                    log::warn!("{} does not exist, skipping its state", mesh.display())
+                }
+                Some(mesh) => log::warn!(
+                    "{} is not loaded, start with --obj {} to restore it",
+                    mesh.display(),
+                    mesh.display()
+                ),
+                None => log::warn!("The scene was saved without --obj, skipping the model state"),
+            }
+        }
 
-            swap_chain_frame_buffers
-                .push(unsafe { device.create_framebuffer(&frame_buffer_info, None).unwrap() });
+        if scene.wireframe && self.wireframe_pipeline.is_none() {
+            log::warn!("fill_mode_non_solid is not supported, wireframe is unavailable");
+        } else {
+            self.wireframe = scene.wireframe;
         }
 
-        swap_chain_frame_buffers
-    }
+        if scene.show_normals && self.normals_pipeline.is_none() {
+            log::warn!("geometry_shader is not supported, normals are unavailable");
+        } else {
+            self.show_normals = scene.show_normals;
+        }
 
-    fn create_command_pool(
-        instance: &Instance,
-        surface: &Surface,
-        surface_khr: SurfaceKHR,
-        physical_device: PhysicalDevice,
-        device: &Device,
-    ) -> vk::CommandPool {
-        let queue_family_indices = QueueFamilyIndices::find_queue_families(
-            instance,
-            surface,
-            surface_khr,
-            physical_device,
-        );
+        if self.has_debug_view(scene.debug_view) {
+            self.debug_view = scene.debug_view;
+        } else {
+            log::warn!(
+                "Debug view {} is unavailable, keeping {}",
+                scene.debug_view.name(),
+                self.debug_view.name()
+            );
+        }
 
-        //Command Bufferのメモリ管理をするためのCommand Pool
-        let pool_info = vk::CommandPoolCreateInfo::builder()
-            //flagは二種類存在し、
-            //VK_COMMAND_POOL_CREATE_TRANSIENT_BITはプールが割り当てたコマンドバッファが短命であることを指定
-            //VK_COMMAND_POOL_CREATE_RESET_COMMAND_BUFFER_BITはそのコマンドバッファをコマンドを積む際にResetして使い回すことを指定
-            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
-            //Command Poolは単一のキューファミリータイプに対して作られる
-            //今回はグラフィックスキューファミリーを選択
-            .queue_family_index(queue_family_indices.graphics_family.unwrap())
-            .build();
+        match &mut self.vertex_pulling {
+            Some(vertex_pulling) => {
+                if vertex_pulling.is_enabled() != scene.vertex_pulling {
+                    vertex_pulling.toggle(self.frame_stats.gpu_average_ms());
+                    self.frame_stats.reset_gpu_times();
+                }
+            }
+            None if scene.vertex_pulling => {
+                log::warn!("Vertex pulling is unavailable without --vertex-pulling")
+            }
+            None => {}
+        }
 
-        unsafe { device.create_command_pool(&pool_info, None).unwrap() }
+        match &mut self.ray_query_shadows {
+            Some(ray_query_shadows) => {
+                if ray_query_shadows.is_enabled() != scene.ray_query_shadows {
+                    ray_query_shadows.toggle();
+                }
+            }
+            None if scene.ray_query_shadows => log::warn!(
+                "Ray query shadows are unavailable without --shadows --ray-query-shadows"
+            ),
+            None => {}
+        }
+
+        self.frame_clock.set_paused(scene.paused);
+        self.frame_advance_requested = false;
+
+        info!("Loaded scene from {}", self.scene_path.display());
+        self.request_redraw();
     }
 
-    //Command Bufferは所属するCommand Poolが破棄されるタイミングで自動的に破棄される
-    fn create_command_buffers(
-        device: &Device,
-        command_pool: CommandPool,
-        size: u32,
-    ) -> Vec<vk::CommandBuffer> {
-        let alloc_info = vk::CommandBufferAllocateInfo::builder()
-            .command_pool(command_pool)
-            //コマンドバッファがプライマリなのかセカンダリなのを指定
-            //PRIMARY: 直接キューに対してサブミットすることができる
-            //SECONDARY: 直接キューに対してサブミットすることは出来ないがプライマリコマンドバッファから間接的に呼び出すことができる
-            //SECONDARYは共通の操作をまとめて再利用したりする時に便利
-            .level(vk::CommandBufferLevel::PRIMARY)
-            .command_buffer_count(size)
-            .build();
+    fn has_debug_view(&self, debug_view: DebugView) -> bool {
+        match debug_view {
+            DebugView::Shading => true,
+            DebugView::Overdraw => {
+                self.overdraw_pipelines.is_some() && self.parallel_renderer.is_none()
+            }
+            //セカンダリコマンドバッファで記録する場合はシーンのパスに重ねられない
+            DebugView::LightTiles => {
+                self.light_tiles_pipeline.is_some() && self.parallel_renderer.is_none()
+            }
+            _ => self
+                .debug_view_pipelines
+                .iter()
+                .any(|&(view, _, _)| view == debug_view),
+        }
+    }
 
-        unsafe { device.allocate_command_buffers(&alloc_info).unwrap() }
+    //このフレームでオーバードローを数えてヒートマップを表示するかどうか
+    fn shows_overdraw(&self) -> bool {
+        self.debug_view == DebugView::Overdraw && self.overdraw_pipelines.is_some()
     }
 
-    fn record_command_buffer(&self, image_index: usize) {
-        //コマンドバッファもフレームバッファもインデックスは同じものを紐づけてあげる
-        //今回は１つだけ使うためfirst()
-        let command_buffer = *self.command_buffers.first().unwrap();
+    //このフレームでシーンのパスの最後にライトのタイルのヒートマップを重ねるかどうか
+    fn shows_light_tiles(&self) -> bool {
+        self.debug_view == DebugView::LightTiles && self.light_tiles_pipeline.is_some()
+    }
 
-        //swapchainにpresentするときにimage_indexを渡してあげているのでそれと同等のものを使用できるようにしてあげる
-        let swap_chain_frame_buffer = self.swap_chain_frame_buffers[image_index];
+    //メインのパイプラインのset = 2にシャドウマップを紐づける、シャドウマップが無い場合は何もしない
+    fn cmd_bind_shadow_map(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(shadow_map) = &self.shadow_map {
+            unsafe {
+                self.device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline_layout,
+                    2,
+                    &[shadow_map.descriptor_set()],
+                    &[],
+                );
+            }
+        }
+    }
 
-        let begin_info = vk::CommandBufferBeginInfo::builder()
-            //コマンドバッファの使用方法を指定
-            //ONE_TIME_SUBMIT: コマンドバッファを一度ジック押したらまたすぐに再記録する
-            //PASS_CONTINUE: 一回のレンダリングパスの中で完結するSECONDARYコマンドバッファ
-            //SIMULTANEOUS_USE: コマンドバッファを実行または保留中に再度Submitすることができる
-            .flags(vk::CommandBufferUsageFlags::empty())
-            //この値はSECONDARYコマンドバッファに対してのみ適用される
-            //これはPRIMARYなコマンドバッファからどのように状態を継承するかを指定する
-            //.inheritance_info()
-            .build();
+    //bindlessの場合はメインのパイプラインのset = 2に全てのマテリアルのテクスチャを紐づける
+    //マテリアルごとのDescriptor Setの場合はcmd_draw_callsで描画ごとに紐づける
+    fn cmd_bind_material_textures(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(material_textures) = &self.material_textures {
+            material_textures.cmd_bind_all(&self.device, command_buffer, self.pipeline_layout);
+        }
+    }
 
-        unsafe {
-            self.device
-                //コマンドバッファの記録を開始する
-                //一度記録されたコマンドバッファに対してもう一度このメソッドを呼び出すと、リセットが暗黙的に走る
-                .begin_command_buffer(command_buffer, &begin_info)
-                .unwrap()
-        };
+    //ライトから見た不透明なオブジェクトの深度値をシャドウマップに書き込む
+    //床は影を受けるだけなので描画しない
+    fn cmd_shadow_pass(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        shadow_map: &ShadowMap,
+        (pipeline, pipeline_layout): (vk::Pipeline, vk::PipelineLayout),
+    ) {
+        debug::cmd_begin_label(
+            self.debug_utils.as_ref(),
+            command_buffer,
+            CStr::from_bytes_with_nul(SHADOW_PASS_LABEL).unwrap(),
+            SHADOW_PASS_LABEL_COLOR,
+        );
 
-        let clear_color = vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.0, 0.0, 0.0, 1.0],
-            },
-        };
+        shadow_map.cmd_begin(
+            &self.device,
+            self.dynamic_rendering.as_ref(),
+            command_buffer,
+        );
 
-        let render_pass_info = vk::RenderPassBeginInfo::builder()
-            //レンダーパスとカラーアタッチメントとして登録されたframebufferを紐づけ
-            .render_pass(self.render_pass)
-            .framebuffer(swap_chain_frame_buffer)
-            .render_area(
-                //レンダリング領域の大きさを指定
-                //レンダリング領域とはシェーダのロードとストアが行われる場所
-                //この領域外のピクセルの値は未定義となる
-                vk::Rect2D::builder()
-                    .offset(vk::Offset2D::builder().x(0).y(0).build())
-                    .extent(self.swap_chain_extent)
-                    .build(),
-            )
-            //color_attachmentの定義時に指定したLOAD_OP_CLEARに使用するクリア値の設定
-            .clear_values(&[clear_color])
-            .build();
+        let constants = ShadowConstants {
+            light_view_proj: ShadowMap::light_view_proj(),
+        };
 
-        //コマンドを積む
         unsafe {
-            //コマンドを記録するすべての関数はprefixとしてcmd(本家だとvkCmd)がつく
-            //基本的にこれらの関数の実行時にはコマンドを記録しているだけで実際に実行しているわけではないので、返り値がResultになっていない
-            //個のコマンドを使用することで描画が始まる
-            self.device.cmd_begin_render_pass(
-                command_buffer,
-                &render_pass_info,
-                //render_pass内の描画コマンドをどのように提供するかを指定
-                //INLINE: render_pass内のコマンドはPRIMARYなコマンドバッファ自体に埋め込まれSECODARYは実行されない
-                //SECONDARY_COMMAND_BUFFER: render_pass内のコマンドはSECONDARYなコマンドバッファから実行される
-                vk::SubpassContents::INLINE,
-            );
+            self.device
+                .cmd_set_viewport(command_buffer, 0, &[shadow_map.viewport()]);
+            self.device
+                .cmd_set_scissor(command_buffer, 0, &[shadow_map.scissor()]);
 
-            //Graphics Pipelineをコマンドバッファに対して紐づける
             self.device.cmd_bind_pipeline(
                 command_buffer,
                 vk::PipelineBindPoint::GRAPHICS,
-                self.pipeline,
+                pipeline,
             );
-
-            //三角形を描画する処理を発行
-            self.device.cmd_draw(
+            self.device.cmd_push_constants(
                 command_buffer,
-                //頂点バッファのサイズ設定
-                3,
-                //インスタンス数
-                1,
-                //頂点バッファのオフセット
-                0,
-                //インスタンスのオフセットでgl_InstanceIndexの最小値となる
+                pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
                 0,
+                std::slice::from_raw_parts(
+                    &constants as *const ShadowConstants as *const u8,
+                    mem::size_of::<ShadowConstants>(),
+                ),
             );
 
-            //render_pass系コマンドの終わり
-            self.device.cmd_end_render_pass(command_buffer);
-        };
+            self.mesh.cmd_bind(&self.device, command_buffer);
 
-        unsafe { self.device.end_command_buffer(command_buffer).unwrap() };
+            for object_index in 0..self.opaque_object_count {
+                self.object_buffers.cmd_bind(
+                    &self.device,
+                    command_buffer,
+                    pipeline_layout,
+                    self.current_frame,
+                    object_index,
+                );
+                self.device
+                    .cmd_draw_indexed(command_buffer, self.mesh.index_count(), 1, 0, 0, 0);
+            }
+        }
+
+        shadow_map.cmd_end(
+            &self.device,
+            self.dynamic_rendering.as_ref(),
+            command_buffer,
+        );
+
+        debug::cmd_end_label(self.debug_utils.as_ref(), command_buffer);
     }
 
-    fn create_sync_objects(
-        device: &Device,
-        size: u32,
-    ) -> (Vec<vk::Semaphore>, Vec<vk::Semaphore>, Vec<vk::Fence>) {
+    fn create_sync_objects(device: &Device, size: u32) -> (Vec<vk::Semaphore>, Vec<vk::Semaphore>) {
         //SemaphoreCreateInfoは今のところsTypeは必須ではなく今後のバージョンによりflagsやpNextが追加される可能性がある
         let semaphore_info = vk::SemaphoreCreateInfo::builder().build();
 
-        //こちらもSemaphoreCreateInfo同様
-        let fence_info = vk::FenceCreateInfo::builder()
-            //最初の位置フレーム目はFenceの待機がレンダリング前に入るのでシグナリングを行うものがいないのに待機してしまう
-            //なので最初はシグナリングされた状態で作る
-            .flags(vk::FenceCreateFlags::SIGNALED)
-            .build();
-
         let mut image_available_semaphores = vec![];
         let mut render_finished_semaphores = vec![];
-        let mut in_flight_fences = vec![];
 
         for _ in 0..size {
             image_available_semaphores
                 .push(unsafe { device.create_semaphore(&semaphore_info, None).unwrap() });
             render_finished_semaphores
                 .push(unsafe { device.create_semaphore(&semaphore_info, None).unwrap() });
-
-            in_flight_fences.push(unsafe { device.create_fence(&fence_info, None).unwrap() });
         }
 
-        (
-            image_available_semaphores,
-            render_finished_semaphores,
-            in_flight_fences,
-        )
+        (image_available_semaphores, render_finished_semaphores)
     }
 }
 
 impl Drop for VulkanApp {
     fn drop(&mut self) {
+        //デバイスロストから復帰した場合は新しいVulkanAppに移してあるので、最後に1回だけ書き出す
+        if let Some(session_recorder) = &self.session_recorder {
+            session_recorder.save();
+        }
+
+        self.destroy();
+    }
+}
+
+impl VulkanApp {
+    //Dropとデバイスロストからの復帰で使う
+    //二回目以降の呼び出しでは何もしない
+    fn destroy(&mut self) {
+        if self.destroyed {
+            return;
+        }
+        self.destroyed = true;
+
         log::debug!("Dropping application.");
         unsafe {
             self.cleanup_swap_chain();
+            self.cleanup_pipelines();
+
+            //次回の起動時に使えるように破棄する前に保存する
+            self.pipeline_cache.save(&self.device);
+            self.pipeline_cache.destroy(&self.device);
+            self.sampler_cache.destroy(&self.device);
 
             self.device.destroy_command_pool(self.command_pool, None);
+            self.one_time_commands.destroy(&self.device);
+
+            self.uniform_buffers.destroy(&self.device);
+
+            if let Some(split_screen) = &mut self.split_screen {
+                split_screen.destroy(&self.device);
+            }
+            self.object_buffers.destroy(&self.device);
+            self.mesh.destroy(&self.device);
+
+            for (mesh, _) in &self.retired_meshes {
+                mesh.destroy(&self.device);
+            }
+
+            if let Some(asset_uploader) = &self.asset_uploader {
+                asset_uploader.destroy(&self.device);
+            }
+
+            if let Some(instanced_grid) = &self.instanced_grid {
+                instanced_grid.destroy(&self.device);
+            }
+
+            if let Some(light_culling) = &self.light_culling {
+                light_culling.destroy(&self.device);
+            }
+
+            if let Some(particles) = &self.particles {
+                particles.destroy(&self.device);
+            }
+
+            if let Some(compute_queue) = &self.compute_queue {
+                compute_queue.destroy(&self.device);
+            }
+
+            if let Some(parallel_renderer) = &self.parallel_renderer {
+                parallel_renderer.destroy(&self.device);
+            }
+
+            if let Some(skybox) = &self.skybox {
+                skybox.destroy(&self.device);
+            }
+
+            self.staging_ring.destroy(&self.device);
+
+            if let Some(egui_overlay) = &self.egui_overlay {
+                egui_overlay.destroy(&self.device);
+            }
+            if let Some(debug_text) = &self.debug_text {
+                debug_text.destroy(&self.device);
+            }
+
+            if let Some(sprite_batch) = &self.sprite_batch {
+                sprite_batch.destroy(&self.device);
+            }
+
+            if let Some(transparent_quads) = &self.transparent_quads {
+                transparent_quads.destroy(&self.device);
+            }
+
+            if let Some(shadow_map) = &self.shadow_map {
+                shadow_map.destroy(&self.device);
+            }
+
+            if let Some(ground) = &self.ground {
+                ground.destroy(&self.device);
+            }
+
+            if let Some(material_textures) = &self.material_textures {
+                material_textures.destroy(&self.device, &mut self.texture_manager);
+            }
+
+            self.texture_manager.destroy(&self.device);
+
+            if let Some(procedural_texture) = &self.procedural_texture {
+                procedural_texture.destroy(&self.device);
+            }
+
+            if let Some(texture_array) = &self.texture_array {
+                texture_array.destroy(&self.device);
+            }
+
+            if let Some(normal_map) = &self.normal_map {
+                normal_map.destroy(&self.device);
+            }
+
+            if let Some(skinning) = &self.skinning {
+                skinning.destroy(&self.device);
+            }
+
+            //material_textures、procedural_texture、texture_array、normal_map、skinningのDescriptor Setの後に破棄する
+            self.descriptor_layout_cache.destroy(&self.device);
+
+            if let Some(vertex_pulling) = &self.vertex_pulling {
+                vertex_pulling.destroy(&self.device);
+            }
+
+            if let Some(mesh_shading) = &self.mesh_shading {
+                mesh_shading.destroy(&self.device);
+            }
+
+            if let Some(overdraw_counters) = &mut self.overdraw_counters {
+                overdraw_counters.destroy(&self.device);
+            }
+
+            if let Some(ray_query_shadows) = &self.ray_query_shadows {
+                ray_query_shadows.destroy(&self.device);
+            }
+
+            if let Some(tessellated_plane) = &self.tessellated_plane {
+                tessellated_plane.destroy(&self.device);
+            }
+
+            if let Some(ray_tracer) = &mut self.ray_tracer {
+                ray_tracer.destroy(&self.device);
+            }
+
+            //BloomのDescriptor SetはPostProcessのDescriptor Set Layoutから作っているので先に破棄する
+            if let Some(bloom) = &mut self.bloom {
+                bloom.destroy(&self.device);
+            }
+
+            if let Some(post_process) = &mut self.post_process {
+                post_process.destroy(&self.device);
+            }
+
+            if let Some(gpu_timer) = &self.gpu_timer {
+                gpu_timer.destroy(&self.device);
+            }
+
+            if let Some(occlusion_culling) = &self.occlusion_culling {
+                occlusion_culling.destroy(&self.device);
+            }
+
+            if let Some(pipeline_statistics) = &self.pipeline_statistics {
+                pipeline_statistics.destroy(&self.device);
+            }
 
             for semaphore in self.image_available_semaphores.clone() {
                 self.device.destroy_semaphore(semaphore, None);
@@ -1285,9 +9727,15 @@ impl Drop for VulkanApp {
                 self.device.destroy_semaphore(semaphore, None);
             }
 
-            for fence in self.in_flight_fences.clone() {
-                self.device.destroy_fence(fence, None);
-            }
+            self.frame_timeline.destroy(&self.device);
+
+            //swapchainはデバイスより先に、surfaceはinstanceより先に破棄する
+            //ウィンドウはメインのsurfaceを破棄した後に閉じる
+            let windows = self
+                .window_targets
+                .drain()
+                .map(|(_, target)| target.destroy(&self.device))
+                .collect::<Vec<_>>();
 
             if let Some(debug_utils) = &self.debug_utils {
                 debug_utils.destroy_debug_utils_messenger(
@@ -1297,11 +9745,26 @@ impl Drop for VulkanApp {
                 );
             }
 
-            self.device.destroy_device(None);
+            //作成した時と同じコールバックを渡す
+            let allocation_callbacks = self
+                .host_alloc_tracker
+                .as_ref()
+                .map(|tracker| tracker.callbacks());
+
+            self.device.destroy_device(allocation_callbacks.as_ref());
 
             self.surface.destroy_surface(self.surface_khr, None);
 
-            self.instance.destroy_instance(None); //ライフタイムが聞いてても呼ばないと駄目
+            //ライフタイムが聞いてても呼ばないと駄目
+            self.instance
+                .destroy_instance(allocation_callbacks.as_ref());
+
+            drop(windows);
+            self.last_window = None;
+        }
+
+        if let Some(tracker) = &self.host_alloc_tracker {
+            tracker.report();
         }
     }
 }
@@ -1,3 +1,5 @@
+use crate::cli::AppConfig;
+use winit::dpi::LogicalSize;
 use winit::event_loop::EventLoop;
 use winit::window::{Window, WindowBuilder};
 
@@ -8,19 +10,53 @@ pub struct WindowHandlers {
     /// (VulkanAppのフィールドが消費されてしまったらDropで呼べない)
     pub event_loop: EventLoop<()>,
     pub window: Window,
+    //メインのwindowに描画した画像を映す追加のウィンドウ
+    pub mirror_windows: Vec<Window>,
 }
 
 impl WindowHandlers {
-    pub fn new() -> Self {
+    //mirrorのウィンドウもメインのウィンドウと同じ大きさで開く
+    pub fn new(config: &AppConfig) -> Self {
         let event_loop = winit::event_loop::EventLoop::new();
 
-        let window = WindowBuilder::new()
-            .with_title("vulkan_tutorial")
-            .with_inner_size(winit::dpi::LogicalSize::new(800.0f32, 800.0f32))
-            .with_resizable(true)
-            .build(&event_loop)
-            .unwrap();
+        let size = LogicalSize::new(config.window_width, config.window_height);
 
-        Self { event_loop, window }
+        let window = Self::build_window(
+            &event_loop,
+            &config.window_title,
+            size,
+            config.window_resizable,
+        );
+
+        let mirror_windows = (0..config.demo.mirror_windows)
+            .map(|index| {
+                Self::build_window(
+                    &event_loop,
+                    &format!("{} (mirror {})", config.window_title, index + 1),
+                    size,
+                    config.window_resizable,
+                )
+            })
+            .collect();
+
+        Self {
+            event_loop,
+            window,
+            mirror_windows,
+        }
+    }
+
+    fn build_window(
+        event_loop: &EventLoop<()>,
+        title: &str,
+        size: LogicalSize<u32>,
+        resizable: bool,
+    ) -> Window {
+        WindowBuilder::new()
+            .with_title(title)
+            .with_inner_size(size)
+            .with_resizable(resizable)
+            .build(event_loop)
+            .unwrap()
     }
 }
@@ -0,0 +1,457 @@
+use crate::submit_queue::SubmitQueue;
+use crate::synchronization::{self, Synchronization};
+use ash::extensions::khr::{Surface, Swapchain};
+use ash::{vk, Device};
+use std::rc::Rc;
+use winit::window::{Window, WindowId};
+
+//VulkanAppが開いているウィンドウ1つ分
+//メインのウィンドウはVulkanAppのsurfaceとswapchainに描画するのでmirrorを持たない
+//それ以外のウィンドウはmirrorのswapchainにメインのswapchainの画像をblitして映す
+pub struct WindowTarget {
+    //イベントループのクロージャがVulkanAppを可変で借りている間もウィンドウを渡せるようにRcで持つ
+    window: Rc<Window>,
+    mirror: Option<MirrorSwapChain>,
+}
+
+impl WindowTarget {
+    pub fn primary(window: Rc<Window>) -> Self {
+        Self {
+            window,
+            mirror: None,
+        }
+    }
+
+    pub fn mirror(window: Rc<Window>, mirror: MirrorSwapChain) -> Self {
+        Self {
+            window,
+            mirror: Some(mirror),
+        }
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.window.id()
+    }
+
+    pub fn window(&self) -> &Rc<Window> {
+        &self.window
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.mirror.is_none()
+    }
+
+    pub fn mirror_swap_chain(&self) -> Option<&MirrorSwapChain> {
+        self.mirror.as_ref()
+    }
+
+    pub fn mirror_swap_chain_mut(&mut self) -> Option<&mut MirrorSwapChain> {
+        self.mirror.as_mut()
+    }
+
+    //最小化されている間は大きさが0のswapchainを作れないので作り直さない
+    //メインのウィンドウはVulkanAppがResizedで作り直すので対象にしない
+    pub fn needs_recreate(&self) -> bool {
+        let size = self.window.inner_size();
+
+        self.mirror
+            .as_ref()
+            .map_or(false, |mirror| mirror.out_of_date)
+            && size.width != 0
+            && size.height != 0
+    }
+
+    //作り直しが必要な間とメインのウィンドウはacquireしない
+    pub fn is_presentable(&self) -> bool {
+        self.mirror
+            .as_ref()
+            .map_or(false, |mirror| !mirror.out_of_date)
+    }
+
+    pub fn mark_out_of_date(&mut self) {
+        if let Some(mirror) = &mut self.mirror {
+            mirror.out_of_date = true;
+        }
+    }
+
+    //メインのウィンドウが閉じられた時に、このウィンドウを新しいメインのウィンドウにする
+    //swapchainとSemaphoreは破棄し、VulkanAppがメインのswapchainを作るためのsurfaceを返す
+    //GPUがこのウィンドウの画像とSemaphoreを使い終わってから呼ぶ
+    pub fn promote(&mut self, device: &Device) -> Option<vk::SurfaceKHR> {
+        let mirror = self.mirror.take()?;
+        let surface_khr = mirror.surface_khr;
+
+        mirror.destroy_swap_chain_and_semaphores(device);
+
+        Some(surface_khr)
+    }
+
+    //GPUがこのウィンドウの画像とSemaphoreを使い終わってから呼ぶ
+    //メインのウィンドウのsurfaceはVulkanAppが破棄する
+    //ウィンドウは返り値を全てdropすると閉じるので、surfaceを破棄した後にdropすること
+    pub fn destroy(self, device: &Device) -> Rc<Window> {
+        if let Some(mirror) = self.mirror {
+            mirror.destroy(device);
+        }
+
+        self.window
+    }
+}
+
+//メイン以外のウィンドウのsurfaceとswapchain、フレームごとのSemaphore
+//InstanceとDeviceはメインのウィンドウと共有し、描画したメインのswapchainの画像をblitして映す
+//blitで書き込むだけなのでframebufferやimage viewは持たない
+pub struct MirrorSwapChain {
+    surface: Surface,
+    surface_khr: vk::SurfaceKHR,
+    swap_chain: Swapchain,
+    swap_chain_khr: vk::SwapchainKHR,
+    images: Vec<vk::Image>,
+    extent: vk::Extent2D,
+    //どちらもフレームごとで、メインのウィンドウのものとは別にsubmitで待機、シグナルする
+    image_available_semaphores: Vec<vk::Semaphore>,
+    render_finished_semaphores: Vec<vk::Semaphore>,
+    //Resizedやsuboptimalで次のフレームの前にswapchainを作り直すかどうか
+    out_of_date: bool,
+}
+
+impl MirrorSwapChain {
+    //swap_chain_khrはTRANSFER_DSTを付けて作ってあること
+    pub fn new(
+        (surface, surface_khr): (Surface, vk::SurfaceKHR),
+        (swap_chain, swap_chain_khr, extent): (Swapchain, vk::SwapchainKHR, vk::Extent2D),
+        (image_available_semaphores, render_finished_semaphores): (
+            Vec<vk::Semaphore>,
+            Vec<vk::Semaphore>,
+        ),
+    ) -> Self {
+        let images = unsafe { swap_chain.get_swapchain_images(swap_chain_khr) }.unwrap();
+
+        Self {
+            surface,
+            surface_khr,
+            swap_chain,
+            swap_chain_khr,
+            images,
+            extent,
+            image_available_semaphores,
+            render_finished_semaphores,
+            out_of_date: false,
+        }
+    }
+
+    pub fn surface_khr(&self) -> vk::SurfaceKHR {
+        self.surface_khr
+    }
+
+    //同じsurfaceには1つしかswapchainを作れないので、作り直す前に破棄する
+    //GPUが古いswapchainの画像を使い終わってから呼ぶ
+    pub fn destroy_swap_chain(&mut self) {
+        unsafe { self.swap_chain.destroy_swapchain(self.swap_chain_khr, None) };
+
+        self.swap_chain_khr = vk::SwapchainKHR::null();
+        self.images.clear();
+    }
+
+    pub fn set_swap_chain(
+        &mut self,
+        (swap_chain, swap_chain_khr, extent): (Swapchain, vk::SwapchainKHR, vk::Extent2D),
+    ) {
+        self.images = unsafe { swap_chain.get_swapchain_images(swap_chain_khr) }.unwrap();
+        self.swap_chain = swap_chain;
+        self.swap_chain_khr = swap_chain_khr;
+        self.extent = extent;
+        self.out_of_date = false;
+    }
+
+    //作り直しが必要な場合はNoneを返し、このフレームでは描画しない
+    //取得できた場合はimage_available_semaphore(frame)にシグナルが送られるので必ずsubmitで待機すること
+    pub fn acquire(&mut self, frame: usize) -> Result<Option<u32>, vk::Result> {
+        let result = unsafe {
+            self.swap_chain.acquire_next_image(
+                self.swap_chain_khr,
+                u64::MAX,
+                self.image_available_semaphores[frame],
+                vk::Fence::null(),
+            )
+        };
+
+        match result {
+            Ok((image_index, is_suboptimal)) => {
+                self.out_of_date |= is_suboptimal;
+                Ok(Some(image_index))
+            }
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.out_of_date = true;
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    //クリアとblitの前に待機する
+    pub fn image_available_wait_info(&self, frame: usize) -> vk::SemaphoreSubmitInfo {
+        synchronization::semaphore_submit_info(
+            self.image_available_semaphores[frame],
+            0,
+            vk::PipelineStageFlags2::TRANSFER,
+        )
+    }
+
+    pub fn render_finished_signal_info(&self, frame: usize) -> vk::SemaphoreSubmitInfo {
+        synchronization::semaphore_submit_info(
+            self.render_finished_semaphores[frame],
+            0,
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+        )
+    }
+
+    //sourceはメインのswapchainの画像で、全てのパスの後のPRESENT_SRC_KHRの状態で渡し、同じ状態に戻す
+    //像が歪まないように縦横比を保ったまま中央に映す
+    pub fn cmd_mirror(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        command_buffer: vk::CommandBuffer,
+        source: vk::Image,
+        source_extent: vk::Extent2D,
+        image_index: u32,
+    ) {
+        let image = self.images[image_index as usize];
+
+        //render passのfinal_layoutによる遷移も含めて、前のパスの書き込みを全て待つ
+        let source_to_transfer = Self::barrier(
+            source,
+            (
+                vk::PipelineStageFlags2::ALL_COMMANDS,
+                vk::AccessFlags2::COLOR_ATTACHMENT_WRITE | vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+            (
+                vk::PipelineStageFlags2::BLIT,
+                vk::AccessFlags2::TRANSFER_READ,
+            ),
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        );
+
+        //image_available_wait_infoと同じTRANSFERから繋げて、取得できた画像をクリアの前に遷移する
+        let target_to_transfer = Self::barrier(
+            image,
+            (vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::NONE),
+            (
+                vk::PipelineStageFlags2::CLEAR,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+
+        synchronization.cmd_pipeline_barrier(
+            device,
+            command_buffer,
+            &[],
+            &[],
+            &[source_to_transfer, target_to_transfer],
+        );
+
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        let region = vk::ImageBlit::builder()
+            .src_subresource(subresource)
+            .src_offsets([
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: source_extent.width as i32,
+                    y: source_extent.height as i32,
+                    z: 1,
+                },
+            ])
+            .dst_subresource(subresource)
+            .dst_offsets(fit_offsets(source_extent, self.extent))
+            .build();
+
+        //余白は前の内容が残らないように黒で塗る
+        let black = vk::ClearColorValue {
+            float32: [0.0, 0.0, 0.0, 1.0],
+        };
+        let range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        unsafe {
+            device.cmd_clear_color_image(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &black,
+                &[range],
+            );
+        }
+
+        //クリアの書き込みの後にblitで書き込む
+        let clear_to_blit = Self::barrier(
+            image,
+            (
+                vk::PipelineStageFlags2::CLEAR,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+            (
+                vk::PipelineStageFlags2::BLIT,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+
+        synchronization.cmd_pipeline_barrier(device, command_buffer, &[], &[], &[clear_to_blit]);
+
+        unsafe {
+            device.cmd_blit_image(
+                command_buffer,
+                source,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+                vk::Filter::LINEAR,
+            );
+        }
+
+        //presentはそれぞれのrender_finished_semaphoreを待つのでdstは何も指定しない
+        let source_to_present = Self::barrier(
+            source,
+            (vk::PipelineStageFlags2::BLIT, vk::AccessFlags2::NONE),
+            (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE),
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        );
+        let target_to_present = Self::barrier(
+            image,
+            (
+                vk::PipelineStageFlags2::BLIT,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+            (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        );
+
+        synchronization.cmd_pipeline_barrier(
+            device,
+            command_buffer,
+            &[],
+            &[],
+            &[source_to_present, target_to_present],
+        );
+    }
+
+    fn barrier(
+        image: vk::Image,
+        (src_stage, src_access): (vk::PipelineStageFlags2, vk::AccessFlags2),
+        (dst_stage, dst_access): (vk::PipelineStageFlags2, vk::AccessFlags2),
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    ) -> vk::ImageMemoryBarrier2 {
+        vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(src_stage)
+            .src_access_mask(src_access)
+            .dst_stage_mask(dst_stage)
+            .dst_access_mask(dst_access)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .base_mip_level(0)
+                    .level_count(1)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .build()
+    }
+
+    //OUT_OF_DATEとSUBOPTIMALは次のフレームの前に作り直すだけなのでエラーにしない
+    pub fn present(
+        &mut self,
+        present_queue: &SubmitQueue,
+        frame: usize,
+        image_index: u32,
+    ) -> Result<(), vk::Result> {
+        let wait_semaphores = [self.render_finished_semaphores[frame]];
+        let swap_chains = [self.swap_chain_khr];
+        let image_indices = [image_index];
+
+        let present_info = vk::PresentInfoKHR::builder()
+            .wait_semaphores(&wait_semaphores)
+            .swapchains(&swap_chains)
+            .image_indices(&image_indices)
+            .build();
+
+        match present_queue.present(&self.swap_chain, &present_info) {
+            Ok(is_suboptimal) => {
+                self.out_of_date |= is_suboptimal;
+                Ok(())
+            }
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.out_of_date = true;
+                Ok(())
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    //surfaceは残し、promoteでメインのswapchainを作るのに使う
+    fn destroy_swap_chain_and_semaphores(&self, device: &Device) {
+        unsafe {
+            self.swap_chain.destroy_swapchain(self.swap_chain_khr, None);
+
+            for semaphore in self
+                .image_available_semaphores
+                .iter()
+                .chain(&self.render_finished_semaphores)
+            {
+                device.destroy_semaphore(*semaphore, None);
+            }
+        }
+    }
+
+    //swapchainはsurfaceより先に破棄する
+    fn destroy(self, device: &Device) {
+        self.destroy_swap_chain_and_semaphores(device);
+
+        unsafe { self.surface.destroy_surface(self.surface_khr, None) };
+    }
+}
+
+//sourceの縦横比を保ったままtargetに収まる最大の領域
+pub fn fit_offsets(source: vk::Extent2D, target: vk::Extent2D) -> [vk::Offset3D; 2] {
+    let scale = (target.width as f32 / source.width as f32)
+        .min(target.height as f32 / source.height as f32);
+
+    let width = ((source.width as f32 * scale) as i32).max(1);
+    let height = ((source.height as f32 * scale) as i32).max(1);
+    let x = (target.width as i32 - width) / 2;
+    let y = (target.height as i32 - height) / 2;
+
+    [
+        vk::Offset3D { x, y, z: 0 },
+        vk::Offset3D {
+            x: x + width,
+            y: y + height,
+            z: 1,
+        },
+    ]
+}